@@ -0,0 +1,65 @@
+use std::io::Read;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lunatic::net::{Poller, TcpListener, TcpStream};
+use lunatic::{memory, Mailbox, Process};
+
+const CONNECTIONS: usize = 10_000;
+const MIB: f64 = 1024.0 * 1024.0;
+
+// Opens `CONNECTIONS` connections and returns the client and server sides.
+fn connect() -> (Vec<TcpStream>, Vec<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    (0..CONNECTIONS)
+        .map(|_| {
+            let client = TcpStream::connect(addr).unwrap();
+            (client, listener.accept().unwrap().0)
+        })
+        .unzip()
+}
+
+// Waits on data, reporting the memory of the process once it started.
+fn connection_process((parent, mut stream): (Process<u64>, TcpStream), _: Mailbox<()>) {
+    parent.send(memory::used());
+    let mut buf = [0; 1024];
+    while stream.read(&mut buf).unwrap_or(0) > 0 {}
+}
+
+fn idle_connections_benchmark(c: &mut Criterion) {
+    // Process per connection.
+    let this = unsafe { Mailbox::<u64>::new() };
+    let (clients, streams) = connect();
+    let processes: Vec<_> = streams
+        .into_iter()
+        .map(|stream| Process::spawn_link((this.this(), stream), connection_process))
+        .collect();
+    let per_process: u64 = (0..CONNECTIONS).map(|_| this.receive()).sum();
+    processes.iter().for_each(Process::kill);
+    drop(clients);
+
+    // One poller for all connections.
+    let before = memory::used();
+    let (_clients, streams) = connect();
+    let mut poller = Poller::new();
+    for stream in streams {
+        poller.register(stream);
+    }
+    let poller_memory = memory::used() - before;
+
+    println!(
+        "memory of {CONNECTIONS} idle connections: {:.1} MiB with a process per connection, \
+         {:.1} MiB with a poller",
+        per_process as f64 / MIB,
+        poller_memory as f64 / MIB,
+    );
+
+    // The CPU time the poller spends per check while all connections are idle.
+    c.bench_function("poll 10k idle connections", |b| {
+        b.iter(|| assert!(poller.poll_ready(Some(Duration::ZERO)).is_empty()))
+    });
+}
+
+criterion_group!(benches, idle_connections_benchmark);
+criterion_main!(benches);
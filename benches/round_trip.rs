@@ -0,0 +1,150 @@
+use std::io::Write;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use lunatic::ap::handlers::Request;
+use lunatic::ap::{AbstractProcess, Config, RequestHandler, State};
+use lunatic::host::api::message;
+use lunatic::serializer::{message_writer, Bincode, MessageWriter};
+use lunatic::{Mailbox, Process};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+const ROUND_TRIPS: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Payload {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+// Sends every received message back to the parent.
+fn echo<M: Serialize + DeserializeOwned>(parent: Process<M>, mailbox: Mailbox<M>) {
+    loop {
+        parent.send(mailbox.receive());
+    }
+}
+
+fn ping_pong<M>(c: &mut Criterion, name: &str, message: M)
+where
+    M: Serialize + DeserializeOwned + Clone,
+{
+    let this = unsafe { Mailbox::<M>::new() };
+    let echo = Process::spawn_link(this.this(), echo::<M>);
+
+    let mut group = c.benchmark_group("round trip");
+    // Each round trip is two messages.
+    group.throughput(Throughput::Elements(2 * ROUND_TRIPS));
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            for _ in 0..ROUND_TRIPS {
+                echo.send(message.clone());
+                this.receive();
+            }
+        })
+    });
+    group.finish();
+}
+
+struct Echo;
+
+impl AbstractProcess for Echo {
+    type Arg = ();
+    type State = ();
+    type Serializer = Bincode;
+    type Handlers = (Request<u64>,);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+impl RequestHandler<u64> for Echo {
+    type Response = u64;
+
+    fn handle(_: State<Self>, value: u64) -> u64 {
+        value
+    }
+}
+
+// Requests of plain processes serialize the whole reply handle into every
+// request, abstract processes only write the IDs of the caller in front of it.
+fn requests(c: &mut Criterion) {
+    let plain = Process::spawn_link(
+        (),
+        |_, mailbox: Mailbox<lunatic::function::Request<u64, u64>>| loop {
+            let request = mailbox.receive();
+            let value = *request.message();
+            request.reply(value);
+        },
+    );
+    let abstract_process = Echo::link().start(()).unwrap();
+
+    let mut group = c.benchmark_group("request");
+    group.throughput(Throughput::Elements(2 * ROUND_TRIPS));
+    group.bench_function("Process::request", |b| {
+        b.iter(|| {
+            for i in 0..ROUND_TRIPS {
+                plain.request(i, None).unwrap();
+            }
+        })
+    });
+    group.bench_function("ProcessRef::request", |b| {
+        b.iter(|| {
+            for i in 0..ROUND_TRIPS {
+                abstract_process.request(i);
+            }
+        })
+    });
+    group.finish();
+}
+
+// Compares encoding into a `MessageWriter` with encoding into the buffered
+// `message_writer`, without the rest of the round trip.
+fn encode<M: Serialize>(c: &mut Criterion, name: &str, message: M) {
+    let mut group = c.benchmark_group(format!("encode {}", name));
+    group.bench_function("MessageWriter", |b| {
+        b.iter(|| {
+            unsafe { message::create_data(0, 0) };
+            let mut writer = MessageWriter::new();
+            bincode::serialize_into(&mut writer, black_box(&message)).unwrap();
+            writer.flush().unwrap();
+        })
+    });
+    group.bench_function("message_writer", |b| {
+        b.iter(|| {
+            unsafe { message::create_data(0, 0) };
+            let mut writer = message_writer();
+            bincode::serialize_into(&mut writer, black_box(&message)).unwrap();
+            writer.flush().unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn encode_benchmark(c: &mut Criterion) {
+    encode(c, "unit", ());
+    encode(c, "u64", 42u64);
+    encode(
+        c,
+        "1KB struct",
+        Payload {
+            data: vec![1; 1024],
+        },
+    );
+}
+
+fn round_trip_benchmark(c: &mut Criterion) {
+    ping_pong(c, "unit", ());
+    ping_pong(c, "u64", 42u64);
+    ping_pong(
+        c,
+        "1KB struct",
+        Payload {
+            data: vec![1; 1024],
+        },
+    );
+}
+
+criterion_group!(benches, encode_benchmark, round_trip_benchmark, requests);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use lunatic::ap::handlers::Message;
+use lunatic::ap::{AbstractProcess, Config, MessageHandler, State};
+use lunatic::serializer::Bincode;
+use lunatic::{Mailbox, Process};
+use serde::{Deserialize, Serialize};
+
+const STEPS: u32 = 10_000;
+
+// A state machine that advances by sending itself a message for each step.
+struct StateMachine {
+    left: u32,
+    done: Option<Process<()>>,
+}
+
+impl AbstractProcess for StateMachine {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = ();
+    type Handlers = (Message<Run>, Message<Step>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(StateMachine {
+            left: 0,
+            done: None,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Run(u32, Process<()>);
+impl MessageHandler<Run> for StateMachine {
+    fn handle(mut state: State<Self>, Run(steps, done): Run) {
+        state.left = steps;
+        state.done = Some(done);
+        state.self_ref().send(Step([0; 8]));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Step([u64; 8]);
+impl MessageHandler<Step> for StateMachine {
+    fn handle(mut state: State<Self>, Step(mut registers): Step) {
+        state.left -= 1;
+        registers[0] += 1;
+        match state.left {
+            0 => state.done.unwrap().send(()),
+            _ => state.self_ref().send(Step(registers)),
+        }
+    }
+}
+
+fn self_send_benchmark(c: &mut Criterion) {
+    let this = unsafe { Mailbox::<()>::new() };
+
+    let machine = StateMachine::link().start(()).unwrap();
+    c.bench_function("state machine 10k steps (abstract process)", |b| {
+        b.iter(|| {
+            machine.send(Run(STEPS, this.this()));
+            this.receive();
+        })
+    });
+
+    // The same steps through the mailbox of a regular process, they are
+    // serialized.
+    let steps = unsafe { Mailbox::<[u64; 8]>::new() };
+    c.bench_function("state machine 10k steps (mailbox)", |b| {
+        b.iter(|| {
+            let mut registers = [0; 8];
+            for _ in 0..STEPS {
+                steps.this().send(registers);
+                registers = steps.receive();
+                registers[0] += 1;
+            }
+        })
+    });
+}
+
+criterion_group!(benches, self_send_benchmark);
+criterion_main!(benches);
@@ -1,5 +1,6 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use lunatic::spawn_link;
+use lunatic::serializer::RawBytes;
+use lunatic::{spawn_link, Mailbox, Process};
 
 fn spawn_benchmark(c: &mut Criterion) {
     c.bench_function("task", |b| {
@@ -11,5 +12,32 @@ fn spawn_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, spawn_benchmark);
+fn large_capture_benchmark(c: &mut Criterion) {
+    let buffer = vec![1u8; 1024 * 1024];
+    let this = unsafe { Mailbox::<usize>::new() };
+
+    c.bench_function("capture 1MB buffer (bincode)", |b| {
+        b.iter(|| {
+            Process::spawn_link_with(
+                (this.this(), buffer.clone()),
+                |(parent, buffer), _: Mailbox<()>| parent.send(buffer.len()),
+            );
+            assert_eq!(this.receive(), buffer.len());
+        })
+    });
+
+    c.bench_function("capture 1MB buffer (raw bytes)", |b| {
+        b.iter(|| {
+            let parent = this.this();
+            Process::spawn_link_with_serializer::<RawBytes, _>(
+                (buffer.clone(),),
+                |(buffer,), mailbox: Mailbox<Process<usize>>| mailbox.receive().send(buffer.len()),
+            )
+            .send(parent);
+            assert_eq!(this.receive(), buffer.len());
+        })
+    });
+}
+
+criterion_group!(benches, spawn_benchmark, large_capture_benchmark);
 criterion_main!(benches);
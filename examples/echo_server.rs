@@ -0,0 +1,19 @@
+use std::io::{Read, Write};
+
+use lunatic::net::{ControlMessage, Server, TcpStream};
+use lunatic::Mailbox;
+
+fn main() {
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .with_max_connections(1000);
+    println!("Listening on addr: {}", server.local_addr().unwrap());
+    server.serve(echo);
+}
+
+fn echo(mut stream: TcpStream, _: Mailbox<ControlMessage>) {
+    let mut buffer = [0; 1024];
+    while let Ok(len @ 1..) = stream.read(&mut buffer) {
+        stream.write_all(&buffer[..len]).unwrap();
+    }
+}
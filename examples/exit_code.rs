@@ -0,0 +1,14 @@
+// Exits with the status selected by the first argument, `ok`, `err` or
+// `exit`. CI runs it to check that the status reaches `lunatic run`.
+use std::env;
+
+use lunatic::Mailbox;
+
+#[lunatic::main]
+fn main(_: Mailbox<()>) -> Result<(), String> {
+    match env::args().nth(1).as_deref() {
+        Some("err") => Err("failed on purpose".to_owned()),
+        Some("exit") => lunatic::exit(3),
+        _ => Ok(()),
+    }
+}
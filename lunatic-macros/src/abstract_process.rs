@@ -1,8 +1,9 @@
+use std::collections::HashMap;
 use std::iter::repeat;
 
 use convert_case::{Case, Casing};
 use proc_macro2::TokenStream;
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use syn::parse::{Parse, ParseStream};
 use syn::spanned::Spanned;
 use syn::{PathArguments, Token, Type, FnArg};
@@ -12,6 +13,8 @@ pub struct AbstractProcess {
     args: Args,
     /// Original impl item.
     item_impl: syn::ItemImpl,
+    /// Name of the type implementing the abstract process.
+    self_ident: syn::Ident,
     /// Arg type in abstract process implementation.
     arg_ty: syn::Type,
     /// `init` method.
@@ -22,6 +25,8 @@ pub struct AbstractProcess {
     handle_link_death: Option<syn::ImplItemMethod>,
     /// Message handler methods.
     message_handlers: Vec<syn::ImplItemMethod>,
+    /// Names of message handlers that use the priority lane.
+    priority_handlers: Vec<syn::Ident>,
     /// Request handler methods.
     request_handlers: Vec<syn::ImplItemMethod>,
     /// Deferred request handler methods.
@@ -59,6 +64,7 @@ impl AbstractProcess {
             terminate,
             handle_link_death,
             message_handlers,
+            priority_handlers,
             request_handlers,
             deferred_request_handlers,
         ) = item_impl
@@ -93,13 +99,14 @@ impl AbstractProcess {
                 Some((item_attr, impl_item_method))
             })
             .fold(
-                Ok((None, None, None, Vec::new(), Vec::new(), Vec::new())),
+                Ok((None, None, None, Vec::new(), Vec::new(), Vec::new(), Vec::new())),
                 |acc, (item_attr, impl_item_method)| {
                     let (
                         mut init,
                         mut terminate,
                         mut handle_link_death,
                         mut message_handlers,
+                        mut priority_handlers,
                         mut request_handlers,
                         mut deferred_request_handlers,
                     ) = acc?;
@@ -138,6 +145,10 @@ impl AbstractProcess {
                         ItemAttr::HandleMessage => {
                             message_handlers.push(impl_item_method);
                         }
+                        ItemAttr::HandlePriority => {
+                            priority_handlers.push(impl_item_method.sig.ident.clone());
+                            message_handlers.push(impl_item_method);
+                        }
                         ItemAttr::HandleRequest => {
                             request_handlers.push(impl_item_method);
                         }
@@ -151,12 +162,20 @@ impl AbstractProcess {
                         terminate,
                         handle_link_death,
                         message_handlers,
+                        priority_handlers,
                         request_handlers,
                         deferred_request_handlers,
                     ))
                 },
             )?;
 
+        check_wrapper_collisions(
+            message_handlers
+                .iter()
+                .chain(request_handlers.iter())
+                .chain(deferred_request_handlers.iter()),
+        )?;
+
         let init =
             init.ok_or_else(|| syn::Error::new(item_impl.self_ty.span(), "missing init method"))?;
         let arg_ty = match init
@@ -185,11 +204,13 @@ impl AbstractProcess {
         Ok(AbstractProcess {
             args,
             item_impl,
+            self_ident,
             arg_ty,
             init,
             terminate,
             handle_link_death,
             message_handlers,
+            priority_handlers,
             request_handlers,
             deferred_request_handlers,
             message_trait_name,
@@ -207,8 +228,11 @@ impl AbstractProcess {
         let deferred_request_handler_impls = self.expand_deferred_request_handler_impls();
         let handler_trait = self.expand_handler_trait();
         let impl_handler_trait = self.expand_impl_handler_trait();
+        let client_trait = self.expand_client_trait();
+        let serde_assertions = self.expand_serde_assertions();
 
         quote! {
+            #serde_assertions
             #handler_wrappers
             #original_impl
             #impl_abstract_process
@@ -217,6 +241,7 @@ impl AbstractProcess {
             #deferred_request_handler_impls
             #handler_trait
             #impl_handler_trait
+            #client_trait
         }
     }
 
@@ -337,7 +362,11 @@ impl AbstractProcess {
         let message_wrappers = self.message_handlers.iter().map(|impl_item_method| {
             let ident = Self::handler_wrapper_ident(&impl_item_method.sig.ident);
             let (_, generics, _) = &self.item_impl.generics.split_for_impl();
-            quote! { lunatic::ap::handlers::Message<#ident #generics>, }
+            if self.priority_handlers.contains(&impl_item_method.sig.ident) {
+                quote! { lunatic::ap::handlers::PriorityMessage<#ident #generics>, }
+            } else {
+                quote! { lunatic::ap::handlers::Message<#ident #generics>, }
+            }
         });
         let request_wrappers = self.request_handlers.iter().map(|impl_item_method| {
             let ident = Self::handler_wrapper_ident(&impl_item_method.sig.ident);
@@ -862,6 +891,236 @@ impl AbstractProcess {
         }
     }
 
+    /// Expands the client trait, if `client_trait_name` or `client_trait` is
+    /// specified.
+    ///
+    /// ```ignore
+    /// trait CounterClient {
+    ///     fn increment(&self);
+    ///     fn count(&self) -> u32;
+    /// }
+    ///
+    /// impl CounterClient for ProcessRef<Counter> { ... }
+    /// ```
+    fn expand_client_trait(&self) -> TokenStream {
+        let Self {
+            args,
+            item_impl,
+            message_handlers,
+            request_handlers,
+            deferred_request_handlers,
+            message_trait_name,
+            request_trait_name,
+            ..
+        } = self;
+        let self_ty = &item_impl.self_ty;
+        let (impl_generics, ty_generics, where_clause) = item_impl.generics.split_for_impl();
+        let (client_trait, definition) = match (&args.client_trait_name, &args.client_trait) {
+            (Some(name), _) => {
+                let name = format_ident!("{}", name.value());
+                (quote! { #name #ty_generics }, Some(name))
+            }
+            (None, Some(path)) => (quote! { #path }, None),
+            (None, None) => return TokenStream::new(),
+        };
+
+        let handlers = message_handlers
+            .iter()
+            .map(|handler| (handler, false, message_trait_name))
+            .chain(
+                request_handlers
+                    .iter()
+                    .map(|handler| (handler, false, request_trait_name)),
+            )
+            .chain(
+                deferred_request_handlers
+                    .iter()
+                    .map(|handler| (handler, true, request_trait_name)),
+            )
+            .map(|(handler, is_deferred, trait_name)| {
+                let mut handler = HandlerStructure::from_handler((handler, is_deferred));
+                if is_deferred {
+                    // Remove the `DeferredResponse` argument.
+                    handler.args.pop();
+                    handler.handler_args.pop();
+                }
+                (handler, trait_name)
+            })
+            .collect::<Vec<_>>();
+
+        let method_defs = handlers.iter().map(|(handler, _)| {
+            let HandlerStructure {
+                attrs,
+                ident,
+                generics,
+                args,
+                return_ty,
+                ..
+            } = handler;
+            quote! {
+                #( #attrs )*
+                fn #ident #generics (&self #(, #args )*) -> #return_ty;
+            }
+        });
+        let method_impls = handlers.iter().map(|(handler, trait_name)| {
+            let HandlerStructure {
+                attrs,
+                ident,
+                generics,
+                args,
+                return_ty,
+                handler_args,
+                ..
+            } = handler;
+            quote! {
+                #( #attrs )*
+                fn #ident #generics (&self #(, #args )*) -> #return_ty {
+                    <Self as #trait_name #ty_generics>::#ident(self #(, #handler_args )*)
+                }
+            }
+        });
+
+        let definition = definition.map(|name| {
+            let vis = &args.visibility;
+            quote! {
+                #vis trait #name #ty_generics #where_clause {
+                    #( #method_defs )*
+                }
+            }
+        });
+        quote! {
+            #definition
+
+            impl #impl_generics #client_trait for lunatic::ap::ProcessRef<#self_ty> #where_clause {
+                #( #method_impls )*
+            }
+        }
+    }
+
+    /// Expands assertions that the arguments and responses of the handlers
+    /// can be serialized.
+    ///
+    /// The wrapper structs derive `Serialize` and `Deserialize`, so a type
+    /// without them fails there too, but with errors about generated code.
+    /// Each assertion reports its own message, pointing at the type that was
+    /// written in the handler.
+    ///
+    /// ```ignore
+    /// const _: () = {
+    ///     #[diagnostic::on_unimplemented(message = "argument `a` of `Counter::add` ...")]
+    ///     trait Serializable {}
+    ///     ...
+    ///     fn check() { assert_serializable::<u32>(); }
+    /// };
+    /// ```
+    fn expand_serde_assertions(&self) -> TokenStream {
+        let self_ident = &self.self_ident;
+        let handlers = self
+            .message_handlers
+            .iter()
+            .map(|handler| (handler, "a message", false))
+            .chain(
+                self.request_handlers
+                    .iter()
+                    .map(|handler| (handler, "a request", false)),
+            )
+            .chain(
+                self.deferred_request_handlers
+                    .iter()
+                    .map(|handler| (handler, "a request", true)),
+            );
+
+        let mut assertions = Vec::new();
+        for (handler, kind, is_deferred) in handlers {
+            let method = format!("{}::{}", self_ident, handler.sig.ident);
+            let mut args: Vec<_> = filter_typed_arg_names(handler.sig.inputs.iter()).collect();
+            if is_deferred {
+                // The `DeferredResponse` isn't sent.
+                args.pop();
+            }
+            for (ident, ty) in args {
+                let message = format!(
+                    "argument `{}` of `{}` does not implement `Serialize`/`Deserialize`",
+                    ident, method
+                );
+                let label = format!("required because it is used in {} to `{}`", kind, method);
+                assertions.push(self.expand_serde_assertion(ty, &message, &label));
+            }
+            if kind == "a request" {
+                let response = HandlerStructure::from_handler((handler, is_deferred)).return_ty;
+                let message = format!(
+                    "the response of `{}` does not implement `Serialize`/`Deserialize`",
+                    method
+                );
+                let label = format!("required because it is sent back from `{}`", method);
+                assertions.push(self.expand_serde_assertion(&response, &message, &label));
+            }
+        }
+
+        quote! {
+            #( #assertions )*
+        }
+    }
+
+    /// Expands a single assertion that `ty` can be serialized, reporting
+    /// `message` and `label` if it can't.
+    ///
+    /// Types that depend on the generics of the impl are skipped, they are
+    /// only known once the abstract process is used.
+    fn expand_serde_assertion(
+        &self,
+        ty: &impl ToTokens,
+        message: &str,
+        label: &str,
+    ) -> TokenStream {
+        let ty = ty.to_token_stream();
+        if self.mentions_generics(ty.clone()) {
+            return TokenStream::new();
+        }
+        let check = quote_spanned! {ty.span()=>
+            #[allow(dead_code)]
+            fn check() {
+                assert_serializable::<#ty>();
+            }
+        };
+
+        quote! {
+            const _: () = {
+                #[diagnostic::on_unimplemented(
+                    message = #message,
+                    label = #label,
+                    note = "messages to abstract processes are serialized, derive `serde::Serialize` and `serde::Deserialize` for the type"
+                )]
+                trait Serializable {}
+
+                #[diagnostic::do_not_recommend]
+                impl<T: serde::Serialize + serde::de::DeserializeOwned> Serializable for T {}
+
+                fn assert_serializable<T: Serializable>() {}
+
+                #check
+            };
+        }
+    }
+
+    /// Returns `true` if `tokens` use a generic parameter of the impl, or
+    /// `Self`.
+    fn mentions_generics(&self, tokens: TokenStream) -> bool {
+        let params = &self.item_impl.generics.params;
+        tokens.into_iter().any(|token| match token {
+            proc_macro2::TokenTree::Ident(ident) => {
+                ident == "Self"
+                    || params.iter().any(|param| match param {
+                        syn::GenericParam::Type(param) => param.ident == ident,
+                        syn::GenericParam::Const(param) => param.ident == ident,
+                        syn::GenericParam::Lifetime(_) => false,
+                    })
+            }
+            proc_macro2::TokenTree::Group(group) => self.mentions_generics(group.stream()),
+            _ => false,
+        })
+    }
+
     /// Create a wrapper name for the request and send
     fn handler_wrapper_ident(ident: impl ToString) -> syn::Ident {
         format_ident!("__MsgWrap{}", ident.to_string().to_case(Case::Pascal))
@@ -874,6 +1133,8 @@ pub struct Args {
     request_trait_name: Option<syn::LitStr>,
     visibility: Option<syn::Visibility>,
     serializer: Option<syn::Type>,
+    client_trait_name: Option<syn::LitStr>,
+    client_trait: Option<syn::Path>,
 }
 
 impl Args {
@@ -920,6 +1181,19 @@ impl Args {
             }
 
             self.serializer = Some(input.parse()?);
+        } else if ident == "client_trait_name" || ident == "client_trait" {
+            if self.client_trait_name.is_some() || self.client_trait.is_some() {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "client trait already specified",
+                ));
+            }
+
+            if ident == "client_trait_name" {
+                self.client_trait_name = Some(input.parse()?);
+            } else {
+                self.client_trait = Some(input.parse()?);
+            }
         } else {
             return Err(syn::Error::new(ident.span(), "unknown argument"));
         }
@@ -944,6 +1218,7 @@ enum ItemAttr {
     Terminate,
     HandleLinkTrapped,
     HandleMessage,
+    HandlePriority,
     HandleRequest,
     HandleDeferredRequest,
 }
@@ -955,6 +1230,7 @@ impl ItemAttr {
             "terminate" => Some(ItemAttr::Terminate),
             "handle_link_death" => Some(ItemAttr::HandleLinkTrapped),
             "handle_message" => Some(ItemAttr::HandleMessage),
+            "handle_priority" => Some(ItemAttr::HandlePriority),
             "handle_request" => Some(ItemAttr::HandleRequest),
             "handle_deferred_request" => Some(ItemAttr::HandleDeferredRequest),
             _ => None,
@@ -962,6 +1238,31 @@ impl ItemAttr {
     }
 }
 
+/// Returns an error if two handlers would get the same wrapper type.
+///
+/// The wrapper types are named after the methods in Pascal case, so e.g.
+/// `add_1` and `add1` would both be handled by the wrapper `__MsgWrapAdd1`.
+fn check_wrapper_collisions<'a>(
+    handlers: impl Iterator<Item = &'a syn::ImplItemMethod>,
+) -> syn::Result<()> {
+    let mut wrappers: HashMap<syn::Ident, &syn::Ident> = HashMap::new();
+    for handler in handlers {
+        let ident = &handler.sig.ident;
+        let wrapper = AbstractProcess::handler_wrapper_ident(ident);
+        if let Some(first) = wrappers.get(&wrapper) {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!(
+                    "handlers `{}` and `{}` would use the same message type `{}`, rename one of them",
+                    first, ident, wrapper
+                ),
+            ));
+        }
+        wrappers.insert(wrapper, ident);
+    }
+    Ok(())
+}
+
 fn filter_typed_args<'a>(
     args: impl Iterator<Item = &'a syn::FnArg>,
 ) -> impl Iterator<Item = &'a syn::PatType> {
@@ -18,6 +18,45 @@ mod abstract_process;
 ///     println!("Hello, world!");
 /// }
 /// ```
+///
+/// # Trapping link failures
+///
+/// By default, the root process dies together with any linked process that
+/// fails, which ends the whole application. If the argument is a catching
+/// mailbox (`Mailbox<T, S, Catching>`), the root process is set up to catch
+/// link failures before `main` runs, the same as with
+/// `Mailbox::catch_link_failure`. Deaths of linked processes are then received
+/// as `MailboxResult::LinkDied`, and `lunatic::panic::link_panic` returns the
+/// panic of the linked process.
+///
+/// A panic hook set with `lunatic::panic::set_hook` still runs inside the
+/// failing process, before its death is reported to the root process.
+///
+/// ```ignore
+/// #[lunatic::main]
+/// fn main(mailbox: Mailbox<(), Bincode, Catching>) {
+///     let tag = Tag::new();
+///     Process::spawn_link_tag((), tag, worker);
+///     if let MailboxResult::LinkDied(_) = mailbox.receive() {
+///         println!("worker failed: {:?}", lunatic::panic::link_panic(tag));
+///     }
+/// }
+/// ```
+///
+/// # Returning errors
+///
+/// `main` can return `Result<(), E>` with any `E: Debug`. An error is printed
+/// to stderr and the application exits with status 1, the same as a `main`
+/// function without the macro. `lunatic::exit` sets another status.
+///
+/// ```ignore
+/// #[lunatic::main]
+/// fn main(_: Mailbox<()>) -> std::io::Result<()> {
+///     let listener = TcpListener::bind("127.0.0.1:8080")?;
+///     // ...
+///     Ok(())
+/// }
+/// ```
 #[allow(clippy::needless_doctest_main)]
 #[proc_macro_attribute]
 pub fn main(_args: TokenStream, item: TokenStream) -> TokenStream {
@@ -34,14 +73,16 @@ pub fn main(_args: TokenStream, item: TokenStream) -> TokenStream {
     }
 
     let arguments = input.sig.inputs;
+    let output = input.sig.output;
     let block = input.block;
 
     quote! {
         fn main() {
-            fn __with_mailbox(#arguments) {
+            fn __with_mailbox(#arguments) #output {
                 #block
             }
-            unsafe { __with_mailbox(lunatic::Mailbox::new()) };
+            let result = unsafe { __with_mailbox(lunatic::__MainMailbox::main_mailbox()) };
+            lunatic::__MainResult::report(result);
         }
     }
     .into()
@@ -55,6 +96,9 @@ pub fn main(_args: TokenStream, item: TokenStream) -> TokenStream {
 /// - Use `#[handle_message]`, `#[handle_request]` and
 ///   `#[handle_deferred_request]` attributes to specify message and request
 ///   handlers.
+/// - Use `#[handle_priority]` instead of `#[handle_message]` for message
+///   handlers that should be delivered through the priority lane, see
+///   [`Process::send_priority`].
 ///
 /// Specifying message types is unnecessary because the macro will create
 /// wrapper types for messages on all handlers. Handlers can take an arbitrary
@@ -66,6 +110,35 @@ pub fn main(_args: TokenStream, item: TokenStream) -> TokenStream {
 /// the generated trait, you can use the `trait_name` and `visbility` arguments
 /// with `#[abstract_process(trait_name = "MyHandler", visibility = pub)]`.
 ///
+/// # Client traits
+///
+/// The generated traits are implemented for `ProcessRef<MyType>`, so callers
+/// need the server type in scope. With `client_trait_name = "MyClient"`, a
+/// trait with a method for each handler is also generated, returning the
+/// responses directly. Code that only calls the methods of the trait, e.g. a
+/// function taking `&impl MyClient`, doesn't depend on the server type.
+///
+/// To move the interface into another crate, define the trait there by hand
+/// and use `client_trait = path::to::MyClient` to only implement it. The
+/// methods must have the same names, arguments and return types as the
+/// handlers, and the argument and return types are the messages sent on the
+/// wire, so both sides agree on them by depending on the interface crate.
+///
+/// The client trait has the same methods as the generated traits, so only one
+/// of them should be in scope where the methods are called.
+///
+/// ```ignore
+/// // In the interface crate.
+/// pub trait CounterClient {
+///     fn increment(&self);
+///     fn count(&self) -> u32;
+/// }
+///
+/// // In the server crate.
+/// #[abstract_process(client_trait = interface::CounterClient)]
+/// impl Counter { ... }
+/// ```
+///
 /// # Examples
 ///
 /// ```ignore
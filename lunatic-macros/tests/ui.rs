@@ -0,0 +1,7 @@
+// The UI tests compile with the host toolchain, they can't run inside lunatic.
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,26 @@
+use lunatic::abstract_process;
+use lunatic::ap::Config;
+
+#[allow(dead_code)]
+struct Counter(u32);
+
+#[abstract_process]
+impl Counter {
+    #[init]
+    fn init(_: Config<Self>, start: u32) -> Result<Self, ()> {
+        Ok(Self(start))
+    }
+
+    #[handle_message]
+    fn add_1(&mut self) {
+        self.0 += 1;
+    }
+
+    #[handle_request]
+    fn add1(&mut self) -> u32 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+fn main() {}
@@ -0,0 +1,25 @@
+use lunatic::abstract_process;
+use lunatic::ap::Config;
+
+struct Counter(u32);
+
+// Doesn't derive `Serialize` and `Deserialize`.
+struct Amount(u32);
+
+#[abstract_process]
+impl Counter {
+    #[init]
+    fn init(_: Config<Self>, start: u32) -> Result<Self, ()> {
+        Ok(Self(start))
+    }
+
+    #[handle_message]
+    fn add(&mut self, amount: Amount) {
+        self.0 += amount.0;
+    }
+}
+
+fn main() {
+    let counter = Counter::link().start(0).unwrap();
+    counter.add(Amount(1));
+}
@@ -7,9 +7,106 @@ use syn::spanned::Spanned;
 
 /// Marks function to be executed by the lunatic runtime as a unit test. This is
 /// a drop-in replacement for the standard `#[test]` attribute macro.
+///
+/// # Arguments
+///
+/// - `timeout = <milliseconds>` - Fails the test if it doesn't finish in the
+///   given time. By default, tests don't have a timeout.
+/// - `max_memory = <bytes>` & `max_fuel = <units>` - Runs the test inside a
+///   process with the given limits. If the process exceeds a limit, the test
+///   fails with a message naming it.
+/// - `config = "path::to::function"` - Runs the test inside a process spawned
+///   with the [`ProcessConfig`](../lunatic/struct.ProcessConfig.html) returned
+///   from the function. It can be combined with `max_memory` & `max_fuel`.
+/// - `fail_on_child_panic` - Fails the test if any process spawned by it
+///   panics, even if the test body finished. Child panics are always printed
+///   as part of a failed test's output.
+/// - `virtual_time` - Runs the test and the processes it spawns on a virtual
+///   clock, see
+///   [`lunatic::test::virtual_time`](../lunatic/test/fn.virtual_time.html).
+///   Sleeps and timeouts finish as soon as all processes are waiting on time,
+///   so tests of timeouts and retries don't wait in real time.
+///
+/// ```ignore
+/// #[lunatic::test(timeout = 5000)]
+/// fn finishes_in_time() {}
+///
+/// #[lunatic::test(max_memory = 10_000_000)]
+/// fn stays_under_10mb() {}
+///
+/// #[lunatic::test(virtual_time)]
+/// fn retries_for_an_hour() {}
+/// ```
 #[proc_macro_attribute]
-pub fn test(_args: TokenStream, item: TokenStream) -> TokenStream {
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(args as syn::AttributeArgs);
     let input = syn::parse_macro_input!(item as syn::ItemFn);
+
+    // Parse arguments passed to the `#[lunatic::test(...)]` macro.
+    let mut timeout = None;
+    let mut max_memory = None;
+    let mut max_fuel = None;
+    let mut config = None;
+    let mut fail_on_child_panic = false;
+    let mut virtual_time = false;
+    for arg in args.iter() {
+        let name_value = match arg {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(name_value)) => name_value,
+            syn::NestedMeta::Meta(syn::Meta::Path(path))
+                if path.is_ident("fail_on_child_panic") =>
+            {
+                fail_on_child_panic = true;
+                continue;
+            }
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("virtual_time") => {
+                virtual_time = true;
+                continue;
+            }
+            _ => return unknown_argument(arg),
+        };
+        let name = match name_value.path.get_ident() {
+            Some(name) => name.to_string(),
+            None => return unknown_argument(arg),
+        };
+        match name.as_str() {
+            "timeout" | "max_memory" | "max_fuel" => {
+                let value = match &name_value.lit {
+                    syn::Lit::Int(lit) => match lit.base10_parse::<u64>() {
+                        Ok(value) => value,
+                        Err(err) => return err.to_compile_error().into(),
+                    },
+                    _ => {
+                        let message =
+                            format!("argument must be of the form: `{} = <integer>`", name);
+                        return syn::Error::new_spanned(&name_value.lit, message)
+                            .to_compile_error()
+                            .into();
+                    }
+                };
+                match name.as_str() {
+                    "timeout" => timeout = Some(value),
+                    "max_memory" => max_memory = Some(value),
+                    _ => max_fuel = Some(value),
+                }
+            }
+            "config" => match &name_value.lit {
+                syn::Lit::Str(lit) => match lit.parse::<syn::Path>() {
+                    Ok(path) => config = Some(path),
+                    Err(err) => return err.to_compile_error().into(),
+                },
+                _ => {
+                    return syn::Error::new_spanned(
+                        &name_value.lit,
+                        "argument must be of the form: `config = \"path::to::function\"`",
+                    )
+                    .to_compile_error()
+                    .into()
+                }
+            },
+            _ => return unknown_argument(arg),
+        }
+    }
+
     let original_input = input.clone();
     let attributes = &input.attrs;
     let span = input.span();
@@ -76,12 +173,9 @@ pub fn test(_args: TokenStream, item: TokenStream) -> TokenStream {
         }
     }
 
-    let mut export_name = format!("#lunatic_test_{}", ignore);
-    if let Some(ref panic_str) = should_panic {
-        // Escape # in panic_str
-        let panic_str = panic_str.replace('#', "\\#");
-        export_name = format!("{}#panic_{}#", export_name, panic_str,);
-    }
+    // Panics are checked inside the test process, against the expected panic
+    // message, and don't need to be encoded into the export name.
+    let export_name = format!("#lunatic_test_{}", ignore);
     let function_name = input.sig.ident.to_string();
 
     let name = input.sig.ident;
@@ -108,13 +202,104 @@ pub fn test(_args: TokenStream, item: TokenStream) -> TokenStream {
         quote! {}
     };
 
+    // The guard kills the test process if it runs longer than the timeout.
+    let timeout_guard = match timeout {
+        Some(timeout) => quote! {
+            let __timeout_guard = lunatic::test::timeout_guard(
+                concat!(module_path!(), "::", #function_name),
+                #timeout,
+            );
+        },
+        None => quote! {},
+    };
+
+    let run_test = match should_panic {
+        // The test process should only succeed if the body panics with the expected message.
+        Some(expected) => quote! {
+            lunatic::test::assert_test_panics(
+                concat!(module_path!(), "::", #function_name),
+                #expected,
+                || unsafe { __with_mailbox(#mailbox) },
+            );
+        },
+        None => quote! {
+            let result = unsafe { __with_mailbox(#mailbox) };
+            lunatic::test::assert_test_result(result);
+        },
+    };
+
+    // If any process limits are set, the test body runs inside a linked
+    // process spawned with the configuration.
+    let run_test = if config.is_some() || max_memory.is_some() || max_fuel.is_some() {
+        let config = match config {
+            Some(config) => quote! { #config() },
+            None => quote! { lunatic::test::default_config() },
+        };
+        let mut limits = Vec::new();
+        if let Some(max_memory) = max_memory {
+            limits.push(quote! { ("max_memory", #max_memory) });
+        }
+        if let Some(max_fuel) = max_fuel {
+            limits.push(quote! { ("max_fuel", #max_fuel) });
+        }
+        let set_max_memory =
+            max_memory.map(|max_memory| quote! { config.set_max_memory(#max_memory); });
+        let set_max_fuel = max_fuel.map(|max_fuel| quote! { config.set_max_fuel(#max_fuel); });
+        quote! {
+            fn __run_test() {
+                #run_test
+            }
+            fn __config_entry(
+                parent: lunatic::Process<lunatic::test::ConfigTestEvent>,
+                _: lunatic::Mailbox<()>,
+            ) {
+                lunatic::test::config_test_entry(parent, __run_test);
+            }
+            #[allow(unused_mut)]
+            let mut config: lunatic::ProcessConfig = #config;
+            #set_max_memory
+            #set_max_fuel
+            lunatic::test::run_with_config(
+                concat!(module_path!(), "::", #function_name),
+                config,
+                &[#(#limits),*],
+                __config_entry,
+            );
+        }
+    } else {
+        run_test
+    };
+
+    // The clock is started after the watchers above, so they keep using real
+    // time, and stopped before the child panics are collected.
+    let run_test = if virtual_time {
+        quote! {
+            {
+                let __virtual_time = lunatic::test::virtual_time();
+                #run_test
+            }
+        }
+    } else {
+        run_test
+    };
+
+    // Panics of processes spawned by the test are attributed to it.
+    let run_test = quote! {
+        let __children = lunatic::test::track_children(
+            concat!(module_path!(), "::", #function_name),
+            #fail_on_child_panic,
+        );
+        #run_test
+        __children.finish();
+    };
+
     let wasm32_test = quote! {
         fn #name() {
             fn __with_mailbox(#arguments) #output {
                 #block
             }
-            let result = unsafe { __with_mailbox(#mailbox) };
-            lunatic::test::assert_test_result(result);
+            #timeout_guard
+            #run_test
         }
     };
 
@@ -133,3 +318,13 @@ pub fn test(_args: TokenStream, item: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+fn unknown_argument(arg: &syn::NestedMeta) -> TokenStream {
+    syn::Error::new_spanned(
+        arg,
+        "unknown argument, expected one of: `timeout`, `max_memory`, `max_fuel`, `config`, \
+         `fail_on_child_panic`, `virtual_time`",
+    )
+    .to_compile_error()
+    .into()
+}
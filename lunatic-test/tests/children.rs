@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+use lunatic::{sleep, Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn child_panic_doesnt_fail_test() {
+    Process::spawn((), |_, _: Mailbox<()>| panic!("child panicked"));
+    sleep(Duration::from_millis(50));
+}
+
+#[test(fail_on_child_panic)]
+fn children_finish_without_panic() {
+    let child = Process::spawn((), |_, mailbox: Mailbox<u64>| {
+        assert_eq!(mailbox.receive(), 1);
+    });
+    child.send(1);
+    sleep(Duration::from_millis(50));
+}
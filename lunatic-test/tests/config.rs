@@ -0,0 +1,70 @@
+use lunatic::test::{config_test_entry, default_config, run_with_config, ConfigTestEvent};
+use lunatic::{Mailbox, Process, ProcessConfig};
+use lunatic_test::test;
+
+fn no_spawn_config() -> ProcessConfig {
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_can_spawn_processes(false);
+    config
+}
+
+#[test(max_memory = 100_000_000)]
+fn stays_under_memory_limit() {
+    let buffer = vec![0u8; 1_000_000];
+    assert_eq!(buffer.len(), 1_000_000);
+}
+
+#[test(max_memory = 100_000_000, max_fuel = 10_000)]
+fn mailbox_under_limits(mailbox: Mailbox<u64>) {
+    mailbox.this().send(42);
+    assert_eq!(mailbox.receive(), 42);
+}
+
+#[test(max_memory = 100_000_000)]
+#[should_panic(expected = "inside config")]
+fn panics_inside_config() {
+    panic!("panicked inside config");
+}
+
+fn exceeds_memory_limit_entry(parent: Process<ConfigTestEvent>, _: Mailbox<()>) {
+    config_test_entry(parent, || {
+        let buffer = vec![1u8; 100_000_000];
+        assert_eq!(buffer.len(), 100_000_000);
+    });
+}
+
+// The test process reports the limit, so it can't be checked with
+// `#[test(max_memory = ...)]` and `#[should_panic]`, which only cover the
+// spawned process.
+#[test]
+#[should_panic(expected = "exceeded the process limit `max_memory = 10000000`")]
+fn exceeds_memory_limit() {
+    let mut config = default_config();
+    config.set_max_memory(10_000_000);
+    run_with_config(
+        "exceeds_memory_limit",
+        config,
+        &[("max_memory", 10_000_000)],
+        exceeds_memory_limit_entry,
+    );
+}
+
+#[test]
+#[should_panic(expected = "one of the process limits `max_memory = 10000000`, `max_fuel = 1`")]
+fn reports_all_limits() {
+    let mut config = default_config();
+    config.set_max_memory(10_000_000);
+    config.set_max_fuel(1);
+    run_with_config(
+        "reports_all_limits",
+        config,
+        &[("max_memory", 10_000_000), ("max_fuel", 1)],
+        exceeds_memory_limit_entry,
+    );
+}
+
+#[test(config = "no_spawn_config")]
+#[should_panic]
+fn spawn_permission_denied() {
+    Process::spawn((), |_, _: Mailbox<()>| {});
+}
@@ -12,3 +12,22 @@ fn assert_failed() {
 fn panic_failed() {
     // Didn't panic
 }
+
+#[test]
+#[ignore]
+#[should_panic(expected = "expected")]
+fn panic_message_mismatch() {
+    panic!("something else");
+}
+
+#[test(fail_on_child_panic)]
+#[ignore]
+fn child_panicked() {
+    lunatic::Process::spawn((), |_, _: lunatic::Mailbox<()>| {
+        lunatic::Process::spawn((), |_, _: lunatic::Mailbox<()>| {
+            panic!("grandchild panicked")
+        });
+        lunatic::sleep(std::time::Duration::from_millis(50));
+    });
+    lunatic::sleep(std::time::Duration::from_millis(100));
+}
@@ -23,3 +23,9 @@ fn assert_failure_is_also_a_panic() {
 fn hashtag_works_in_panic_string() {
     panic!("#")
 }
+
+#[test]
+#[should_panic(expected = "formatted 42")]
+fn formatted_panic_message() {
+    panic!("formatted {}", 42);
+}
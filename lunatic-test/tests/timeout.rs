@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use lunatic::sleep;
+use lunatic_test::test;
+
+#[test(timeout = 1000)]
+fn finishes_before_timeout() {
+    sleep(Duration::from_millis(10));
+}
+
+#[test(timeout = 100)]
+#[ignore]
+fn exceeds_timeout() {
+    sleep(Duration::from_millis(1000));
+}
+
+#[test(timeout = 1000)]
+#[should_panic]
+fn panics_before_timeout() {
+    panic!("fails");
+}
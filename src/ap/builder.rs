@@ -1,5 +1,7 @@
 use std::marker::PhantomData;
 
+use super::snapshot::{SnapshotSetup, WITH_SNAPSHOTS};
+use super::tag::AbstractProcessTag;
 use super::{lifecycles, AbstractProcess, ProcessRef, StartupError};
 use crate::{host, Mailbox, Process, ProcessConfig, Tag};
 
@@ -17,6 +19,7 @@ pub struct AbstractProcessBuilder<'a, T: ?Sized> {
     link: Option<Tag>,
     config: Option<&'a ProcessConfig>,
     node: Option<u64>,
+    snapshots: Option<SnapshotSetup>,
     phantom: PhantomData<T>,
 }
 
@@ -29,6 +32,7 @@ where
             link: None,
             config: None,
             node: None,
+            snapshots: None,
             phantom: PhantomData,
         }
     }
@@ -39,6 +43,7 @@ where
             link: Some(Tag::new()),
             config: self.config,
             node: self.node,
+            snapshots: self.snapshots,
             phantom: PhantomData,
         }
     }
@@ -49,6 +54,7 @@ where
             link: Some(tag),
             config: self.config,
             node: self.node,
+            snapshots: self.snapshots,
             phantom: PhantomData,
         }
     }
@@ -59,6 +65,7 @@ where
             link: self.link,
             config: Some(config),
             node: self.node,
+            snapshots: self.snapshots,
             phantom: PhantomData,
         }
     }
@@ -69,6 +76,22 @@ where
             link: self.link,
             config: self.config,
             node: Some(node),
+            snapshots: self.snapshots,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Sets the snapshot the process is restored from and the process keeping
+    /// its future snapshots.
+    pub(crate) fn with_snapshots(
+        self,
+        setup: Option<SnapshotSetup>,
+    ) -> AbstractProcessBuilder<'a, T> {
+        AbstractProcessBuilder {
+            link: self.link,
+            config: self.config,
+            node: self.node,
+            snapshots: setup,
             phantom: PhantomData,
         }
     }
@@ -78,10 +101,11 @@ where
     /// This call will block until the `init` function finishes. If the `init`
     /// function returns an error, it will be returned as
     /// `StartupError::Custom(error)`. If the `init` function panics during
-    /// execution, it will return [`StartupError::InitPanicked`].
+    /// execution, it will return [`StartupError::InitPanicked`], and the panic
+    /// is available with [`last_init_panic`](super::last_init_panic).
     #[track_caller]
     pub fn start(&self, arg: T::Arg) -> Result<ProcessRef<T>, StartupError<T>> {
-        let init_tag = Tag::new();
+        let init_tag = self.init_tag();
         let process = self.start_without_wait_on_init(arg, init_tag);
 
         // Wait on `init()`
@@ -89,7 +113,12 @@ where
             unsafe { Mailbox::new() };
         match mailbox.tag_receive(&[init_tag]) {
             Ok(()) => Ok(ProcessRef { process }),
-            Err(err) => Err(err),
+            Err(err) => {
+                if let StartupError::InitPanicked = err {
+                    lifecycles::store_init_panic(init_tag);
+                }
+                Err(err)
+            }
         }
     }
 
@@ -112,8 +141,7 @@ where
         name: S,
         arg: T::Arg,
     ) -> Result<ProcessRef<T>, StartupError<T>> {
-        let name: &str = name.as_ref();
-        let name = format!("{} + ProcessRef + {}", name, std::any::type_name::<T>());
+        let name = ProcessRef::<T>::registry_name(name.as_ref());
         let mut node_id: u64 = 0;
         let mut process_id: u64 = 0;
         unsafe {
@@ -123,11 +151,14 @@ where
                 &mut node_id,
                 &mut process_id,
             ) {
-                0 => Err(StartupError::NameAlreadyRegistered(ProcessRef::new(
-                    node_id, process_id,
-                ))),
+                // A local process that isn't running anymore is replaced.
+                0 if node_id != host::node_id() || host::api::process::exists(process_id) != 0 => {
+                    Err(StartupError::NameAlreadyRegistered(ProcessRef::new(
+                        node_id, process_id,
+                    )))
+                }
                 _ => {
-                    let init_tag = Tag::new();
+                    let init_tag = self.init_tag();
                     let process = self.start_without_wait_on_init(arg, init_tag);
                     // Register the name
                     host::api::registry::put(
@@ -144,6 +175,9 @@ where
                         Err(err) => {
                             // In case of an error during `init`, unregister the process.
                             host::api::registry::remove(name.as_ptr(), name.len());
+                            if let StartupError::InitPanicked = err {
+                                lifecycles::store_init_panic(init_tag);
+                            }
                             Err(err)
                         }
                     }
@@ -152,13 +186,23 @@ where
         }
     }
 
+    /// Returns the tag used to notify the parent that `init` finished.
+    ///
+    /// It also tells the new process if a [`SnapshotSetup`] is sent to it.
+    fn init_tag(&self) -> Tag {
+        match self.snapshots {
+            Some(_) => AbstractProcessTag::from_u6(WITH_SNAPSHOTS),
+            None => Tag::new(),
+        }
+    }
+
     /// The startup code is the same for `start` and `start_as`, but can't wait
     /// on the result in both cases to avoid deadlocks with the registry.
     #[track_caller]
     fn start_without_wait_on_init(&self, arg: T::Arg, init_tag: Tag) -> Process<(), T::Serializer> {
         let this = Process::<Result<(), StartupError<T>>, T::Serializer>::this();
         let entry_data = (this, init_tag, arg);
-        match (self.link, &self.config, self.node) {
+        let process = match (self.link, &self.config, self.node) {
             (Some(_), _, Some(_node)) => {
                 unimplemented!("Linking across nodes is not supported yet");
             }
@@ -190,6 +234,11 @@ where
             (None, None, None) => {
                 Process::<(), T::Serializer>::spawn(entry_data, lifecycles::entry::<T>)
             }
+        };
+        if let Some(setup) = &self.snapshots {
+            let child = Process::<SnapshotSetup>::new(process.node_id(), process.id());
+            child.tag_send(init_tag, setup.clone());
         }
+        process
     }
 }
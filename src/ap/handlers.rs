@@ -3,29 +3,81 @@
 use std::any::{type_name, TypeId};
 use std::marker::PhantomData;
 
-use super::messages::RequestMessage;
+use super::messages::{RequestMessage, ReturnAddress};
 use super::{AbstractProcess, DeferredRequestHandler, MessageHandler, RequestHandler};
 use crate::serializer::CanSerialize;
 use crate::Tag;
 
 pub struct Message<T>(PhantomData<T>);
+/// A [`Message`] delivered through the priority lane, see
+/// [`Process::send_priority`](crate::Process::send_priority).
+///
+/// It's sent with [`ProcessRef::send`](super::ProcessRef::send) like a
+/// regular message.
+pub struct PriorityMessage<T>(PhantomData<T>);
 pub struct Request<T>(PhantomData<T>);
 pub struct DeferredRequest<T>(PhantomData<T>);
 
 pub trait Handler<AP: AbstractProcess> {
+    /// `true` if messages to this handler use the priority lane.
+    const PRIORITY: bool = false;
+    /// `true` if messages to this handler start with a request header.
+    const REQUEST: bool = false;
+
     fn handle(response_tag: Tag, state: &mut AP::State);
+
+    /// Name of the handled type, used in metrics.
+    fn name() -> &'static str {
+        type_name::<Self>()
+    }
+
+    /// The type that is used to look up this handler.
+    #[doc(hidden)]
+    fn id() -> TypeId
+    where
+        Self: 'static,
+    {
+        TypeId::of::<Self>()
+    }
 }
 
 impl<AP, T> Handler<AP> for Message<T>
 where
     AP: MessageHandler<T>,
     AP::Serializer: CanSerialize<T>,
+    T: 'static,
 {
     fn handle(_: Tag, state: &mut <AP as AbstractProcess>::State) {
         let state = super::State { state };
-        let message = AP::Serializer::decode().unwrap();
+        let message = super::self_send::decode_static::<T, AP::Serializer>().unwrap();
+        crate::panic::set_handled_message(type_name::<T>());
         AP::handle(state, message);
     }
+
+    fn name() -> &'static str {
+        type_name::<T>()
+    }
+}
+
+impl<AP, T> Handler<AP> for PriorityMessage<T>
+where
+    AP: MessageHandler<T>,
+    AP::Serializer: CanSerialize<T>,
+    T: 'static,
+{
+    const PRIORITY: bool = true;
+
+    fn handle(response_tag: Tag, state: &mut <AP as AbstractProcess>::State) {
+        <Message<T> as Handler<AP>>::handle(response_tag, state);
+    }
+
+    fn name() -> &'static str {
+        type_name::<T>()
+    }
+
+    fn id() -> TypeId {
+        TypeId::of::<Message<T>>()
+    }
 }
 
 impl<AP, T> Handler<AP> for Request<T>
@@ -35,12 +87,21 @@ where
     AP::Serializer: CanSerialize<AP::Response>,
     AP::Serializer: CanSerialize<RequestMessage<T, AP::Response, AP::Serializer>>,
 {
+    const REQUEST: bool = true;
+
     fn handle(response_tag: Tag, state: &mut <AP as AbstractProcess>::State) {
         let state = super::State { state };
-        let request: RequestMessage<T, AP::Response, AP::Serializer> =
-            AP::Serializer::decode().unwrap();
-        let response = AP::handle(state, request.0);
-        request.1.send_response(response, response_tag);
+        let return_address = ReturnAddress::<AP::Response, AP::Serializer>::read();
+        let deadline = super::messages::read_deadline();
+        let request: T = AP::Serializer::decode().unwrap();
+        crate::panic::set_handled_message(type_name::<T>());
+        let response = crate::context::enter(deadline, || AP::handle(state, request));
+        super::journal::handled();
+        return_address.send_response(response, response_tag);
+    }
+
+    fn name() -> &'static str {
+        type_name::<T>()
     }
 }
 
@@ -51,24 +112,37 @@ where
     AP::Serializer: CanSerialize<AP::Response>,
     AP::Serializer: CanSerialize<RequestMessage<T, AP::Response, AP::Serializer>>,
 {
+    const REQUEST: bool = true;
+
     fn handle(response_tag: Tag, state: &mut <AP as AbstractProcess>::State) {
         let state = super::State { state };
-        let request: RequestMessage<T, AP::Response, AP::Serializer> =
-            AP::Serializer::decode().unwrap();
-        AP::handle(
-            state,
-            request.0,
-            super::DeferredResponse {
-                tag: response_tag,
-                return_address: request.1,
-            },
-        );
+        let return_address = ReturnAddress::<AP::Response, AP::Serializer>::read();
+        let deadline = super::messages::read_deadline();
+        let request: T = AP::Serializer::decode().unwrap();
+        crate::panic::set_handled_message(type_name::<T>());
+        let response = super::DeferredResponse {
+            tag: response_tag,
+            return_address,
+        };
+        crate::context::enter(deadline, || AP::handle(state, request, response));
+    }
+
+    fn name() -> &'static str {
+        type_name::<T>()
     }
 }
 
 pub trait Handlers<AP: AbstractProcess> {
     fn handler_id<Handler: 'static>() -> u8;
     fn handle(response_tag: Tag, id: u8, state: &mut AP::State);
+    /// Returns `true` if the handler `id` uses the priority lane.
+    fn is_priority(id: u8) -> bool;
+    /// Returns `true` if messages to the handler `id` are requests.
+    fn is_request(id: u8) -> bool;
+    /// Returns the name of the type handled by the handler `id`.
+    fn handler_name(id: u8) -> &'static str;
+    /// Returns the ids of all handlers that use the priority lane.
+    fn priority_handlers() -> Vec<u8>;
 }
 
 // Implement `Handlers` for tuple containing up to 16 handlers.
@@ -101,7 +175,7 @@ mod macros {
                 #[track_caller]
                 fn handler_id<Handler: 'static>() -> u8 {
                     match TypeId::of::<Handler>() {
-                        $(id if id == TypeId::of::<$args>() => $i,)*
+                        $(id if id == $args::id() => $i,)*
                         _ => panic!(
                             "Called `send/request()` on type '{}' that doesn't match any handler defined in '<{} as AbstractProcess>::Handlers'",
                             type_name::<Handler>(),
@@ -110,6 +184,39 @@ mod macros {
                     }
                 }
 
+                #[allow(unused_variables)]
+                fn is_priority(id: u8) -> bool {
+                    match id {
+                        $($i => $args::PRIORITY,)*
+                        _ => false,
+                    }
+                }
+
+                #[allow(unused_variables)]
+                fn is_request(id: u8) -> bool {
+                    match id {
+                        $($i => $args::REQUEST,)*
+                        _ => false,
+                    }
+                }
+
+                #[allow(unused_variables)]
+                fn handler_name(id: u8) -> &'static str {
+                    match id {
+                        $($i => $args::name(),)*
+                        _ => "unknown",
+                    }
+                }
+
+                fn priority_handlers() -> Vec<u8> {
+                    let handlers: &[(u8, bool)] = &[$(($i, $args::PRIORITY)),*];
+                    handlers
+                        .iter()
+                        .filter(|(_, priority)| *priority)
+                        .map(|(id, _)| *id)
+                        .collect()
+                }
+
                 #[allow(unused_variables)]
                 fn handle(response_tag: Tag, id: u8, state: &mut <AP as AbstractProcess>::State) {
                     match id {
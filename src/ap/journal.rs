@@ -0,0 +1,400 @@
+//! Write-ahead journal of the messages handled by an [`AbstractProcess`], used
+//! to rebuild its state after a restart.
+//!
+//! A process whose [`AbstractProcess::journal`] returns a [`Journal`] appends
+//! each message to it before the handler runs, and marks the message as
+//! handled once the handler returns. A new process started with the same
+//! journal calls [`AbstractProcess::init_from_journal`] with the last snapshot
+//! in the journal, and then handles the journaled messages that follow the
+//! snapshot again, in their original order, before it handles new messages.
+//!
+//! Each handled message is replayed exactly once, as long as the journal is
+//! only used by one process at a time. Messages whose handler didn't return,
+//! because the process panicked or was killed while handling them, are removed
+//! from the journal and aren't replayed.
+//!
+//! # Replay
+//!
+//! Handlers are called again during the replay, including their side effects,
+//! e.g. messages sent to other processes. Handlers can skip them while
+//! [`is_replaying`] returns `true`. Replies to replayed requests are dropped.
+//! Messages that the process sent to itself with
+//! [`ProcessRef::send`](super::ProcessRef::send) aren't journaled, the
+//! replayed handlers send them again.
+//!
+//! Only the serialized data of a message is journaled, not the resources that
+//! it carries, like a [`TcpStream`](crate::net::TcpStream). Messages with
+//! resources can't be replayed, their handlers fail to take the resources out
+//! of the replayed message.
+//!
+//! # Compaction
+//!
+//! After every [`compact_every`](Journal::compact_every) handled messages,
+//! the [`snapshot`](AbstractProcess::snapshot) of the state replaces the
+//! messages journaled before it. Processes that don't return snapshots keep
+//! all of their messages.
+//!
+//! # Cost
+//!
+//! Each message takes two SQLite statements, so journals are meant for a few
+//! processes whose state can't be lost, not for every process. The journal is
+//! a database on the node, restarting a process on another node with it is
+//! out of scope.
+//!
+//! # Example
+//!
+//! ```
+//! impl AbstractProcess for Counter {
+//!     // ...
+//!     fn journal(name: &String) -> Option<Journal> {
+//!         Some(Journal::open("counters.db", name).unwrap())
+//!     }
+//!
+//!     fn snapshot(state: &Counter) -> Option<Snapshot> {
+//!         Some(Snapshot::new(1, &state.count))
+//!     }
+//!
+//!     fn init_from_journal(
+//!         config: Config<Self>,
+//!         name: String,
+//!         snapshot: Option<Snapshot>,
+//!     ) -> Result<Counter, ()> {
+//!         let count = snapshot.and_then(|snapshot| snapshot.decode(1));
+//!         Ok(Counter { count: count.unwrap_or(0) })
+//!     }
+//! }
+//! ```
+
+use std::cell::{Cell, RefCell};
+
+use super::handlers::Handlers;
+use super::messages::{DEADLINE_SIZE, RETURN_ADDRESS_SIZE};
+use super::tag::AbstractProcessTag;
+use super::{pending, self_send, AbstractProcess, Config, Snapshot};
+use crate::host::api::message;
+use crate::sqlite::{Connection, Row, SqliteError, Statement, Value};
+use crate::{host, process_local};
+
+/// Number of handled messages between two compactions, if no other number is
+/// set.
+pub const DEFAULT_COMPACT_EVERY: u64 = 1000;
+
+process_local! {
+    // The journal of the current process, once it's initialized.
+    static JOURNAL: RefCell<Option<Journal>> = RefCell::new(None);
+    static REPLAYING: Cell<bool> = Cell::new(false);
+}
+
+/// Returns `true` while the current process handles messages replayed from
+/// its journal.
+pub fn is_replaying() -> bool {
+    REPLAYING.get()
+}
+
+/// The journal of an [`AbstractProcess`], stored in an SQLite database.
+///
+/// One database can hold many journals, each one is identified by its name.
+pub struct Journal {
+    connection: Connection,
+    name: String,
+    last_seq: i64,
+    // `true` until the last message is marked as handled.
+    unmarked: bool,
+    compact_every: u64,
+    since_compaction: u64,
+}
+
+impl Journal {
+    /// Opens the journal `name` in the database at `path`, creating both if
+    /// they don't exist.
+    pub fn open(path: &str, name: &str) -> Result<Self, SqliteError> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS lunatic_journal (
+                journal TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                handler INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                handled INTEGER NOT NULL,
+                PRIMARY KEY (journal, seq)
+            );
+            CREATE TABLE IF NOT EXISTS lunatic_journal_snapshot (
+                journal TEXT PRIMARY KEY,
+                seq INTEGER NOT NULL,
+                snapshot BLOB NOT NULL
+            );",
+        )?;
+        let mut journal = Journal {
+            connection,
+            name: name.to_owned(),
+            last_seq: 0,
+            unmarked: false,
+            compact_every: DEFAULT_COMPACT_EVERY,
+            since_compaction: 0,
+        };
+        let rows = journal.rows(
+            "SELECT MAX(seq) FROM (
+                SELECT seq FROM lunatic_journal WHERE journal = ?1
+                UNION ALL
+                SELECT seq FROM lunatic_journal_snapshot WHERE journal = ?1
+            )",
+            [],
+        )?;
+        if let Some(Value::Integer(seq)) = rows.first().and_then(|row| row.get(0)) {
+            journal.last_seq = *seq;
+        }
+        Ok(journal)
+    }
+
+    /// Sets the number of handled messages between two compactions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `messages` is 0.
+    pub fn compact_every(mut self, messages: u64) -> Self {
+        assert!(messages > 0, "the compaction interval must be at least 1");
+        self.compact_every = messages;
+        self
+    }
+
+    /// Returns the number of messages in the journal, that weren't compacted
+    /// yet.
+    pub fn len(&self) -> Result<u64, SqliteError> {
+        let rows = self.rows(
+            "SELECT COUNT(*) FROM lunatic_journal WHERE journal = ?1",
+            [],
+        )?;
+        match rows.first().and_then(|row| row.get(0)) {
+            Some(Value::Integer(len)) => Ok(*len as u64),
+            _ => Ok(0),
+        }
+    }
+
+    /// Returns `true` if the journal has no messages.
+    pub fn is_empty(&self) -> Result<bool, SqliteError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Removes all messages and the snapshot of the journal, the next process
+    /// started with it starts fresh.
+    pub fn clear(&mut self) -> Result<(), SqliteError> {
+        self.execute("DELETE FROM lunatic_journal WHERE journal = ?1", [])?;
+        self.execute(
+            "DELETE FROM lunatic_journal_snapshot WHERE journal = ?1",
+            [],
+        )?;
+        self.last_seq = 0;
+        self.since_compaction = 0;
+        Ok(())
+    }
+
+    /// Calls `init_from_journal` and replays the handled messages.
+    pub(crate) fn init<AP: AbstractProcess>(
+        &mut self,
+        config: Config<AP>,
+        arg: AP::Arg,
+    ) -> Result<AP::State, AP::StartupError> {
+        let snapshot = self.snapshot().expect("Failed to read the journal");
+        let mut state = AP::init_from_journal(config, arg, snapshot)?;
+        let messages = self.handled_messages().expect("Failed to read the journal");
+        REPLAYING.set(true);
+        for (handler, data) in messages {
+            replay::<AP>(&mut state, handler, data);
+        }
+        REPLAYING.set(false);
+        Ok(state)
+    }
+
+    // Appends the message in the buffer for the `handler`.
+    fn append(&mut self, handler: u8) {
+        let size = unsafe { message::data_size() } as usize;
+        let mut data = vec![0; size];
+        unsafe {
+            message::read_data(data.as_mut_ptr(), data.len());
+            message::seek_data(0);
+        }
+        self.last_seq += 1;
+        self.execute(
+            "INSERT INTO lunatic_journal VALUES (?1, ?2, ?3, ?4, 0)",
+            [
+                Value::Integer(self.last_seq),
+                Value::Integer(handler.into()),
+                Value::Blob(data),
+            ],
+        )
+        .expect("Failed to append to the journal");
+        self.unmarked = true;
+    }
+
+    fn mark_handled(&mut self) {
+        if !self.unmarked {
+            return;
+        }
+        self.execute(
+            "UPDATE lunatic_journal SET handled = 1 WHERE journal = ?1 AND seq = ?2",
+            [Value::Integer(self.last_seq)],
+        )
+        .expect("Failed to append to the journal");
+        self.unmarked = false;
+        self.since_compaction += 1;
+    }
+
+    // Compacts the journal if it's time to.
+    fn compact_if_due<AP: AbstractProcess>(&mut self, state: &AP::State) {
+        if self.since_compaction < self.compact_every {
+            return;
+        }
+        self.since_compaction = 0;
+        if let Some(snapshot) = AP::snapshot(state) {
+            self.compact(&snapshot)
+                .expect("Failed to compact the journal");
+        }
+    }
+
+    // Replaces the messages up to the last one with `snapshot`.
+    fn compact(&mut self, snapshot: &Snapshot) -> Result<(), SqliteError> {
+        let snapshot = bincode::serialize(snapshot).expect("Failed to serialize the snapshot");
+        // Messages would be replayed on top of a snapshot that includes them,
+        // if only the snapshot was written.
+        self.connection.execute("BEGIN")?;
+        let result = self
+            .execute(
+                "INSERT OR REPLACE INTO lunatic_journal_snapshot VALUES (?1, ?2, ?3)",
+                [Value::Integer(self.last_seq), Value::Blob(snapshot)],
+            )
+            .and_then(|()| {
+                self.execute(
+                    "DELETE FROM lunatic_journal WHERE journal = ?1 AND seq <= ?2",
+                    [Value::Integer(self.last_seq)],
+                )
+            });
+        match result {
+            Ok(()) => self.connection.execute("COMMIT"),
+            Err(err) => {
+                let _ = self.connection.execute("ROLLBACK");
+                Err(err)
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Result<Option<Snapshot>, SqliteError> {
+        let rows = self.rows(
+            "SELECT snapshot FROM lunatic_journal_snapshot WHERE journal = ?1",
+            [],
+        )?;
+        match rows.first().and_then(|row| row.get(0)) {
+            Some(Value::Blob(snapshot)) => bincode::deserialize(snapshot)
+                .map(Some)
+                .map_err(|err| SqliteError::Decode(err.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    // Removes the messages that weren't handled and returns the others.
+    fn handled_messages(&self) -> Result<Vec<(u8, Vec<u8>)>, SqliteError> {
+        self.execute(
+            "DELETE FROM lunatic_journal WHERE journal = ?1 AND handled = 0",
+            [],
+        )?;
+        let mut statement = self.prepare(
+            "SELECT handler, data FROM lunatic_journal WHERE journal = ?1 ORDER BY seq",
+            [],
+        )?;
+        let messages = statement
+            .query()
+            .map(|row| match row?.into_values().as_mut_slice() {
+                [Value::Integer(handler), Value::Blob(data)] => {
+                    Ok((*handler as u8, std::mem::take(data)))
+                }
+                _ => Err(SqliteError::Decode("unexpected journal row".to_owned())),
+            })
+            .collect();
+        messages
+    }
+
+    // Prepares `sql` with the name of the journal bound to `?1`, and `values`
+    // to the parameters that follow it.
+    fn prepare<const N: usize>(
+        &self,
+        sql: &str,
+        values: [Value; N],
+    ) -> Result<Statement<'_>, SqliteError> {
+        let mut statement = self.connection.prepare(sql)?;
+        statement.bind(1, Value::Text(self.name.clone()))?;
+        for (index, value) in values.into_iter().enumerate() {
+            statement.bind(index + 2, value)?;
+        }
+        Ok(statement)
+    }
+
+    fn execute<const N: usize>(&self, sql: &str, values: [Value; N]) -> Result<(), SqliteError> {
+        self.prepare(sql, values)?.execute()
+    }
+
+    fn rows<const N: usize>(&self, sql: &str, values: [Value; N]) -> Result<Vec<Row>, SqliteError> {
+        let mut statement = self.prepare(sql, values)?;
+        let rows = statement.query().collect();
+        rows
+    }
+}
+
+/// Makes `journal` the journal of the messages handled by the current process.
+pub(crate) fn activate(journal: Option<Journal>) {
+    JOURNAL.set(journal);
+}
+
+/// Appends the message in the buffer for the `handler`, before it's handled.
+pub(crate) fn before_message(handler: u8) {
+    JOURNAL.with_borrow_mut(|mut journal| {
+        if let Some(journal) = journal.as_mut() {
+            // Messages sent to itself are sent again during the replay.
+            if !self_send::is_marker() {
+                journal.append(handler);
+            }
+        }
+    });
+}
+
+/// Marks the current message as handled.
+///
+/// Requests are marked before the reply is sent, so that a reply is never
+/// received for a request that isn't replayed.
+pub(crate) fn handled() {
+    JOURNAL.with_borrow_mut(|mut journal| {
+        if let Some(journal) = journal.as_mut() {
+            journal.mark_handled();
+        }
+    });
+}
+
+/// Marks the current message as handled if it isn't yet, and compacts the
+/// journal if it's time to.
+pub(crate) fn after_message<AP: AbstractProcess>(state: &AP::State) {
+    JOURNAL.with_borrow_mut(|mut journal| {
+        if let Some(journal) = journal.as_mut() {
+            journal.mark_handled();
+            journal.compact_if_due::<AP>(state);
+        }
+    });
+}
+
+// Hands the journaled message to the handler, as if it was received again.
+fn replay<AP: AbstractProcess>(state: &mut AP::State, handler: u8, mut data: Vec<u8>) {
+    let tag = AbstractProcessTag::from_u6(handler);
+    let (response_tag, _) = AbstractProcessTag::extract_u6_data(tag);
+    if AP::Handlers::is_request(handler) && data.len() >= RETURN_ADDRESS_SIZE + DEADLINE_SIZE {
+        // The reply goes to the current process, which drops it.
+        data[..8].copy_from_slice(&host::node_id().to_le_bytes());
+        data[8..16].copy_from_slice(&host::process_id().to_le_bytes());
+        data[16..24].fill(0);
+        pending::abandon(response_tag);
+    }
+    // Received messages are read from the same buffer, the handler takes the
+    // message as if it was received again.
+    unsafe {
+        message::create_data(tag.id(), data.len() as u64);
+        message::write_data(data.as_ptr(), data.len());
+        message::seek_data(0);
+    }
+    AP::Handlers::handle(response_tag, handler, state);
+}
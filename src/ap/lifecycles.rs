@@ -1,20 +1,46 @@
 //! The [`AbstractProcess`] has well defined lifecycles, from startup to
 //! termination. This file contains the implementation of each lifecycle.
 
-use std::ptr::null;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 
 use super::handlers::Handlers;
+use super::journal::{self, Journal};
 use super::messages::{ShutdownMessage, SHUTDOWN_HANDLER};
+use super::pending;
+use super::snapshot::{Snapshot, SnapshotSetup, Snapshotter, WITH_SNAPSHOTS};
 use super::tag::AbstractProcessTag;
-use super::{AbstractProcess, Config, StartupError};
-use crate::mailbox::LINK_DIED;
-use crate::panic::{catch_panic, Panicked};
-use crate::serializer::CanSerialize;
-use crate::{host, Mailbox, Process, Tag};
+use super::{AbstractProcess, Config, Idle, StartupError};
+use crate::mailbox::{LINK_DIED, TIMEOUT};
+use crate::panic::{self, catch_panic, Panicked};
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{
+    config_data, crash_dump, debug, host, metrics, process_local, select, supervisor, sync, time,
+    trace, Mailbox, Process, Tag,
+};
 
 type ParentProcessRef<AP> =
     Process<Result<(), StartupError<AP>>, <AP as AbstractProcess>::Serializer>;
 
+// Reserved tag used to send the panic of a failed `init` to the parent, before
+// the `StartupError::InitPanicked` itself.
+const INIT_PANIC_TAG: i64 = 14;
+
+process_local! {
+    // The panic of the last `init` started by this process that panicked.
+    static LAST_INIT_PANIC: RefCell<Option<Panicked>> = RefCell::new(None);
+    // Set with `Config::set_idle_timeout`.
+    static IDLE_TIMEOUT: Cell<Option<Duration>> = Cell::new(None);
+}
+
+/// How the message loop of an [`AbstractProcess`] ended.
+enum Exit {
+    /// A shutdown message was received, with the tag of the response.
+    Shutdown(Tag),
+    /// [`AbstractProcess::handle_idle`] returned [`Idle::Stop`].
+    Idle,
+}
+
 /// This is the entry point into the [`AbstractProcess`].
 ///
 /// The entry point will get a reference to the parent, so that it can notify it
@@ -31,63 +57,215 @@ pub(crate) fn entry<AP: AbstractProcess>(
     AP::Serializer: CanSerialize<()>,
     AP::Serializer: CanSerialize<ShutdownMessage<AP::Serializer>>,
 {
+    let setup = if AbstractProcessTag::extract_u6_data(init_tag).1 == WITH_SNAPSHOTS {
+        let mailbox = unsafe { Mailbox::<SnapshotSetup, Bincode>::new() };
+        Some(mailbox.tag_receive(&[init_tag]))
+    } else {
+        None
+    };
+    let (restore, keeper) = match setup {
+        Some(setup) => (setup.restore, setup.keeper),
+        None => (None, None),
+    };
+
+    // Children report to the parent once they are ready.
+    supervisor::set_parent(parent.node_id(), parent.id());
+    let mut journal = AP::journal(&arg);
     // Catch errors during startup and notify parent. Panics will also be caught.
-    let mut state = match startup::<AP>(arg) {
+    let mut state = match startup::<AP>(arg, restore, journal.as_mut()) {
         Ok(state) => {
             // Notify spawner that startup succeeded & continue.
             parent.tag_send(init_tag, Ok(()));
             state
         }
-        Err(err) => {
+        Err((err, panicked)) => {
+            if let Some(panicked) = panicked {
+                let parent = Process::<(Tag, Panicked)>::new(parent.node_id(), parent.id());
+                parent.tag_send(Tag::from(INIT_PANIC_TAG), (init_tag, panicked));
+            }
             // Notify spawner that startup failed with the reason why it failed.
             parent.tag_send(init_tag, Err(err));
             return;
         }
     };
 
-    let shutdown_tag = loop_and_handle::<AP>(&mut state);
-    shutdown::<AP>(shutdown_tag, state);
+    debug::label_abstract_process(std::any::type_name::<AP>());
+    journal::activate(journal);
+    let mut snapshotter = keeper.map(Snapshotter::new);
+    match loop_and_handle::<AP>(&mut state, &mut snapshotter) {
+        Exit::Shutdown(shutdown_tag) => shutdown::<AP>(shutdown_tag, state, snapshotter),
+        Exit::Idle => {
+            if let Some(mut snapshotter) = snapshotter {
+                snapshotter.send::<AP>(&state);
+            }
+            AP::terminate(state);
+        }
+    }
 }
 
 /// This code is executed during the [`AbstractProcess::start`] call.
-fn startup<AP: AbstractProcess>(arg: AP::Arg) -> Result<AP::State, StartupError<AP>> {
+///
+/// If `init` panics, the panic is returned next to the error. Processes with
+/// a journal are restored from it, instead of the snapshot.
+fn startup<AP: AbstractProcess>(
+    arg: AP::Arg,
+    restore: Option<Snapshot>,
+    journal: Option<&mut Journal>,
+) -> Result<AP::State, (StartupError<AP>, Option<Panicked>)> {
     let config = Config::new();
-    match catch_panic(|| AP::init(config, arg)) {
+    let init = || match (journal, restore) {
+        (Some(journal), _) => journal.init(config, arg),
+        (None, Some(snapshot)) => AP::init_from_snapshot(config, arg, snapshot),
+        (None, None) => AP::init(config, arg),
+    };
+    match catch_panic(init) {
         Ok(Ok(state)) => Ok(state),
-        Ok(Err(custom)) => Err(StartupError::Custom(custom)),
-        Err(Panicked) => Err(StartupError::InitPanicked),
+        Ok(Err(custom)) => Err((StartupError::Custom(custom), None)),
+        Err(panicked) => Err((StartupError::InitPanicked, Some(panicked))),
     }
 }
 
+/// Takes the panic of the failed `init` started with `init_tag` out of the
+/// mailbox, and keeps it for [`last_init_panic`].
+pub(crate) fn store_init_panic(init_tag: Tag) {
+    let tags = [Tag::from(INIT_PANIC_TAG)];
+    let mut panicked = None;
+    // The panic is sent before the error, so it's already in the mailbox.
+    while select::receive(&tags, Some(Duration::ZERO)).is_some() {
+        if let Ok((tag, panic)) = <Bincode as CanSerialize<(Tag, Panicked)>>::decode() {
+            if tag == init_tag {
+                panicked = Some(panic);
+                break;
+            }
+        }
+    }
+    LAST_INIT_PANIC.set(panicked);
+}
+
+/// Returns the panic of the last [`AbstractProcess`] started by the current
+/// process, whose `init` failed with [`StartupError::InitPanicked`].
+///
+/// Returns `None` if the panic wasn't reported, e.g. because the process ran
+/// out of fuel.
+pub fn last_init_panic() -> Option<Panicked> {
+    LAST_INIT_PANIC.with_borrow(|last| last.clone())
+}
+
+/// Sets the idle timeout of the current process, see
+/// [`Config::set_idle_timeout`].
+pub(crate) fn set_idle_timeout(timeout: Option<Duration>) {
+    IDLE_TIMEOUT.set(timeout);
+}
+
 /// Extracts the handler out of the tag for each incoming message, until
-/// shutdown message is received.
-fn loop_and_handle<AP: AbstractProcess>(state: &mut AP::State) -> Tag {
+/// shutdown message is received or the process stops after being idle.
+fn loop_and_handle<AP: AbstractProcess>(
+    state: &mut AP::State,
+    snapshotter: &mut Option<Snapshotter>,
+) -> Exit {
+    let priority_tags: Vec<i64> = AP::Handlers::priority_handlers()
+        .into_iter()
+        .map(|id| AbstractProcessTag::priority(id).id())
+        .collect();
+    let idle_timeout = IDLE_TIMEOUT.get();
+    let mut last_handled = time::instant();
     loop {
+        metrics::wait_started();
+        // Messages to priority handlers are taken out of the mailbox first.
+        let priority = !priority_tags.is_empty()
+            && unsafe {
+                host::api::message::receive(priority_tags.as_ptr(), priority_tags.len(), 0)
+            } != TIMEOUT;
+        // The idle time counts from the last handled message, messages that
+        // are handled internally don't reset it.
+        let timeout_ms = match idle_timeout {
+            Some(timeout) => {
+                let idle = time::instant().saturating_duration_since(last_handled);
+                time::millis_ceil(timeout.saturating_sub(idle))
+            }
+            None => u64::MAX,
+        };
         // Wait for next message & handle link died if result matches constant.
-        if unsafe { host::api::message::receive(null(), 0, u64::MAX) } == LINK_DIED {
-            let tag = unsafe { host::api::message::get_tag() };
-            let tag = Tag::from(tag);
-            AP::handle_link_death(super::State { state }, tag);
-            continue;
+        if !priority {
+            match crate::virtual_time::receive(&[], timeout_ms) {
+                LINK_DIED => {
+                    let tag = unsafe { host::api::message::get_tag() };
+                    let tag = Tag::from(tag);
+                    AP::handle_link_death(super::State { state }, tag);
+                    last_handled = time::instant();
+                    continue;
+                }
+                // The mailbox stayed empty for the whole timeout.
+                TIMEOUT => {
+                    match AP::handle_idle(super::State { state }) {
+                        Idle::Continue => last_handled = time::instant(),
+                        Idle::Stop => return Exit::Idle,
+                    }
+                    continue;
+                }
+                _ => (),
+            }
         }
 
         // Extract `data` from tag
         let tag = unsafe { host::api::message::get_tag() };
+        // Panic reports from linked processes are kept for `panic::link_panic`.
+        if tag == panic::LINK_PANIC_TAG {
+            panic::store_link_panic();
+            continue;
+        }
+        // Cancellations are kept for `CancellationToken::is_cancelled`.
+        if tag == sync::CANCELLED_TAG {
+            sync::store_cancellation();
+            continue;
+        }
+        // Config updates are kept for `AppConfig::get`.
+        if tag == config_data::UPDATE_TAG {
+            config_data::store_update();
+            continue;
+        }
+        if tag == trace::TRACE_TAG {
+            trace::handle_control();
+            continue;
+        }
+        if tag == debug::DEBUG_INFO_TAG {
+            debug::handle_request();
+            continue;
+        }
+        // Readiness of children that nobody waits on.
+        if tag == supervisor::READY_TAG {
+            continue;
+        }
+        if pending::discard_abandoned(tag) {
+            continue;
+        }
         let tag = Tag::from(tag);
         let (response_tag, data) = AbstractProcessTag::extract_u6_data(tag);
 
         // Check if `data` matches the shutdown message
         if data == SHUTDOWN_HANDLER {
-            break response_tag;
+            return Exit::Shutdown(response_tag);
         }
 
         // Use `data` to look up the right handler function
+        metrics::received(AP::Handlers::handler_name(data));
+        crash_dump::record(AP::Handlers::handler_name(data));
+        trace::start(std::any::type_name::<AP>(), Some(data));
+        debug::set_handler(Some(AP::Handlers::handler_name(data)));
+        journal::before_message(data);
         AP::Handlers::handle(response_tag, data, state);
+        journal::after_message::<AP>(state);
+        debug::set_handler(None);
+        trace::finish();
+        if let Some(snapshotter) = snapshotter.as_mut() {
+            snapshotter.after_message::<AP>(state);
+        }
+        last_handled = time::instant();
     }
 }
 
 /// Is executed if the [`AbstractProcess`] receives a `shutdown` command.
-fn shutdown<AP>(shutdown_tag: Tag, state: AP::State)
+fn shutdown<AP>(shutdown_tag: Tag, state: AP::State, snapshotter: Option<Snapshotter>)
 where
     AP: AbstractProcess,
     AP::Serializer: CanSerialize<()>,
@@ -96,6 +274,9 @@ where
     // The shutdown message needs to deserialize before `terminate` is called.
     // After `terminate` we could have another message in the buffer.
     let shutdown_message: ShutdownMessage<AP::Serializer> = AP::Serializer::decode().unwrap();
+    if let Some(mut snapshotter) = snapshotter {
+        snapshotter.send::<AP>(&state);
+    }
     AP::terminate(state);
     shutdown_message.0.send_response((), shutdown_tag);
 }
@@ -1,8 +1,44 @@
+use crate::host::api::message;
 use crate::serializer::CanSerialize;
 use crate::{Process, Tag};
 
+/// Size of the return address written in front of requests, the node and
+/// process ID of the sender.
+pub(crate) const RETURN_ADDRESS_SIZE: usize = 16;
+/// Size of the deadline following the return address, see
+/// [`context`](crate::context).
+pub(crate) const DEADLINE_SIZE: usize = 8;
+
+/// Writes the return address of the current process and the `deadline` of the
+/// request into the message buffer, in front of a request.
+pub(crate) fn write_request_header(deadline: Option<u64>) {
+    let mut header = [0; RETURN_ADDRESS_SIZE + DEADLINE_SIZE];
+    header[..8].copy_from_slice(&crate::host::node_id().to_le_bytes());
+    header[8..16].copy_from_slice(&crate::host::process_id().to_le_bytes());
+    // Deadlines are milliseconds since the Unix epoch, 0 means none.
+    header[16..].copy_from_slice(&deadline.unwrap_or(0).to_le_bytes());
+    unsafe { message::write_data(header.as_ptr(), header.len()) };
+}
+
+/// Reads the deadline following the return address in the message buffer.
+pub(crate) fn read_deadline() -> Option<u64> {
+    let mut deadline = [0; DEADLINE_SIZE];
+    unsafe { message::read_data(deadline.as_mut_ptr(), deadline.len()) };
+    match u64::from_le_bytes(deadline) {
+        0 => None,
+        deadline => Some(deadline),
+    }
+}
+
 /// Contains information about the request sender, so that a response can be
 /// sent back to the correct process.
+///
+/// Requests don't serialize it as part of the message. The IDs of the sender
+/// are written in front of the serialized request instead, see
+/// [`write_request_header`], which is cheaper for every serializer than
+/// encoding a whole [`Process`]. Return addresses that are kept around,
+/// like the one in a [`DeferredResponse`](super::DeferredResponse), are still
+/// serializable.
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(bound = "")]
 pub(crate) struct ReturnAddress<Response, Serializer> {
@@ -18,6 +54,18 @@ where
         ReturnAddress { process }
     }
 
+    /// Reads the return address in front of the request in the message
+    /// buffer.
+    pub(crate) fn read() -> Self {
+        let mut header = [0; RETURN_ADDRESS_SIZE];
+        unsafe { message::read_data(header.as_mut_ptr(), header.len()) };
+        let node_id = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let process_id = u64::from_le_bytes(header[8..].try_into().unwrap());
+        ReturnAddress {
+            process: Process::new(node_id, process_id),
+        }
+    }
+
     /// Sends response back to a process.
     ///
     /// The tag should be provided by the sender and should be extracted from
@@ -40,6 +88,9 @@ pub(crate) const SHUTDOWN_HANDLER: u8 = 32;
 pub struct ShutdownMessage<Serializer>(pub(crate) ReturnAddress<(), Serializer>);
 
 /// An incoming message indicating a request for the [`AbstractProcess`].
+///
+/// On the wire a request is the raw node and process ID of the sender, followed
+/// by the serialized request.
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct RequestMessage<T, Response, Serializer>(
     pub(crate) T,
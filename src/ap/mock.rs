@@ -0,0 +1,426 @@
+//! Scripted fakes of abstract processes, for unit tests.
+//!
+//! A [`MockRef`] stands in for a dependency of the abstract process under
+//! test. It's backed by a lightweight process that speaks the same protocol
+//! as the real one, so its [`ProcessRef`] can be passed to the code under
+//! test, and the methods generated by
+//! [`abstract_process`](crate::abstract_process) or calls like
+//! [`ProcessRef::request`] work unchanged.
+//!
+//! The mock expects the calls of its script in order. For each request it
+//! replies with the canned response of the expectation. The first call that
+//! doesn't match the script panics the mock process with the expected and the
+//! actual call, which also fails the test process linked to it. Expectations
+//! that are still unmet when the [`MockRef`] is dropped panic the test.
+//!
+//! Messages are compared and printed in their serialized form, so they don't
+//! need to implement `PartialEq` or `Debug`, but must support `serde`.
+//!
+//! # Example
+//!
+//! ```
+//! #[lunatic::test]
+//! fn cache_loads_missing_keys() {
+//!     let db = MockRef::<Db>::new().expect_request(Load("a".into()), Some(1));
+//!     let cache = Cache::link().start(db.process_ref()).unwrap();
+//!     assert_eq!(cache.get("a".into()), Some(1));
+//!     // Served from the cache.
+//!     assert_eq!(cache.get("a".into()), Some(1));
+//! }
+//! ```
+
+use std::any::type_name;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::handlers::{DeferredRequest, Handlers, Message, Request};
+use super::messages::{self, ReturnAddress, ShutdownMessage, SHUTDOWN_HANDLER};
+use super::tag::AbstractProcessTag;
+use super::{AbstractProcess, DeferredRequestHandler, MessageHandler, ProcessRef, RequestHandler};
+use crate::host::api::message;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{Mailbox, Process, Tag};
+
+/// How long [`MockRef::verify`] waits on the mock to report its unmet
+/// expectations.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decides if a message matches an expectation of a [`MockRef`].
+///
+/// Any serializable value converts into a matcher that is equal to it.
+pub struct Matcher<M> {
+    matcher: ErasedMatcher,
+    message: PhantomData<fn(&M)>,
+}
+
+impl<M: Serialize> Matcher<M> {
+    /// Matches messages that serialize the same as `expected`.
+    pub fn eq(expected: M) -> Self {
+        Self::new(ErasedMatcher {
+            check: equals::<M> as Check<M> as usize,
+            predicate: 0,
+            expected: bincode::serialize(&expected).expect("Failed to serialize the expectation"),
+            description: describe(&expected),
+        })
+    }
+
+    /// Matches all messages.
+    pub fn any() -> Self {
+        Self::new(ErasedMatcher {
+            check: anything::<M> as Check<M> as usize,
+            predicate: 0,
+            expected: Vec::new(),
+            description: "any value".to_owned(),
+        })
+    }
+
+    /// Matches messages for which `predicate` returns `true`.
+    ///
+    /// The predicate runs inside of the mock process.
+    pub fn when(predicate: fn(&M) -> bool) -> Self {
+        Self::new(ErasedMatcher {
+            check: satisfies::<M> as Check<M> as usize,
+            predicate: predicate as usize,
+            expected: Vec::new(),
+            description: "a value matching the predicate".to_owned(),
+        })
+    }
+
+    fn new(matcher: ErasedMatcher) -> Self {
+        Matcher {
+            matcher,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<M: Serialize> From<M> for Matcher<M> {
+    fn from(expected: M) -> Self {
+        Matcher::eq(expected)
+    }
+}
+
+/// A scripted fake of the abstract process `AP`.
+///
+/// See the [module level documentation](self) for details.
+pub struct MockRef<AP: AbstractProcess> {
+    process: Process<Control>,
+    control: Tag,
+    ap: PhantomData<AP>,
+}
+
+impl<AP: AbstractProcess> MockRef<AP> {
+    /// Starts a mock without expectations, linked to the current process.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let control = Tag::new();
+        let name = type_name::<AP>().to_owned();
+        let process = Process::<Control>::spawn_link((control, name), mock_process::<AP>);
+        MockRef {
+            process,
+            control,
+            ap: PhantomData,
+        }
+    }
+
+    /// Expects a request that matches `matcher`, and replies to it with
+    /// `reply`.
+    #[track_caller]
+    pub fn expect_request<M>(self, matcher: impl Into<Matcher<M>>, reply: AP::Response) -> Self
+    where
+        AP: RequestHandler<M>,
+        AP::Serializer: CanSerialize<M>,
+        AP::Serializer: CanSerialize<AP::Response>,
+        M: Serialize + 'static,
+        AP::Response: Serialize + DeserializeOwned,
+    {
+        self.expect(Step {
+            handler: AP::Handlers::handler_id::<Request<M>>(),
+            call: format!("request `{}`", type_name::<M>()),
+            matcher: matcher.into().matcher,
+            reply: bincode::serialize(&reply).expect("Failed to serialize the reply"),
+            run: run_request::<M, AP::Response, AP::Serializer> as Run as usize,
+        })
+    }
+
+    /// Expects a deferred request that matches `matcher`, and replies to it
+    /// with `reply`.
+    #[track_caller]
+    pub fn expect_deferred_request<M>(
+        self,
+        matcher: impl Into<Matcher<M>>,
+        reply: <AP as DeferredRequestHandler<M>>::Response,
+    ) -> Self
+    where
+        AP: DeferredRequestHandler<M>,
+        AP::Serializer: CanSerialize<M>,
+        AP::Serializer: CanSerialize<<AP as DeferredRequestHandler<M>>::Response>,
+        M: Serialize + 'static,
+        <AP as DeferredRequestHandler<M>>::Response: Serialize + DeserializeOwned,
+    {
+        self.expect(Step {
+            handler: AP::Handlers::handler_id::<DeferredRequest<M>>(),
+            call: format!("deferred request `{}`", type_name::<M>()),
+            matcher: matcher.into().matcher,
+            reply: bincode::serialize(&reply).expect("Failed to serialize the reply"),
+            run: run_request::<M, <AP as DeferredRequestHandler<M>>::Response, AP::Serializer>
+                as Run as usize,
+        })
+    }
+
+    /// Expects a message that matches `matcher`, sent with
+    /// [`ProcessRef::send`].
+    #[track_caller]
+    pub fn expect_cast<M>(self, matcher: impl Into<Matcher<M>>) -> Self
+    where
+        AP: MessageHandler<M>,
+        AP::Serializer: CanSerialize<M>,
+        M: Serialize + 'static,
+    {
+        self.expect(Step {
+            handler: AP::Handlers::handler_id::<Message<M>>(),
+            call: format!("message `{}`", type_name::<M>()),
+            matcher: matcher.into().matcher,
+            reply: Vec::new(),
+            run: run_message::<M, AP::Serializer> as Run as usize,
+        })
+    }
+
+    /// Expects a [shutdown](ProcessRef::shutdown). The mock confirms it, but
+    /// keeps running.
+    pub fn expect_shutdown(self) -> Self
+    where
+        AP::Serializer: CanSerialize<ShutdownMessage<AP::Serializer>>,
+        AP::Serializer: CanSerialize<()>,
+    {
+        self.expect(Step {
+            handler: SHUTDOWN_HANDLER,
+            call: "shutdown".to_owned(),
+            matcher: Matcher::<()>::any().matcher,
+            reply: Vec::new(),
+            run: run_shutdown::<AP::Serializer> as Run as usize,
+        })
+    }
+
+    /// Returns the reference to pass to the code under test.
+    pub fn process_ref(&self) -> ProcessRef<AP> {
+        unsafe { ProcessRef::new(self.process.node_id(), self.process.id()) }
+    }
+
+    /// Panics if some of the expectations are still unmet.
+    ///
+    /// It's called when the mock is dropped.
+    pub fn verify(&self) {
+        report::<AP>(self.unmet());
+    }
+
+    fn expect(self, step: Step) -> Self {
+        self.process.tag_send(self.control, Control::Expect(step));
+        self
+    }
+
+    // Returns the unmet expectations, or `None` if the mock died.
+    fn unmet(&self) -> Option<Vec<String>> {
+        let tag = Tag::new();
+        self.process
+            .tag_send(self.control, Control::Verify(Process::this(), tag));
+        unsafe { Mailbox::<Vec<String>>::new() }
+            .tag_receive_timeout(&[tag], VERIFY_TIMEOUT)
+            .ok()
+    }
+}
+
+impl<AP: AbstractProcess> Drop for MockRef<AP> {
+    fn drop(&mut self) {
+        let unmet = if std::thread::panicking() {
+            Some(Vec::new())
+        } else {
+            self.unmet()
+        };
+        self.process.kill();
+        report::<AP>(unmet);
+    }
+}
+
+fn report<AP>(unmet: Option<Vec<String>>) {
+    match unmet {
+        Some(unmet) if unmet.is_empty() => (),
+        Some(unmet) => panic!(
+            "mock of `{}` has unmet expectations:\n  {}",
+            type_name::<AP>(),
+            unmet.join("\n  ")
+        ),
+        None => panic!(
+            "mock of `{}` failed, see its panic message above",
+            type_name::<AP>()
+        ),
+    }
+}
+
+// Messages sent to the mock process on its control tag.
+#[derive(Serialize, Deserialize)]
+enum Control {
+    Expect(Step),
+    // Asks for the unmet expectations, replying on the tag.
+    Verify(Process<Vec<String>>, Tag),
+}
+
+// An expected call.
+#[derive(Serialize, Deserialize)]
+struct Step {
+    handler: u8,
+    call: String,
+    matcher: ErasedMatcher,
+    // The serialized response, empty for messages.
+    reply: Vec<u8>,
+    // A `Run` that handles the call in the message buffer.
+    run: usize,
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        format!("{} {}", self.call, self.matcher.description)
+    }
+}
+
+// Handles the call in the message buffer, replying with the response tag if
+// it's a request. Returns the description of the call if it doesn't match.
+type Run = fn(Step, Tag) -> Result<(), String>;
+
+#[derive(Serialize, Deserialize)]
+struct ErasedMatcher {
+    // A `Check<M>` for the message type.
+    check: usize,
+    // The `fn(&M) -> bool` of `Matcher::when`.
+    predicate: usize,
+    // The serialized value of `Matcher::eq`.
+    expected: Vec<u8>,
+    description: String,
+}
+
+type Check<M> = fn(&ErasedMatcher, &M) -> bool;
+
+fn equals<M: Serialize>(matcher: &ErasedMatcher, actual: &M) -> bool {
+    bincode::serialize(actual).is_ok_and(|actual| actual == matcher.expected)
+}
+
+fn anything<M>(_: &ErasedMatcher, _: &M) -> bool {
+    true
+}
+
+fn satisfies<M>(matcher: &ErasedMatcher, actual: &M) -> bool {
+    let predicate: fn(&M) -> bool = unsafe { mem::transmute(matcher.predicate) };
+    predicate(actual)
+}
+
+// Checks `actual` against the matcher of `step`.
+fn check<M: Serialize>(step: &Step, actual: &M) -> Result<(), String> {
+    let check: Check<M> = unsafe { mem::transmute(step.matcher.check) };
+    if check(&step.matcher, actual) {
+        Ok(())
+    } else {
+        Err(format!("{} {}", step.call, describe(actual)))
+    }
+}
+
+fn describe<M: Serialize>(value: &M) -> String {
+    match serde_json::to_string(value) {
+        Ok(json) => json,
+        Err(_) => format!("{:?}", bincode::serialize(value).unwrap_or_default()),
+    }
+}
+
+fn run_message<M, S>(step: Step, _: Tag) -> Result<(), String>
+where
+    M: Serialize,
+    S: CanSerialize<M>,
+{
+    let message: M = decode::<M, S>(&step)?;
+    check(&step, &message)
+}
+
+fn run_request<M, R, S>(step: Step, response_tag: Tag) -> Result<(), String>
+where
+    M: Serialize,
+    R: DeserializeOwned,
+    S: CanSerialize<M> + CanSerialize<R>,
+{
+    let return_address = ReturnAddress::<R, S>::read();
+    messages::read_deadline();
+    let request: M = decode::<M, S>(&step)?;
+    check(&step, &request)?;
+    let reply: R = bincode::deserialize(&step.reply).expect("Failed to deserialize the reply");
+    return_address.send_response(reply, response_tag);
+    Ok(())
+}
+
+fn run_shutdown<S>(step: Step, response_tag: Tag) -> Result<(), String>
+where
+    S: CanSerialize<ShutdownMessage<S>> + CanSerialize<()>,
+{
+    let ShutdownMessage(return_address) = decode::<ShutdownMessage<S>, S>(&step)?;
+    return_address.send_response((), response_tag);
+    Ok(())
+}
+
+fn decode<M, S: CanSerialize<M>>(step: &Step) -> Result<M, String> {
+    S::decode().map_err(|_| format!("{} that can't be deserialized", step.call))
+}
+
+fn mock_process<AP>((control, name): (Tag, String), _: Mailbox<Control>)
+where
+    AP: AbstractProcess,
+{
+    let mut steps: VecDeque<Step> = VecDeque::new();
+    let mut calls = 0;
+    loop {
+        crate::virtual_time::receive(&[], u64::MAX);
+        let tag = Tag::from(unsafe { message::get_tag() });
+        if tag == control {
+            match <Bincode as CanSerialize<Control>>::decode() {
+                Ok(Control::Expect(step)) => steps.push_back(step),
+                Ok(Control::Verify(reply_to, reply_tag)) => {
+                    let unmet = steps.iter().map(Step::describe).collect();
+                    reply_to.tag_send(reply_tag, unmet);
+                }
+                Err(_) => (),
+            }
+            continue;
+        }
+        let (response_tag, handler) = AbstractProcessTag::extract_u6_data(tag);
+        // Reserved tags, e.g. of debug requests, don't carry a handler.
+        if handler == 0 {
+            continue;
+        }
+        calls += 1;
+        let actual = match handler {
+            SHUTDOWN_HANDLER => "shutdown",
+            handler => AP::Handlers::handler_name(handler),
+        };
+        let Some(step) = steps.pop_front() else {
+            panic!(
+                "mock of `{name}` got unexpected call {calls} to `{actual}`, \
+                 after all expectations were met"
+            );
+        };
+        let expected = step.describe();
+        let result = if step.handler == handler {
+            let run: Run = unsafe { mem::transmute(step.run) };
+            run(step, response_tag)
+        } else {
+            Err(format!("call to `{actual}`"))
+        };
+        if let Err(actual) = result {
+            panic!(
+                "mock of `{name}` got unexpected call {calls}\n  \
+                 expected: {expected}\n    \
+                 actual: {actual}"
+            );
+        }
+    }
+}
@@ -5,7 +5,21 @@ mod lifecycles;
 mod tag;
 
 pub mod handlers;
+pub mod journal;
 pub(crate) mod messages;
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod mock;
+pub(crate) mod pending;
+pub(crate) mod self_send;
+pub mod singleton;
+pub mod snapshot;
+
+pub use self::journal::Journal;
+pub use self::lifecycles::last_init_panic;
+pub use self::pending::{wait_all, PendingReply};
+pub use self::singleton::GlobalSingleton;
+pub use self::snapshot::Snapshot;
 
 use std::any::type_name;
 use std::fmt::Debug;
@@ -18,10 +32,12 @@ use self::builder::AbstractProcessBuilder;
 use self::handlers::{DeferredRequest, Handlers, Message, Request};
 use self::messages::{RequestMessage, ReturnAddress, ShutdownMessage, SHUTDOWN_HANDLER};
 use self::tag::AbstractProcessTag;
+use crate::debug::DebugInfo;
 use crate::protocol::ProtocolCapture;
 use crate::serializer::CanSerialize;
 use crate::time::{Timeout, TimerRef, WithDelay, WithTimeout};
-use crate::{host, Process, ProcessConfig, Tag};
+use crate::trace::TraceEvent;
+use crate::{context, host, Process, ProcessConfig, Tag};
 
 /// Building block for processes that act as a server of a client-server
 /// relation.
@@ -183,6 +199,70 @@ where
     /// This function will be called if another linked process dies.
     fn handle_link_death(_state: State<Self>, _tag: Tag) {}
 
+    /// Called when no message arrived during the idle timeout, see
+    /// [`Config::set_idle_timeout`].
+    ///
+    /// The state can be shrunk here, or the process can stop by returning
+    /// [`Idle::Stop`]. A stopped process calls [`terminate`](Self::terminate)
+    /// and exits normally, so a supervisor doesn't restart it. If the process
+    /// continues, the timeout starts again.
+    fn handle_idle(_state: State<Self>) -> Idle {
+        Idle::Continue
+    }
+
+    /// Returns a snapshot of the state, used to restore it if the process is
+    /// restarted.
+    ///
+    /// It's only called for processes that have a keeper for their snapshots,
+    /// e.g. children of a supervisor with
+    /// [`restart_with_snapshot`](crate::supervisor::SupervisorConfig::restart_with_snapshot)
+    /// set. The snapshot is taken after handling a message, and before the
+    /// process is shut down. Processes with a [`journal`](Self::journal) also
+    /// use it to compact the journal.
+    fn snapshot(_state: &Self::State) -> Option<Snapshot> {
+        None
+    }
+
+    /// Entry function of a process that is restarted with a snapshot of its
+    /// previous state.
+    ///
+    /// By default, the snapshot is ignored and [`Self::init`] is called.
+    /// Implementations should fall back to `init` if the snapshot can't be
+    /// decoded, e.g. because it has an older version.
+    fn init_from_snapshot(
+        config: Config<Self>,
+        arg: Self::Arg,
+        _snapshot: Snapshot,
+    ) -> Result<Self::State, Self::StartupError> {
+        Self::init(config, arg)
+    }
+
+    /// Returns the journal of the process, if the messages it handles are
+    /// journaled, see [`journal`](self::journal).
+    ///
+    /// It's called inside the new process, before it's initialized. A process
+    /// started with the journal of a previous one continues from its state.
+    fn journal(_arg: &Self::Arg) -> Option<Journal> {
+        None
+    }
+
+    /// Entry function of a process with a [`journal`](Self::journal), with the
+    /// last snapshot in the journal.
+    ///
+    /// The messages journaled after the snapshot are handled again once it
+    /// returns. By default, [`Self::init_from_snapshot`] is called if there is
+    /// a snapshot, and [`Self::init`] otherwise.
+    fn init_from_journal(
+        config: Config<Self>,
+        arg: Self::Arg,
+        snapshot: Option<Snapshot>,
+    ) -> Result<Self::State, Self::StartupError> {
+        match snapshot {
+            Some(snapshot) => Self::init_from_snapshot(config, arg, snapshot),
+            None => Self::init(config, arg),
+        }
+    }
+
     /// Starts a new `AbstractProcess` and returns a reference to it.
     ///
     /// This call will block until the `init` function finishes. If the `init`
@@ -266,6 +346,16 @@ impl<AP: AbstractProcess> Config<AP> {
     /// Default value is `false`.
     pub fn die_if_link_dies(&self, die: bool) {
         unsafe { host::api::process::die_when_link_dies(die as u32) };
+        crate::panic::report_link_panics(!die);
+    }
+
+    /// Calls [`AbstractProcess::handle_idle`] if no message arrives for
+    /// `timeout`.
+    ///
+    /// The timeout is reset by every handled message. `None` disables it,
+    /// which is the default.
+    pub fn set_idle_timeout(&self, timeout: Option<Duration>) {
+        lifecycles::set_idle_timeout(timeout);
     }
 
     /// Get a reference to the running [`AbstractProcess`].
@@ -275,6 +365,15 @@ impl<AP: AbstractProcess> Config<AP> {
     }
 }
 
+/// Returned from [`AbstractProcess::handle_idle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idle {
+    /// Keep running and wait for the next idle timeout.
+    Continue,
+    /// Stop the process.
+    Stop,
+}
+
 pub trait MessageHandler<Message>: AbstractProcess
 where
     Self::Serializer: CanSerialize<Message>,
@@ -356,7 +455,7 @@ where
 /// `MessageHandler<Message>`, `RequestHandler<Request>` or
 /// `DeferredRequestHandler<Request>` are implemented for `T`.
 #[derive(serde::Serialize, serde::Deserialize)]
-#[serde(bound = "")]
+#[serde(bound = "", transparent)]
 pub struct ProcessRef<T>
 where
     T: AbstractProcess,
@@ -373,11 +472,20 @@ where
     T: AbstractProcess,
 {
     /// Construct a process from a raw ID.
-    unsafe fn new(node_id: u64, process_id: u64) -> Self {
+    pub(crate) unsafe fn new(node_id: u64, process_id: u64) -> Self {
         let process = Process::new(node_id, process_id);
         ProcessRef { process }
     }
 
+    /// Returns the name under which the process is kept in the registry.
+    ///
+    /// It contains a fingerprint of `T`, so that only lookups of the same type
+    /// find it.
+    pub(crate) fn registry_name(name: &str) -> String {
+        let fingerprint = crate::function::process::fingerprint(&[type_name::<T>()]);
+        format!("{} + ProcessRef + {:016x}", name, fingerprint)
+    }
+
     /// Returns the process ID.
     pub fn id(&self) -> u64 {
         self.process.id()
@@ -390,14 +498,19 @@ where
 
     /// Returns a process registered under `name` if it exists and the signature
     /// matches.
+    ///
+    /// Processes on the local node that aren't running anymore are not
+    /// returned.
     pub fn lookup<S: AsRef<str>>(name: S) -> Option<Self> {
-        let name: &str = name.as_ref();
-        let name = format!("{} + ProcessRef + {}", name, std::any::type_name::<T>());
+        let name = Self::registry_name(name.as_ref());
         let mut id = 0;
         let mut node_id = 0;
         let result =
             unsafe { host::api::registry::get(name.as_ptr(), name.len(), &mut node_id, &mut id) };
-        if result == 0 {
+        // Local processes that stopped, e.g. after being idle, are skipped.
+        if result == 0
+            && (node_id != host::node_id() || unsafe { host::api::process::exists(id) } != 0)
+        {
             unsafe { Some(Self::new(node_id, id)) }
         } else {
             None
@@ -406,8 +519,7 @@ where
 
     /// Registers process under `name`.
     pub fn register<S: AsRef<str>>(&self, name: S) {
-        let name: &str = name.as_ref();
-        let name = format!("{} + ProcessRef + {}", name, std::any::type_name::<T>());
+        let name = Self::registry_name(name.as_ref());
         unsafe { host::api::registry::put(name.as_ptr(), name.len(), self.node_id(), self.id()) };
     }
 
@@ -444,6 +556,20 @@ where
         unsafe { host::api::process::kill(self.process.id()) };
     }
 
+    /// Enables tracing of the [`AbstractProcess`], sending events to
+    /// `collector`, or disables it if `collector` is `None`.
+    ///
+    /// See [`trace`](crate::trace) for more details.
+    pub fn trace(&self, collector: Option<Process<TraceEvent>>) {
+        self.process.trace(collector);
+    }
+
+    /// Asks the [`AbstractProcess`] for its debug information, see
+    /// [`Process::debug_info`].
+    pub fn debug_info(&self, timeout: Duration) -> Option<DebugInfo> {
+        self.process.debug_info(timeout)
+    }
+
     /// Shuts the [`AbstractProcess`] down.
     #[track_caller]
     pub fn shutdown(&self)
@@ -470,28 +596,31 @@ where
     {
         let return_address = ReturnAddress::from_self();
         let message = ShutdownMessage(return_address);
-        let send_tag = AbstractProcessTag::from_u6(SHUTDOWN_HANDLER);
+        let send_tag = AbstractProcessTag::request(SHUTDOWN_HANDLER);
         let (receive_tag, _) = AbstractProcessTag::extract_u6_data(send_tag);
         unsafe {
             // Cast into the right type for sending.
             let process: Process<ShutdownMessage<T::Serializer>, T::Serializer> =
                 mem::transmute(self.process);
-            match process.tag_send_receive(send_tag, receive_tag, message, timeout) {
-                crate::MailboxResult::Message(()) => Ok(()),
-                crate::MailboxResult::TimedOut => Err(Timeout),
-                _ => unreachable!("send_receive should panic in case of other errors"),
-            }
+            Timeout::from_result(process.tag_send_receive(send_tag, receive_tag, message, timeout))
         }
     }
 
     /// Send message to the process.
+    ///
+    /// Messages that a process sends to itself aren't serialized, they are
+    /// kept in the memory of the process until they are received.
     #[track_caller]
     pub fn send<M: 'static>(&self, message: M)
     where
         T::Serializer: CanSerialize<M>,
     {
         let handler_id = T::Handlers::handler_id::<Message<M>>();
-        let tag = AbstractProcessTag::from_u6(handler_id);
+        let tag = AbstractProcessTag::message::<T>(handler_id);
+        if self.process.node_id() == host::node_id() && self.process.id() == host::process_id() {
+            self_send::send::<M, T::Serializer>(tag, message);
+            return;
+        }
         // Cast into the right type for sending.
         let process: Process<M, T::Serializer> = unsafe { std::mem::transmute(self.process) };
         process.tag_send(tag, message);
@@ -504,7 +633,7 @@ where
         T::Serializer: CanSerialize<M>,
     {
         let handler_id = T::Handlers::handler_id::<Message<M>>();
-        let tag = AbstractProcessTag::from_u6(handler_id);
+        let tag = AbstractProcessTag::message::<T>(handler_id);
         // Cast into the right type for sending.
         let process: Process<M, T::Serializer> = unsafe { std::mem::transmute(self.process) };
         process.tag_send_after(tag, message, duration)
@@ -538,20 +667,72 @@ where
         T::Serializer: CanSerialize<T::Response>,
         T::Serializer: CanSerialize<RequestMessage<R, T::Response, T::Serializer>>,
     {
-        let return_address = ReturnAddress::from_self();
-        let message = RequestMessage(request, return_address);
         let handler_id = T::Handlers::handler_id::<Request<R>>();
-        let send_tag = AbstractProcessTag::from_u6(handler_id);
+        let send_tag = AbstractProcessTag::request(handler_id);
         let (receive_tag, _) = AbstractProcessTag::extract_u6_data(send_tag);
-        unsafe {
-            // Cast into the right type for sending.
-            let process: Process<RequestMessage<R, T::Response, T::Serializer>, T::Serializer> =
-                mem::transmute(self.process);
-            match process.tag_send_receive(send_tag, receive_tag, message, timeout) {
-                crate::MailboxResult::Message(message) => Ok(message),
-                crate::MailboxResult::TimedOut => Err(Timeout),
-                _ => unreachable!("send_receive should panic in case of other errors"),
-            }
+        let (timeout, deadline) = context::outgoing(timeout);
+        Timeout::from_result(self.process.request_send_receive(
+            send_tag,
+            receive_tag,
+            &request,
+            timeout,
+            deadline,
+        ))
+    }
+
+    /// Make a request to the process without waiting on the response.
+    ///
+    /// Multiple requests can be in flight at the same time, each returned
+    /// [`PendingReply`] receives only the response to its own request. Use
+    /// [`wait_all`] to collect the responses of many requests.
+    #[track_caller]
+    pub fn request_async<R: 'static>(&self, request: R) -> PendingReply<T::Response, T::Serializer>
+    where
+        T: RequestHandler<R>,
+        T::Serializer: CanSerialize<R>,
+        T::Serializer: CanSerialize<T::Response>,
+        T::Serializer: CanSerialize<RequestMessage<R, T::Response, T::Serializer>>,
+    {
+        let handler_id = T::Handlers::handler_id::<Request<R>>();
+        let send_tag = AbstractProcessTag::request(handler_id);
+        let (receive_tag, _) = AbstractProcessTag::extract_u6_data(send_tag);
+        let (_, deadline) = context::outgoing(None);
+        self.process.request_send(send_tag, &request, deadline);
+        PendingReply::new(receive_tag)
+    }
+
+    /// Make a request to the process, but stop waiting on the response if a
+    /// message tagged with `abort_tag` arrives first.
+    ///
+    /// Returns `None` if the request was aborted.
+    #[track_caller]
+    pub(crate) fn request_abortable<R: 'static>(
+        &self,
+        request: R,
+        abort_tag: Tag,
+        timeout: Option<Duration>,
+    ) -> Option<Result<T::Response, Timeout>>
+    where
+        T: RequestHandler<R>,
+        T::Serializer: CanSerialize<R>,
+        T::Serializer: CanSerialize<T::Response>,
+        T::Serializer: CanSerialize<RequestMessage<R, T::Response, T::Serializer>>,
+    {
+        let handler_id = T::Handlers::handler_id::<Request<R>>();
+        let send_tag = AbstractProcessTag::request(handler_id);
+        let (receive_tag, _) = AbstractProcessTag::extract_u6_data(send_tag);
+        let (timeout, deadline) = context::outgoing(timeout);
+        self.process.request_send(send_tag, &request, deadline);
+        match crate::select::receive(&[receive_tag, abort_tag], timeout) {
+            Some((_, tag)) if tag == receive_tag => match T::Serializer::decode() {
+                Ok(response) => Some(Ok(response)),
+                Err(_) => panic!(
+                    "Could not deserialize message: {}",
+                    type_name::<T::Response>()
+                ),
+            },
+            Some(_) => None,
+            None => Some(Err(Timeout)),
         }
     }
 
@@ -583,27 +764,25 @@ where
         T::Serializer: CanSerialize<T::Response>,
         T::Serializer: CanSerialize<RequestMessage<R, T::Response, T::Serializer>>,
     {
-        let return_address = ReturnAddress::from_self();
-        let message = RequestMessage(request, return_address);
         let handler_id = T::Handlers::handler_id::<DeferredRequest<R>>();
-        let send_tag = AbstractProcessTag::from_u6(handler_id);
+        let send_tag = AbstractProcessTag::request(handler_id);
         let (receive_tag, _) = AbstractProcessTag::extract_u6_data(send_tag);
-        unsafe {
-            // Cast into the right type for sending.
-            let process: Process<RequestMessage<R, T::Response, T::Serializer>, T::Serializer> =
-                mem::transmute(self.process);
-            match process.tag_send_receive(send_tag, receive_tag, message, timeout) {
-                crate::MailboxResult::Message(message) => Ok(message),
-                crate::MailboxResult::TimedOut => Err(Timeout),
-                _ => unreachable!("send_receive should panic in case of other errors"),
-            }
-        }
+        let (timeout, deadline) = context::outgoing(timeout);
+        Timeout::from_result(self.process.request_send_receive(
+            send_tag,
+            receive_tag,
+            &request,
+            timeout,
+            deadline,
+        ))
     }
 
     /// Set a timeout on the next action performed on this process.
     ///
     /// Timeouts affect [`ProcessRef::shutdown`], [`ProcessRef::request`] and
-    /// [`ProcessRef::deferred_request`] functions.
+    /// [`ProcessRef::deferred_request`] functions. Requests send the timeout
+    /// along as their deadline, and it's capped to the deadline of the current
+    /// process, see [`context`](crate::context).
     pub fn with_timeout(self, timeout: Duration) -> WithTimeout<ProcessRef<T>> {
         WithTimeout::from(timeout, self)
     }
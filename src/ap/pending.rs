@@ -0,0 +1,132 @@
+//! Replies to requests that were sent without waiting on them, see
+//! [`ProcessRef::request_async`](super::ProcessRef::request_async).
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use crate::serializer::CanSerialize;
+use crate::time::Timeout;
+use crate::{process_local, select, Tag};
+
+process_local! {
+    // Tags of replies that are dropped when they arrive, because nobody waits
+    // on them anymore.
+    static ABANDONED: RefCell<HashSet<i64>> = RefCell::new(HashSet::new());
+}
+
+/// The reply to a request that is in flight.
+///
+/// Each reply has its own tag, so replies can be waited on in any order. A
+/// reply that isn't waited on, or arrives after [`wait`](PendingReply::wait)
+/// timed out, is dropped when the pending reply is dropped and doesn't show
+/// up in other receives.
+#[must_use = "the reply is dropped if it's not waited on"]
+pub struct PendingReply<Response, S> {
+    tag: Tag,
+    claimed: bool,
+    response: PhantomData<(Response, S)>,
+}
+
+impl<Response, S> PendingReply<Response, S>
+where
+    S: CanSerialize<Response>,
+{
+    pub(crate) fn new(tag: Tag) -> Self {
+        PendingReply {
+            tag,
+            claimed: false,
+            response: PhantomData,
+        }
+    }
+
+    /// Waits on the reply, until the `timeout` expires if one is specified.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the reply can't be deserialized.
+    #[track_caller]
+    pub fn wait(mut self, timeout: Option<Duration>) -> Result<Response, Timeout> {
+        select::receive(&[self.tag], timeout).ok_or(Timeout)?;
+        self.claimed = true;
+        Ok(decode::<Response, S>())
+    }
+}
+
+impl<Response, S> Drop for PendingReply<Response, S> {
+    fn drop(&mut self) {
+        if self.claimed {
+            return;
+        }
+        // The reply may already be in the mailbox.
+        if select::receive(&[self.tag], Some(Duration::ZERO)).is_none() {
+            ABANDONED.with_borrow_mut(|mut abandoned| abandoned.insert(self.tag.id()));
+        }
+    }
+}
+
+/// Waits on all `pending` replies, until the `timeout` expires if one is
+/// specified.
+///
+/// Replies are collected in the order they arrive, and returned in the order
+/// of `pending`. Replies that didn't arrive before the timeout are returned as
+/// `Err(Timeout)`.
+///
+/// # Panics
+///
+/// This function will panic if a reply can't be deserialized.
+#[track_caller]
+pub fn wait_all<Response, S>(
+    pending: Vec<PendingReply<Response, S>>,
+    timeout: Option<Duration>,
+) -> Vec<Result<Response, Timeout>>
+where
+    S: CanSerialize<Response>,
+{
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut pending: Vec<Option<PendingReply<Response, S>>> =
+        pending.into_iter().map(Some).collect();
+    let mut results: Vec<Result<Response, Timeout>> =
+        pending.iter().map(|_| Err(Timeout)).collect();
+    loop {
+        let tags: Vec<Tag> = pending.iter().flatten().map(|reply| reply.tag).collect();
+        if tags.is_empty() {
+            break;
+        }
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let Some((_, tag)) = select::receive(&tags, timeout) else {
+            break;
+        };
+        let index = pending
+            .iter()
+            .position(|reply| reply.as_ref().is_some_and(|reply| reply.tag == tag))
+            .expect("Received a reply that isn't pending");
+        let mut reply = pending[index].take().unwrap();
+        reply.claimed = true;
+        results[index] = Ok(decode::<Response, S>());
+    }
+    results
+}
+
+#[track_caller]
+fn decode<Response, S: CanSerialize<Response>>() -> Response {
+    match S::decode() {
+        Ok(response) => response,
+        Err(_) => panic!(
+            "Could not deserialize message: {}",
+            std::any::type_name::<Response>()
+        ),
+    }
+}
+
+/// Drops the reply tagged with `tag` when it arrives.
+pub(crate) fn abandon(tag: Tag) {
+    ABANDONED.with_borrow_mut(|mut abandoned| abandoned.insert(tag.id()));
+}
+
+/// Returns `true` if the message in the buffer, tagged with `tag`, is a reply
+/// that nobody waits on anymore. It isn't expected again afterwards.
+pub(crate) fn discard_abandoned(tag: i64) -> bool {
+    ABANDONED.with_borrow_mut(|mut abandoned| !abandoned.is_empty() && abandoned.remove(&tag))
+}
@@ -0,0 +1,188 @@
+//! Messages that a process sends to itself, see
+//! [`ProcessRef::send`](super::ProcessRef::send).
+//!
+//! The message stays in the memory of the process and isn't serialized. Only
+//! a marker with the tag of the message goes through the host, so that the
+//! message is received in the same order, and with the same selective
+//! receives, as if it had been sent as a whole. The marker holds the id of the
+//! message and a random value that only the current process knows, other
+//! processes can't send messages that are taken for a marker.
+//!
+//! All receives that can return a marker decode the message with [`decode`]
+//! or [`decode_static`], they replace the marker with its message.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::host::api::message;
+use crate::serializer::{CanSerialize, DecodeError, EncodeError, MessageRw};
+use crate::{host, process_local, random, Tag};
+
+/// Size of a marker, the secret followed by the id of the message.
+const MARKER_SIZE: usize = 16;
+
+process_local! {
+    // Identifies markers sent by the current process, 0 until the first one.
+    static SECRET: Cell<u64> = Cell::new(0);
+    static NEXT_ID: Cell<u64> = Cell::new(0);
+    // Messages whose markers weren't received yet, by the id of the message.
+    static PENDING: RefCell<HashMap<u64, Kept>> = RefCell::new(HashMap::new());
+}
+
+/// A message kept until its marker is received.
+struct Kept {
+    message: Box<dyn Any>,
+    // Encodes the message with its serializer, if it's received as another type.
+    encode: fn(&dyn Any) -> Result<(), EncodeError>,
+}
+
+/// Keeps `message` for the current process and sends it a marker tagged with
+/// `tag`.
+pub(crate) fn send<M, S>(tag: Tag, message: M)
+where
+    M: 'static,
+    S: CanSerialize<M>,
+{
+    let id = NEXT_ID.get();
+    NEXT_ID.set(id + 1);
+    let kept = Kept {
+        message: Box::new(message),
+        encode: |message| S::encode(message.downcast_ref::<M>().unwrap()),
+    };
+    PENDING.with_borrow_mut(|mut pending| pending.insert(id, kept));
+
+    let mut marker = [0; MARKER_SIZE];
+    marker[..8].copy_from_slice(&secret().to_le_bytes());
+    marker[8..].copy_from_slice(&id.to_le_bytes());
+    unsafe { message::create_data(tag.id(), MARKER_SIZE as u64) };
+    MessageRw {}.write_all(&marker).unwrap();
+    host::send(host::node_id(), host::process_id());
+}
+
+/// Decodes the message in the buffer as `M` with `S`.
+///
+/// If the buffer holds a marker sent by [`send`], its message is encoded into
+/// the buffer first, as if it had been sent as a whole.
+pub(crate) fn decode<M, S: CanSerialize<M>>() -> Result<M, DecodeError> {
+    if let Some(kept) = take_kept() {
+        write(kept.encode, &*kept.message);
+    }
+    S::decode()
+}
+
+/// Same as [`decode`], but returns the message of a marker without
+/// serializing it, if it was sent as `M`.
+pub(crate) fn decode_static<M: 'static, S: CanSerialize<M>>() -> Result<M, DecodeError> {
+    if let Some(Kept { message, encode }) = take_kept() {
+        match message.downcast::<M>() {
+            Ok(message) => return Ok(*message),
+            Err(message) => write(encode, &*message),
+        }
+    }
+    S::decode()
+}
+
+// Replaces the marker in the buffer with the encoded `message`.
+fn write(encode: fn(&dyn Any) -> Result<(), EncodeError>, message: &dyn Any) {
+    unsafe { message::create_data(message::get_tag(), 0) };
+    // The serializer fails to decode the empty buffer if this fails.
+    let _ = encode(message);
+    unsafe { message::seek_data(0) };
+}
+
+// Removes the message of the marker in the buffer.
+fn take_kept() -> Option<Kept> {
+    if SECRET.get() == 0 || unsafe { message::data_size() } != MARKER_SIZE as u64 {
+        return None;
+    }
+    let mut marker = [0; MARKER_SIZE];
+    MessageRw {}.read_exact(&mut marker).ok()?;
+    let [secret, id] =
+        [&marker[..8], &marker[8..]].map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+    let kept = (secret == SECRET.get())
+        .then(|| PENDING.with_borrow_mut(|mut pending| pending.remove(&id)))
+        .flatten();
+    if kept.is_none() {
+        // A regular message of the same size, rewind it for the serializer.
+        unsafe { message::seek_data(0) };
+    }
+    kept
+}
+
+/// Returns `true` if the message in the buffer is a marker sent by [`send`],
+/// leaving the buffer at the start of the data.
+pub(crate) fn is_marker() -> bool {
+    if SECRET.get() == 0 || unsafe { message::data_size() } != MARKER_SIZE as u64 {
+        return false;
+    }
+    let mut secret = [0; 8];
+    unsafe {
+        message::seek_data(0);
+        message::read_data(secret.as_mut_ptr(), secret.len());
+        message::seek_data(0);
+    }
+    u64::from_le_bytes(secret) == SECRET.get()
+}
+
+fn secret() -> u64 {
+    if SECRET.get() == 0 {
+        // 0 means that no marker was sent yet.
+        SECRET.set(random::u64().max(1));
+    }
+    SECRET.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use lunatic_test::test;
+
+    use super::send;
+    use crate::protocol::{End, Protocol, ProtocolOrMailbox, Send};
+    use crate::select::{self, SelectMailbox};
+    use crate::serializer::Bincode;
+    use crate::{Mailbox, Process, Tag};
+
+    #[test]
+    fn receive_marker() {
+        send::<String, Bincode>(Tag::none(), "message".to_owned());
+        let mailbox = unsafe { Mailbox::<String>::new() };
+        assert_eq!(mailbox.receive(), "message");
+    }
+
+    #[test]
+    fn receive_marker_as_another_type() {
+        send::<String, Bincode>(Tag::none(), "message".to_owned());
+        // Bincode encodes strings and byte vectors the same way.
+        let mailbox = unsafe { Mailbox::<Vec<u8>>::new() };
+        assert_eq!(mailbox.receive(), b"message");
+    }
+
+    #[test]
+    fn select_marker() {
+        let tag = Tag::new();
+        send::<String, Bincode>(tag, "message".to_owned());
+        let mailbox = unsafe { Mailbox::<String>::new() };
+        // What the mailbox arm of `select!` expands to.
+        let (received, _) = select::receive(&[tag], None).unwrap();
+        assert_eq!(SelectMailbox::decode(&mailbox, received), "message");
+    }
+
+    #[test]
+    fn protocol_or_marker() {
+        send::<String, Bincode>(Tag::none(), "message".to_owned());
+        let mailbox = unsafe { Mailbox::<String>::new() };
+        let protocol = Process::spawn_link((), |_, protocol: Protocol<Send<i32, End>>| {
+            let _ = protocol.send(1);
+        });
+        match protocol.receive_or_mailbox(&mailbox) {
+            ProtocolOrMailbox::MailboxMsg(protocol, message) => {
+                assert_eq!(message, "message");
+                let (_, number) = protocol.receive();
+                assert_eq!(number, 1);
+            }
+            ProtocolOrMailbox::ProtocolMsg(..) => panic!("the marker was sent first"),
+        }
+    }
+}
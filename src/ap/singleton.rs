@@ -0,0 +1,318 @@
+//! Abstract processes that run once in the whole cluster.
+//!
+//! [`GlobalSingleton::start_or_lookup`] returns the instance of an
+//! [`AbstractProcess`] that is registered under a name on any node, or starts
+//! it on the current node. Each node that called it runs a small manager
+//! process for the name, which keeps track of the owner and takes over if the
+//! owner goes away.
+//!
+//! The host registry is local to each node, so the nodes agree on the owner
+//! through their [attributes](crate::distributed::set_attribute) instead. The
+//! node that runs the instance sets an attribute with its process ID, and the
+//! other nodes see it with the next heartbeat. If two nodes start an instance
+//! at the same time, the one with the lowest node ID wins, and the other
+//! instance is stopped. A started instance is only returned once it held the
+//! claim for [`CLAIM_PERIOD`] without a lower node claiming it too.
+//!
+//! # Takeover
+//!
+//! The owner goes away if the instance dies, or if its node doesn't send a
+//! heartbeat for [`STALE_AFTER`]. The managers of the other nodes then race
+//! to start a new instance, and again the lowest node ID wins. Only nodes that
+//! called [`start_or_lookup`](GlobalSingleton::start_or_lookup) for the name
+//! take part, since they have the argument to start it with. Processes that
+//! [`watch`](GlobalSingleton::watch) the name are notified every time the
+//! instance moves.
+//!
+//! # Partitions
+//!
+//! Nodes that can't reach each other can't agree on an owner. Each side of a
+//! partition elects its own instance, so there is one instance per side while
+//! the partition lasts. Once the nodes reach each other again, the instance
+//! on the lowest node ID is kept and all others are stopped, without handing
+//! over any state. Messages sent to a stopped instance are lost.
+//!
+//! # Example
+//!
+//! ```
+//! let locks = GlobalSingleton::<LockManager>::start_or_lookup("locks", ())?;
+//! locks.request(Acquire("migrations"));
+//!
+//! let moved = GlobalSingleton::<LockManager>::watch("locks");
+//! let locks = moved.receive();
+//! ```
+
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{AbstractProcess, ProcessRef};
+use crate::distributed::{self, HEARTBEAT_INTERVAL};
+use crate::pubsub::{Subscription, Topic};
+use crate::{host, Mailbox, MailboxResult, Process, Tag};
+
+/// How long a node needs to hold the claim on a singleton before its instance
+/// is returned.
+pub const CLAIM_PERIOD: Duration = Duration::from_secs(2);
+/// How long after the last heartbeat of a node its claims are ignored.
+pub const STALE_AFTER: Duration = Duration::from_secs(3);
+
+/// Error returned from [`GlobalSingleton::start_or_lookup`].
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SingletonError {
+    /// No other node runs the singleton, and starting it on the current node
+    /// failed.
+    #[error("failed to start the singleton: {0}")]
+    StartFailed(String),
+}
+
+/// Cluster-wide singletons of the abstract process `T`, see the
+/// [module documentation](self).
+pub struct GlobalSingleton<T>(PhantomData<T>);
+
+impl<T> GlobalSingleton<T>
+where
+    T: AbstractProcess,
+    T::Arg: Clone + Serialize + DeserializeOwned,
+{
+    /// Returns the instance registered under `name` on any node, or starts it
+    /// with `arg` on the current node.
+    ///
+    /// Blocks until the owner is settled, which can take [`CLAIM_PERIOD`] if
+    /// the instance is started. The current node takes part in the takeover
+    /// from now on, with `arg`. Later calls on the same node use the argument
+    /// of the first one.
+    pub fn start_or_lookup(name: &str, arg: T::Arg) -> Result<ProcessRef<T>, SingletonError> {
+        let tag = Tag::new();
+        manager_or_start::<T>(name, arg).send(ManagerMessage::Owner(Process::this(), tag));
+        unsafe { Mailbox::<Result<(u64, u64), SingletonError>>::new() }
+            .tag_receive(&[tag])
+            .map(|(node_id, process_id)| unsafe { ProcessRef::new(node_id, process_id) })
+    }
+
+    /// Returns the instance registered under `name`, as last seen by the
+    /// current node.
+    ///
+    /// Returns `None` if the current node didn't call
+    /// [`start_or_lookup`](Self::start_or_lookup) for the name, or the owner
+    /// isn't settled.
+    pub fn lookup(name: &str) -> Option<ProcessRef<T>> {
+        let tag = Tag::new();
+        manager::<T>(name)?.send(ManagerMessage::Lookup(Process::this(), tag));
+        unsafe { Mailbox::<Option<(u64, u64)>>::new() }
+            .tag_receive(&[tag])
+            .map(|(node_id, process_id)| unsafe { ProcessRef::new(node_id, process_id) })
+    }
+
+    /// Subscribes the current process to the instances of `name`.
+    ///
+    /// Every time the singleton moves, e.g. after a takeover, the new instance
+    /// is published to the subscription. Notifications are sent by the manager
+    /// of the current node, so the node needs to call
+    /// [`start_or_lookup`](Self::start_or_lookup) for the name.
+    pub fn watch(name: &str) -> Subscription<ProcessRef<T>> {
+        Topic::new(&moved_topic::<T>(name)).subscribe()
+    }
+}
+
+/// A process waiting on the owner of a singleton, and the tag of the reply.
+type OwnerWaiter = (Process<Result<(u64, u64), SingletonError>>, Tag);
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum ManagerMessage {
+    /// Waits on the settled owner.
+    Owner(Process<Result<(u64, u64), SingletonError>>, Tag),
+    /// Returns the settled owner, without waiting.
+    Lookup(Process<Option<(u64, u64)>>, Tag),
+}
+
+/// Name of the manager process of `name`, in the local registry.
+fn manager_name<T: AbstractProcess>(name: &str) -> String {
+    format!("{} + GlobalSingleton", ProcessRef::<T>::registry_name(name))
+}
+
+/// Name of the node attribute that holds the claim on `name`.
+fn claim_attribute<T: AbstractProcess>(name: &str) -> String {
+    format!("singleton: {}", ProcessRef::<T>::registry_name(name))
+}
+
+/// Name of the topic that the moves of `name` are published to.
+fn moved_topic<T: AbstractProcess>(name: &str) -> String {
+    format!("{} + moved", manager_name::<T>(name))
+}
+
+/// Returns the manager of `name` on the current node, if it's running.
+fn manager<T: AbstractProcess>(name: &str) -> Option<Process<ManagerMessage>> {
+    let registry_name = manager_name::<T>(name);
+    let mut node_id: u64 = 0;
+    let mut process_id: u64 = 0;
+    let result = unsafe {
+        host::api::registry::get(
+            registry_name.as_ptr(),
+            registry_name.len(),
+            &mut node_id,
+            &mut process_id,
+        )
+    };
+    match result {
+        0 => Some(Process::new(node_id, process_id)),
+        _ => None,
+    }
+}
+
+/// Returns the manager of `name` on the current node, or starts it with `arg`.
+fn manager_or_start<T>(name: &str, arg: T::Arg) -> Process<ManagerMessage>
+where
+    T: AbstractProcess,
+    T::Arg: Clone + Serialize + DeserializeOwned,
+{
+    let registry_name = manager_name::<T>(name);
+    let mut node_id: u64 = 0;
+    let mut process_id: u64 = 0;
+    unsafe {
+        match host::api::registry::get_or_put_later(
+            registry_name.as_ptr(),
+            registry_name.len(),
+            &mut node_id,
+            &mut process_id,
+        ) {
+            0 => Process::new(node_id, process_id),
+            _ => {
+                let process = Process::spawn((name.to_owned(), arg), manager_process::<T>);
+                host::api::registry::put(
+                    registry_name.as_ptr(),
+                    registry_name.len(),
+                    process.node_id(),
+                    process.id(),
+                );
+                process
+            }
+        }
+    }
+}
+
+/// State of the manager of one singleton on the current node.
+struct Manager<T: AbstractProcess> {
+    name: String,
+    arg: T::Arg,
+    claim: String,
+    /// The instance started by the current node, and when it was claimed.
+    local: Option<(ProcessRef<T>, Instant)>,
+    /// The settled owner.
+    owner: Option<(u64, u64)>,
+    /// The last owner published to the watchers.
+    announced: Option<(u64, u64)>,
+    waiting: Vec<OwnerWaiter>,
+}
+
+impl<T> Manager<T>
+where
+    T: AbstractProcess,
+    T::Arg: Clone,
+{
+    /// Decides which node owns the singleton and answers waiting callers.
+    fn elect(&mut self) {
+        let local_node = host::node_id();
+        if let Some((instance, _)) = self.local {
+            if !instance.is_alive() {
+                distributed::remove_attribute(self.claim.as_str());
+                self.local = None;
+            }
+        }
+        let remote = distributed::nodes()
+            .into_iter()
+            .filter(|&node_id| node_id != local_node)
+            .filter(|&node_id| {
+                distributed::node_health(node_id).is_some_and(|age| age < STALE_AFTER)
+            })
+            .filter_map(|node_id| {
+                let process_id = distributed::attributes(node_id)?
+                    .get(&self.claim)?
+                    .parse()
+                    .ok()?;
+                Some((node_id, process_id))
+            })
+            .min();
+
+        let owner = match (self.local, remote) {
+            (Some((instance, _)), Some(remote)) if remote.0 < local_node => {
+                // Lost the race, or the partition healed.
+                instance.kill();
+                distributed::remove_attribute(self.claim.as_str());
+                self.local = None;
+                Some(remote)
+            }
+            (Some((instance, claimed)), _) => {
+                (claimed.elapsed() >= CLAIM_PERIOD).then(|| (local_node, instance.id()))
+            }
+            (None, Some(remote)) => Some(remote),
+            (None, None) => {
+                match T::start(self.arg.clone()) {
+                    Ok(instance) => {
+                        distributed::set_attribute(self.claim.as_str(), instance.id().to_string());
+                        self.local = Some((instance, Instant::now()));
+                    }
+                    Err(err) => {
+                        let err = SingletonError::StartFailed(format!("{:?}", err));
+                        for (waiter, tag) in self.waiting.drain(..) {
+                            waiter.tag_send(tag, Err(err.clone()));
+                        }
+                    }
+                }
+                None
+            }
+        };
+
+        self.owner = owner;
+        if let Some(owner) = owner {
+            if self.announced != Some(owner) {
+                self.announced = Some(owner);
+                let instance = unsafe { ProcessRef::<T>::new(owner.0, owner.1) };
+                Topic::<ProcessRef<T>>::new(&moved_topic::<T>(&self.name)).publish(instance);
+            }
+            for (waiter, tag) in self.waiting.drain(..) {
+                waiter.tag_send(tag, Ok(owner));
+            }
+        }
+    }
+}
+
+fn manager_process<T>((name, arg): (String, T::Arg), mailbox: Mailbox<ManagerMessage>)
+where
+    T: AbstractProcess,
+    T::Arg: Clone + Serialize + DeserializeOwned,
+{
+    let mut manager = Manager::<T> {
+        claim: claim_attribute::<T>(&name),
+        name,
+        arg,
+        local: None,
+        owner: None,
+        announced: None,
+        waiting: Vec::new(),
+    };
+    // Elections run with the heartbeats, since claims of other nodes don't
+    // change in between.
+    let mut next_election = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= next_election {
+            manager.elect();
+            next_election = now + HEARTBEAT_INTERVAL;
+        }
+        match mailbox.receive_timeout(next_election.saturating_duration_since(now)) {
+            MailboxResult::Message(ManagerMessage::Owner(waiter, tag)) => match manager.owner {
+                Some(owner) => waiter.tag_send(tag, Ok(owner)),
+                None => manager.waiting.push((waiter, tag)),
+            },
+            MailboxResult::Message(ManagerMessage::Lookup(waiter, tag)) => {
+                waiter.tag_send(tag, manager.owner)
+            }
+            _ => (),
+        }
+    }
+}
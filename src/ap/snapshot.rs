@@ -0,0 +1,141 @@
+//! Snapshots of the state of an [`AbstractProcess`], used to restore it after
+//! a restart.
+//!
+//! A process that implements [`AbstractProcess::snapshot`] sends a snapshot
+//! of its state to a keeper process while it's running, and once more before
+//! it's shut down. When restarted, the last snapshot is passed to
+//! [`AbstractProcess::init_from_snapshot`]. Supervisors act as the keeper of
+//! their children, see
+//! [`SupervisorConfig::restart_with_snapshot`](crate::supervisor::SupervisorConfig::restart_with_snapshot).
+
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::handlers::{Handlers, Message};
+use super::tag::AbstractProcessTag;
+use super::{AbstractProcess, MessageHandler};
+use crate::serializer::Bincode;
+use crate::{Process, Tag};
+
+/// Handler data of the init tag, if a [`SnapshotSetup`] is sent to the new
+/// process.
+pub(crate) const WITH_SNAPSHOTS: u8 = 1;
+
+/// Serialized state of an [`AbstractProcess`].
+///
+/// The version is chosen by the process. A process restarted with a snapshot
+/// of a different version, e.g. after an upgrade changed the layout of the
+/// state, can ignore it and start fresh.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    version: u32,
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot of `state`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `state` can't be serialized.
+    pub fn new<T: Serialize>(version: u32, state: &T) -> Self {
+        let data = bincode::serialize(state).expect("Failed to serialize the snapshot");
+        Snapshot { version, data }
+    }
+
+    /// Returns the version of the snapshot.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the state in the snapshot.
+    ///
+    /// Returns `None` if the snapshot has a different version, or if it can't
+    /// be deserialized.
+    pub fn decode<T: DeserializeOwned>(&self, version: u32) -> Option<T> {
+        if self.version != version {
+            return None;
+        }
+        bincode::deserialize(&self.data).ok()
+    }
+}
+
+/// A snapshot sent to the keeper.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    pub(crate) key: u64,
+    pub(crate) snapshot: Snapshot,
+}
+
+/// Sent to a new process before `init` is called.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct SnapshotSetup {
+    pub(crate) restore: Option<Snapshot>,
+    pub(crate) keeper: Option<SnapshotKeeper>,
+}
+
+/// The process storing the snapshots of another one.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct SnapshotKeeper {
+    process: Process<StoreSnapshot>,
+    tag: Tag,
+    // Identifies the process to the keeper, stays the same across restarts.
+    key: u64,
+    // Minimum time between two snapshots.
+    interval: Duration,
+}
+
+impl SnapshotKeeper {
+    /// Returns the current process as the keeper of the snapshots sent with
+    /// `key`.
+    pub(crate) fn this<K>(key: u64, interval: Duration) -> Self
+    where
+        K: AbstractProcess<Serializer = Bincode> + MessageHandler<StoreSnapshot>,
+    {
+        let handler_id = K::Handlers::handler_id::<Message<StoreSnapshot>>();
+        SnapshotKeeper {
+            process: Process::this(),
+            tag: AbstractProcessTag::from_u6(handler_id),
+            key,
+            interval,
+        }
+    }
+}
+
+/// Sends snapshots of the running process to its keeper.
+pub(crate) struct Snapshotter {
+    keeper: SnapshotKeeper,
+    last: Option<Instant>,
+}
+
+impl Snapshotter {
+    pub(crate) fn new(keeper: SnapshotKeeper) -> Self {
+        Snapshotter { keeper, last: None }
+    }
+
+    /// Sends a snapshot if the interval passed since the last one.
+    pub(crate) fn after_message<AP: AbstractProcess>(&mut self, state: &AP::State) {
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < self.keeper.interval)
+        {
+            return;
+        }
+        self.send::<AP>(state);
+    }
+
+    /// Sends a snapshot.
+    pub(crate) fn send<AP: AbstractProcess>(&mut self, state: &AP::State) {
+        self.last = Some(Instant::now());
+        if let Some(snapshot) = AP::snapshot(state) {
+            let message = StoreSnapshot {
+                key: self.keeper.key,
+                snapshot,
+            };
+            self.keeper.process.tag_send(self.keeper.tag, message);
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use super::handlers::Handlers;
+use super::AbstractProcess;
+use crate::mailbox::PRIORITY_TAG;
 use crate::Tag;
 
 /// Unique tags that also hold additional `u6` data used to dispatch to the
@@ -18,6 +21,36 @@ impl AbstractProcessTag {
         Tag::from(id)
     }
 
+    /// Returns a [`Tag`] for a request to the handler `data`, with `u6` data
+    /// encoded into it.
+    ///
+    /// The tag without the data is used for the reply, so it's created with
+    /// [`Tag::for_reply`].
+    #[track_caller]
+    pub(crate) fn request(data: u8) -> Tag {
+        assert!(data < 64, "Only values less than 64 can fit into a `u6`");
+        let tag = Tag::for_reply();
+        Tag::from(((data as i64) << 56) | tag.id())
+    }
+
+    /// Returns the [`Tag`] used to send a message to the handler `data`.
+    ///
+    /// Messages to priority handlers share one tag per handler, so that the
+    /// receiving process can look for them before all other messages.
+    #[track_caller]
+    pub(crate) fn message<AP: AbstractProcess>(data: u8) -> Tag {
+        if AP::Handlers::is_priority(data) {
+            AbstractProcessTag::priority(data)
+        } else {
+            AbstractProcessTag::from_u6(data)
+        }
+    }
+
+    /// Returns the priority lane [`Tag`] with `u6` data encoded into it.
+    pub(crate) fn priority(data: u8) -> Tag {
+        Tag::from(((data as i64) << 56) | PRIORITY_TAG)
+    }
+
     /// Extracts `u6` data encoded into the [`Tag`].
     ///
     /// The returned `Tag` doesn't contain the data anymore.
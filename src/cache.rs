@@ -0,0 +1,302 @@
+//! Caches that are replicated into the processes using them.
+//!
+//! A [`ReplicatedCache`] keeps the entries a process used in a process-local
+//! map, so that repeated reads don't leave the process. Misses are looked up
+//! in the owner process of the cache, which holds all entries. Changes go
+//! through the owner, which publishes them on a [`Topic`] to every process
+//! that uses the cache. Each process applies the changes the next time it
+//! accesses the cache, so a process that doesn't touch it isn't interrupted.
+//!
+//! The local map holds at most `capacity` entries, the least recently used
+//! ones are evicted first. Cache accesses are counted in [`HITS_COUNTER`] and
+//! [`MISSES_COUNTER`], labeled by the name of the cache.
+//!
+//! # Example
+//!
+//! ```
+//! let users = ReplicatedCache::<u64, String>::new("users", 1_000);
+//! users.put(1, "alice".to_owned());
+//!
+//! Process::spawn((), |_, _: Mailbox<()>| {
+//!     let users = ReplicatedCache::<u64, String>::new("users", 1_000);
+//!     // Looked up in the owner once, and locally afterwards.
+//!     assert_eq!(users.get(&1).as_deref(), Some("alice"));
+//! });
+//! ```
+
+use std::any::{type_name, Any};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::pubsub::{Subscription, Topic};
+use crate::{metrics, process_local, registry, Mailbox, Process, Tag};
+
+/// Counter of reads served from the local map, labeled by the cache.
+pub const HITS_COUNTER: &str = "lunatic.cache.hits";
+/// Counter of reads that were looked up in the owner, labeled by the cache.
+pub const MISSES_COUNTER: &str = "lunatic.cache.misses";
+
+/// A cache shared by many processes, see the [module level
+/// documentation](self).
+///
+/// Caches are identified by their name and the key and value types. Handles
+/// with the same name share the local map within a process.
+pub struct ReplicatedCache<K, V> {
+    name: String,
+    capacity: usize,
+    owner: Process<OwnerMessage<K, V>>,
+    topic: Topic<Change<K, V>>,
+    hits: String,
+    misses: String,
+}
+
+impl<K, V> ReplicatedCache<K, V>
+where
+    K: Serialize + DeserializeOwned + Eq + Hash + Clone + 'static,
+    V: Serialize + DeserializeOwned + Clone + 'static,
+{
+    /// Returns the cache registered under `name`, or creates it.
+    ///
+    /// The local map of the current process holds at most `capacity` entries.
+    /// The first handle created in a process sets the capacity.
+    pub fn new(name: &str, capacity: usize) -> Self {
+        let name = format!(
+            "{} + ReplicatedCache + {}/{}",
+            name,
+            type_name::<K>(),
+            type_name::<V>()
+        );
+        let owner = match registry::get_or_put_later(&name) {
+            Some((node_id, process_id)) => Process::new(node_id, process_id),
+            None => {
+                let owner = Process::spawn(name.clone(), owner_process::<K, V>);
+                registry::put(&name, owner.node_id(), owner.id());
+                owner
+            }
+        };
+        let labels = [("cache", name.as_str())];
+        ReplicatedCache {
+            capacity: capacity.max(1),
+            owner,
+            topic: Topic::new(&name),
+            hits: metrics::with_labels(HITS_COUNTER, &labels),
+            misses: metrics::with_labels(MISSES_COUNTER, &labels),
+            name,
+        }
+    }
+
+    /// Returns the value of `key`.
+    ///
+    /// Changes published since the last access are applied first. If the
+    /// local map doesn't have the key, it's looked up in the owner and kept in
+    /// the local map.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let cached = self.with_local(|local| local.get(key));
+        if let Some(value) = cached {
+            metrics::increment_counter(&self.hits);
+            return Some(value);
+        }
+        metrics::increment_counter(&self.misses);
+        let value = self.ask(|reply, tag| OwnerMessage::Get(key.clone(), reply, tag))?;
+        // Changes that were published before the owner replied are applied
+        // first, the reply is at least as new as them.
+        self.with_local(|local| local.insert(key.clone(), value.clone()));
+        Some(value)
+    }
+
+    /// Sets the value of `key` in all processes using the cache.
+    ///
+    /// Returns once the change is in the mailboxes of the other processes, so
+    /// they see the new value on their next access.
+    pub fn put(&self, key: K, value: V) {
+        self.ask::<()>(|reply, tag| OwnerMessage::Put(key.clone(), value.clone(), reply, tag));
+        self.with_local(|local| local.insert(key, value));
+    }
+
+    /// Removes `key` from all processes using the cache, including the owner.
+    pub fn remove(&self, key: &K) {
+        self.ask::<()>(|reply, tag| OwnerMessage::Remove(key.clone(), reply, tag));
+        self.with_local(|local| local.remove(key));
+    }
+
+    /// Returns the number of entries in the local map of the current process.
+    pub fn local_len(&self) -> usize {
+        self.with_local(|local| local.entries.len())
+    }
+
+    /// Drops the local map of the current process, without changing the
+    /// other processes.
+    pub fn clear_local(&self) {
+        self.with_local(|local| {
+            local.entries.clear();
+            local.order.clear();
+        });
+    }
+
+    // Sends a message to the owner and waits on the reply.
+    fn ask<R>(&self, message: impl FnOnce(Process<R>, Tag) -> OwnerMessage<K, V>) -> R
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        let tag = Tag::new();
+        let mailbox = unsafe { Mailbox::<R>::new() };
+        self.owner.send(message(mailbox.this(), tag));
+        mailbox.tag_receive(&[tag])
+    }
+
+    // Runs `f` on the local map of the current process, after applying the
+    // changes that arrived since the last access.
+    fn with_local<T>(&self, f: impl FnOnce(&mut Local<K, V>) -> T) -> T {
+        let mut local = LOCALS
+            .with_borrow_mut(|mut locals| locals.remove(&self.name))
+            .and_then(|local| local.downcast::<Local<K, V>>().ok())
+            .unwrap_or_else(|| {
+                Box::new(Local {
+                    capacity: self.capacity,
+                    entries: HashMap::new(),
+                    order: BTreeMap::new(),
+                    next_use: 0,
+                    changes: self.topic.subscribe(),
+                })
+            });
+        local.apply_changes();
+        let result = f(&mut local);
+        LOCALS.with_borrow_mut(|mut locals| locals.insert(self.name.clone(), local));
+        result
+    }
+}
+
+impl<K, V> Clone for ReplicatedCache<K, V> {
+    fn clone(&self) -> Self {
+        ReplicatedCache {
+            name: self.name.clone(),
+            capacity: self.capacity,
+            owner: self.owner,
+            topic: self.topic,
+            hits: self.hits.clone(),
+            misses: self.misses.clone(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for ReplicatedCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicatedCache")
+            .field("name", &self.name)
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+process_local! {
+    // The local maps of the caches used by the current process, by name.
+    static LOCALS: RefCell<HashMap<String, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+// The local map of a cache.
+struct Local<K, V> {
+    capacity: usize,
+    // The values and when they were last used.
+    entries: HashMap<K, (V, u64)>,
+    // The keys by when they were last used.
+    order: BTreeMap<u64, K>,
+    next_use: u64,
+    changes: Subscription<Change<K, V>>,
+}
+
+impl<K, V> Local<K, V>
+where
+    K: Serialize + DeserializeOwned + Eq + Hash + Clone,
+    V: Serialize + DeserializeOwned + Clone,
+{
+    fn get(&mut self, key: &K) -> Option<V> {
+        let used = self.next_use;
+        let (value, last_used) = self.entries.get_mut(key)?;
+        self.order.remove(last_used);
+        *last_used = used;
+        self.order.insert(used, key.clone());
+        self.next_use += 1;
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.insert(self.next_use, key.clone());
+        self.entries.insert(key, (value, self.next_use));
+        self.next_use += 1;
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some((_, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+        }
+    }
+
+    // Applies the changes waiting in the mailbox.
+    fn apply_changes(&mut self) {
+        while let Ok(change) = self.changes.try_receive(Some(Duration::ZERO)) {
+            match change {
+                // Only entries that are used by the process are kept.
+                Change::Updated(key, value) => {
+                    if let Some((cached, _)) = self.entries.get_mut(&key) {
+                        *cached = value;
+                    }
+                }
+                Change::Removed(key) => self.remove(&key),
+            }
+        }
+    }
+}
+
+/// A change published by the owner of a cache.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum Change<K, V> {
+    Updated(K, V),
+    Removed(K),
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum OwnerMessage<K, V> {
+    Get(K, Process<Option<V>>, Tag),
+    Put(K, V, Process<()>, Tag),
+    Remove(K, Process<()>, Tag),
+}
+
+// Holds all entries of a cache and publishes the changes.
+fn owner_process<K, V>(name: String, mailbox: Mailbox<OwnerMessage<K, V>>)
+where
+    K: Serialize + DeserializeOwned + Eq + Hash + Clone,
+    V: Serialize + DeserializeOwned + Clone,
+{
+    let mut entries: HashMap<K, V> = HashMap::new();
+    let topic = Topic::<Change<K, V>>::new(&name);
+    loop {
+        match mailbox.receive() {
+            OwnerMessage::Get(key, reply, tag) => {
+                reply.tag_send(tag, entries.get(&key).cloned());
+            }
+            OwnerMessage::Put(key, value, reply, tag) => {
+                entries.insert(key.clone(), value.clone());
+                topic.publish_confirmed(Change::Updated(key, value));
+                reply.tag_send(tag, ());
+            }
+            OwnerMessage::Remove(key, reply, tag) => {
+                entries.remove(&key);
+                topic.publish_confirmed(Change::Removed(key));
+                reply.tag_send(tag, ());
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Multi-producer, single-consumer channels built on top of processes.
+//!
+//! The API follows [`std::sync::mpsc`]. Each channel is backed by a dedicated
+//! process that holds the queued values. The process is linked to the process
+//! that created the channel, and the [`Receiver`] should stay inside of it.
+//! [`Sender`]s can be cloned and sent to other processes.
+//!
+//! # Example
+//!
+//! ```
+//! let (sender, receiver) = lunatic::channel::<u64, Bincode>();
+//! Process::spawn(sender, |sender, _: Mailbox<()>| {
+//!     sender.send(42).unwrap();
+//! });
+//! assert_eq!(receiver.recv(), Ok(42));
+//! ```
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::host::api::message;
+use crate::select::{self, SelectMailbox};
+use crate::serializer::CanSerialize;
+use crate::{host, Mailbox, MailboxResult, Process, Tag};
+
+/// Tag of the notification sent when a sender is dropped.
+const DROP_SENDER_TAG: i64 = 4;
+/// Tag of the notification sent when the receiver is dropped.
+const CLOSE_TAG: i64 = 5;
+
+/// How often a sender blocked on a full channel checks if the channel process
+/// is still alive.
+const ALIVE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates a new unbounded channel.
+///
+/// Sending on an unbounded channel never blocks.
+pub fn channel<T, S>() -> (Sender<T, S>, Receiver<T, S>)
+where
+    S: CanSerialize<ChannelMessage<T, S>> + CanSerialize<Reply<T>>,
+{
+    new_channel(None)
+}
+
+/// Creates a new bounded channel.
+///
+/// Sending on a bounded channel blocks while `capacity` values are queued. If
+/// `capacity` is 0, each send blocks until the value is received.
+pub fn sync_channel<T, S>(capacity: usize) -> (Sender<T, S>, Receiver<T, S>)
+where
+    S: CanSerialize<ChannelMessage<T, S>> + CanSerialize<Reply<T>>,
+{
+    new_channel(Some(capacity))
+}
+
+fn new_channel<T, S>(capacity: Option<usize>) -> (Sender<T, S>, Receiver<T, S>)
+where
+    S: CanSerialize<ChannelMessage<T, S>> + CanSerialize<Reply<T>>,
+{
+    // The channel process is spawned with `Bincode`, so that the capture doesn't
+    // add requirements to the channel serializer.
+    let process: Process<()> = Process::spawn_link(capacity, channel_process::<T, S>);
+    let process = Process::new(process.node_id(), process.id());
+    let sender = Sender {
+        process,
+        bounded: capacity.is_some(),
+        transferred: Cell::new(false),
+    };
+    let receiver = Receiver { process };
+    (sender, receiver)
+}
+
+/// The sending half of a channel.
+///
+/// Senders can be cloned and sent to other processes. Sending a `Sender` to
+/// another process transfers it, it should be cloned first if the same sender
+/// is sent more than once.
+pub struct Sender<T, S> {
+    process: Process<ChannelMessage<T, S>, S>,
+    bounded: bool,
+    transferred: Cell<bool>,
+}
+
+impl<T, S> Sender<T, S>
+where
+    S: CanSerialize<ChannelMessage<T, S>> + CanSerialize<Reply<T>>,
+{
+    /// Sends a value over the channel.
+    ///
+    /// On a bounded channel this blocks until there is room for the value. An
+    /// error is returned if the [`Receiver`] was dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.closed() {
+            return Err(SendError::Closed(value));
+        }
+        if !self.bounded {
+            self.process.send(ChannelMessage::Send(value, None));
+            return Ok(());
+        }
+
+        let tag = Tag::new();
+        self.process
+            .send(ChannelMessage::Send(value, Some((Process::this(), tag))));
+        let mailbox = unsafe { Mailbox::<Reply<T>, S>::new() };
+        loop {
+            match mailbox.tag_receive_timeout(&[tag], ALIVE_CHECK_INTERVAL) {
+                MailboxResult::Message(Reply::Sent) => return Ok(()),
+                MailboxResult::Message(Reply::Rejected(value)) => {
+                    return Err(SendError::Closed(value))
+                }
+                MailboxResult::TimedOut if self.closed() => return Err(SendError::Disconnected),
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns `true` if the channel process is known to be dead.
+    ///
+    /// The liveness of remote processes can't be checked.
+    fn closed(&self) -> bool {
+        self.process.node_id() == host::node_id() && !self.process.is_alive()
+    }
+}
+
+impl<T, S> Clone for Sender<T, S>
+where
+    S: CanSerialize<ChannelMessage<T, S>>,
+{
+    fn clone(&self) -> Self {
+        self.process.send(ChannelMessage::AddSender);
+        Self {
+            process: self.process,
+            bounded: self.bounded,
+            transferred: Cell::new(false),
+        }
+    }
+}
+
+impl<T, S> Drop for Sender<T, S> {
+    fn drop(&mut self) {
+        if !self.transferred.get() {
+            notify(self.process, DROP_SENDER_TAG);
+        }
+    }
+}
+
+impl<T, S> fmt::Debug for Sender<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("process", &self.process)
+            .field("bounded", &self.bounded)
+            .finish()
+    }
+}
+
+impl<T, S> Serialize for Sender<T, S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        // The deserialized sender takes over, this one doesn't count anymore.
+        self.transferred.set(true);
+        (self.process, self.bounded).serialize(serializer)
+    }
+}
+
+impl<'de, T, S> Deserialize<'de> for Sender<T, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (process, bounded) = Deserialize::deserialize(deserializer)?;
+        Ok(Self {
+            process,
+            bounded,
+            transferred: Cell::new(false),
+        })
+    }
+}
+
+/// The receiving half of a channel.
+///
+/// Dropping the receiver stops the channel process, all further sends fail.
+pub struct Receiver<T, S> {
+    process: Process<ChannelMessage<T, S>, S>,
+}
+
+impl<T, S> Receiver<T, S>
+where
+    S: CanSerialize<ChannelMessage<T, S>> + CanSerialize<Reply<T>>,
+{
+    /// Waits for the next value.
+    ///
+    /// Returns an error if all [`Sender`]s were dropped and no values are left
+    /// in the channel.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.recv_(true, None) {
+            Reply::Value(value) => Ok(value),
+            _ => Err(RecvError),
+        }
+    }
+
+    /// Returns the next value, without waiting for it.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.recv_(false, None) {
+            Reply::Value(value) => Ok(value),
+            Reply::Empty => Err(TryRecvError::Empty),
+            _ => Err(TryRecvError::Closed),
+        }
+    }
+
+    /// Waits for the next value, until the `timeout` expires.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match self.recv_(true, Some(timeout)) {
+            Reply::Value(value) => Ok(value),
+            Reply::Empty => Err(RecvTimeoutError::Timeout),
+            _ => Err(RecvTimeoutError::Closed),
+        }
+    }
+
+    fn recv_(&self, wait: bool, timeout: Option<Duration>) -> Reply<T> {
+        let tag = Tag::new();
+        self.process
+            .send(ChannelMessage::Recv(Process::this(), tag, wait));
+        let mailbox = unsafe { Mailbox::<Reply<T>, S>::new() };
+        let result = match timeout {
+            Some(timeout) => mailbox.tag_receive_timeout(&[tag], timeout),
+            None => MailboxResult::Message(mailbox.tag_receive(&[tag])),
+        };
+        match result {
+            MailboxResult::Message(reply) => reply,
+            _ => {
+                // If a reply was sent at the same time as the request was cancelled, the
+                // channel process ignores the cancellation and the reply is received.
+                self.process.send(ChannelMessage::CancelRecv(tag));
+                mailbox.tag_receive(&[tag])
+            }
+        }
+    }
+}
+
+impl<T, S> Drop for Receiver<T, S> {
+    fn drop(&mut self) {
+        notify(self.process, CLOSE_TAG);
+    }
+}
+
+impl<T, S> fmt::Debug for Receiver<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("process", &self.process)
+            .finish()
+    }
+}
+
+/// Error returned from [`Sender::send`].
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendError<T> {
+    /// The [`Receiver`] was dropped, the value is returned back.
+    Closed(T),
+    /// The channel process died while the sender was waiting on room in the
+    /// channel. The value is lost.
+    Disconnected,
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Closed(_) => write!(f, "Closed(..)"),
+            SendError::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Closed(_) => write!(f, "sending on a closed channel"),
+            SendError::Disconnected => write!(f, "the channel process died"),
+        }
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+/// Error returned from [`Receiver::recv`] if all senders were dropped.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiving on a closed channel")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+/// Error returned from [`Receiver::try_recv`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    /// No value is currently in the channel.
+    Empty,
+    /// All senders were dropped and no values are left in the channel.
+    Closed,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "receiving on an empty channel"),
+            TryRecvError::Closed => write!(f, "receiving on a closed channel"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+/// Error returned from [`Receiver::recv_timeout`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecvTimeoutError {
+    /// No value arrived before the timeout expired.
+    Timeout,
+    /// All senders were dropped and no values are left in the channel.
+    Closed,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => write!(f, "timed out waiting on channel"),
+            RecvTimeoutError::Closed => write!(f, "receiving on a closed channel"),
+        }
+    }
+}
+
+impl std::error::Error for RecvTimeoutError {}
+
+/// Messages handled by the channel process.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: serde::de::DeserializeOwned"
+))]
+pub enum ChannelMessage<T, S> {
+    Send(T, Option<(Process<Reply<T>, S>, Tag)>),
+    Recv(Process<Reply<T>, S>, Tag, bool),
+    CancelRecv(Tag),
+    AddSender,
+}
+
+/// Replies sent from the channel process.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum Reply<T> {
+    Sent,
+    Rejected(T),
+    Value(T),
+    Empty,
+    Closed,
+}
+
+/// Sends an empty notification tagged with `tag` to the channel process.
+///
+/// Notifications don't depend on the channel serializer, so that they can be
+/// sent from `Drop` implementations.
+fn notify<M, S>(process: Process<M, S>, tag: i64) {
+    unsafe { message::create_data(tag, 0) };
+    host::send(process.node_id(), process.id());
+}
+
+/// A value waiting in the channel, with the sender blocked on it.
+type Queued<T, S> = (T, Option<(Process<Reply<T>, S>, Tag)>);
+
+fn channel_process<T, S>(capacity: Option<usize>, _: Mailbox<()>)
+where
+    S: CanSerialize<ChannelMessage<T, S>> + CanSerialize<Reply<T>>,
+{
+    let mailbox = unsafe { Mailbox::<ChannelMessage<T, S>, S>::new() };
+    let mut queue: VecDeque<Queued<T, S>> = VecDeque::new();
+    let mut receivers: VecDeque<(Process<Reply<T>, S>, Tag)> = VecDeque::new();
+    let mut senders = 1;
+    let tags = [
+        Tag::none(),
+        Tag::from(DROP_SENDER_TAG),
+        Tag::from(CLOSE_TAG),
+    ];
+    loop {
+        let message = match select::receive(&tags, None) {
+            Some((_, tag)) if tag.id() == DROP_SENDER_TAG => {
+                senders -= 1;
+                if senders == 0 {
+                    for (receiver, tag) in receivers.drain(..) {
+                        receiver.tag_send(tag, Reply::Closed);
+                    }
+                }
+                continue;
+            }
+            Some((_, tag)) if tag.id() == CLOSE_TAG => {
+                for (value, waiting) in queue.drain(..) {
+                    if let Some((sender, tag)) = waiting {
+                        sender.tag_send(tag, Reply::Rejected(value));
+                    }
+                }
+                return;
+            }
+            _ => mailbox.decode(0),
+        };
+        match message {
+            ChannelMessage::Send(value, waiting) => {
+                if let Some((receiver, tag)) = receivers.pop_front() {
+                    receiver.tag_send(tag, Reply::Value(value));
+                    if let Some((sender, tag)) = waiting {
+                        sender.tag_send(tag, Reply::Sent);
+                    }
+                    continue;
+                }
+                let waiting = match (capacity, waiting) {
+                    (Some(capacity), Some((sender, tag))) if queue.len() < capacity => {
+                        sender.tag_send(tag, Reply::Sent);
+                        None
+                    }
+                    (_, waiting) => waiting,
+                };
+                queue.push_back((value, waiting));
+            }
+            ChannelMessage::Recv(receiver, tag, wait) => match queue.pop_front() {
+                Some((value, waiting)) => {
+                    receiver.tag_send(tag, Reply::Value(value));
+                    if let Some((sender, tag)) = waiting {
+                        sender.tag_send(tag, Reply::Sent);
+                    }
+                    // A value moved into the capacity of the channel.
+                    let index = capacity.and_then(|capacity| capacity.checked_sub(1));
+                    if let Some((_, waiting)) = index.and_then(|index| queue.get_mut(index)) {
+                        if let Some((sender, tag)) = waiting.take() {
+                            sender.tag_send(tag, Reply::Sent);
+                        }
+                    }
+                }
+                None if senders == 0 => receiver.tag_send(tag, Reply::Closed),
+                None if wait => receivers.push_back((receiver, tag)),
+                None => receiver.tag_send(tag, Reply::Empty),
+            },
+            ChannelMessage::CancelRecv(tag) => {
+                if let Some(index) = receivers.iter().position(|(_, t)| *t == tag) {
+                    let (receiver, tag) = receivers.remove(index).unwrap();
+                    receiver.tag_send(tag, Reply::Empty);
+                }
+            }
+            ChannelMessage::AddSender => senders += 1,
+        }
+    }
+}
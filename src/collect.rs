@@ -0,0 +1,129 @@
+//! Scatter-gather over many processes.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::function::FuncRef;
+use crate::panic::{catch_panic, Panicked};
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{select, Mailbox, Process, Tag};
+
+/// Error of a single task spawned with [`spawn_collect`].
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskError {
+    /// The task panicked.
+    #[error("task panicked: {0}")]
+    Panicked(Panicked),
+    /// The task didn't finish before the deadline.
+    #[error("task didn't finish before the deadline")]
+    Timeout,
+}
+
+/// Spawns a process for each of the `inputs` that calls `f` with it, and
+/// collects the results.
+///
+/// Results are returned in the same order as the inputs. If the `deadline`
+/// expires before all tasks finish, the remaining ones are killed and return
+/// `Err(TaskError::Timeout)`.
+///
+/// # Example
+///
+/// ```
+/// let squares = lunatic::spawn_collect(1..=3, |x: u64| x * x, Duration::from_secs(1));
+/// assert_eq!(squares, vec![Ok(1), Ok(4), Ok(9)]);
+/// ```
+pub fn spawn_collect<I, O>(
+    inputs: impl IntoIterator<Item = I>,
+    f: fn(I) -> O,
+    deadline: Duration,
+) -> Vec<Result<O, TaskError>>
+where
+    I: Serialize + DeserializeOwned,
+    O: Serialize + DeserializeOwned,
+{
+    spawn_collect_limit(inputs, f, deadline, usize::MAX)
+}
+
+/// Same as [`spawn_collect`], but runs at most `limit` tasks at the same time.
+///
+/// # Panics
+///
+/// Panics if `limit` is 0.
+pub fn spawn_collect_limit<I, O>(
+    inputs: impl IntoIterator<Item = I>,
+    f: fn(I) -> O,
+    deadline: Duration,
+    limit: usize,
+) -> Vec<Result<O, TaskError>>
+where
+    I: Serialize + DeserializeOwned,
+    O: Serialize + DeserializeOwned,
+{
+    assert!(
+        limit > 0,
+        "The concurrency limit of `spawn_collect` can't be 0"
+    );
+    let deadline = Instant::now() + deadline;
+    let mut inputs = inputs.into_iter().enumerate();
+    let mut results: Vec<Option<Result<O, TaskError>>> = Vec::new();
+    let mut running: HashMap<Tag, (usize, Process<()>)> = HashMap::new();
+    let this = Process::<Result<O, Panicked>>::this();
+    let f = FuncRef::new(f);
+
+    loop {
+        while running.len() < limit {
+            let Some((index, input)) = inputs.next() else {
+                break;
+            };
+            results.push(None);
+            let tag = Tag::new();
+            let process = Process::spawn_link((this, tag, f, input), task_entry::<I, O>);
+            running.insert(tag, (index, process));
+        }
+        if running.is_empty() {
+            break;
+        }
+
+        let tags: Vec<Tag> = running.keys().copied().collect();
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match select::receive(&tags, Some(remaining)) {
+            Some((_, tag)) => {
+                let (index, _) = running.remove(&tag).unwrap();
+                let result: Result<O, Panicked> = Bincode::decode().unwrap();
+                results[index] = Some(result.map_err(TaskError::Panicked));
+            }
+            None => {
+                // Unlink before killing, so that the kill doesn't propagate back.
+                for (_, process) in running.values() {
+                    process.unlink();
+                    process.kill();
+                }
+                running.clear();
+                // Tasks that didn't start yet also missed the deadline.
+                results.extend(inputs.by_ref().map(|_| None));
+                break;
+            }
+        }
+    }
+
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or(Err(TaskError::Timeout)))
+        .collect()
+}
+
+type TaskCapture<I, O> = (Process<Result<O, Panicked>>, Tag, FuncRef<fn(I) -> O>, I);
+
+fn task_entry<I, O>((parent, tag, f, input): TaskCapture<I, O>, _: Mailbox<()>)
+where
+    I: Serialize + DeserializeOwned,
+    O: Serialize + DeserializeOwned,
+{
+    // Panics are reported as results, so that they don't kill the collector.
+    let result = catch_panic(|| f.get()(input));
+    parent.tag_send(tag, result);
+}
@@ -0,0 +1,258 @@
+//! Running small `Future`s inside a process.
+//!
+//! Many libraries expose async APIs without doing any IO, e.g. parsers, state
+//! machines or traits written with `async-trait`. [`block_on`] drives such a
+//! future to completion in the current process. Futures can wait on messages
+//! with [`receive`] and [`tag_receive`], while the process blocks on the
+//! receive underneath.
+//!
+//! This is not a general async runtime. There is no reactor, timers or
+//! spawning of tasks, so futures that depend on another runtime, e.g. tokio
+//! sockets or `tokio::time::sleep`, don't work. A future that waits on
+//! something else than a message can never be woken, and [`block_on`] panics
+//! instead of blocking forever.
+//!
+//! # Example
+//!
+//! ```
+//! async fn ask(server: Process<Question>, mailbox: Mailbox<Answer>) -> Answer {
+//!     let tag = Tag::new();
+//!     server.send(Question(mailbox.this(), tag));
+//!     compat::tag_receive(mailbox, &[tag]).await
+//! }
+//!
+//! let answer = compat::block_on(ask(server, mailbox));
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::ptr;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::time::Duration;
+
+use crate::host::api::message;
+use crate::mailbox;
+use crate::serializer::CanSerialize;
+use crate::{process_local, Mailbox, MailboxResult, Tag};
+
+process_local! {
+    // Set if a future was woken since the executor polled it.
+    static WOKEN: Cell<bool> = Cell::new(false);
+    // Set while `block_on` is running.
+    static RUNNING: Cell<bool> = Cell::new(false);
+    // Receives that wait on a message, in the order they started waiting.
+    static WAITING: RefCell<Vec<Waiting>> = RefCell::new(Vec::new());
+    static NEXT_ID: Cell<u64> = Cell::new(0);
+}
+
+// A receive that waits on a message.
+struct Waiting {
+    id: u64,
+    // Empty if it takes any message.
+    tags: Vec<i64>,
+    // Decodes the message in the scratch buffer and wakes the receive.
+    deliver: Box<dyn FnOnce(u32)>,
+}
+
+/// Runs `future` to completion in the current process and returns its output.
+///
+/// While the future is pending, the process blocks until a message arrives
+/// for one of the [`Receive`] futures it waits on.
+///
+/// # Panics
+///
+/// Panics if the future is pending without waiting on a message, because it
+/// couldn't be woken anymore. Also panics if it's called from a future that
+/// is run by `block_on`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    assert!(!RUNNING.get(), "`compat::block_on` can't be nested");
+    RUNNING.set(true);
+    // Resets the state if the future panics.
+    let _running = Running;
+    let mut future = pin!(future);
+    let waker = waker();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        WOKEN.set(false);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        while !WOKEN.get() {
+            park();
+        }
+    }
+}
+
+struct Running;
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.set(false);
+        WAITING.with_borrow_mut(|mut waiting| waiting.clear());
+    }
+}
+
+// Blocks until a message arrives for one of the waiting receives, and hands
+// it over.
+fn park() {
+    let tags = WAITING.with_borrow(|waiting| {
+        assert!(
+            !waiting.is_empty(),
+            "The future passed to `compat::block_on` waits on something else than a message"
+        );
+        // A receive of any message takes all of them.
+        if waiting.iter().any(|waiting| waiting.tags.is_empty()) {
+            Vec::new()
+        } else {
+            waiting
+                .iter()
+                .flat_map(|waiting| waiting.tags.iter().copied())
+                .collect()
+        }
+    });
+    let message_type = mailbox::wait(&tags, None);
+    let tag = unsafe { message::get_tag() };
+    let waiting = WAITING.with_borrow_mut(|mut waiting| {
+        let index = waiting
+            .iter()
+            .position(|waiting| waiting.tags.contains(&tag))
+            .or_else(|| waiting.iter().position(|waiting| waiting.tags.is_empty()))?;
+        Some(waiting.remove(index))
+    });
+    if let Some(waiting) = waiting {
+        (waiting.deliver)(message_type);
+    }
+}
+
+// Returns a waker that marks the future as woken.
+fn waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+
+    fn wake(_: *const ()) {
+        WOKEN.set(true);
+    }
+
+    fn drop(_: *const ()) {}
+
+    // The waker has no data, it only sets the flag of the current process.
+    unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) }
+}
+
+/// Returns a future that resolves to the next message of the mailbox.
+pub fn receive<M, S>(mailbox: Mailbox<M, S>) -> Receive<M, S>
+where
+    S: CanSerialize<M>,
+{
+    tag_receive(mailbox, &[])
+}
+
+/// Returns a future that resolves to the next message of the mailbox that is
+/// tagged with one of the `tags`, see [`Mailbox::tag_receive`].
+pub fn tag_receive<M, S>(mailbox: Mailbox<M, S>, tags: &[Tag]) -> Receive<M, S>
+where
+    S: CanSerialize<M>,
+{
+    Receive {
+        mailbox,
+        tags: tags.to_vec(),
+        slot: Rc::new(RefCell::new(Slot {
+            message: None,
+            waker: None,
+        })),
+        id: None,
+    }
+}
+
+/// A future that waits on a message, see [`receive`] and [`tag_receive`].
+///
+/// It can only be awaited inside of [`block_on`]. The message is taken out of
+/// the mailbox once it arrives, so if the future is dropped before it's
+/// polled again, e.g. by a `select`, the message is lost.
+///
+/// # Panics
+///
+/// Polling the future panics if the message can't be deserialized into `M`
+/// with serializer `S`.
+pub struct Receive<M, S>
+where
+    S: CanSerialize<M>,
+{
+    mailbox: Mailbox<M, S>,
+    tags: Vec<Tag>,
+    slot: Rc<RefCell<Slot<M>>>,
+    // Set once the receive waits on the executor.
+    id: Option<u64>,
+}
+
+struct Slot<M> {
+    message: Option<MailboxResult<M>>,
+    waker: Option<Waker>,
+}
+
+// None of the fields is pinned.
+impl<M, S> Unpin for Receive<M, S> where S: CanSerialize<M> {}
+
+impl<M, S> Future for Receive<M, S>
+where
+    M: 'static,
+    S: CanSerialize<M> + 'static,
+{
+    type Output = M;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<M> {
+        let this = self.get_mut();
+        let mut slot = this.slot.borrow_mut();
+        if let Some(message) = slot.message.take() {
+            this.id = None;
+            return Poll::Ready(message.unwrap());
+        }
+        slot.waker = Some(cx.waker().clone());
+        if this.id.is_some() {
+            return Poll::Pending;
+        }
+        drop(slot);
+
+        // The message could already be in the mailbox.
+        match this.mailbox.tag_receive_timeout(&this.tags, Duration::ZERO) {
+            MailboxResult::TimedOut => (),
+            message => return Poll::Ready(message.unwrap()),
+        }
+        let id = NEXT_ID.get();
+        NEXT_ID.set(id + 1);
+        let slot = this.slot.clone();
+        let deliver = move |message_type: u32| {
+            let mut slot = slot.borrow_mut();
+            slot.message = Some(mailbox::decode_received::<M, S>(message_type));
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        };
+        WAITING.with_borrow_mut(|mut waiting| {
+            waiting.push(Waiting {
+                id,
+                tags: this.tags.iter().map(Tag::id).collect(),
+                deliver: Box::new(deliver),
+            })
+        });
+        this.id = Some(id);
+        Poll::Pending
+    }
+}
+
+impl<M, S> Drop for Receive<M, S>
+where
+    S: CanSerialize<M>,
+{
+    fn drop(&mut self) {
+        // A receive that is dropped while waiting doesn't take a message.
+        if let Some(id) = self.id {
+            WAITING.with_borrow_mut(|mut waiting| waiting.retain(|waiting| waiting.id != id));
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{host, LunaticError};
+use std::time::Duration;
+
+use crate::{crash_dump, distributed, fuel, host, resource, LunaticError};
 
 /// Process configurations determine permissions of processes.
 ///
@@ -81,7 +83,9 @@ impl ProcessConfig {
     /// Sets the maximum amount of fuel available to the process.
     ///
     /// One unit of fuel is approximately 100k wasm instructions. If a process
-    /// runs out of fuel it will trap.
+    /// runs out of fuel it will trap. Use
+    /// [`set_max_cpu_time`](Self::set_max_cpu_time) to set the limit as a
+    /// duration instead.
     pub fn set_max_fuel(&mut self, max_fuel: u64) {
         unsafe { host::api::process::config_set_max_fuel(self.id() as u64, max_fuel) };
     }
@@ -91,6 +95,19 @@ impl ProcessConfig {
         unsafe { host::api::process::config_get_max_fuel(self.id() as u64) }
     }
 
+    /// Sets the maximum amount of fuel available to the process to about
+    /// `cpu_time`.
+    ///
+    /// The fuel is estimated with the rate measured by [`fuel::fuel_per_ms`],
+    /// see the [`fuel`] module for how accurate it is. The rate is passed to
+    /// the processes in an environment variable, so that
+    /// [`fuel::fuel_to_duration`] gives the same results in them.
+    pub fn set_max_cpu_time(&mut self, cpu_time: Duration) {
+        self.set_max_fuel(fuel::duration_to_fuel(cpu_time));
+        let rate = fuel::fuel_per_ms().to_string();
+        self.add_environment_variable(fuel::FUEL_PER_MS_ENV, &rate);
+    }
+
     /// Sets the ability of a process to compile WebAssembly modules.
     pub fn set_can_compile_modules(&mut self, can: bool) {
         unsafe { host::api::process::config_set_can_compile_modules(self.id() as u64, can as u32) };
@@ -138,6 +155,33 @@ impl ProcessConfig {
         }
     }
 
+    /// Enables metrics on the sends to other nodes in processes spawned with
+    /// this config, see [`distributed::instrument_sends`].
+    ///
+    /// The setting is passed to the processes in an environment variable.
+    pub fn instrument_remote_sends(&mut self, enabled: bool) {
+        let value = if enabled { "1" } else { "0" };
+        self.add_environment_variable(distributed::INSTRUMENT_SENDS_ENV, value);
+    }
+
+    /// Warns processes spawned with this config once they own more than
+    /// `threshold` host resources, see [`resource::set_warning_threshold`].
+    ///
+    /// The setting is passed to the processes in an environment variable.
+    pub fn resource_warning_threshold(&mut self, threshold: usize) {
+        let value = threshold.to_string();
+        self.add_environment_variable(resource::WARNING_THRESHOLD_ENV, &value);
+    }
+
+    /// Records the last `messages` received messages in processes spawned with
+    /// this config, see [`crash_dump::record_messages`].
+    ///
+    /// The setting is passed to the processes in an environment variable.
+    pub fn crash_dump_messages(&mut self, messages: usize) {
+        let value = messages.to_string();
+        self.add_environment_variable(crash_dump::MESSAGES_ENV, &value);
+    }
+
     /// Adds command line argument.
     pub fn add_command_line_argument(&mut self, argument: &str) {
         unsafe {
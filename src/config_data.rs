@@ -0,0 +1,351 @@
+//! Application configuration that is parsed once and shared with all
+//! processes.
+//!
+//! Each process has its own memory, so without help every process reads and
+//! parses the configuration again, e.g. from environment variables or a file.
+//! An [`AppConfig`] is set once, usually in `main`, and processes spawned
+//! afterwards receive the serialized value from their parent. They decode it
+//! on first use, without reading any files. This includes processes spawned on
+//! other nodes and the processes they spawn.
+//!
+//! [`AppConfig::update`] replaces the value in every process that
+//! [subscribed](AppConfig::subscribe) to updates. Each value has a
+//! [`Revision`], processes that see the same revision see the same value.
+//!
+//! # Example
+//!
+//! ```
+//! #[derive(Serialize, Deserialize)]
+//! struct Settings {
+//!     database_url: String,
+//! }
+//!
+//! #[lunatic::main]
+//! fn main(_: Mailbox<()>) {
+//!     AppConfig::init(Settings {
+//!         database_url: std::env::var("DATABASE_URL").unwrap(),
+//!     });
+//!     Process::spawn((), |_, _: Mailbox<()>| {
+//!         let settings = AppConfig::<Settings>::get().unwrap();
+//!         connect(&settings.database_url);
+//!     });
+//! }
+//! ```
+
+use std::any::{type_name, Any};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::host::api::message;
+use crate::mailbox::TIMEOUT;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, process_local, Mailbox, Process, Tag};
+
+/// Tag of the message carrying the configs to a newly spawned process.
+const INHERIT_TAG: i64 = 18;
+/// Tag of the updates sent to subscribed processes.
+pub(crate) const UPDATE_TAG: i64 = 19;
+
+/// Identifies a value of an [`AppConfig`].
+///
+/// The source is the process that distributes the updates of the config, the
+/// number counts the updates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Revision {
+    pub source: u64,
+    pub number: u64,
+}
+
+/// A config of type `T`, shared with the processes spawned from the one that
+/// set it, see the [module level documentation](self).
+///
+/// The type is only used to look up the config, each process holds at most
+/// one value per type.
+pub struct AppConfig<T> {
+    phantom: PhantomData<T>,
+}
+
+impl<T> AppConfig<T>
+where
+    T: Serialize + DeserializeOwned + 'static,
+{
+    /// Sets the config of the current process, and of the processes spawned
+    /// from it afterwards.
+    ///
+    /// Spawns the process that distributes the updates of the config. The
+    /// current process is subscribed to updates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` can't be serialized.
+    pub fn init(value: T) -> Rc<T> {
+        let data = bincode::serialize(&value).expect("Failed to serialize the config");
+        let source = Process::spawn((), source_process);
+        let shared = Shared {
+            source,
+            revision: Revision {
+                source: source.id(),
+                number: 0,
+            },
+            data,
+        };
+        source.send(SourceMessage::Init(shared.data.clone()));
+        let value = Rc::new(value);
+        CONFIGS.with_borrow_mut(|mut configs| {
+            configs.insert(
+                type_name::<T>().to_owned(),
+                Entry {
+                    shared,
+                    decoded: Some(value.clone()),
+                },
+            )
+        });
+        Self::subscribe();
+        value
+    }
+
+    /// Returns the config of the current process, or sets it to the value
+    /// returned by `parse` if it has none.
+    ///
+    /// Processes that inherited the config don't call `parse`, so it's only
+    /// called once in the tree of processes.
+    pub fn get_or_init(parse: impl FnOnce() -> T) -> Rc<T> {
+        match Self::get() {
+            Some(value) => value,
+            None => Self::init(parse()),
+        }
+    }
+
+    /// Returns the config of the current process, or `None` if it wasn't set
+    /// or inherited.
+    ///
+    /// The value is decoded on first use. Updates that arrived since the last
+    /// call are applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the inherited value can't be deserialized into `T`.
+    pub fn get() -> Option<Rc<T>> {
+        store_updates();
+        CONFIGS.with_borrow_mut(|mut configs| {
+            let entry = configs.get_mut(type_name::<T>())?;
+            if entry.decoded.is_none() {
+                let value: T = bincode::deserialize(&entry.shared.data)
+                    .expect("Failed to deserialize the config");
+                entry.decoded = Some(Rc::new(value));
+            }
+            entry.decoded.clone()?.downcast().ok()
+        })
+    }
+
+    /// Returns the revision of the config of the current process.
+    pub fn revision() -> Option<Revision> {
+        store_updates();
+        CONFIGS.with_borrow(|configs| {
+            configs
+                .get(type_name::<T>())
+                .map(|entry| entry.shared.revision)
+        })
+    }
+
+    /// Subscribes the current process to updates of the config.
+    ///
+    /// If the config was updated since the current process got it, the latest
+    /// value is sent right away. Does nothing if the process has no config.
+    pub fn subscribe() {
+        let Some((source, revision)) = CONFIGS.with_borrow(|configs| {
+            configs
+                .get(type_name::<T>())
+                .map(|entry| (entry.shared.source, entry.shared.revision))
+        }) else {
+            return;
+        };
+        source.send(SourceMessage::Subscribe(
+            Process::this(),
+            type_name::<T>().to_owned(),
+            revision.number,
+        ));
+    }
+
+    /// Replaces the config in all subscribed processes, including the current
+    /// one, and returns the new revision.
+    ///
+    /// Waits until the update is distributed. Processes spawned afterwards
+    /// inherit the new value from their parent, once it received the update.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current process has no config of type `T`, or if `value`
+    /// can't be serialized.
+    pub fn update(value: T) -> Revision {
+        let source = CONFIGS
+            .with_borrow(|configs| {
+                configs
+                    .get(type_name::<T>())
+                    .map(|entry| entry.shared.source)
+            })
+            .unwrap_or_else(|| panic!("No `AppConfig<{}>` to update", type_name::<T>()));
+        let data = bincode::serialize(&value).expect("Failed to serialize the config");
+        let tag = Tag::new();
+        source.send(SourceMessage::Update(
+            Process::this(),
+            tag,
+            type_name::<T>().to_owned(),
+            data.clone(),
+        ));
+        let revision = unsafe { Mailbox::<Revision>::new() }.tag_receive(&[tag]);
+        CONFIGS.with_borrow_mut(|mut configs| {
+            let update = Shared {
+                source,
+                revision,
+                data,
+            };
+            let decoded: Rc<dyn Any> = Rc::new(value);
+            apply(&mut configs, type_name::<T>(), update, Some(decoded));
+        });
+        revision
+    }
+}
+
+/// The serialized value of a config, as it's sent to other processes.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Shared {
+    source: Process<SourceMessage>,
+    revision: Revision,
+    data: Vec<u8>,
+}
+
+struct Entry {
+    shared: Shared,
+    // The decoded data, once it's used.
+    decoded: Option<Rc<dyn Any>>,
+}
+
+process_local! {
+    // Configs of the current process, by type name.
+    static CONFIGS: RefCell<HashMap<String, Entry>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the configs that processes spawned from the current one should
+/// inherit.
+pub(crate) fn inherited() -> Option<Vec<(String, Shared)>> {
+    CONFIGS.with_borrow(|configs| {
+        if configs.is_empty() {
+            return None;
+        }
+        let configs = configs
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.shared.clone()))
+            .collect();
+        Some(configs)
+    })
+}
+
+// Sends the configs to a newly spawned process.
+pub(crate) fn send_inherited(node_id: u64, process_id: u64, configs: Vec<(String, Shared)>) {
+    let child = Process::<Vec<(String, Shared)>>::new(node_id, process_id);
+    child.tag_send(Tag::from(INHERIT_TAG), configs);
+}
+
+// Called at the start of a process that inherits the configs of its parent.
+pub(crate) fn adopt_inherited() {
+    let configs =
+        unsafe { Mailbox::<Vec<(String, Shared)>>::new() }.tag_receive(&[Tag::from(INHERIT_TAG)]);
+    CONFIGS.with_borrow_mut(|mut entries| {
+        for (name, shared) in configs {
+            entries.insert(
+                name,
+                Entry {
+                    shared,
+                    decoded: None,
+                },
+            );
+        }
+    });
+}
+
+/// Stores the update that is currently in the message buffer.
+pub(crate) fn store_update() {
+    if let Ok((name, update)) = <Bincode as CanSerialize<(String, Shared)>>::decode() {
+        CONFIGS.with_borrow_mut(|mut configs| apply(&mut configs, &name, update, None));
+    }
+}
+
+// Collects the updates that are still waiting in the mailbox.
+fn store_updates() {
+    let tags = [UPDATE_TAG];
+    while unsafe { message::receive(tags.as_ptr(), tags.len(), 0) } != TIMEOUT {
+        store_update();
+    }
+}
+
+// Replaces the config `name` if `update` is newer.
+fn apply(
+    configs: &mut HashMap<String, Entry>,
+    name: &str,
+    update: Shared,
+    decoded: Option<Rc<dyn Any>>,
+) {
+    // Updates of configs that the process doesn't have are ignored.
+    let Some(entry) = configs.get_mut(name) else {
+        return;
+    };
+    if update.revision.number > entry.shared.revision.number {
+        *entry = Entry {
+            shared: update,
+            decoded,
+        };
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum SourceMessage {
+    Init(Vec<u8>),
+    Subscribe(Process<()>, String, u64),
+    Update(Process<Revision>, Tag, String, Vec<u8>),
+}
+
+// Distributes the updates of one config.
+fn source_process(_: (), mailbox: Mailbox<SourceMessage>) {
+    let this = Process::<SourceMessage>::this();
+    let mut current = Shared {
+        source: this,
+        revision: Revision {
+            source: this.id(),
+            number: 0,
+        },
+        data: Vec::new(),
+    };
+    let mut subscribers: Vec<Process<(String, Shared)>> = Vec::new();
+    loop {
+        match mailbox.receive() {
+            SourceMessage::Init(data) => current.data = data,
+            SourceMessage::Subscribe(process, name, number) => {
+                let process = Process::new(process.node_id(), process.id());
+                if number < current.revision.number {
+                    process.tag_send(Tag::from(UPDATE_TAG), (name, current.clone()));
+                }
+                subscribers.push(process);
+            }
+            SourceMessage::Update(process, tag, name, data) => {
+                current.revision.number += 1;
+                current.data = data;
+                // Subscribers on this node that are gone don't get updates anymore.
+                subscribers.retain(|sub| sub.node_id() != host::node_id() || sub.is_alive());
+                for subscriber in subscribers.iter() {
+                    if subscriber.id() != process.id() || subscriber.node_id() != process.node_id()
+                    {
+                        subscriber.tag_send(Tag::from(UPDATE_TAG), (name.clone(), current.clone()));
+                    }
+                }
+                process.tag_send(tag, current.revision);
+            }
+        }
+    }
+}
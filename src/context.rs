@@ -0,0 +1,129 @@
+//! Deadlines of the requests that are currently handled.
+//!
+//! A request to an [`AbstractProcess`](crate::ap::AbstractProcess) carries a
+//! deadline if it was sent with a timeout, e.g. with
+//! [`ProcessRef::with_timeout`](crate::ap::ProcessRef::with_timeout), or from
+//! a handler that has a deadline itself. While the handler runs, [`deadline`]
+//! and [`remaining`] return how much time the caller is still waiting, and
+//! [`check`] lets it give up early once nobody waits for the response anymore.
+//!
+//! Requests sent from a handler inherit its deadline, so that the budget of
+//! the first caller is passed down the whole chain. Their timeouts are capped
+//! to the remaining time, a request sent after the deadline times out right
+//! away. Requests without a timeout still carry the deadline, but wait on the
+//! response as long as it takes.
+//!
+//! Deadlines are sent as wall-clock time, so they are exact between processes
+//! of the same node, but shift with the clock difference between nodes.
+//!
+//! # Example
+//!
+//! ```
+//! impl RequestHandler<Report> for Reports {
+//!     type Response = Result<Vec<u8>, DeadlineExceeded>;
+//!
+//!     fn handle(state: State<Self>, report: Report) -> Self::Response {
+//!         let mut pages = Vec::new();
+//!         for page in report.pages() {
+//!             context::check()?;
+//!             pages.extend(state.render(page));
+//!         }
+//!         Ok(pages)
+//!     }
+//! }
+//! ```
+
+use std::cell::Cell;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::process_local;
+
+process_local! {
+    // Milliseconds since the Unix epoch.
+    static DEADLINE: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Error returned from [`check`] after the deadline passed.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the deadline of the request passed")]
+pub struct DeadlineExceeded;
+
+/// Returns the deadline of the current process, if it has one.
+pub fn deadline() -> Option<Instant> {
+    remaining().map(|remaining| Instant::now() + remaining)
+}
+
+/// Returns the time until the deadline of the current process, or zero if it
+/// passed.
+pub fn remaining() -> Option<Duration> {
+    let deadline = DEADLINE.get()?;
+    Some(Duration::from_millis(deadline.saturating_sub(now())))
+}
+
+/// Returns `true` if the deadline of the current process passed.
+pub fn is_expired() -> bool {
+    remaining().is_some_and(|remaining| remaining.is_zero())
+}
+
+/// Fails once the deadline of the current process passed.
+pub fn check() -> Result<(), DeadlineExceeded> {
+    if is_expired() {
+        Err(DeadlineExceeded)
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs `f` with a deadline `timeout` from now, or the deadline of the current
+/// process if it's earlier.
+///
+/// Requests sent by `f` carry the deadline, like the requests sent from a
+/// handler.
+pub fn with_timeout<R>(timeout: Duration, f: impl FnOnce() -> R) -> R {
+    let deadline = now().saturating_add(millis(timeout));
+    enter(earliest(DEADLINE.get(), Some(deadline)), f)
+}
+
+// Runs `f` with the `deadline` of an incoming request, replacing the current
+// one.
+pub(crate) fn enter<R>(deadline: Option<u64>, f: impl FnOnce() -> R) -> R {
+    // Restores the previous deadline, also if `f` panics.
+    struct Restore(Option<u64>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            DEADLINE.set(self.0);
+        }
+    }
+    let _restore = Restore(DEADLINE.get());
+    DEADLINE.set(deadline);
+    f()
+}
+
+// Returns the timeout and the deadline of an outgoing request with `timeout`.
+pub(crate) fn outgoing(timeout: Option<Duration>) -> (Option<Duration>, Option<u64>) {
+    let timeout = match (timeout, remaining()) {
+        (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+        (timeout, _) => timeout,
+    };
+    let deadline = timeout.map(|timeout| now().saturating_add(millis(timeout)));
+    (timeout, earliest(DEADLINE.get(), deadline))
+}
+
+fn earliest(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
@@ -0,0 +1,163 @@
+//! Recording of the last messages received by a process, for post-mortem
+//! debugging.
+//!
+//! A process that [records messages](record_messages) keeps the type name,
+//! tag and the first [`PAYLOAD_LIMIT`] bytes of the payload of each message
+//! it receives, up to the configured number of messages. When it panics, the
+//! recorded messages are attached as a [`CrashDump`] to the [`Panicked`]
+//! information that is sent to the hook of [`panic::set_hook`] and to linked
+//! processes, see [`panic::link_panic`]. For an
+//! [`AbstractProcess`](crate::AbstractProcess), the dump also includes the
+//! handler that was running.
+//!
+//! Recording is off by default. It can be enabled for processes spawned with
+//! a config, see
+//! [`ProcessConfig::crash_dump_messages`](crate::ProcessConfig::crash_dump_messages).
+//!
+//! Processes that are killed or trap without panicking don't produce a dump.
+//!
+//! # Example
+//!
+//! ```
+//! let worker = Process::spawn_link((), |_, mailbox: Mailbox<Job>| {
+//!     crash_dump::record_messages(16);
+//!     loop {
+//!         mailbox.receive().run();
+//!     }
+//! });
+//! ```
+//!
+//! [`Panicked`]: crate::panic::Panicked
+//! [`panic::set_hook`]: crate::panic::set_hook
+//! [`panic::link_panic`]: crate::panic::link_panic
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::api::message;
+use crate::{debug, process_local};
+
+/// Environment variable that sets the number of recorded messages of
+/// processes spawned with a config, see
+/// [`ProcessConfig::crash_dump_messages`](crate::ProcessConfig::crash_dump_messages).
+pub const MESSAGES_ENV: &str = "LUNATIC_CRASH_DUMP_MESSAGES";
+/// Number of payload bytes that are recorded per message.
+pub const PAYLOAD_LIMIT: usize = 64;
+
+process_local! {
+    // Unset until the environment is checked.
+    static CAPACITY: Cell<Option<usize>> = Cell::new(None);
+    static MESSAGES: RefCell<VecDeque<RecordedMessage>> = RefCell::new(VecDeque::new());
+}
+
+/// A message received by a process, see [`CrashDump`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    /// The type of the message, or of the handler's message for an
+    /// [`AbstractProcess`](crate::AbstractProcess).
+    pub type_name: String,
+    pub tag: i64,
+    /// The first [`PAYLOAD_LIMIT`] bytes of the serialized message.
+    pub payload: Vec<u8>,
+    /// The size of the whole serialized message.
+    pub size: usize,
+}
+
+/// The last messages received by a process before it panicked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashDump {
+    /// The handler of the abstract process that was running, if any.
+    pub handler: Option<String>,
+    /// The recorded messages, oldest first. The last one is usually the
+    /// message that was handled during the panic.
+    pub messages: Vec<RecordedMessage>,
+}
+
+impl Display for CrashDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "last {} messages", self.messages.len())?;
+        if let Some(handler) = &self.handler {
+            write!(f, " (in handler {handler})")?;
+        }
+        for message in self.messages.iter() {
+            write!(
+                f,
+                "\n  {} tag={} size={}",
+                message.type_name, message.tag, message.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the last `capacity` messages received by the current process, or
+/// stops recording if it's 0.
+///
+/// Messages that were already recorded are kept, up to the new capacity.
+pub fn record_messages(capacity: usize) {
+    CAPACITY.set(Some(capacity));
+    MESSAGES.with_borrow_mut(|mut messages| {
+        while messages.len() > capacity {
+            messages.pop_front();
+        }
+        messages.shrink_to(capacity);
+    });
+}
+
+/// Returns the number of messages the current process records.
+pub fn recorded_messages() -> usize {
+    match CAPACITY.get() {
+        Some(capacity) => capacity,
+        None => {
+            let capacity = std::env::var(MESSAGES_ENV)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0);
+            CAPACITY.set(Some(capacity));
+            capacity
+        }
+    }
+}
+
+/// Returns the dump of the current process, or `None` if it doesn't record
+/// messages.
+pub fn crash_dump() -> Option<CrashDump> {
+    if recorded_messages() == 0 {
+        return None;
+    }
+    Some(CrashDump {
+        handler: debug::running_handler(),
+        messages: MESSAGES.with_borrow(|messages| messages.iter().cloned().collect()),
+    })
+}
+
+// Records the message in the message buffer as `type_name`, leaving the
+// buffer at the start of the data.
+pub(crate) fn record(type_name: &str) {
+    let capacity = recorded_messages();
+    if capacity == 0 {
+        return;
+    }
+    let size = unsafe { message::data_size() } as usize;
+    let mut payload = vec![0; size.min(PAYLOAD_LIMIT)];
+    unsafe {
+        message::seek_data(0);
+        message::read_data(payload.as_mut_ptr(), payload.len());
+        message::seek_data(0);
+    }
+    let recorded = RecordedMessage {
+        type_name: type_name.to_owned(),
+        tag: unsafe { message::get_tag() },
+        payload,
+        size,
+    };
+    MESSAGES.with_borrow_mut(|mut messages| {
+        if messages.len() == capacity {
+            messages.pop_front();
+        }
+        messages.push_back(recorded);
+    });
+}
@@ -0,0 +1,97 @@
+//! Handling of messages that can't be delivered.
+//!
+//! Messages sent to a process that already died are dropped by the runtime.
+//! This can hide bugs, like holding on to a stale handle after a supervisor
+//! restarted the process. Every dropped local send increments the
+//! `lunatic.process.dead_letters` counter. If a dead letter handler is set with
+//! [`set_handler`], dropped messages are also forwarded to it as a
+//! [`DeadLetter`].
+//!
+//! This covers messages sent with [`Process::send`] & [`Process::tag_send`],
+//! and messages sent to an [`AbstractProcess`](crate::AbstractProcess). Requests
+//! are not covered, they can use a timeout instead.
+//!
+//! # Example
+//!
+//! ```
+//! let handler = Process::spawn((), |_, mailbox: Mailbox<DeadLetter>| loop {
+//!     let dead_letter = mailbox.receive();
+//!     println!("Message to {} was dropped", dead_letter.target_id);
+//! });
+//! lunatic::dead_letter::set_handler(handler);
+//! ```
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::api::{message, process};
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{metrics, process_local, Process, Tag};
+
+/// Name of the counter that is incremented every time a message is dropped.
+pub const DEAD_LETTERS_COUNTER: &str = "lunatic.process.dead_letters";
+
+/// A message that couldn't be delivered, because the receiving process doesn't
+/// exist anymore.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// Id of the process that the message was sent to.
+    pub target_id: u64,
+    /// Tag of the message.
+    pub tag: Tag,
+    /// Serialized message.
+    pub payload: Vec<u8>,
+}
+
+process_local! {
+    static HANDLER: Cell<Option<Process<DeadLetter>>> = Cell::new(None);
+}
+
+/// Forwards local messages sent by the current process that can't be delivered
+/// to `handler`.
+///
+/// The handler is only set for the current process.
+pub fn set_handler(handler: Process<DeadLetter>) {
+    HANDLER.set(Some(handler));
+}
+
+/// Removes the dead letter handler of the current process and returns it.
+pub fn take_handler() -> Option<Process<DeadLetter>> {
+    HANDLER.take()
+}
+
+/// Sends the message in the message buffer to a local process.
+pub(crate) fn send(process_id: u64) {
+    match HANDLER.get() {
+        Some(handler) if unsafe { process::exists(process_id) } == 0 => {
+            metrics::increment_counter(DEAD_LETTERS_COUNTER);
+            forward(handler, process_id);
+        }
+        _ => {
+            if unsafe { message::send(process_id) } != 0 {
+                metrics::increment_counter(DEAD_LETTERS_COUNTER);
+            }
+        }
+    }
+}
+
+/// Forwards the message in the message buffer to the handler.
+fn forward(handler: Process<DeadLetter>, target_id: u64) {
+    let tag = Tag::from(unsafe { message::get_tag() });
+    let mut payload = vec![0; unsafe { message::data_size() } as usize];
+    unsafe {
+        message::seek_data(0);
+        message::read_data(payload.as_mut_ptr(), payload.len());
+    }
+    let dead_letter = DeadLetter {
+        target_id,
+        tag,
+        payload,
+    };
+    unsafe { message::create_data(Tag::none().id(), 0) };
+    if Bincode::encode(&dead_letter).is_ok() {
+        // Use the host call directly, so that a dead handler doesn't forward to itself.
+        unsafe { message::send(handler.id()) };
+    }
+}
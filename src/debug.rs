@@ -0,0 +1,171 @@
+//! Debug information that processes attach to themselves.
+//!
+//! A process can describe itself with a label and a few key/value pairs, e.g.
+//! the peer of a connection and the state of a protocol. Other processes can
+//! query the information with [`Process::debug_info`], to show what each
+//! process is doing. An [`AbstractProcess`](crate::ap::AbstractProcess) is
+//! labeled with its type and reports the handler that is currently running.
+//!
+//! The information is kept in the process and bounded by [`MAX_ENTRIES`],
+//! [`MAX_KEY_LEN`] and [`MAX_VALUE_LEN`], longer labels, keys and values are
+//! truncated. Queried processes answer the next time they wait on a message
+//! that isn't filtered by tags, so a busy process answers late or not at all.
+//!
+//! # Example
+//!
+//! ```
+//! debug::set_label(format!("tcp-conn {peer}"));
+//! debug::set_debug_kv("state", "awaiting_auth");
+//!
+//! // In an admin process.
+//! for process in processes {
+//!     if let Some(info) = process.debug_info(Duration::from_millis(100)) {
+//!         println!("{info}");
+//!     }
+//! }
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, process_local, Process, Tag};
+
+/// Tag of the message requesting the debug information of a process.
+pub(crate) const DEBUG_INFO_TAG: i64 = 15;
+
+/// Maximum number of key/value pairs a process can set.
+pub const MAX_ENTRIES: usize = 32;
+/// Maximum length of keys in bytes.
+pub const MAX_KEY_LEN: usize = 64;
+/// Maximum length of labels and values in bytes.
+pub const MAX_VALUE_LEN: usize = 256;
+
+process_local! {
+    static LABEL: RefCell<Option<String>> = RefCell::new(None);
+    static HANDLER: Cell<Option<&'static str>> = Cell::new(None);
+    static ENTRIES: RefCell<BTreeMap<String, String>> = RefCell::new(BTreeMap::new());
+}
+
+/// The debug information of a process, see [`Process::debug_info`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugInfo {
+    pub node_id: u64,
+    pub process_id: u64,
+    pub label: Option<String>,
+    /// The handler of the abstract process that is currently running.
+    pub handler: Option<String>,
+    pub entries: BTreeMap<String, String>,
+}
+
+impl Display for DebugInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.node_id, self.process_id)?;
+        if let Some(label) = &self.label {
+            write!(f, " {label}")?;
+        }
+        if let Some(handler) = &self.handler {
+            write!(f, " [{handler}]")?;
+        }
+        for (key, value) in self.entries.iter() {
+            write!(f, " {key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets the label of the current process.
+pub fn set_label(label: impl Into<String>) {
+    LABEL.set(Some(truncate(label.into(), MAX_VALUE_LEN)));
+}
+
+/// Returns the label of the current process.
+pub fn label() -> Option<String> {
+    LABEL.with_borrow(|label| label.clone())
+}
+
+/// Sets the debug value of `key` in the current process.
+///
+/// Returns `false` if the key was new and the process already has
+/// [`MAX_ENTRIES`] pairs, in which case it isn't set.
+pub fn set_debug_kv(key: impl Into<String>, value: impl Into<String>) -> bool {
+    let key = truncate(key.into(), MAX_KEY_LEN);
+    let value = truncate(value.into(), MAX_VALUE_LEN);
+    ENTRIES.with_borrow_mut(|mut entries| {
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&key) {
+            return false;
+        }
+        entries.insert(key, value);
+        true
+    })
+}
+
+/// Removes the debug value of `key` from the current process.
+pub fn remove_debug_kv(key: &str) {
+    ENTRIES.with_borrow_mut(|mut entries| entries.remove(key));
+}
+
+/// Returns the debug information of the current process.
+pub fn debug_info() -> DebugInfo {
+    DebugInfo {
+        node_id: host::node_id(),
+        process_id: host::process_id(),
+        label: label(),
+        handler: running_handler(),
+        entries: ENTRIES.with_borrow(|entries| entries.clone()),
+    }
+}
+
+// Labels the current process with the abstract process type, if it isn't
+// labeled yet.
+pub(crate) fn label_abstract_process(type_name: &'static str) {
+    if label().is_none() {
+        set_label(type_name);
+    }
+}
+
+// Sets the handler whose type name is `handler` as running, or none.
+pub(crate) fn set_handler(handler: Option<&'static str>) {
+    HANDLER.set(handler);
+}
+
+// Returns the label of the handler that is running.
+pub(crate) fn running_handler() -> Option<String> {
+    HANDLER.get().map(handler_label)
+}
+
+// Asks the process for its debug information, replying to `reply_to`.
+pub(crate) fn send_request(node_id: u64, process_id: u64, reply_to: Process<DebugInfo>, tag: Tag) {
+    let process = Process::<(Process<DebugInfo>, Tag)>::new(node_id, process_id);
+    process.tag_send(Tag::from(DEBUG_INFO_TAG), (reply_to, tag));
+}
+
+// Answers the request in the message buffer.
+pub(crate) fn handle_request() {
+    if let Ok((reply_to, tag)) = <Bincode as CanSerialize<(Process<DebugInfo>, Tag)>>::decode() {
+        reply_to.tag_send(tag, debug_info());
+    }
+}
+
+// Turns the type name of a handler's message into a readable name, e.g.
+// `app::__MsgWrapIncrement` into `Increment`.
+fn handler_label(type_name: &'static str) -> String {
+    let path = type_name.split('<').next().unwrap_or(type_name);
+    let name = path.rsplit("::").next().unwrap_or(path);
+    let name = name.strip_prefix("__MsgWrap").unwrap_or(name);
+    name.to_owned()
+}
+
+fn truncate(mut value: String, max_len: usize) -> String {
+    if value.len() > max_len {
+        let mut end = max_len;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+    value
+}
@@ -0,0 +1,229 @@
+//! Dropping duplicates of messages from at-least-once sources.
+//!
+//! Sources that retry sends, e.g. after a node reconnected, can deliver the
+//! same message more than once. Messages that carry an id implement
+//! [`HasMessageId`], and a [`Dedup`] mailbox drops the ones whose id was
+//! received within the [`Window`]. Abstract processes keep a [`SeenIds`] in
+//! their state and check the messages in the handlers. Dropped duplicates are
+//! counted in [`DROPPED_COUNTER`].
+//!
+//! The ids are only remembered within the window, so a duplicate that arrives
+//! after its id was forgotten is delivered again. The number of remembered ids
+//! is always bounded, also for time windows.
+//!
+//! # Example
+//!
+//! ```
+//! impl HasMessageId for Event {
+//!     type Id = u64;
+//!
+//!     fn message_id(&self) -> u64 {
+//!         self.sequence
+//!     }
+//! }
+//!
+//! let mut mailbox = Dedup::new(mailbox, Window::count(10_000));
+//! loop {
+//!     // Each event is only returned once.
+//!     ingest(mailbox.receive());
+//! }
+//! ```
+
+use std::any::type_name;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{metrics, time, Mailbox, MailboxResult};
+
+/// Counter of dropped duplicates, labeled by the message type.
+pub const DROPPED_COUNTER: &str = "lunatic.dedup.dropped";
+
+/// A message that carries an id, which is the same for all copies of the
+/// message.
+pub trait HasMessageId {
+    type Id: Eq + Hash + Clone;
+
+    fn message_id(&self) -> Self::Id;
+}
+
+/// How long ids are remembered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    max_ids: usize,
+    max_age: Option<Duration>,
+}
+
+impl Window {
+    /// Remembers the last `max_ids` ids.
+    pub fn count(max_ids: usize) -> Self {
+        Window {
+            max_ids: max_ids.max(1),
+            max_age: None,
+        }
+    }
+
+    /// Remembers the ids received within `max_age`, but not more than the
+    /// last `max_ids` of them.
+    pub fn time(max_age: Duration, max_ids: usize) -> Self {
+        Window {
+            max_ids: max_ids.max(1),
+            max_age: Some(max_age),
+        }
+    }
+
+    /// Returns the largest number of ids that are remembered.
+    pub fn max_ids(&self) -> usize {
+        self.max_ids
+    }
+}
+
+/// The ids seen within a [`Window`].
+pub struct SeenIds<Id> {
+    window: Window,
+    ids: HashSet<Id>,
+    // The ids in the order they were seen.
+    order: VecDeque<(Id, Instant)>,
+    dropped: u64,
+    // Counter that is incremented for each duplicate.
+    counter: Option<String>,
+}
+
+impl<Id> SeenIds<Id>
+where
+    Id: Eq + Hash + Clone,
+{
+    pub fn new(window: Window) -> Self {
+        SeenIds {
+            window,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            dropped: 0,
+            counter: None,
+        }
+    }
+
+    /// Increments [`DROPPED_COUNTER`] for each duplicate, labeled with
+    /// `message`.
+    pub fn with_metric(mut self, message: &str) -> Self {
+        self.counter = Some(metrics::with_labels(
+            DROPPED_COUNTER,
+            &[("message", message)],
+        ));
+        self
+    }
+
+    /// Remembers `id` and returns `true`, or returns `false` if it was seen
+    /// within the window.
+    pub fn insert(&mut self, id: Id) -> bool {
+        let now = time::instant();
+        self.expire(now);
+        if self.ids.contains(&id) {
+            self.dropped += 1;
+            if let Some(counter) = &self.counter {
+                metrics::increment_counter(counter);
+            }
+            return false;
+        }
+        if self.order.len() == self.window.max_ids {
+            if let Some((oldest, _)) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.clone());
+        self.order.push_back((id, now));
+        true
+    }
+
+    /// Returns the number of remembered ids.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns `true` if no ids are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns the number of duplicates seen.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Forgets the ids that are older than the window.
+    fn expire(&mut self, now: Instant) {
+        let Some(max_age) = self.window.max_age else {
+            return;
+        };
+        while let Some((id, seen)) = self.order.front() {
+            if now.saturating_duration_since(*seen) < max_age {
+                break;
+            }
+            self.ids.remove(id);
+            self.order.pop_front();
+        }
+    }
+}
+
+/// A mailbox that drops messages whose id was received within a [`Window`].
+///
+/// Each dropped duplicate increments [`DROPPED_COUNTER`], labeled with the
+/// type of the message.
+pub struct Dedup<M, S = Bincode>
+where
+    M: HasMessageId,
+    S: CanSerialize<M>,
+{
+    mailbox: Mailbox<M, S>,
+    seen: SeenIds<M::Id>,
+}
+
+impl<M, S> Dedup<M, S>
+where
+    M: HasMessageId,
+    S: CanSerialize<M>,
+{
+    pub fn new(mailbox: Mailbox<M, S>, window: Window) -> Self {
+        Dedup {
+            mailbox,
+            seen: SeenIds::new(window).with_metric(type_name::<M>()),
+        }
+    }
+
+    /// Returns the next message that isn't a duplicate, see
+    /// [`Mailbox::receive`].
+    pub fn receive(&mut self) -> M {
+        loop {
+            let message = self.mailbox.receive();
+            if self.seen.insert(message.message_id()) {
+                return message;
+            }
+        }
+    }
+
+    /// Returns the next message that isn't a duplicate, or times out after
+    /// `timeout`.
+    pub fn receive_timeout(&mut self, timeout: Duration) -> MailboxResult<M> {
+        let deadline = time::instant() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(time::instant());
+            match self.mailbox.receive_timeout(remaining) {
+                MailboxResult::Message(message) if !self.seen.insert(message.message_id()) => {
+                    continue
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns the ids seen by this mailbox.
+    pub fn seen(&self) -> &SeenIds<M::Id> {
+        &self.seen
+    }
+
+    /// Returns the wrapped mailbox.
+    pub fn into_inner(self) -> Mailbox<M, S> {
+        self.mailbox
+    }
+}
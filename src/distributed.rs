@@ -1,9 +1,60 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
 use crate::host::api::distributed::{
-    copy_lookup_nodes_results, exec_lookup_nodes, get_nodes, module_id, nodes_count,
+    copy_lookup_nodes_results, exec_lookup_nodes, get_nodes, nodes_count,
 };
 use crate::host::api::{self};
 use crate::module::{params_to_vec, Param};
-use crate::LunaticError;
+use crate::{
+    host, metrics, process_local, registry, LunaticError, Mailbox, MailboxResult, Process, Tag,
+};
+
+/// Name under which the node agent is registered on each node.
+const AGENT_NAME: &str = "lunatic::distributed::agent";
+/// How often each node sends its attributes to the other nodes.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a node agent waits for another node to answer before trying to
+/// reach it again.
+const INTRODUCTION_RETRY: Duration = Duration::from_secs(10);
+/// How often [`drain`] checks if the tracked processes are still alive.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// Attribute set by [`drain`].
+pub const DRAINING: &str = "draining";
+/// Registry name that is set while the current node is a [partial mesh](partial_mesh).
+const PARTIAL_MESH_NAME: &str = "lunatic::distributed::partial_mesh";
+/// Prefix of the registry names of the connections of the current node.
+const CONNECTION_PREFIX: &str = "lunatic::distributed::connection::";
+/// How long [`connect`] waits for the other node to accept the connection.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a connection stays established without a heartbeat from the
+/// other node.
+pub const CONNECTION_LOST_AFTER: Duration = Duration::from_secs(3);
+
+/// Counter of messages sent to processes on other nodes, labeled by node.
+pub const SENT_MESSAGES_COUNTER: &str = "lunatic.distributed.sent_messages";
+/// Counter of bytes sent to processes on other nodes, labeled by node.
+pub const SENT_BYTES_COUNTER: &str = "lunatic.distributed.sent_bytes";
+/// Histogram of the seconds until the host accepted a message for another
+/// node, labeled by node.
+pub const SEND_LATENCY_HISTOGRAM: &str = "lunatic.distributed.send_latency";
+/// Environment variable that enables [`instrument_sends`] for processes
+/// spawned with a config, see [`ProcessConfig::instrument_remote_sends`].
+///
+/// [`ProcessConfig::instrument_remote_sends`]: crate::ProcessConfig::instrument_remote_sends
+pub const INSTRUMENT_SENDS_ENV: &str = "LUNATIC_INSTRUMENT_REMOTE_SENDS";
+
+process_local! {
+    // Unset until the environment is checked.
+    static INSTRUMENTED: Cell<Option<bool>> = Cell::new(None);
+    static CONNECTIONS: RefCell<HashMap<u64, ConnectionStats>> = RefCell::new(HashMap::new());
+    // Set in processes that establish connections, so that they can reach
+    // the other node before it's connected.
+    static UNGATED: Cell<bool> = Cell::new(false);
+}
 
 pub fn node_id() -> u64 {
     unsafe { api::distributed::node_id() }
@@ -48,9 +99,24 @@ pub fn lookup_nodes(query: &'static str) -> Result<Vec<u64>, LunaticError> {
     Ok(nodes)
 }
 
+/// Returns the id of the module the current process is running, under which
+/// it's known to the other nodes.
+pub fn module_id() -> u64 {
+    unsafe { api::distributed::module_id() }
+}
+
 pub fn spawn(node_id: u64, config_id: i64, entry: fn(i32), arg: i32) -> Result<u64, LunaticError> {
+    if !is_reachable(node_id) {
+        return Err(LunaticError::NotConnected { node_id });
+    }
     let entry = entry as usize as i32;
-    let params = params_to_vec(&[Param::I32(entry), Param::I32(arg)]);
+    // Remote processes don't set any spawn flags.
+    let params = params_to_vec(&[
+        Param::I32(entry),
+        Param::I32(arg),
+        Param::I32(0),
+        Param::I32(0),
+    ]);
     let mut id = 0;
     let func = concat!("_lunatic_spawn_by_index_", env!("CARGO_PKG_VERSION"));
     let result = unsafe {
@@ -68,6 +134,665 @@ pub fn spawn(node_id: u64, config_id: i64, entry: fn(i32), arg: i32) -> Result<u
     if result == 0 {
         Ok(id)
     } else {
-        Err(LunaticError::from(id))
+        Err(LunaticError::node(id, node_id))
+    }
+}
+
+/// Sets the attribute `key` of the current node to `value`.
+///
+/// Attributes are sent to the other nodes together with the heartbeat of the
+/// node, so they see the change within about one [`HEARTBEAT_INTERVAL`].
+pub fn set_attribute<K: Into<String>, V: Into<String>>(key: K, value: V) {
+    agent().send(AgentMessage::SetAttribute(key.into(), Some(value.into())));
+}
+
+/// Removes the attribute `key` of the current node.
+pub fn remove_attribute<K: Into<String>>(key: K) {
+    agent().send(AgentMessage::SetAttribute(key.into(), None));
+}
+
+/// Returns the attributes of `node_id`, as last seen by the current node.
+///
+/// Returns `None` if no heartbeat was received from the node yet.
+pub fn attributes(node_id: u64) -> Option<HashMap<String, String>> {
+    let tag = Tag::new();
+    agent().send(AgentMessage::Attributes(node_id, Process::this(), tag));
+    unsafe { Mailbox::<Option<HashMap<String, String>>>::new() }.tag_receive(&[tag])
+}
+
+/// Returns the connected nodes, including the current one, that have the
+/// attribute `key` set to `value`.
+///
+/// The attributes of other nodes are the ones from their last heartbeat, see
+/// [`node_health`].
+pub fn nodes_matching(key: &str, value: &str) -> Vec<u64> {
+    let tag = Tag::new();
+    agent().send(AgentMessage::Matching(
+        key.to_owned(),
+        value.to_owned(),
+        Process::this(),
+        tag,
+    ));
+    unsafe { Mailbox::<Vec<u64>>::new() }.tag_receive(&[tag])
+}
+
+/// Returns the time since the last heartbeat from `node_id` was received.
+///
+/// Returns `None` if no heartbeat was received from the node yet. The current
+/// node is always reported as healthy.
+pub fn node_health(node_id: u64) -> Option<Duration> {
+    let tag = Tag::new();
+    agent().send(AgentMessage::Health(node_id, Process::this(), tag));
+    unsafe { Mailbox::<Option<Duration>>::new() }.tag_receive(&[tag])
+}
+
+/// Statistics of the messages sent by the current process to one node, see
+/// [`connection_stats`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub sent_messages: u64,
+    pub sent_bytes: u64,
+    /// Sends that the host returned an error for.
+    pub failed_sends: u64,
+    /// Total time until the host accepted the messages. Requests that wait on
+    /// the reply in the same host call aren't included.
+    pub send_latency: Duration,
+    pub max_send_latency: Duration,
+}
+
+/// Enables or disables metrics on the sends of the current process to other
+/// nodes.
+///
+/// While enabled, each message sent to another node increments
+/// [`SENT_MESSAGES_COUNTER`] and [`SENT_BYTES_COUNTER`], and the time until
+/// the host accepted it is recorded in [`SEND_LATENCY_HISTOGRAM`]. The
+/// metrics are labeled with the id of the node. The statistics can also be
+/// queried with [`connection_stats`].
+///
+/// Measuring each send adds overhead, so it's disabled by default. It can be
+/// enabled for new processes with
+/// [`ProcessConfig::instrument_remote_sends`](crate::ProcessConfig::instrument_remote_sends).
+/// The host doesn't tell from which node a message was received, so only
+/// sent messages are measured.
+pub fn instrument_sends(enabled: bool) {
+    INSTRUMENTED.set(Some(enabled));
+}
+
+/// Returns `true` if the sends of the current process to other nodes are
+/// instrumented.
+pub fn is_instrumented() -> bool {
+    match INSTRUMENTED.get() {
+        Some(enabled) => enabled,
+        None => {
+            let enabled = std::env::var(INSTRUMENT_SENDS_ENV).is_ok_and(|value| value == "1");
+            INSTRUMENTED.set(Some(enabled));
+            enabled
+        }
+    }
+}
+
+/// Returns the statistics of the messages sent by the current process to
+/// `node_id`, collected while [`instrument_sends`] was enabled.
+///
+/// Returns `None` if no message was sent to the node while instrumented.
+pub fn connection_stats(node_id: u64) -> Option<ConnectionStats> {
+    CONNECTIONS.with_borrow(|connections| connections.get(&node_id).copied())
+}
+
+/// Sends the message in the buffer to a process on another node, recording
+/// it if the sends are instrumented.
+pub(crate) fn send(node_id: u64, process_id: u64) -> u32 {
+    if !is_instrumented() {
+        return unsafe { api::distributed::send(node_id, process_id) };
+    }
+    let bytes = unsafe { api::message::data_size() };
+    let start = Instant::now();
+    let result = unsafe { api::distributed::send(node_id, process_id) };
+    record_send(node_id, bytes, Some(start.elapsed()), result != 0);
+    result
+}
+
+/// Sends the message in the buffer to a process on another node and waits on
+/// the reply, recording the send if the sends are instrumented.
+///
+/// The host call only returns with the reply, so its latency isn't recorded.
+pub(crate) fn send_receive_skip_search(
+    node_id: u64,
+    process_id: u64,
+    wait_on_tag: i64,
+    timeout: u64,
+) -> u32 {
+    let bytes = is_instrumented().then(|| unsafe { api::message::data_size() });
+    let result = unsafe {
+        api::distributed::send_receive_skip_search(node_id, process_id, wait_on_tag, timeout)
+    };
+    if let Some(bytes) = bytes {
+        record_send(node_id, bytes, None, false);
+    }
+    result
+}
+
+fn record_send(node_id: u64, bytes: u64, latency: Option<Duration>, failed: bool) {
+    CONNECTIONS.with_borrow_mut(|mut connections| {
+        let stats = connections.entry(node_id).or_default();
+        stats.sent_messages += 1;
+        stats.sent_bytes += bytes;
+        stats.failed_sends += failed as u64;
+        if let Some(latency) = latency {
+            stats.send_latency += latency;
+            stats.max_send_latency = stats.max_send_latency.max(latency);
+        }
+    });
+    let node = node_id.to_string();
+    let labels = [("node", node.as_str())];
+    metrics::increment_counter(&metrics::with_labels(SENT_MESSAGES_COUNTER, &labels));
+    // The host adds the value to the counter.
+    metrics::counter(&metrics::with_labels(SENT_BYTES_COUNTER, &labels), bytes);
+    if let Some(latency) = latency {
+        metrics::histogram(
+            &metrics::with_labels(SEND_LATENCY_HISTOGRAM, &labels),
+            latency.as_secs_f64(),
+        );
+    }
+}
+
+/// Tracks `process` as in-flight work of the current node, so that [`drain`]
+/// waits on it.
+///
+/// Panics if `process` is on another node.
+#[track_caller]
+pub fn track<M, S>(process: Process<M, S>) {
+    assert_eq!(
+        process.node_id(),
+        host::node_id(),
+        "Only processes on the current node can be tracked"
+    );
+    agent().send(AgentMessage::Track(Process::new(
+        process.node_id(),
+        process.id(),
+    )));
+}
+
+/// Marks the current node as draining and waits until all processes tracked
+/// with [`track`] finish.
+///
+/// The node gets the [`DRAINING`] attribute set to `"true"`, so that other
+/// nodes can stop sending work to it. Returns `false` if some processes are
+/// still running after `timeout`.
+pub fn drain(timeout: Option<Duration>) -> bool {
+    set_attribute(DRAINING, "true");
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let tag = Tag::new();
+        agent().send(AgentMessage::InFlight(Process::this(), tag));
+        let in_flight = unsafe { Mailbox::<usize>::new() }.tag_receive(&[tag]);
+        if in_flight == 0 {
+            return true;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return false;
+        }
+        crate::sleep(DRAIN_CHECK_INTERVAL);
+    }
+}
+
+/// Restricts the sends of all processes on the current node to the nodes it's
+/// [connected](connect) to, or lifts the restriction.
+///
+/// By default the host connects each node to every other node of the
+/// cluster, so any node can be reached. Deployments where this isn't
+/// possible, e.g. with nodes in separate network zones, manage the
+/// connections explicitly. In a partial mesh, messages sent or processes
+/// spawned on a node without a connection fail right away with
+/// [`EncodeError::NotConnected`] and [`LunaticError::NotConnected`], instead
+/// of being relayed over other nodes. Routing between nodes that aren't
+/// connected is left to the application.
+///
+/// The host still owns the network transport, connections are agreed on
+/// between the node agents. Each send to another node checks the registry of
+/// the node, which adds one host call, or two in a partial mesh.
+///
+/// [`EncodeError::NotConnected`]: crate::serializer::EncodeError::NotConnected
+pub fn partial_mesh(enabled: bool) {
+    if enabled {
+        let agent = agent();
+        registry::put(PARTIAL_MESH_NAME, agent.node_id(), agent.id());
+    } else {
+        registry::remove(PARTIAL_MESH_NAME);
+    }
+}
+
+/// Returns `true` if the current node is a [partial mesh](partial_mesh).
+pub fn is_partial_mesh() -> bool {
+    registry::get(PARTIAL_MESH_NAME).is_some()
+}
+
+/// The state of a connection to another node, see [`connections`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnState {
+    /// Waiting for the other node to accept the connection.
+    Connecting,
+    /// Messages can be sent to the other node.
+    Connected,
+    /// No heartbeat was received from the other node within
+    /// [`CONNECTION_LOST_AFTER`], or it left the cluster. Messages can still
+    /// be sent, the connection is established again with the next heartbeat.
+    Lost,
+}
+
+/// Changes of the connections of the current node, see
+/// [`subscribe_connections`].
+///
+/// Nodes joining or leaving the cluster don't cause events, only connections.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionEvent {
+    /// The connection to the node was accepted, or came back after it was
+    /// lost.
+    Established(u64),
+    /// The connection to the node was [lost](ConnState::Lost).
+    Lost(u64),
+    /// One of the nodes [disconnected](disconnect).
+    Closed(u64),
+}
+
+/// Connects the current node to `node_id`, and waits until the other node
+/// accepted the connection.
+///
+/// Nodes are identified by their id, e.g. from [`nodes`] or
+/// [`lookup_nodes`]. Only the control node knows their addresses. Returns
+/// right away if the nodes are connected already. Fails with
+/// [`LunaticError::NotConnected`] if the node isn't part of the cluster, and
+/// with [`LunaticError::TimedOut`] if it didn't accept the connection within
+/// [`CONNECT_TIMEOUT`]. The current node keeps trying to connect until
+/// [`disconnect`] is called.
+pub fn connect(node_id: u64) -> Result<(), LunaticError> {
+    if node_id == host::node_id() {
+        return Ok(());
+    }
+    if !nodes().contains(&node_id) {
+        return Err(LunaticError::NotConnected { node_id });
+    }
+    let tag = Tag::new();
+    agent().send(AgentMessage::Connect(node_id, Process::this(), tag));
+    let mailbox = unsafe { Mailbox::<()>::new() };
+    match mailbox.tag_receive_timeout(&[tag], CONNECT_TIMEOUT) {
+        MailboxResult::Message(()) => Ok(()),
+        _ => Err(LunaticError::TimedOut),
+    }
+}
+
+/// Closes the connection between the current node and `node_id`, on both
+/// nodes.
+///
+/// In a [partial mesh](partial_mesh), sends to the node fail once this
+/// function returns.
+pub fn disconnect(node_id: u64) {
+    let tag = Tag::new();
+    agent().send(AgentMessage::Disconnect(node_id, Process::this(), tag));
+    unsafe { Mailbox::<()>::new() }.tag_receive(&[tag]);
+}
+
+/// Returns the connections of the current node, ordered by node id.
+pub fn connections() -> Vec<(u64, ConnState)> {
+    let tag = Tag::new();
+    agent().send(AgentMessage::Connections(Process::this(), tag));
+    unsafe { Mailbox::<Vec<(u64, ConnState)>>::new() }.tag_receive(&[tag])
+}
+
+/// Sends each [`ConnectionEvent`] of the current node to `subscriber`.
+///
+/// Panics if `subscriber` is on another node.
+#[track_caller]
+pub fn subscribe_connections(subscriber: Process<ConnectionEvent>) {
+    assert_eq!(
+        subscriber.node_id(),
+        host::node_id(),
+        "Only processes on the current node can subscribe to connections"
+    );
+    agent().send(AgentMessage::SubscribeConnections(subscriber));
+}
+
+/// Returns `true` if messages can be sent to `node_id` from the current
+/// process.
+pub(crate) fn is_reachable(node_id: u64) -> bool {
+    node_id == host::node_id()
+        || UNGATED.get()
+        || !is_partial_mesh()
+        || registry::get(&connection_name(node_id)).is_some()
+}
+
+fn connection_name(node_id: u64) -> String {
+    format!("{CONNECTION_PREFIX}{node_id}")
+}
+
+fn agent() -> Process<AgentMessage> {
+    match registry::get_or_put_later(AGENT_NAME) {
+        Some((node_id, process_id)) => Process::new(node_id, process_id),
+        None => {
+            let process = Process::spawn((), agent_process);
+            registry::put(AGENT_NAME, process.node_id(), process.id());
+            process
+        }
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum AgentMessage {
+    Heartbeat {
+        node_id: u64,
+        agent: Process<AgentMessage>,
+        attributes: HashMap<String, String>,
+    },
+    Introduce(Process<AgentMessage>),
+    SetAttribute(String, Option<String>),
+    Attributes(u64, Process<Option<HashMap<String, String>>>, Tag),
+    Matching(String, String, Process<Vec<u64>>, Tag),
+    Health(u64, Process<Option<Duration>>, Tag),
+    Track(Process<()>),
+    InFlight(Process<usize>, Tag),
+    Connect(u64, Process<()>, Tag),
+    Accept(Process<AgentMessage>),
+    Accepted(Process<AgentMessage>),
+    Disconnect(u64, Process<()>, Tag),
+    Disconnected(u64),
+    Connections(Process<Vec<(u64, ConnState)>>, Tag),
+    SubscribeConnections(Process<ConnectionEvent>),
+}
+
+struct Peer {
+    agent: Process<AgentMessage>,
+    attributes: HashMap<String, String>,
+    // Not set for peers that introduced themselves, but didn't send a heartbeat yet.
+    last_heartbeat: Option<Instant>,
+}
+
+struct Connection {
+    state: ConnState,
+    // Not set while connecting.
+    agent: Option<Process<AgentMessage>>,
+    // When the connection was requested or accepted.
+    since: Instant,
+    // Processes waiting in `connect`.
+    waiters: Vec<(Process<()>, Tag)>,
+}
+
+fn agent_process(_: (), mailbox: Mailbox<AgentMessage>) {
+    let this = mailbox.this();
+    let local = host::node_id();
+    let mut attributes: HashMap<String, String> = HashMap::new();
+    let mut peers: HashMap<u64, Peer> = HashMap::new();
+    // Nodes that were asked to introduce their agent, and when.
+    let mut introductions: HashMap<u64, Instant> = HashMap::new();
+    let mut tracked: Vec<Process<()>> = Vec::new();
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    let mut subscribers: Vec<Process<ConnectionEvent>> = Vec::new();
+    let mut next_heartbeat = Instant::now();
+    let notify = |subscribers: &mut Vec<Process<ConnectionEvent>>, event: ConnectionEvent| {
+        subscribers.retain(|subscriber| subscriber.is_alive());
+        for subscriber in subscribers.iter() {
+            subscriber.send(event);
+        }
+    };
+
+    loop {
+        let now = Instant::now();
+        if now >= next_heartbeat {
+            let mut connected: HashSet<u64> = nodes().into_iter().collect();
+            peers.retain(|node_id, _| connected.contains(node_id));
+            for (&node_id, connection) in connections.iter_mut() {
+                let last_seen = peers
+                    .get(&node_id)
+                    .and_then(|peer| peer.last_heartbeat)
+                    .map_or(connection.since, |last| last.max(connection.since));
+                let alive = connected.contains(&node_id)
+                    && now.saturating_duration_since(last_seen) < CONNECTION_LOST_AFTER;
+                match connection.state {
+                    ConnState::Connecting
+                        if now.saturating_duration_since(connection.since)
+                            >= INTRODUCTION_RETRY =>
+                    {
+                        connection.since = now;
+                        accept(node_id, this);
+                    }
+                    ConnState::Connected if !alive => {
+                        connection.state = ConnState::Lost;
+                        notify(&mut subscribers, ConnectionEvent::Lost(node_id));
+                    }
+                    ConnState::Lost if alive => {
+                        connection.state = ConnState::Connected;
+                        notify(&mut subscribers, ConnectionEvent::Established(node_id));
+                    }
+                    _ => (),
+                }
+            }
+            // Nodes in a partial mesh only talk to the nodes they are connected to.
+            if is_partial_mesh() {
+                connected.retain(|node_id| {
+                    connections
+                        .get(node_id)
+                        .is_some_and(|connection| connection.agent.is_some())
+                });
+            }
+            for &node_id in connected.iter().filter(|&&node_id| node_id != local) {
+                match peers.get(&node_id) {
+                    Some(peer) => peer.agent.send(AgentMessage::Heartbeat {
+                        node_id: local,
+                        agent: this,
+                        attributes: attributes.clone(),
+                    }),
+                    None => {
+                        let pending = introductions
+                            .get(&node_id)
+                            .is_some_and(|asked| now - *asked < INTRODUCTION_RETRY);
+                        if !pending {
+                            introductions.insert(node_id, now);
+                            introduce(node_id, this);
+                        }
+                    }
+                }
+            }
+            next_heartbeat = now + HEARTBEAT_INTERVAL;
+        }
+
+        let message = match mailbox.receive_timeout(next_heartbeat.saturating_duration_since(now)) {
+            MailboxResult::Message(message) => message,
+            _ => continue,
+        };
+        match message {
+            AgentMessage::Heartbeat {
+                node_id,
+                agent,
+                attributes: peer_attributes,
+            } => {
+                introductions.remove(&node_id);
+                peers.insert(
+                    node_id,
+                    Peer {
+                        agent,
+                        attributes: peer_attributes,
+                        last_heartbeat: Some(Instant::now()),
+                    },
+                );
+            }
+            AgentMessage::Introduce(agent) => {
+                let node_id = agent.node_id();
+                peers.entry(node_id).or_insert(Peer {
+                    agent,
+                    attributes: HashMap::new(),
+                    last_heartbeat: None,
+                });
+                // Let the other node know about this agent right away.
+                agent.send(AgentMessage::Heartbeat {
+                    node_id: local,
+                    agent: this,
+                    attributes: attributes.clone(),
+                });
+            }
+            AgentMessage::SetAttribute(key, Some(value)) => {
+                attributes.insert(key, value);
+            }
+            AgentMessage::SetAttribute(key, None) => {
+                attributes.remove(&key);
+            }
+            AgentMessage::Attributes(node_id, reply, tag) => {
+                let found = if node_id == local {
+                    Some(attributes.clone())
+                } else {
+                    peers
+                        .get(&node_id)
+                        .filter(|peer| peer.last_heartbeat.is_some())
+                        .map(|peer| peer.attributes.clone())
+                };
+                reply.tag_send(tag, found);
+            }
+            AgentMessage::Matching(key, value, reply, tag) => {
+                let matches = |attributes: &HashMap<String, String>| {
+                    attributes.get(&key).is_some_and(|found| *found == value)
+                };
+                let mut found: Vec<u64> = peers
+                    .iter()
+                    .filter(|(_, peer)| matches(&peer.attributes))
+                    .map(|(&node_id, _)| node_id)
+                    .collect();
+                if matches(&attributes) {
+                    found.push(local);
+                }
+                found.sort_unstable();
+                reply.tag_send(tag, found);
+            }
+            AgentMessage::Health(node_id, reply, tag) => {
+                let age = if node_id == local {
+                    Some(Duration::ZERO)
+                } else {
+                    peers
+                        .get(&node_id)
+                        .and_then(|peer| peer.last_heartbeat)
+                        .map(|last_heartbeat| last_heartbeat.elapsed())
+                };
+                reply.tag_send(tag, age);
+            }
+            AgentMessage::Track(process) => {
+                tracked.push(process);
+            }
+            AgentMessage::InFlight(reply, tag) => {
+                tracked.retain(|process| process.is_alive());
+                reply.tag_send(tag, tracked.len());
+            }
+            AgentMessage::Connect(node_id, reply, tag) => {
+                let connection = connections.entry(node_id).or_insert_with(|| {
+                    accept(node_id, this);
+                    Connection {
+                        state: ConnState::Connecting,
+                        agent: None,
+                        since: Instant::now(),
+                        waiters: Vec::new(),
+                    }
+                });
+                match connection.state {
+                    ConnState::Connecting => connection.waiters.push((reply, tag)),
+                    _ => reply.tag_send(tag, ()),
+                }
+            }
+            AgentMessage::Accept(agent) => {
+                let node_id = agent.node_id();
+                let connection = connections.entry(node_id).or_insert_with(|| Connection {
+                    state: ConnState::Connecting,
+                    agent: None,
+                    since: Instant::now(),
+                    waiters: Vec::new(),
+                });
+                let established = connection.state != ConnState::Connected;
+                establish(connection, agent);
+                if established {
+                    notify(&mut subscribers, ConnectionEvent::Established(node_id));
+                }
+                agent.send(AgentMessage::Accepted(this));
+            }
+            AgentMessage::Accepted(agent) => {
+                let node_id = agent.node_id();
+                match connections.get_mut(&node_id) {
+                    Some(connection) => {
+                        let established = connection.state != ConnState::Connected;
+                        establish(connection, agent);
+                        if established {
+                            notify(&mut subscribers, ConnectionEvent::Established(node_id));
+                        }
+                    }
+                    // The connection was closed while the other node accepted it.
+                    None => agent.send(AgentMessage::Disconnected(local)),
+                }
+            }
+            AgentMessage::Disconnect(node_id, reply, tag) => {
+                if let Some(connection) = connections.remove(&node_id) {
+                    if let Some(agent) = connection.agent {
+                        agent.send(AgentMessage::Disconnected(local));
+                    }
+                    close(node_id);
+                    notify(&mut subscribers, ConnectionEvent::Closed(node_id));
+                }
+                reply.tag_send(tag, ());
+            }
+            AgentMessage::Disconnected(node_id) => {
+                if connections.remove(&node_id).is_some() {
+                    close(node_id);
+                    notify(&mut subscribers, ConnectionEvent::Closed(node_id));
+                }
+            }
+            AgentMessage::Connections(reply, tag) => {
+                let mut found: Vec<(u64, ConnState)> = connections
+                    .iter()
+                    .map(|(&node_id, connection)| (node_id, connection.state))
+                    .collect();
+                found.sort_unstable_by_key(|(node_id, _)| *node_id);
+                reply.tag_send(tag, found);
+            }
+            AgentMessage::SubscribeConnections(subscriber) => {
+                subscribers.push(subscriber);
+            }
+        }
+    }
+}
+
+/// Asks the agent on `node_id` to introduce itself to `agent`, starting it if
+/// necessary.
+fn introduce(node_id: u64, agent: Process<AgentMessage>) {
+    // The remote spawn is done from a separate process, so that a node leaving
+    // the cluster can't bring the agent down.
+    Process::spawn((node_id, agent), |(node_id, agent), _: Mailbox<()>| {
+        Process::spawn_node(node_id, agent, |agent, _: Mailbox<()>| {
+            self::agent().send(AgentMessage::Introduce(agent));
+        });
+    });
+}
+
+/// Asks the agent on `node_id` to accept a connection from `agent`.
+fn accept(node_id: u64, agent: Process<AgentMessage>) {
+    Process::spawn((node_id, agent), |(node_id, agent), _: Mailbox<()>| {
+        // The other node can't be reached through the registry before it's connected.
+        UNGATED.set(true);
+        Process::spawn_node(node_id, agent, |agent, _: Mailbox<()>| {
+            self::agent().send(AgentMessage::Accept(agent));
+        });
+    });
+}
+
+// Marks `connection` as established with the agent `agent`.
+fn establish(connection: &mut Connection, agent: Process<AgentMessage>) {
+    registry::put(
+        &connection_name(agent.node_id()),
+        agent.node_id(),
+        agent.id(),
+    );
+    connection.state = ConnState::Connected;
+    connection.agent = Some(agent);
+    connection.since = Instant::now();
+    for (waiter, tag) in connection.waiters.drain(..) {
+        waiter.tag_send(tag, ());
     }
 }
+
+// Removes the connection to `node_id` from the registry.
+fn close(node_id: u64) {
+    registry::remove(&connection_name(node_id));
+}
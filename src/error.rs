@@ -1,64 +1,166 @@
 use std::fmt::{Debug, Display, Formatter};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
 
 use thiserror::Error;
 
 use crate::host::api::error;
 
-/// An opaque error returned from host calls.
+/// An error returned from lunatic.
 ///
-/// Host calls can have a big number of failure reasons, and it's impossible to
-/// enumerate all of them. This is especially true for calls that involve
-/// compiling raw binary data to WebAssembly modules. Because of this an opaque
-/// error ID is returned from host that can be transformed to a string.
-#[derive(Error)]
+/// Errors with a well known reason have their own variant containing relevant
+/// context. Host calls can have a big number of other failure reasons, and it's
+/// impossible to enumerate all of them. This is especially true for calls that
+/// involve compiling raw binary data to WebAssembly modules. Because of this an
+/// opaque [`HostError`] is returned in [`LunaticError::Error`] that can be
+/// transformed to a string.
+///
+/// New variants may be added in the future, so matches on this enum should
+/// always contain a wildcard arm.
+#[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum LunaticError {
-    Error(u64),
+    /// An opaque error returned from host calls.
+    #[error("{0}")]
+    Error(#[source] HostError),
+    /// The process doesn't have the permission to perform the operation.
+    #[error("The process doesn't have the permission to perform this operation.")]
     PermissionDenied,
+    /// A networking operation failed.
+    #[error("{}", network_message(.address, .source))]
+    Network {
+        /// Address of the peer, if known.
+        address: Option<SocketAddr>,
+        source: HostError,
+    },
+    /// The process doesn't exist, or is not alive anymore.
+    #[error("The process {process_id} doesn't exist.")]
+    ProcessNotFound { process_id: u64 },
+    /// The node can't be reached.
+    #[error("The node {node_id} can't be reached: {source}")]
+    NodeUnreachable { node_id: u64, source: HostError },
+    /// The current node isn't connected to the node, see
+    /// [`distributed::partial_mesh`](crate::distributed::partial_mesh).
+    #[error("The node {node_id} isn't connected.")]
+    NotConnected { node_id: u64 },
+    /// A resource (e.g. a socket or a module) with the given id doesn't exist.
+    #[error("The resource {resource_id} doesn't exist.")]
+    ResourceNotFound { resource_id: u64 },
+    /// A message couldn't be serialized or deserialized.
+    #[error("Serialization failed: {message}")]
+    Serialization { message: String },
+    /// The operation timed out.
+    #[error("The operation timed out.")]
+    TimedOut,
+    /// The host doesn't support the operation.
+    #[error("The operation {operation} isn't supported by the host.")]
+    Unsupported { operation: &'static str },
 }
 
-impl Drop for LunaticError {
-    fn drop(&mut self) {
-        match self {
-            LunaticError::Error(id) => {
-                unsafe { error::drop(*id) };
-            }
-            LunaticError::PermissionDenied => (),
-        }
+fn network_message(address: &Option<SocketAddr>, source: &HostError) -> String {
+    match address {
+        Some(address) => format!("Networking operation with {} failed: {}", address, source),
+        None => format!("Networking operation failed: {}", source),
     }
 }
 
 impl LunaticError {
     pub(crate) fn from(id: u64) -> Self {
-        LunaticError::Error(id)
+        LunaticError::Error(HostError(id))
     }
-}
 
-impl Debug for LunaticError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+    /// Creates a networking error out of the host error `id`.
+    pub(crate) fn network(id: u64, address: Option<SocketAddr>) -> Self {
+        LunaticError::Network {
+            address,
+            source: HostError(id),
+        }
+    }
+
+    /// Creates an error for a failed operation on node `node_id` out of the
+    /// host error `id`.
+    pub(crate) fn node(id: u64, node_id: u64) -> Self {
+        LunaticError::NodeUnreachable {
+            node_id,
+            source: HostError(id),
+        }
+    }
+
+    /// Returns the [`ErrorKind`] that best describes this error.
+    pub fn kind(&self) -> ErrorKind {
         match self {
-            LunaticError::Error(id) => {
-                let size = unsafe { error::string_size(*id) };
-                let mut buff = vec![0; size as usize];
-                unsafe { error::to_string(*id, buff.as_mut_ptr()) };
-                let error = std::str::from_utf8(&buff).unwrap();
-                write!(f, "{}", error)
-            }
-            LunaticError::PermissionDenied => write!(f, "Permission denied"),
+            LunaticError::Error(source) => source.kind(),
+            LunaticError::PermissionDenied => ErrorKind::PermissionDenied,
+            LunaticError::Network { source, .. } => source.kind(),
+            LunaticError::ProcessNotFound { .. } => ErrorKind::NotFound,
+            LunaticError::NodeUnreachable { .. } => ErrorKind::NotConnected,
+            LunaticError::NotConnected { .. } => ErrorKind::NotConnected,
+            LunaticError::ResourceNotFound { .. } => ErrorKind::NotFound,
+            LunaticError::Serialization { .. } => ErrorKind::InvalidData,
+            LunaticError::TimedOut => ErrorKind::TimedOut,
+            LunaticError::Unsupported { .. } => ErrorKind::Unsupported,
         }
     }
 }
 
-impl Display for LunaticError {
+impl From<LunaticError> for std::io::Error {
+    fn from(error: LunaticError) -> Self {
+        std::io::Error::new(error.kind(), error)
+    }
+}
+
+/// An opaque error returned from host calls.
+///
+/// The error is held by the host as a resource and can be transformed to a
+/// string with the `Display` implementation.
+#[derive(Error)]
+pub struct HostError(u64);
+
+impl HostError {
+    /// Returns the error message provided by the host.
+    pub fn message(&self) -> String {
+        let size = unsafe { error::string_size(self.0) };
+        let mut buff = vec![0; size as usize];
+        unsafe { error::to_string(self.0, buff.as_mut_ptr()) };
+        String::from_utf8_lossy(&buff).into_owned()
+    }
+
+    /// Guesses the [`ErrorKind`] from the host error message.
+    fn kind(&self) -> ErrorKind {
+        let message = self.message().to_lowercase();
+        let kinds = [
+            ("refused", ErrorKind::ConnectionRefused),
+            ("reset", ErrorKind::ConnectionReset),
+            ("aborted", ErrorKind::ConnectionAborted),
+            ("not connected", ErrorKind::NotConnected),
+            ("in use", ErrorKind::AddrInUse),
+            ("not available", ErrorKind::AddrNotAvailable),
+            ("broken pipe", ErrorKind::BrokenPipe),
+            ("timed out", ErrorKind::TimedOut),
+            ("not found", ErrorKind::NotFound),
+            ("permission denied", ErrorKind::PermissionDenied),
+        ];
+        kinds
+            .into_iter()
+            .find(|(pattern, _)| message.contains(pattern))
+            .map_or(ErrorKind::Other, |(_, kind)| kind)
+    }
+}
+
+impl Drop for HostError {
+    fn drop(&mut self) {
+        unsafe { error::drop(self.0) };
+    }
+}
+
+impl Debug for HostError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match self {
-            LunaticError::Error(id) => {
-                let size = unsafe { error::string_size(*id) };
-                let mut buff = vec![0; size as usize];
-                unsafe { error::to_string(*id, buff.as_mut_ptr()) };
-                let error = std::str::from_utf8(&buff).unwrap();
-                write!(f, "{}", error)
-            }
-            LunaticError::PermissionDenied => write!(f, "Permission denied"),
-        }
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.message())
     }
 }
@@ -0,0 +1,261 @@
+//! Filesystem access that can be shared between processes.
+//!
+//! Processes only see the directories that were preopened in the
+//! [`ProcessConfig`](crate::ProcessConfig) used to spawn them, see
+//! [`preopen_dir`](crate::ProcessConfig::preopen_dir). Operations on paths
+//! outside of these directories fail with [`FsError::OutsideSandbox`].
+//!
+//! A [`File`] can be sent to another process. The receiving process reopens
+//! the same file at the same offset, so it needs access to the file's
+//! directory too.
+//!
+//! # Example
+//!
+//! ```
+//! let mut file = File::create("uploads/image.png")?;
+//! file.write_all(&data)?;
+//! file.rewind()?;
+//! checksum_worker.send(file);
+//! ```
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// WASI error code returned for paths outside of the preopened directories.
+const ENOTCAPABLE: i32 = 76;
+
+/// Error of a filesystem operation.
+#[derive(Error, Debug)]
+pub enum FsError {
+    /// The path is outside of the directories the process has access to.
+    #[error("path {0:?} is outside of the directories granted to the process")]
+    OutsideSandbox(PathBuf),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+impl FsError {
+    fn from_io(error: io::Error, path: &Path) -> Self {
+        if error.raw_os_error() == Some(ENOTCAPABLE) {
+            FsError::OutsideSandbox(path.to_owned())
+        } else {
+            FsError::Io(error)
+        }
+    }
+}
+
+impl From<FsError> for io::Error {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::OutsideSandbox(path) => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                FsError::OutsideSandbox(path),
+            ),
+            FsError::Io(error) => error,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, FsError>;
+
+/// An open file.
+///
+/// When sent to another process, the file is reopened by the receiver at the
+/// current offset. Both processes can keep using their handle, each of them
+/// has its own offset.
+#[derive(Debug)]
+pub struct File {
+    file: std::fs::File,
+    path: PathBuf,
+    options: Options,
+}
+
+/// Options used to open a [`File`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub create: bool,
+    pub truncate: bool,
+}
+
+impl Options {
+    fn open(&self, path: &Path) -> Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .read(self.read)
+            .write(self.write)
+            .append(self.append)
+            .create(self.create)
+            .truncate(self.truncate)
+            .open(path)
+            .map_err(|error| FsError::from_io(error, path))
+    }
+}
+
+impl File {
+    /// Opens a file in read-only mode.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<File> {
+        let options = Options {
+            read: true,
+            ..Options::default()
+        };
+        File::with_options(path, options)
+    }
+
+    /// Opens a file in write-only mode, creating it if it doesn't exist and
+    /// truncating it if it does.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<File> {
+        let options = Options {
+            write: true,
+            create: true,
+            truncate: true,
+            ..Options::default()
+        };
+        File::with_options(path, options)
+    }
+
+    /// Opens a file with the given `options`.
+    pub fn with_options<P: AsRef<Path>>(path: P, options: Options) -> Result<File> {
+        let path = path.as_ref().to_owned();
+        let file = options.open(&path)?;
+        Ok(File {
+            file,
+            path,
+            options,
+        })
+    }
+
+    /// Returns the path used to open the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the metadata of the file.
+    pub fn metadata(&self) -> Result<std::fs::Metadata> {
+        self.file
+            .metadata()
+            .map_err(|error| FsError::from_io(error, &self.path))
+    }
+
+    /// Flushes all data and metadata to disk.
+    pub fn sync_all(&self) -> Result<()> {
+        self.file
+            .sync_all()
+            .map_err(|error| FsError::from_io(error, &self.path))
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.file.seek(position)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedFile {
+    path: PathBuf,
+    options: Options,
+    position: u64,
+}
+
+impl Serialize for File {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let position = (&self.file)
+            .stream_position()
+            .map_err(serde::ser::Error::custom)?;
+        SerializedFile {
+            path: self.path.clone(),
+            // The file was already created and truncated by the sender.
+            options: Options {
+                create: false,
+                truncate: false,
+                ..self.options
+            },
+            position,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for File {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<File, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let SerializedFile {
+            path,
+            options,
+            position,
+        } = SerializedFile::deserialize(deserializer)?;
+        let mut file = File::with_options(path, options).map_err(D::Error::custom)?;
+        file.seek(SeekFrom::Start(position))
+            .map_err(D::Error::custom)?;
+        Ok(file)
+    }
+}
+
+/// Reads the whole content of a file.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    std::fs::read(path).map_err(|error| FsError::from_io(error, path))
+}
+
+/// Writes `contents` to a file, replacing its content.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, contents).map_err(|error| FsError::from_io(error, path))
+}
+
+/// Returns the metadata of a file or directory.
+pub fn metadata<P: AsRef<Path>>(path: P) -> Result<std::fs::Metadata> {
+    let path = path.as_ref();
+    std::fs::metadata(path).map_err(|error| FsError::from_io(error, path))
+}
+
+/// Returns the paths of the entries inside a directory.
+pub fn read_dir<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    std::fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect()
+        })
+        .map_err(|error| FsError::from_io(error, path))
+}
+
+/// Creates a directory and all of its missing parents.
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    std::fs::create_dir_all(path).map_err(|error| FsError::from_io(error, path))
+}
+
+/// Removes a file.
+pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    std::fs::remove_file(path).map_err(|error| FsError::from_io(error, path))
+}
@@ -0,0 +1,134 @@
+//! Conversion between fuel and CPU time.
+//!
+//! The runtime limits the CPU usage of a process in fuel, see
+//! [`ProcessConfig::set_max_fuel`](crate::ProcessConfig::set_max_fuel). One
+//! unit of fuel is approximately 100k wasm instructions, how long that takes
+//! depends on the host. This module measures how much fuel the host burns per
+//! millisecond, so that limits can be set as a [`Duration`], see
+//! [`ProcessConfig::set_max_cpu_time`](crate::ProcessConfig::set_max_cpu_time).
+//!
+//! The measurement runs a busy loop in a process with [`CALIBRATION_FUEL`],
+//! and times it until the process is stopped. It takes a few dozen
+//! milliseconds and is done once per process, on first use. Processes spawned
+//! with [`set_max_cpu_time`](crate::ProcessConfig::set_max_cpu_time) get the
+//! rate of their parent and don't measure it again.
+//!
+//! # Accuracy
+//!
+//! The conversion is an estimate, expect it to be off by a factor of 2 or
+//! more:
+//!
+//! * Fuel counts instructions, not time. Code with many memory accesses or
+//!   calls runs fewer instructions per millisecond than the busy loop of the
+//!   measurement.
+//! * Host calls, e.g. to send messages or use the network, take time but
+//!   almost no fuel.
+//! * The measurement includes the time it takes to spawn a process, and
+//!   overestimates the time of a unit of fuel while the node is busy. The
+//!   resulting limits are rather too short than too long.
+//! * A process only burns fuel while it's running, not while it waits for
+//!   messages. The limit is on CPU time, not on the lifetime of the process.
+//!
+//! If the current process can't create configs, or the measurement fails, a
+//! conservative [default rate](DEFAULT_FUEL_PER_MS) is used.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use crate::{host, process_local, sleep, Mailbox, Process, ProcessConfig};
+
+/// Environment variable that sets the rate of processes spawned with a CPU
+/// time limit, see
+/// [`ProcessConfig::set_max_cpu_time`](crate::ProcessConfig::set_max_cpu_time).
+pub const FUEL_PER_MS_ENV: &str = "LUNATIC_FUEL_PER_MS";
+/// The fuel of the process that measures the rate.
+pub const CALIBRATION_FUEL: u64 = 200;
+/// The rate that is used if it can't be measured.
+pub const DEFAULT_FUEL_PER_MS: f64 = 10.0;
+
+// How often the measurement checks if the process was stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+process_local! {
+    // Unset until it's read from the environment or measured.
+    static FUEL_PER_MS: Cell<Option<f64>> = Cell::new(None);
+}
+
+/// Returns the fuel the host burns per millisecond of CPU time.
+///
+/// The rate is measured on first use, see [`calibrate`].
+pub fn fuel_per_ms() -> f64 {
+    if let Some(rate) = FUEL_PER_MS.get() {
+        return rate;
+    }
+    let rate = std::env::var(FUEL_PER_MS_ENV)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|rate: &f64| rate.is_finite() && *rate > 0.0)
+        .unwrap_or_else(measure);
+    FUEL_PER_MS.set(Some(rate));
+    rate
+}
+
+/// Measures the rate again and returns it.
+///
+/// This is only useful if the load of the node changed a lot since the last
+/// measurement.
+pub fn calibrate() -> f64 {
+    let rate = measure();
+    FUEL_PER_MS.set(Some(rate));
+    rate
+}
+
+/// Overrides the rate of the current process, e.g. with a value that was
+/// measured on the host beforehand.
+///
+/// # Panics
+///
+/// Panics if `rate` isn't positive.
+pub fn set_fuel_per_ms(rate: f64) {
+    assert!(
+        rate.is_finite() && rate > 0.0,
+        "The fuel rate must be positive"
+    );
+    FUEL_PER_MS.set(Some(rate));
+}
+
+/// Returns the fuel that lasts for `duration` of CPU time, but at least 1.
+pub fn duration_to_fuel(duration: Duration) -> u64 {
+    let fuel = (duration.as_secs_f64() * 1000.0 * fuel_per_ms()).ceil();
+    // The cast saturates for durations that are too long.
+    (fuel as u64).max(1)
+}
+
+/// Returns the CPU time it takes to burn `fuel`, e.g. to report the fuel used
+/// by a process in milliseconds.
+pub fn fuel_to_duration(fuel: u64) -> Duration {
+    Duration::from_secs_f64(fuel as f64 / fuel_per_ms() / 1000.0)
+}
+
+fn measure() -> f64 {
+    let Ok(mut config) = ProcessConfig::new() else {
+        return DEFAULT_FUEL_PER_MS;
+    };
+    config.set_max_fuel(CALIBRATION_FUEL);
+    let start = Instant::now();
+    let process = Process::spawn_config(&config, (), burn);
+    while unsafe { host::api::process::exists(process.id()) } != 0 {
+        sleep(POLL_INTERVAL);
+    }
+    let millis = start.elapsed().as_secs_f64() * 1000.0;
+    if millis > 0.0 {
+        CALIBRATION_FUEL as f64 / millis
+    } else {
+        DEFAULT_FUEL_PER_MS
+    }
+}
+
+// Runs until the process is out of fuel.
+fn burn(_: (), _: Mailbox<()>) {
+    let mut counter = 0u64;
+    loop {
+        counter = std::hint::black_box(counter.wrapping_add(1));
+    }
+}
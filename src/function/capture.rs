@@ -0,0 +1,114 @@
+//! Explicit capture lists for spawned processes.
+//!
+//! [`Process::spawn_with`] takes the values captured by the new process as a
+//! tuple. Each value is sent to the new process as a separate message, so that
+//! a type that can't be serialized is reported on its own, instead of as a
+//! failure to serialize the whole capture. The values can also use a different
+//! serializer than the mailbox of the process, e.g. [`RawBytes`] for a large
+//! buffer.
+//!
+//! # Example
+//!
+//! ```
+//! let buffer = vec![0u8; 1024 * 1024];
+//! let child = Process::spawn_with_serializer::<RawBytes, _>(
+//!     (buffer,),
+//!     |(buffer,), mailbox: Mailbox<u64>| {
+//!         let index = mailbox.receive();
+//!         assert_eq!(buffer[index as usize], 0);
+//!     },
+//! );
+//! child.send(42);
+//! ```
+//!
+//! [`RawBytes`]: crate::serializer::RawBytes
+
+use crate::serializer::CanSerialize;
+use crate::{host, Mailbox, Process, ProcessConfig, Tag};
+
+/// A tuple of values captured by a process.
+///
+/// It's implemented for tuples of up to 12 values, where each value can be
+/// serialized with `S`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be used as a capture list with the `{S}` serializer",
+    label = "captured values must be passed as a tuple",
+    note = "use `(value,)` to capture a single value"
+)]
+pub trait CaptureList<S>: Sized {
+    #[doc(hidden)]
+    fn send(self, node_id: u64, process_id: u64);
+    #[doc(hidden)]
+    fn receive() -> Self;
+}
+
+macro_rules! impl_capture_list {
+    ($($value:ident),*) => {
+        impl<S, $($value),*> CaptureList<S> for ($($value,)*)
+        where
+            $(S: CanSerialize<$value>,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn send(self, node_id: u64, process_id: u64) {
+                let ($($value,)*) = self;
+                $(Process::<$value, S>::new(node_id, process_id).send($value);)*
+            }
+
+            // The empty list receives `()`.
+            #[allow(clippy::unused_unit)]
+            fn receive() -> Self {
+                // Tuple expressions are evaluated from left to right, the same order the
+                // values were sent in.
+                ($(unsafe { Mailbox::<$value, S>::new() }.receive(),)*)
+            }
+        }
+    };
+}
+
+impl_capture_list!();
+impl_capture_list!(A);
+impl_capture_list!(A, B);
+impl_capture_list!(A, B, C);
+impl_capture_list!(A, B, C, D);
+impl_capture_list!(A, B, C, D, E);
+impl_capture_list!(A, B, C, D, E, F);
+impl_capture_list!(A, B, C, D, E, F, G);
+impl_capture_list!(A, B, C, D, E, F, G, H);
+impl_capture_list!(A, B, C, D, E, F, G, H, I);
+impl_capture_list!(A, B, C, D, E, F, G, H, I, J);
+impl_capture_list!(A, B, C, D, E, F, G, H, I, J, K);
+impl_capture_list!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+pub(crate) fn spawn<C, CS, M, S>(
+    capture: C,
+    entry: fn(C, Mailbox<M, S>),
+    link: Option<Tag>,
+    config: Option<&ProcessConfig>,
+    node: Option<u64>,
+) -> Process<M, S>
+where
+    C: CaptureList<CS>,
+    S: CanSerialize<M>,
+{
+    let entry = entry as usize as i32;
+    let node_id = node.unwrap_or_else(host::node_id);
+    match host::spawn(node, config, link, capture_wrapper::<C, CS, M, S>, entry) {
+        Ok(id) => {
+            capture.send(node_id, id);
+            Process::new(node_id, id)
+        }
+        Err(err) => panic!("Failed to spawn a process: {}", err),
+    }
+}
+
+/// Wrapper function to help transfer the generic types into the new process.
+fn capture_wrapper<C, CS, M, S>(function: i32)
+where
+    C: CaptureList<CS>,
+    S: CanSerialize<M>,
+{
+    let captured = C::receive();
+    let mailbox = unsafe { Mailbox::new() };
+    let function: fn(C, Mailbox<M, S>) = unsafe { std::mem::transmute(function as usize) };
+    function(captured, mailbox);
+}
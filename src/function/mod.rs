@@ -1,4 +1,7 @@
+pub mod capture;
 pub(crate) mod process;
 pub mod reference;
+pub mod request;
 
 pub use reference::FuncRef;
+pub use request::Request;
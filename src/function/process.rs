@@ -5,14 +5,18 @@ use std::any::type_name;
 use std::marker::PhantomData;
 use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
 
+use crate::debug::DebugInfo;
+use crate::function::capture::{self, CaptureList};
 use crate::host::{self, node_id, process_id};
-use crate::mailbox::TIMEOUT;
+use crate::mailbox::{PRIORITY_TAG, TIMEOUT};
 use crate::protocol::ProtocolCapture;
-use crate::serializer::{Bincode, CanSerialize};
-use crate::time::TimerRef;
-use crate::{MailboxResult, ProcessConfig, Tag};
+use crate::serializer::{self, Bincode, CanSerialize, EncodeError};
+use crate::time::{self, TimerRef};
+use crate::trace::TraceEvent;
+use crate::{Mailbox, MailboxResult, ProcessConfig, Tag};
 
 /// Decides what can be turned into a process.
 ///
@@ -117,24 +121,82 @@ pub trait NoLink {}
 /// serializer (e.g. `Protocol<AddProtocol, MessagePack>`).
 ///
 /// If a protocol based process is dropped before the `End` state is reached,
-/// the drop will panic.
-#[derive(Serialize, Deserialize)]
+/// the session is aborted and the other side is notified. The other side will
+/// observe this as an [`Aborted`](crate::protocol::Aborted) error from the
+/// `try_*` functions, or as a panic from the regular ones.
+///
+/// ### Sending handles to other processes
+///
+/// A serialized handle carries a fingerprint of its message type and
+/// serializer. The receiving side can't check the type parameters during
+/// deserialization, but it can verify them with
+/// [`checked_cast`](Process::checked_cast).
+///
+/// Handles don't hold any resources, so they can be nested anywhere in a
+/// message, e.g. in a `Vec`, a struct or the values of a `HashMap`, with every
+/// serializer. Binary formats encode them as the tuple `(node_id, id,
+/// fingerprint)`. Human-readable formats like JSON encode them as a string of
+/// the form `process:<node_id>:<id>:<fingerprint in hex>`, which also works as
+/// a map key and doesn't lose precision in consumers that can't represent all
+/// `u64`s. Values for external systems can be checked for handles with
+/// [`encode_external`](crate::serializer::encode_external).
 pub struct Process<M, S = Bincode> {
     node_id: u64,
     id: u64,
-    #[serde(skip_serializing, default)]
+    // Fingerprint of `M` and `S` of the handle that was serialized.
+    fingerprint: u64,
     serializer_type: PhantomData<(M, S)>,
 }
 
+/// Error returned from [`Process::checked_cast`] if the handle was created
+/// with a different message type or serializer.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the process handle doesn't match `{expected}`")]
+pub struct TypeMismatch {
+    /// Name of the handle type that was expected.
+    pub expected: &'static str,
+}
+
 impl<M, S> Process<M, S> {
     pub(crate) fn new(node_id: u64, process_id: u64) -> Self {
         Self {
             node_id,
             id: process_id,
+            fingerprint: Self::fingerprint(),
             serializer_type: PhantomData,
         }
     }
 
+    /// Returns a handle to the process `id` on node `node_id`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing checks that the process expects messages of type `M`,
+    /// serialized with `S`. Sending a message of the wrong type results in a
+    /// deserialization failure on the receiving side.
+    pub unsafe fn from_id(node_id: u64, id: u64) -> Self {
+        Self::new(node_id, id)
+    }
+
+    /// Turns the handle into one with a different message type or serializer,
+    /// if they match the ones of the handle that was serialized.
+    ///
+    /// Locally created handles always carry the fingerprint of their own type
+    /// parameters. For a deserialized handle, it's the fingerprint of the
+    /// handle on the sending side.
+    pub fn checked_cast<M2, S2>(self) -> Result<Process<M2, S2>, TypeMismatch> {
+        if self.fingerprint != Process::<M2, S2>::fingerprint() {
+            return Err(TypeMismatch {
+                expected: type_name::<Process<M2, S2>>(),
+            });
+        }
+        Ok(Process::new(self.node_id, self.id))
+    }
+
+    fn fingerprint() -> u64 {
+        fingerprint(&[type_name::<M>(), type_name::<S>()])
+    }
+
     /// Return reference to self.
     pub(crate) fn this() -> Self {
         Self::new(node_id(), process_id())
@@ -245,6 +307,48 @@ impl<M, S> Process<M, S> {
         T::spawn(capture, entry, Some(tag), Some(config), None)
     }
 
+    /// Spawn a process with an explicit list of captured values.
+    ///
+    /// The captured values are passed as a tuple (e.g. `(a, b, c)`) and each of
+    /// them is serialized separately. See the [`capture`](crate::function::capture)
+    /// module for more details.
+    pub fn spawn_with<C>(capture: C, entry: fn(C, Mailbox<M, S>)) -> Self
+    where
+        C: CaptureList<S>,
+        S: CanSerialize<M>,
+    {
+        capture::spawn(capture, entry, None, None, None)
+    }
+
+    /// Spawn a linked process with an explicit list of captured values.
+    pub fn spawn_link_with<C>(capture: C, entry: fn(C, Mailbox<M, S>)) -> Self
+    where
+        C: CaptureList<S>,
+        S: CanSerialize<M>,
+    {
+        capture::spawn(capture, entry, Some(Tag::new()), None, None)
+    }
+
+    /// Spawn a process with an explicit list of captured values, that are
+    /// serialized with `CS` instead of the mailbox serializer.
+    pub fn spawn_with_serializer<CS, C>(capture: C, entry: fn(C, Mailbox<M, S>)) -> Self
+    where
+        C: CaptureList<CS>,
+        S: CanSerialize<M>,
+    {
+        capture::spawn(capture, entry, None, None, None)
+    }
+
+    /// Spawn a linked process with an explicit list of captured values, that
+    /// are serialized with `CS` instead of the mailbox serializer.
+    pub fn spawn_link_with_serializer<CS, C>(capture: C, entry: fn(C, Mailbox<M, S>)) -> Self
+    where
+        C: CaptureList<CS>,
+        S: CanSerialize<M>,
+    {
+        capture::spawn(capture, entry, Some(Tag::new()), None, None)
+    }
+
     /// Returns a local node process ID.
     pub fn id(&self) -> u64 {
         self.id
@@ -273,36 +377,48 @@ impl<M, S> Process<M, S> {
         unsafe { host::api::process::kill(self.id) };
     }
 
+    /// Enables tracing of this process, sending events to `collector`, or
+    /// disables it if `collector` is `None`.
+    ///
+    /// The process picks up the change the next time it waits on a message.
+    /// See [`trace`](crate::trace) for more details.
+    pub fn trace(&self, collector: Option<Process<TraceEvent>>) {
+        crate::trace::send_control(self.node_id, self.id, collector);
+    }
+
+    /// Asks the process for its debug information, waiting up to `timeout`
+    /// for the answer.
+    ///
+    /// The process answers the next time it waits on a message. Returns
+    /// `None` if it doesn't answer in time. See [`debug`](crate::debug) for
+    /// more details.
+    pub fn debug_info(&self, timeout: Duration) -> Option<DebugInfo> {
+        if self.node_id == host::node_id() && self.id == host::process_id() {
+            return Some(crate::debug::debug_info());
+        }
+        let tag = Tag::new();
+        crate::debug::send_request(self.node_id, self.id, Process::this(), tag);
+        unsafe { Mailbox::<DebugInfo>::new() }
+            .tag_receive_timeout(&[tag], timeout)
+            .ok()
+    }
+
     /// Register process under a name.
     pub fn register(&self, name: &str) {
         // Encode type information in name
-        let name = format!(
-            "{} + Process + {}/{}",
-            name,
-            std::any::type_name::<M>(),
-            std::any::type_name::<S>()
-        );
+        let name = format!("{} + Process + {:016x}", name, Self::fingerprint());
         unsafe { host::api::registry::put(name.as_ptr(), name.len(), self.node_id, self.id) };
     }
 
     /// Look up a process.
     pub fn lookup(name: &str) -> Option<Self> {
-        let name = format!(
-            "{} + Process + {}/{}",
-            name,
-            std::any::type_name::<M>(),
-            std::any::type_name::<S>()
-        );
+        let name = format!("{} + Process + {:016x}", name, Self::fingerprint());
         let mut id = 0;
         let mut node_id = 0;
         let result =
             unsafe { host::api::registry::get(name.as_ptr(), name.len(), &mut node_id, &mut id) };
         if result == 0 {
-            Some(Self {
-                node_id,
-                id,
-                serializer_type: PhantomData,
-            })
+            Some(Self::new(node_id, id))
         } else {
             None
         }
@@ -320,12 +436,42 @@ where
     /// This function will panic if the received message can't be serialized
     /// into `M` with serializer `S`.
     pub fn send(&self, message: M) {
+        self.try_send(message).unwrap();
+    }
+
+    /// Send a message to the process, or return an error if it can't be
+    /// serialized.
+    ///
+    /// Resources like a [`TcpStream`](crate::net::TcpStream) can only be sent
+    /// to processes on the same node. They fail with
+    /// [`EncodeError::LocalResource`] if the process is on another node, and
+    /// the message isn't sent.
+    pub fn try_send(&self, message: M) -> Result<(), EncodeError> {
         // Create new message.
         unsafe { host::api::message::create_data(Tag::none().id(), 0) };
         // During serialization resources will add themselves to the message.
-        S::encode(&message).unwrap();
+        serializer::encode_for::<M, S>(self.node_id, &message)?;
         // Send it!
         host::send(self.node_id, self.id);
+        Ok(())
+    }
+
+    /// Send a message to the process through the priority lane.
+    ///
+    /// The receiving [`Mailbox`] returns priority messages before all other
+    /// messages, so they can be used for control messages that shouldn't wait
+    /// behind a long queue of regular ones. Messages within each lane keep the
+    /// order in which they arrive.
+    ///
+    /// Regular messages are only returned once there are no priority messages
+    /// left. A steady stream of priority messages starves the regular lane.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the received message can't be serialized
+    /// into `M` with serializer `S`.
+    pub fn send_priority(&self, message: M) {
+        self.tag_send(Tag::from(PRIORITY_TAG), message);
     }
 
     /// Send a message to the process after the specified duration has passed.
@@ -340,9 +486,7 @@ where
         // During serialization resources will add themselves to the message.
         S::encode(&message).unwrap();
         // Send it!
-        let timer_id =
-            unsafe { host::api::timer::send_after(self.id, duration.as_millis() as u64) };
-        TimerRef::new(timer_id)
+        time::send_after(self.id, duration)
     }
 
     /// Send message to process with a specific tag.
@@ -355,7 +499,7 @@ where
         // Create new message.
         unsafe { host::api::message::create_data(tag.id(), 0) };
         // During serialization resources will add themselves to the message.
-        S::encode(&message).unwrap();
+        serializer::encode_for::<M, S>(self.node_id, &message).unwrap();
         // Send it!
         host::send(self.node_id, self.id);
     }
@@ -373,9 +517,7 @@ where
         // During serialization resources will add themselves to the message.
         S::encode(&message).unwrap();
         // Send it!
-        let timer_id =
-            unsafe { host::api::timer::send_after(self.id, duration.as_millis() as u64) };
-        TimerRef::new(timer_id)
+        time::send_after(self.id, duration)
     }
 
     /// Sends message and waits on response until timeout (if specified).
@@ -404,7 +546,58 @@ where
     {
         unsafe { host::api::message::create_data(send_tag.id(), 0) };
 
-        S::encode(&message).unwrap();
+        serializer::encode_for::<M, S>(self.node_id, &message).unwrap();
+        self.receive_response(receive_tag, timeout)
+    }
+
+    /// Sends a request of an [`AbstractProcess`](crate::AbstractProcess),
+    /// with the return address of the current process and the `deadline` in
+    /// front of it.
+    ///
+    /// The serializer of the process is used for the request, independent of
+    /// `M`.
+    pub(crate) fn request_send<R>(&self, send_tag: Tag, request: &R, deadline: Option<u64>)
+    where
+        S: CanSerialize<R>,
+    {
+        unsafe { host::api::message::create_data(send_tag.id(), 0) };
+        crate::ap::messages::write_request_header(deadline);
+        serializer::encode_for::<R, S>(self.node_id, request).unwrap();
+        host::send(self.node_id, self.id);
+    }
+
+    /// Same as [`request_send`](Self::request_send), but waits on the response
+    /// until timeout (if specified), like
+    /// [`tag_send_receive`](Self::tag_send_receive).
+    #[track_caller]
+    pub(crate) fn request_send_receive<R, Response>(
+        &self,
+        send_tag: Tag,
+        receive_tag: Tag,
+        request: &R,
+        timeout: Option<Duration>,
+        deadline: Option<u64>,
+    ) -> MailboxResult<Response>
+    where
+        S: CanSerialize<R>,
+        S: CanSerialize<Response>,
+    {
+        unsafe { host::api::message::create_data(send_tag.id(), 0) };
+        crate::ap::messages::write_request_header(deadline);
+        serializer::encode_for::<R, S>(self.node_id, request).unwrap();
+        self.receive_response(receive_tag, timeout)
+    }
+
+    /// Sends the message in the buffer and waits on the response.
+    #[track_caller]
+    fn receive_response<Response>(
+        &self,
+        receive_tag: Tag,
+        timeout: Option<Duration>,
+    ) -> MailboxResult<Response>
+    where
+        S: CanSerialize<Response>,
+    {
         let timeout_ms = match timeout {
             Some(timeout) => timeout.as_millis() as u64,
             None => u64::MAX,
@@ -455,9 +648,87 @@ impl<M, S> Clone for Process<M, S> {
         Self {
             node_id: self.node_id,
             id: self.id,
+            fingerprint: self.fingerprint,
             serializer_type: self.serializer_type,
         }
     }
 }
 
 impl<M, S> Copy for Process<M, S> {}
+
+impl<M, S> Serialize for Process<M, S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        crate::serializer::handle(type_name::<Self>())?;
+        if serializer.is_human_readable() {
+            let handle = format!(
+                "process:{}:{}:{:016x}",
+                self.node_id, self.id, self.fingerprint
+            );
+            serializer.serialize_str(&handle)
+        } else {
+            (self.node_id, self.id, self.fingerprint).serialize(serializer)
+        }
+    }
+}
+
+impl<'de, M, S> Deserialize<'de> for Process<M, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (node_id, id, fingerprint) = if deserializer.is_human_readable() {
+            deserializer.deserialize_any(HandleVisitor)?
+        } else {
+            deserializer.deserialize_tuple(3, HandleVisitor)?
+        };
+        Ok(Process {
+            node_id,
+            id,
+            fingerprint,
+            serializer_type: PhantomData,
+        })
+    }
+}
+
+// Reads both representations of a handle, see the `Process` docs.
+struct HandleVisitor;
+
+impl<'de> serde::de::Visitor<'de> for HandleVisitor {
+    type Value = (u64, u64, u64);
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a process handle")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, handle: &str) -> Result<Self::Value, E> {
+        let parse = || {
+            let mut parts = handle.strip_prefix("process:")?.split(':');
+            let node_id = parts.next()?.parse().ok()?;
+            let id = parts.next()?.parse().ok()?;
+            let fingerprint = u64::from_str_radix(parts.next()?, 16).ok()?;
+            parts.next().is_none().then_some((node_id, id, fingerprint))
+        };
+        parse().ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(handle), &self))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut next = |index| -> Result<u64, A::Error> {
+            match seq.next_element()? {
+                Some(value) => Ok(value),
+                None => Err(serde::de::Error::invalid_length(index, &"a process handle")),
+            }
+        };
+        Ok((next(0)?, next(1)?, next(2)?))
+    }
+}
+
+/// Returns a fingerprint of the type `names`, that is the same on all nodes.
+pub(crate) fn fingerprint(names: &[&str]) -> u64 {
+    // 64-bit FNV-1a, the std hashers don't guarantee stable results.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in names.join("/").bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
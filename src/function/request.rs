@@ -0,0 +1,93 @@
+//! Request/response on top of plain processes.
+//!
+//! A process receiving [`Request`]s can answer each of them with
+//! [`Request::reply`], the response is routed back to the caller of
+//! [`Process::request`] with a tag that is unique to the request. Responses
+//! arriving after the caller stopped waiting are never mistaken for the
+//! response of a later request.
+//!
+//! # Example
+//!
+//! ```
+//! let doubler = Process::spawn((), |_, mailbox: Mailbox<Request<u64, u64>>| loop {
+//!     let request = mailbox.receive();
+//!     let value = *request.message();
+//!     request.reply(value * 2);
+//! });
+//! assert_eq!(doubler.request(21, None), Ok(42));
+//! ```
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::serializer::{Bincode, CanSerialize};
+use crate::time::Timeout;
+use crate::{Process, Tag};
+
+/// A request that expects a response of type `Resp`.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "Req: Serialize", deserialize = "Req: Deserialize<'de>"))]
+pub struct Request<Req, Resp, S = Bincode> {
+    message: Req,
+    tag: Tag,
+    reply_to: Process<Resp, S>,
+}
+
+impl<Req, Resp, S> Request<Req, Resp, S>
+where
+    S: CanSerialize<Resp>,
+{
+    /// Returns the request message.
+    pub fn message(&self) -> &Req {
+        &self.message
+    }
+
+    /// Returns the process waiting on the response.
+    pub fn sender(&self) -> Process<Resp, S> {
+        self.reply_to
+    }
+
+    /// Sends the `response` back to the caller.
+    pub fn reply(self, response: Resp) {
+        self.reply_to.tag_send(self.tag, response);
+    }
+}
+
+impl<Req, Resp, S> std::fmt::Debug for Request<Req, Resp, S>
+where
+    Req: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Request")
+            .field("message", &self.message)
+            .field("tag", &self.tag)
+            .field("reply_to", &self.reply_to)
+            .finish()
+    }
+}
+
+impl<Req, Resp, S> Process<Request<Req, Resp, S>, S>
+where
+    S: CanSerialize<Request<Req, Resp, S>> + CanSerialize<Resp>,
+{
+    /// Sends a request to the process and waits on the response.
+    ///
+    /// If a timeout is specified the function will only block for the timeout
+    /// period before returning `Err(Timeout)`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the request can't be serialized or the
+    /// response can't be deserialized.
+    #[track_caller]
+    pub fn request(&self, message: Req, timeout: Option<Duration>) -> Result<Resp, Timeout> {
+        let tag = Tag::for_reply();
+        let request = Request {
+            message,
+            tag,
+            reply_to: Process::this(),
+        };
+        Timeout::from_result(unsafe { self.tag_send_receive(Tag::none(), tag, request, timeout) })
+    }
+}
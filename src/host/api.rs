@@ -15,16 +15,20 @@ pub mod message {
         pub fn create_data(tag: i64, capacity: u64);
         pub fn write_data(data: *const u8, data_len: usize) -> usize;
         pub fn read_data(data: *mut u8, data_len: usize) -> usize;
-        #[allow(dead_code)]
         pub fn seek_data(position: u64);
         pub fn get_tag() -> i64;
-        #[allow(dead_code)]
         pub fn data_size() -> u64;
+        #[doc(hidden)]
         pub fn push_module(module_id: u64) -> u64;
+        #[doc(hidden)]
         pub fn take_module(index: u64) -> u64;
+        #[doc(hidden)]
         pub fn push_tcp_stream(tcp_stream_id: u64) -> u64;
+        #[doc(hidden)]
         pub fn take_tcp_stream(index: u64) -> u64;
+        #[doc(hidden)]
         pub fn push_tls_stream(tls_stream_id: u64) -> u64;
+        #[doc(hidden)]
         pub fn take_tls_stream(index: u64) -> u64;
         pub fn send(process_id: u64) -> u32;
         pub fn send_receive_skip_search(process_id: u64, wait_on_tag: i64, timeout: u64) -> u32;
@@ -286,6 +290,13 @@ pub mod wasi {
         pub fn config_add_command_line_argument(config_id: u64, key: *const u8, key_len: usize);
         pub fn config_preopen_dir(config_id: u64, key: *const u8, key_len: usize);
     }
+
+    // The host provides this function to every process, independent of the
+    // WASI permissions in its config.
+    #[link(wasm_import_module = "wasi_snapshot_preview1")]
+    extern "C" {
+        pub fn random_get(buf: *mut u8, buf_len: usize) -> u16;
+    }
 }
 
 #[allow(clashing_extern_declarations)]
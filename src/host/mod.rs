@@ -1,10 +1,34 @@
 //! Low level lunatic VM syscalls.
+//!
+//! The raw bindings in [`api`] are unsafe and used by the rest of the crate.
+//! Everything they provide is also available through safe functions, e.g.
+//! [`registry`](crate::registry) for the registry and
+//! [`serializer::message_size`](crate::serializer::message_size) for the
+//! message buffer, so applications don't need to call them.
 
 pub mod api;
 
 use crate::module::{params_to_vec, Param, WasmModule};
 use crate::{LunaticError, ProcessConfig, Tag};
 
+/// Spawn flag indicating that the new process is owned by a test.
+const SPAWN_OWNED: i32 = 1;
+/// Spawn flag indicating that the new process should report panics to the
+/// parent.
+const SPAWN_REPORT_PANIC: i32 = 2;
+/// Spawn flag indicating that the new process inherits the parent's panic
+/// hook.
+const SPAWN_INHERIT_HOOK: i32 = 4;
+/// Spawn flag indicating that the new process inherits the parent's logger.
+const SPAWN_INHERIT_LOGGER: i32 = 8;
+/// Spawn flag indicating that the new process receives its memory limit.
+const SPAWN_MEMORY_LIMIT: i32 = 16;
+/// Spawn flag indicating that the new process inherits the parent's configs.
+const SPAWN_APP_CONFIG: i32 = 32;
+/// Spawn flag indicating that the new process runs on the parent's virtual
+/// clock.
+const SPAWN_VIRTUAL_TIME: i32 = 64;
+
 /// Performs the low level dance that will turn a high level rust function into
 /// a lunatic process.
 ///
@@ -24,7 +48,61 @@ pub(crate) fn spawn(
     arg: i32,
 ) -> Result<u64, LunaticError> {
     let entry = entry as usize as i32;
-    let params = params_to_vec(&[Param::I32(entry), Param::I32(arg)]);
+    if let Some(node_id) = node.filter(|&node_id| !crate::distributed::is_reachable(node_id)) {
+        return Err(LunaticError::NotConnected { node_id });
+    }
+    // Processes spawned locally during tests are owned by the same test.
+    let owner = match node {
+        Some(_) => None,
+        None => crate::test::test_owner(),
+    };
+    // Local linked processes report panics if the parent is catching link failures.
+    let report_link = match (node, link) {
+        (None, Some(tag)) if crate::panic::reports_link_panics() => Some(tag),
+        _ => None,
+    };
+    // The hook is passed as an index into the function table of the module,
+    // it's only valid in processes running an instance of the parent's module.
+    // Local spawns always use the parent's module, processes on other nodes
+    // don't inherit the hook.
+    let same_module = node.is_none();
+    let hook = if same_module {
+        crate::panic::inherited_hook()
+    } else {
+        None
+    };
+    // The logger is also inherited by processes on other nodes.
+    #[cfg(feature = "logger")]
+    let logger = crate::logger::inherited();
+    #[cfg(not(feature = "logger"))]
+    let logger: Option<()> = None;
+    // A new config sets the memory limit, otherwise the parent's limit is inherited.
+    let memory_limit = match (node, config) {
+        (Some(_), _) => None,
+        (None, Some(config)) => Some(config.get_max_memory()),
+        (None, None) => crate::memory::limit(),
+    };
+    // Configs are inherited by processes on all nodes.
+    let app_config = crate::config_data::inherited();
+    // Virtual time only covers processes on the local node.
+    let clock = match node {
+        Some(_) => None,
+        None => crate::virtual_time::inherited(),
+    };
+    let flags = (owner.is_some() as i32 * SPAWN_OWNED)
+        | (report_link.is_some() as i32 * SPAWN_REPORT_PANIC)
+        | (hook.is_some() as i32 * SPAWN_INHERIT_HOOK)
+        | (logger.is_some() as i32 * SPAWN_INHERIT_LOGGER)
+        | (memory_limit.is_some() as i32 * SPAWN_MEMORY_LIMIT)
+        | (app_config.is_some() as i32 * SPAWN_APP_CONFIG)
+        | (clock.is_some() as i32 * SPAWN_VIRTUAL_TIME);
+    let hook = hook.map_or(0, |hook| hook as usize as i32);
+    let params = params_to_vec(&[
+        Param::I32(entry),
+        Param::I32(arg),
+        Param::I32(flags),
+        Param::I32(hook),
+    ]);
     let mut id = 0;
     let func = concat!("_lunatic_spawn_by_index_", env!("CARGO_PKG_VERSION"));
     let link = match link {
@@ -59,6 +137,25 @@ pub(crate) fn spawn(
     };
 
     if result == 0 {
+        if let Some(owner) = owner {
+            crate::test::send_test_owner(id, owner);
+        }
+        if let Some(tag) = report_link {
+            crate::panic::send_link_parent(id, tag);
+        }
+        if let Some(limit) = memory_limit {
+            crate::memory::send_limit(id, limit);
+        }
+        #[cfg(feature = "logger")]
+        if let Some(logger) = logger {
+            crate::logger::send_inherited(node.unwrap_or_else(node_id), id, logger);
+        }
+        if let Some(configs) = app_config {
+            crate::config_data::send_inherited(node.unwrap_or_else(node_id), id, configs);
+        }
+        if let Some(clock) = clock {
+            crate::virtual_time::send_inherited(id, clock);
+        }
         Ok(id)
     } else {
         Err(LunaticError::from(id))
@@ -69,7 +166,32 @@ pub(crate) fn spawn(
 /// multiple dependencies use different versions of this crate. See:
 /// https://github.com/lunatic-solutions/lunatic-rs/issues/71
 #[export_name = concat!("_lunatic_spawn_by_index_", env!("CARGO_PKG_VERSION"))]
-extern "C" fn _lunatic_spawn_by_index(function: i32, arg: i32) {
+extern "C" fn _lunatic_spawn_by_index(function: i32, arg: i32, flags: i32, hook: i32) {
+    // Only set by `spawn` for processes running the parent's module.
+    if flags & SPAWN_INHERIT_HOOK != 0 {
+        let hook: fn(&crate::panic::CrashReport) = unsafe { std::mem::transmute(hook as usize) };
+        crate::panic::install_inherited_hook(hook);
+    }
+    if flags & SPAWN_MEMORY_LIMIT != 0 {
+        crate::memory::receive_limit();
+    }
+    #[cfg(feature = "logger")]
+    if flags & SPAWN_INHERIT_LOGGER != 0 {
+        crate::logger::adopt_inherited();
+    }
+    if flags & SPAWN_APP_CONFIG != 0 {
+        crate::config_data::adopt_inherited();
+    }
+    if flags & SPAWN_OWNED != 0 {
+        crate::test::adopt_test_owner();
+    }
+    if flags & SPAWN_REPORT_PANIC != 0 {
+        crate::panic::report_panics_to_parent();
+    }
+    // Adopted last, the messages from the parent are received on real time.
+    if flags & SPAWN_VIRTUAL_TIME != 0 {
+        crate::virtual_time::adopt_inherited();
+    }
     let function: fn(i32) = unsafe { std::mem::transmute(function as usize) };
     function(arg);
 }
@@ -82,20 +204,34 @@ pub fn node_id() -> u64 {
     unsafe { api::distributed::node_id() }
 }
 
+/// Returns the version of the lunatic runtime, as `(major, minor, patch)`.
+pub fn version() -> (u32, u32, u32) {
+    unsafe {
+        (
+            api::version::major(),
+            api::version::minor(),
+            api::version::patch(),
+        )
+    }
+}
+
 pub fn send(node: u64, process_id: u64) {
     if node_id() == node {
-        unsafe { api::message::send(process_id) }
+        crate::dead_letter::send(process_id)
     } else {
-        unsafe { api::distributed::send(node, process_id) }
-    };
+        crate::distributed::send(node, process_id);
+    }
 }
 
 pub fn send_receive_skip_search(node: u64, process_id: u64, wait_on_tag: i64, timeout: u64) -> u32 {
+    // The timeout is measured by the virtual clock.
+    if crate::virtual_time::is_enabled() {
+        send(node, process_id);
+        return crate::virtual_time::receive(&[wait_on_tag], timeout);
+    }
     if node_id() == node {
         unsafe { api::message::send_receive_skip_search(process_id, wait_on_tag, timeout) }
     } else {
-        unsafe {
-            api::distributed::send_receive_skip_search(node, process_id, wait_on_tag, timeout)
-        }
+        crate::distributed::send_receive_skip_search(node, process_id, wait_on_tag, timeout)
     }
 }
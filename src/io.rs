@@ -0,0 +1,311 @@
+//! Line based input that doesn't block the process reading it, and byte
+//! streams between processes.
+//!
+//! Waiting on stdin blocks the process, so it can't handle any other messages
+//! in the meantime. [`stdin_lines`] moves the reading into a dedicated process
+//! that forwards each line over a [`channel`](crate::channel). A REPL or admin
+//! console can combine user input with other events by polling the
+//! [`Receiver`] with [`recv_timeout`](Receiver::recv_timeout) between them.
+//!
+//! Once the input reaches EOF, the reader process finishes and the receiver is
+//! closed. Dropping the receiver stops the reader at the next line.
+//!
+//! # Example
+//!
+//! ```
+//! let input = lunatic::io::stdin_lines();
+//! while let Ok(line) = input.recv() {
+//!     println!("> {}", line);
+//! }
+//! ```
+//!
+//! [`MailboxStream::pipe`] connects two processes with a stream of bytes. The
+//! writing half implements [`Write`](std::io::Write) and can be sent to
+//! another process, the reading half implements [`Read`](std::io::Read). Code
+//! written against the std I/O traits, like protocol parsers or compressors,
+//! works over it unchanged.
+//!
+//! ```
+//! let (writer, mut reader) = MailboxStream::pipe();
+//! Process::spawn(writer, |mut writer, _: Mailbox<()>| {
+//!     writer.write_all(b"hello").unwrap();
+//! });
+//! let mut received = String::new();
+//! reader.read_to_string(&mut received)?;
+//! ```
+
+use std::cell::Cell;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::ap::{AbstractProcess, Config};
+use crate::channel::{self, Receiver, Sender};
+use crate::fs::File;
+use crate::serializer::Bincode;
+use crate::{Mailbox, Process};
+
+/// Returns the lines of stdin, read by a process linked to the current one.
+///
+/// Line endings (`\n` or `\r\n`) are removed. Only one reader of stdin should
+/// exist at a time, as each line goes to one of them.
+pub fn stdin_lines() -> Receiver<String, Bincode> {
+    spawn_reader(Input::Stdin)
+}
+
+/// Returns the lines of `file`, read by a process linked to the current one.
+///
+/// Reading starts at the current offset of the file.
+pub fn file_lines(file: File) -> Receiver<String, Bincode> {
+    spawn_reader(Input::File(file))
+}
+
+fn spawn_reader(input: Input) -> Receiver<String, Bincode> {
+    let (sender, receiver) = channel::channel();
+    Process::spawn_link((input, sender), reader_process);
+    receiver
+}
+
+/// A process reading stdin that can be started under a
+/// [`Supervisor`](crate::supervisor::Supervisor).
+///
+/// The lines are sent to the [`Sender`] passed as argument. The reading
+/// itself happens in a linked process, if it fails the `StdinReader` fails
+/// too and can be restarted.
+///
+/// The supervisor keeps a clone of the argument to restart the process, so
+/// the channel isn't closed at EOF while the supervisor is running.
+pub struct StdinReader;
+
+impl AbstractProcess for StdinReader {
+    type Arg = Sender<String, Bincode>;
+    type State = Self;
+    type Serializer = Bincode;
+    type Handlers = ();
+    type StartupError = ();
+
+    fn init(_: Config<Self>, sender: Sender<String, Bincode>) -> Result<Self, ()> {
+        Process::spawn_link((Input::Stdin, sender), reader_process);
+        Ok(StdinReader)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Input {
+    Stdin,
+    File(File),
+}
+
+fn reader_process((input, sender): (Input, Sender<String, Bincode>), _: Mailbox<()>) {
+    let lines: Box<dyn Iterator<Item = std::io::Result<String>>> = match input {
+        Input::Stdin => Box::new(std::io::stdin().lock().lines()),
+        Input::File(file) => Box::new(BufReader::new(file).lines()),
+    };
+    for line in lines {
+        let Ok(line) = line else {
+            break;
+        };
+        // The receiver was dropped.
+        if sender.send(line).is_err() {
+            break;
+        }
+    }
+    // Dropping the sender closes the channel.
+}
+
+/// Size of the chunks sent by a [`MailboxStreamWriter`], if no other size is
+/// set.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks that can be in flight between the halves of a pipe, if no
+/// other capacity is set.
+const DEFAULT_CAPACITY: usize = 4;
+
+/// The reading half of a pipe between two processes, see
+/// [`MailboxStream::pipe`].
+///
+/// Reading returns the bytes written to the [`MailboxStreamWriter`] in the
+/// same order. Once the writer is dropped and all bytes are read, reading
+/// returns EOF. Like the [`Receiver`] of a channel, the reader should stay in
+/// the process that created the pipe.
+pub struct MailboxStream {
+    receiver: Receiver<Chunk, Bincode>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl MailboxStream {
+    /// Creates a pipe, returning its writing and reading half.
+    ///
+    /// The bytes are sent in chunks of 64 KiB, and at most 4 chunks can be
+    /// in flight. A writer that gets ahead of the reader blocks.
+    pub fn pipe() -> (MailboxStreamWriter, MailboxStream) {
+        MailboxStream::pipe_with_capacity(DEFAULT_CAPACITY, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Creates a pipe that sends chunks of `chunk_size` bytes, with at most
+    /// `capacity` chunks in flight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn pipe_with_capacity(
+        capacity: usize,
+        chunk_size: usize,
+    ) -> (MailboxStreamWriter, MailboxStream) {
+        assert!(chunk_size > 0, "the chunk size must be at least 1");
+        let (sender, receiver) = channel::sync_channel(capacity);
+        let writer = MailboxStreamWriter {
+            sender,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            transferred: Cell::new(false),
+        };
+        let reader = MailboxStream {
+            receiver,
+            chunk: Vec::new(),
+            position: 0,
+        };
+        (writer, reader)
+    }
+}
+
+impl Read for MailboxStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(Chunk(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                // The writer was dropped.
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// The writing half of a pipe between two processes, see
+/// [`MailboxStream::pipe`].
+///
+/// Written bytes are collected into chunks, a chunk is sent once it's full or
+/// the writer is flushed. Dropping the writer sends the remaining bytes and
+/// closes the pipe. The writer can be sent to another process, the bytes
+/// waiting in it are sent along.
+pub struct MailboxStreamWriter {
+    sender: Sender<Chunk, Bincode>,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    transferred: Cell<bool>,
+}
+
+impl MailboxStreamWriter {
+    fn send_chunk(&mut self) -> io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.chunk_size));
+        self.sender
+            .send(Chunk(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the reader was dropped"))
+    }
+}
+
+impl Write for MailboxStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.chunk_size {
+            self.send_chunk()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send_chunk()
+    }
+}
+
+impl Drop for MailboxStreamWriter {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, the reader is gone anyway.
+        if !self.transferred.get() {
+            let _ = self.flush();
+        }
+    }
+}
+
+impl Serialize for MailboxStreamWriter {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        // The deserialized writer takes over the remaining bytes.
+        self.transferred.set(true);
+        (&self.sender, self.chunk_size, Chunk(self.buffer.clone())).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MailboxStreamWriter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (sender, chunk_size, Chunk(mut buffer)) = Deserialize::deserialize(deserializer)?;
+        buffer.reserve(chunk_size - buffer.len());
+        Ok(MailboxStreamWriter {
+            sender,
+            chunk_size,
+            buffer,
+            transferred: Cell::new(false),
+        })
+    }
+}
+
+impl fmt::Debug for MailboxStreamWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailboxStreamWriter")
+            .field("sender", &self.sender)
+            .field("chunk_size", &self.chunk_size)
+            .field("buffered", &self.buffer.len())
+            .finish()
+    }
+}
+
+/// Bytes sent through a pipe.
+///
+/// They are serialized as one byte string, serde serializes a plain `Vec<u8>`
+/// byte by byte.
+struct Chunk(Vec<u8>);
+
+impl Serialize for Chunk {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Chunk {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(ChunkVisitor)
+    }
+}
+
+struct ChunkVisitor;
+
+impl<'de> Visitor<'de> for ChunkVisitor {
+    type Value = Chunk;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Chunk, E> {
+        Ok(Chunk(bytes.to_vec()))
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, bytes: Vec<u8>) -> Result<Chunk, E> {
+        Ok(Chunk(bytes))
+    }
+}
@@ -97,6 +97,12 @@ directory for examples.
 
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
+// Unit tests use the `#[lunatic_test::test]` macro, which refers to this crate
+// as `lunatic`.
+#[cfg(test)]
+extern crate self as lunatic;
+
+mod collect;
 mod config;
 mod error;
 mod macros;
@@ -104,52 +110,73 @@ mod mailbox;
 mod module;
 mod process_local;
 mod tag;
+mod termination;
+mod virtual_time;
 
 pub mod ap;
+pub mod cache;
+pub mod channel;
+pub mod compat;
+pub mod config_data;
+pub mod context;
+pub mod crash_dump;
+pub mod dead_letter;
+pub mod debug;
+pub mod dedup;
 pub mod distributed;
+pub mod fs;
+pub mod fuel;
 pub mod function;
 pub mod host;
+pub mod io;
+#[cfg(feature = "logger")]
+#[cfg_attr(docsrs, doc(cfg(feature = "logger")))]
+pub mod logger;
+pub mod memory;
+pub mod message;
 pub mod metrics;
 pub mod net;
 pub mod panic;
+pub mod pool;
 pub mod protocol;
+pub mod pubsub;
+pub mod random;
+pub mod registry;
+pub mod resource;
+pub mod routing;
+pub mod scope;
+#[doc(hidden)]
+pub mod select;
 pub mod serializer;
+pub mod shutdown;
+pub mod sqlite;
 pub mod supervisor;
+pub mod sync;
+pub mod task;
 #[doc(hidden)]
 pub mod test;
 pub mod time;
+pub mod trace;
 
 pub use ap::AbstractProcess;
+pub use channel::{channel, sync_channel};
+pub use collect::{spawn_collect, spawn_collect_limit, TaskError};
 pub use config::ProcessConfig;
-pub use error::LunaticError;
-pub use function::process::Process;
+pub use error::{HostError, LunaticError};
+pub use function::process::{Process, TypeMismatch};
 pub use lunatic_macros::{abstract_process, main};
 pub use lunatic_test::test;
-pub use mailbox::{Mailbox, MailboxResult};
+#[doc(hidden)]
+pub use mailbox::MainMailbox as __MainMailbox;
+pub use mailbox::{Catching, Mailbox, MailboxError, MailboxResult};
 pub use module::{Param, WasmModule};
 #[doc(hidden)]
 pub use process_local::statik::Key as __StaticProcessLocalInner;
 pub use process_local::ProcessLocal;
 pub use tag::Tag;
-
-// temporary until merged,
-// discussed here: https://github.com/lunatic-solutions/lunatic/pull/160
-pub mod sqlite {
-    #[link(wasm_import_module = "lunatic::sqlite")]
-    extern "C" {
-        pub fn open(path: *const u8, path_len: usize, conn_id: *mut u32) -> u64;
-        pub fn query_prepare(
-            conn_id: u64,
-            query_str: *const u8,
-            query_str_len: u32,
-            len_ptr: *mut u32,
-            resource_id: *mut u32,
-        ) -> ();
-        pub fn query_result_get(resource_id: u64, write_buf: *const u8, write_buf_len: u32) -> ();
-        pub fn drop_query_result(resource_id: u64) -> ();
-        pub fn execute(conn_id: u64, exec_str: *const u8, exec_str_len: u32) -> u32;
-    }
-}
+pub use termination::exit;
+#[doc(hidden)]
+pub use termination::MainResult as __MainResult;
 
 /// Implemented for all resources held by the VM.
 pub trait Resource {
@@ -165,6 +192,15 @@ pub trait Resource {
 }
 
 /// Suspends the current process for `duration` of time.
+///
+/// The host sleeps with millisecond precision, so `duration` is rounded up to
+/// the next millisecond. See [`time::sleep_until`] for periodic loops. In tests
+/// with virtual time, the process sleeps for exactly `duration` of virtual
+/// time.
 pub fn sleep(duration: std::time::Duration) {
-    unsafe { host::api::process::sleep_ms(duration.as_millis() as u64) };
+    if virtual_time::is_enabled() {
+        virtual_time::sleep(duration);
+    } else {
+        unsafe { host::api::process::sleep_ms(time::millis_ceil(duration)) };
+    }
 }
@@ -0,0 +1,191 @@
+//! A [`log`] implementation for lunatic processes.
+//!
+//! Each process has its own memory, and with that its own global logger. The
+//! logger from this module forwards records as messages to a single sink
+//! process, so that output from many processes isn't interleaved. Each record
+//! is tagged with the id of the process that emitted it.
+//!
+//! The logger only needs to be initialized once, processes spawned afterwards
+//! inherit it from their parent. This includes processes spawned on other
+//! nodes, their records are sent back to the same sink.
+//!
+//! # Example
+//!
+//! ```
+//! #[lunatic::main]
+//! fn main(_: Mailbox<()>) {
+//!     lunatic::logger::init(LevelFilter::Info).unwrap();
+//!     Process::spawn((), |_, _: Mailbox<()>| {
+//!         log::info!("hello from a child process");
+//!     });
+//! }
+//! ```
+
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::time::SystemTime;
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::{Deserialize, Serialize};
+
+use crate::time::{self, format_rfc3339};
+use crate::{host, process_local, Mailbox, Process, Tag};
+
+/// Tag of the message carrying the logger setup to a newly spawned process.
+const LOGGER_TAG: i64 = 10;
+
+process_local! {
+    static SINK: Cell<Option<Process<LogRecord>>> = Cell::new(None);
+    static LEVEL: Cell<LevelFilter> = Cell::new(LevelFilter::Off);
+}
+
+/// A log record forwarded to the sink process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Wall-clock time at which the record was emitted.
+    pub timestamp: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Node of the process that emitted the record.
+    pub node_id: u64,
+    /// Process that emitted the record.
+    pub process_id: u64,
+    pub message: String,
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<5} [{}.{}] {}: {}",
+            format_rfc3339(self.timestamp),
+            self.level,
+            self.node_id,
+            self.process_id,
+            self.target,
+            self.message
+        )
+    }
+}
+
+struct ProcessLogger;
+
+static LOGGER: ProcessLogger = ProcessLogger;
+
+impl Log for ProcessLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LEVEL.get()
+    }
+
+    fn log(&self, record: &Record) {
+        // Filtered records are never sent, so that they don't flood the sink.
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let Some(sink) = SINK.get() else {
+            return;
+        };
+        sink.send(LogRecord {
+            timestamp: time::now(),
+            level: record.level(),
+            target: record.target().to_owned(),
+            module_path: record.module_path().map(str::to_owned),
+            file: record.file().map(str::to_owned),
+            line: record.line(),
+            node_id: host::node_id(),
+            process_id: host::process_id(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Spawns a collector process writing records to stderr and installs the
+/// logger in the current process.
+///
+/// Records above `level` are discarded by the emitting process. The collector
+/// writes records in the order it receives them. Records of a single process
+/// are always received in the order they were emitted.
+///
+/// Returns an error if another logger is already installed in the current
+/// process. It's fine to call this function multiple times, the old collector
+/// keeps running but only receives records from processes that were spawned
+/// before.
+pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+    install()?;
+    configure(Process::spawn((), collector_process), level);
+    Ok(())
+}
+
+/// Installs the logger in the current process, forwarding records to `sink`.
+///
+/// See [`init`] for more details.
+pub fn init_with_sink(sink: Process<LogRecord>, level: LevelFilter) -> Result<(), SetLoggerError> {
+    install()?;
+    configure(sink, level);
+    Ok(())
+}
+
+/// Changes the maximum level of records sent from the current process.
+///
+/// Processes spawned afterwards inherit the new level.
+pub fn set_level(level: LevelFilter) {
+    LEVEL.set(level);
+    log::set_max_level(level);
+}
+
+/// Returns the sink process used by the current process, if the logger is
+/// installed.
+pub fn sink() -> Option<Process<LogRecord>> {
+    SINK.get()
+}
+
+fn install() -> Result<(), SetLoggerError> {
+    if SINK.get().is_none() {
+        log::set_logger(&LOGGER)?;
+    }
+    Ok(())
+}
+
+fn configure(sink: Process<LogRecord>, level: LevelFilter) {
+    SINK.set(Some(sink));
+    set_level(level);
+}
+
+fn collector_process(_: (), mailbox: Mailbox<LogRecord>) {
+    loop {
+        let record = mailbox.receive();
+        // Write errors are ignored, a closed stderr shouldn't bring the collector down.
+        let _ = writeln!(std::io::stderr().lock(), "{record}");
+    }
+}
+
+/// Returns the logger setup that processes spawned from the current one
+/// should inherit.
+pub(crate) fn inherited() -> Option<(Process<LogRecord>, LevelFilter)> {
+    SINK.get().map(|sink| (sink, LEVEL.get()))
+}
+
+// Sends the logger setup to a newly spawned process.
+pub(crate) fn send_inherited(
+    node_id: u64,
+    process_id: u64,
+    setup: (Process<LogRecord>, LevelFilter),
+) {
+    let child = Process::<(Process<LogRecord>, LevelFilter)>::new(node_id, process_id);
+    child.tag_send(Tag::from(LOGGER_TAG), setup);
+}
+
+// Called at the start of a process that inherits the logger from its parent.
+pub(crate) fn adopt_inherited() {
+    let (sink, level) = unsafe { Mailbox::<(Process<LogRecord>, LevelFilter)>::new() }
+        .tag_receive(&[Tag::from(LOGGER_TAG)]);
+    // A fresh process doesn't have a logger installed yet.
+    let _ = install();
+    configure(sink, level);
+}
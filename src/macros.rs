@@ -109,6 +109,8 @@ macro_rules! spawn {
 /// // Protocol, capture local_var
 /// let local_var = "Hello".to_owned();
 /// spawn_link!(|local_var, _proto: Protocol<End>| assert_eq!(local_var, "Hello"));
+/// // Protocol with a custom serializer
+/// spawn_link!(|_proto: Protocol<End, MessagePack>| {});
 /// // Background process with config
 /// let config = ProcessConfig::new().unwrap();
 /// spawn_link!(&config, || {});
@@ -206,19 +208,136 @@ macro_rules! spawn_link {
     };
 
     // A protocol that is not capturing any variables.
-    ($(&$config:ident,)? |$protocol:ident : Protocol<$proto_ty:ty>| $body:expr) => {
+    ($(&$config:ident,)? |$protocol:ident : Protocol<$proto_ty:ty $( , $proto_s:ty )?>| $body:expr) => {
         lunatic::spawn_link_config!(@link $($config)?) (
             $(&$config,)?
             (),
-            |_, $protocol: lunatic::protocol::Protocol<$proto_ty>| $body,
+            |_, $protocol: lunatic::protocol::Protocol<$proto_ty $( , $proto_s )?>| $body,
         )
     };
     // A protocol capturing variable `$argument`.
-    ($(&$config:ident,)? |$argument:ident, $protocol:ident : Protocol<$proto_ty:ty>| $body:expr) => {
+    ($(&$config:ident,)? |$argument:ident, $protocol:ident : Protocol<$proto_ty:ty $( , $proto_s:ty )?>| $body:expr) => {
         lunatic::spawn_link_config!(@link $($config)?) (
             $(&$config,)?
             $argument,
-            |mut $argument, $protocol: lunatic::protocol::Protocol<$proto_ty>| $body,
+            |mut $argument, $protocol: lunatic::protocol::Protocol<$proto_ty $( , $proto_s )?>| $body,
         )
     };
 }
+
+/// Waits on multiple sources of messages and runs the arm of the one that
+/// fires first.
+///
+/// Each arm can be one of:
+/// - `pattern = mailbox.tag_receive(tags) => body` - A message with one of the
+///   `tags` arrived in the `mailbox`. If the mailbox is catching link failures,
+///   the pattern matches a [`MailboxResult`](crate::MailboxResult).
+/// - `pattern = protocol.receive() => body` - A value was received from the
+///   protocol session. The pattern matches a tuple of the resulting session and
+///   the value. The `protocol` is moved into the arm.
+/// - `after duration => body` - No message arrived before the `duration`
+///   expired. At most one such arm can be present.
+///
+/// All sources are waited on with a single receive, messages not matching any
+/// of the arms stay untouched in the mailbox.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use lunatic::{select, Mailbox, Tag};
+///
+/// let mailbox = unsafe { Mailbox::<String>::new() };
+/// let reply_tag = Tag::new();
+/// let shutdown_tag = Tag::new();
+/// select! {
+///     reply = mailbox.tag_receive(&[reply_tag]) => println!("Reply: {}", reply),
+///     _ = mailbox.tag_receive(&[shutdown_tag]) => println!("Shutdown"),
+///     after Duration::from_secs(1) => println!("Tick"),
+/// }
+/// ```
+#[macro_export]
+macro_rules! select {
+    // Timeout arm.
+    (@arms ($($idents:ident)*) [$($prelude:tt)*] [$($branches:tt)*] []
+        after $duration:expr => $body:expr $(, $($rest:tt)*)?) => {
+        lunatic::select!(@arms ($($idents)*) [$($prelude)*] [$($branches)*] [($duration) ($body)] $($($rest)*)?)
+    };
+    (@arms ($($idents:ident)*) [$($prelude:tt)*] [$($branches:tt)*] [$($timeout:tt)+]
+        after $duration:expr => $body:expr $(, $($rest:tt)*)?) => {
+        compile_error!("`select!` can't contain more than one `after` arm")
+    };
+    // Mailbox arm.
+    (@arms ($all:ident $received:ident $tag:ident) [$($prelude:tt)*] [$($branches:tt)*] [$($timeout:tt)*]
+        $pat:pat = $mailbox:ident . tag_receive ( $tags:expr $(,)? ) => $body:expr $(, $($rest:tt)*)?) => {
+        lunatic::select!(@arms ($all $received $tag)
+            [
+                $($prelude)*
+                let __tags: &[lunatic::Tag] = $tags;
+                $all.extend_from_slice(__tags);
+            ]
+            [
+                $($branches)*
+                if __tags.contains(&$tag) {
+                    let $pat = lunatic::select::SelectMailbox::decode(&$mailbox, $received);
+                    $body
+                } else
+            ]
+            [$($timeout)*]
+            $($($rest)*)?
+        )
+    };
+    // Protocol arm.
+    (@arms ($all:ident $received:ident $tag:ident) [$($prelude:tt)*] [$($branches:tt)*] [$($timeout:tt)*]
+        $pat:pat = $protocol:ident . receive ( ) => $body:expr $(, $($rest:tt)*)?) => {
+        lunatic::select!(@arms ($all $received $tag)
+            [
+                $($prelude)*
+                let __tags = lunatic::select::SelectProtocol::tags(&$protocol);
+                $all.extend_from_slice(&__tags);
+            ]
+            [
+                $($branches)*
+                if __tags.contains(&$tag) {
+                    let $pat = lunatic::select::SelectProtocol::decode($protocol, $tag);
+                    $body
+                } else
+            ]
+            [$($timeout)*]
+            $($($rest)*)?
+        )
+    };
+    // All arms are parsed.
+    (@arms ($all:ident $received:ident $tag:ident) [$($prelude:tt)*] [$($branches:tt)*] [$(($duration:expr) ($timeout:expr))?]) => {
+        {
+            let mut $all: Vec<lunatic::Tag> = Vec::new();
+            $($prelude)*
+            match lunatic::select::receive(&$all, lunatic::select!(@timeout $($duration)?)) {
+                Some(($received, $tag)) => {
+                    let _ = $received;
+                    $($branches)*
+                    {
+                        unreachable!("`select!` received a message not matching any arm")
+                    }
+                }
+                None => lunatic::select!(@timed_out $($timeout)?),
+            }
+        }
+    };
+    (@timeout) => { None };
+    (@timeout $duration:expr) => { Some($duration) };
+    (@timed_out) => { unreachable!("`select!` without an `after` arm timed out") };
+    (@timed_out $timeout:expr) => { $timeout };
+    (@arms ($($idents:ident)*) [$($prelude:tt)*] [$($branches:tt)*] [$($timeout:tt)*] $($rest:tt)+) => {
+        compile_error!(concat!(
+            "invalid `select!` arm: `", stringify!($($rest)+), "`, expected one of: ",
+            "`pattern = mailbox.tag_receive(tags) => body`, ",
+            "`pattern = protocol.receive() => body`, ",
+            "`after duration => body`"
+        ))
+    };
+    ($($arms:tt)+) => {
+        lunatic::select!(@arms (__all __received __tag) [] [] [] $($arms)+)
+    };
+}
@@ -2,6 +2,8 @@ use std::fmt;
 use std::marker::PhantomData;
 use std::time::Duration;
 
+use thiserror::Error;
+
 use crate::function::process::{IntoProcess, NoLink};
 use crate::host::api::message;
 use crate::host::{self};
@@ -11,6 +13,9 @@ use crate::{Process, ProcessConfig, Tag};
 pub const LINK_DIED: u32 = 1;
 pub const TIMEOUT: u32 = 9027;
 
+/// Tag of messages sent with [`Process::send_priority`].
+pub(crate) const PRIORITY_TAG: i64 = 13;
+
 /// Marker type indicating that the [`Mailbox`] **IS** catching deaths of linked
 /// processes.
 pub struct Catching;
@@ -30,11 +35,37 @@ pub struct Catching;
 /// [`try_receive`](./struct.Mailbox.html#method.try_receive) can be used. It
 /// will not panic in case it can't deserialize the message buffer.
 ///
-/// ## Message ordering
+/// ## Delivery guarantees
+///
+/// Between processes on the same node, messaging follows these rules, which
+/// are checked by the `delivery` conformance tests:
+///
+/// - **Per-sender FIFO.** Messages sent by one process to another one are
+///   received in the order they were sent. There is no ordering between
+///   messages of different senders.
+/// - **Exactly once.** A message sent to a running process is received at most
+///   once, and isn't lost while the process is alive. Messages sent to a
+///   process that doesn't exist are handed to the
+///   [dead letter handler](crate::dead_letter) instead.
+/// - **Tagged receives.** A receive filtered by tags takes the oldest matching
+///   message. The messages it skips keep their order for later receives.
+/// - **Failed deserialization.** A message that can't be deserialized is still
+///   taken out of the mailbox, it's returned as
+///   [`MailboxResult::DeserializationFailed`] or causes a panic, depending on
+///   the receive. The messages after it aren't affected.
+/// - **Link deaths.** The [`MailboxResult::LinkDied`] of a linked process is
+///   received after all messages the process sent before it died.
+///
+/// Messages that take part in the implementation of the library, e.g. panic
+/// reports of linked processes or tracing, are filtered out by the receive
+/// functions and don't count against their timeout.
 ///
-/// Lunatic guarantees that messages sent between two processes will arrive in
-/// the same order they were sent. Ordering is not guaranteed if more than two
-/// processes are involved.
+/// ## Priority messages
+///
+/// Messages sent with [`Process::send_priority`] are returned before all
+/// other messages, in the order they arrived. Receives filtered by tags don't
+/// look at the priority lane first. Priority messages of one sender are
+/// ordered among themselves, but not relative to its regular messages.
 ///
 /// ## Link deaths
 ///
@@ -89,6 +120,7 @@ where
     /// This function returns a [`Mailbox`] that will get
     /// [`MailboxResult::LinkDied`]  messages every time a linked process dies.
     pub fn catch_link_failure(self) -> Mailbox<M, S, Catching> {
+        crate::panic::report_link_panics(true);
         unsafe {
             host::api::process::die_when_link_dies(0);
             Mailbox::<M, S, Catching>::new()
@@ -159,19 +191,7 @@ where
 
     fn receive_(&self, tags: &[Tag], timeout: Option<Duration>) -> MailboxResult<M> {
         let tags: Vec<i64> = tags.iter().map(|tag| tag.id()).collect();
-        let timeout_ms = match timeout {
-            Some(timeout) => timeout.as_millis() as u64,
-            None => u64::MAX,
-        };
-        let message_type = unsafe { message::receive(tags.as_ptr(), tags.len(), timeout_ms) };
-        match message_type {
-            LINK_DIED => MailboxResult::LinkDied(unsafe { Tag::from(message::get_tag()) }),
-            TIMEOUT => MailboxResult::TimedOut,
-            _ => match S::decode() {
-                Ok(msg) => MailboxResult::Message(msg),
-                Err(err) => MailboxResult::DeserializationFailed(err),
-            },
-        }
+        decode_received::<M, S>(wait(&tags, timeout))
     }
 
     /// Create a mailbox with a specific type.
@@ -189,6 +209,114 @@ where
     }
 }
 
+/// Waits for the next message tagged with one of `tags`, or any message if
+/// `tags` is empty, and returns its type.
+///
+/// The message is left in the scratch buffer. Messages used by the crate
+/// itself, e.g. tracing and config updates, are handled on the way.
+pub(crate) fn wait(tags: &[i64], timeout: Option<Duration>) -> u32 {
+    crate::memory::check();
+    crate::trace::finish();
+    crate::metrics::wait_started();
+    // Messages filtered out below don't restart the timeout.
+    let deadline = timeout.map(|timeout| crate::time::instant() + timeout);
+    loop {
+        let timeout_ms = match deadline {
+            Some(deadline) => deadline
+                .saturating_duration_since(crate::time::instant())
+                .as_millis() as u64,
+            None => u64::MAX,
+        };
+        let message_type = match receive_priority(tags) {
+            Some(message_type) => message_type,
+            None => crate::virtual_time::receive(tags, timeout_ms),
+        };
+        if message_type == LINK_DIED || message_type == TIMEOUT {
+            return message_type;
+        }
+        let tag = unsafe { message::get_tag() };
+        if tag == crate::trace::TRACE_TAG {
+            crate::trace::handle_control();
+        } else if tag == crate::debug::DEBUG_INFO_TAG {
+            crate::debug::handle_request();
+        } else if crate::ap::pending::discard_abandoned(tag) {
+            // A late reply to a request that nobody waits on anymore.
+        } else if tag == crate::panic::LINK_PANIC_TAG && crate::panic::reports_link_panics() {
+            // Panic reports from linked processes are kept for `panic::link_panic`.
+            crate::panic::store_link_panic();
+        } else if tag == crate::sync::CANCELLED_TAG {
+            // Cancellations are kept for `CancellationToken::is_cancelled`.
+            crate::sync::store_cancellation();
+        } else if tag == crate::config_data::UPDATE_TAG {
+            // Config updates are kept for `AppConfig::get`.
+            crate::config_data::store_update();
+        } else {
+            return message_type;
+        }
+    }
+}
+
+/// Decodes the message that [`wait`] returned the type of.
+pub(crate) fn decode_received<M, S>(message_type: u32) -> MailboxResult<M>
+where
+    S: CanSerialize<M>,
+{
+    match message_type {
+        LINK_DIED => MailboxResult::LinkDied(unsafe { Tag::from(message::get_tag()) }),
+        TIMEOUT => MailboxResult::TimedOut,
+        _ => {
+            crate::trace::received::<M>();
+            crate::metrics::received(std::any::type_name::<M>());
+            crate::crash_dump::record(std::any::type_name::<M>());
+            match crate::ap::self_send::decode::<M, S>() {
+                Ok(msg) => MailboxResult::Message(msg),
+                Err(err) => MailboxResult::DeserializationFailed(err),
+            }
+        }
+    }
+}
+
+/// Creates the mailbox of the root process, used by the `main` macro.
+///
+/// A [`Catching`] mailbox also sets up the process to catch link failures.
+#[doc(hidden)]
+pub trait MainMailbox {
+    /// ### Safety
+    ///
+    /// Same as [`Mailbox::new`].
+    unsafe fn main_mailbox() -> Self;
+}
+
+impl<M, S> MainMailbox for Mailbox<M, S, ()>
+where
+    S: CanSerialize<M>,
+{
+    unsafe fn main_mailbox() -> Self {
+        Mailbox::new()
+    }
+}
+
+impl<M, S> MainMailbox for Mailbox<M, S, Catching>
+where
+    S: CanSerialize<M>,
+{
+    unsafe fn main_mailbox() -> Self {
+        Mailbox::<M, S>::new().catch_link_failure()
+    }
+}
+
+// Takes the oldest priority message out of the mailbox, if there is one and
+// the receive isn't filtered by tags. Returns the message type.
+fn receive_priority(tags: &[i64]) -> Option<u32> {
+    if !tags.is_empty() {
+        return None;
+    }
+    match unsafe { message::receive(&PRIORITY_TAG, 1, 0) } {
+        TIMEOUT => None,
+        message_type => Some(message_type),
+    }
+}
+
 impl<M, S, L> Clone for Mailbox<M, S, L>
 where
     S: CanSerialize<M>,
@@ -216,6 +344,17 @@ where
 }
 
 /// Result of a `recieve*` call on a [`Mailbox`].
+///
+/// It can be turned into a `Result` with [`into_result`](Self::into_result) or
+/// [`ok_or`](Self::ok_or), so that timeouts and link deaths can be propagated
+/// with `?`:
+///
+/// ```
+/// fn next_job(mailbox: Mailbox<Job>) -> Result<Job, MailboxError> {
+///     let job = mailbox.receive_timeout(Duration::from_secs(5)).into_result()?;
+///     Ok(job)
+/// }
+/// ```
 #[derive(Debug)]
 pub enum MailboxResult<T> {
     Message(T),
@@ -224,14 +363,75 @@ pub enum MailboxResult<T> {
     LinkDied(Tag),
 }
 
+/// Error of a `receive*` call that didn't return a message.
+#[derive(Error, Debug)]
+pub enum MailboxError {
+    #[error("the message couldn't be deserialized: {0}")]
+    DeserializationFailed(#[from] DecodeError),
+    #[error("timed out waiting on a message")]
+    TimedOut,
+    /// A linked process died.
+    #[error("linked process died (tag: {0:?})")]
+    LinkDied(Tag),
+}
+
 impl<T> MailboxResult<T> {
     #[track_caller]
     pub fn unwrap(self) -> T {
+        match self.into_result() {
+            Ok(msg) => msg,
+            Err(MailboxError::DeserializationFailed(err)) => panic!("{:?}", err),
+            Err(MailboxError::TimedOut) => panic!("TimedOut"),
+            Err(MailboxError::LinkDied(_)) => panic!("LinkDied"),
+        }
+    }
+
+    /// Returns the message, or panics with `msg` and the reason if there is
+    /// no message.
+    #[track_caller]
+    pub fn expect_message(self, msg: &str) -> T {
+        match self.into_result() {
+            Ok(message) => message,
+            Err(err) => panic!("{msg}: {err}"),
+        }
+    }
+
+    /// Converts the result into a `Result`, keeping the reason if there is no
+    /// message.
+    pub fn into_result(self) -> Result<T, MailboxError> {
+        match self {
+            MailboxResult::Message(msg) => Ok(msg),
+            MailboxResult::DeserializationFailed(err) => {
+                Err(MailboxError::DeserializationFailed(err))
+            }
+            MailboxResult::TimedOut => Err(MailboxError::TimedOut),
+            MailboxResult::LinkDied(tag) => Err(MailboxError::LinkDied(tag)),
+        }
+    }
+
+    /// Returns the message, or `err` if there is no message.
+    pub fn ok_or<E>(self, err: E) -> Result<T, E> {
+        match self {
+            MailboxResult::Message(msg) => Ok(msg),
+            _ => Err(err),
+        }
+    }
+
+    /// Returns the message, if there is one.
+    pub fn ok(self) -> Option<T> {
+        match self {
+            MailboxResult::Message(msg) => Some(msg),
+            _ => None,
+        }
+    }
+
+    /// Maps the message with `f`, leaving the other variants untouched.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> MailboxResult<U> {
         match self {
-            MailboxResult::Message(msg) => msg,
-            MailboxResult::DeserializationFailed(err) => panic!("{:?}", err),
-            MailboxResult::TimedOut => panic!("TimedOut"),
-            MailboxResult::LinkDied(_) => panic!("LinkDied"),
+            MailboxResult::Message(msg) => MailboxResult::Message(f(msg)),
+            MailboxResult::DeserializationFailed(err) => MailboxResult::DeserializationFailed(err),
+            MailboxResult::TimedOut => MailboxResult::TimedOut,
+            MailboxResult::LinkDied(tag) => MailboxResult::LinkDied(tag),
         }
     }
 
@@ -251,6 +451,12 @@ impl<T> MailboxResult<T> {
     }
 }
 
+impl<T> From<MailboxResult<T>> for Result<T, MailboxError> {
+    fn from(result: MailboxResult<T>) -> Self {
+        result.into_result()
+    }
+}
+
 impl<M, S> NoLink for Mailbox<M, S> where S: CanSerialize<M> {}
 
 impl<M, S> IntoProcess<M, S> for Mailbox<M, S>
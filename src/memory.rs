@@ -0,0 +1,116 @@
+//! Memory usage of the current process.
+//!
+//! A process that exceeds the memory limit of its
+//! [`ProcessConfig`](crate::ProcessConfig) is killed. Processes like caches can
+//! use the functions in this module to free memory before that happens.
+//!
+//! # Example
+//!
+//! ```
+//! Process::spawn_config(&config, (), |_, mailbox: Mailbox<MemoryPressure>| {
+//!     memory::notify_on_pressure(mailbox.this(), 0.8);
+//!     // ...
+//!     if let MailboxResult::Message(pressure) = mailbox.receive_timeout(Duration::ZERO) {
+//!         cache.evict_half();
+//!     }
+//! });
+//! ```
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{host, process_local, Mailbox, Process, Tag};
+
+/// Size of a WebAssembly memory page.
+const PAGE_SIZE: u64 = 64 * 1024;
+/// Tag of the message carrying the memory limit to a newly spawned process.
+const MEMORY_LIMIT_TAG: i64 = 11;
+
+process_local! {
+    static LIMIT: Cell<Option<u64>> = Cell::new(None);
+    // Process to notify and the threshold in bytes.
+    static PRESSURE: Cell<Option<(Process<MemoryPressure>, u64)>> = Cell::new(None);
+}
+
+/// Message sent when the memory used by a process crosses the threshold set
+/// with [`notify_on_pressure`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryPressure {
+    /// Memory used by the process in bytes.
+    pub used: u64,
+    /// Memory limit of the process in bytes.
+    pub limit: u64,
+}
+
+/// Returns the amount of memory in bytes used by the current process.
+///
+/// This is the size of the linear memory, it never shrinks. Memory freed by
+/// the process is reused by later allocations.
+pub fn used() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    let pages = core::arch::wasm32::memory_size(0) as u64;
+    #[cfg(not(target_arch = "wasm32"))]
+    let pages = 0;
+    pages * PAGE_SIZE
+}
+
+/// Returns the memory limit in bytes of the current process.
+///
+/// The limit is only known for processes spawned with a
+/// [`ProcessConfig`](crate::ProcessConfig), or for processes spawned from them.
+pub fn limit() -> Option<u64> {
+    LIMIT.get()
+}
+
+/// Sends a [`MemoryPressure`] message to `process` once the current process
+/// uses more than `fraction` of its memory limit.
+///
+/// The memory usage is checked every time the current process receives a
+/// message, and with [`check`]. The notification is only sent once, call this
+/// function again to get notified again. Returns `false` if the memory limit
+/// of the current process is unknown.
+///
+/// # Panics
+///
+/// Panics if `fraction` is not between 0 and 1.
+pub fn notify_on_pressure(process: Process<MemoryPressure>, fraction: f64) -> bool {
+    assert!(
+        (0.0..=1.0).contains(&fraction),
+        "The memory pressure fraction must be between 0 and 1"
+    );
+    let Some(limit) = limit() else {
+        return false;
+    };
+    PRESSURE.set(Some((process, (limit as f64 * fraction) as u64)));
+    check();
+    true
+}
+
+/// Checks if the memory usage crossed the threshold set with
+/// [`notify_on_pressure`] and sends the notification.
+pub fn check() {
+    let Some((process, threshold)) = PRESSURE.get() else {
+        return;
+    };
+    let used = used();
+    if used < threshold {
+        return;
+    }
+    PRESSURE.set(None);
+    if let Some(limit) = limit() {
+        process.send(MemoryPressure { used, limit });
+    }
+}
+
+// Sends the memory limit to a newly spawned process.
+pub(crate) fn send_limit(process_id: u64, limit: u64) {
+    let child = Process::<u64>::new(host::node_id(), process_id);
+    child.tag_send(Tag::from(MEMORY_LIMIT_TAG), limit);
+}
+
+// Called at the start of a process that knows about its memory limit.
+pub(crate) fn receive_limit() {
+    let limit = unsafe { Mailbox::<u64>::new() }.tag_receive(&[Tag::from(MEMORY_LIMIT_TAG)]);
+    LIMIT.set(Some(limit));
+}
@@ -0,0 +1,149 @@
+//! Envelopes for messages that travel through multiple processes.
+//!
+//! In a pipeline where the last stage replies to the process that started
+//! the request, each message needs to carry a correlation id and the process
+//! to reply to. An [`Envelope`] holds both next to the payload, together with
+//! the processes it passed through. [`Envelope::forward`] hands it to the next
+//! stage with a new payload, and [`Envelope::reply`] sends the response back,
+//! tagged with the correlation id of the original request.
+//!
+//! Envelopes can be used as regular messages, or as arguments of
+//! [`AbstractProcess`](crate::ap::AbstractProcess) handlers.
+//!
+//! # Example
+//!
+//! ```
+//! let request = Envelope::request(query);
+//! let correlation = request.correlation();
+//! parser.send(request);
+//! // The parser forwards the parsed query to the planner, which replies.
+//! let plan = mailbox.tag_receive(&[correlation]);
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::serializer::CanSerialize;
+use crate::{host, Process, Tag};
+
+/// A process that an [`Envelope`] passed through.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hop {
+    pub node_id: u64,
+    pub process_id: u64,
+}
+
+/// A payload of type `T`, with the correlation id and reply-to process of the
+/// request it belongs to.
+///
+/// Replies of type `R` are sent to the reply-to process.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned"))]
+pub struct Envelope<T, R = ()> {
+    correlation: Tag,
+    reply_to: Option<Process<R>>,
+    hops: Vec<Hop>,
+    payload: T,
+}
+
+impl<T, R> Envelope<T, R> {
+    /// Creates an envelope with a new correlation id, that doesn't expect a
+    /// reply.
+    pub fn new(payload: T) -> Self {
+        Envelope {
+            correlation: Tag::new(),
+            reply_to: None,
+            hops: Vec::new(),
+            payload,
+        }
+    }
+
+    /// Creates an envelope with a new correlation id, that expects a reply to
+    /// the current process.
+    ///
+    /// The reply is tagged with the correlation id and can be received with
+    /// [`Mailbox::tag_receive`](crate::Mailbox::tag_receive).
+    pub fn request(payload: T) -> Self {
+        Envelope::with_reply_to(payload, Process::this())
+    }
+
+    /// Creates an envelope with a new correlation id, that expects a reply to
+    /// `reply_to`.
+    pub fn with_reply_to(payload: T, reply_to: Process<R>) -> Self {
+        Envelope {
+            reply_to: Some(reply_to),
+            ..Envelope::new(payload)
+        }
+    }
+
+    /// Returns the correlation id shared by all envelopes of the request.
+    pub fn correlation(&self) -> Tag {
+        self.correlation
+    }
+
+    /// Returns the process the reply is sent to.
+    pub fn reply_to(&self) -> Option<Process<R>> {
+        self.reply_to
+    }
+
+    /// Returns the processes that forwarded the envelope, starting with the
+    /// first one.
+    pub fn hops(&self) -> &[Hop] {
+        &self.hops
+    }
+
+    pub fn payload(&self) -> &T {
+        &self.payload
+    }
+
+    pub fn payload_mut(&mut self) -> &mut T {
+        &mut self.payload
+    }
+
+    pub fn into_payload(self) -> T {
+        self.payload
+    }
+
+    /// Replaces the payload, keeping the correlation id, the reply-to process
+    /// and the hops.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Envelope<U, R> {
+        Envelope {
+            correlation: self.correlation,
+            reply_to: self.reply_to,
+            hops: self.hops,
+            payload: f(self.payload),
+        }
+    }
+
+    /// Sends `payload` in an envelope of the same request to `next`.
+    ///
+    /// The current process is added to the hops.
+    pub fn forward<U, S>(self, next: &Process<Envelope<U, R>, S>, payload: U)
+    where
+        S: CanSerialize<Envelope<U, R>>,
+    {
+        let mut envelope = self.map(|_| payload);
+        envelope.hops.push(Hop {
+            node_id: host::node_id(),
+            process_id: host::process_id(),
+        });
+        next.send(envelope);
+    }
+
+    /// Sends `response` to the reply-to process, tagged with the correlation
+    /// id.
+    ///
+    /// Returns `false` if the envelope doesn't have a reply-to process.
+    pub fn reply(self, response: R) -> bool
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        match self.reply_to {
+            Some(reply_to) => {
+                reply_to.tag_send(self.correlation, response);
+                true
+            }
+            None => false,
+        }
+    }
+}
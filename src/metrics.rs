@@ -4,30 +4,48 @@
 //! flag to start the exporter
 //!
 //! All this functions are similar to the macros defined in [metrics docs](https://docs.rs/metrics/latest/metrics/index.html#emission)
+//!
+//! [`instrument_mailbox`] adds metrics to the receive path of a process.
+//!
+//! The host doesn't return the values of metrics, [`record_published`] keeps
+//! a copy of the values published by the current process, e.g. for tests.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
 use crate::host::api::metrics;
+use crate::process_local;
 
 /// Sets a counter
 pub fn counter(name: &str, value: u64) {
+    record(name, |_| value as f64);
     unsafe { metrics::counter(name.as_ptr(), name.len(), value) }
 }
 
 /// Increments a counter
 pub fn increment_counter(name: &str) {
+    record(name, |current| current + 1.0);
     unsafe { metrics::increment_counter(name.as_ptr(), name.len()) }
 }
 
 /// Sets a gauge
 pub fn gauge(name: &str, value: f64) {
+    record(name, |_| value);
     unsafe { metrics::gauge(name.as_ptr(), name.len(), value) }
 }
 
 /// Increments a gauge
 pub fn increment_gauge(name: &str, value: f64) {
+    record(name, |current| current + value);
     unsafe { metrics::increment_gauge(name.as_ptr(), name.len(), value) }
 }
 
 /// Decrements a gauge
 pub fn decrement_gauge(name: &str, value: f64) {
+    record(name, |current| current - value);
     unsafe { metrics::decrement_gauge(name.as_ptr(), name.len(), value) }
 }
 
@@ -35,3 +53,154 @@ pub fn decrement_gauge(name: &str, value: f64) {
 pub fn histogram(name: &str, value: f64) {
     unsafe { metrics::histogram(name.as_ptr(), name.len(), value) }
 }
+
+/// Returns `name` with `labels` attached, in the Prometheus text format.
+///
+/// The host API doesn't take labels, so they are encoded into the name.
+pub fn with_labels(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return name.to_owned();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    format!("{}{{{}}}", name, labels.join(","))
+}
+
+/// Counter of received messages, labeled by message type.
+pub const RECEIVED_COUNTER: &str = "lunatic.mailbox.received";
+/// Histogram of the seconds spent waiting on a message, labeled by message
+/// type.
+pub const WAITING_HISTOGRAM: &str = "lunatic.mailbox.waiting";
+/// Histogram of the seconds spent handling a message, labeled by message type.
+pub const HANDLING_HISTOGRAM: &str = "lunatic.mailbox.handling";
+
+process_local! {
+    static INSTRUMENTED: Cell<bool> = Cell::new(false);
+    // Start of the current wait, or of the handling of a message type.
+    static PENDING: Cell<Option<(Instant, Option<&'static str>)>> = Cell::new(None);
+    static STATS: RefCell<HashMap<&'static str, MessageStats>> = RefCell::new(HashMap::new());
+    // Values of counters and gauges, by name with labels.
+    static PUBLISHED: RefCell<Option<HashMap<String, f64>>> = RefCell::new(None);
+}
+
+/// Enables or disables keeping a copy of the counters and gauges published
+/// by the current process, see [`published`].
+///
+/// Disabling it drops the recorded values. Histograms aren't recorded.
+pub fn record_published(enabled: bool) {
+    PUBLISHED.with_borrow_mut(|mut published| match (enabled, published.is_some()) {
+        (true, false) => *published = Some(HashMap::new()),
+        (false, _) => *published = None,
+        _ => (),
+    });
+}
+
+/// Returns the values of the counters and gauges published by the current
+/// process since [`record_published`] was enabled, by name with labels.
+pub fn published() -> HashMap<String, f64> {
+    PUBLISHED.with_borrow(|published| published.clone().unwrap_or_default())
+}
+
+fn record(name: &str, f: impl FnOnce(f64) -> f64) {
+    PUBLISHED.with_borrow_mut(|mut published| {
+        if let Some(published) = published.as_mut() {
+            let value = published.entry(name.to_owned()).or_default();
+            *value = f(*value);
+        }
+    });
+}
+
+/// Receive statistics of one message type, see [`mailbox_stats`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageStats {
+    /// Number of received messages.
+    pub received: u64,
+    /// Total time spent waiting on these messages.
+    pub waiting: Duration,
+    /// Total time spent handling these messages.
+    pub handling: Duration,
+}
+
+/// Enables or disables metrics on the receive path of the current process.
+///
+/// While enabled, each received message increments [`RECEIVED_COUNTER`] and
+/// records the time spent waiting on it and handling it in
+/// [`WAITING_HISTOGRAM`] and [`HANDLING_HISTOGRAM`]. All of them are labeled
+/// with the type name of the message, or of the handler for an
+/// [`AbstractProcess`](crate::ap::AbstractProcess). A message is considered
+/// handled once the process starts waiting on the next one.
+///
+/// The number of labels grows with the number of message types handled by
+/// instrumented processes, so it's disabled by default.
+pub fn instrument_mailbox(enabled: bool) {
+    INSTRUMENTED.set(enabled);
+    PENDING.set(None);
+}
+
+/// Returns `true` if the receive path of the current process is instrumented.
+pub fn is_mailbox_instrumented() -> bool {
+    INSTRUMENTED.get()
+}
+
+/// Returns the receive statistics of the current process by message type,
+/// collected while [`instrument_mailbox`] was enabled.
+pub fn mailbox_stats() -> HashMap<String, MessageStats> {
+    STATS.with_borrow(|stats| {
+        stats
+            .iter()
+            .map(|(name, stats)| (name.to_string(), *stats))
+            .collect()
+    })
+}
+
+// Called before the process waits on a message.
+pub(crate) fn wait_started() {
+    if !INSTRUMENTED.get() {
+        return;
+    }
+    match PENDING.get() {
+        // Internal messages restart the wait, keep the original start.
+        Some((_, None)) => (),
+        Some((start, Some(message_type))) => {
+            let handling = start.elapsed();
+            update(message_type, |stats| stats.handling += handling);
+            histogram(
+                &with_labels(HANDLING_HISTOGRAM, &[("message", message_type)]),
+                handling.as_secs_f64(),
+            );
+            PENDING.set(Some((Instant::now(), None)));
+        }
+        None => PENDING.set(Some((Instant::now(), None))),
+    }
+}
+
+// Called after a message of `message_type` was received.
+pub(crate) fn received(message_type: &'static str) {
+    if !INSTRUMENTED.get() {
+        return;
+    }
+    let waiting = match PENDING.get() {
+        Some((start, None)) => start.elapsed(),
+        _ => Duration::ZERO,
+    };
+    update(message_type, |stats| {
+        stats.received += 1;
+        stats.waiting += waiting;
+    });
+    let labels = [("message", message_type)];
+    increment_counter(&with_labels(RECEIVED_COUNTER, &labels));
+    histogram(
+        &with_labels(WAITING_HISTOGRAM, &labels),
+        waiting.as_secs_f64(),
+    );
+    PENDING.set(Some((Instant::now(), Some(message_type))));
+}
+
+fn update(message_type: &'static str, f: impl FnOnce(&mut MessageStats)) {
+    STATS.with_borrow_mut(|mut stats| f(stats.entry(message_type).or_default()));
+}
@@ -0,0 +1,598 @@
+//! A stub resolver that sends DNS queries to chosen nameservers.
+//!
+//! [`resolve`](super::resolve) goes through the resolver of the host, which
+//! picks the nameservers and caches the answers on its own. A [`Resolver`]
+//! sends the queries itself, over UDP to the nameservers it's created with,
+//! and parses the responses. It looks up `A`, `AAAA` and `SRV` records.
+//!
+//! Answers are cached in a process shared by all resolvers of the node, for as
+//! long as the TTL of the records allows. Names that don't exist, or have no
+//! records of the type, are cached for the negative TTL of their zone, see [RFC
+//! 2308]. The cache runs under a supervisor, if it crashes the answers are
+//! queried again.
+//!
+//! Each attempt waits up to the timeout of the resolver on a response, then the
+//! next nameserver is asked. Truncated responses are repeated over TCP to the
+//! same nameserver.
+//!
+//! [RFC 2308]: https://tools.ietf.org/html/rfc2308
+//!
+//! # Example
+//!
+//! ```
+//! let resolver = Resolver::new("10.0.0.2:53".parse()?)
+//!     .with_nameserver("10.0.0.3:53".parse()?)
+//!     .with_timeout(Duration::from_millis(500));
+//! for srv in resolver.lookup_srv("_http._tcp.api.internal")? {
+//!     println!("{}:{}", srv.target, srv.port);
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{TcpStream, UdpSocket};
+use crate::ap::handlers::{Message, Request};
+use crate::ap::{
+    AbstractProcess, Config, MessageHandler, ProcessRef, RequestHandler, StartupError, State,
+};
+use crate::serializer::Bincode;
+use crate::supervisor::{Supervisor, SupervisorConfig};
+use crate::{random, time, Mailbox, MailboxResult, Process, Tag};
+
+/// Name of the cache shared by all resolvers of the node.
+const CACHE_NAME: &str = "lunatic::net::dns::cache";
+/// Name of the supervisor of the cache.
+const SUPERVISOR_NAME: &str = "lunatic::net::dns::supervisor";
+/// How long an attempt waits on a response, if no other timeout is set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often each nameserver is asked, if no other number is set.
+const DEFAULT_ATTEMPTS: u32 = 2;
+/// Largest response sent over UDP, longer ones are truncated.
+const MAX_UDP_SIZE: usize = 512;
+/// Number of cached answers above which the expired ones are dropped.
+const PRUNE_ABOVE: usize = 1024;
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_NAME_ERROR: u8 = 3;
+
+/// Error of a DNS lookup.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DnsError {
+    /// The name doesn't exist, or has no records of the type.
+    #[error("no records found for `{0}`")]
+    NotFound(String),
+    /// The name can't be encoded in a query.
+    #[error("invalid name `{0}`")]
+    InvalidName(String),
+    /// None of the nameservers responded within the timeout.
+    #[error("timed out waiting on the nameservers")]
+    TimedOut,
+    /// The nameserver failed to answer, with the response code.
+    #[error("the nameserver failed with response code {0}")]
+    Server(u8),
+    /// The response couldn't be parsed.
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    /// Sending the query or receiving the response failed.
+    #[error("{0}")]
+    Io(String),
+}
+
+/// A `SRV` record, see [RFC 2782](https://tools.ietf.org/html/rfc2782).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    /// The host of the service, without the trailing dot.
+    pub target: String,
+}
+
+/// Looks up records on a list of nameservers, see the [module
+/// documentation](self).
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    nameservers: Vec<SocketAddr>,
+    timeout: Duration,
+    attempts: u32,
+}
+
+impl Resolver {
+    /// Creates a resolver that sends its queries to `nameserver`.
+    pub fn new(nameserver: SocketAddr) -> Self {
+        Resolver {
+            nameservers: vec![nameserver],
+            timeout: DEFAULT_TIMEOUT,
+            attempts: DEFAULT_ATTEMPTS,
+        }
+    }
+
+    /// Adds a nameserver that is asked if the previous ones don't respond.
+    pub fn with_nameserver(mut self, nameserver: SocketAddr) -> Self {
+        self.nameservers.push(nameserver);
+        self
+    }
+
+    /// Sets how long each attempt waits on a response. Default value is 2
+    /// seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how often each nameserver is asked before the lookup fails.
+    /// Default value is 2.
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Returns the IPv4 addresses of `name`.
+    pub fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        let records = self.lookup(name, TYPE_A)?;
+        Ok(records
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::A(ip) => Some(ip),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the IPv6 addresses of `name`.
+    pub fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        let records = self.lookup(name, TYPE_AAAA)?;
+        Ok(records
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::Aaaa(ip) => Some(ip),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the `SRV` records of `name`, e.g. `_http._tcp.example.com`,
+    /// ordered by their priority.
+    ///
+    /// Records with the same priority keep the order of the response, picking
+    /// one of them by weight is left to the caller.
+    pub fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, DnsError> {
+        let records = self.lookup(name, TYPE_SRV)?;
+        let mut records: Vec<SrvRecord> = records
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::Srv(srv) => Some(srv),
+                _ => None,
+            })
+            .collect();
+        records.sort_by_key(|srv| srv.priority);
+        Ok(records)
+    }
+
+    // Returns the cached answer for the records of `name`, or asks the
+    // nameservers and caches their answer.
+    fn lookup(&self, name: &str, record_type: u16) -> Result<Vec<Record>, DnsError> {
+        let key = Key {
+            nameservers: self.nameservers.clone(),
+            name: name.trim_end_matches('.').to_ascii_lowercase(),
+            record_type,
+        };
+        let cache = shared_cache();
+        if let Some(answer) = cache.request(Get(key.clone())) {
+            return answer;
+        }
+        let (answer, ttl) = match self.query(&key.name, record_type)? {
+            Answer::Records(records, ttl) => (Ok(records), Some(ttl)),
+            Answer::NotFound(ttl) => (Err(DnsError::NotFound(key.name.clone())), ttl),
+        };
+        if let Some(ttl) = ttl.filter(|&ttl| ttl > 0) {
+            cache.send(Put(key, answer.clone(), Duration::from_secs(ttl.into())));
+        }
+        answer
+    }
+
+    // Asks the nameservers in turn until one of them answers.
+    fn query(&self, name: &str, record_type: u16) -> Result<Answer, DnsError> {
+        let id = random::u32() as u16;
+        let query = encode_query(id, name, record_type)?;
+        let mut error = DnsError::TimedOut;
+        for _ in 0..self.attempts {
+            for &nameserver in self.nameservers.iter() {
+                let answer = udp_exchange(nameserver, &query, self.timeout)
+                    .and_then(|response| {
+                        if is_truncated(&response) {
+                            tcp_exchange(nameserver, &query, self.timeout)
+                        } else {
+                            Ok(response)
+                        }
+                    })
+                    .and_then(|response| decode_response(&response, id, record_type));
+                match answer {
+                    Ok(answer) => return Ok(answer),
+                    Err(err) => error = err,
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Srv(SrvRecord),
+}
+
+// The answer of a nameserver, with the TTL it can be cached for.
+enum Answer {
+    Records(Vec<Record>, u32),
+    // Without a TTL if the nameserver didn't send the SOA record of the zone.
+    NotFound(Option<u32>),
+}
+
+// Encodes a recursive query for the records of `name`.
+fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, DnsError> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, no records.
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsError::InvalidName(name.to_owned()));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    if query.len() - 12 > 255 {
+        return Err(DnsError::InvalidName(name.to_owned()));
+    }
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn is_truncated(response: &[u8]) -> bool {
+    response.len() >= 4 && u16::from_be_bytes([response[2], response[3]]) & FLAG_TRUNCATED != 0
+}
+
+// Decodes the response to the query with `id`.
+fn decode_response(response: &[u8], id: u16, record_type: u16) -> Result<Answer, DnsError> {
+    let mut reader = Reader {
+        message: response,
+        position: 0,
+    };
+    if reader.u16()? != id {
+        return Err(invalid("the id doesn't match the query"));
+    }
+    let flags = reader.u16()?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Err(invalid("the message isn't a response"));
+    }
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    let authorities = reader.u16()?;
+    reader.u16()?;
+    let rcode = (flags & 0x000F) as u8;
+    if rcode != 0 && rcode != RCODE_NAME_ERROR {
+        return Err(DnsError::Server(rcode));
+    }
+
+    for _ in 0..questions {
+        reader.name()?;
+        reader.bytes(4)?;
+    }
+    let mut records = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answers {
+        let (kind, record_ttl, mut data) = reader.record()?;
+        // The CNAMEs leading to the records expire with them.
+        if kind == record_type || kind == TYPE_CNAME {
+            ttl = ttl.min(record_ttl);
+        }
+        if kind != record_type {
+            continue;
+        }
+        let record = match kind {
+            TYPE_A => {
+                let ip: [u8; 4] = data.bytes(4)?.try_into().unwrap();
+                Record::A(Ipv4Addr::from(ip))
+            }
+            TYPE_AAAA => {
+                let ip: [u8; 16] = data.bytes(16)?.try_into().unwrap();
+                Record::Aaaa(Ipv6Addr::from(ip))
+            }
+            _ => Record::Srv(SrvRecord {
+                priority: data.u16()?,
+                weight: data.u16()?,
+                port: data.u16()?,
+                target: data.name()?,
+            }),
+        };
+        records.push(record);
+    }
+    if rcode == 0 && !records.is_empty() {
+        return Ok(Answer::Records(records, ttl));
+    }
+
+    // The SOA record of the zone tells how long the name is known to be
+    // missing.
+    for _ in 0..authorities {
+        let (kind, record_ttl, mut data) = reader.record()?;
+        if kind == TYPE_SOA {
+            data.name()?;
+            data.name()?;
+            // Serial, refresh, retry and expire.
+            data.bytes(16)?;
+            let minimum = data.u32()?;
+            return Ok(Answer::NotFound(Some(record_ttl.min(minimum))));
+        }
+    }
+    Ok(Answer::NotFound(None))
+}
+
+fn invalid(reason: &str) -> DnsError {
+    DnsError::InvalidResponse(reason.to_owned())
+}
+
+// Reads the fields of a message, see RFC 1035.
+struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DnsError> {
+        let bytes = self
+            .message
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("the message ends early"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DnsError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, DnsError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    // Reads a name without the trailing dot, following compression pointers.
+    fn name(&mut self) -> Result<String, DnsError> {
+        let mut labels = Vec::new();
+        let mut position = self.position;
+        // Each pointer has to point before the previous one, so that they
+        // can't form a loop.
+        let mut limit = position;
+        let mut end = None;
+        loop {
+            let len = *self
+                .message
+                .get(position)
+                .ok_or_else(|| invalid("the message ends early"))? as usize;
+            if len == 0 {
+                position += 1;
+                break;
+            }
+            if len & 0xC0 == 0xC0 {
+                let low = *self
+                    .message
+                    .get(position + 1)
+                    .ok_or_else(|| invalid("the message ends early"))?
+                    as usize;
+                let target = ((len & 0x3F) << 8) | low;
+                if target >= limit {
+                    return Err(invalid("a name pointer doesn't point back"));
+                }
+                end.get_or_insert(position + 2);
+                limit = target;
+                position = target;
+                continue;
+            }
+            if len > 63 {
+                return Err(invalid("a label is too long"));
+            }
+            let label = self
+                .message
+                .get(position + 1..position + 1 + len)
+                .ok_or_else(|| invalid("the message ends early"))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            position += 1 + len;
+        }
+        self.position = end.unwrap_or(position);
+        Ok(labels.join("."))
+    }
+
+    // Reads a resource record and returns its type, TTL and a reader of its
+    // data.
+    fn record(&mut self) -> Result<(u16, u32, Reader<'a>), DnsError> {
+        self.name()?;
+        let kind = self.u16()?;
+        let _class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        // Names in the data can point to the rest of the message.
+        let data = Reader {
+            message: self.message,
+            position: self.position,
+        };
+        self.bytes(len)?;
+        Ok((kind, ttl, data))
+    }
+}
+
+// Sends the query over UDP and waits on the response.
+//
+// UDP sockets don't have a read timeout, the exchange happens in a process
+// that is killed if it doesn't respond in time.
+fn udp_exchange(
+    nameserver: SocketAddr,
+    query: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, DnsError> {
+    let mailbox = unsafe { Mailbox::<Result<Vec<u8>, DnsError>>::new() };
+    let tag = Tag::new();
+    let exchange = Process::spawn(
+        (mailbox.this(), tag, nameserver, query.to_vec()),
+        udp_exchange_process,
+    );
+    match mailbox.tag_receive_timeout(&[tag], timeout) {
+        MailboxResult::Message(response) => response,
+        _ => {
+            exchange.kill();
+            Err(DnsError::TimedOut)
+        }
+    }
+}
+
+/// Process to reply to, tag of the reply, nameserver and query of an exchange.
+type ExchangeArg = (Process<Result<Vec<u8>, DnsError>>, Tag, SocketAddr, Vec<u8>);
+
+fn udp_exchange_process((parent, tag, nameserver, query): ExchangeArg, _: Mailbox<()>) {
+    let exchange = || -> io::Result<Vec<u8>> {
+        let local = match nameserver {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(nameserver)?;
+        socket.send(&query)?;
+        let mut response = vec![0; MAX_UDP_SIZE];
+        loop {
+            let len = socket.recv(&mut response)?;
+            // Late responses to earlier queries are skipped.
+            if len >= 2 && response[..2] == query[..2] {
+                response.truncate(len);
+                return Ok(response);
+            }
+        }
+    };
+    parent.tag_send(tag, exchange().map_err(io_error));
+}
+
+// Sends the query over TCP, where each message is prefixed with its length.
+fn tcp_exchange(
+    nameserver: SocketAddr,
+    query: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, DnsError> {
+    let exchange = || -> io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(nameserver, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut message = (query.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(query);
+        stream.write_all(&message)?;
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut response)?;
+        Ok(response)
+    };
+    exchange().map_err(io_error)
+}
+
+fn io_error(err: io::Error) -> DnsError {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => DnsError::TimedOut,
+        _ => DnsError::Io(err.to_string()),
+    }
+}
+
+// Returns the cache of the node, and starts it if it isn't running.
+fn shared_cache() -> ProcessRef<DnsCache> {
+    match ProcessRef::<DnsCache>::lookup(CACHE_NAME) {
+        Some(cache) if cache.is_alive() => cache,
+        // The supervisor registers the cache once it started it.
+        _ => match CacheSupervisor::start_as(SUPERVISOR_NAME, ()) {
+            Ok(supervisor) | Err(StartupError::NameAlreadyRegistered(supervisor)) => {
+                supervisor.children().0
+            }
+            Err(err) => panic!("Failed to start the DNS cache: {:?}", err),
+        },
+    }
+}
+
+// Identifies an answer, the same name can have different records on other
+// nameservers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    nameservers: Vec<SocketAddr>,
+    name: String,
+    record_type: u16,
+}
+
+// Holds the answers of all resolvers, until they expire.
+struct DnsCache {
+    answers: HashMap<Key, (Result<Vec<Record>, DnsError>, Instant)>,
+}
+
+impl AbstractProcess for DnsCache {
+    type Arg = ();
+    type State = Self;
+    type Serializer = Bincode;
+    type Handlers = (Request<Get>, Message<Put>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(DnsCache {
+            answers: HashMap::new(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Get(Key);
+impl RequestHandler<Get> for DnsCache {
+    type Response = Option<Result<Vec<Record>, DnsError>>;
+
+    fn handle(mut state: State<Self>, Get(key): Get) -> Self::Response {
+        let (answer, expires) = state.answers.get(&key)?;
+        if *expires > time::instant() {
+            return Some(answer.clone());
+        }
+        state.answers.remove(&key);
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Put(Key, Result<Vec<Record>, DnsError>, Duration);
+impl MessageHandler<Put> for DnsCache {
+    fn handle(mut state: State<Self>, Put(key, answer, ttl): Put) {
+        let now = time::instant();
+        if state.answers.len() >= PRUNE_ABOVE {
+            state.answers.retain(|_, (_, expires)| *expires > now);
+        }
+        state.answers.insert(key, (answer, now + ttl));
+    }
+}
+
+struct CacheSupervisor;
+impl Supervisor for CacheSupervisor {
+    type Arg = ();
+    type Children = (DnsCache,);
+
+    fn init(config: &mut SupervisorConfig<Self>, _: ()) {
+        config.children_args((((), Some(CACHE_NAME.to_owned())),));
+    }
+}
@@ -0,0 +1,279 @@
+use std::cell::Cell;
+use std::io::Result;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::{ControlMessage, TcpListener, TcpStream};
+use crate::shutdown::{self, ShutdownSignal};
+use crate::{Mailbox, MailboxResult, Process, Tag};
+
+/// How often the tracker checks if handlers are still alive while draining.
+const ALIVE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The outcome of [`GracefulListener::begin_drain`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Handlers that finished while draining.
+    pub finished: usize,
+    /// Handlers that were still running at the deadline and got killed.
+    pub killed: usize,
+}
+
+/// A [`TcpListener`] that keeps track of the processes handling its
+/// connections, so they can be drained before the server stops.
+///
+/// Connections are accepted with [`accept`](GracefulListener::accept) and
+/// handled by processes spawned with [`spawn`](GracefulListener::spawn).
+/// [`begin_drain`](GracefulListener::begin_drain) stops accepting new
+/// connections and gives the running handlers time to finish.
+///
+/// The listener is also registered with [`shutdown::on_shutdown`]. Once the
+/// shutdown is initiated, the process that created the listener is killed to
+/// close the listening socket, and the handlers are drained within the grace
+/// period of the shutdown.
+///
+/// # Example
+///
+/// ```
+/// let listener = GracefulListener::new(TcpListener::bind("0.0.0.0:8080")?);
+/// while let Ok((stream, _)) = listener.accept() {
+///     listener.spawn(stream, |stream, _: Mailbox<()>| serve(stream));
+/// }
+/// ```
+pub struct GracefulListener {
+    listener: TcpListener,
+    tracker: Process<TrackerMessage>,
+    drained: Cell<bool>,
+}
+
+impl GracefulListener {
+    pub fn new(listener: TcpListener) -> Self {
+        let tracker = Process::spawn_link(Process::<()>::this(), tracker_process);
+        GracefulListener {
+            listener,
+            tracker,
+            drained: Cell::new(false),
+        }
+    }
+
+    /// Accepts a new incoming connection, see [`TcpListener::accept`].
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        self.listener.accept()
+    }
+
+    /// Returns the local address that this listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Spawns a process running `entry` with `capture`, usually containing
+    /// the accepted [`TcpStream`], and tracks it until it finishes.
+    ///
+    /// The handler isn't linked to the current process. If the current
+    /// process dies without draining, all handlers are killed.
+    pub fn spawn<C>(&self, capture: C, entry: fn(C, Mailbox<()>)) -> Process<()>
+    where
+        C: Serialize + DeserializeOwned,
+    {
+        let handler = Process::spawn(capture, entry);
+        self.tracker.send(TrackerMessage::Track(handler));
+        handler
+    }
+
+    /// Tracks `handler` until it finishes, and sends it
+    /// [`ControlMessage::Drain`] once the drain starts.
+    pub(crate) fn track_controlled(&self, handler: Process<ControlMessage>) {
+        self.tracker.send(TrackerMessage::TrackControlled(handler));
+    }
+
+    pub(crate) fn tracker(&self) -> Process<TrackerMessage> {
+        self.tracker
+    }
+
+    /// Closes the listening socket and waits up to `deadline` for the running
+    /// handlers to finish.
+    ///
+    /// Handlers that are still running after the deadline are killed.
+    pub fn begin_drain(self, deadline: Duration) -> DrainReport {
+        self.drained.set(true);
+        let tracker = self.tracker;
+        // Dropping the listener closes the socket.
+        drop(self);
+        let tag = Tag::new();
+        tracker.send(TrackerMessage::Drain {
+            deadline,
+            waiter: Process::this(),
+            tag,
+        });
+        unsafe { Mailbox::<DrainReport>::new() }.tag_receive(&[tag])
+    }
+}
+
+impl Drop for GracefulListener {
+    fn drop(&mut self) {
+        if !self.drained.get() {
+            self.tracker.send(TrackerMessage::Release);
+        }
+    }
+}
+
+/// Kills the process that owns the listener behind `tracker`, and waits up to
+/// `deadline` for the running handlers to finish.
+pub(crate) fn stop(tracker: Process<TrackerMessage>, deadline: Duration) -> DrainReport {
+    let tag = Tag::new();
+    tracker.send(TrackerMessage::Stop {
+        deadline,
+        waiter: Process::this(),
+        tag,
+    });
+    unsafe { Mailbox::<DrainReport>::new() }.tag_receive(&[tag])
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum TrackerMessage {
+    Track(Process<()>),
+    TrackControlled(Process<ControlMessage>),
+    Drain {
+        deadline: Duration,
+        waiter: Process<DrainReport>,
+        tag: Tag,
+    },
+    // Like `Drain`, but sent by another process than the owner.
+    Stop {
+        deadline: Duration,
+        waiter: Process<DrainReport>,
+        tag: Tag,
+    },
+    Shutdown(ShutdownSignal),
+    // The listener was dropped without draining, the handlers keep running.
+    Release,
+}
+
+/// Where the outcome of a drain goes.
+enum Drain {
+    Waiter(Process<DrainReport>, Tag),
+    Shutdown(ShutdownSignal),
+}
+
+fn tracker_process(owner: Process<()>, mailbox: Mailbox<TrackerMessage>) {
+    let helper = Process::spawn(mailbox.this(), shutdown_process);
+    track(owner, mailbox);
+    helper.kill();
+}
+
+fn track(owner: Process<()>, mailbox: Mailbox<TrackerMessage>) {
+    let mailbox = mailbox.catch_link_failure();
+    // Handlers, and if they receive control messages.
+    let mut handlers: Vec<(Process<()>, bool)> = Vec::new();
+    let mut finished = 0;
+    // Set while draining.
+    let mut drain: Option<(Instant, Drain)> = None;
+    loop {
+        let message = match drain {
+            Some((deadline, _)) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                mailbox.receive_timeout(remaining.min(ALIVE_CHECK_INTERVAL))
+            }
+            None => mailbox.receive(),
+        };
+        match message {
+            MailboxResult::Message(TrackerMessage::Track(handler)) => {
+                handlers.retain(|(handler, _)| handler.is_alive());
+                handlers.push((handler, false));
+            }
+            MailboxResult::Message(TrackerMessage::TrackControlled(handler)) => {
+                handlers.retain(|(handler, _)| handler.is_alive());
+                // Handlers spawned right before the drain started are told right away.
+                if let Some((deadline, _)) = drain {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    handler.send(ControlMessage::Drain(remaining));
+                }
+                handlers.push((Process::new(handler.node_id(), handler.id()), true));
+            }
+            MailboxResult::Message(TrackerMessage::Drain {
+                deadline,
+                waiter,
+                tag,
+            }) if drain.is_none() => {
+                drain = Some((Instant::now() + deadline, Drain::Waiter(waiter, tag)));
+                notify_drain(&handlers, deadline);
+            }
+            MailboxResult::Message(TrackerMessage::Stop {
+                deadline,
+                waiter,
+                tag,
+            }) if drain.is_none() => {
+                close(owner);
+                drain = Some((Instant::now() + deadline, Drain::Waiter(waiter, tag)));
+                notify_drain(&handlers, deadline);
+            }
+            MailboxResult::Message(TrackerMessage::Shutdown(signal)) => {
+                if drain.is_none() {
+                    close(owner);
+                    let deadline = Instant::now() + signal.grace_period();
+                    notify_drain(&handlers, signal.grace_period());
+                    drain = Some((deadline, Drain::Shutdown(signal)));
+                } else {
+                    signal.done();
+                }
+            }
+            MailboxResult::Message(TrackerMessage::Release) => return,
+            // The owner died without draining, take the handlers with it.
+            MailboxResult::LinkDied(_) if drain.is_none() => {
+                for (handler, _) in handlers {
+                    handler.kill();
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        let Some((deadline, _)) = drain else {
+            continue;
+        };
+        let running = handlers.len();
+        handlers.retain(|(handler, _)| handler.is_alive());
+        finished += running - handlers.len();
+        if handlers.is_empty() || Instant::now() >= deadline {
+            for (handler, _) in handlers.iter() {
+                handler.kill();
+            }
+            let report = DrainReport {
+                finished,
+                killed: handlers.len(),
+            };
+            match drain.take().map(|(_, drain)| drain) {
+                Some(Drain::Waiter(waiter, tag)) => waiter.tag_send(tag, report),
+                Some(Drain::Shutdown(signal)) => signal.done(),
+                None => (),
+            }
+            return;
+        }
+    }
+}
+
+// The owner is blocked in `accept`, killing it is the only way to close the
+// listening socket.
+fn close(owner: Process<()>) {
+    owner.unlink();
+    owner.kill();
+}
+
+// Tells the handlers that receive control messages that the drain started.
+fn notify_drain(handlers: &[(Process<()>, bool)], deadline: Duration) {
+    for (handler, _) in handlers.iter().filter(|(_, controlled)| *controlled) {
+        let handler = Process::<ControlMessage>::new(handler.node_id(), handler.id());
+        handler.send(ControlMessage::Drain(deadline));
+    }
+}
+
+/// Forwards the shutdown signal to the tracker.
+fn shutdown_process(tracker: Process<TrackerMessage>, mailbox: Mailbox<ShutdownSignal>) {
+    shutdown::on_shutdown(mailbox.this());
+    let signal = mailbox.receive();
+    tracker.send(TrackerMessage::Shutdown(signal));
+}
@@ -1,20 +1,33 @@
 //! Networking related functions.
 
+pub mod dns;
+mod graceful;
+mod poller;
+mod proxy;
 mod resolver;
+mod server;
+mod shared_listener;
 mod tcp_listener;
 mod tcp_stream;
 mod tls_listener;
 mod tls_stream;
 mod udp;
 
-use std::io::{Error, ErrorKind, Result};
+use std::io::Result;
 use std::iter::Cloned;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::option::IntoIter;
 use std::slice::Iter;
 
+pub use graceful::{DrainReport, GracefulListener};
+pub use poller::{Poller, Readiness, StreamId};
+pub use proxy::{proxy, Direction, ProxyOptions, ProxyStats};
 pub use resolver::{resolve, resolve_timeout, SocketAddrIterator};
-pub use tcp_listener::TcpListener;
+pub use server::{
+    ControlMessage, Server, ServerHandle, ACCEPT_ERRORS_COUNTER, CONNECTIONS_COUNTER,
+};
+pub use shared_listener::SharedListener;
+pub use tcp_listener::{ListenerOptions, TcpListener};
 pub use tcp_stream::TcpStream;
 pub use tls_listener::TlsListener;
 pub use tls_stream::TlsStream;
@@ -139,7 +152,7 @@ impl ToSocketAddrs for &str {
     fn to_socket_addrs(&self) -> Result<Self::Iter> {
         match resolve(self) {
             Ok(iter) => Ok(iter),
-            Err(err) => Err(Error::new(ErrorKind::Other, err)),
+            Err(err) => Err(err.into()),
         }
     }
 }
@@ -150,7 +163,7 @@ impl ToSocketAddrs for String {
     fn to_socket_addrs(&self) -> Result<Self::Iter> {
         match resolve(self) {
             Ok(iter) => Ok(iter),
-            Err(err) => Err(Error::new(ErrorKind::Other, err)),
+            Err(err) => Err(err.into()),
         }
     }
 }
@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind, Read, Result};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::TcpStream;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{Mailbox, MailboxResult};
+
+/// The first wait between two sweeps over the streams.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+/// The longest wait between two sweeps, if no other interval is set.
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Identifies a stream registered with a [`Poller`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId(u64);
+
+impl StreamId {
+    /// Reported by [`Poller::poll_ready`] while messages are waiting, see
+    /// [`Poller::with_mailbox`].
+    pub const MAILBOX: StreamId = StreamId(0);
+}
+
+/// The state of a stream reported by [`Poller::poll_ready`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Readiness {
+    /// Data can be read without waiting.
+    Readable,
+    /// The peer closed the connection, reads return 0.
+    Closed,
+    /// The stream failed.
+    Error(ErrorKind),
+}
+
+/// Waits on many [`TcpStream`]s at once, so that one process can own many
+/// mostly idle connections.
+///
+/// Spawning a process per connection is the simplest model and the right
+/// default. When most of the connections are idle, e.g. websockets, the
+/// memory of a process per connection adds up. A `Poller` lets a single
+/// process [`register`](Poller::register) the streams, wait until some of
+/// them are [ready](Poller::poll_ready), and [`read`](Poller::read) the ready
+/// ones without blocking.
+///
+/// A process that also handles messages creates the poller
+/// [`with_mailbox`](Poller::with_mailbox), so that waiting on the streams
+/// returns as soon as a message arrives.
+///
+/// # Cost
+///
+/// The host doesn't notify processes about readiness yet, the poller checks
+/// the streams itself. Each check peeks every registered stream without
+/// waiting, one host call per stream, and the poller waits between the
+/// checks, starting at 1ms and doubling up to the
+/// [max interval](Poller::set_max_interval). A stream that becomes readable
+/// is noticed with a delay of up to that interval, and the CPU time of a
+/// check grows with the number of streams. Messages wake the poller without
+/// delay.
+///
+/// Registered streams have a zero [peek timeout](TcpStream::set_peek_timeout),
+/// the previous timeout is restored when they are
+/// [deregistered](Poller::deregister). Writes to the streams still wait until
+/// the data is written.
+///
+/// # Example
+///
+/// ```
+/// let mut poller = Poller::with_mailbox(mailbox);
+/// loop {
+///     for (id, readiness) in poller.poll_ready(None) {
+///         match readiness {
+///             Readiness::Readable if id == StreamId::MAILBOX => {
+///                 while let Some(stream) = poller.take_message() {
+///                     poller.register(stream);
+///                 }
+///             }
+///             Readiness::Readable => {
+///                 let len = poller.read(id, &mut buffer)?;
+///                 handle(poller.get_mut(id).unwrap(), &buffer[..len]);
+///             }
+///             _ => drop(poller.deregister(id)),
+///         }
+///     }
+/// }
+/// ```
+pub struct Poller<M = (), S = Bincode>
+where
+    S: CanSerialize<M>,
+{
+    streams: BTreeMap<StreamId, Registered>,
+    next_id: u64,
+    mailbox: Option<Mailbox<M, S>>,
+    messages: VecDeque<M>,
+    max_interval: Duration,
+}
+
+struct Registered {
+    stream: TcpStream,
+    peek_timeout: Option<Duration>,
+}
+
+impl Poller {
+    /// Creates a poller that doesn't watch the mailbox.
+    pub fn new() -> Self {
+        Self::create(None)
+    }
+}
+
+impl Default for Poller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, S> Poller<M, S>
+where
+    S: CanSerialize<M>,
+{
+    /// Creates a poller that also waits on messages of `mailbox`.
+    ///
+    /// Messages that arrive while waiting are kept by the poller until they
+    /// are [taken](Poller::take_message), [`StreamId::MAILBOX`] is reported
+    /// as readable as long as there are messages.
+    pub fn with_mailbox(mailbox: Mailbox<M, S>) -> Self {
+        Self::create(Some(mailbox))
+    }
+
+    fn create(mailbox: Option<Mailbox<M, S>>) -> Self {
+        Poller {
+            streams: BTreeMap::new(),
+            next_id: 1,
+            mailbox,
+            messages: VecDeque::new(),
+            max_interval: DEFAULT_MAX_INTERVAL,
+        }
+    }
+
+    /// Sets the longest wait between two checks of the streams.
+    ///
+    /// Shorter intervals notice readable streams sooner, but cost more CPU
+    /// time while all streams are idle.
+    pub fn set_max_interval(&mut self, interval: Duration) {
+        self.max_interval = interval.max(MIN_INTERVAL);
+    }
+
+    /// Registers `stream` and returns its ID.
+    pub fn register(&mut self, mut stream: TcpStream) -> StreamId {
+        let id = StreamId(self.next_id);
+        self.next_id += 1;
+        let peek_timeout = stream.peek_timeout();
+        let _ = stream.set_peek_timeout(Some(Duration::ZERO));
+        self.streams.insert(
+            id,
+            Registered {
+                stream,
+                peek_timeout,
+            },
+        );
+        id
+    }
+
+    /// Removes the stream `id` and returns it, with its previous peek timeout.
+    pub fn deregister(&mut self, id: StreamId) -> Option<TcpStream> {
+        let Registered {
+            mut stream,
+            peek_timeout,
+        } = self.streams.remove(&id)?;
+        let _ = stream.set_peek_timeout(peek_timeout);
+        Some(stream)
+    }
+
+    /// Returns the stream `id`, e.g. to write to it.
+    pub fn get_mut(&mut self, id: StreamId) -> Option<&mut TcpStream> {
+        self.streams
+            .get_mut(&id)
+            .map(|registered| &mut registered.stream)
+    }
+
+    /// Returns the number of registered streams.
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Returns `true` if no streams are registered.
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Waits until at least one stream is ready or a message arrives, and
+    /// returns the ready streams.
+    ///
+    /// A stream stays ready until all of its data is read. Returns an empty
+    /// list if nothing was ready within `timeout`, `None` waits forever.
+    pub fn poll_ready(&mut self, timeout: Option<Duration>) -> Vec<(StreamId, Readiness)> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut interval = MIN_INTERVAL;
+        loop {
+            let mut ready = self.check();
+            if !self.messages.is_empty() {
+                ready.insert(0, (StreamId::MAILBOX, Readiness::Readable));
+            }
+            if !ready.is_empty() {
+                return ready;
+            }
+            let wait = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return ready;
+                    }
+                    interval.min(remaining)
+                }
+                None => interval,
+            };
+            self.wait(wait);
+            interval = (interval * 2).min(self.max_interval);
+        }
+    }
+
+    /// Reads the data that is available on the stream `id` into `buf`,
+    /// without waiting.
+    ///
+    /// Fails with [`ErrorKind::WouldBlock`] if no data is available, and with
+    /// [`ErrorKind::NotFound`] if the stream isn't registered. Returns 0 if
+    /// the peer closed the connection.
+    pub fn read(&mut self, id: StreamId, buf: &mut [u8]) -> Result<usize> {
+        let stream = self
+            .get_mut(id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "the stream isn't registered"))?;
+        let available = match stream.peek(buf) {
+            Ok(available) => available,
+            Err(err) if err.kind() == ErrorKind::TimedOut => {
+                return Err(Error::new(ErrorKind::WouldBlock, "no data available"))
+            }
+            Err(err) => return Err(err),
+        };
+        if available == 0 {
+            return Ok(0);
+        }
+        stream.read(&mut buf[..available])
+    }
+
+    /// Takes the oldest message that arrived while waiting.
+    pub fn take_message(&mut self) -> Option<M> {
+        self.messages.pop_front()
+    }
+
+    // Peeks every stream once, without waiting.
+    fn check(&mut self) -> Vec<(StreamId, Readiness)> {
+        let mut byte = [0];
+        let mut ready = Vec::new();
+        for (&id, registered) in self.streams.iter_mut() {
+            let readiness = match registered.stream.peek(&mut byte) {
+                Ok(0) => Readiness::Closed,
+                Ok(_) => Readiness::Readable,
+                Err(err) if err.kind() == ErrorKind::TimedOut => continue,
+                Err(err) => Readiness::Error(err.kind()),
+            };
+            ready.push((id, readiness));
+        }
+        ready
+    }
+
+    // Waits for `duration`, or until a message arrives.
+    fn wait(&mut self, duration: Duration) {
+        match self.mailbox {
+            Some(mailbox) => {
+                if let MailboxResult::Message(message) = mailbox.receive_timeout(duration) {
+                    self.messages.push_back(message);
+                }
+            }
+            None => crate::sleep(duration),
+        }
+    }
+}
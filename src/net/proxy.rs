@@ -0,0 +1,197 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::TcpStream;
+use crate::{Mailbox, MailboxResult, Process, Tag};
+
+/// Size of the buffer used by each direction, if no other size is set.
+const DEFAULT_BUFFER_SIZE: usize = 16 * 1024;
+
+/// A direction of the traffic relayed by [`proxy`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// From the first stream to the second one.
+    AToB,
+    /// From the second stream to the first one.
+    BToA,
+}
+
+/// Options of [`proxy`].
+pub struct ProxyOptions<'a> {
+    idle_timeout: Option<Duration>,
+    buffer_size: usize,
+    on_transfer: Option<Box<dyn FnMut(Direction, usize) + 'a>>,
+}
+
+impl<'a> ProxyOptions<'a> {
+    pub fn new() -> Self {
+        ProxyOptions {
+            idle_timeout: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            on_transfer: None,
+        }
+    }
+
+    /// Tears the relay down if no data is transferred in either direction for
+    /// `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the size of the buffer used by each direction, the upper bound of
+    /// the bytes copied with one read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "the buffer size must be at least 1");
+        self.buffer_size = size;
+        self
+    }
+
+    /// Calls `callback` with the number of bytes of each chunk relayed, e.g.
+    /// for metering.
+    ///
+    /// The callback runs in the process calling [`proxy`].
+    pub fn on_transfer(mut self, callback: impl FnMut(Direction, usize) + 'a) -> Self {
+        self.on_transfer = Some(Box::new(callback));
+        self
+    }
+}
+
+impl<'a> Default for ProxyOptions<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bytes relayed by [`proxy`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProxyStats {
+    pub a_to_b: u64,
+    pub b_to_a: u64,
+    /// Set if the relay was torn down by the idle timeout.
+    pub idle_timed_out: bool,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum ProxyMessage {
+    Transferred(Direction, usize),
+    Done(Direction),
+}
+
+/// Relays data between `a` and `b` in both directions, until both of them
+/// are done.
+///
+/// Each direction is copied by its own process, using clones of the streams.
+/// A direction is done when its source reaches EOF or fails. The other
+/// direction keeps running, so a connection closed by one side can still
+/// receive the rest of the data from the other side. The host can't shut
+/// down only the write side of a socket, so closing one side is passed on to
+/// the other stream only once both directions are done and the streams are
+/// dropped.
+///
+/// # Example
+///
+/// ```
+/// let (client, _) = listener.accept()?;
+/// let backend = TcpStream::connect("10.0.0.2:8080")?;
+/// let stats = net::proxy(client, backend, ProxyOptions::new().idle_timeout(Duration::from_secs(60)));
+/// ```
+pub fn proxy(a: TcpStream, b: TcpStream, mut options: ProxyOptions<'_>) -> ProxyStats {
+    let tag = Tag::new();
+    let this = Process::<ProxyMessage>::this();
+    let report = options.idle_timeout.is_some() || options.on_transfer.is_some();
+    let copiers = [
+        (a.clone(), b.clone(), Direction::AToB),
+        (b, a, Direction::BToA),
+    ]
+    .map(|(from, to, direction)| {
+        Process::spawn_link(
+            (from, to, direction, this, tag, options.buffer_size, report),
+            copy_process,
+        )
+    });
+
+    let mailbox = unsafe { Mailbox::<ProxyMessage>::new() };
+    let mut stats = ProxyStats::default();
+    let mut running = copiers.len();
+    let mut last_transfer = Instant::now();
+    while running > 0 {
+        let message = match options.idle_timeout {
+            Some(timeout) => {
+                let remaining = timeout.saturating_sub(last_transfer.elapsed());
+                mailbox.tag_receive_timeout(&[tag], remaining)
+            }
+            None => MailboxResult::Message(mailbox.tag_receive(&[tag])),
+        };
+        match message {
+            MailboxResult::Message(ProxyMessage::Transferred(direction, bytes)) => {
+                last_transfer = Instant::now();
+                match direction {
+                    Direction::AToB => stats.a_to_b += bytes as u64,
+                    Direction::BToA => stats.b_to_a += bytes as u64,
+                }
+                if let Some(on_transfer) = options.on_transfer.as_mut() {
+                    on_transfer(direction, bytes);
+                }
+            }
+            MailboxResult::Message(ProxyMessage::Done(_)) => running -= 1,
+            MailboxResult::TimedOut => {
+                // Killing the copiers drops the last handles and closes both
+                // connections.
+                for copier in copiers.iter() {
+                    // Unlink before killing, so that the kill isn't reported as a failure.
+                    copier.unlink();
+                    copier.kill();
+                }
+                stats.idle_timed_out = true;
+                break;
+            }
+            _ => (),
+        }
+    }
+    stats
+}
+
+type CopyCapture = (
+    TcpStream,
+    TcpStream,
+    Direction,
+    Process<ProxyMessage>,
+    Tag,
+    usize,
+    bool,
+);
+
+fn copy_process(
+    (mut from, mut to, direction, parent, tag, buffer_size, report): CopyCapture,
+    _: Mailbox<()>,
+) {
+    let mut buffer = vec![0; buffer_size];
+    let mut total = 0;
+    loop {
+        let n = match from.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if to.write_all(&buffer[..n]).is_err() {
+            break;
+        }
+        if report {
+            parent.tag_send(tag, ProxyMessage::Transferred(direction, n));
+        } else {
+            total += n;
+        }
+    }
+    if !report && total > 0 {
+        // Without per-chunk reports the bytes are sent once at the end.
+        parent.tag_send(tag, ProxyMessage::Transferred(direction, total));
+    }
+    parent.tag_send(tag, ProxyMessage::Done(direction));
+}
@@ -0,0 +1,186 @@
+use std::io::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::graceful::{self, TrackerMessage};
+use super::{DrainReport, GracefulListener, TcpListener, TcpStream, ToSocketAddrs};
+use crate::{metrics, sleep, Mailbox, Process, ProcessConfig};
+
+/// Counter of accepted connections, labeled by the address of the server.
+pub const CONNECTIONS_COUNTER: &str = "lunatic.net.server.connections";
+/// Counter of failed accepts, labeled by the address of the server.
+pub const ACCEPT_ERRORS_COUNTER: &str = "lunatic.net.server.accept_errors";
+
+/// The first wait after a failed accept.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+/// The longest wait between two failed accepts.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+/// The first wait for a handler to finish while the connection limit is
+/// reached.
+const MIN_LIMIT_INTERVAL: Duration = Duration::from_millis(1);
+/// The longest wait for a handler to finish while the connection limit is
+/// reached.
+const MAX_LIMIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Messages sent by a [`Server`] to the processes handling its connections.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// The server is draining. The handler has the given time to finish
+    /// before it's killed.
+    Drain(Duration),
+}
+
+/// A TCP server that handles each connection in its own process.
+///
+/// The server accepts connections and spawns a process running the handler
+/// for each of them, with the [`TcpStream`] and a mailbox for
+/// [`ControlMessage`]s. Failed accepts are retried with a backoff, from 10ms
+/// up to 1s, so that e.g. running out of file descriptors doesn't stop the
+/// server.
+///
+/// The handlers are tracked like the ones of a [`GracefulListener`]. They are
+/// killed if the server process dies, and drained when the server is stopped
+/// with a [`ServerHandle`] or the [shutdown](crate::shutdown) is initiated.
+/// Handlers receive [`ControlMessage::Drain`] once the drain starts.
+///
+/// Each accepted connection increments [`CONNECTIONS_COUNTER`], and each
+/// failed accept [`ACCEPT_ERRORS_COUNTER`].
+///
+/// # Example
+///
+/// ```
+/// Server::bind("0.0.0.0:8080")?
+///     .with_max_connections(10_000)
+///     .serve(|mut stream, _| {
+///         let mut buffer = [0; 1024];
+///         while let Ok(len @ 1..) = stream.read(&mut buffer) {
+///             stream.write_all(&buffer[..len]).unwrap();
+///         }
+///     });
+/// ```
+pub struct Server {
+    listener: GracefulListener,
+    config: Option<ProcessConfig>,
+    max_connections: Option<usize>,
+}
+
+impl Server {
+    /// Creates a server listening on `addr`, see [`TcpListener::bind`].
+    pub fn bind<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        Ok(Self::from_listener(TcpListener::bind(addr)?))
+    }
+
+    /// Creates a server accepting the connections of `listener`.
+    pub fn from_listener(listener: TcpListener) -> Self {
+        Server {
+            listener: GracefulListener::new(listener),
+            config: None,
+            max_connections: None,
+        }
+    }
+
+    /// Spawns the handlers with `config`, instead of the config of the
+    /// current process.
+    pub fn with_config(mut self, config: ProcessConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Limits the number of connections that are handled at once.
+    ///
+    /// While the limit is reached, no more connections are accepted and they
+    /// wait in the backlog of the socket. The server checks if a handler
+    /// finished with a delay, from 1ms up to 50ms.
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max.max(1));
+        self
+    }
+
+    /// Returns the local address that the server is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a handle that can stop the server from other processes.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            tracker: self.listener.tracker(),
+        }
+    }
+
+    /// Accepts connections and handles each of them with `handler` in a new
+    /// process, until the server is stopped.
+    ///
+    /// The current process is killed once the server is stopped, to close the
+    /// listening socket, so this function doesn't return. It's usually called
+    /// from `main` or a process dedicated to the server.
+    pub fn serve(self, handler: fn(TcpStream, Mailbox<ControlMessage>)) -> ! {
+        let addr = self
+            .local_addr()
+            .map_or_else(|_| String::from("unknown"), |addr| addr.to_string());
+        let labels = [("addr", addr.as_str())];
+        let connections_counter = metrics::with_labels(CONNECTIONS_COUNTER, &labels);
+        let errors_counter = metrics::with_labels(ACCEPT_ERRORS_COUNTER, &labels);
+        // Only kept while the connections are limited.
+        let mut handlers: Vec<Process<ControlMessage>> = Vec::new();
+        let mut backoff = MIN_ACCEPT_BACKOFF;
+        loop {
+            if let Some(max) = self.max_connections {
+                wait_below(&mut handlers, max);
+            }
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    metrics::increment_counter(&errors_counter);
+                    sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = MIN_ACCEPT_BACKOFF;
+            metrics::increment_counter(&connections_counter);
+            let handler = match &self.config {
+                Some(config) => Process::spawn_config(config, stream, handler),
+                None => Process::spawn(stream, handler),
+            };
+            self.listener.track_controlled(handler);
+            if self.max_connections.is_some() {
+                handlers.push(handler);
+            }
+        }
+    }
+}
+
+// Waits until fewer than `max` of the `handlers` are running.
+fn wait_below(handlers: &mut Vec<Process<ControlMessage>>, max: usize) {
+    let mut interval = MIN_LIMIT_INTERVAL;
+    while handlers.len() >= max {
+        handlers.retain(|handler| handler.is_alive());
+        if handlers.len() >= max {
+            sleep(interval);
+            interval = (interval * 2).min(MAX_LIMIT_INTERVAL);
+        }
+    }
+}
+
+/// Stops a [`Server`] from another process.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerHandle {
+    tracker: Process<TrackerMessage>,
+}
+
+impl ServerHandle {
+    /// Stops accepting connections and waits up to `deadline` for the running
+    /// handlers to finish.
+    ///
+    /// The process serving the connections is killed to close the listening
+    /// socket. Handlers that are still running after the deadline are killed.
+    pub fn drain(&self, deadline: Duration) -> DrainReport {
+        graceful::stop(self.tracker, deadline)
+    }
+}
@@ -0,0 +1,175 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{ListenerOptions, TcpListener, TcpStream, ToSocketAddrs};
+use crate::{Mailbox, MailboxResult, Process, Tag};
+
+/// How often a waiting process checks if the listener process is still alive.
+const ALIVE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A [`TcpListener`] bound by its own process, whose connections can be
+/// accepted by any process on the same node.
+///
+/// The host can't move a listener between processes, so a listener is closed
+/// when the process that bound it dies. If that process is restarted, e.g. by
+/// a supervisor, binding the address again can fail, and connections in the
+/// backlog of the old socket are lost. A shared listener keeps the socket in
+/// a separate process that isn't restarted with the acceptor. The handle is
+/// cheap to copy and can be passed to each new acceptor, e.g. as the argument
+/// of a supervisor child, see [`ChildArg`](crate::supervisor::ChildArg).
+///
+/// The listener process is linked to the process that created the shared
+/// listener, and is stopped when that process dies or
+/// [`close`](SharedListener::close) is called.
+///
+/// Connections are accepted by the listener process and handed to the
+/// processes waiting in [`accept`](SharedListener::accept), in the order they
+/// called it. If a waiting process died before a connection arrived, the
+/// connection goes to the next one instead.
+///
+/// # Example
+///
+/// ```
+/// let listener = SharedListener::bind("0.0.0.0:8080")?;
+/// // Restarted acceptors get the same socket.
+/// Process::spawn_link(listener, |listener, _: Mailbox<()>| {
+///     while let Ok((stream, _)) = listener.accept() {
+///         Process::spawn(stream, serve);
+///     }
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharedListener {
+    holder: Process<AcceptRequest>,
+    local_addr: SocketAddr,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub struct AcceptRequest {
+    waiter: Process<Accepted>,
+    tag: Tag,
+}
+
+#[doc(hidden)]
+pub type Accepted = std::result::Result<(TcpStream, SocketAddr), String>;
+
+#[doc(hidden)]
+pub type Bound = std::result::Result<SocketAddr, String>;
+
+type HolderCapture = (Vec<SocketAddr>, ListenerOptions, Process<Bound>, Tag);
+
+impl SharedListener {
+    /// Spawns a listener process bound to the given address, see
+    /// [`TcpListener::bind`].
+    pub fn bind<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        SharedListener::bind_with(addr, ListenerOptions::new())
+    }
+
+    /// Spawns a listener process bound to the given address, with the socket
+    /// `options`, see [`TcpListener::bind_with`].
+    pub fn bind_with<A>(addr: A, options: ListenerOptions) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let tag = Tag::new();
+        let holder = Process::spawn_link((addrs, options, Process::this(), tag), holder_process);
+        match unsafe { Mailbox::<Bound>::new() }.tag_receive(&[tag]) {
+            Ok(local_addr) => Ok(SharedListener { holder, local_addr }),
+            Err(err) => Err(Error::other(err)),
+        }
+    }
+
+    /// Accepts a new incoming connection, see [`TcpListener::accept`].
+    ///
+    /// Fails with [`ErrorKind::NotConnected`] if the listener was closed.
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        let tag = Tag::new();
+        let mailbox = unsafe { Mailbox::<Accepted>::new() };
+        self.holder.send(AcceptRequest {
+            waiter: Process::this(),
+            tag,
+        });
+        // Linking to the listener process would also take it down with a
+        // dying acceptor, so it's polled instead.
+        loop {
+            match mailbox.tag_receive_timeout(&[tag], ALIVE_CHECK_INTERVAL) {
+                MailboxResult::Message(accepted) => {
+                    return accepted.map_err(Error::other)
+                }
+                MailboxResult::TimedOut if self.holder.is_alive() => (),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::NotConnected,
+                        "the shared listener was closed",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Returns the local address that the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops the listener process, closing the socket.
+    pub fn close(self) {
+        self.holder.kill();
+    }
+}
+
+impl Serialize for SharedListener {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Accepted streams can't leave the node.
+        crate::serializer::local_resource("SharedListener")?;
+        (self.holder, self.local_addr).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SharedListener {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<SharedListener, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (holder, local_addr) = Deserialize::deserialize(deserializer)?;
+        Ok(SharedListener { holder, local_addr })
+    }
+}
+
+fn holder_process((addrs, options, parent, tag): HolderCapture, mailbox: Mailbox<AcceptRequest>) {
+    let bound = TcpListener::bind_with(addrs.as_slice(), options)
+        .and_then(|listener| Ok((listener.local_addr()?, listener)));
+    let listener = match bound {
+        Ok((local_addr, listener)) => {
+            parent.tag_send(tag, Ok(local_addr));
+            listener
+        }
+        Err(err) => {
+            parent.tag_send(tag, Err(err.to_string()));
+            return;
+        }
+    };
+    loop {
+        // Requests queue up in the mailbox while the listener is accepting.
+        let accepted = listener.accept().map_err(|err| err.to_string());
+        loop {
+            let request = mailbox.receive();
+            // Waiters that died keep the connection for the next one.
+            if request.waiter.is_alive() {
+                request.waiter.tag_send(request.tag, accepted);
+                break;
+            }
+        }
+    }
+}
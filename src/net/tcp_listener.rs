@@ -1,10 +1,72 @@
-use std::io::{Error, ErrorKind, Result};
+use std::io::{ErrorKind, Result};
 use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
 
 use super::SocketAddrIterator;
 use crate::error::LunaticError;
-use crate::host;
 use crate::net::TcpStream;
+use crate::{host, resource, sleep};
+
+/// How long [`TcpListener::bind_with`] waits between two attempts to bind an
+/// address that is in use.
+const BIND_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// Kind of the listener in the [resource counts](resource::resource_counts).
+const RESOURCE_KIND: &str = "TcpListener";
+
+/// Options of [`TcpListener::bind_with`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerOptions {
+    reuse_addr: bool,
+    reuse_port: bool,
+    retry_timeout: Option<Duration>,
+}
+
+impl ListenerOptions {
+    pub fn new() -> Self {
+        ListenerOptions {
+            reuse_addr: true,
+            reuse_port: false,
+            retry_timeout: None,
+        }
+    }
+
+    /// Sets `SO_REUSEADDR` on the socket, so that the address can be bound
+    /// again while connections of a previous listener are in `TIME_WAIT`.
+    ///
+    /// The host always sets it on Unix, so this is enabled by default and
+    /// disabling it isn't supported.
+    pub fn reuse_addr(mut self, enabled: bool) -> Self {
+        self.reuse_addr = enabled;
+        self
+    }
+
+    /// Sets `SO_REUSEPORT` on the socket, so that multiple listeners can be
+    /// bound to the same address.
+    ///
+    /// The host doesn't support this yet, binding with it enabled fails with
+    /// [`ErrorKind::Unsupported`]. Use a
+    /// [`SharedListener`](super::SharedListener) to accept connections of one
+    /// socket in multiple processes.
+    pub fn reuse_port(mut self, enabled: bool) -> Self {
+        self.reuse_port = enabled;
+        self
+    }
+
+    /// Retries binding for up to `timeout` while the address is in use, e.g.
+    /// until a listener of a process that just died is closed by the host.
+    pub fn retry_timeout(mut self, timeout: Duration) -> Self {
+        self.retry_timeout = Some(timeout);
+        self
+    }
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// A TCP server, listening for connections.
 ///
@@ -50,6 +112,7 @@ pub struct TcpListener {
 impl Drop for TcpListener {
     fn drop(&mut self) {
         unsafe { host::api::networking::drop_tcp_listener(self.id) };
+        resource::released(RESOURCE_KIND, self.id);
     }
 }
 
@@ -63,12 +126,15 @@ impl TcpListener {
     /// of the addresses until one succeeds and returns the listener. If
     /// none of the addresses succeed in creating a listener, the error from
     /// the last attempt is returned.
+    #[track_caller]
     pub fn bind<A>(addr: A) -> Result<Self>
     where
         A: super::ToSocketAddrs,
     {
         let mut id = 0;
+        let mut last_addr = None;
         for addr in addr.to_socket_addrs()? {
+            last_addr = Some(addr);
             let result = match addr {
                 SocketAddr::V4(v4_addr) => {
                     let ip = v4_addr.ip().octets();
@@ -102,11 +168,54 @@ impl TcpListener {
                 }
             };
             if result == 0 {
+                resource::acquired(RESOURCE_KIND, id);
                 return Ok(Self { id });
             }
         }
-        let lunatic_error = LunaticError::from(id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(id, last_addr).into())
+    }
+
+    /// Creates a new [`TcpListener`] bound to the given address, with the
+    /// socket `options`.
+    ///
+    /// Fails with [`ErrorKind::Unsupported`] if the options can't be applied
+    /// by the host.
+    ///
+    /// A listener closes when the process holding it dies. To keep the socket
+    /// open while the accepting process is restarted, e.g. by a supervisor,
+    /// bind a [`SharedListener`](super::SharedListener) instead.
+    #[track_caller]
+    pub fn bind_with<A>(addr: A, options: ListenerOptions) -> Result<Self>
+    where
+        A: super::ToSocketAddrs,
+    {
+        if !options.reuse_addr {
+            return Err(LunaticError::Unsupported {
+                operation: "reuse_addr(false)",
+            }
+            .into());
+        }
+        if options.reuse_port {
+            return Err(LunaticError::Unsupported {
+                operation: "reuse_port",
+            }
+            .into());
+        }
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let deadline = options
+            .retry_timeout
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            match TcpListener::bind(addrs.as_slice()) {
+                Err(err)
+                    if err.kind() == ErrorKind::AddrInUse
+                        && deadline.is_some_and(|deadline| Instant::now() < deadline) =>
+                {
+                    sleep(BIND_RETRY_INTERVAL)
+                }
+                result => return result,
+            }
+        }
     }
 
     /// Accepts a new incoming connection.
@@ -115,6 +224,7 @@ impl TcpListener {
     /// loop.
     ///
     /// Returns a TCP stream and the peer address.
+    #[track_caller]
     pub fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
         let mut tcp_stream_or_error_id = 0;
         let mut dns_iter_id = 0;
@@ -131,8 +241,7 @@ impl TcpListener {
             let peer = dns_iter.next().expect("must contain one element");
             Ok((tcp_stream, peer))
         } else {
-            let lunatic_error = LunaticError::from(tcp_stream_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(tcp_stream_or_error_id, None).into())
         }
     }
 
@@ -150,8 +259,7 @@ impl TcpListener {
             let addr = dns_iter.next().expect("must contain one element");
             Ok(addr)
         } else {
-            let lunatic_error = LunaticError::from(dns_iter_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(dns_iter_or_error_id, None).into())
         }
     }
 }
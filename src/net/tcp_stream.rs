@@ -3,13 +3,22 @@ use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
 use std::net::SocketAddr;
 use std::time::Duration;
 
+use serde::de::Error as _;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::SocketAddrIterator;
 use crate::error::LunaticError;
-use crate::host;
+use crate::{host, resource};
 
 const TIMEOUT: u32 = 9027;
+/// Kind of the stream in the [resource counts](resource::resource_counts).
+const RESOURCE_KIND: &str = "TcpStream";
+
+/// Maximum number of bytes peeked at once by [`TcpStream::read_until_limited`].
+const PEEK_CHUNK_SIZE: usize = 512;
+
+/// Read, write and peek timeouts of a stream.
+type Timeouts = (Option<Duration>, Option<Duration>, Option<Duration>);
 
 /// A TCP connection.
 ///
@@ -23,6 +32,12 @@ const TIMEOUT: u32 = 9027;
 /// Cloning a [`TcpStream`] creates another handle to the same socket. The
 /// socket will be closed when all handles to it are dropped.
 ///
+/// A [`TcpStream`] can be sent to another process on the same node, together
+/// with its timeouts. Sockets can't be moved between nodes, sending a stream to
+/// a process on another node fails with
+/// [`EncodeError::LocalResource`](crate::serializer::EncodeError::LocalResource)
+/// before the message is sent, see [`Process::try_send`](crate::Process::try_send).
+///
 /// The Transmission Control Protocol is specified in [IETF RFC 793].
 ///
 /// [IETF RFC 793]: https://tools.ietf.org/html/rfc793
@@ -39,17 +54,16 @@ impl Drop for TcpStream {
         // Only drop stream if it's not already consumed
         if unsafe { !*self.consumed.get() } {
             unsafe { host::api::networking::drop_tcp_stream(self.id) };
+            resource::released(RESOURCE_KIND, self.id);
         }
     }
 }
 
 impl Clone for TcpStream {
+    #[track_caller]
     fn clone(&self) -> Self {
         let id = unsafe { host::api::networking::clone_tcp_stream(self.id) };
-        Self {
-            id,
-            consumed: UnsafeCell::new(false),
-        }
+        TcpStream::from(id)
     }
 }
 
@@ -58,11 +72,17 @@ impl Serialize for TcpStream {
     where
         S: Serializer,
     {
+        crate::serializer::local_resource("TcpStream")?;
+        let timeouts = (
+            self.read_timeout(),
+            self.write_timeout(),
+            self.peek_timeout(),
+        );
         // Mark process as consumed
         unsafe { *self.consumed.get() = true };
-        // TODO: Timeout info is not serialized
+        resource::released(RESOURCE_KIND, self.id);
         let index = unsafe { host::api::message::push_tcp_stream(self.id) };
-        serializer.serialize_u64(index)
+        (index, timeouts).serialize(serializer)
     }
 }
 
@@ -71,14 +91,22 @@ impl<'de> Deserialize<'de> for TcpStream {
     where
         D: Deserializer<'de>,
     {
-        let index = Deserialize::deserialize(deserializer)?;
+        let (index, (read, write, peek)): (u64, Timeouts) = Deserialize::deserialize(deserializer)?;
         let id = unsafe { host::api::message::take_tcp_stream(index) };
-        Ok(TcpStream::from(id))
+        let mut stream = TcpStream::from(id);
+        // Apply the timeouts again, in case the host doesn't move them together with the
+        // socket.
+        stream.set_read_timeout(read).map_err(D::Error::custom)?;
+        stream.set_write_timeout(write).map_err(D::Error::custom)?;
+        stream.set_peek_timeout(peek).map_err(D::Error::custom)?;
+        Ok(stream)
     }
 }
 
 impl TcpStream {
+    #[track_caller]
     pub(crate) fn from(id: u64) -> Self {
+        resource::acquired(RESOURCE_KIND, id);
         TcpStream {
             id,
             consumed: UnsafeCell::new(false),
@@ -94,6 +122,7 @@ impl TcpStream {
     /// each of the addresses until connecting to one succeeds. If none of
     /// the addresses result in a successful connection, the error from the
     /// last connect attempt is returned.
+    #[track_caller]
     pub fn connect<A>(addr: A) -> Result<Self>
     where
         A: super::ToSocketAddrs,
@@ -103,6 +132,7 @@ impl TcpStream {
 
     /// Same as [`TcpStream::connect`], but only waits for the duration of
     /// timeout to connect.
+    #[track_caller]
     pub fn connect_timeout<A>(addr: A, timeout: Duration) -> Result<Self>
     where
         A: super::ToSocketAddrs,
@@ -110,12 +140,15 @@ impl TcpStream {
         TcpStream::connect_timeout_(addr, Some(timeout))
     }
 
+    #[track_caller]
     fn connect_timeout_<A>(addr: A, timeout: Option<Duration>) -> Result<Self>
     where
         A: super::ToSocketAddrs,
     {
         let mut id = 0;
+        let mut last_addr = None;
         for addr in addr.to_socket_addrs()? {
+            last_addr = Some(addr);
             let timeout_ms = match timeout {
                 Some(timeout) => timeout.as_millis() as u64,
                 None => u64::MAX,
@@ -158,8 +191,7 @@ impl TcpStream {
                 return Ok(TcpStream::from(id));
             }
         }
-        let lunatic_error = LunaticError::from(id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(id, last_addr).into())
     }
 
     /// Returns the remote address this socket is connected to.
@@ -173,8 +205,7 @@ impl TcpStream {
             let addr = dns_iter.next().expect("must contain one element");
             Ok(addr)
         } else {
-            let lunatic_error = LunaticError::from(dns_iter_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(dns_iter_or_error_id, None).into())
         }
     }
 
@@ -262,6 +293,12 @@ impl TcpStream {
     /// Peek value on the tcp stream without removing it from internal buffer.
     /// Any subsequent calls to `peek` will read from the internal buffer
     /// and only calls to `read` will consume the buffered data
+    ///
+    /// The peeked data stays in the socket, so it can still be read after the
+    /// stream was sent to another process, e.g. to route a connection by its
+    /// first bytes. Waits on data for at most the
+    /// [peek timeout](TcpStream::set_peek_timeout), and may return fewer bytes
+    /// than `buf` holds if less data arrived.
     pub fn peek(&mut self, buf: &mut [u8]) -> Result<usize> {
         let mut nread_or_error_id: u64 = 0;
         let result = unsafe {
@@ -277,9 +314,58 @@ impl TcpStream {
         } else if result == TIMEOUT {
             Err(Error::new(ErrorKind::TimedOut, "TcpStream peek timed out"))
         } else {
-            let lunatic_error = LunaticError::from(nread_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nread_or_error_id, None).into())
+        }
+    }
+
+    /// Reads all bytes until the `delimiter` or EOF is reached, and appends
+    /// them to `buf`, like [`BufRead::read_until`](std::io::BufRead::read_until).
+    ///
+    /// Fails with [`ErrorKind::InvalidData`] if the delimiter isn't found within
+    /// `max` bytes, the delimiter included. The bytes read until then are still
+    /// appended to `buf`.
+    ///
+    /// The stream isn't buffered, so data is peeked first and only the bytes up
+    /// to the delimiter are read. The rest stays in the socket and is returned
+    /// by the next read, also after the stream was sent to another process.
+    /// Waiting on data is bounded by the
+    /// [peek timeout](TcpStream::set_peek_timeout), reading the peeked bytes
+    /// doesn't wait.
+    ///
+    /// Returns the number of bytes appended to `buf`.
+    pub fn read_until_limited(
+        &mut self,
+        delimiter: u8,
+        max: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        let mut chunk = [0; PEEK_CHUNK_SIZE];
+        let mut total = 0;
+        while total < max {
+            let len = (max - total).min(PEEK_CHUNK_SIZE);
+            let peeked = self.peek(&mut chunk[..len])?;
+            if peeked == 0 {
+                return Ok(total);
+            }
+            let (consume, found) = match chunk[..peeked].iter().position(|&b| b == delimiter) {
+                Some(index) => (index + 1, true),
+                None => (peeked, false),
+            };
+            let start = buf.len();
+            buf.resize(start + consume, 0);
+            if let Err(err) = self.read_exact(&mut buf[start..]) {
+                buf.truncate(start);
+                return Err(err);
+            }
+            total += consume;
+            if found {
+                return Ok(total);
+            }
         }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("delimiter not found within {max} bytes"),
+        ))
     }
 }
 
@@ -304,8 +390,7 @@ impl Write for TcpStream {
         } else if result == TIMEOUT {
             Err(Error::new(ErrorKind::TimedOut, "TcpStream write timed out"))
         } else {
-            let lunatic_error = LunaticError::from(nwritten_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nwritten_or_error_id, None).into())
         }
     }
 
@@ -313,10 +398,7 @@ impl Write for TcpStream {
         let mut error_id = 0;
         match unsafe { host::api::networking::tcp_flush(self.id, &mut error_id as *mut u64) } {
             0 => Ok(()),
-            _ => {
-                let lunatic_error = LunaticError::from(error_id);
-                Err(Error::new(ErrorKind::Other, lunatic_error))
-            }
+            _ => Err(LunaticError::network(error_id, None).into()),
         }
     }
 }
@@ -337,8 +419,7 @@ impl Read for TcpStream {
         } else if result == TIMEOUT {
             Err(Error::new(ErrorKind::TimedOut, "TcpStream read timed out"))
         } else {
-            let lunatic_error = LunaticError::from(nread_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nread_or_error_id, None).into())
         }
     }
 }
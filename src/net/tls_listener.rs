@@ -1,8 +1,11 @@
-use std::io::{Error, ErrorKind, Result};
+use std::io::Result;
 use std::net::SocketAddr;
 
 use super::SocketAddrIterator;
-use crate::{error::LunaticError, host, net::TlsStream};
+use crate::{error::LunaticError, host, net::TlsStream, resource};
+
+/// Kind of the listener in the [resource counts](resource::resource_counts).
+const RESOURCE_KIND: &str = "TlsListener";
 
 /// A TLS server, listening for connections.
 ///
@@ -48,6 +51,7 @@ pub struct TlsListener {
 impl Drop for TlsListener {
     fn drop(&mut self) {
         unsafe { host::api::networking::drop_tls_listener(self.id) };
+        resource::released(RESOURCE_KIND, self.id);
     }
 }
 
@@ -60,12 +64,15 @@ impl TlsListener {
     /// If `addr` yields multiple addresses, binding will be attempted with each of the addresses
     /// until one succeeds and returns the listener. If none of the addresses succeed in creating a
     /// listener, the error from the last attempt is returned.
+    #[track_caller]
     pub fn bind<A>(addr: A, certs: Vec<u8>, keys: Vec<u8>) -> Result<Self>
     where
         A: super::ToSocketAddrs,
     {
         let mut id = 0;
+        let mut last_addr = None;
         for addr in addr.to_socket_addrs()? {
+            last_addr = Some(addr);
             let result = match addr {
                 SocketAddr::V4(v4_addr) => {
                     let ip = v4_addr.ip().octets();
@@ -107,11 +114,11 @@ impl TlsListener {
                 }
             };
             if result == 0 {
+                resource::acquired(RESOURCE_KIND, id);
                 return Ok(Self { id });
             }
         }
-        let lunatic_error = LunaticError::from(id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(id, last_addr).into())
     }
 
     /// Accepts a new incoming connection.
@@ -119,6 +126,7 @@ impl TlsListener {
     /// This will block and typically needs its own dedicated child process loop.
     ///
     /// Returns a TLS stream and the peer address.
+    #[track_caller]
     pub fn accept(&self) -> Result<(TlsStream, SocketAddr)> {
         let mut tls_stream_or_error_id = 0;
         let mut dns_iter_id = 0;
@@ -135,8 +143,7 @@ impl TlsListener {
             let peer = dns_iter.next().expect("must contain one element");
             Ok((tls_stream, peer))
         } else {
-            let lunatic_error = LunaticError::from(tls_stream_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(tls_stream_or_error_id, None).into())
         }
     }
 
@@ -153,8 +160,7 @@ impl TlsListener {
             let addr = dns_iter.next().expect("must contain one element");
             Ok(addr)
         } else {
-            let lunatic_error = LunaticError::from(dns_iter_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(dns_iter_or_error_id, None).into())
         }
     }
 }
@@ -9,9 +9,11 @@ use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{error::LunaticError, host};
+use crate::{error::LunaticError, host, resource};
 
 const TIMEOUT: u32 = 9027;
+/// Kind of the stream in the [resource counts](resource::resource_counts).
+const RESOURCE_KIND: &str = "TlsStream";
 
 /// A TCP connection.
 ///
@@ -39,17 +41,16 @@ impl Drop for TlsStream {
         // Only drop stream if it's not already consumed
         if unsafe { !*self.consumed.get() } {
             unsafe { host::api::networking::drop_tls_stream(self.id) };
+            resource::released(RESOURCE_KIND, self.id);
         }
     }
 }
 
 impl Clone for TlsStream {
+    #[track_caller]
     fn clone(&self) -> Self {
         let id = unsafe { host::api::networking::clone_tls_stream(self.id) };
-        Self {
-            id,
-            consumed: UnsafeCell::new(false),
-        }
+        TlsStream::from(id)
     }
 }
 
@@ -58,8 +59,10 @@ impl Serialize for TlsStream {
     where
         S: Serializer,
     {
+        crate::serializer::local_resource("TlsStream")?;
         // Mark process as consumed
         unsafe { *self.consumed.get() = true };
+        resource::released(RESOURCE_KIND, self.id);
         // TODO: Timeout info is not serialized
         let index = unsafe { host::api::message::push_tls_stream(self.id) };
         // panic!("Need stacktrace");
@@ -93,7 +96,9 @@ impl<'de> Deserialize<'de> for TlsStream {
 }
 
 impl TlsStream {
+    #[track_caller]
     pub(crate) fn from(id: u64) -> Self {
+        resource::acquired(RESOURCE_KIND, id);
         TlsStream {
             id,
             consumed: UnsafeCell::new(false),
@@ -107,6 +112,7 @@ impl TlsStream {
     /// If `addr` yields multiple addresses, connecting will be attempted with each of the
     /// addresses until connecting to one succeeds. If none of the addresses result in a successful
     /// connection, the error from the last connect attempt is returned.
+    #[track_caller]
     pub fn connect(addr: &str, port: u32) -> Result<Self> {
         TlsStream::connect_timeout_(addr, None, port, vec![])
     }
@@ -118,11 +124,13 @@ impl TlsStream {
     /// If `addr` yields multiple addresses, connecting will be attempted with each of the
     /// addresses until connecting to one succeeds. If none of the addresses result in a successful
     /// connection, the error from the last connect attempt is returned.
+    #[track_caller]
     pub fn connect_with_certs(addr: &str, port: u32, certs: Vec<Vec<u8>>) -> Result<Self> {
         TlsStream::connect_timeout_(addr, None, port, certs)
     }
 
     /// Same as [`TlsStream::connect`], but only waits for the duration of timeout to connect.
+    #[track_caller]
     pub fn connect_timeout(
         addr: &str,
         timeout: Duration,
@@ -132,6 +140,7 @@ impl TlsStream {
         TlsStream::connect_timeout_(addr, Some(timeout), port, certs)
     }
 
+    #[track_caller]
     fn connect_timeout_(
         addr: &str,
         timeout: Option<Duration>,
@@ -161,8 +170,7 @@ impl TlsStream {
         if result == 0 {
             return Ok(TlsStream::from(id));
         }
-        let lunatic_error = LunaticError::from(id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(id, None).into())
     }
 
     /// Sets write timeout for TlsStream
@@ -239,8 +247,7 @@ impl Write for TlsStream {
         } else if result == TIMEOUT {
             Err(Error::new(ErrorKind::TimedOut, "TlsStream write timed out"))
         } else {
-            let lunatic_error = LunaticError::from(nwritten_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nwritten_or_error_id, None).into())
         }
     }
 
@@ -248,10 +255,7 @@ impl Write for TlsStream {
         let mut error_id = 0;
         match unsafe { host::api::networking::tls_flush(self.id, &mut error_id as *mut u64) } {
             0 => Ok(()),
-            _ => {
-                let lunatic_error = LunaticError::from(error_id);
-                Err(Error::new(ErrorKind::Other, lunatic_error))
-            }
+            _ => Err(LunaticError::network(error_id, None).into()),
         }
     }
 }
@@ -272,8 +276,7 @@ impl Read for TlsStream {
         } else if result == TIMEOUT {
             Err(Error::new(ErrorKind::TimedOut, "TlsStream read timed out"))
         } else {
-            let lunatic_error = LunaticError::from(nread_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nread_or_error_id, None).into())
         }
     }
 }
@@ -1,10 +1,13 @@
 use std::cell::UnsafeCell;
 use std::io::{Error, ErrorKind, Result};
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
 use super::SocketAddrIterator;
 use crate::error::LunaticError;
-use crate::host;
+use crate::{host, resource};
+
+/// Kind of the socket in the [resource counts](resource::resource_counts).
+const RESOURCE_KIND: &str = "UdpSocket";
 
 /// A UDP socket.
 ///
@@ -66,6 +69,7 @@ impl Drop for UdpSocket {
         // Only drop stream if it's not already consumed
         if unsafe { !*self.consumed.get() } {
             unsafe { host::api::networking::drop_udp_socket(self.id) };
+            resource::released(RESOURCE_KIND, self.id);
         }
     }
 }
@@ -80,12 +84,15 @@ impl UdpSocket {
     /// of the addresses until one succeeds and returns the listener. If
     /// none of the addresses succeed in creating a listener, the error from
     /// the last attempt is returned.
+    #[track_caller]
     pub fn bind<A>(addr: A) -> Result<Self>
     where
         A: super::ToSocketAddrs,
     {
         let mut id = 0;
+        let mut last_addr = None;
         for addr in addr.to_socket_addrs()? {
+            last_addr = Some(addr);
             let result = match addr {
                 SocketAddr::V4(v4_addr) => {
                     let ip = v4_addr.ip().octets();
@@ -119,14 +126,14 @@ impl UdpSocket {
                 }
             };
             if result == 0 {
+                resource::acquired(RESOURCE_KIND, id);
                 return Ok(Self {
                     id,
                     consumed: UnsafeCell::new(false),
                 });
             }
         }
-        let lunatic_error = LunaticError::from(id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(id, last_addr).into())
     }
 
     /// Returns the local address that this UdpSocket is bound to.
@@ -143,8 +150,7 @@ impl UdpSocket {
             let addr = dns_iter.next().expect("must contain one element");
             Ok(addr)
         } else {
-            let lunatic_error = LunaticError::from(dns_iter_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(dns_iter_or_error_id, None).into())
         }
     }
 
@@ -161,8 +167,7 @@ impl UdpSocket {
         } else if result == 1 {
             Err(Error::new(ErrorKind::NotConnected, "not connected"))
         } else {
-            let lunatic_error = LunaticError::from(dns_iter_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(dns_iter_or_error_id, None).into())
         }
     }
 
@@ -199,7 +204,9 @@ impl UdpSocket {
         A: super::ToSocketAddrs,
     {
         let mut id = 0;
+        let mut last_addr = None;
         for addr in addr.to_socket_addrs()? {
+            last_addr = Some(addr);
             let result = match addr {
                 SocketAddr::V4(v4_addr) => {
                     let ip = v4_addr.ip().octets();
@@ -241,8 +248,7 @@ impl UdpSocket {
                 return Ok(());
             }
         }
-        let lunatic_error = LunaticError::from(id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(id, last_addr).into())
     }
 
     /// Sends data on the socket to the remote address to which it is connected.
@@ -272,8 +278,7 @@ impl UdpSocket {
         if result == 0 {
             Ok(nsend_or_error_id as usize)
         } else {
-            let lunatic_error = LunaticError::from(nsend_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nsend_or_error_id, None).into())
         }
     }
 
@@ -302,7 +307,9 @@ impl UdpSocket {
         A: super::ToSocketAddrs,
     {
         let mut nsend_or_error_id = 0;
+        let mut last_addr = None;
         for addr in addr.to_socket_addrs()? {
+            last_addr = Some(addr);
             let result = match addr {
                 SocketAddr::V4(v4_addr) => {
                     let ip = v4_addr.ip().octets();
@@ -345,8 +352,7 @@ impl UdpSocket {
                 return Ok(nsend_or_error_id as usize);
             }
         }
-        let lunatic_error = LunaticError::from(nsend_or_error_id);
-        Err(Error::new(ErrorKind::Other, lunatic_error))
+        Err(LunaticError::network(nsend_or_error_id, last_addr).into())
     }
 
     /// Receives a single datagram message on the socket from the remote address
@@ -386,8 +392,7 @@ impl UdpSocket {
         if result == 0 {
             Ok(nrecv_or_error_id as usize)
         } else {
-            let lunatic_error = LunaticError::from(nrecv_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nrecv_or_error_id, None).into())
         }
     }
 
@@ -426,8 +431,7 @@ impl UdpSocket {
             let peer = dns_iter.next().expect("must contain one element");
             Ok((nrecv_or_error_id as usize, peer))
         } else {
-            let lunatic_error = LunaticError::from(nrecv_or_error_id);
-            Err(Error::new(ErrorKind::Other, lunatic_error))
+            Err(LunaticError::network(nrecv_or_error_id, None).into())
         }
     }
 
@@ -530,8 +534,10 @@ impl UdpSocket {
     /// let socket = UdpSocket::bind("127.0.0.1:34254").expect("couldn't bind to address");
     /// let socket_clone = socket.try_clone().expect("couldn't clone the socket");
     /// ```
+    #[track_caller]
     pub fn try_clone(&self) -> Result<UdpSocket> {
         let result = unsafe { host::api::networking::clone_udp_socket(self.id) };
+        resource::acquired(RESOURCE_KIND, result);
         Ok(Self {
             id: result,
 
@@ -539,6 +545,83 @@ impl UdpSocket {
         })
     }
 
+    /// Joins the IPv4 multicast group `multiaddr` on the interface with the
+    /// address `interface`, or on any interface if it's
+    /// [`Ipv4Addr::UNSPECIFIED`].
+    ///
+    /// Datagrams sent to the group are received with
+    /// [`recv_from`](UdpSocket::recv_from), together with the address of the
+    /// sender.
+    ///
+    /// The host doesn't support multicast yet, so this and the other
+    /// multicast options fail with [`LunaticError::Unsupported`], converted
+    /// to an [`ErrorKind::Unsupported`] error.
+    pub fn join_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> Result<()> {
+        let _ = (multiaddr, interface);
+        unsupported("join_multicast_v4")
+    }
+
+    /// Leaves the IPv4 multicast group `multiaddr`, see
+    /// [`UdpSocket::join_multicast_v4`].
+    pub fn leave_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> Result<()> {
+        let _ = (multiaddr, interface);
+        unsupported("leave_multicast_v4")
+    }
+
+    /// Joins the IPv6 multicast group `multiaddr` on the interface with the
+    /// index `interface`, or on any interface if it's 0.
+    pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> Result<()> {
+        let _ = (multiaddr, interface);
+        unsupported("join_multicast_v6")
+    }
+
+    /// Leaves the IPv6 multicast group `multiaddr`, see
+    /// [`UdpSocket::join_multicast_v6`].
+    pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> Result<()> {
+        let _ = (multiaddr, interface);
+        unsupported("leave_multicast_v6")
+    }
+
+    /// Sets the value of the `IP_MULTICAST_LOOP` option for this socket.
+    ///
+    /// If enabled, multicast packets sent from this socket are also received
+    /// by the local host.
+    pub fn set_multicast_loop_v4(&self, multicast_loop_v4: bool) -> Result<()> {
+        let _ = multicast_loop_v4;
+        unsupported("set_multicast_loop_v4")
+    }
+
+    /// Gets the value of the `IP_MULTICAST_LOOP` option for this socket.
+    pub fn multicast_loop_v4(&self) -> Result<bool> {
+        unsupported("multicast_loop_v4")
+    }
+
+    /// Sets the value of the `IP_MULTICAST_TTL` option for this socket.
+    ///
+    /// This is the time-to-live of multicast packets sent from this socket,
+    /// which limits how many routers they pass. The default of 1 keeps them
+    /// in the local network.
+    pub fn set_multicast_ttl_v4(&self, multicast_ttl_v4: u32) -> Result<()> {
+        let _ = multicast_ttl_v4;
+        unsupported("set_multicast_ttl_v4")
+    }
+
+    /// Gets the value of the `IP_MULTICAST_TTL` option for this socket.
+    pub fn multicast_ttl_v4(&self) -> Result<u32> {
+        unsupported("multicast_ttl_v4")
+    }
+
+    /// Sets the value of the `IPV6_MULTICAST_LOOP` option for this socket.
+    pub fn set_multicast_loop_v6(&self, multicast_loop_v6: bool) -> Result<()> {
+        let _ = multicast_loop_v6;
+        unsupported("set_multicast_loop_v6")
+    }
+
+    /// Gets the value of the `IPV6_MULTICAST_LOOP` option for this socket.
+    pub fn multicast_loop_v6(&self) -> Result<bool> {
+        unsupported("multicast_loop_v6")
+    }
+
     /// Dummy fn - This is just to make porting from std easier?
     pub fn set_nonblocking(&self, _: bool) -> Result<()> {
         Ok(())
@@ -549,3 +632,7 @@ impl UdpSocket {
         Ok(None)
     }
 }
+
+fn unsupported<T>(operation: &'static str) -> Result<T> {
+    Err(LunaticError::Unsupported { operation }.into())
+}
@@ -1,12 +1,209 @@
-use crate::host;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Panicked;
+use serde::{Deserialize, Serialize};
+
+use crate::crash_dump::{self, CrashDump};
+use crate::host::api::message;
+use crate::mailbox::TIMEOUT;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, process_local, Process, Tag};
+
+/// Information about a panic, returned by [`catch_panic`] and
+/// [`link_panic`].
+///
+/// It's only available for panics. If the process died because of a trap
+/// (e.g. running out of fuel), all fields will be empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Panicked {
+    message: Option<String>,
+    payload_kind: PayloadKind,
+    location: Option<Location>,
+    backtrace: Option<String>,
+    // Boxed to keep `Result`s with a `Panicked` error small.
+    crash_dump: Option<Box<CrashDump>>,
+}
+
+/// The type of the panic payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayloadKind {
+    /// `panic!("literal")`
+    Str,
+    /// `panic!("formatted {}", message)`
+    String,
+    /// Any other payload, or a trap instead of a panic.
+    Other,
+}
+
+/// The location in the source code where the panic happened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    file: String,
+    line: u32,
+    column: u32,
+}
+
+impl Location {
+    /// Returns the name of the source file.
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Returns the line number.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Returns the column.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+impl Panicked {
+    /// Collects the information from a panic hook.
+    pub(crate) fn from_hook(
+        payload: &(dyn Any + Send),
+        location: Option<&std::panic::Location>,
+    ) -> Self {
+        let (message, payload_kind) = match payload_message(payload) {
+            Some((message, kind)) => (Some(message), kind),
+            None => (None, PayloadKind::Other),
+        };
+        let location = location.map(|location| Location {
+            file: location.file().to_owned(),
+            line: location.line(),
+            column: location.column(),
+        });
+        let backtrace = std::backtrace::Backtrace::capture();
+        let backtrace = match backtrace.status() {
+            std::backtrace::BacktraceStatus::Captured => Some(backtrace.to_string()),
+            _ => None,
+        };
+        Self {
+            message,
+            payload_kind,
+            location,
+            backtrace,
+            crash_dump: crash_dump::crash_dump().map(Box::new),
+        }
+    }
+
+    /// Used if a trap happened without a panic.
+    fn trap() -> Self {
+        Self {
+            message: None,
+            payload_kind: PayloadKind::Other,
+            location: None,
+            backtrace: None,
+            crash_dump: None,
+        }
+    }
+
+    /// Returns the panic message, if the payload was a `&str` or `String`.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Returns the type of the panic payload.
+    pub fn payload_kind(&self) -> PayloadKind {
+        self.payload_kind
+    }
+
+    /// Returns the raw bytes of the panic payload.
+    ///
+    /// Only `&str` and `String` payloads can be recovered, for other payloads
+    /// this is empty.
+    pub fn payload(&self) -> &[u8] {
+        self.message.as_deref().unwrap_or_default().as_bytes()
+    }
+
+    /// Returns the location of the panic.
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_ref()
+    }
+
+    /// Returns the backtrace, if the runtime was able to capture one.
+    ///
+    /// Backtraces are only captured if enabled through the `RUST_BACKTRACE`
+    /// environment variable and the runtime supports capturing them.
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_deref()
+    }
+
+    /// Returns the last messages the process received before the panic.
+    ///
+    /// Only processes that [record messages](crash_dump::record_messages)
+    /// produce a dump.
+    pub fn crash_dump(&self) -> Option<&CrashDump> {
+        self.crash_dump.as_deref()
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.message, &self.location) {
+            (Some(message), Some(location)) => write!(f, "panicked at '{}', {}", message, location),
+            (Some(message), None) => write!(f, "panicked at '{}'", message),
+            (None, Some(location)) => write!(f, "panicked at {}", location),
+            (None, None) => write!(f, "trapped"),
+        }
+    }
+}
+
+impl std::error::Error for Panicked {}
+
+/// Extracts the message out of a `&str` or `String` panic payload.
+pub(crate) fn payload_message(payload: &(dyn Any + Send)) -> Option<(String, PayloadKind)> {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        Some((message.to_string(), PayloadKind::Str))
+    } else {
+        payload
+            .downcast_ref::<String>()
+            .map(|message| (message.clone(), PayloadKind::String))
+    }
+}
+
+process_local! {
+    static HOOK_INSTALLED: Cell<bool> = Cell::new(false);
+    // Number of nested `catch_panic` calls currently running.
+    static CATCH_DEPTH: Cell<u32> = Cell::new(0);
+    // Information about the last panic inside this process.
+    static LAST_PANIC: RefCell<Option<Panicked>> = RefCell::new(None);
+}
+
+/// Installs a panic hook that records information about panics, before
+/// delegating to the previous hook.
+fn record_panics() {
+    if HOOK_INSTALLED.replace(true) {
+        return;
+    }
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let panicked = Panicked::from_hook(info.payload(), info.location());
+        // Panics caught by `catch_panic` don't terminate the process.
+        if CATCH_DEPTH.get() == 0 {
+            run_process_hook(&panicked);
+        }
+        LAST_PANIC.set(Some(panicked));
+        previous_hook(info);
+    }));
+}
 
 /// Invokes a closure, capturing a panic if one occurs.
 ///
 /// This function will return Ok with the closure’s result if the closure does
-/// not panic, and will return `Err(Panicked)` if the closure panics.
+/// not panic, and will return `Err(Panicked)` if the closure panics. The
+/// [`Panicked`] value contains the panic message and location.
 ///
 /// Different than [`catch_unwind`](std::panic::catch_unwind), this function
 /// doesn't depend on unwinding to work. This allows it to work in lunatic
@@ -17,17 +214,212 @@ pub struct Panicked;
 /// general try/catch mechanism. The `Result` type is more appropriate to use
 /// for functions that can fail on a regular basis.
 pub fn catch_panic<R, F: FnOnce() -> R>(f: F) -> Result<R, Panicked> {
+    record_panics();
+    LAST_PANIC.set(None);
     let function = Box::new(f);
     let raw_function = Box::<F>::into_raw(function) as usize;
+    CATCH_DEPTH.set(CATCH_DEPTH.get() + 1);
     let raw_result =
         unsafe { host::api::trap::catch(re_entry::<R, F> as usize, raw_function) } as *mut R;
+    CATCH_DEPTH.set(CATCH_DEPTH.get() - 1);
     if raw_result.is_null() {
-        Err(Panicked)
+        Err(LAST_PANIC.take().unwrap_or_else(Panicked::trap))
     } else {
         Ok(*unsafe { Box::<R>::from_raw(raw_result) })
     }
 }
 
+/// A report about a panic that is going to terminate the process.
+///
+/// It's passed to the hook set with [`set_hook`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// Id of the process that panicked.
+    pub process_id: u64,
+    /// Id of the node running the process.
+    pub node_id: u64,
+    /// Time of the panic.
+    pub timestamp: SystemTime,
+    /// Type of the last message handled by the process, if known.
+    ///
+    /// It's only tracked for messages handled by an
+    /// [`AbstractProcess`](crate::AbstractProcess).
+    pub message_type: Option<String>,
+    /// Information about the panic.
+    pub panic: Panicked,
+}
+
+process_local! {
+    static PROCESS_HOOK: Cell<Option<fn(&CrashReport)>> = Cell::new(None);
+    // Set if the hook should be installed in spawned processes.
+    static INHERIT_HOOK: Cell<bool> = Cell::new(false);
+    // Set while the hook is running, to prevent recursion.
+    static IN_HOOK: Cell<bool> = Cell::new(false);
+    static HANDLED_MESSAGE: Cell<Option<&'static str>> = Cell::new(None);
+}
+
+/// Registers a panic hook for the current process.
+///
+/// The hook is called with a [`CrashReport`] when the process panics, before
+/// the process terminates and its links are notified. This makes it possible
+/// to send crash reports to a central process, e.g.:
+///
+/// ```
+/// use lunatic::panic::CrashReport;
+/// use lunatic::Process;
+///
+/// fn report(crash: &CrashReport) {
+///     if let Some(collector) = Process::<CrashReport>::lookup("crash_collector") {
+///         collector.send(crash.clone());
+///     }
+/// }
+///
+/// lunatic::panic::set_hook(report);
+/// ```
+///
+/// Panics caught with [`catch_panic`] don't invoke the hook. If the hook
+/// itself panics, the process is terminated without calling the hook again.
+///
+/// The hook is scoped to the current process. Use [`inherit_hook`] to also
+/// install it into processes spawned from the current one.
+pub fn set_hook(hook: fn(&CrashReport)) {
+    record_panics();
+    PROCESS_HOOK.set(Some(hook));
+}
+
+/// Unregisters the panic hook of the current process and returns it.
+pub fn take_hook() -> Option<fn(&CrashReport)> {
+    PROCESS_HOOK.take()
+}
+
+/// Sets if the panic hook of the current process should be installed into
+/// processes spawned from it.
+///
+/// Spawned processes also inherit this setting, so that the hook is installed
+/// into the whole tree of processes. It only applies to processes spawned on
+/// the same node, from the module of the current process. Processes spawned
+/// from other modules, e.g. with
+/// [`WasmModule::spawn`](crate::WasmModule::spawn), or on other nodes don't
+/// inherit it.
+pub fn inherit_hook(inherit: bool) {
+    INHERIT_HOOK.set(inherit);
+}
+
+/// Returns the hook that should be installed in processes spawned from the
+/// current one.
+pub(crate) fn inherited_hook() -> Option<fn(&CrashReport)> {
+    if INHERIT_HOOK.get() {
+        PROCESS_HOOK.get()
+    } else {
+        None
+    }
+}
+
+/// Installs the hook inherited from the parent.
+pub(crate) fn install_inherited_hook(hook: fn(&CrashReport)) {
+    set_hook(hook);
+    inherit_hook(true);
+}
+
+/// Remembers the type of the message currently handled by the process.
+pub(crate) fn set_handled_message(message_type: &'static str) {
+    HANDLED_MESSAGE.set(Some(message_type));
+}
+
+fn run_process_hook(panicked: &Panicked) {
+    let hook = match PROCESS_HOOK.get() {
+        Some(hook) => hook,
+        None => return,
+    };
+    if IN_HOOK.replace(true) {
+        return;
+    }
+    let report = CrashReport {
+        process_id: host::process_id(),
+        node_id: host::node_id(),
+        timestamp: SystemTime::now(),
+        message_type: HANDLED_MESSAGE.get().map(str::to_owned),
+        panic: panicked.clone(),
+    };
+    hook(&report);
+    IN_HOOK.set(false);
+}
+
+// Reserved tag used to send the parent's reference to a newly spawned linked
+// process.
+pub(crate) const LINK_PANIC_PARENT_TAG: i64 = 2;
+// Reserved tag used by linked processes to report panics to the parent.
+pub(crate) const LINK_PANIC_TAG: i64 = 3;
+
+process_local! {
+    // Set if linked processes should report panics to this process.
+    static REPORT_LINK_PANICS: Cell<bool> = Cell::new(false);
+    // Panics of linked processes, by link tag.
+    static LINK_PANICS: RefCell<HashMap<i64, Panicked>> = RefCell::new(HashMap::new());
+}
+
+/// Returns information about the panic of a linked process.
+///
+/// After a [`MailboxResult::LinkDied(tag)`](crate::MailboxResult::LinkDied)
+/// is received, this function can be used to get the reason why the linked
+/// process spawned with `tag` died. It returns `None` if the process didn't
+/// report a panic (e.g. it was killed).
+///
+/// Only processes spawned after the current process started catching link
+/// failures (with [`Mailbox::catch_link_failure`](crate::Mailbox::catch_link_failure)
+/// or [`Config::die_if_link_dies`](crate::ap::Config::die_if_link_dies))
+/// report panics.
+pub fn link_panic(tag: Tag) -> Option<Panicked> {
+    // Collect reports that are still waiting in the mailbox.
+    let tags = [LINK_PANIC_TAG];
+    while unsafe { message::receive(tags.as_ptr(), tags.len(), 0) } != TIMEOUT {
+        store_link_panic();
+    }
+    LINK_PANICS.with_borrow_mut(|mut panics| panics.remove(&tag.id()))
+}
+
+/// Enables or disables panic reports from linked processes.
+pub(crate) fn report_link_panics(report: bool) {
+    REPORT_LINK_PANICS.set(report);
+}
+
+/// Returns true if newly spawned linked processes should report panics.
+pub(crate) fn reports_link_panics() -> bool {
+    REPORT_LINK_PANICS.get()
+}
+
+/// Stores the report of a linked process that is currently in the message
+/// buffer.
+pub(crate) fn store_link_panic() {
+    if let Ok((tag, panicked)) = <Bincode as CanSerialize<(Tag, Panicked)>>::decode() {
+        LINK_PANICS.with_borrow_mut(|mut panics| panics.insert(tag.id(), panicked));
+    }
+}
+
+/// Sends the parent's reference to a newly spawned linked process, before any
+/// other message.
+pub(crate) fn send_link_parent(process_id: u64, link: Tag) {
+    let child = Process::<(Process<()>, Tag)>::new(host::node_id(), process_id);
+    child.tag_send(Tag::from(LINK_PANIC_PARENT_TAG), (Process::this(), link));
+}
+
+/// Called at the start of a linked process. It waits for the parent's reference
+/// and reports a panic to it before the process dies.
+pub(crate) fn report_panics_to_parent() {
+    let tags = [LINK_PANIC_PARENT_TAG];
+    unsafe { message::receive(tags.as_ptr(), tags.len(), u64::MAX) };
+    let (parent, link): (Process<()>, Tag) = Bincode::decode().unwrap();
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let panicked = Panicked::from_hook(info.payload(), info.location());
+        unsafe { message::create_data(LINK_PANIC_TAG, 0) };
+        if Bincode::encode(&(link, panicked)).is_ok() {
+            host::send(parent.node_id(), parent.id());
+        }
+        previous_hook(info);
+    }));
+}
+
 /// Wrapper function to help transfer the generic types R & F through the host.
 fn re_entry<R, F: FnOnce() -> R>(pointer: usize) -> usize {
     let function = unsafe { Box::<F>::from_raw(pointer as *mut F) };
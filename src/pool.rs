@@ -0,0 +1,418 @@
+//! Pools of identical [`AbstractProcess`] workers behind a single handle.
+//!
+//! A [`Pool`] is an abstract process that starts `size` linked workers and
+//! restarts them if they fail, similar to a [`Supervisor`](crate::supervisor)
+//! with a dynamic number of children. Requests and messages sent through a
+//! [`PoolRef`] are routed to one of the workers, based on the pool's
+//! [`Routing`] strategy.
+//!
+//! # Example
+//!
+//! ```
+//! let pool = Pool::<Counter>::start(4, 0).unwrap();
+//! pool.send(Increment);
+//! let count = pool.request(Count).unwrap();
+//! pool.resize(8);
+//! ```
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::ap::handlers::{Message, Request};
+use crate::ap::messages::RequestMessage;
+use crate::ap::{
+    AbstractProcess, Config, MessageHandler, ProcessRef, RequestHandler, StartupError, State,
+};
+use crate::serializer::{Bincode, CanSerialize};
+use crate::time::Timeout;
+use crate::{Process, Tag};
+
+/// Decides which worker receives the next request or message.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Routing {
+    /// Workers are used one after another.
+    #[default]
+    RoundRobin,
+    /// The worker with the smallest number of in-flight requests is used.
+    LeastBusy,
+}
+
+/// Error returned from [`PoolRef::request`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+    /// The worker handling the request died before responding.
+    #[error("the worker died while handling the request")]
+    WorkerDied,
+    /// The worker didn't respond in time.
+    #[error("the request timed out")]
+    Timeout,
+    /// The pool doesn't have any workers.
+    #[error("the pool doesn't have any workers")]
+    Empty,
+}
+
+/// Metrics of a single worker, returned from [`PoolRef::stats`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerStats {
+    /// Process ID of the currently running worker.
+    pub process_id: u64,
+    /// Number of requests sent to the worker that didn't complete yet.
+    pub in_flight: usize,
+    /// How many times the worker was restarted.
+    pub restarts: u64,
+}
+
+/// An [`AbstractProcess`] managing a pool of `W` workers.
+pub struct Pool<W>(PhantomData<W>);
+
+impl<W> Pool<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    /// Starts a pool of `size` workers with round-robin routing.
+    ///
+    /// Each worker is started with a copy of `arg`.
+    pub fn start(size: usize, arg: W::Arg) -> Result<PoolRef<W>, StartupError<Self>> {
+        Self::start_routing(size, Routing::default(), arg)
+    }
+
+    /// Starts a pool of `size` workers with the `routing` strategy.
+    pub fn start_routing(
+        size: usize,
+        routing: Routing,
+        arg: W::Arg,
+    ) -> Result<PoolRef<W>, StartupError<Self>> {
+        <Self as AbstractProcess>::start((size, routing, arg)).map(|pool| PoolRef { pool })
+    }
+}
+
+/// State of a [`Pool`].
+pub struct PoolState<W: AbstractProcess> {
+    arg: W::Arg,
+    routing: Routing,
+    next: usize,
+    workers: Vec<Worker<W>>,
+}
+
+struct Worker<W: AbstractProcess> {
+    process: ProcessRef<W>,
+    link_tag: Tag,
+    /// Callers waiting on a response, with the tag used to notify them if the
+    /// worker dies.
+    in_flight: Vec<(Process<()>, Tag)>,
+    restarts: u64,
+}
+
+impl<W> PoolState<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone,
+{
+    fn start_worker(&self) -> (ProcessRef<W>, Tag) {
+        let link_tag = Tag::new();
+        match W::link_with(link_tag).start(self.arg.clone()) {
+            Ok(process) => (process, link_tag),
+            Err(err) => panic!("Pool failed to start worker `{:?}`", err),
+        }
+    }
+
+    fn next_worker(&mut self) -> Option<usize> {
+        if self.workers.is_empty() {
+            return None;
+        }
+        let start = self.next % self.workers.len();
+        self.next = start + 1;
+        let index = match self.routing {
+            Routing::RoundRobin => start,
+            Routing::LeastBusy => (0..self.workers.len())
+                .map(|offset| (start + offset) % self.workers.len())
+                .min_by_key(|&index| self.workers[index].in_flight.len())
+                .unwrap(),
+        };
+        Some(index)
+    }
+}
+
+impl<W> AbstractProcess for Pool<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Arg = (usize, Routing, W::Arg);
+    type State = PoolState<W>;
+    type Serializer = Bincode;
+    type Handlers = (
+        Request<Checkout>,
+        Message<Checkin>,
+        Request<Resize>,
+        Request<GetStats>,
+    );
+    type StartupError = ();
+
+    fn init(config: Config<Self>, (size, routing, arg): Self::Arg) -> Result<PoolState<W>, ()> {
+        // The pool shouldn't die if the workers die
+        config.die_if_link_dies(false);
+
+        let mut state = PoolState {
+            arg,
+            routing,
+            next: 0,
+            workers: Vec::with_capacity(size),
+        };
+        resize(&mut state, size);
+        Ok(state)
+    }
+
+    fn terminate(mut state: PoolState<W>) {
+        resize(&mut state, 0);
+    }
+
+    fn handle_link_death(mut state: State<Self>, tag: Tag) {
+        // Workers removed during a resize are not restarted.
+        let Some(index) = state.workers.iter().position(|w| w.link_tag == tag) else {
+            return;
+        };
+        let (process, link_tag) = state.start_worker();
+        let worker = &mut state.workers[index];
+        // Callers waiting on the dead worker fail right away.
+        for (caller, abort_tag) in worker.in_flight.drain(..) {
+            caller.tag_send(abort_tag, ());
+        }
+        worker.process = process;
+        worker.link_tag = link_tag;
+        worker.restarts += 1;
+    }
+}
+
+/// Starts or shuts down workers until the pool has `size` of them.
+fn resize<W>(state: &mut PoolState<W>, size: usize)
+where
+    W: AbstractProcess,
+    W::Arg: Clone,
+{
+    while state.workers.len() < size {
+        let (process, link_tag) = state.start_worker();
+        state.workers.push(Worker {
+            process,
+            link_tag,
+            in_flight: Vec::new(),
+            restarts: 0,
+        });
+    }
+    // Workers handle all queued requests before shutting down.
+    for worker in state.workers.drain(size..).rev() {
+        worker.process.unlink();
+        worker.process.shutdown();
+    }
+}
+
+/// Picks the next worker.
+///
+/// If a caller is included, the request is tracked as in-flight until the
+/// caller sends a [`Checkin`].
+#[derive(Serialize, Deserialize)]
+pub struct Checkout(Option<(Process<()>, Tag)>);
+
+impl<W> RequestHandler<Checkout> for Pool<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Response = Option<(usize, ProcessRef<W>)>;
+
+    fn handle(mut state: State<Self>, Checkout(caller): Checkout) -> Self::Response {
+        let index = state.next_worker()?;
+        let worker = &mut state.workers[index];
+        if let Some(caller) = caller {
+            worker.in_flight.push(caller);
+        }
+        Some((index, worker.process))
+    }
+}
+
+/// Marks an in-flight request as completed.
+#[derive(Serialize, Deserialize)]
+pub struct Checkin(usize, Tag);
+
+impl<W> MessageHandler<Checkin> for Pool<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    fn handle(mut state: State<Self>, Checkin(index, abort_tag): Checkin) {
+        // The worker could have been removed or restarted in the meantime.
+        if let Some(worker) = state.workers.get_mut(index) {
+            worker.in_flight.retain(|(_, tag)| *tag != abort_tag);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Resize(usize);
+
+impl<W> RequestHandler<Resize> for Pool<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Response = ();
+
+    fn handle(mut state: State<Self>, Resize(size): Resize) {
+        resize(&mut *state, size);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetStats;
+
+impl<W> RequestHandler<GetStats> for Pool<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Response = Vec<WorkerStats>;
+
+    fn handle(state: State<Self>, _: GetStats) -> Self::Response {
+        state
+            .workers
+            .iter()
+            .map(|worker| WorkerStats {
+                process_id: worker.process.id(),
+                in_flight: worker.in_flight.len(),
+                restarts: worker.restarts,
+            })
+            .collect()
+    }
+}
+
+/// A reference to a running [`Pool`].
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct PoolRef<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    pool: ProcessRef<Pool<W>>,
+}
+
+impl<W> PoolRef<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    /// Sends a message to one of the workers.
+    ///
+    /// The message is dropped if the pool doesn't have any workers.
+    pub fn send<M: 'static>(&self, message: M)
+    where
+        W: MessageHandler<M>,
+        W::Serializer: CanSerialize<M>,
+    {
+        if let Some((_, worker)) = self.pool.request(Checkout(None)) {
+            worker.send(message);
+        }
+    }
+
+    /// Makes a request to one of the workers.
+    ///
+    /// If the worker dies before responding, `Err(PoolError::WorkerDied)` is
+    /// returned.
+    pub fn request<R: 'static>(&self, request: R) -> Result<W::Response, PoolError>
+    where
+        W: RequestHandler<R>,
+        W::Serializer: CanSerialize<R>,
+        W::Serializer: CanSerialize<W::Response>,
+        W::Serializer: CanSerialize<RequestMessage<R, W::Response, W::Serializer>>,
+    {
+        self.request_(request, None)
+    }
+
+    /// Makes a request to one of the workers, waiting at most `timeout` on the
+    /// response.
+    pub fn request_timeout<R: 'static>(
+        &self,
+        request: R,
+        timeout: Duration,
+    ) -> Result<W::Response, PoolError>
+    where
+        W: RequestHandler<R>,
+        W::Serializer: CanSerialize<R>,
+        W::Serializer: CanSerialize<W::Response>,
+        W::Serializer: CanSerialize<RequestMessage<R, W::Response, W::Serializer>>,
+    {
+        self.request_(request, Some(timeout))
+    }
+
+    fn request_<R: 'static>(
+        &self,
+        request: R,
+        timeout: Option<Duration>,
+    ) -> Result<W::Response, PoolError>
+    where
+        W: RequestHandler<R>,
+        W::Serializer: CanSerialize<R>,
+        W::Serializer: CanSerialize<W::Response>,
+        W::Serializer: CanSerialize<RequestMessage<R, W::Response, W::Serializer>>,
+    {
+        let abort_tag = Tag::new();
+        let (index, worker) = self
+            .pool
+            .request(Checkout(Some((Process::this(), abort_tag))))
+            .ok_or(PoolError::Empty)?;
+        let result = worker.request_abortable(request, abort_tag, timeout);
+        self.pool.send(Checkin(index, abort_tag));
+        match result {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(Timeout)) => Err(PoolError::Timeout),
+            None => Err(PoolError::WorkerDied),
+        }
+    }
+
+    /// Starts or shuts down workers until the pool has `size` of them.
+    pub fn resize(&self, size: usize) {
+        self.pool.request(Resize(size));
+    }
+
+    /// Returns the metrics of each worker.
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.pool.request(GetStats)
+    }
+
+    /// Shuts the pool and all workers down.
+    pub fn shutdown(&self) {
+        self.pool.shutdown();
+    }
+}
+
+impl<W> Clone for PoolRef<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<W> Copy for PoolRef<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+}
+
+impl<W> std::fmt::Debug for PoolRef<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolRef").field("pool", &self.pool).finish()
+    }
+}
@@ -1,11 +1,20 @@
-use std::any::TypeId;
+use std::any::{type_name, TypeId};
+use std::fmt;
 use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
 use std::time::Duration;
 
+use crate::ap::self_send;
 use crate::function::process::IntoProcess;
+use crate::host::api::message;
+use crate::mailbox::TIMEOUT;
+use crate::select::SelectProtocol;
 use crate::serializer::{Bincode, CanSerialize};
-use crate::{host, Mailbox, MailboxResult, Process, ProcessConfig, Tag};
+use crate::{host, time, Mailbox, MailboxResult, Process, ProcessConfig, Tag};
+
+/// Bit of the tags reserved for abort notifications of protocol sessions, see
+/// [`Tag`] for the tag ranges.
+const ABORT_BIT: i64 = 1 << 62;
 
 /// A value that the protocol captures from the parent process.
 ///
@@ -34,18 +43,46 @@ pub struct Protocol<P: 'static, S = Bincode, Z: 'static = ()> {
     phantom: PhantomData<(P, S, Z)>,
 }
 
+/// Dropping a protocol before it reached the `End` or `TaskEnd` state will
+/// abort the session and notify the other side.
+///
+/// The other side will observe the abort as an [`Aborted`] error on the next
+/// `try_*` call, or as a panic on the next regular protocol call.
 impl<P: 'static, S, Z: 'static> Drop for Protocol<P, S, Z> {
     fn drop(&mut self) {
         if TypeId::of::<P>() != TypeId::of::<End>() && TypeId::of::<P>() != TypeId::of::<TaskEnd>()
         {
-            panic!(
-                "Protocol prematurely dropped, before reaching the `End` or `TaskEnd` state (currently: {}).",
-                std::any::type_name::<P>()
-            );
+            self.notify_abort("protocol dropped before reaching the `End` or `TaskEnd` state");
         }
     }
 }
 
+/// A notification that the other side of the session aborted it.
+///
+/// A session is aborted if the other side explicitly calls
+/// [`abort`](Protocol::abort) or drops the protocol before reaching the `End`
+/// or `TaskEnd` state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aborted {
+    /// The protocol state of the other side at the moment it aborted the
+    /// session.
+    pub step: String,
+    /// The reason for aborting the session.
+    pub reason: String,
+}
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Protocol session aborted by the other side in state `{}`: {}",
+            self.step, self.reason
+        )
+    }
+}
+
+impl std::error::Error for Aborted {}
+
 impl<P, S, Z> Protocol<P, S, Z> {
     /// Turn a process into a protocol
     fn from_process<M, S2>(process: Process<M, S2>, tag: Tag) -> Self {
@@ -70,6 +107,61 @@ impl<P, S, Z> Protocol<P, S, Z> {
             phantom: PhantomData,
         }
     }
+
+    /// Abort the session, notifying the other side with a `reason`.
+    ///
+    /// The other side will receive an [`Aborted`] error containing the
+    /// `reason` and the current protocol state of this side.
+    pub fn abort<R: AsRef<str>>(self, reason: R) {
+        // The notification replaces the drop, so that only one is sent.
+        let self_ = ManuallyDrop::new(self);
+        self_.notify_abort(reason.as_ref());
+    }
+
+    /// Sends an abort notification to the other side.
+    fn notify_abort(&self, reason: &str) {
+        let notification = (type_name::<P>().to_owned(), reason.to_owned());
+        unsafe { message::create_data(abort_tag(self.tag).id(), 0) };
+        // Abort notifications don't depend on the session serializer, so that they
+        // can always be delivered.
+        Bincode::encode(&notification).unwrap();
+        host::send(self.node_id, self.id);
+    }
+}
+
+/// Returns the tag used to deliver abort notifications for the session `tag`.
+///
+/// Session tags are created by [`Tag::new`] or [`Tag::new_secure`] and stay
+/// below `2^56`. Abort notifications set [`ABORT_BIT`], which no other tag
+/// uses, so they never mix with regular session messages or named tags.
+fn abort_tag(tag: Tag) -> Tag {
+    Tag::from(tag.id() | ABORT_BIT)
+}
+
+/// Waits on the next message of the session identified by `tag`, or on an abort
+/// notification for it.
+fn session_receive<A, S>(tag: Tag, timeout: Option<Duration>) -> Result<MailboxResult<A>, Aborted>
+where
+    S: CanSerialize<A>,
+{
+    let abort_tag = abort_tag(tag);
+    let tags = [tag.id(), abort_tag.id()];
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_millis() as u64,
+        None => u64::MAX,
+    };
+    let message_type = crate::virtual_time::receive(&tags, timeout_ms);
+    if message_type == TIMEOUT {
+        return Ok(MailboxResult::TimedOut);
+    }
+    if unsafe { message::get_tag() } == abort_tag.id() {
+        let (step, reason): (String, String) = Bincode::decode().unwrap();
+        return Err(Aborted { step, reason });
+    }
+    match S::decode() {
+        Ok(message) => Ok(MailboxResult::Message(message)),
+        Err(err) => Ok(MailboxResult::DeserializationFailed(err)),
+    }
 }
 
 impl<P, A, S, Z> Protocol<Send<A, P>, S, Z>
@@ -95,12 +187,107 @@ where
 {
     /// Receives a value of type `A` from the session. Returns a tuple
     /// containing the resulting session and the received value.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the other side aborted the session.
     #[must_use]
+    #[track_caller]
     pub fn receive(self) -> (Protocol<P, S, Z>, A) {
-        // Temporarily cast to right mailbox type.
-        let mailbox: Mailbox<A, S> = unsafe { Mailbox::new() };
-        let received = mailbox.tag_receive(&[self.tag]);
-        (self.cast(), received)
+        match self.try_receive() {
+            Ok(result) => result,
+            Err(aborted) => panic!("{}", aborted),
+        }
+    }
+
+    /// Same as `receive`, but returns an [`Aborted`] error instead of
+    /// panicking if the other side aborted the session.
+    #[track_caller]
+    pub fn try_receive(self) -> Result<(Protocol<P, S, Z>, A), Aborted> {
+        let tag = self.tag;
+        match session_receive::<A, S>(tag, None) {
+            Ok(received) => Ok((
+                self.cast(),
+                received.expect_message("Failed to receive a session message"),
+            )),
+            Err(aborted) => {
+                // The session is already gone, don't notify the other side.
+                std::mem::forget(self);
+                Err(aborted)
+            }
+        }
+    }
+}
+
+impl<P, A, S, Z> SelectProtocol for Protocol<Recv<A, P>, S, Z>
+where
+    S: CanSerialize<A>,
+{
+    type Output = (Protocol<P, S, Z>, A);
+
+    fn tags(&self) -> [Tag; 2] {
+        [self.tag, abort_tag(self.tag)]
+    }
+
+    #[track_caller]
+    fn decode(self, tag: Tag) -> Self::Output {
+        if tag == abort_tag(self.tag) {
+            let (step, reason): (String, String) = Bincode::decode().unwrap();
+            std::mem::forget(self);
+            panic!("{}", Aborted { step, reason })
+        }
+        (self.cast(), S::decode().unwrap())
+    }
+}
+
+impl<P, A, S, Z> Protocol<Recv<A, P>, S, Z>
+where
+    S: CanSerialize<A>,
+{
+    /// Waits on the next value of type `A` from the session, or on the next
+    /// message arriving in the process' `mailbox`, whichever comes first.
+    ///
+    /// If a value from the session is received, the resulting session is
+    /// returned together with it as [`ProtocolOrMailbox::ProtocolMsg`]. If a
+    /// mailbox message is received, the unchanged session is returned together
+    /// with the message as [`ProtocolOrMailbox::MailboxMsg`], so that it can be
+    /// used to wait again.
+    ///
+    /// Only untagged messages, sent with [`Process::send`], are considered
+    /// mailbox messages.
+    ///
+    /// # Fairness
+    ///
+    /// Both sources are waited on with a single host call. The message that
+    /// arrived first in the mailbox is always returned first, independent of
+    /// its source. This means that neither the session nor the mailbox can
+    /// starve the other one.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the other side aborted the session or if
+    /// the received message can't be deserialized.
+    #[track_caller]
+    pub fn receive_or_mailbox<M, MS>(
+        self,
+        _mailbox: &Mailbox<M, MS>,
+    ) -> ProtocolOrMailbox<Protocol<P, S, Z>, A, Self, M>
+    where
+        MS: CanSerialize<M>,
+    {
+        let abort_tag = abort_tag(self.tag);
+        let tags = [self.tag.id(), abort_tag.id(), Tag::none().id()];
+        crate::virtual_time::receive(&tags, u64::MAX);
+        let tag = unsafe { message::get_tag() };
+        if tag == Tag::none().id() {
+            ProtocolOrMailbox::MailboxMsg(self, self_send::decode::<M, MS>().unwrap())
+        } else if tag == abort_tag.id() {
+            let (step, reason): (String, String) = Bincode::decode().unwrap();
+            std::mem::forget(self);
+            panic!("{}", Aborted { step, reason })
+        } else {
+            ProtocolOrMailbox::ProtocolMsg(self.cast(), S::decode().unwrap())
+        }
     }
 }
 
@@ -111,22 +298,34 @@ where
     /// A task is a special case of a protocol spawned with the `spawn!(@task
     /// ...)` macro. It only returns one value.
     #[must_use]
+    #[track_caller]
     pub fn result(self) -> A {
-        // Temporarily cast to right mailbox type.
-        let mailbox: Mailbox<A, S> = unsafe { Mailbox::new() };
-        let result = mailbox.tag_receive(&[self.tag]);
-        let _: Protocol<TaskEnd, S, Z> = self.cast(); // Only `End` protocols can be dropped
-        result
+        match session_receive::<A, S>(self.tag, None) {
+            Ok(result) => {
+                let _: Protocol<TaskEnd, S, Z> = self.cast(); // Only `End` protocols can be dropped
+                result.expect_message("Failed to receive the task result")
+            }
+            Err(aborted) => {
+                std::mem::forget(self);
+                panic!("{}", aborted)
+            }
+        }
     }
 
     /// A task is a special case of a protocol spawned with the `spawn!(@task
     /// ...)` macro. It only returns one value.
+    #[track_caller]
     pub fn result_timeout(self, duration: Duration) -> MailboxResult<A> {
-        // Temporarily cast to right mailbox type.
-        let mailbox: Mailbox<A, S> = unsafe { Mailbox::new() };
-        let result = mailbox.tag_receive_timeout(&[self.tag], duration);
-        let _: Protocol<TaskEnd, S, Z> = self.cast(); // Only `End` protocols can be dropped
-        result
+        match session_receive::<A, S>(self.tag, Some(duration)) {
+            Ok(result) => {
+                let _: Protocol<TaskEnd, S, Z> = self.cast(); // Only `End` protocols can be dropped
+                result
+            }
+            Err(aborted) => {
+                std::mem::forget(self);
+                panic!("{}", aborted)
+            }
+        }
     }
 }
 
@@ -163,15 +362,35 @@ where
 {
     /// Passive choice. This allows the other end of the session to select one
     /// of two options for continuing the protocol: either `P` or `Q`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the other side aborted the session.
     #[must_use]
+    #[track_caller]
     pub fn offer(self) -> Branch<Protocol<P, S, Z>, Protocol<Q, S, Z>> {
-        unsafe {
-            // Temporarily cast to right mailbox type.
-            let mailbox: Mailbox<bool, S> = Mailbox::new();
-            if mailbox.receive() {
-                Branch::Left(self.cast())
-            } else {
-                Branch::Right(self.cast())
+        match self.try_offer() {
+            Ok(branch) => branch,
+            Err(aborted) => panic!("{}", aborted),
+        }
+    }
+
+    /// Same as `offer`, but returns an [`Aborted`] error instead of panicking
+    /// if the other side aborted the session.
+    #[track_caller]
+    #[allow(clippy::type_complexity)]
+    pub fn try_offer(self) -> Result<Branch<Protocol<P, S, Z>, Protocol<Q, S, Z>>, Aborted> {
+        match session_receive::<bool, S>(self.tag, None) {
+            Ok(choice) => {
+                if choice.unwrap() {
+                    Ok(Branch::Left(self.cast()))
+                } else {
+                    Ok(Branch::Right(self.cast()))
+                }
+            }
+            Err(aborted) => {
+                std::mem::forget(self);
+                Err(aborted)
             }
         }
     }
@@ -269,6 +488,254 @@ pub enum Branch<L, R> {
     Right(R),
 }
 
+/// Result of [`Protocol::receive_or_mailbox`].
+pub enum ProtocolOrMailbox<N, A, C, M> {
+    /// A value of type `A` was received from the session. `N` is the resulting
+    /// session.
+    ProtocolMsg(N, A),
+    /// A message of type `M` was received from the mailbox. `C` is the
+    /// unchanged session.
+    MailboxMsg(C, M),
+}
+
+/// Accepts sessions of protocol `P` from many clients.
+///
+/// Processes spawned with a [`Protocol`] have one session with their parent.
+/// A listener lets a server process speak the same protocol with any number
+/// of clients instead. The server hands out its [`SessionServer`] handle, and
+/// each client that [`connect`]s to it gets a `Protocol<P, S>`, while
+/// [`accept`](SessionListener::accept) returns the matching
+/// `Protocol<P::Dual, S>` on the server side. The sessions are independent of
+/// each other and of the listener, they are typically handed to a new process
+/// per session.
+///
+/// # Handshake
+///
+/// A client sends the type names of its protocol and serializer to the
+/// listener with a tag that only the listener receives. If they don't match
+/// the ones of the listener, e.g. because the handle was deserialized as
+/// another protocol, the client is rejected with
+/// [`ConnectError::Rejected`] before any session message is sent. Otherwise
+/// the listener replies with a new random session tag, so that sessions of
+/// different clients don't mix in the mailbox of the server. Handshake
+/// messages are always serialized with [`Bincode`].
+///
+/// Clients are only accepted while the server waits in `accept`, until then
+/// they wait in [`connect`].
+///
+/// # Example
+///
+/// ```
+/// use lunatic::protocol::{self, End, Recv, Send, SessionListener};
+/// use lunatic::{Mailbox, Process};
+///
+/// type Greet = Send<String, Recv<String, End>>;
+///
+/// let listener = SessionListener::<Greet>::new();
+/// Process::spawn(listener.server(), |server, _: Mailbox<()>| {
+///     let session = protocol::connect(&server).unwrap();
+///     let (_, greeting) = session.send("world".to_owned()).receive();
+///     println!("{}", greeting);
+/// });
+/// let (session, name) = listener.accept().receive();
+/// let _ = session.send(format!("Hello {}", name));
+/// ```
+pub struct SessionListener<P: 'static, S = Bincode> {
+    tag: Tag,
+    phantom: PhantomData<(P, S)>,
+}
+
+/// A handle to a [`SessionListener`] that clients [`connect`] to.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(bound = "")]
+pub struct SessionServer<P: 'static, S = Bincode> {
+    process: Process<()>,
+    tag: Tag,
+    phantom: PhantomData<(P, S)>,
+}
+
+impl<P, S> Clone for SessionServer<P, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P, S> Copy for SessionServer<P, S> {}
+
+impl<P, S> fmt::Debug for SessionServer<P, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionServer")
+            .field("process", &self.process)
+            .field("tag", &self.tag)
+            .finish()
+    }
+}
+
+/// The first message of a client, see [Handshake](SessionListener#handshake).
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Hello {
+    client: Process<()>,
+    reply_tag: Tag,
+    protocol: String,
+    serializer: String,
+}
+
+/// The listener's reply to a [`Hello`], the session tag or why the client was
+/// rejected.
+type Welcome = Result<Tag, String>;
+
+/// Error returned from [`connect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectError {
+    /// The listener expects another protocol or serializer.
+    Rejected(String),
+    /// The listener didn't accept the session in time.
+    TimedOut,
+    /// The local listener process was already dead when the client connected.
+    ServerDied,
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Rejected(reason) => write!(f, "Session rejected: {}", reason),
+            ConnectError::TimedOut => write!(f, "Session not accepted in time"),
+            ConnectError::ServerDied => write!(f, "Session server died"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl<P, S> SessionListener<P, S>
+where
+    P: HasDual,
+    P::Dual: 'static,
+{
+    /// Creates a listener in the current process.
+    pub fn new() -> Self {
+        SessionListener {
+            tag: Tag::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the handle that clients [`connect`] to.
+    pub fn server(&self) -> SessionServer<P, S> {
+        SessionServer {
+            process: Process::this(),
+            tag: self.tag,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Waits on the next client and returns the server side of its session.
+    ///
+    /// Clients with another protocol are rejected while waiting.
+    pub fn accept(&self) -> Protocol<P::Dual, S> {
+        loop {
+            if let Some(session) = self.accept_(None) {
+                return session;
+            }
+        }
+    }
+
+    /// Same as [`accept`](SessionListener::accept), but returns `None` if no
+    /// client connected within `timeout`.
+    pub fn accept_timeout(&self, timeout: Duration) -> Option<Protocol<P::Dual, S>> {
+        let deadline = time::instant() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(time::instant());
+            match self.accept_(Some(left)) {
+                Some(session) => return Some(session),
+                None if left.is_zero() => return None,
+                None => (),
+            }
+        }
+    }
+
+    // Handles one handshake, returns `None` if it timed out or the client
+    // was rejected.
+    fn accept_(&self, timeout: Option<Duration>) -> Option<Protocol<P::Dual, S>> {
+        let Ok(MailboxResult::Message(hello)) =
+            session_receive::<Hello, Bincode>(self.tag, timeout)
+        else {
+            return None;
+        };
+        let client = Process::<Welcome>::new(hello.client.node_id(), hello.client.id());
+        let (protocol, serializer) = (type_name::<P>(), type_name::<S>());
+        if hello.protocol != protocol || hello.serializer != serializer {
+            let reason = format!(
+                "expected protocol `{}` with serializer `{}`, got `{}` with `{}`",
+                protocol, serializer, hello.protocol, hello.serializer
+            );
+            client.tag_send(hello.reply_tag, Err(reason));
+            return None;
+        }
+        // Random tags don't mix with the sessions of other clients, or with
+        // the tags of the client.
+        let tag = Tag::new_secure();
+        client.tag_send(hello.reply_tag, Ok(tag));
+        Some(Protocol::from_process(client, tag))
+    }
+}
+
+impl<P, S> Default for SessionListener<P, S>
+where
+    P: HasDual,
+    P::Dual: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connects to the listener of `server`, returning the client side of a new
+/// session.
+///
+/// Blocks until the server accepts the session. If the server is on the
+/// local node and already dead, [`ConnectError::ServerDied`] is returned at
+/// once. A server that dies while the client waits isn't noticed, use
+/// [`connect_timeout`] to give up on it.
+pub fn connect<P, S>(server: &SessionServer<P, S>) -> Result<Protocol<P, S>, ConnectError> {
+    connect_(server, None)
+}
+
+/// Same as [`connect`], but fails with [`ConnectError::TimedOut`] if the
+/// server didn't accept the session within `timeout`.
+pub fn connect_timeout<P, S>(
+    server: &SessionServer<P, S>,
+    timeout: Duration,
+) -> Result<Protocol<P, S>, ConnectError> {
+    connect_(server, Some(timeout))
+}
+
+fn connect_<P, S>(
+    server: &SessionServer<P, S>,
+    timeout: Option<Duration>,
+) -> Result<Protocol<P, S>, ConnectError> {
+    // Remote processes can't be checked.
+    if server.process.node_id() == host::node_id() && !server.process.is_alive() {
+        return Err(ConnectError::ServerDied);
+    }
+    let reply_tag = Tag::new();
+    let listener = Process::<Hello>::new(server.process.node_id(), server.process.id());
+    listener.tag_send(
+        server.tag,
+        Hello {
+            client: Process::this(),
+            reply_tag,
+            protocol: type_name::<P>().to_owned(),
+            serializer: type_name::<S>().to_owned(),
+        },
+    );
+    match session_receive::<Welcome, Bincode>(reply_tag, timeout) {
+        Ok(MailboxResult::Message(Ok(tag))) => Ok(Protocol::from_process(listener, tag)),
+        Ok(MailboxResult::Message(Err(reason))) => Err(ConnectError::Rejected(reason)),
+        _ => Err(ConnectError::TimedOut),
+    }
+}
+
 mod private {
     use super::*;
     pub trait Sealed {}
@@ -0,0 +1,679 @@
+//! Publish-subscribe topics.
+//!
+//! A [`Topic`] is a named process that forwards every published message to all
+//! of its subscribers. Topics are looked up by name & message type, the first
+//! call to [`Topic::new`] on a node spawns the topic process and all following
+//! ones return a handle to it.
+//!
+//! Delivery is at-most-once and messages from the same publisher are received
+//! in the order they were published. Subscribers that died are removed the next
+//! time a message is published to the topic.
+//!
+//! Each subscriber can have its own [`Policy`] for the case that it doesn't
+//! keep up with the publishers. The host can't tell how many messages are
+//! waiting in a mailbox, so subscribers with a bounded policy acknowledge each
+//! message they take out, and the topic keeps count of the messages waiting in
+//! their mailboxes.
+//!
+//! # Example
+//!
+//! ```
+//! let prices = Topic::<f64>::new("prices");
+//! let subscription = prices.subscribe();
+//! prices.publish(1.25);
+//! assert_eq!(subscription.receive(), 1.25);
+//! ```
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::host::api::message;
+use crate::serializer::{Bincode, CanSerialize, DecodeError};
+use crate::{host, metrics, select, Mailbox, MailboxResult, Process, Tag};
+
+/// Tag of published messages.
+const PUBLISH_TAG: i64 = 6;
+/// Tag of subscription requests.
+const SUBSCRIBE_TAG: i64 = 7;
+/// Tag of the notification sent when a subscription is dropped.
+const UNSUBSCRIBE_TAG: i64 = 8;
+/// Tag of the acknowledgment sent after a subscriber received a message.
+const ACK_TAG: i64 = 9;
+/// Tag of published messages whose publisher waits on the number of
+/// subscribers that received them.
+const PUBLISH_CONFIRMED_TAG: i64 = 16;
+
+/// How often a blocked topic checks if the subscribers that block it are
+/// still alive.
+const BLOCKED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Size of the header of confirmed publishes, the node, process and tag to
+/// reply to.
+const CONFIRMED_HEADER_SIZE: usize = 24;
+
+/// Name of the counter that is incremented every time a message is dropped
+/// because a subscriber didn't keep up, labeled by the subscriber.
+pub const DROPPED_COUNTER: &str = "lunatic.pubsub.dropped";
+/// Name of the counter that is incremented every time a subscriber with
+/// [`Policy::Disconnect`] is disconnected, labeled by the subscriber.
+pub const DISCONNECTED_COUNTER: &str = "lunatic.pubsub.disconnected";
+
+/// What happens with messages published to a subscriber that doesn't keep up.
+///
+/// The bounded policies allow at most `n` messages to wait in the subscriber's
+/// mailbox, they differ in what happens with the next ones.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// All messages are delivered to the subscriber's mailbox.
+    #[default]
+    Unbounded,
+    /// At most `n` messages are waiting in the subscriber's mailbox and at most
+    /// `n` more are buffered by the topic. If the buffer is full, the oldest
+    /// buffered message is dropped.
+    DropOldest(usize),
+    /// At most `n` messages are waiting in the subscriber's mailbox and at most
+    /// `n` more are buffered by the topic. If the buffer is full, new messages
+    /// are dropped.
+    DropNewest(usize),
+    /// At most `n` messages are waiting in the subscriber's mailbox. The next
+    /// message unsubscribes the subscriber, which is notified, see
+    /// [`Subscription::is_disconnected`].
+    Disconnect(usize),
+    /// At most `n` messages are waiting in the subscriber's mailbox. The next
+    /// message is buffered by the topic, and the topic doesn't take further
+    /// messages until the subscriber caught up. Publishers waiting in
+    /// [`Topic::publish_confirmed`] are blocked until then, messages sent by
+    /// [`Topic::publish`] queue up in the mailbox of the topic.
+    Block(usize),
+}
+
+impl Policy {
+    /// Returns how many messages can wait in the subscriber's mailbox, or
+    /// `None` if it's unbounded.
+    fn capacity(self) -> Option<usize> {
+        match self {
+            Policy::Unbounded => None,
+            Policy::DropOldest(n)
+            | Policy::DropNewest(n)
+            | Policy::Disconnect(n)
+            | Policy::Block(n) => Some(n),
+        }
+    }
+}
+
+/// Error returned by [`Subscription::try_receive`].
+#[derive(Error, Debug)]
+pub enum SubscriptionError {
+    #[error("the message couldn't be deserialized: {0}")]
+    DeserializationFailed(#[from] DecodeError),
+    #[error("no message was published before the timeout expired")]
+    TimedOut,
+    /// The topic unsubscribed the subscriber, because it didn't keep up.
+    #[error("the subscriber was disconnected because it didn't keep up")]
+    Disconnected,
+}
+
+/// A handle to a named topic.
+///
+/// The topic is identified by its name and the message type `T`. Topics with
+/// the same name and different message types are independent.
+#[derive(Serialize, Deserialize)]
+pub struct Topic<T, S = Bincode> {
+    process: Process<()>,
+    #[serde(skip_serializing, default)]
+    messages: PhantomData<(T, S)>,
+}
+
+impl<T, S> Topic<T, S>
+where
+    S: CanSerialize<T>,
+{
+    /// Returns the topic registered under `name`, or creates it.
+    pub fn new(name: &str) -> Self {
+        Self::with_policy(name, Policy::default())
+    }
+
+    /// Returns the topic registered under `name`, or creates it with the
+    /// default slow-subscriber `policy`.
+    ///
+    /// The policy is only used if the topic doesn't exist yet. Subscribers
+    /// can choose another one, see [`Topic::subscribe_with_policy`].
+    pub fn with_policy(name: &str, policy: Policy) -> Self {
+        let name = format!(
+            "{} + Topic + {}/{}",
+            name,
+            std::any::type_name::<T>(),
+            std::any::type_name::<S>()
+        );
+        let mut node_id: u64 = 0;
+        let mut process_id: u64 = 0;
+        let process = unsafe {
+            match host::api::registry::get_or_put_later(
+                name.as_ptr(),
+                name.len(),
+                &mut node_id,
+                &mut process_id,
+            ) {
+                0 => Process::new(node_id, process_id),
+                _ => {
+                    let process = Process::spawn(policy, topic_process);
+                    host::api::registry::put(
+                        name.as_ptr(),
+                        name.len(),
+                        process.node_id(),
+                        process.id(),
+                    );
+                    process
+                }
+            }
+        };
+        Topic {
+            process,
+            messages: PhantomData,
+        }
+    }
+
+    /// Subscribes the current process to the topic, with the default policy
+    /// of the topic.
+    ///
+    /// Published messages are delivered to the mailbox of the current process,
+    /// tagged with [`Subscription::tag`]. The subscription ends when the
+    /// returned value is dropped.
+    pub fn subscribe(&self) -> Subscription<T, S> {
+        self.subscribe_(None)
+    }
+
+    /// Subscribes the current process to the topic, with the slow-subscriber
+    /// `policy`.
+    pub fn subscribe_with_policy(&self, policy: Policy) -> Subscription<T, S> {
+        self.subscribe_(Some(policy))
+    }
+
+    fn subscribe_(&self, policy: Option<Policy>) -> Subscription<T, S> {
+        let subscriber = Subscriber {
+            node_id: host::node_id(),
+            process_id: host::process_id(),
+            tag: Tag::new(),
+            disconnect_tag: Tag::new(),
+        };
+        let reply = Tag::new();
+        notify(self.process, SUBSCRIBE_TAG, &(subscriber, reply, policy));
+        let mailbox = unsafe { Mailbox::<bool, Bincode>::new() };
+        let acknowledged = mailbox.tag_receive(&[reply]);
+        Subscription {
+            topic: self.process,
+            subscriber,
+            acknowledged,
+            disconnected: Cell::new(false),
+            messages: PhantomData,
+        }
+    }
+
+    /// Publishes `message` to all subscribers of the topic.
+    ///
+    /// Resources, like TCP streams, can't be published.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the message can't be serialized with `S`.
+    pub fn publish(&self, message: T) {
+        unsafe { message::create_data(PUBLISH_TAG, 0) };
+        S::encode(&message).unwrap();
+        host::send(self.process.node_id(), self.process.id());
+    }
+
+    /// Publishes `message` to all subscribers of the topic, and returns how
+    /// many of them received it.
+    ///
+    /// A message is received once it's in the mailbox of the subscriber.
+    /// Messages that are buffered by the topic for slow subscribers with
+    /// [`Policy::DropOldest`] or [`Policy::DropNewest`] don't count. If a
+    /// subscriber with [`Policy::Block`] doesn't keep up, this waits until it
+    /// received the message.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the message can't be serialized with `S`.
+    pub fn publish_confirmed(&self, message: T) -> usize {
+        let reply = Tag::new();
+        let mut header = [0; CONFIRMED_HEADER_SIZE];
+        header[..8].copy_from_slice(&host::node_id().to_le_bytes());
+        header[8..16].copy_from_slice(&host::process_id().to_le_bytes());
+        header[16..].copy_from_slice(&reply.id().to_le_bytes());
+        unsafe {
+            message::create_data(PUBLISH_CONFIRMED_TAG, 0);
+            message::write_data(header.as_ptr(), header.len());
+        }
+        S::encode(&message).unwrap();
+        host::send(self.process.node_id(), self.process.id());
+        unsafe { Mailbox::<usize, Bincode>::new() }.tag_receive(&[reply])
+    }
+}
+
+impl<T, S> Clone for Topic<T, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, S> Copy for Topic<T, S> {}
+
+impl<T, S> std::fmt::Debug for Topic<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Topic")
+            .field("process", &self.process)
+            .finish()
+    }
+}
+
+/// A subscription to a [`Topic`].
+///
+/// Dropping it unsubscribes the current process.
+pub struct Subscription<T, S = Bincode> {
+    topic: Process<()>,
+    subscriber: Subscriber,
+    acknowledged: bool,
+    disconnected: Cell<bool>,
+    messages: PhantomData<(T, S)>,
+}
+
+impl<T, S> Subscription<T, S>
+where
+    S: CanSerialize<T>,
+{
+    /// Waits on the next published message.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the message can't be deserialized, or the subscriber was
+    /// disconnected by [`Policy::Disconnect`].
+    pub fn receive(&self) -> T {
+        match self.try_receive(None) {
+            Ok(message) => message,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Waits on the next published message, until the `timeout` expires.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the subscriber was disconnected by [`Policy::Disconnect`].
+    pub fn receive_timeout(&self, timeout: Duration) -> MailboxResult<T> {
+        match self.try_receive(Some(timeout)) {
+            Ok(message) => MailboxResult::Message(message),
+            Err(SubscriptionError::DeserializationFailed(err)) => {
+                MailboxResult::DeserializationFailed(err)
+            }
+            Err(SubscriptionError::TimedOut) => MailboxResult::TimedOut,
+            Err(err @ SubscriptionError::Disconnected) => panic!("{}", err),
+        }
+    }
+
+    /// Waits on the next published message, until the `timeout` expires, or
+    /// the subscriber is disconnected.
+    ///
+    /// Messages published before the subscriber was disconnected are still
+    /// returned first.
+    pub fn try_receive(&self, timeout: Option<Duration>) -> Result<T, SubscriptionError> {
+        let tags = if self.disconnected.get() {
+            vec![self.subscriber.tag]
+        } else {
+            vec![self.subscriber.tag, self.subscriber.disconnect_tag]
+        };
+        let timeout = match self.disconnected.get() {
+            true => Some(Duration::ZERO),
+            false => timeout,
+        };
+        match select::receive(&tags, timeout) {
+            Some((_, tag)) if tag == self.subscriber.tag => {
+                // The acknowledgement reuses the message buffer.
+                let message = S::decode();
+                self.ack();
+                Ok(message?)
+            }
+            Some(_) => {
+                self.disconnected.set(true);
+                // Messages sent before the notification are still delivered.
+                self.try_receive(None)
+            }
+            None if self.disconnected.get() => Err(SubscriptionError::Disconnected),
+            None => Err(SubscriptionError::TimedOut),
+        }
+    }
+
+    /// Returns `true` if the topic disconnected the subscriber, because it
+    /// didn't keep up, see [`Policy::Disconnect`].
+    ///
+    /// Published messages that are still in the mailbox can be received after
+    /// the subscriber was disconnected.
+    pub fn is_disconnected(&self) -> bool {
+        if !self.disconnected.get()
+            && select::receive(&[self.subscriber.disconnect_tag], Some(Duration::ZERO)).is_some()
+        {
+            self.disconnected.set(true);
+        }
+        self.disconnected.get()
+    }
+
+    /// Returns the tag of published messages delivered to this subscription.
+    ///
+    /// Messages received directly through a mailbox need to be acknowledged
+    /// with [`ack`](Self::ack) if the subscription has a bounded [`Policy`].
+    pub fn tag(&self) -> Tag {
+        self.subscriber.tag
+    }
+
+    /// Notifies the topic that a message was taken out of the mailbox.
+    ///
+    /// The notification reuses the message buffer, so it needs to come after
+    /// the received message was decoded.
+    pub fn ack(&self) {
+        if self.acknowledged {
+            notify(self.topic, ACK_TAG, &self.subscriber);
+        }
+    }
+}
+
+impl<T, S> Drop for Subscription<T, S> {
+    fn drop(&mut self) {
+        if !self.disconnected.get() {
+            notify(self.topic, UNSUBSCRIBE_TAG, &self.subscriber);
+        }
+    }
+}
+
+impl<T, S> std::fmt::Debug for Subscription<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("topic", &self.topic)
+            .field("tag", &self.subscriber.tag)
+            .field("disconnected", &self.disconnected.get())
+            .finish()
+    }
+}
+
+/// Identifies a subscription inside the topic process.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct Subscriber {
+    node_id: u64,
+    process_id: u64,
+    tag: Tag,
+    /// Tag of the notification sent if the topic disconnects the subscriber.
+    disconnect_tag: Tag,
+}
+
+impl Subscriber {
+    /// Returns the metric `name`, labeled with the subscriber.
+    fn metric_name(&self, name: &str) -> String {
+        let subscriber = format!("{}/{}", self.node_id, self.process_id);
+        metrics::with_labels(name, &[("subscriber", subscriber.as_str())])
+    }
+}
+
+/// Sends a control message to the topic process.
+///
+/// Control messages don't depend on the topic serializer, so that they can be
+/// sent from `Drop` implementations.
+fn notify<M: Serialize + DeserializeOwned>(topic: Process<()>, tag: i64, message: &M) {
+    unsafe { message::create_data(tag, 0) };
+    Bincode::encode(message).unwrap();
+    host::send(topic.node_id(), topic.id());
+}
+
+/// A published message inside the topic process.
+struct Published {
+    payload: Vec<u8>,
+    /// The publisher waiting on the number of subscribers that received it.
+    confirm: Option<Confirm>,
+}
+
+/// A publisher waiting in [`Topic::publish_confirmed`].
+struct Confirm {
+    node_id: u64,
+    process_id: u64,
+    tag: Tag,
+    received: usize,
+}
+
+impl Confirm {
+    fn reply(&self) {
+        let process: Process<usize, Bincode> = Process::new(self.node_id, self.process_id);
+        process.tag_send(self.tag, self.received);
+    }
+}
+
+/// State of a subscriber inside the topic process.
+struct Subscribed {
+    subscriber: Subscriber,
+    policy: Policy,
+    /// Number of messages that can still be delivered before buffering them.
+    credits: usize,
+    /// Messages waiting on the subscriber to catch up.
+    buffered: VecDeque<Vec<u8>>,
+}
+
+/// What happened with a message published to a subscriber.
+enum Delivery {
+    /// It's in the subscriber's mailbox.
+    Delivered,
+    /// It's buffered by the topic, or was dropped.
+    Deferred,
+    /// A [`Policy::Block`] subscriber is blocking the topic.
+    Blocked,
+    /// The subscriber is gone.
+    Removed,
+}
+
+impl Subscribed {
+    /// Publishes a serialized message to the subscriber, following its policy.
+    fn publish(&mut self, payload: &[u8]) -> Delivery {
+        if self.policy == Policy::Unbounded || self.credits > 0 {
+            self.credits = self.credits.saturating_sub(1);
+            return match self.deliver(payload) {
+                true => Delivery::Delivered,
+                false => Delivery::Removed,
+            };
+        }
+        match self.policy {
+            Policy::DropOldest(capacity) => {
+                self.buffered.push_back(payload.to_vec());
+                if self.buffered.len() > capacity {
+                    self.buffered.pop_front();
+                    self.dropped();
+                }
+                Delivery::Deferred
+            }
+            Policy::DropNewest(capacity) => {
+                if self.buffered.len() < capacity {
+                    self.buffered.push_back(payload.to_vec());
+                } else {
+                    self.dropped();
+                }
+                Delivery::Deferred
+            }
+            Policy::Disconnect(_) => {
+                let process: Process<(), Bincode> =
+                    Process::new(self.subscriber.node_id, self.subscriber.process_id);
+                process.tag_send(self.subscriber.disconnect_tag, ());
+                metrics::increment_counter(&self.subscriber.metric_name(DISCONNECTED_COUNTER));
+                Delivery::Removed
+            }
+            Policy::Block(_) => {
+                self.buffered.push_back(payload.to_vec());
+                Delivery::Blocked
+            }
+            Policy::Unbounded => unreachable!("unbounded subscribers always have credits"),
+        }
+    }
+
+    /// Delivers a serialized message to the subscriber.
+    ///
+    /// Returns `false` if the subscriber doesn't exist anymore.
+    fn deliver(&self, payload: &[u8]) -> bool {
+        unsafe {
+            message::create_data(self.subscriber.tag.id(), payload.len() as u64);
+            message::write_data(payload.as_ptr(), payload.len());
+        }
+        if self.subscriber.node_id == host::node_id() {
+            // Use the host call directly, so that pruned subscribers don't count as
+            // dead letters.
+            unsafe { message::send(self.subscriber.process_id) == 0 }
+        } else {
+            host::send(self.subscriber.node_id, self.subscriber.process_id);
+            true
+        }
+    }
+
+    fn dropped(&self) {
+        metrics::increment_counter(&self.subscriber.metric_name(DROPPED_COUNTER));
+    }
+
+    fn is_blocking(&self) -> bool {
+        matches!(self.policy, Policy::Block(_)) && !self.buffered.is_empty()
+    }
+}
+
+/// Reads the message in the buffer, starting at `offset`.
+fn read_payload(offset: usize) -> Vec<u8> {
+    let size = unsafe { message::data_size() } as usize;
+    let mut payload = vec![0; size.saturating_sub(offset)];
+    unsafe {
+        message::seek_data(offset as u64);
+        message::read_data(payload.as_mut_ptr(), payload.len());
+    }
+    payload
+}
+
+fn topic_process(policy: Policy, _: Mailbox<()>) {
+    let mut subscribers: Vec<Subscribed> = Vec::new();
+    // The message that a `Policy::Block` subscriber didn't take yet. Until
+    // it's delivered to all of them, new messages aren't received.
+    let mut blocked: Option<Published> = None;
+    let all_tags = [
+        Tag::from(PUBLISH_TAG),
+        Tag::from(PUBLISH_CONFIRMED_TAG),
+        Tag::from(SUBSCRIBE_TAG),
+        Tag::from(UNSUBSCRIBE_TAG),
+        Tag::from(ACK_TAG),
+    ];
+    loop {
+        if let Some(published) = blocked.take() {
+            if subscribers.iter().any(Subscribed::is_blocking) {
+                blocked = Some(published);
+            } else if let Some(confirm) = published.confirm {
+                confirm.reply();
+            }
+        }
+        let (tags, timeout) = match blocked {
+            Some(_) => (&all_tags[2..], Some(BLOCKED_CHECK_INTERVAL)),
+            None => (&all_tags[..], None),
+        };
+        let tag = match select::receive(tags, timeout) {
+            Some((_, tag)) => tag.id(),
+            None => {
+                // Subscribers that died without unsubscribing never ack.
+                subscribers.retain(|subscribed| {
+                    !subscribed.is_blocking()
+                        || subscribed.subscriber.node_id != host::node_id()
+                        || Process::<()>::new(host::node_id(), subscribed.subscriber.process_id)
+                            .is_alive()
+                });
+                continue;
+            }
+        };
+        match tag {
+            PUBLISH_TAG | PUBLISH_CONFIRMED_TAG => {
+                let confirm = (tag == PUBLISH_CONFIRMED_TAG).then(|| {
+                    let mut header = [0; CONFIRMED_HEADER_SIZE];
+                    unsafe {
+                        message::seek_data(0);
+                        message::read_data(header.as_mut_ptr(), header.len());
+                    }
+                    Confirm {
+                        node_id: u64::from_le_bytes(header[..8].try_into().unwrap()),
+                        process_id: u64::from_le_bytes(header[8..16].try_into().unwrap()),
+                        tag: Tag::from(i64::from_le_bytes(header[16..].try_into().unwrap())),
+                        received: 0,
+                    }
+                });
+                let offset = confirm.as_ref().map_or(0, |_| CONFIRMED_HEADER_SIZE);
+                let mut published = Published {
+                    payload: read_payload(offset),
+                    confirm,
+                };
+                let mut is_blocked = false;
+                subscribers.retain_mut(|subscribed| match subscribed.publish(&published.payload) {
+                    Delivery::Delivered => {
+                        if let Some(confirm) = published.confirm.as_mut() {
+                            confirm.received += 1;
+                        }
+                        true
+                    }
+                    Delivery::Deferred => true,
+                    Delivery::Blocked => {
+                        is_blocked = true;
+                        true
+                    }
+                    Delivery::Removed => false,
+                });
+                if is_blocked {
+                    blocked = Some(published);
+                } else if let Some(confirm) = published.confirm {
+                    confirm.reply();
+                }
+            }
+            SUBSCRIBE_TAG => {
+                let (subscriber, reply, subscriber_policy): (Subscriber, Tag, Option<Policy>) =
+                    Bincode::decode().unwrap();
+                let subscriber_policy = subscriber_policy.unwrap_or(policy);
+                let credits = subscriber_policy.capacity();
+                subscribers.push(Subscribed {
+                    subscriber,
+                    policy: subscriber_policy,
+                    credits: credits.unwrap_or(0),
+                    buffered: VecDeque::new(),
+                });
+                let process: Process<bool, Bincode> =
+                    Process::new(subscriber.node_id, subscriber.process_id);
+                process.tag_send(reply, credits.is_some());
+            }
+            UNSUBSCRIBE_TAG => {
+                let subscriber: Subscriber = Bincode::decode().unwrap();
+                subscribers.retain(|subscribed| subscribed.subscriber != subscriber);
+            }
+            ACK_TAG => {
+                let subscriber: Subscriber = Bincode::decode().unwrap();
+                let subscribed = subscribers
+                    .iter_mut()
+                    .position(|subscribed| subscribed.subscriber == subscriber);
+                if let Some(index) = subscribed {
+                    let subscribed = &mut subscribers[index];
+                    match subscribed.buffered.pop_front() {
+                        Some(payload) => {
+                            let delivered = subscribed.deliver(&payload);
+                            if let (true, Policy::Block(_), Some(published)) =
+                                (delivered, subscribed.policy, blocked.as_mut())
+                            {
+                                if let Some(confirm) = published.confirm.as_mut() {
+                                    confirm.received += 1;
+                                }
+                            }
+                            if !delivered {
+                                subscribers.remove(index);
+                            }
+                        }
+                        None => subscribed.credits += 1,
+                    }
+                }
+            }
+            _ => unreachable!("only topic tags are received"),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Random numbers from the host's entropy source.
+//!
+//! All functions read directly from the host, they don't keep any state in the
+//! process and are safe to use for secrets.
+//!
+//! # Example
+//!
+//! ```
+//! let request_id = random::uuid_v4();
+//! println!("handling request {request_id}");
+//! ```
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::host;
+
+/// Fills `buffer` with random bytes.
+///
+/// # Panics
+///
+/// Panics if the host fails to provide entropy.
+pub fn fill_bytes(buffer: &mut [u8]) {
+    let result = unsafe { host::api::wasi::random_get(buffer.as_mut_ptr(), buffer.len()) };
+    assert_eq!(result, 0, "The host failed to provide random bytes");
+}
+
+/// Returns a random `u32`.
+pub fn u32() -> u32 {
+    let mut bytes = [0; 4];
+    fill_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+/// Returns a random `u64`.
+pub fn u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns a random version 4 [`Uuid`].
+pub fn uuid_v4() -> Uuid {
+    let mut bytes = [0; 16];
+    fill_bytes(&mut bytes);
+    // Set the version (4) and the variant (RFC 4122).
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Uuid(bytes)
+}
+
+/// A universally unique identifier.
+///
+/// It's displayed in the hyphenated form, e.g.
+/// `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid([u8; 16]);
+
+impl Uuid {
+    /// Returns the 16 bytes of the UUID.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Returns the version of the UUID.
+    pub fn version(&self) -> u8 {
+        self.0[6] >> 4
+    }
+}
+
+impl From<[u8; 16]> for Uuid {
+    fn from(bytes: [u8; 16]) -> Self {
+        Uuid(bytes)
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! The registry of process names on the current node.
+//!
+//! [`Process::register`](crate::Process::register) and
+//! [`ProcessRef::register`](crate::ap::ProcessRef::register) register typed
+//! handles, with the type encoded in the name. The functions in this module
+//! access the registry with the names unchanged, e.g. to share a name with
+//! code that doesn't know the type of the process.
+//!
+//! # Example
+//!
+//! ```
+//! let (node_id, process_id) = match registry::get_or_put_later("cache") {
+//!     Some(registered) => registered,
+//!     None => {
+//!         // Other processes wait on the name until it's put.
+//!         let cache = Process::spawn((), cache_process);
+//!         registry::put("cache", cache.node_id(), cache.id());
+//!         (cache.node_id(), cache.id())
+//!     }
+//! };
+//! ```
+
+use crate::host::api::registry;
+
+/// Registers the process `process_id` on the node `node_id` under `name`.
+///
+/// A process that was registered under the same name before is replaced.
+pub fn put(name: &str, node_id: u64, process_id: u64) {
+    unsafe { registry::put(name.as_ptr(), name.len(), node_id, process_id) };
+}
+
+/// Returns the node and process id registered under `name`.
+pub fn get(name: &str) -> Option<(u64, u64)> {
+    let mut node_id = 0;
+    let mut process_id = 0;
+    match unsafe { registry::get(name.as_ptr(), name.len(), &mut node_id, &mut process_id) } {
+        0 => Some((node_id, process_id)),
+        _ => None,
+    }
+}
+
+/// Returns the node and process id registered under `name`, or locks the name
+/// if nothing is registered under it.
+///
+/// While the name is locked, other processes that access it wait until the
+/// current process [`put`]s a process under it, or dies. Accessing the name
+/// again from the current process before that traps the process, instead of
+/// waiting forever.
+pub fn get_or_put_later(name: &str) -> Option<(u64, u64)> {
+    let mut node_id = 0;
+    let mut process_id = 0;
+    let result = unsafe {
+        registry::get_or_put_later(name.as_ptr(), name.len(), &mut node_id, &mut process_id)
+    };
+    match result {
+        0 => Some((node_id, process_id)),
+        _ => None,
+    }
+}
+
+/// Removes the process registered under `name`.
+pub fn remove(name: &str) {
+    unsafe { registry::remove(name.as_ptr(), name.len()) };
+}
@@ -0,0 +1,234 @@
+//! Accounting of the host resources owned by the current process.
+//!
+//! The wrappers of host resources, like [`TcpStream`](crate::net::TcpStream)
+//! or the results of SQLite queries, count themselves while they are alive.
+//! A resource that is sent to another process is counted by the receiver. A
+//! count that keeps growing in a long-running process hints at a code path
+//! that skips the drop of a handle, e.g. with [`std::mem::forget`].
+//!
+//! A process can get a warning once it owns more resources than a threshold,
+//! see [`set_warning_threshold`] and
+//! [`ProcessConfig::resource_warning_threshold`](crate::ProcessConfig::resource_warning_threshold).
+//!
+//! With the `resource_tracking` feature, each resource also records where it
+//! was created, so that [`resource_report`] can point at the code that leaked
+//! it. Tracking costs an allocation per resource and is meant for debugging.
+//!
+//! Resources that are created through the raw [`host::api`](crate::host::api)
+//! functions aren't counted.
+//!
+//! # Example
+//!
+//! ```
+//! resource::set_warning_threshold(Some(1_000));
+//! // ...
+//! if resource::resource_count() > 900 {
+//!     println!("{}", resource::resource_report());
+//! }
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::panic::Location;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{host, metrics, process_local, Process};
+
+/// Environment variable that sets the [warning threshold](set_warning_threshold)
+/// of processes spawned with a config, see
+/// [`ProcessConfig::resource_warning_threshold`].
+///
+/// [`ProcessConfig::resource_warning_threshold`]: crate::ProcessConfig::resource_warning_threshold
+pub const WARNING_THRESHOLD_ENV: &str = "LUNATIC_RESOURCE_WARNING_THRESHOLD";
+/// Name of the counter that is incremented every time a process exceeds its
+/// warning threshold.
+pub const WARNINGS_COUNTER: &str = "lunatic.resources.warnings";
+
+process_local! {
+    static COUNTS: RefCell<BTreeMap<&'static str, usize>> = RefCell::new(BTreeMap::new());
+    // Unset until the environment is checked.
+    static THRESHOLD: Cell<Option<Option<usize>>> = Cell::new(None);
+    // Set while the count is above the threshold, so that only crossing it warns.
+    static WARNED: Cell<bool> = Cell::new(false);
+    static RECEIVER: Cell<Option<Process<ResourceWarning>>> = Cell::new(None);
+}
+
+#[cfg(feature = "resource_tracking")]
+process_local! {
+    static ORIGINS: RefCell<BTreeMap<(&'static str, u64), &'static Location<'static>>> =
+        RefCell::new(BTreeMap::new());
+}
+
+/// Sent to the [warning receiver](set_warning_receiver) when the process
+/// exceeds its warning threshold.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResourceWarning {
+    pub process_id: u64,
+    pub threshold: usize,
+    /// The resources owned by the process, by kind.
+    pub counts: BTreeMap<String, usize>,
+}
+
+/// A resource that is alive, and where it was created, see [`resource_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceOrigin {
+    pub kind: &'static str,
+    /// The ID of the resource in the host.
+    pub id: u64,
+    pub location: &'static Location<'static>,
+}
+
+/// The resources owned by the current process, see [`resource_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceReport {
+    pub counts: BTreeMap<&'static str, usize>,
+    /// Where the resources were created. Only recorded with the
+    /// `resource_tracking` feature, empty otherwise.
+    pub origins: Vec<ResourceOrigin>,
+}
+
+impl ResourceReport {
+    /// Returns the origins of the resources of `kind`.
+    pub fn origins_of<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a ResourceOrigin> {
+        self.origins
+            .iter()
+            .filter(move |origin| origin.kind == kind)
+    }
+}
+
+impl Display for ResourceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: usize = self.counts.values().sum();
+        write!(f, "{} host resources", total)?;
+        for (kind, count) in self.counts.iter() {
+            write!(f, "\n  {kind}: {count}")?;
+            for origin in self.origins_of(kind) {
+                write!(f, "\n    #{} created at {}", origin.id, origin.location)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the number of host resources owned by the current process.
+pub fn resource_count() -> usize {
+    COUNTS.with_borrow(|counts| counts.values().sum())
+}
+
+/// Returns the number of host resources owned by the current process, by
+/// kind, e.g. `"TcpStream"`.
+pub fn resource_counts() -> BTreeMap<&'static str, usize> {
+    COUNTS.with_borrow(|counts| counts.clone())
+}
+
+/// Returns the resources owned by the current process.
+pub fn resource_report() -> ResourceReport {
+    ResourceReport {
+        counts: resource_counts(),
+        origins: origins(),
+    }
+}
+
+/// Warns once the current process owns more than `threshold` resources, or
+/// disables the warning if it's `None`.
+///
+/// A warning increments the [`WARNINGS_COUNTER`] and is sent to the
+/// [warning receiver](set_warning_receiver), if there is one. The process
+/// warns again after the count dropped to the threshold and exceeds it
+/// again.
+pub fn set_warning_threshold(threshold: Option<usize>) {
+    THRESHOLD.set(Some(threshold));
+    WARNED.set(false);
+}
+
+/// Returns the warning threshold of the current process.
+pub fn warning_threshold() -> Option<usize> {
+    match THRESHOLD.get() {
+        Some(threshold) => threshold,
+        None => {
+            let threshold = std::env::var(WARNING_THRESHOLD_ENV)
+                .ok()
+                .and_then(|value| value.parse().ok());
+            THRESHOLD.set(Some(threshold));
+            threshold
+        }
+    }
+}
+
+/// Sends the warnings of the current process to `receiver`, or stops sending
+/// them if it's `None`.
+pub fn set_warning_receiver(receiver: Option<Process<ResourceWarning>>) {
+    RECEIVER.set(receiver);
+}
+
+// Counts the resource `id` of `kind` as owned by the current process.
+#[track_caller]
+pub(crate) fn acquired(kind: &'static str, id: u64) {
+    #[cfg(feature = "resource_tracking")]
+    ORIGINS.with_borrow_mut(|mut origins| origins.insert((kind, id), Location::caller()));
+    #[cfg(not(feature = "resource_tracking"))]
+    let _ = id;
+    COUNTS.with_borrow_mut(|mut counts| *counts.entry(kind).or_default() += 1);
+    check_threshold();
+}
+
+// Stops counting the resource `id` of `kind`, after it was dropped or sent to
+// another process.
+pub(crate) fn released(kind: &'static str, id: u64) {
+    #[cfg(feature = "resource_tracking")]
+    ORIGINS.with_borrow_mut(|mut origins| origins.remove(&(kind, id)));
+    #[cfg(not(feature = "resource_tracking"))]
+    let _ = id;
+    COUNTS.with_borrow_mut(|mut counts| {
+        if let Some(count) = counts.get_mut(kind) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(kind);
+            }
+        }
+    });
+    if let Some(threshold) = warning_threshold() {
+        if resource_count() <= threshold {
+            WARNED.set(false);
+        }
+    }
+}
+
+fn check_threshold() {
+    let Some(threshold) = warning_threshold() else {
+        return;
+    };
+    if WARNED.get() || resource_count() <= threshold {
+        return;
+    }
+    WARNED.set(true);
+    metrics::increment_counter(WARNINGS_COUNTER);
+    if let Some(receiver) = RECEIVER.get() {
+        let counts = resource_counts()
+            .into_iter()
+            .map(|(kind, count)| (kind.to_owned(), count))
+            .collect();
+        receiver.send(ResourceWarning {
+            process_id: host::process_id(),
+            threshold,
+            counts,
+        });
+    }
+}
+
+#[cfg(feature = "resource_tracking")]
+fn origins() -> Vec<ResourceOrigin> {
+    ORIGINS.with_borrow(|origins| {
+        origins
+            .iter()
+            .map(|(&(kind, id), &location)| ResourceOrigin { kind, id, location })
+            .collect()
+    })
+}
+
+#[cfg(not(feature = "resource_tracking"))]
+fn origins() -> Vec<ResourceOrigin> {
+    Vec::new()
+}
@@ -0,0 +1,481 @@
+//! Routing of keys to a fixed set of stateful workers.
+//!
+//! A [`ShardRouter`] starts a number of linked [`AbstractProcess`] workers,
+//! one per shard, and routes each request or message to the worker owning its
+//! key. All messages for the same key are handled by the same worker, in the
+//! order they were sent, so a worker can keep the state of its keys, e.g. one
+//! session per user ID.
+//!
+//! Keys are hashed by the caller, with a hasher that returns the same hashes
+//! in all processes of the same build, and mapped to shards by a router
+//! process.
+//!
+//! # Worker death
+//!
+//! The router restarts a dead worker in the same shard, with a new copy of the
+//! argument, so its keys keep going to the same shard. The state of the dead
+//! worker is lost. Requests it didn't respond to fail with
+//! [`ShardError::WorkerDied`], and messages still waiting in its mailbox are
+//! dropped.
+//!
+//! # Resizing
+//!
+//! Shards own [`VIRTUAL_NODES`] points each on a hash ring, and a key belongs
+//! to the shard of the first point following its hash. Resizing only adds or
+//! removes the points of the shards that are added or removed, so other keys
+//! don't move. Growing from `n` to `m` shards moves about `(m - n) / m` of the
+//! keys to the new shards, and shrinking moves the keys of the removed shards
+//! to the remaining ones.
+//!
+//! State isn't moved together with the keys, the new owner of a key that moved
+//! starts without it. Removed workers handle all queued requests before
+//! shutting down.
+//!
+//! # Example
+//!
+//! ```
+//! let sessions = ShardRouter::<UserId, Session>::start(8, ()).unwrap();
+//! sessions.cast(&user, Login(token));
+//! let cart = sessions.request(&user, GetCart).unwrap();
+//!
+//! let hot = sessions.stats().into_iter().max_by_key(|shard| shard.in_flight);
+//! ```
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::ap::handlers::{Message, Request};
+use crate::ap::messages::RequestMessage;
+use crate::ap::{
+    AbstractProcess, Config, MessageHandler, ProcessRef, RequestHandler, StartupError, State,
+};
+use crate::serializer::{Bincode, CanSerialize};
+use crate::time::Timeout;
+use crate::{Process, Tag};
+
+/// Number of points each shard owns on the hash ring.
+///
+/// More points spread the keys more evenly over the shards.
+pub const VIRTUAL_NODES: u32 = 128;
+
+/// Error returned from [`ShardRouter::request`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardError {
+    /// The worker owning the key died before responding.
+    #[error("the worker died while handling the request")]
+    WorkerDied,
+    /// The worker didn't respond in time.
+    #[error("the request timed out")]
+    Timeout,
+    /// The router doesn't have any shards.
+    #[error("the router doesn't have any shards")]
+    Empty,
+}
+
+/// Metrics of a single shard, returned from [`ShardRouter::stats`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardStats {
+    /// Process ID of the currently running worker.
+    pub process_id: u64,
+    /// Number of requests routed to the shard that didn't complete yet.
+    pub in_flight: usize,
+    /// Number of requests and messages routed to the shard.
+    pub routed: u64,
+    /// How many times the worker was restarted.
+    pub restarts: u64,
+}
+
+/// An [`AbstractProcess`] routing keys to `W` workers, started by
+/// [`ShardRouter::start`].
+pub struct Router<W>(PhantomData<W>);
+
+/// State of a [`Router`].
+pub struct RouterState<W: AbstractProcess> {
+    arg: W::Arg,
+    /// Points on the hash ring, and the shards owning them.
+    ring: BTreeMap<u64, usize>,
+    shards: Vec<Shard<W>>,
+}
+
+struct Shard<W: AbstractProcess> {
+    process: ProcessRef<W>,
+    link_tag: Tag,
+    /// Callers waiting on a response, with the tag used to notify them if the
+    /// worker dies.
+    in_flight: Vec<(Process<()>, Tag)>,
+    routed: u64,
+    restarts: u64,
+}
+
+impl<W> RouterState<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone,
+{
+    fn start_worker(&self) -> (ProcessRef<W>, Tag) {
+        let link_tag = Tag::new();
+        match W::link_with(link_tag).start(self.arg.clone()) {
+            Ok(process) => (process, link_tag),
+            Err(err) => panic!("ShardRouter failed to start worker `{:?}`", err),
+        }
+    }
+
+    fn shard_of(&self, hash: u64) -> Option<usize> {
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, &shard)| shard)
+    }
+
+    /// Replaces the worker of `shard`, failing the requests in flight on it.
+    fn restart(&mut self, shard: usize) {
+        let (process, link_tag) = self.start_worker();
+        let shard = &mut self.shards[shard];
+        for (caller, abort_tag) in shard.in_flight.drain(..) {
+            caller.tag_send(abort_tag, ());
+        }
+        shard.process = process;
+        shard.link_tag = link_tag;
+        shard.restarts += 1;
+    }
+}
+
+impl<W> AbstractProcess for Router<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Arg = (usize, W::Arg);
+    type State = RouterState<W>;
+    type Serializer = Bincode;
+    type Handlers = (
+        Request<Route>,
+        Message<Checkin>,
+        Request<Resize>,
+        Request<GetStats>,
+    );
+    type StartupError = ();
+
+    fn init(config: Config<Self>, (shards, arg): Self::Arg) -> Result<RouterState<W>, ()> {
+        // The router shouldn't die if the workers die
+        config.die_if_link_dies(false);
+
+        let mut state = RouterState {
+            arg,
+            ring: BTreeMap::new(),
+            shards: Vec::with_capacity(shards),
+        };
+        resize(&mut state, shards);
+        Ok(state)
+    }
+
+    fn terminate(mut state: RouterState<W>) {
+        resize(&mut state, 0);
+    }
+
+    fn handle_link_death(mut state: State<Self>, tag: Tag) {
+        // Workers removed during a resize, or already replaced when routing,
+        // are not restarted.
+        if let Some(shard) = state.shards.iter().position(|s| s.link_tag == tag) {
+            state.restart(shard);
+        }
+    }
+}
+
+/// Starts or shuts down workers until the router has `size` shards, and
+/// rebuilds the ring.
+fn resize<W>(state: &mut RouterState<W>, size: usize)
+where
+    W: AbstractProcess,
+    W::Arg: Clone,
+{
+    while state.shards.len() < size {
+        let (process, link_tag) = state.start_worker();
+        state.shards.push(Shard {
+            process,
+            link_tag,
+            in_flight: Vec::new(),
+            routed: 0,
+            restarts: 0,
+        });
+    }
+    // Workers handle all queued requests before shutting down.
+    for shard in state.shards.drain(size..).rev() {
+        shard.process.unlink();
+        shard.process.shutdown();
+    }
+    state.ring = (0..size)
+        .flat_map(|shard| (0..VIRTUAL_NODES).map(move |point| (hash(&(shard, point)), shard)))
+        .collect();
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    // The keys of `DefaultHasher::new` are fixed, so all processes agree on the
+    // hashes.
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the shard owning the key hash and its worker.
+///
+/// If a caller is included, the request is tracked as in-flight until the
+/// caller sends a [`Checkin`]. Routing counts towards
+/// [`ShardStats::routed`], unless it's only a lookup.
+#[derive(Serialize, Deserialize)]
+pub struct Route {
+    hash: u64,
+    caller: Option<(Process<()>, Tag)>,
+    lookup: bool,
+}
+
+impl<W> RequestHandler<Route> for Router<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Response = Option<(usize, ProcessRef<W>)>;
+
+    fn handle(mut state: State<Self>, route: Route) -> Self::Response {
+        let index = state.shard_of(route.hash)?;
+        // Don't hand out a worker that died, even if the router wasn't
+        // notified yet.
+        if !state.shards[index].process.is_alive() {
+            state.restart(index);
+        }
+        let shard = &mut state.shards[index];
+        if !route.lookup {
+            shard.routed += 1;
+        }
+        if let Some(caller) = route.caller {
+            shard.in_flight.push(caller);
+        }
+        Some((index, shard.process))
+    }
+}
+
+/// Marks an in-flight request as completed.
+#[derive(Serialize, Deserialize)]
+pub struct Checkin(usize, Tag);
+
+impl<W> MessageHandler<Checkin> for Router<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    fn handle(mut state: State<Self>, Checkin(index, abort_tag): Checkin) {
+        // The shard could have been removed in the meantime.
+        if let Some(shard) = state.shards.get_mut(index) {
+            shard.in_flight.retain(|(_, tag)| *tag != abort_tag);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Resize(usize);
+
+impl<W> RequestHandler<Resize> for Router<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Response = ();
+
+    fn handle(mut state: State<Self>, Resize(size): Resize) {
+        resize(&mut *state, size);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetStats;
+
+impl<W> RequestHandler<GetStats> for Router<W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    type Response = Vec<ShardStats>;
+
+    fn handle(state: State<Self>, _: GetStats) -> Self::Response {
+        state
+            .shards
+            .iter()
+            .map(|shard| ShardStats {
+                process_id: shard.process.id(),
+                in_flight: shard.in_flight.len(),
+                routed: shard.routed,
+                restarts: shard.restarts,
+            })
+            .collect()
+    }
+}
+
+/// Routes requests and messages with keys of type `K` to the `W` worker
+/// owning the key, see the [module documentation](self).
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ShardRouter<K, W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    router: ProcessRef<Router<W>>,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K, W> ShardRouter<K, W>
+where
+    K: Hash,
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    /// Starts a router with `shards` workers.
+    ///
+    /// Each worker is started with a copy of `arg`, also when it's restarted.
+    pub fn start(shards: usize, arg: W::Arg) -> Result<Self, StartupError<Router<W>>> {
+        <Router<W> as AbstractProcess>::start((shards, arg)).map(|router| ShardRouter {
+            router,
+            key: PhantomData,
+        })
+    }
+
+    /// Sends a message to the worker owning `key`.
+    ///
+    /// The message is dropped if the router doesn't have any shards.
+    pub fn cast<M: 'static>(&self, key: &K, message: M)
+    where
+        W: MessageHandler<M>,
+        W::Serializer: CanSerialize<M>,
+    {
+        let route = Route {
+            hash: hash(key),
+            caller: None,
+            lookup: false,
+        };
+        if let Some((_, worker)) = self.router.request(route) {
+            worker.send(message);
+        }
+    }
+
+    /// Makes a request to the worker owning `key`.
+    ///
+    /// If the worker dies before responding, `Err(ShardError::WorkerDied)` is
+    /// returned. Later requests for the key go to the restarted worker.
+    pub fn request<R: 'static>(&self, key: &K, request: R) -> Result<W::Response, ShardError>
+    where
+        W: RequestHandler<R>,
+        W::Serializer: CanSerialize<R>,
+        W::Serializer: CanSerialize<W::Response>,
+        W::Serializer: CanSerialize<RequestMessage<R, W::Response, W::Serializer>>,
+    {
+        self.request_(key, request, None)
+    }
+
+    /// Makes a request to the worker owning `key`, waiting at most `timeout`
+    /// on the response.
+    pub fn request_timeout<R: 'static>(
+        &self,
+        key: &K,
+        request: R,
+        timeout: Duration,
+    ) -> Result<W::Response, ShardError>
+    where
+        W: RequestHandler<R>,
+        W::Serializer: CanSerialize<R>,
+        W::Serializer: CanSerialize<W::Response>,
+        W::Serializer: CanSerialize<RequestMessage<R, W::Response, W::Serializer>>,
+    {
+        self.request_(key, request, Some(timeout))
+    }
+
+    fn request_<R: 'static>(
+        &self,
+        key: &K,
+        request: R,
+        timeout: Option<Duration>,
+    ) -> Result<W::Response, ShardError>
+    where
+        W: RequestHandler<R>,
+        W::Serializer: CanSerialize<R>,
+        W::Serializer: CanSerialize<W::Response>,
+        W::Serializer: CanSerialize<RequestMessage<R, W::Response, W::Serializer>>,
+    {
+        let abort_tag = Tag::new();
+        let route = Route {
+            hash: hash(key),
+            caller: Some((Process::this(), abort_tag)),
+            lookup: false,
+        };
+        let (index, worker) = self.router.request(route).ok_or(ShardError::Empty)?;
+        let result = worker.request_abortable(request, abort_tag, timeout);
+        self.router.send(Checkin(index, abort_tag));
+        match result {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(Timeout)) => Err(ShardError::Timeout),
+            None => Err(ShardError::WorkerDied),
+        }
+    }
+
+    /// Returns the shard owning `key` and its current worker, or `None` if
+    /// the router doesn't have any shards.
+    pub fn owner(&self, key: &K) -> Option<(usize, ProcessRef<W>)> {
+        self.router.request(Route {
+            hash: hash(key),
+            caller: None,
+            lookup: true,
+        })
+    }
+
+    /// Starts or shuts down workers until the router has `shards` of them, see
+    /// [Resizing](self#resizing).
+    pub fn resize(&self, shards: usize) {
+        self.router.request(Resize(shards));
+    }
+
+    /// Returns the metrics of each shard, e.g. to find hot shards.
+    pub fn stats(&self) -> Vec<ShardStats> {
+        self.router.request(GetStats)
+    }
+
+    /// Shuts the router and all workers down.
+    pub fn shutdown(&self) {
+        self.router.shutdown();
+    }
+}
+
+impl<K, W> Clone for ShardRouter<K, W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, W> Copy for ShardRouter<K, W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+}
+
+impl<K, W> std::fmt::Debug for ShardRouter<K, W>
+where
+    W: AbstractProcess,
+    W::Arg: Clone + Serialize + DeserializeOwned,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardRouter")
+            .field("router", &self.router)
+            .finish()
+    }
+}
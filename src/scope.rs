@@ -0,0 +1,264 @@
+//! Structured concurrency for processes.
+//!
+//! Processes spawned through a [`Scope`] can't outlive it. When the closure
+//! passed to [`scope`] returns, the scope waits for all of its processes to
+//! finish. [`scope_with_deadline`] waits only up to a deadline and kills the
+//! processes that are still running afterwards. If the process owning the
+//! scope dies, all processes of the scope are killed too.
+//!
+//! The processes are linked to a hidden coordinator process instead of the
+//! owner, so a failing process doesn't bring the owner down. Its failure is
+//! reported as a [`ScopeError`] instead.
+//!
+//! # Example
+//!
+//! ```
+//! let total = scope(|s| {
+//!     for url in urls {
+//!         s.spawn(url, |url| fetch(&url).len());
+//!     }
+//!     // Results come in the order the processes finished.
+//!     let mut total = 0;
+//!     while let Some(result) = s.next() {
+//!         total += result.unwrap_or(0);
+//!     }
+//!     total
+//! });
+//! ```
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::function::FuncRef;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{select, Mailbox, MailboxResult, Process, Tag};
+
+/// Error reported for a process of a [`Scope`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScopeError {
+    /// The process died before returning a result, e.g. it panicked or ran out
+    /// of fuel.
+    #[error("the scoped process failed")]
+    Failed,
+}
+
+/// Runs `f` with a new [`Scope`] and waits for all processes spawned through
+/// it to finish.
+///
+/// Results that weren't collected with [`Scope::next`] are discarded.
+pub fn scope<T, F, R>(f: F) -> R
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce(&Scope<T>) -> R,
+{
+    scope_(None, f)
+}
+
+/// Runs `f` with a new [`Scope`] and waits up to `deadline` for all processes
+/// spawned through it to finish.
+///
+/// The deadline starts once `f` returns. Processes still running after it
+/// are killed.
+pub fn scope_with_deadline<T, F, R>(deadline: Duration, f: F) -> R
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce(&Scope<T>) -> R,
+{
+    scope_(Some(deadline), f)
+}
+
+fn scope_<T, F, R>(deadline: Option<Duration>, f: F) -> R
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce(&Scope<T>) -> R,
+{
+    let scope = Scope::new();
+    let result = f(&scope);
+    scope.join(deadline);
+    result
+}
+
+/// A scope for spawning processes, see [`scope`].
+///
+/// All processes of a scope return a value of type `T`.
+pub struct Scope<T> {
+    coordinator: Process<ScopeMessage>,
+    result_tag: Tag,
+    failure_tag: Tag,
+    // Number of spawned processes whose result wasn't collected yet.
+    pending: Cell<usize>,
+    result: PhantomData<T>,
+}
+
+impl<T> Scope<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn new() -> Self {
+        let result_tag = Tag::new();
+        let failure_tag = Tag::new();
+        let owner = Process::this();
+        let coordinator = Process::spawn_link((owner, failure_tag), coordinator_process);
+        Scope {
+            coordinator,
+            result_tag,
+            failure_tag,
+            pending: Cell::new(0),
+            result: PhantomData,
+        }
+    }
+
+    /// Spawns a process running `entry` with `capture` inside of the scope.
+    pub fn spawn<C>(&self, capture: C, entry: fn(C) -> T)
+    where
+        C: Serialize + DeserializeOwned,
+    {
+        let job: Job<C, T> = (
+            Process::this(),
+            self.result_tag,
+            FuncRef::new(entry),
+            capture,
+        );
+        let payload = bincode::serialize(&job).expect("Failed to serialize the scoped process");
+        self.coordinator.send(ScopeMessage::Spawn {
+            runner: FuncRef::new(run_job::<C, T>),
+            payload,
+        });
+        self.pending.set(self.pending.get() + 1);
+    }
+
+    /// Waits on the next process of the scope to finish and returns its
+    /// result.
+    ///
+    /// Results are returned in the order the processes finished. Returns
+    /// `None` once the results of all spawned processes were collected.
+    pub fn next(&self) -> Option<Result<T, ScopeError>> {
+        if self.pending.get() == 0 {
+            return None;
+        }
+        let result = match select::receive(&[self.result_tag, self.failure_tag], None) {
+            Some((_, tag)) if tag == self.result_tag => {
+                Ok(Bincode::decode().expect("Failed to deserialize the scoped result"))
+            }
+            _ => Err(Bincode::decode().expect("Failed to deserialize the scope error")),
+        };
+        self.pending.set(self.pending.get() - 1);
+        Some(result)
+    }
+
+    fn join(self, deadline: Option<Duration>) {
+        let tag = Tag::new();
+        self.coordinator.send(ScopeMessage::Finish {
+            deadline,
+            owner: Process::this(),
+            tag,
+        });
+        unsafe { Mailbox::<()>::new() }.tag_receive(&[tag]);
+        // Drop results that weren't collected, so they don't stay in the mailbox.
+        while select::receive(&[self.result_tag, self.failure_tag], Some(Duration::ZERO)).is_some()
+        {
+        }
+    }
+}
+
+type Job<C, T> = (Process<T>, Tag, FuncRef<fn(C) -> T>, C);
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum ScopeMessage {
+    Spawn {
+        runner: FuncRef<fn(Vec<u8>)>,
+        payload: Vec<u8>,
+    },
+    Done(Tag),
+    Finish {
+        deadline: Option<Duration>,
+        owner: Process<()>,
+        tag: Tag,
+    },
+}
+
+fn coordinator_process(
+    (owner, failure_tag): (Process<ScopeError>, Tag),
+    mailbox: Mailbox<ScopeMessage>,
+) {
+    let mailbox = mailbox.catch_link_failure();
+    let this = mailbox.this();
+    // Running processes by the tag of their link.
+    let mut children: HashMap<Tag, Process<()>> = HashMap::new();
+    // Set once the owner left the scope.
+    let mut finish: Option<(Option<Instant>, Process<()>, Tag)> = None;
+    loop {
+        if let Some((deadline, waiter, tag)) = finish {
+            let expired = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if children.is_empty() || expired {
+                for child in children.values() {
+                    // Unlink before killing, so that the kill isn't reported as a failure.
+                    child.unlink();
+                    child.kill();
+                }
+                waiter.tag_send(tag, ());
+                return;
+            }
+        }
+        let message = match finish {
+            Some((Some(deadline), _, _)) => {
+                mailbox.receive_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            _ => mailbox.receive(),
+        };
+        match message {
+            MailboxResult::Message(ScopeMessage::Spawn { runner, payload }) => {
+                let tag = Tag::new();
+                let child =
+                    Process::spawn_link_tag((this, tag, runner, payload), tag, child_process);
+                children.insert(tag, child);
+            }
+            MailboxResult::Message(ScopeMessage::Done(tag)) => {
+                children.remove(&tag);
+            }
+            MailboxResult::Message(ScopeMessage::Finish {
+                deadline,
+                owner: waiter,
+                tag,
+            }) => {
+                let deadline = deadline.map(|deadline| Instant::now() + deadline);
+                finish = Some((deadline, waiter, tag));
+            }
+            MailboxResult::LinkDied(tag) => match children.remove(&tag) {
+                Some(_) => owner.tag_send(failure_tag, ScopeError::Failed),
+                // The owner died, take all of its processes with it.
+                None => {
+                    for child in children.values() {
+                        child.kill();
+                    }
+                    return;
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+type ChildCapture = (Process<ScopeMessage>, Tag, FuncRef<fn(Vec<u8>)>, Vec<u8>);
+
+fn child_process((coordinator, tag, runner, payload): ChildCapture, _: Mailbox<()>) {
+    runner.get()(payload);
+    coordinator.send(ScopeMessage::Done(tag));
+}
+
+fn run_job<C, T>(payload: Vec<u8>)
+where
+    C: Serialize + DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+{
+    let (owner, tag, entry, capture): Job<C, T> =
+        bincode::deserialize(&payload).expect("Failed to deserialize the scoped process");
+    owner.tag_send(tag, entry.get()(capture));
+}
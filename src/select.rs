@@ -0,0 +1,81 @@
+//! Helpers used by the [`select!`](crate::select!) macro.
+
+use std::time::Duration;
+
+use crate::ap::self_send;
+use crate::host::api::message;
+use crate::mailbox::{Catching, LINK_DIED, TIMEOUT};
+use crate::serializer::CanSerialize;
+use crate::{Mailbox, MailboxResult, Tag};
+
+/// Waits on the first message tagged with one of the `tags`.
+///
+/// Returns `None` if the `timeout` expired, or the type of the received message
+/// and its tag.
+pub fn receive(tags: &[Tag], timeout: Option<Duration>) -> Option<(u32, Tag)> {
+    crate::memory::check();
+    let tags: Vec<i64> = tags.iter().map(|tag| tag.id()).collect();
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_millis() as u64,
+        None => u64::MAX,
+    };
+    match crate::virtual_time::receive(&tags, timeout_ms) {
+        TIMEOUT => None,
+        received => Some((received, Tag::from(unsafe { message::get_tag() }))),
+    }
+}
+
+/// A mailbox that can be used in a `select!` arm.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be used in a `select!` arm of the form `pattern = mailbox.tag_receive(tags)`",
+    label = "expected a `Mailbox`"
+)]
+pub trait SelectMailbox {
+    type Output;
+
+    /// Decodes the message that was received by [`receive`].
+    fn decode(&self, received: u32) -> Self::Output;
+}
+
+impl<M, S> SelectMailbox for Mailbox<M, S>
+where
+    S: CanSerialize<M>,
+{
+    type Output = M;
+
+    #[track_caller]
+    fn decode(&self, _: u32) -> M {
+        self_send::decode::<M, S>().unwrap()
+    }
+}
+
+impl<M, S> SelectMailbox for Mailbox<M, S, Catching>
+where
+    S: CanSerialize<M>,
+{
+    type Output = MailboxResult<M>;
+
+    fn decode(&self, received: u32) -> MailboxResult<M> {
+        if received == LINK_DIED {
+            return MailboxResult::LinkDied(Tag::from(unsafe { message::get_tag() }));
+        }
+        match self_send::decode::<M, S>() {
+            Ok(message) => MailboxResult::Message(message),
+            Err(err) => MailboxResult::DeserializationFailed(err),
+        }
+    }
+}
+
+/// A protocol that can be used in a `select!` arm.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be used in a `select!` arm of the form `pattern = protocol.receive()`",
+    label = "expected a `Protocol` in a `Recv` state"
+)]
+pub trait SelectProtocol: Sized {
+    type Output;
+
+    /// Returns the tags used by the session.
+    fn tags(&self) -> [Tag; 2];
+    /// Decodes the value that was received by [`receive`].
+    fn decode(self, tag: Tag) -> Self::Output;
+}
@@ -1,7 +1,12 @@
 //! Serializer implementations for messages.
+use std::cell::Cell;
+use std::io::BufReader;
+use std::marker::PhantomData;
+
 use thiserror::Error;
 
 use crate::host::api::message;
+use crate::{process_local, Tag};
 
 #[derive(Error, Debug)]
 pub enum EncodeError {
@@ -23,6 +28,15 @@ pub enum EncodeError {
     IO(#[from] std::io::Error),
     #[error("serialization failed: {0}")]
     Custom(String),
+    /// The message contains a resource (e.g. a [`TcpStream`](crate::net::TcpStream))
+    /// that can't leave the current node, but it was sent to a process on
+    /// another node.
+    #[error("{0} can't be sent to a process on another node")]
+    LocalResource(&'static str),
+    /// The message was sent to a process on a node that the current node
+    /// isn't connected to, see [`distributed::partial_mesh`](crate::distributed::partial_mesh).
+    #[error("the node {0} isn't connected")]
+    NotConnected(u64),
 }
 
 #[derive(Error, Debug)]
@@ -45,6 +59,10 @@ pub enum DecodeError {
     IO(#[from] std::io::Error),
     #[error("deserialization failed: {0}")]
     Custom(String),
+    #[error("unknown message version: {0}")]
+    UnknownVersion(u8),
+    #[error("message was encoded with an unknown serializer: {0}")]
+    UnknownSerializer(u8),
 }
 
 /// The `CanSerialize` trait is implemented for serializers that can encode and
@@ -78,6 +96,10 @@ pub enum DecodeError {
 /// FFI, that works on a streaming basis and can avoid unnecessary copies.
 /// Serializer that require raw access to chunks of mutable memories (e.g.
 /// `Prost`) require additional copies between guest and host memories.
+#[diagnostic::on_unimplemented(
+    message = "the `{Self}` serializer can't serialize `{M}`",
+    label = "`{M}` can't be sent as a message or captured by a process"
+)]
 pub trait CanSerialize<M> {
     fn encode(message: &M) -> Result<(), EncodeError>;
     fn decode() -> Result<M, DecodeError>;
@@ -102,11 +124,14 @@ where
     M: serde::Serialize + serde::de::DeserializeOwned,
 {
     fn encode(message: &M) -> Result<(), EncodeError> {
-        Ok(bincode::serialize_into(MessageRw {}, message)?)
+        use std::io::Write;
+        let mut writer = MessageWriter::new();
+        bincode::serialize_into(&mut writer, message)?;
+        Ok(writer.flush()?)
     }
 
     fn decode() -> Result<M, DecodeError> {
-        Ok(bincode::deserialize_from(MessageRw {})?)
+        Ok(bincode::deserialize_from(MessageReader::new())?)
     }
 }
 
@@ -131,12 +156,13 @@ where
 {
     fn encode(message: &M) -> Result<(), EncodeError> {
         use std::io::Write;
-        let data = rmp_serde::to_vec(message)?;
-        Ok(MessageRw {}.write_all(&data)?)
+        let mut writer = MessageWriter::new();
+        rmp_serde::encode::write(&mut writer, message)?;
+        Ok(writer.flush()?)
     }
 
     fn decode() -> Result<M, DecodeError> {
-        Ok(rmp_serde::decode::from_read(MessageRw {})?)
+        Ok(rmp_serde::decode::from_read(MessageReader::new())?)
     }
 }
 
@@ -161,12 +187,13 @@ where
 {
     fn encode(message: &M) -> Result<(), EncodeError> {
         use std::io::Write;
-        let data = serde_json::to_vec(message)?;
-        Ok(MessageRw {}.write_all(&data)?)
+        let mut writer = MessageWriter::new();
+        serde_json::to_writer(&mut writer, message)?;
+        Ok(writer.flush()?)
     }
 
     fn decode() -> Result<M, DecodeError> {
-        Ok(serde_json::from_reader(MessageRw {})?)
+        Ok(serde_json::from_reader(MessageReader::new())?)
     }
 }
 
@@ -185,13 +212,492 @@ where
 {
     fn encode(message: &M) -> Result<(), EncodeError> {
         use std::io::Write;
-        let mut data = Vec::new();
-        message.write_to_vec(&mut data)?;
-        Ok(MessageRw {}.write_all(&data)?)
+        let mut writer = MessageWriter::new();
+        message.write_to_writer(&mut writer)?;
+        Ok(writer.flush()?)
     }
 
     fn decode() -> Result<M, DecodeError> {
-        Ok(M::parse_from_reader(&mut MessageRw {})?)
+        Ok(M::parse_from_reader(&mut MessageReader::new())?)
+    }
+}
+
+/// A serializer that sends a `Vec<u8>` as it is.
+///
+/// The bytes are written to the message with a single host call. Serde based
+/// serializers encode a `Vec<u8>` byte by byte, which gets slow for large
+/// buffers.
+#[derive(Debug, Hash)]
+pub struct RawBytes {}
+
+impl CanSerialize<Vec<u8>> for RawBytes {
+    fn encode(message: &Vec<u8>) -> Result<(), EncodeError> {
+        use std::io::Write;
+        Ok(MessageRw {}.write_all(message)?)
+    }
+
+    fn decode() -> Result<Vec<u8>, DecodeError> {
+        use std::io::Read;
+        let size = unsafe { message::data_size() } as usize;
+        let mut message = vec![0; size];
+        MessageRw {}.read_exact(&mut message)?;
+        Ok(message)
+    }
+}
+
+/// A unique id of a serializer, written into messages by [`Versioned`].
+pub trait SerializerId {
+    const ID: u8;
+}
+
+impl SerializerId for Bincode {
+    const ID: u8 = 1;
+}
+
+#[cfg(feature = "msgpack_serializer")]
+#[cfg_attr(docsrs, doc(cfg(feature = "msgpack_serializer")))]
+impl SerializerId for MessagePack {
+    const ID: u8 = 2;
+}
+
+#[cfg(feature = "json_serializer")]
+#[cfg_attr(docsrs, doc(cfg(feature = "json_serializer")))]
+impl SerializerId for Json {
+    const ID: u8 = 3;
+}
+
+#[cfg(feature = "protobuf_serializer")]
+#[cfg_attr(docsrs, doc(cfg(feature = "protobuf_serializer")))]
+impl SerializerId for ProtocolBuffers {
+    const ID: u8 = 4;
+}
+
+impl SerializerId for RawBytes {
+    const ID: u8 = 5;
+}
+
+/// The schema version of a message type used with the [`Versioned`]
+/// serializer.
+///
+/// # Example
+///
+/// ```
+/// #[derive(Serialize, Deserialize)]
+/// struct UserV1 {
+///     name: String,
+/// }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct User {
+///     name: String,
+///     age: Option<u8>,
+/// }
+///
+/// impl MessageVersion for User {
+///     const VERSION: u8 = 2;
+///
+///     fn upgrade(version: u8, data: &[u8]) -> Result<Self, DecodeError> {
+///         match version {
+///             1 => {
+///                 let user: UserV1 = bincode::deserialize(data)?;
+///                 Ok(User { name: user.name, age: None })
+///             }
+///             _ => Err(DecodeError::UnknownVersion(version)),
+///         }
+///     }
+/// }
+/// ```
+pub trait MessageVersion: Sized {
+    /// The current version of the message type.
+    const VERSION: u8;
+
+    /// Decodes the `data` of a message encoded with an older `version`.
+    ///
+    /// `data` doesn't contain the version header. By default all older versions
+    /// are rejected.
+    fn upgrade(version: u8, data: &[u8]) -> Result<Self, DecodeError> {
+        let _ = data;
+        Err(DecodeError::UnknownVersion(version))
+    }
+}
+
+/// A serializer that prefixes messages with the version of the message type
+/// and the id of the serializer `S`.
+///
+/// Messages encoded with an older version of the type are decoded with
+/// [`MessageVersion::upgrade`]. Messages with unknown versions or serializers
+/// result in a [`DecodeError`], they are reported as
+/// [`MailboxResult::DeserializationFailed`](crate::MailboxResult) by the
+/// non-panicking receive functions. This allows processes running different
+/// versions of the same code to keep exchanging messages.
+///
+/// Messages of the current version only carry two extra bytes, and are
+/// decoded directly with `S`.
+#[derive(Debug, Hash)]
+pub struct Versioned<S> {
+    serializer: PhantomData<S>,
+}
+
+impl<M, S> CanSerialize<M> for Versioned<S>
+where
+    M: MessageVersion,
+    S: CanSerialize<M> + SerializerId,
+{
+    fn encode(message: &M) -> Result<(), EncodeError> {
+        use std::io::Write;
+        MessageRw {}.write_all(&[M::VERSION, S::ID])?;
+        S::encode(message)
+    }
+
+    fn decode() -> Result<M, DecodeError> {
+        use std::io::Read;
+        let mut header = [0; 2];
+        MessageRw {}.read_exact(&mut header)?;
+        let [version, serializer] = header;
+        if serializer != S::ID {
+            return Err(DecodeError::UnknownSerializer(serializer));
+        }
+        if version == M::VERSION {
+            return S::decode();
+        }
+        let size = unsafe { message::data_size() } as usize;
+        let mut data = vec![0; size.saturating_sub(header.len())];
+        MessageRw {}.read_exact(&mut data)?;
+        M::upgrade(version, &data)
+    }
+}
+
+/// Default value of [`chunk_size`].
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Messages up to this size are passed between the guest and the host from a
+/// buffer on the stack, see [`MessageWriter`] and [`MessageReader`].
+const INLINE_SIZE: usize = 128;
+
+process_local! {
+    static CHUNK_SIZE: Cell<usize> = Cell::new(DEFAULT_CHUNK_SIZE);
+    // Set while a message for a process on another node is encoded.
+    static REMOTE_DESTINATION: Cell<bool> = Cell::new(false);
+    // Resource that refused to be encoded for another node.
+    static LOCAL_RESOURCE: Cell<Option<&'static str>> = Cell::new(None);
+    // Set while `encode_external` runs.
+    static EXTERNAL: Cell<bool> = Cell::new(false);
+    // Handle that refused to be encoded by `encode_external`.
+    static EXTERNAL_HANDLE: Cell<Option<&'static str>> = Cell::new(None);
+}
+
+/// Encodes `message` with `S` into the message buffer, for a process on the
+/// node `node_id`.
+pub(crate) fn encode_for<M, S: CanSerialize<M>>(
+    node_id: u64,
+    message: &M,
+) -> Result<(), EncodeError> {
+    let remote = node_id != crate::host::node_id();
+    if remote && !crate::distributed::is_reachable(node_id) {
+        return Err(EncodeError::NotConnected(node_id));
+    }
+    REMOTE_DESTINATION.set(remote);
+    let result = S::encode(message);
+    REMOTE_DESTINATION.set(false);
+    match LOCAL_RESOURCE.take() {
+        Some(resource) => Err(EncodeError::LocalResource(resource)),
+        None => result,
+    }
+}
+
+/// Fails the serialization of a resource that only exists on the current
+/// node, if the message is sent to another node.
+pub(crate) fn local_resource<E: serde::ser::Error>(resource: &'static str) -> Result<(), E> {
+    if !REMOTE_DESTINATION.get() {
+        return Ok(());
+    }
+    LOCAL_RESOURCE.set(Some(resource));
+    Err(E::custom(EncodeError::LocalResource(resource)))
+}
+
+/// Error returned by [`encode_external`].
+#[derive(Error, Debug)]
+pub enum ExternalError<E> {
+    /// The value contains a process handle, e.g. a [`Process`](crate::Process)
+    /// or [`ProcessRef`](crate::ap::ProcessRef), at the field `path`.
+    #[error("the {handle} at `{path}` can't be serialized for an external system")]
+    Handle { path: String, handle: &'static str },
+    /// The serializer failed at the field `path`.
+    #[error("serialization failed at `{path}`: {error}")]
+    Serializer { path: String, error: E },
+}
+
+/// Serializes `value` with `serializer`, failing if it contains a process
+/// handle.
+///
+/// Handles only mean something to lunatic processes, so values for other
+/// systems, e.g. JSON sent to an HTTP API or stored in a database, should be
+/// encoded with this function instead of the serializer directly. The error
+/// names the path of the field that failed, e.g. `jobs[2].owner`.
+///
+/// ```
+/// let mut json = Vec::new();
+/// let result = encode_external(&event, &mut serde_json::Serializer::new(&mut json));
+/// if let Err(ExternalError::Handle { path, .. }) = result {
+///     panic!("the event contains a handle at {}", path);
+/// }
+/// ```
+pub fn encode_external<T, Ser>(
+    value: &T,
+    serializer: Ser,
+) -> Result<Ser::Ok, ExternalError<Ser::Error>>
+where
+    T: serde::Serialize + ?Sized,
+    Ser: serde::Serializer,
+{
+    let nested = EXTERNAL.replace(true);
+    let result = serde_path_to_error::serialize(value, serializer);
+    EXTERNAL.set(nested);
+    let handle = EXTERNAL_HANDLE.take();
+    result.map_err(|err| {
+        let path = err.path().to_string();
+        match handle {
+            Some(handle) => ExternalError::Handle { path, handle },
+            None => ExternalError::Serializer {
+                path,
+                error: err.into_inner(),
+            },
+        }
+    })
+}
+
+/// Fails the serialization of a process handle inside [`encode_external`].
+pub(crate) fn handle<E: serde::ser::Error>(handle: &'static str) -> Result<(), E> {
+    if !EXTERNAL.get() {
+        return Ok(());
+    }
+    EXTERNAL_HANDLE.set(Some(handle));
+    Err(E::custom(format!(
+        "{} can't be serialized for an external system",
+        handle
+    )))
+}
+
+/// Returns the size of chunks in which serialized messages are passed between
+/// the guest and the host.
+pub fn chunk_size() -> usize {
+    CHUNK_SIZE.get()
+}
+
+/// Sets the size of chunks in which serialized messages are passed between the
+/// guest and the host, for the current process.
+///
+/// Bigger chunks result in fewer calls into the host, but use more memory
+/// while a message is serialized. The default chunk size is 64 KiB.
+///
+/// # Panics
+///
+/// Panics if `size` is 0.
+pub fn set_chunk_size(size: usize) {
+    assert!(size > 0, "The chunk size can't be 0");
+    CHUNK_SIZE.set(size);
+}
+
+/// Returns a writer into the message scratch buffer, that passes the data to
+/// the host in chunks of [`chunk_size`] bytes.
+///
+/// Serializers should use it instead of serializing the whole message into
+/// memory first. This keeps the memory used while serializing bounded,
+/// independent of the size of the message. The writer needs to be flushed at
+/// the end. A [`MessageWriter`] avoids the buffer for small messages.
+pub fn message_writer() -> ChunkWriter {
+    ChunkWriter {
+        buffer: Vec::new(),
+        chunk_size: chunk_size(),
+    }
+}
+
+/// Returns a reader from the message scratch buffer, that reads the data from
+/// the host in chunks of [`chunk_size`] bytes.
+///
+/// The buffer of the reader isn't bigger than the message. The reader can read
+/// ahead up to the end of the message, data following the value that was
+/// decoded with it should be read with the same reader.
+pub fn message_reader() -> BufReader<MessageRw> {
+    let size = unsafe { message::data_size() } as usize;
+    BufReader::with_capacity(chunk_size().min(size), MessageRw {})
+}
+
+/// A writer into the message scratch buffer, that passes the data to the host
+/// in chunks, see [`message_writer`].
+///
+/// The buffer grows with the message, up to the size of a chunk, so a message
+/// that is smaller than a chunk doesn't allocate a whole chunk.
+pub struct ChunkWriter {
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl std::io::Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.buffer.len() + buf.len() > self.chunk_size {
+            self.flush()?;
+        }
+        // Data that doesn't fit into a chunk is passed to the host right away.
+        if buf.len() >= self.chunk_size {
+            return MessageRw {}.write(buf);
+        }
+        let needed = self.buffer.len() + buf.len();
+        if needed > self.buffer.capacity() {
+            let capacity = needed.max(2 * self.buffer.capacity()).min(self.chunk_size);
+            self.buffer.reserve_exact(capacity - self.buffer.len());
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        MessageRw {}.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Returns the size in bytes of the data of the message in the scratch
+/// buffer, e.g. to allocate a buffer for it while decoding.
+pub fn message_size() -> u64 {
+    unsafe { message::data_size() }
+}
+
+/// Returns the tag of the message in the scratch buffer.
+pub fn message_tag() -> Tag {
+    Tag::from(unsafe { message::get_tag() })
+}
+
+/// Moves the reading position of the message in the scratch buffer back to
+/// the start, so that the message can be read again.
+///
+/// Decoders use it to peek at the start of a message, e.g. a header, before
+/// decoding the whole message.
+pub fn rewind_message() {
+    unsafe { message::seek_data(0) };
+}
+
+/// A writer into the message scratch buffer, that keeps small messages on
+/// the stack.
+///
+/// Messages of up to 128 bytes, like a `u64`, are collected on the stack and
+/// passed to the host with a single call when the writer is flushed. Empty
+/// messages, like a `()` encoded with [`Bincode`], don't call the host at all.
+/// Only messages that don't fit switch to a [`message_writer`]. The writer
+/// needs to be flushed at the end.
+pub struct MessageWriter {
+    inline: [u8; INLINE_SIZE],
+    len: usize,
+    writer: Option<ChunkWriter>,
+}
+
+impl MessageWriter {
+    pub fn new() -> Self {
+        MessageWriter {
+            inline: [0; INLINE_SIZE],
+            len: 0,
+            writer: None,
+        }
+    }
+}
+
+impl Default for MessageWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::io::Write for MessageWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(writer) = self.writer.as_mut() {
+            return writer.write(buf);
+        }
+        if self.len + buf.len() <= INLINE_SIZE {
+            self.inline[self.len..self.len + buf.len()].copy_from_slice(buf);
+            self.len += buf.len();
+            return Ok(buf.len());
+        }
+        // The message doesn't fit, continue with a regular writer.
+        let mut writer = message_writer();
+        writer.write_all(&self.inline[..self.len])?;
+        self.len = 0;
+        self.writer.insert(writer).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            // Nothing to pass to the host.
+            None if self.len == 0 => Ok(()),
+            None => {
+                MessageRw {}.write_all(&self.inline[..self.len])?;
+                self.len = 0;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A reader from the message scratch buffer, that keeps small messages on
+/// the stack.
+///
+/// If the message isn't bigger than 128 bytes, the rest of it is read with a
+/// single host call into a buffer on the stack, empty messages aren't read at
+/// all. Bigger messages are read with a [`message_reader`].
+pub struct MessageReader {
+    inline: [u8; INLINE_SIZE],
+    position: usize,
+    len: usize,
+    reader: Option<BufReader<MessageRw>>,
+}
+
+impl MessageReader {
+    pub fn new() -> Self {
+        let mut inline = [0; INLINE_SIZE];
+        // The size of the whole message is an upper bound of what's left.
+        let size = unsafe { message::data_size() } as usize;
+        if size == 0 {
+            MessageReader {
+                inline,
+                position: 0,
+                len: 0,
+                reader: None,
+            }
+        } else if size <= INLINE_SIZE {
+            let len = unsafe { message::read_data(inline.as_mut_ptr(), size) };
+            MessageReader {
+                inline,
+                position: 0,
+                len,
+                reader: None,
+            }
+        } else {
+            MessageReader {
+                inline,
+                position: 0,
+                len: 0,
+                reader: Some(message_reader()),
+            }
+        }
+    }
+}
+
+impl Default for MessageReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::io::Read for MessageReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(reader) = self.reader.as_mut() {
+            return reader.read(buf);
+        }
+        let n = buf.len().min(self.len - self.position);
+        buf[..n].copy_from_slice(&self.inline[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
     }
 }
 
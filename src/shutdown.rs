@@ -0,0 +1,227 @@
+//! Graceful shutdown of an application.
+//!
+//! Long-lived processes register with [`on_shutdown`] to receive a
+//! [`ShutdownSignal`] once the shutdown is initiated. Each of them has a grace
+//! period to finish its work (e.g. drain connections or flush data to disk)
+//! and acknowledge it with [`ShutdownSignal::done`]. Processes that don't
+//! finish in time are killed.
+//!
+//! The host doesn't notify processes when the node is stopped, the shutdown
+//! needs to be triggered by the application itself with [`initiate`].
+//!
+//! # Example
+//!
+//! ```
+//! #[lunatic::main]
+//! fn main(_: Mailbox<()>) {
+//!     Process::spawn((), |_, mailbox: Mailbox<ShutdownSignal>| {
+//!         shutdown::on_shutdown(mailbox.this());
+//!         let signal = mailbox.receive();
+//!         // Clean up ...
+//!         signal.done();
+//!     });
+//!     // ...
+//!     shutdown::initiate();
+//!     shutdown::wait_for_shutdown();
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{host, Mailbox, MailboxResult, Process, Tag};
+
+/// Name under which the shutdown coordinator is registered.
+const COORDINATOR_NAME: &str = "lunatic::shutdown::coordinator";
+/// Grace period used if none is set with [`set_grace_period`].
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// How often the coordinator checks if registered processes are still alive
+/// during the shutdown.
+const ALIVE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Message sent to registered processes when the shutdown is initiated.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownSignal {
+    coordinator: Process<CoordinatorMessage>,
+    id: u64,
+    grace_period: Duration,
+}
+
+impl ShutdownSignal {
+    /// Returns the time the process has to finish, counted from the moment
+    /// the signal was sent.
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Signals that the process finished cleaning up.
+    ///
+    /// The shutdown completes once all registered processes are done or
+    /// dead. Processes can also just exit instead of calling this function.
+    pub fn done(self) {
+        self.coordinator.send(CoordinatorMessage::Done(self.id));
+    }
+}
+
+/// Registers `process` to receive a [`ShutdownSignal`] when the shutdown is
+/// initiated.
+///
+/// If the shutdown is already in progress, the signal is sent right away.
+pub fn on_shutdown(process: Process<ShutdownSignal>) {
+    coordinator().send(CoordinatorMessage::Register(process));
+}
+
+/// Initiates the shutdown.
+///
+/// This function doesn't wait for the shutdown to complete, use
+/// [`wait_for_shutdown`] for that. Calling it multiple times has no effect.
+pub fn initiate() {
+    coordinator().send(CoordinatorMessage::Initiate);
+}
+
+/// Blocks until the shutdown is complete.
+///
+/// The shutdown is complete when all registered processes finished, or after
+/// the grace period expired and the remaining ones were killed.
+pub fn wait_for_shutdown() {
+    let tag = Tag::new();
+    coordinator().send(CoordinatorMessage::Wait(Process::this(), tag));
+    unsafe { Mailbox::<()>::new() }.tag_receive(&[tag]);
+}
+
+/// Sets how long registered processes have to finish after the shutdown was
+/// initiated.
+///
+/// The default grace period is 5 seconds. It needs to be set before the
+/// shutdown is initiated.
+pub fn set_grace_period(grace_period: Duration) {
+    coordinator().send(CoordinatorMessage::SetGracePeriod(grace_period));
+}
+
+fn coordinator() -> Process<CoordinatorMessage> {
+    let mut node_id: u64 = 0;
+    let mut process_id: u64 = 0;
+    unsafe {
+        match host::api::registry::get_or_put_later(
+            COORDINATOR_NAME.as_ptr(),
+            COORDINATOR_NAME.len(),
+            &mut node_id,
+            &mut process_id,
+        ) {
+            0 => Process::new(node_id, process_id),
+            _ => {
+                let process = Process::spawn((), coordinator_process);
+                host::api::registry::put(
+                    COORDINATOR_NAME.as_ptr(),
+                    COORDINATOR_NAME.len(),
+                    process.node_id(),
+                    process.id(),
+                );
+                process
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum CoordinatorMessage {
+    Register(Process<ShutdownSignal>),
+    Initiate,
+    Done(u64),
+    Wait(Process<()>, Tag),
+    SetGracePeriod(Duration),
+}
+
+fn coordinator_process(_: (), mailbox: Mailbox<CoordinatorMessage>) {
+    let this = mailbox.this();
+    let mut grace_period = DEFAULT_GRACE_PERIOD;
+    let mut next_id = 0;
+    let mut registered: HashMap<u64, Process<ShutdownSignal>> = HashMap::new();
+    let mut waiting: Vec<(Process<()>, Tag)> = Vec::new();
+    // Set while the shutdown is in progress.
+    let mut deadline: Option<Instant> = None;
+    let mut finished = false;
+    let is_alive = |process: &Process<ShutdownSignal>| {
+        process.node_id() != host::node_id() || process.is_alive()
+    };
+
+    loop {
+        let message = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                mailbox.receive_timeout(remaining.min(ALIVE_CHECK_INTERVAL))
+            }
+            None => MailboxResult::Message(mailbox.receive()),
+        };
+        match message {
+            MailboxResult::Message(CoordinatorMessage::Register(process)) => {
+                next_id += 1;
+                if finished {
+                    process.send(ShutdownSignal {
+                        coordinator: this,
+                        id: next_id,
+                        grace_period: Duration::ZERO,
+                    });
+                    continue;
+                }
+                if let Some(deadline) = deadline {
+                    process.send(ShutdownSignal {
+                        coordinator: this,
+                        id: next_id,
+                        grace_period: deadline.saturating_duration_since(Instant::now()),
+                    });
+                } else {
+                    // Forget processes that died before the shutdown.
+                    registered.retain(|_, process| is_alive(process));
+                }
+                registered.insert(next_id, process);
+            }
+            MailboxResult::Message(CoordinatorMessage::Initiate)
+                if deadline.is_none() && !finished =>
+            {
+                deadline = Some(Instant::now() + grace_period);
+                for (&id, process) in registered.iter() {
+                    process.send(ShutdownSignal {
+                        coordinator: this,
+                        id,
+                        grace_period,
+                    });
+                }
+            }
+            MailboxResult::Message(CoordinatorMessage::Done(id)) => {
+                registered.remove(&id);
+            }
+            MailboxResult::Message(CoordinatorMessage::Wait(process, tag)) => {
+                if finished {
+                    process.tag_send(tag, ());
+                } else {
+                    waiting.push((process, tag));
+                }
+            }
+            MailboxResult::Message(CoordinatorMessage::SetGracePeriod(period)) => {
+                grace_period = period;
+            }
+            _ => (),
+        }
+
+        let Some(until) = deadline else {
+            continue;
+        };
+        registered.retain(|_, process| is_alive(process));
+        if registered.is_empty() || Instant::now() >= until {
+            // Processes that didn't finish in time are killed.
+            for process in registered.values() {
+                process.kill();
+            }
+            registered.clear();
+            deadline = None;
+            finished = true;
+            for (process, tag) in waiting.drain(..) {
+                process.tag_send(tag, ());
+            }
+        }
+    }
+}
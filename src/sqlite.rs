@@ -0,0 +1,314 @@
+//! SQLite databases.
+//!
+//! [`Connection::query`] returns the whole result set at once. For large
+//! results, a [`Statement`] prepared with [`Connection::prepare`] steps
+//! through the rows one at a time, so the memory used by the process stays
+//! proportional to a single row instead of the size of the result set.
+//! Statements also take values that are bound to their `?` parameters, so
+//! that they don't need to be quoted in the SQL text.
+//!
+//! # Example
+//!
+//! ```
+//! # use lunatic::sqlite::{Connection, SqliteError, Value};
+//! # fn main() -> Result<(), SqliteError> {
+//! let conn = Connection::open("app.db")?;
+//! let mut stmt = conn.prepare("SELECT id, name FROM users WHERE active = ?")?;
+//! stmt.bind(1, Value::Integer(1))?;
+//! for row in stmt.query() {
+//!     let row = row?;
+//!     println!("{:?} {:?}", row.get(0), row.get(1));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::resource;
+
+/// Kind of query results in the [resource counts](resource::resource_counts).
+const QUERY_RESULT_KIND: &str = "SqliteQueryResult";
+/// Kind of prepared statements in the [resource counts](resource::resource_counts).
+const STATEMENT_KIND: &str = "SqliteStatement";
+/// Returned by [`sqlite3_step`] if the statement produced a row.
+const SQLITE_ROW: u32 = 100;
+/// Returned by [`sqlite3_step`] if the statement ran to completion.
+const SQLITE_DONE: u32 = 101;
+
+// temporary until merged,
+// discussed here: https://github.com/lunatic-solutions/lunatic/pull/160
+#[link(wasm_import_module = "lunatic::sqlite")]
+extern "C" {
+    pub(crate) fn open(path: *const u8, path_len: usize, conn_id: *mut u32) -> u64;
+    pub(crate) fn query_prepare(
+        conn_id: u64,
+        query_str: *const u8,
+        query_str_len: u32,
+        len_ptr: *mut u32,
+        resource_id: *mut u32,
+    ) -> ();
+    pub(crate) fn query_result_get(
+        resource_id: u64,
+        write_buf: *const u8,
+        write_buf_len: u32,
+    ) -> ();
+    pub(crate) fn drop_query_result(resource_id: u64) -> ();
+    pub(crate) fn execute(conn_id: u64, exec_str: *const u8, exec_str_len: u32) -> u32;
+    pub(crate) fn statement_prepare(
+        conn_id: u64,
+        query_str: *const u8,
+        query_str_len: u32,
+        statement_id: *mut u32,
+    ) -> u32;
+    pub(crate) fn bind_value(statement_id: u64, bind_data: *const u8, bind_data_len: u32) -> u32;
+    pub(crate) fn sqlite3_step(statement_id: u64, row_len: *mut u32) -> u32;
+    pub(crate) fn read_row(statement_id: u64, write_buf: *mut u8, write_buf_len: u32) -> ();
+    pub(crate) fn statement_reset(statement_id: u64) -> ();
+    pub(crate) fn sqlite3_finalize(statement_id: u64) -> ();
+}
+
+/// Error returned by SQLite operations.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SqliteError {
+    /// The database couldn't be opened.
+    #[error("failed to open the database {0}")]
+    Open(String),
+    /// The statement couldn't be prepared.
+    #[error("failed to prepare the statement")]
+    Prepare,
+    /// The value couldn't be bound to the parameter at this index.
+    #[error("failed to bind parameter {0}")]
+    Bind(usize),
+    /// The statement failed.
+    #[error("failed to execute the statement")]
+    Execute,
+    /// The result returned by the host couldn't be decoded.
+    #[error("failed to decode the query result: {0}")]
+    Decode(String),
+}
+
+/// A value stored in an SQLite column.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// A row of a query result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row(Vec<Value>);
+
+impl Row {
+    /// Returns the value of the column at `index`.
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.0.get(index)
+    }
+
+    /// Returns the values of all columns.
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.0
+    }
+}
+
+/// A connection to an SQLite database.
+pub struct Connection {
+    id: u64,
+}
+
+impl Connection {
+    /// Opens the database at `path`, creating it if it doesn't exist.
+    ///
+    /// `":memory:"` opens a new in-memory database.
+    pub fn open(path: &str) -> Result<Self, SqliteError> {
+        let mut id: u32 = 0;
+        match unsafe { open(path.as_ptr(), path.len(), &mut id) } {
+            0 => Ok(Connection { id: id as u64 }),
+            _ => Err(SqliteError::Open(path.to_owned())),
+        }
+    }
+
+    /// Executes one or more statements that don't return rows.
+    pub fn execute(&self, sql: &str) -> Result<(), SqliteError> {
+        match unsafe { execute(self.id, sql.as_ptr(), sql.len() as u32) } {
+            0 => Ok(()),
+            _ => Err(SqliteError::Execute),
+        }
+    }
+
+    /// Runs the query and returns all rows at once.
+    ///
+    /// The whole result is copied into the process, use a [`Statement`] for
+    /// large results.
+    #[track_caller]
+    pub fn query(&self, sql: &str) -> Result<Vec<Row>, SqliteError> {
+        Ok(self.fetch(sql)?.into_iter().map(Row).collect())
+    }
+
+    /// Prepares a statement that can be executed and queried multiple times.
+    ///
+    /// The statement is compiled once by the host and stays open until it's
+    /// dropped.
+    #[track_caller]
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>, SqliteError> {
+        let mut id: u32 = 0;
+        match unsafe { statement_prepare(self.id, sql.as_ptr(), sql.len() as u32, &mut id) } {
+            0 => Ok(Statement {
+                connection: PhantomData,
+                handle: StatementHandle::new(id as u64),
+            }),
+            _ => Err(SqliteError::Prepare),
+        }
+    }
+
+    #[track_caller]
+    fn fetch(&self, sql: &str) -> Result<Vec<Vec<Value>>, SqliteError> {
+        let mut len: u32 = 0;
+        let mut resource_id: u32 = 0;
+        unsafe {
+            query_prepare(
+                self.id,
+                sql.as_ptr(),
+                sql.len() as u32,
+                &mut len,
+                &mut resource_id,
+            )
+        };
+        let result = QueryResult::new(resource_id as u64);
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let mut buffer: Vec<u8> = vec![0; len as usize];
+        unsafe { query_result_get(result.0, buffer.as_mut_ptr(), len) };
+        bincode::deserialize(&buffer).map_err(|err| SqliteError::Decode(err.to_string()))
+    }
+}
+
+/// The result of a query held by the host, dropped with the value.
+struct QueryResult(u64);
+
+impl QueryResult {
+    #[track_caller]
+    fn new(id: u64) -> Self {
+        resource::acquired(QUERY_RESULT_KIND, id);
+        QueryResult(id)
+    }
+}
+
+impl Drop for QueryResult {
+    fn drop(&mut self) {
+        unsafe { drop_query_result(self.0) };
+        resource::released(QUERY_RESULT_KIND, self.0);
+    }
+}
+
+/// A statement prepared with [`Connection::prepare`].
+///
+/// Values bound to its parameters are kept for all following executions,
+/// until they are bound again.
+pub struct Statement<'a> {
+    connection: PhantomData<&'a Connection>,
+    handle: StatementHandle,
+}
+
+impl<'a> Statement<'a> {
+    /// Binds `value` to the parameter at `index`, starting at 1 as in SQLite.
+    pub fn bind(&mut self, index: usize, value: Value) -> Result<(), SqliteError> {
+        let data =
+            bincode::serialize(&(index as u32, value)).map_err(|_| SqliteError::Bind(index))?;
+        match unsafe { bind_value(self.handle.0, data.as_ptr(), data.len() as u32) } {
+            0 => Ok(()),
+            _ => Err(SqliteError::Bind(index)),
+        }
+    }
+
+    /// Runs the statement to completion and ignores the rows that it returns.
+    pub fn execute(&mut self) -> Result<(), SqliteError> {
+        self.query().try_for_each(|row| row.map(drop))
+    }
+
+    /// Returns the rows of the statement.
+    ///
+    /// Each call to `next` steps the statement to the next row and copies only
+    /// that row into the process. Other statements can run on the connection
+    /// in between. The statement is reset once the rows are dropped, so that
+    /// it can be queried again.
+    pub fn query(&mut self) -> Rows<'_> {
+        Rows {
+            statement: &self.handle,
+            done: false,
+        }
+    }
+}
+
+/// A statement held by the host, finalized with the value.
+struct StatementHandle(u64);
+
+impl StatementHandle {
+    #[track_caller]
+    fn new(id: u64) -> Self {
+        resource::acquired(STATEMENT_KIND, id);
+        StatementHandle(id)
+    }
+}
+
+impl Drop for StatementHandle {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.0) };
+        resource::released(STATEMENT_KIND, self.0);
+    }
+}
+
+/// The rows of a [`Statement`], see [`Statement::query`].
+///
+/// Only the current row is held by the process.
+pub struct Rows<'a> {
+    statement: &'a StatementHandle,
+    done: bool,
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<Row, SqliteError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut len: u32 = 0;
+        match unsafe { sqlite3_step(self.statement.0, &mut len) } {
+            SQLITE_ROW => {
+                let mut buffer: Vec<u8> = vec![0; len as usize];
+                unsafe { read_row(self.statement.0, buffer.as_mut_ptr(), len) };
+                Some(
+                    bincode::deserialize(&buffer)
+                        .map(Row)
+                        .map_err(|err| SqliteError::Decode(err.to_string())),
+                )
+            }
+            SQLITE_DONE => {
+                self.done = true;
+                None
+            }
+            _ => {
+                self.done = true;
+                Some(Err(SqliteError::Execute))
+            }
+        }
+    }
+}
+
+impl<'a> Drop for Rows<'a> {
+    fn drop(&mut self) {
+        unsafe { statement_reset(self.statement.0) };
+    }
+}
@@ -1,12 +1,52 @@
+use std::any::type_name;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::time::{Duration, Instant};
 
-use crate::ap::handlers::{DeferredRequest, Request};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::ap::handlers::{DeferredRequest, Message, Request};
+use crate::ap::messages::RequestMessage;
+use crate::ap::snapshot::{Snapshot, SnapshotKeeper, SnapshotSetup, StoreSnapshot};
 use crate::ap::{
-    AbstractProcess, Config, DeferredRequestHandler, DeferredResponse, ProcessRef, RequestHandler,
-    State,
+    last_init_panic, AbstractProcess, Config, DeferredRequestHandler, DeferredResponse,
+    MessageHandler, ProcessRef, RequestHandler, StartupError, State,
 };
-use crate::serializer::Bincode;
-use crate::{host, Tag};
+use crate::metrics::{self, with_labels};
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, mailbox, panic, process_local, time, Mailbox, MailboxResult, Process, Tag};
+
+/// How long a restart waits on the death of a previous instance that was shut
+/// down. The kill is lost if the instance exits on its own first.
+const EXIT_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long a [`SupervisedRef`] waits on the restart of a dead child.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Reserved tag of the message a child sends to its supervisor once it's
+/// ready, see [`ready`].
+pub(crate) const READY_TAG: i64 = 21;
+
+process_local! {
+    // The process that started the current `AbstractProcess`, until `ready`
+    // is called.
+    static PARENT: Cell<Option<(u64, u64)>> = Cell::new(None);
+}
+
+/// Counter of child restarts, labeled by `supervisor`, `child` and `reason`,
+/// see [`SupervisorConfig::publish_metrics`].
+///
+/// The reason is `panic` or `killed` for the child that died, `normal` for
+/// the children that were shut down to restart together with it, and
+/// `failed_start` for retries of failed starts.
+pub const RESTARTS_COUNTER: &str = "supervisor_child_restarts_total";
+/// Gauge of the children that are running, labeled by `supervisor`.
+pub const RUNNING_GAUGE: &str = "supervisor_children_running";
+/// Counter of supervisors that exceeded their restart intensity and gave up,
+/// labeled by `supervisor` and `child`.
+pub const GAVE_UP_COUNTER: &str = "supervisor_gave_up_total";
 
 /// A `Supervisor` can detect failures (panics) inside
 /// [`AbstractProcesses`](AbstractProcess) and restart them.
@@ -67,7 +107,14 @@ where
     type Arg = T::Arg;
     type State = SupervisorConfig<T>;
     type Serializer = Bincode;
-    type Handlers = (Request<GetChildren>, DeferredRequest<ShutdownSubscribe>);
+    type Handlers = (
+        Request<GetChildren>,
+        Request<WhichChildren>,
+        Request<PublishedMetrics>,
+        DeferredRequest<ChildProcess>,
+        DeferredRequest<ShutdownSubscribe>,
+        Message<StoreSnapshot>,
+    );
     type StartupError = ();
 
     fn init(config: Config<Self>, arg: T::Arg) -> Result<Self::State, ()> {
@@ -75,11 +122,13 @@ where
         config.die_if_link_dies(false);
 
         let mut sup_config = SupervisorConfig::default();
+        // Keeps the values of the metrics for `published_metrics`.
+        metrics::record_published(true);
         <T as Supervisor>::init(&mut sup_config, arg);
 
         // Check if children arguments are configured inside of supervisor's `init`
         // call.
-        if sup_config.children_args.is_none() {
+        if sup_config.children_specs.is_none() {
             panic!(
                 "SupervisorConfig<{0}>::children_args not set inside `{0}:init` function.",
                 std::any::type_name::<T>()
@@ -160,21 +209,476 @@ where
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct WhichChildren;
+impl<T> RequestHandler<WhichChildren> for T
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    type Response = Vec<ChildStatus>;
+
+    fn handle(state: State<Self>, _: WhichChildren) -> Self::Response {
+        state.children_status.clone()
+    }
+}
+
+impl<T> ProcessRef<T>
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    /// Returns the status of each child, in start order.
+    pub fn which_children(&self) -> Vec<ChildStatus> {
+        self.request(WhichChildren)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PublishedMetrics;
+impl<T> RequestHandler<PublishedMetrics> for T
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    type Response = HashMap<String, f64>;
+
+    fn handle(_: State<Self>, _: PublishedMetrics) -> Self::Response {
+        metrics::published()
+    }
+}
+
+impl<T> ProcessRef<T>
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    /// Returns the current values of the metrics published by the supervisor,
+    /// by name with labels.
+    pub fn published_metrics(&self) -> HashMap<String, f64> {
+        self.request(PublishedMetrics)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ChildProcess {
+    index: usize,
+    // The dead instance, the response waits until it's replaced.
+    replaces: Option<u64>,
+}
+impl<T> DeferredRequestHandler<ChildProcess> for T
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    type Response = Option<(u64, u64)>;
+
+    fn handle(
+        mut state: State<Self>,
+        ChildProcess { index, replaces }: ChildProcess,
+        response: DeferredResponse<Option<(u64, u64)>, Self>,
+    ) {
+        let child = T::Children::child_id(&state.get_children(), index);
+        match (child, replaces) {
+            (Some((_, process_id)), Some(dead)) if process_id == dead => {
+                state.wait_on_restart(index, response)
+            }
+            _ => response.send_response(child),
+        }
+    }
+}
+
+impl<T> ProcessRef<T>
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    /// Returns a handle to the child with `index` that follows its restarts,
+    /// see [`SupervisedRef`].
+    ///
+    /// Fails if the supervisor is gone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the child with `index` isn't of type `C`.
+    pub fn supervised<C>(&self, index: usize) -> Result<SupervisedRef<C>, SupervisedError>
+    where
+        C: AbstractProcess,
+    {
+        assert_eq!(
+            T::Children::child_type(index),
+            Some(type_name::<C>()),
+            "child {} of supervisor {} has a different type",
+            index,
+            type_name::<T>()
+        );
+        let (node_id, process_id) =
+            child_of::<T>(self.node_id(), self.id(), index, None).ok_or(SupervisedError::Gone)?;
+        Ok(SupervisedRef {
+            process: Cell::new(unsafe { ProcessRef::new(node_id, process_id) }),
+            source: Source::Supervisor {
+                node_id: self.node_id(),
+                process_id: self.id(),
+                index,
+                child: child_of::<T>,
+            },
+        })
+    }
+}
+
+// Asks the supervisor `process_id` for the current instance of the child with
+// `index`, or for the one replacing the dead instance `replaces`. Returns
+// `None` if the supervisor is gone or doesn't respond in time.
+fn child_of<T>(
+    node_id: u64,
+    process_id: u64,
+    index: usize,
+    replaces: Option<u64>,
+) -> Option<(u64, u64)>
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    let supervisor = unsafe { ProcessRef::<T>::new(node_id, process_id) };
+    if !is_running(&supervisor) {
+        return None;
+    }
+    supervisor
+        .deferred_request_timeout(ChildProcess { index, replaces }, Some(RESOLVE_TIMEOUT))
+        .ok()
+        .flatten()
+}
+
+// Processes on other nodes are assumed to be running.
+fn is_running<C: AbstractProcess>(process: &ProcessRef<C>) -> bool {
+    process.node_id() != host::node_id() || process.is_alive()
+}
+
+/// Error returned from [`SupervisedRef`] if the child can't be reached.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisedError {
+    /// The child died and wasn't restarted, because its supervisor is gone
+    /// or no new instance was registered in time.
+    #[error("the supervised process is gone")]
+    Gone,
+}
+
+/// A handle to a supervised child that follows it across restarts.
+///
+/// A [`ProcessRef`] points to one instance of a process, after a restart it
+/// points to a dead process and messages sent to it are lost. A
+/// `SupervisedRef` keeps the last known instance and checks that it's still
+/// running before each message or request. If it isn't, the current instance
+/// is looked up again, from the supervisor of the child, which responds once
+/// the child is restarted, or from the registry, and the message goes to it
+/// instead. Only if there is no new instance the
+/// call fails with [`SupervisedError::Gone`].
+///
+/// As long as the child keeps running, a call costs one check of the process
+/// on top of the plain [`ProcessRef`] call. A request already sent to an
+/// instance that dies before responding isn't repeated. Processes on other
+/// nodes can't be checked and are assumed to be running.
+///
+/// # Example
+///
+/// ```
+/// use lunatic::ap::handlers::Request;
+/// use lunatic::ap::{AbstractProcess, Config, RequestHandler, State};
+/// use lunatic::serializer::Bincode;
+/// use lunatic::supervisor::{SupervisedError, Supervisor, SupervisorConfig};
+///
+/// struct Pool;
+/// impl AbstractProcess for Pool {
+///     type Arg = ();
+///     type State = Self;
+///     type Serializer = Bincode;
+///     type Handlers = (Request<Checkout>,);
+///     type StartupError = ();
+///
+///     fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+///         Ok(Pool)
+///     }
+/// }
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Checkout;
+/// impl RequestHandler<Checkout> for Pool {
+///     type Response = u32;
+///
+///     fn handle(_: State<Self>, _: Checkout) -> u32 {
+///         0
+///     }
+/// }
+///
+/// struct Sup;
+/// impl Supervisor for Sup {
+///     type Arg = ();
+///     type Children = (Pool,);
+///
+///     fn init(config: &mut SupervisorConfig<Self>, _: ()) {
+///         config.children_args((((), None),));
+///     }
+/// }
+///
+/// fn checkout() -> Result<u32, SupervisedError> {
+///     let sup = Sup::start(()).unwrap();
+///     let pool = sup.supervised::<Pool>(0)?;
+///     // Still reaches the pool after it was restarted.
+///     pool.request(Checkout)
+/// }
+/// ```
+pub struct SupervisedRef<C>
+where
+    C: AbstractProcess,
+{
+    process: Cell<ProcessRef<C>>,
+    source: Source,
+}
+
+// Signature of `child_of`, without the type of the supervisor.
+type ChildOf = fn(u64, u64, usize, Option<u64>) -> Option<(u64, u64)>;
+
+#[derive(Clone)]
+enum Source {
+    Registry(String),
+    Supervisor {
+        node_id: u64,
+        process_id: u64,
+        index: usize,
+        child: ChildOf,
+    },
+}
+
+impl<C> SupervisedRef<C>
+where
+    C: AbstractProcess,
+{
+    /// Returns a handle to the process registered under `name`, if it exists.
+    ///
+    /// After the process dies, the handle switches to the process registered
+    /// under the name at that time, e.g. by the supervisor restarting it. If
+    /// the name wasn't registered again yet, the call fails.
+    pub fn lookup<S: AsRef<str>>(name: S) -> Option<Self> {
+        let process = ProcessRef::lookup(name.as_ref())?;
+        Some(SupervisedRef {
+            process: Cell::new(process),
+            source: Source::Registry(name.as_ref().to_owned()),
+        })
+    }
+
+    /// Returns the current instance of the process.
+    ///
+    /// The last known instance is returned while it's running, otherwise the
+    /// new one is looked up.
+    pub fn process(&self) -> Result<ProcessRef<C>, SupervisedError> {
+        let process = self.process.get();
+        if is_running(&process) {
+            return Ok(process);
+        }
+        self.resolve(process)
+    }
+
+    /// Sends a message to the current instance of the process.
+    #[track_caller]
+    pub fn send<M: 'static>(&self, message: M) -> Result<(), SupervisedError>
+    where
+        C::Serializer: CanSerialize<M>,
+    {
+        self.process()?.send(message);
+        Ok(())
+    }
+
+    /// Makes a request to the current instance of the process.
+    #[track_caller]
+    pub fn request<R: 'static>(&self, request: R) -> Result<C::Response, SupervisedError>
+    where
+        C: RequestHandler<R>,
+        C::Serializer: CanSerialize<R>,
+        C::Serializer: CanSerialize<C::Response>,
+        C::Serializer: CanSerialize<RequestMessage<R, C::Response, C::Serializer>>,
+    {
+        Ok(self.process()?.request(request))
+    }
+
+    // Looks up the instance replacing the dead one. The supervisor may not
+    // have noticed the death yet, it responds once the child is restarted. The
+    // registry is only checked once.
+    fn resolve(&self, dead: ProcessRef<C>) -> Result<ProcessRef<C>, SupervisedError> {
+        let found = match &self.source {
+            Source::Registry(name) => ProcessRef::<C>::lookup(name),
+            Source::Supervisor {
+                node_id,
+                process_id,
+                index,
+                child,
+            } => child(*node_id, *process_id, *index, Some(dead.id()))
+                .map(|(node_id, process_id)| unsafe { ProcessRef::new(node_id, process_id) }),
+        };
+        match found {
+            Some(process) if process != dead && is_running(&process) => {
+                self.process.set(process);
+                Ok(process)
+            }
+            _ => Err(SupervisedError::Gone),
+        }
+    }
+}
+
+impl<C> Clone for SupervisedRef<C>
+where
+    C: AbstractProcess,
+{
+    fn clone(&self) -> Self {
+        SupervisedRef {
+            process: Cell::new(self.process.get()),
+            source: self.source.clone(),
+        }
+    }
+}
+
+impl<C> Debug for SupervisedRef<C>
+where
+    C: AbstractProcess,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SupervisedRef")
+            .field("process", &self.process.get())
+            .finish()
+    }
+}
+
+impl<T> MessageHandler<StoreSnapshot> for T
+where
+    T: Supervisor,
+    T: AbstractProcess<State = SupervisorConfig<T>, Serializer = Bincode>,
+{
+    fn handle(mut state: State<Self>, message: StoreSnapshot) {
+        state.snapshots.insert(message.key, message.snapshot);
+    }
+}
+
+/// Tells the supervisor of the current process that the process is ready,
+/// see [`SupervisorConfig::wait_until_ready`].
+///
+/// It can be called from `init` or any time after it, only the first call
+/// sends the report. The report goes to the process that started the current
+/// [`AbstractProcess`], and is dropped if it's an `AbstractProcess` that
+/// doesn't wait on it. Outside of an `AbstractProcess` it does nothing.
+pub fn ready() {
+    if let Some((node_id, process_id)) = PARENT.take() {
+        let parent = Process::<u64>::new(node_id, process_id);
+        parent.tag_send(Tag::from(READY_TAG), host::process_id());
+    }
+}
+
+/// Remembers the process that started the current `AbstractProcess`.
+pub(crate) fn set_parent(node_id: u64, process_id: u64) {
+    PARENT.set(Some((node_id, process_id)));
+}
+
 pub enum SupervisorStrategy {
     OneForOne,
     OneForAll,
     RestForOne,
 }
 
+/// An event of a supervisor, sent to the processes subscribed with
+/// [`SupervisorConfig::subscribe_events`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SupervisorEvent {
+    /// The child with this index was started.
+    Started { child: usize },
+    /// The `init` function of the child failed. The reason contains the panic
+    /// message if `init` panicked.
+    StartFailed { child: usize, reason: String },
+    /// The child died after it was started.
+    Crashed { child: usize, panic: Option<String> },
+    /// The restart intensity was exceeded after a failure of the child, or the
+    /// child failed to start without a restart intensity. The supervisor
+    /// fails.
+    GaveUp { child: usize },
+}
+
+/// The last failure of a child, see [`ChildStatus`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ChildFailure {
+    /// The `init` function failed, with the reason of the failure.
+    FailedToStart(String),
+    /// The child died after it was started, with the panic message if it
+    /// panicked.
+    Crashed(Option<String>),
+}
+
+/// The argument a child is started with, see
+/// [`SupervisorConfig::children_specs`].
+#[derive(Debug, Clone)]
+pub enum ChildArg<A> {
+    /// The same argument is used for each start of the child.
+    Static(A),
+    /// The function is called inside the supervisor for each start of the
+    /// child, including failed starts. On restarts, it's called after the
+    /// previous instance of the child is dead.
+    FromFn(fn() -> A),
+}
+
+impl<A: Clone> ChildArg<A> {
+    fn get(&self) -> A {
+        match self {
+            ChildArg::Static(arg) => arg.clone(),
+            ChildArg::FromFn(factory) => factory(),
+        }
+    }
+}
+
+impl<A> From<A> for ChildArg<A> {
+    fn from(arg: A) -> Self {
+        ChildArg::Static(arg)
+    }
+}
+
+/// Status of a child, returned by
+/// [`ProcessRef::which_children`](crate::ap::ProcessRef::which_children).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChildStatus {
+    /// Index of the child in the [`Supervisor::Children`] tuple.
+    pub index: usize,
+    /// How many times the child was restarted, including failed starts.
+    pub restarts: u32,
+    pub last_failure: Option<ChildFailure>,
+}
+
+type RestartWaiter<T> = DeferredResponse<Option<(u64, u64)>, T>;
+
 pub struct SupervisorConfig<T>
 where
     T: Supervisor,
 {
     strategy: SupervisorStrategy,
     children: Option<<<T as Supervisor>::Children as Supervisable<T>>::Processes>,
-    children_args: Option<<<T as Supervisor>::Children as Supervisable<T>>::Args>,
+    children_specs: Option<<<T as Supervisor>::Children as Supervisable<T>>::Specs>,
     children_tags: Option<<<T as Supervisor>::Children as Supervisable<T>>::Tags>,
     terminate_subscribers: Vec<DeferredResponse<(), T>>,
+    restart_with_snapshot: bool,
+    snapshot_interval: Duration,
+    // Last snapshot of each child, by the index of the child.
+    snapshots: HashMap<u64, Snapshot>,
+    // Maximum number of restarts in a period of time.
+    restart_intensity: Option<(u32, Duration)>,
+    // Times of the restarts within the current period.
+    restarts: VecDeque<Instant>,
+    children_status: Vec<ChildStatus>,
+    event_subscribers: Vec<Process<SupervisorEvent>>,
+    publish_metrics: bool,
+    // Readiness timeout of the children that block the start of the next
+    // ones, by the index of the child.
+    ready_timeouts: HashMap<usize, Duration>,
+    // Number of children that are running.
+    running: usize,
+    // The child that died last and the reason, until it's restarted.
+    crashed: Option<(usize, &'static str)>,
+    // Requests for the instance replacing a dead child, by the index of the
+    // child.
+    restart_waiters: Vec<(usize, RestartWaiter<T>)>,
     phantom: PhantomData<T>,
 }
 
@@ -186,8 +690,408 @@ where
         self.strategy = strategy;
     }
 
+    /// If set to `true`, children are restarted from the last snapshot of
+    /// their state, see [`AbstractProcess::snapshot`].
+    ///
+    /// It needs to be set before [`children_args`](Self::children_args) is
+    /// called. Default value is `false`.
+    pub fn restart_with_snapshot(&mut self, enabled: bool) {
+        self.restart_with_snapshot = enabled;
+    }
+
+    /// Sets the minimum time between two snapshots of a child.
+    ///
+    /// By default, a snapshot is taken after each message handled by the
+    /// child.
+    pub fn set_snapshot_interval(&mut self, interval: Duration) {
+        self.snapshot_interval = interval;
+    }
+
+    /// Limits the restarts of children to `max_restarts` within `period`.
+    ///
+    /// If a child fails more often, the supervisor gives up and fails too.
+    /// Failed starts count as restarts and are retried only if a restart
+    /// intensity is set. Without one, children are restarted after each crash,
+    /// but a child that fails to start makes the supervisor give up right away.
+    ///
+    /// It needs to be set before [`children_args`](Self::children_args) is
+    /// called.
+    pub fn set_restart_intensity(&mut self, max_restarts: u32, period: Duration) {
+        self.restart_intensity = Some((max_restarts, period));
+    }
+
+    /// Sends each [`SupervisorEvent`] to `subscriber`.
+    ///
+    /// It needs to be set before [`children_args`](Self::children_args) is
+    /// called to receive the events of the first start.
+    pub fn subscribe_events(&mut self, subscriber: Process<SupervisorEvent>) {
+        self.event_subscribers.push(subscriber);
+    }
+
+    /// If set to `false`, the supervisor doesn't publish the
+    /// [`RESTARTS_COUNTER`], [`RUNNING_GAUGE`] and [`GAVE_UP_COUNTER`]
+    /// metrics.
+    ///
+    /// Children are labeled with their name, or their index if they have no
+    /// name. It needs to be set before [`children_args`](Self::children_args)
+    /// is called. Default value is `true`.
+    pub fn publish_metrics(&mut self, enabled: bool) {
+        self.publish_metrics = enabled;
+        metrics::record_published(enabled);
+    }
+
+    /// Waits until the child with `index` calls [`ready`] before the next
+    /// children are started.
+    ///
+    /// If the child doesn't report that it's ready within `timeout`, it's
+    /// killed and the start counts as failed. The same holds for restarts, so
+    /// the children after it always start once it's ready. It needs to be set
+    /// before [`children_args`](Self::children_args) is called.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use lunatic::supervisor::{Supervisor, SupervisorConfig};
+    /// # use lunatic::ap::{AbstractProcess, Config};
+    /// # use lunatic::serializer::Bincode;
+    /// #
+    /// # struct Pool;
+    /// # impl AbstractProcess for Pool {
+    /// #     type Arg = usize;
+    /// #     type State = Self;
+    /// #     type Serializer = Bincode;
+    /// #     type Handlers = ();
+    /// #     type StartupError = ();
+    /// #
+    /// #     fn init(_: Config<Self>, _: usize) -> Result<Self, ()> {
+    /// #         lunatic::supervisor::ready();
+    /// #         Ok(Pool)
+    /// #     }
+    /// # }
+    /// #
+    /// # struct Listener;
+    /// # impl AbstractProcess for Listener {
+    /// #     type Arg = u16;
+    /// #     type State = Self;
+    /// #     type Serializer = Bincode;
+    /// #     type Handlers = ();
+    /// #     type StartupError = ();
+    /// #
+    /// #     fn init(_: Config<Self>, _: u16) -> Result<Self, ()> {
+    /// #         Ok(Listener)
+    /// #     }
+    /// # }
+    ///
+    /// struct Sup;
+    /// impl Supervisor for Sup {
+    ///     type Arg = (usize, u16);
+    ///     type Children = (Pool, Listener);
+    ///
+    ///     fn init(config: &mut SupervisorConfig<Self>, (pool_size, port): (usize, u16)) {
+    ///         // The listener (child 1) is started once the pool accepts checkouts.
+    ///         config.wait_until_ready(0, Duration::from_secs(5));
+    ///         config.children_args(((pool_size, None), (port, None)));
+    ///     }
+    /// }
+    /// ```
+    pub fn wait_until_ready(&mut self, index: usize, timeout: Duration) {
+        self.ready_timeouts.insert(index, timeout);
+    }
+
+    /// Returns the snapshot setup of the child with `index`.
+    pub(crate) fn snapshot_setup(&self, index: u64) -> Option<SnapshotSetup> {
+        if !self.restart_with_snapshot {
+            return None;
+        }
+        Some(SnapshotSetup {
+            restore: self.snapshots.get(&index).cloned(),
+            keeper: Some(SnapshotKeeper::this::<T>(index, self.snapshot_interval)),
+        })
+    }
+
+    /// Starts the children with the arguments and names in `args`.
+    ///
+    /// The arguments are reused for each restart, see
+    /// [`children_specs`](Self::children_specs) for arguments that are created
+    /// on each start.
     pub fn children_args(&mut self, args: <<T as Supervisor>::Children as Supervisable<T>>::Args) {
-        T::Children::start_links(self, args)
+        T::Children::start_links(self, T::Children::specs(args))
+    }
+
+    /// Starts the children with the arguments and names in `specs`.
+    ///
+    /// Each argument is a [`ChildArg`], so it can be created by a function
+    /// each time the child is started.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lunatic::random;
+    /// use lunatic::supervisor::{ChildArg, Supervisor, SupervisorConfig};
+    /// # use lunatic::ap::{AbstractProcess, Config};
+    /// # use lunatic::serializer::Bincode;
+    /// #
+    /// # struct Session;
+    /// # impl AbstractProcess for Session {
+    /// #     type Arg = String;
+    /// #     type State = Self;
+    /// #     type Serializer = Bincode;
+    /// #     type Handlers = ();
+    /// #     type StartupError = ();
+    /// #
+    /// #     fn init(_: Config<Self>, _: String) -> Result<Self, ()> {
+    /// #         Ok(Session)
+    /// #     }
+    /// # }
+    ///
+    /// fn new_token() -> String {
+    ///     random::uuid_v4().to_string()
+    /// }
+    ///
+    /// struct Sup;
+    /// impl Supervisor for Sup {
+    ///     type Arg = ();
+    ///     type Children = (Session,);
+    ///
+    ///     fn init(config: &mut SupervisorConfig<Self>, _: ()) {
+    ///         // Each start of the session gets a new token.
+    ///         config.children_specs(((ChildArg::FromFn(new_token), None),));
+    ///     }
+    /// }
+    /// ```
+    pub fn children_specs(
+        &mut self,
+        specs: <<T as Supervisor>::Children as Supervisable<T>>::Specs,
+    ) {
+        T::Children::start_links(self, specs)
+    }
+
+    /// Starts the child with `index`, retrying failed starts as long as the
+    /// restart intensity allows it.
+    ///
+    /// If the child replaces a `previous` instance, linked with its tag, it's
+    /// started once the previous one is dead. Panics with the reason of the last failure if
+    /// the child can't be started.
+    pub(crate) fn start_child<C>(
+        &mut self,
+        index: usize,
+        (arg, name): &(ChildArg<C::Arg>, Option<String>),
+        link_tag: Tag,
+        previous: Option<(ProcessRef<C>, Tag)>,
+    ) -> ProcessRef<C>
+    where
+        C: AbstractProcess,
+        C::Arg: Clone,
+    {
+        let mut restart = previous.map(|(previous, tag)| match self.crashed {
+            Some((crashed, reason)) if crashed == index => {
+                self.crashed = None;
+                reason
+            }
+            _ => {
+                // Children that are shut down may still be running for a
+                // moment. Normal exits aren't signaled to links, kills are.
+                if previous.is_alive() {
+                    previous.kill();
+                    mailbox::wait(&[tag.id()], Some(EXIT_TIMEOUT));
+                }
+                self.set_running(self.running.saturating_sub(1));
+                "normal"
+            }
+        });
+        loop {
+            if let Some(reason) = restart {
+                self.status(index).restarts += 1;
+                self.count_restart(index, name.as_deref(), reason);
+                if let Some(name) = name.as_deref() {
+                    // Remove first the previous registration
+                    let remove = ProcessRef::<C>::registry_name(name);
+                    unsafe { host::api::registry::remove(remove.as_ptr(), remove.len()) };
+                }
+            }
+            let builder = C::link_with(link_tag).with_snapshots(self.snapshot_setup(index as u64));
+            let result = match name.as_deref() {
+                Some(name) => builder.start_as(name, arg.get()),
+                None => builder.start(arg.get()),
+            };
+            let reason = match result {
+                Ok(process) => match self.wait_ready(index, process, link_tag) {
+                    Ok(()) => {
+                        self.set_running(self.running + 1);
+                        self.notify(SupervisorEvent::Started { child: index });
+                        self.restarted(index, process.node_id(), process.id());
+                        return process;
+                    }
+                    Err(reason) => reason,
+                },
+                Err(StartupError::InitPanicked) => last_init_panic()
+                    .and_then(|panicked| panicked.message().map(str::to_owned))
+                    .unwrap_or_else(|| "`init` panicked".to_owned()),
+                Err(err) => format!("{:?}", err),
+            };
+            self.status(index).last_failure = Some(ChildFailure::FailedToStart(reason.clone()));
+            self.notify(SupervisorEvent::StartFailed {
+                child: index,
+                reason: reason.clone(),
+            });
+            // Without a restart intensity, failed starts aren't retried.
+            if self.restart_intensity.is_none() || !self.register_restart() {
+                self.give_up(index, name.as_deref());
+                panic!(
+                    "Supervisor {} failed to start child {} ({}): {}",
+                    type_name::<T>(),
+                    index,
+                    type_name::<C>(),
+                    reason
+                );
+            }
+            restart = Some("failed_start");
+        }
+    }
+
+    // Waits until the started child with `index` is ready, if it blocks the
+    // next children. Fails if the child dies or the timeout expires first.
+    fn wait_ready<C>(
+        &self,
+        index: usize,
+        process: ProcessRef<C>,
+        link_tag: Tag,
+    ) -> Result<(), String>
+    where
+        C: AbstractProcess,
+    {
+        let Some(&timeout) = self.ready_timeouts.get(&index) else {
+            return Ok(());
+        };
+        let mailbox = unsafe { Mailbox::<u64>::new() };
+        let deadline = time::instant() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(time::instant());
+            match mailbox.tag_receive_timeout(&[Tag::from(READY_TAG), link_tag], remaining) {
+                MailboxResult::Message(process_id) if process_id == process.id() => return Ok(()),
+                // Reports of children that don't block.
+                MailboxResult::Message(_) | MailboxResult::DeserializationFailed(_) => (),
+                MailboxResult::LinkDied(_) => {
+                    return Err(panic::link_panic(link_tag)
+                        .and_then(|panicked| panicked.message().map(str::to_owned))
+                        .unwrap_or_else(|| "died before it was ready".to_owned()));
+                }
+                MailboxResult::TimedOut => {
+                    process.kill();
+                    // The death of the killed child isn't handled as a crash.
+                    mailbox::wait(&[link_tag.id()], None);
+                    return Err(format!("not ready within {:?}", timeout));
+                }
+            }
+        }
+    }
+
+    /// Records the death of the child with `index`, linked with `tag`.
+    ///
+    /// Panics if the restart intensity is exceeded.
+    pub(crate) fn child_crashed(&mut self, index: usize, tag: Tag) {
+        let panicked = panic::link_panic(tag);
+        let reason = if panicked.is_some() {
+            "panic"
+        } else {
+            "killed"
+        };
+        let panic = panicked.and_then(|panicked| panicked.message().map(str::to_owned));
+        self.crashed = Some((index, reason));
+        self.set_running(self.running.saturating_sub(1));
+        self.status(index).last_failure = Some(ChildFailure::Crashed(panic.clone()));
+        self.notify(SupervisorEvent::Crashed {
+            child: index,
+            panic: panic.clone(),
+        });
+        if !self.register_restart() {
+            let name = self.child_name(index);
+            self.give_up(index, name.as_deref());
+            panic!(
+                "Supervisor {} exceeded its restart intensity, child {} crashed: {}",
+                type_name::<T>(),
+                index,
+                panic.as_deref().unwrap_or("no panic message")
+            );
+        }
+    }
+
+    // Returns `false` if the restart would exceed the restart intensity.
+    fn register_restart(&mut self) -> bool {
+        let Some((max_restarts, period)) = self.restart_intensity else {
+            return true;
+        };
+        let now = Instant::now();
+        while self
+            .restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > period)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= max_restarts as usize {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+
+    fn status(&mut self, index: usize) -> &mut ChildStatus {
+        while self.children_status.len() <= index {
+            self.children_status.push(ChildStatus {
+                index: self.children_status.len(),
+                restarts: 0,
+                last_failure: None,
+            });
+        }
+        &mut self.children_status[index]
+    }
+
+    fn set_running(&mut self, running: usize) {
+        self.running = running;
+        if self.publish_metrics {
+            let labels = [("supervisor", type_name::<T>())];
+            metrics::gauge(&with_labels(RUNNING_GAUGE, &labels), running as f64);
+        }
+    }
+
+    fn count_restart(&self, index: usize, name: Option<&str>, reason: &str) {
+        if self.publish_metrics {
+            let child = child_label(index, name);
+            let labels = [
+                ("supervisor", type_name::<T>()),
+                ("child", &*child),
+                ("reason", reason),
+            ];
+            metrics::increment_counter(&with_labels(RESTARTS_COUNTER, &labels));
+        }
+    }
+
+    fn give_up(&mut self, index: usize, name: Option<&str>) {
+        self.restart_waiters
+            .drain(..)
+            .for_each(|(_, waiter)| waiter.send_response(None));
+        if self.publish_metrics {
+            let child = child_label(index, name);
+            let labels = [("supervisor", type_name::<T>()), ("child", &*child)];
+            metrics::increment_counter(&with_labels(GAVE_UP_COUNTER, &labels));
+        }
+        self.notify(SupervisorEvent::GaveUp { child: index });
+    }
+
+    // Returns the name of the child with `index` from the specs.
+    fn child_name(&self, index: usize) -> Option<String> {
+        self.children_specs
+            .as_ref()
+            .and_then(|specs| T::Children::name(specs, index))
+    }
+
+    fn notify(&self, event: SupervisorEvent) {
+        for subscriber in self.event_subscribers.iter() {
+            subscriber.send(event.clone());
+        }
     }
 
     pub(crate) fn get_children(
@@ -197,15 +1101,35 @@ where
     }
 
     fn terminate(mut self) {
+        self.set_running(0);
         self.terminate_subscribers
             .drain(..)
             .for_each(|sub| sub.send_response(()));
+        self.restart_waiters
+            .drain(..)
+            .for_each(|(_, waiter)| waiter.send_response(None));
         T::Children::terminate(self);
     }
 
     pub(crate) fn subscribe_shutdown(&mut self, subscriber: DeferredResponse<(), T>) {
         self.terminate_subscribers.push(subscriber);
     }
+
+    // Responds with the next instance of the child with `index`.
+    fn wait_on_restart(&mut self, index: usize, waiter: RestartWaiter<T>) {
+        self.restart_waiters.push((index, waiter));
+    }
+
+    // Responds to the requests waiting on the restart of the child with `index`.
+    fn restarted(&mut self, index: usize, node_id: u64, process_id: u64) {
+        let (restarted, waiting) = std::mem::take(&mut self.restart_waiters)
+            .into_iter()
+            .partition(|(child, _)| *child == index);
+        self.restart_waiters = waiting;
+        for (_, waiter) in restarted {
+            waiter.send_response(Some((node_id, process_id)));
+        }
+    }
 }
 
 impl<T> Default for SupervisorConfig<T>
@@ -216,23 +1140,48 @@ where
         SupervisorConfig {
             phantom: PhantomData,
             children: None,
-            children_args: None,
+            children_specs: None,
             children_tags: None,
             terminate_subscribers: vec![],
             strategy: SupervisorStrategy::OneForOne,
+            restart_with_snapshot: false,
+            snapshot_interval: Duration::ZERO,
+            snapshots: HashMap::new(),
+            restart_intensity: None,
+            restarts: VecDeque::new(),
+            children_status: Vec::new(),
+            event_subscribers: Vec::new(),
+            publish_metrics: true,
+            ready_timeouts: HashMap::new(),
+            running: 0,
+            crashed: None,
+            restart_waiters: Vec::new(),
         }
     }
 }
 
+// Returns the label of a child, its name or its index.
+fn child_label(index: usize, name: Option<&str>) -> Cow<'_, str> {
+    match name {
+        Some(name) => Cow::Borrowed(name),
+        None => Cow::Owned(index.to_string()),
+    }
+}
+
 pub trait Supervisable<T>
 where
     T: Supervisor,
 {
     type Processes: serde::Serialize + serde::de::DeserializeOwned + Clone;
     type Args: Clone;
+    type Specs;
     type Tags;
 
-    fn start_links(config: &mut SupervisorConfig<T>, args: Self::Args);
+    fn specs(args: Self::Args) -> Self::Specs;
+    fn name(specs: &Self::Specs, index: usize) -> Option<String>;
+    fn child_type(index: usize) -> Option<&'static str>;
+    fn child_id(processes: &Self::Processes, index: usize) -> Option<(u64, u64)>;
+    fn start_links(config: &mut SupervisorConfig<T>, specs: Self::Specs);
     fn terminate(config: SupervisorConfig<T>);
     fn handle_failure(config: &mut SupervisorConfig<T>, tag: Tag);
 }
@@ -299,22 +1248,51 @@ mod macros {
             {
                 type Processes = ($(ProcessRef<$t>,)*);
                 type Args = ($(($t ::Arg, Option<String>),)*);
+                type Specs = ($((ChildArg<$t ::Arg>, Option<String>),)*);
                 type Tags = ($(macros::tag!($t),)*);
 
-                fn start_links(config: &mut SupervisorConfig<K>, args: Self::Args) {
-                    config.children_args = Some(args.clone());
+                #[allow(unused_variables, clippy::unused_unit)]
+                fn specs(args: Self::Args) -> Self::Specs {
+                    ($((ChildArg::Static(args.$i.0), args.$i.1),)*)
+                }
 
+                #[allow(unused_variables)]
+                fn name(specs: &Self::Specs, index: usize) -> Option<String> {
+                    $(
+                        if index == $i {
+                            return specs.$i.1.clone();
+                        }
+                    )*
+                    None
+                }
+
+                #[allow(unused_variables)]
+                fn child_type(index: usize) -> Option<&'static str> {
+                    $(
+                        if index == $i {
+                            return Some(std::any::type_name::<$t>());
+                        }
+                    )*
+                    None
+                }
+
+                #[allow(unused_variables)]
+                fn child_id(processes: &Self::Processes, index: usize) -> Option<(u64, u64)> {
+                    $(
+                        if index == $i {
+                            return Some((processes.$i.node_id(), processes.$i.id()));
+                        }
+                    )*
+                    None
+                }
+
+                #[allow(unused_variables)]
+                fn start_links(config: &mut SupervisorConfig<K>, specs: Self::Specs) {
                     $(
                         let paste::paste!([<tag$i>]) = Tag::new();
-                        let result = match args.$i.1 {
-                            Some(name) => $t::link_with(paste::paste!([<tag$i>])).start_as(name, args.$i.0),
-                            None => $t::link_with(paste::paste!([<tag$i>])).start(args.$i.0),
-                        };
-                        let paste::paste!([<proc$i>]) = match result {
-                            Ok(proc) => proc,
-                            Err(err) => panic!("Supervisor failed to start child `{:?}`", err),
-                        };
+                        let paste::paste!([<proc$i>]) = config.start_child::<$t>($i, &specs.$i, paste::paste!([<tag$i>]), None);
                     )*
+                    config.children_specs = Some(specs);
                     config.children = Some(($(paste::paste!([<proc$i>]),)*));
                     config.children_tags = Some(($(paste::paste!([<tag$i>]),)*));
                 }
@@ -324,8 +1302,21 @@ mod macros {
                     macros::reverse_shutdown!(config, [ $($i)* ]);
                 }
 
-                #[allow(unused_variables)]
+                #[allow(unused_variables, unreachable_code)]
                 fn handle_failure(config: &mut SupervisorConfig<K>, tag: Tag) {
+                    // Check if the tag belongs to one of the children
+                    $(
+                        if tag == config.children_tags.unwrap().$i {
+                            config.child_crashed($i, tag);
+                        } else
+                    )*
+                    {
+                        panic!(
+                            "Supervisor {} received link death signal not belonging to a child",
+                            std::any::type_name::<K>()
+                        );
+                    }
+
                     match config.strategy {
                         // After a failure, just restart the same process.
                         SupervisorStrategy::OneForOne => {
@@ -333,24 +1324,10 @@ mod macros {
                             $(
 
                                 if tag == config.children_tags.unwrap().$i {
-                                    let args = (
-                                        config.children_args.as_ref().unwrap().$i.0.clone(),
-                                        config.children_args.as_ref().unwrap().$i.1.as_deref(),
-                                    );
+                                    let spec = config.children_specs.as_ref().unwrap().$i.clone();
+                                    let previous = (config.children.as_ref().unwrap().$i, config.children_tags.unwrap().$i);
                                     let link_tag = Tag::new();
-                                    let result = match args.1 {
-                                        Some(name) => {
-                                            // Remove first the previous registration
-                                            let remove = format!("{} + ProcessRef + {}", name, std::any::type_name::<$t>());
-                                            unsafe { host::api::registry::remove(remove.as_ptr(), remove.len()) };
-                                            $t::link().start_as(name, args.0)
-                                        },
-                                        None => $t::link_with(link_tag).start(args.0),
-                                    };
-                                    let proc = match result {
-                                        Ok(proc) => proc,
-                                        Err(err) => panic!("Supervisor failed to start child `{:?}`", err),
-                                    };
+                                    let proc = config.start_child::<$t>($i, &spec, link_tag, Some(previous));
                                     config.children.as_mut().unwrap().$i = proc;
                                     config.children_tags.as_mut().unwrap().$i = link_tag;
                                 } else
@@ -366,41 +1343,16 @@ mod macros {
                         }
                         // After a failure, restart all children
                         SupervisorStrategy::OneForAll => {
-                            // check if the tag belongs to one of the children
-                            $(
-                                if tag == config.children_tags.unwrap().$i { } else
-                            )*
-                            {
-                                panic!(
-                                    "Supervisor {} received link death signal not belonging to a child",
-                                    std::any::type_name::<K>()
-                                );
-                            }
-
                             // shutdown children in reversed start order
                             macros::reverse_shutdown!(config, skip tag, [ $($i)* ]);
 
                             // restart all
                             $(
 
-                                let args = (
-                                    config.children_args.as_ref().unwrap().$i.0.clone(),
-                                    config.children_args.as_ref().unwrap().$i.1.as_deref(),
-                                );
+                                let spec = config.children_specs.as_ref().unwrap().$i.clone();
+                                let previous = (config.children.as_ref().unwrap().$i, config.children_tags.unwrap().$i);
                                 let link_tag = Tag::new();
-                                let result = match args.1 {
-                                    Some(name) => {
-                                        // Remove first the previous registration
-                                        let remove = format!("{} + ProcessRef + {}", name, std::any::type_name::<$t>());
-                                        unsafe { host::api::registry::remove(remove.as_ptr(), remove.len()) };
-                                        $t::link().start_as(name, args.0)
-                                    },
-                                    None => $t::link_with(link_tag).start(args.0),
-                                };
-                                let proc = match result {
-                                    Ok(proc) => proc,
-                                    Err(err) => panic!("Supervisor failed to start child `{:?}`", err),
-                                };
+                                let proc = config.start_child::<$t>($i, &spec, link_tag, Some(previous));
                                 config.children.as_mut().unwrap().$i = proc;
                                 config.children_tags.as_mut().unwrap().$i = link_tag;
 
@@ -411,17 +1363,6 @@ mod macros {
                         // are terminated. Then the terminated child process and the rest of the
                         // child processes are restarted.
                         SupervisorStrategy::RestForOne => {
-                            // check if the tag belongs to one of the children
-                            $(
-                                if tag == config.children_tags.unwrap().$i { } else
-                            )*
-                            {
-                                panic!(
-                                    "Supervisor {} received link death signal not belonging to a child",
-                                    std::any::type_name::<K>()
-                                );
-                            }
-
                             // shutdown children after the tag in reversed start order
                             macros::reverse_shutdown!(config, after tag, [ $($i)* ]);
 
@@ -434,24 +1375,10 @@ mod macros {
                                     if seen_tag == true || tag == config.children_tags.unwrap().$i {
                                         seen_tag = true;
 
-                                        let args = (
-                                            config.children_args.as_ref().unwrap().$i.0.clone(),
-                                            config.children_args.as_ref().unwrap().$i.1.as_deref(),
-                                        );
+                                        let spec = config.children_specs.as_ref().unwrap().$i.clone();
+                                        let previous = (config.children.as_ref().unwrap().$i, config.children_tags.unwrap().$i);
                                         let link_tag = Tag::new();
-                                        let result = match args.1 {
-                                            Some(name) => {
-                                                // Remove first the previous registration
-                                                let remove = format!("{} + ProcessRef + {}", name, std::any::type_name::<$t>());
-                                                unsafe { host::api::registry::remove(remove.as_ptr(), remove.len()) };
-                                                $t::link().start_as(name, args.0)
-                                            },
-                                            None => $t::link_with(link_tag).start(args.0),
-                                        };
-                                        let proc = match result {
-                                            Ok(proc) => proc,
-                                            Err(err) => panic!("Supervisor failed to start child `{:?}`", err),
-                                        };
+                                        let proc = config.start_child::<$t>($i, &spec, link_tag, Some(previous));
                                         config.children.as_mut().unwrap().$i = proc;
                                         config.children_tags.as_mut().unwrap().$i = link_tag;
 
@@ -0,0 +1,472 @@
+//! Concurrency limits and cancellation shared between processes.
+//!
+//! A [`Semaphore`] bounds the number of processes that can do something at the
+//! same time, and a [`RateLimiter`] bounds how often something can be done.
+//! A [`CancellationToken`] asks processes to stop their work when they get to
+//! it. All of them are backed by a dedicated process, their handles can be
+//! copied and sent to other processes.
+//!
+//! # Example
+//!
+//! ```
+//! // At most 10 simultaneous connections to the upstream.
+//! let connections = Semaphore::new(10);
+//! Process::spawn(connections, |connections, _: Mailbox<()>| {
+//!     let _permit = connections.acquire();
+//!     let stream = TcpStream::connect("upstream:8080").unwrap();
+//!     // The permit is released when it's dropped.
+//! });
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::api::message;
+use crate::mailbox::TIMEOUT;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, process_local, Mailbox, MailboxResult, Process, Tag};
+
+/// How often the semaphore checks if permit holders are still alive, while
+/// processes are waiting on a permit.
+const HOLDER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tag of the notifications sent to the processes that checked a cancelled
+/// token.
+pub(crate) const CANCELLED_TAG: i64 = 17;
+/// How often the first check of a token makes sure that its owner is alive,
+/// while waiting on the state of the token.
+const OWNER_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+/// How long the owner of a token keeps answering after it was cancelled,
+/// since the last message it received.
+const CANCELLED_LINGER: Duration = Duration::from_secs(5);
+
+/// A counting semaphore.
+///
+/// Permits are released when the [`Permit`] is dropped, or when the process
+/// holding it dies. The runtime doesn't notify the semaphore about the death of
+/// a holder, instead it periodically checks them while other processes are
+/// waiting on a permit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Semaphore {
+    process: Process<SemaphoreMessage>,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with `permits` available permits.
+    pub fn new(permits: usize) -> Self {
+        let process = Process::spawn(permits, semaphore_process);
+        Semaphore { process }
+    }
+
+    /// Waits until a permit is available and returns it.
+    pub fn acquire(&self) -> Permit {
+        self.acquire_(true, None).unwrap()
+    }
+
+    /// Waits until a permit is available, until the `timeout` expires.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Option<Permit> {
+        self.acquire_(true, Some(timeout))
+    }
+
+    /// Returns a permit, if one is available right away.
+    pub fn try_acquire(&self) -> Option<Permit> {
+        self.acquire_(false, None)
+    }
+
+    fn acquire_(&self, wait: bool, timeout: Option<Duration>) -> Option<Permit> {
+        let tag = Tag::new();
+        self.process
+            .send(SemaphoreMessage::Acquire(Process::this(), tag, wait));
+        let mailbox = unsafe { Mailbox::<Option<u64>>::new() };
+        let id = match timeout {
+            Some(timeout) => match mailbox.tag_receive_timeout(&[tag], timeout) {
+                MailboxResult::Message(id) => id,
+                _ => {
+                    // If a permit was granted at the same time as the request was cancelled,
+                    // the semaphore ignores the cancellation and the permit is received.
+                    self.process.send(SemaphoreMessage::Cancel(tag));
+                    mailbox.tag_receive(&[tag])
+                }
+            },
+            None => mailbox.tag_receive(&[tag]),
+        };
+        id.map(|id| Permit {
+            semaphore: self.process,
+            id,
+        })
+    }
+}
+
+/// A permit acquired from a [`Semaphore`].
+///
+/// The permit is released when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    semaphore: Process<SemaphoreMessage>,
+    id: u64,
+}
+
+impl Permit {
+    /// Releases the permit.
+    pub fn release(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.semaphore.send(SemaphoreMessage::Release(self.id));
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum SemaphoreMessage {
+    Acquire(Process<Option<u64>>, Tag, bool),
+    Cancel(Tag),
+    Release(u64),
+}
+
+fn semaphore_process(permits: usize, mailbox: Mailbox<SemaphoreMessage>) {
+    let mut available = permits;
+    let mut next_id = 0;
+    let mut holders: HashMap<u64, Process<Option<u64>>> = HashMap::new();
+    let mut waiting: VecDeque<(Process<Option<u64>>, Tag)> = VecDeque::new();
+    let mut checked = Instant::now();
+    loop {
+        let message = if waiting.is_empty() {
+            MailboxResult::Message(mailbox.receive())
+        } else {
+            mailbox.receive_timeout(HOLDER_CHECK_INTERVAL)
+        };
+        match message {
+            MailboxResult::Message(SemaphoreMessage::Acquire(process, tag, wait)) => {
+                if wait || (waiting.is_empty() && available > 0) {
+                    waiting.push_back((process, tag));
+                } else {
+                    process.tag_send(tag, None);
+                }
+            }
+            MailboxResult::Message(SemaphoreMessage::Cancel(tag)) => {
+                if let Some(index) = waiting.iter().position(|(_, t)| *t == tag) {
+                    let (process, tag) = waiting.remove(index).unwrap();
+                    process.tag_send(tag, None);
+                }
+            }
+            MailboxResult::Message(SemaphoreMessage::Release(id))
+                if holders.remove(&id).is_some() =>
+            {
+                available += 1;
+            }
+            _ => (),
+        }
+        if !waiting.is_empty() && checked.elapsed() >= HOLDER_CHECK_INTERVAL {
+            // Release permits of holders that died.
+            let before = holders.len();
+            holders.retain(|_, holder| holder.node_id() != host::node_id() || holder.is_alive());
+            available += before - holders.len();
+            checked = Instant::now();
+        }
+        while available > 0 {
+            let Some((process, tag)) = waiting.pop_front() else {
+                break;
+            };
+            next_id += 1;
+            available -= 1;
+            holders.insert(next_id, process);
+            process.tag_send(tag, Some(next_id));
+        }
+    }
+}
+
+/// A token bucket rate limiter.
+///
+/// The bucket holds up to `burst` tokens and is refilled with `rate` tokens
+/// per second. Each call to [`until_ready`](Self::until_ready) takes one token.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimiter {
+    process: Process<RateLimiterMessage>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter allowing `rate` operations per second, with
+    /// bursts of up to `burst` operations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` or `burst` is 0.
+    pub fn new(rate: u32, burst: u32) -> Self {
+        assert!(rate > 0, "The rate of a `RateLimiter` can't be 0");
+        assert!(burst > 0, "The burst of a `RateLimiter` can't be 0");
+        let process = Process::spawn((rate, burst), rate_limiter_process);
+        RateLimiter { process }
+    }
+
+    /// Waits until the operation is allowed.
+    pub fn until_ready(&self) {
+        self.ready(true);
+    }
+
+    /// Returns `true` if the operation is allowed right away.
+    ///
+    /// A token is only taken if `true` is returned.
+    pub fn try_ready(&self) -> bool {
+        self.ready(false)
+    }
+
+    fn ready(&self, wait: bool) -> bool {
+        let tag = Tag::new();
+        self.process
+            .send(RateLimiterMessage::Ready(Process::this(), tag, wait));
+        let mailbox = unsafe { Mailbox::<bool>::new() };
+        mailbox.tag_receive(&[tag])
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum RateLimiterMessage {
+    Ready(Process<bool>, Tag, bool),
+}
+
+fn rate_limiter_process((rate, burst): (u32, u32), mailbox: Mailbox<RateLimiterMessage>) {
+    let interval = Duration::from_secs(1) / rate;
+    let mut tokens = burst as f64;
+    let mut refilled = Instant::now();
+    let mut waiting: VecDeque<(Process<bool>, Tag)> = VecDeque::new();
+    loop {
+        let message = if waiting.is_empty() {
+            MailboxResult::Message(mailbox.receive())
+        } else {
+            // Wake up when the next token is available.
+            let missing = (1.0 - tokens).max(0.0);
+            mailbox.receive_timeout(interval.mul_f64(missing))
+        };
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(refilled).as_secs_f64();
+        tokens = (tokens + elapsed * rate as f64).min(burst as f64);
+        refilled = now;
+
+        if let MailboxResult::Message(RateLimiterMessage::Ready(process, tag, wait)) = message {
+            if wait || (waiting.is_empty() && tokens >= 1.0) {
+                waiting.push_back((process, tag));
+            } else {
+                process.tag_send(tag, false);
+            }
+        }
+        while tokens >= 1.0 {
+            let Some((process, tag)) = waiting.pop_front() else {
+                break;
+            };
+            tokens -= 1.0;
+            process.tag_send(tag, true);
+        }
+    }
+}
+
+/// A token that asks processes to stop their work, e.g. once the result of a
+/// request isn't needed anymore.
+///
+/// Unlike [killing](Process::kill) a process, cancellation is cooperative:
+/// the work checks [`is_cancelled`](Self::is_cancelled) when it's convenient,
+/// and can finish what it's doing or return partial results. Tokens can be
+/// copied and sent to other processes, [`cancel`](Self::cancel) is observed
+/// by all of them.
+///
+/// [Child tokens](Self::child_token) are cancelled together with their parent,
+/// but can also be cancelled alone, e.g. to clean up after one request of a
+/// connection.
+///
+/// # Cost
+///
+/// The state of the token is kept by an owner process. The first check of a
+/// token in a process waits on the owner, and subscribes the process to the
+/// cancellation. Later checks only look at the state cached by the process,
+/// which is updated by a notification from the owner, so they can be used in
+/// tight loops.
+///
+/// The owner process runs until the token is cancelled, plus a few seconds to
+/// answer late checks. A token is also considered cancelled if its owner on
+/// the local node isn't running anymore.
+///
+/// # Example
+///
+/// ```
+/// let token = CancellationToken::new();
+/// Process::spawn(token, |token, _: Mailbox<()>| {
+///     let mut result = Vec::new();
+///     while !token.is_cancelled() {
+///         result.push(compute_next());
+///     }
+///     store(result);
+/// });
+/// // Later, from any process.
+/// token.cancel();
+/// ```
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CancellationToken {
+    process: Process<TokenMessage>,
+}
+
+impl CancellationToken {
+    /// Creates a new token that isn't cancelled.
+    pub fn new() -> Self {
+        Self::spawn(None)
+    }
+
+    /// Creates a token that is cancelled when this token is cancelled.
+    ///
+    /// Cancelling the child doesn't cancel this token.
+    pub fn child_token(&self) -> Self {
+        if self.is_cancelled() {
+            let child = Self::new();
+            child.cancel();
+            return child;
+        }
+        Self::spawn(Some(self.process))
+    }
+
+    fn spawn(parent: Option<Process<TokenMessage>>) -> Self {
+        let process = Process::spawn(parent, token_process);
+        CancellationToken { process }
+    }
+
+    /// Cancels the token and all of its children.
+    ///
+    /// The processes that checked the token are notified and observe the
+    /// cancellation on their next check.
+    pub fn cancel(&self) {
+        self.process.send(TokenMessage::Cancel);
+        TOKENS.with_borrow_mut(|mut tokens| tokens.insert(self.key(), true));
+    }
+
+    /// Returns `true` if the token was cancelled.
+    ///
+    /// The first check in a process waits on the owner of the token, later
+    /// checks don't wait.
+    pub fn is_cancelled(&self) -> bool {
+        match TOKENS.with_borrow(|tokens| tokens.get(&self.key()).copied()) {
+            Some(true) => true,
+            Some(false) => {
+                store_cancellations();
+                TOKENS.with_borrow(|tokens| tokens[&self.key()])
+            }
+            None => {
+                let cancelled = self.subscribe();
+                TOKENS.with_borrow_mut(|mut tokens| tokens.insert(self.key(), cancelled));
+                cancelled
+            }
+        }
+    }
+
+    /// Sends an empty message `()`, serialized with [`Bincode`], with `tag` to
+    /// the current process once the token is cancelled.
+    ///
+    /// The message can be received with [`Mailbox::tag_receive`], e.g. in a
+    /// [`select!`](crate::select!) arm next to other messages. It's sent right
+    /// away if the token is already cancelled.
+    pub fn cancelled_into_mailbox(&self, tag: Tag) {
+        self.process
+            .send(TokenMessage::NotifyMailbox(Process::this(), tag));
+    }
+
+    // Subscribes the current process and returns the state of the token.
+    fn subscribe(&self) -> bool {
+        let tag = Tag::new();
+        self.process
+            .send(TokenMessage::Subscribe(Process::this(), tag));
+        let mailbox = unsafe { Mailbox::<bool>::new() };
+        loop {
+            match mailbox.tag_receive_timeout(&[tag], OWNER_CHECK_INTERVAL) {
+                MailboxResult::Message(cancelled) => return cancelled,
+                _ if self.process.node_id() == host::node_id() && !self.process.is_alive() => {
+                    return true
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn key(&self) -> (u64, u64) {
+        (self.process.node_id(), self.process.id())
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+process_local! {
+    // State of the tokens checked by the current process, by owner.
+    static TOKENS: RefCell<HashMap<(u64, u64), bool>> = RefCell::new(HashMap::new());
+}
+
+/// Stores the cancellation that is currently in the message buffer.
+pub(crate) fn store_cancellation() {
+    if let Ok(key) = <Bincode as CanSerialize<(u64, u64)>>::decode() {
+        TOKENS.with_borrow_mut(|mut tokens| tokens.insert(key, true));
+    }
+}
+
+// Collects the cancellations that are still waiting in the mailbox.
+fn store_cancellations() {
+    let tags = [CANCELLED_TAG];
+    while unsafe { message::receive(tags.as_ptr(), tags.len(), 0) } != TIMEOUT {
+        store_cancellation();
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum TokenMessage {
+    Cancel,
+    Subscribe(Process<bool>, Tag),
+    NotifyMailbox(Process<()>, Tag),
+    AddChild(Process<TokenMessage>),
+}
+
+fn token_process(parent: Option<Process<TokenMessage>>, mailbox: Mailbox<TokenMessage>) {
+    let this = Process::<TokenMessage>::this();
+    if let Some(parent) = parent {
+        parent.send(TokenMessage::AddChild(this));
+    }
+    let key = (this.node_id(), this.id());
+    let notify = |process: Process<(u64, u64)>| process.tag_send(Tag::from(CANCELLED_TAG), key);
+    let mut subscribers: Vec<Process<(u64, u64)>> = Vec::new();
+    let mut mailboxes: Vec<(Process<()>, Tag)> = Vec::new();
+    let mut children: Vec<Process<TokenMessage>> = Vec::new();
+    // Waits on the cancellation.
+    loop {
+        match mailbox.receive() {
+            TokenMessage::Cancel => break,
+            TokenMessage::Subscribe(process, tag) => {
+                process.tag_send(tag, false);
+                subscribers.push(Process::new(process.node_id(), process.id()));
+            }
+            TokenMessage::NotifyMailbox(process, tag) => mailboxes.push((process, tag)),
+            TokenMessage::AddChild(child) => children.push(child),
+        }
+    }
+    subscribers.into_iter().for_each(notify);
+    mailboxes
+        .into_iter()
+        .for_each(|(process, tag)| process.tag_send(tag, ()));
+    children
+        .into_iter()
+        .for_each(|child| child.send(TokenMessage::Cancel));
+    // Answers late checks until it's idle.
+    while let MailboxResult::Message(message) = mailbox.receive_timeout(CANCELLED_LINGER) {
+        match message {
+            TokenMessage::Cancel => (),
+            TokenMessage::Subscribe(process, tag) => process.tag_send(tag, true),
+            TokenMessage::NotifyMailbox(process, tag) => process.tag_send(tag, ()),
+            TokenMessage::AddChild(child) => child.send(TokenMessage::Cancel),
+        }
+    }
+}
@@ -1,13 +1,50 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{process_local, random};
+
 /// A `i64` value used as a message tag.
 ///
 /// Processes can selectively receive messages based on the message's tag. This
 /// mechanism can be used to handle messages in a different order from their
 /// arrival.
 ///
-/// Creating a new tag will return a process-unique value. Some tag values are
-/// reserved for internal use only, but the range from 64 to 128 can be used by
-/// the developer to assign application specific meaning.
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+/// Creating a new tag will return a process-unique value. Tags that need to be
+/// known by multiple processes, or even nodes, can be derived from a name with
+/// [`Tag::from_str`].
+///
+/// The values of tags are split into the following ranges:
+/// - `0` is used by [`Tag::none`].
+/// - `1..=63` is reserved for internal use.
+/// - `64..=128` can be used by the developer to assign application specific
+///   meaning, see [`Tag::special`].
+/// - `129..2^56` is used by [`Tag::new`].
+/// - `2^56..2^62` is reserved for internal use, like encoding the handler ID
+///   for `AbstractProcesses` into the top byte of a tag from [`Tag::new`].
+/// - `2^62..2^63` is used by the abort notifications of
+///   [`Protocol`](crate::protocol::Protocol) sessions.
+/// - Negative values are used by named tags, see [`Tag::from_str`].
+///
+/// # Secure tags
+///
+/// Tags are only values, the host doesn't check who sends a message with a
+/// given tag. The tags returned by [`Tag::new`] follow each other, so a
+/// process that holds a handle to another process and knows roughly how many
+/// tags it created can guess the tag of a request it has in flight and send
+/// it a forged reply. This matters if processes that don't trust each other,
+/// like processes of different tenants, can get handles to each other.
+///
+/// A process can't protect itself from receiving messages, but it can make
+/// its tags unguessable. [`Tag::new_secure`] returns a random tag, and
+/// [`Tag::use_secure_tags`] makes all requests sent by the current process use
+/// random tags for their replies. The host can't rewrite or check the tags of
+/// messages crossing from one process to another, so this doesn't prevent a
+/// process from sending messages with tags it learned, e.g. tags that were
+/// sent to it as part of a request it handles.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Tag(i64);
 
 impl Tag {
@@ -29,6 +66,41 @@ impl Tag {
         }
     }
 
+    /// Returns a tag that can't be guessed by other processes.
+    ///
+    /// The tag holds 56 random bits from the host's entropy source, in the
+    /// same range as the tags returned by [`Tag::new`]. It's very unlikely,
+    /// but not impossible, that it's equal to another tag of this process.
+    pub fn new_secure() -> Tag {
+        loop {
+            let id = (random::u64() & 0xFFFFFFFFFFFFFF) as i64;
+            if id > 128 {
+                return Tag(id);
+            }
+        }
+    }
+
+    /// Makes the requests sent by the current process use tags created with
+    /// [`Tag::new_secure`] for their replies, so that other processes can't
+    /// guess them and answer in place of the process handling the request.
+    ///
+    /// This affects requests to
+    /// [`AbstractProcesses`](crate::ap::AbstractProcess) and
+    /// [`Process::request`](crate::Process). Secure tags are disabled by
+    /// default, because each of them needs a call to the host.
+    pub fn use_secure_tags(enabled: bool) {
+        SECURE.set(enabled);
+    }
+
+    /// Returns a tag for the reply to a request sent by the current process.
+    pub(crate) fn for_reply() -> Tag {
+        if SECURE.get() {
+            Tag::new_secure()
+        } else {
+            Tag::new()
+        }
+    }
+
     /// Returns a special tag that is used by [`Process::send`](crate::Process).
     ///
     /// Most messages where the order is not significant use this tag.
@@ -48,16 +120,88 @@ impl Tag {
         }
     }
 
+    /// Returns a tag derived from the `name`.
+    ///
+    /// The tag is the same in every process and on every node, as long as the
+    /// same `name` is used. It's derived from a 63 bit hash of the name, so
+    /// it's very unlikely that two names result in the same tag. Named tags
+    /// are negative, so they never collide with tags from other ranges.
+    ///
+    /// The [`Debug`](fmt::Debug) output shows the name for the first 256 names
+    /// used in the current process, and the value for all others.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lunatic::{Mailbox, Process, Tag};
+    ///
+    /// let worker = Process::spawn((), |_, mailbox: Mailbox<()>| {
+    ///     mailbox.tag_receive(&[Tag::from_str("shutdown")]);
+    /// });
+    /// worker.tag_send(Tag::from_str("shutdown"), ());
+    /// ```
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Tag {
+        // 64 bit FNV-1a, it's stable across compiler versions and platforms.
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in name.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        let tag = Tag((hash | (1 << 63)) as i64);
+        NAMES.with_borrow_mut(|mut names| {
+            if names.len() < MAX_NAMES {
+                names.entry(tag.0).or_insert_with(|| name.to_owned());
+            }
+        });
+        tag
+    }
+
+    /// Returns `true` if the tag was created with [`Tag::from_str`].
+    pub fn is_named(&self) -> bool {
+        self.0 < 0
+    }
+
     pub fn id(&self) -> i64 {
         self.0
     }
 }
 
+// How many names of tags created with `Tag::from_str` a process keeps for
+// their `Debug` output.
+const MAX_NAMES: usize = 256;
+
+process_local! {
+    // Names of the tags created with `Tag::from_str` inside this process.
+    static NAMES: RefCell<HashMap<i64, String>> = RefCell::new(HashMap::new());
+    // Set if requests use tags created with `Tag::new_secure`.
+    static SECURE: Cell<bool> = Cell::new(false);
+}
+
+impl FromStr for Tag {
+    type Err = Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(Tag::from_str(name))
+    }
+}
+
+impl fmt::Debug for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only names used inside this process are known.
+        let name = self
+            .is_named()
+            .then(|| NAMES.with_borrow(|names| names.get(&self.0).cloned()));
+        match name.flatten() {
+            Some(name) => f.debug_tuple("Tag").field(&name).finish(),
+            None => f.debug_tuple("Tag").field(&self.0).finish(),
+        }
+    }
+}
+
 // Reserve first 128 tags for special purposes.
 static mut COUNTER: i64 = 128;
 
-impl Tag {}
-
 impl Default for Tag {
     fn default() -> Self {
         Self::new()
@@ -88,4 +232,15 @@ mod tests {
 
         assert_eq!(Tag::none(), Tag::none());
     }
+
+    #[test]
+    fn named_tag() {
+        let tag = Tag::from_str("shutdown");
+        assert_eq!(tag, Tag::from_str("shutdown"));
+        assert_ne!(tag, Tag::from_str("startup"));
+        assert!(tag.is_named());
+        assert!(!Tag::new().is_named());
+        assert_eq!(format!("{tag:?}"), "Tag(\"shutdown\")");
+        assert_eq!(format!("{:?}", Tag::none()), "Tag(0)");
+    }
 }
@@ -0,0 +1,333 @@
+//! Running CPU heavy work outside of the current process.
+//!
+//! A long computation blocks the process running it from handling any other
+//! messages. [`spawn_blocking`] moves the computation into a separate process
+//! and returns a [`JoinHandle`] that can be used to wait on the result.
+//!
+//! The number of computations running at the same time is bounded by a compute
+//! pool that is shared by all processes of the application. It's created on
+//! first use and can be configured with [`configure`]. Each computation runs in
+//! a fresh process, with the fuel and memory limits of the pool applied to it.
+//!
+//! # Example
+//!
+//! ```
+//! let handle = task::spawn_blocking_with(image, |image| resize(image, 128, 128));
+//! // Keep handling other messages ...
+//! let thumbnail = handle.join_timeout(Duration::from_secs(5))?;
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::function::FuncRef;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, select, Mailbox, MailboxResult, Process, ProcessConfig, Tag};
+
+/// Name under which the compute pool is registered.
+const POOL_NAME: &str = "lunatic::task::compute_pool";
+
+/// Error returned when waiting on a [`JoinHandle`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinError {
+    /// The process running the computation died, e.g. it panicked or ran out
+    /// of fuel.
+    #[error("the computation failed")]
+    Failed,
+    /// The compute pool was saturated and rejected the computation.
+    #[error("the compute pool is saturated")]
+    Rejected,
+    /// The computation didn't finish before the timeout.
+    #[error("the computation didn't finish in time")]
+    Timeout,
+}
+
+/// What the compute pool does with new computations when all workers are busy.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Saturation {
+    /// Queue the computation until a worker is free.
+    #[default]
+    Queue,
+    /// Queue up to the given number of computations and reject the rest.
+    QueueLimit(usize),
+    /// Reject the computation.
+    Reject,
+}
+
+/// Configuration of the compute pool.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The maximum number of computations running at the same time.
+    pub max_workers: usize,
+    /// The maximum amount of fuel each computation can use.
+    pub max_fuel: Option<u64>,
+    /// The maximum amount of memory in bytes each computation can use.
+    pub max_memory: Option<u64>,
+    pub saturation: Saturation,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_workers: 8,
+            max_fuel: None,
+            max_memory: None,
+            saturation: Saturation::Queue,
+        }
+    }
+}
+
+/// Changes the configuration of the compute pool.
+///
+/// Computations that are already running keep the limits they were started
+/// with.
+///
+/// # Panics
+///
+/// Panics if `max_workers` is 0.
+pub fn configure(config: PoolConfig) {
+    assert!(
+        config.max_workers > 0,
+        "The compute pool needs at least one worker"
+    );
+    pool().send(PoolMessage::Configure(config));
+}
+
+/// Runs `f` in a separate process and returns a handle to its result.
+pub fn spawn_blocking<T>(f: fn() -> T) -> JoinHandle<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    spawn_blocking_with(FuncRef::new(f), |f: FuncRef<fn() -> T>| f.get()())
+}
+
+/// Runs `f` with `input` in a separate process and returns a handle to its
+/// result.
+pub fn spawn_blocking_with<I, T>(input: I, f: fn(I) -> T) -> JoinHandle<T>
+where
+    I: Serialize + DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+{
+    let tag = Tag::new();
+    let abort = Tag::new();
+    let job: Job<I, T> = (Process::this(), tag, FuncRef::new(f), input);
+    let payload = bincode::serialize(&job).expect("Failed to serialize the computation");
+    pool().send(PoolMessage::Run {
+        runner: FuncRef::new(run_job::<I, T>),
+        payload,
+        caller: Process::this(),
+        abort,
+    });
+    JoinHandle {
+        tag,
+        abort,
+        result: PhantomData,
+    }
+}
+
+type Job<I, T> = (Process<T>, Tag, FuncRef<fn(I) -> T>, I);
+
+/// Handle to the result of a computation started with [`spawn_blocking`].
+///
+/// Dropping the handle doesn't stop the computation.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    tag: Tag,
+    abort: Tag,
+    result: PhantomData<T>,
+}
+
+impl<T> JoinHandle<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Waits on the result of the computation.
+    pub fn join(self) -> Result<T, JoinError> {
+        self.join_(None)
+    }
+
+    /// Waits on the result of the computation, until the `timeout` expires.
+    ///
+    /// If the timeout expires, the computation is stopped.
+    pub fn join_timeout(self, timeout: Duration) -> Result<T, JoinError> {
+        self.join_(Some(timeout))
+    }
+
+    fn join_(self, timeout: Option<Duration>) -> Result<T, JoinError> {
+        match select::receive(&[self.tag, self.abort], timeout) {
+            Some((_, tag)) if tag == self.tag => {
+                Ok(Bincode::decode().expect("Failed to deserialize the result"))
+            }
+            Some(_) => Err(Bincode::decode().expect("Failed to deserialize the join error")),
+            None => {
+                pool().send(PoolMessage::Cancel(self.abort));
+                Err(JoinError::Timeout)
+            }
+        }
+    }
+}
+
+fn pool() -> Process<PoolMessage> {
+    let mut node_id: u64 = 0;
+    let mut process_id: u64 = 0;
+    unsafe {
+        match host::api::registry::get_or_put_later(
+            POOL_NAME.as_ptr(),
+            POOL_NAME.len(),
+            &mut node_id,
+            &mut process_id,
+        ) {
+            0 => Process::new(node_id, process_id),
+            _ => {
+                let process = Process::spawn(PoolConfig::default(), pool_process);
+                host::api::registry::put(
+                    POOL_NAME.as_ptr(),
+                    POOL_NAME.len(),
+                    process.node_id(),
+                    process.id(),
+                );
+                process
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum PoolMessage {
+    Run {
+        runner: FuncRef<fn(Vec<u8>)>,
+        payload: Vec<u8>,
+        caller: Process<()>,
+        abort: Tag,
+    },
+    Done(Tag),
+    Cancel(Tag),
+    Configure(PoolConfig),
+}
+
+struct Pending {
+    runner: FuncRef<fn(Vec<u8>)>,
+    payload: Vec<u8>,
+    caller: Process<()>,
+    abort: Tag,
+}
+
+fn pool_process(config: PoolConfig, mailbox: Mailbox<PoolMessage>) {
+    let mailbox = mailbox.catch_link_failure();
+    let this = mailbox.this();
+    let mut config = config;
+    let mut spawn_config = process_config(&config);
+    // Running computations by the tag of the link, which is also the abort tag
+    // of the caller.
+    let mut running: HashMap<Tag, (Process<()>, Process<()>)> = HashMap::new();
+    let mut queue: VecDeque<Pending> = VecDeque::new();
+    loop {
+        match mailbox.receive() {
+            MailboxResult::Message(PoolMessage::Run {
+                runner,
+                payload,
+                caller,
+                abort,
+            }) => {
+                let pending = Pending {
+                    runner,
+                    payload,
+                    caller,
+                    abort,
+                };
+                let queue_limit = match config.saturation {
+                    Saturation::Queue => usize::MAX,
+                    Saturation::QueueLimit(limit) => limit,
+                    Saturation::Reject => 0,
+                };
+                if running.len() < config.max_workers || queue.len() < queue_limit {
+                    queue.push_back(pending);
+                } else {
+                    notify(caller, abort, JoinError::Rejected);
+                }
+            }
+            MailboxResult::Message(PoolMessage::Done(tag)) => {
+                running.remove(&tag);
+            }
+            MailboxResult::Message(PoolMessage::Cancel(tag)) => {
+                if let Some((_, worker)) = running.remove(&tag) {
+                    // Unlink before killing, so that the kill isn't reported as a failure.
+                    worker.unlink();
+                    worker.kill();
+                }
+                queue.retain(|pending| pending.abort != tag);
+            }
+            MailboxResult::Message(PoolMessage::Configure(new_config)) => {
+                config = new_config;
+                spawn_config = process_config(&config);
+            }
+            MailboxResult::LinkDied(tag) => {
+                if let Some((caller, _)) = running.remove(&tag) {
+                    notify(caller, tag, JoinError::Failed);
+                }
+            }
+            _ => (),
+        }
+
+        while running.len() < config.max_workers {
+            let Some(pending) = queue.pop_front() else {
+                break;
+            };
+            let capture = (this, pending.abort, pending.runner, pending.payload);
+            let worker = match &spawn_config {
+                Some(spawn_config) => Process::spawn_link_config_tag(
+                    spawn_config,
+                    capture,
+                    pending.abort,
+                    worker_process,
+                ),
+                None => Process::spawn_link_tag(capture, pending.abort, worker_process),
+            };
+            running.insert(pending.abort, (pending.caller, worker));
+        }
+    }
+}
+
+/// Returns the configuration used to spawn workers, or `None` if the pool
+/// doesn't set any limits.
+fn process_config(config: &PoolConfig) -> Option<ProcessConfig> {
+    if config.max_fuel.is_none() && config.max_memory.is_none() {
+        return None;
+    }
+    let mut process_config = ProcessConfig::new().expect("The compute pool can't create configs");
+    if let Some(max_fuel) = config.max_fuel {
+        process_config.set_max_fuel(max_fuel);
+    }
+    if let Some(max_memory) = config.max_memory {
+        process_config.set_max_memory(max_memory);
+    }
+    Some(process_config)
+}
+
+fn notify(caller: Process<()>, abort: Tag, error: JoinError) {
+    let caller = Process::<JoinError>::new(caller.node_id(), caller.id());
+    caller.tag_send(abort, error);
+}
+
+type WorkerCapture = (Process<PoolMessage>, Tag, FuncRef<fn(Vec<u8>)>, Vec<u8>);
+
+fn worker_process((pool, tag, runner, payload): WorkerCapture, _: Mailbox<()>) {
+    runner.get()(payload);
+    pool.send(PoolMessage::Done(tag));
+}
+
+fn run_job<I, T>(payload: Vec<u8>)
+where
+    I: Serialize + DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+{
+    let (caller, tag, f, input): Job<I, T> =
+        bincode::deserialize(&payload).expect("Failed to deserialize the computation");
+    caller.tag_send(tag, f.get()(input));
+}
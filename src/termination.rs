@@ -0,0 +1,45 @@
+use std::fmt::Debug;
+
+/// Ends the current process with the exit status `code`.
+///
+/// In the root process, `code` becomes the exit status of `lunatic run`, so
+/// scripts can check it. Other processes only end themselves. Destructors on
+/// the stack of the current process don't run, the same as with
+/// [`std::process::exit`].
+///
+/// # Example
+///
+/// ```no_run
+/// #[lunatic::main]
+/// fn main(_: Mailbox<()>) {
+///     if std::env::args().len() < 2 {
+///         eprintln!("usage: app <config>");
+///         lunatic::exit(2);
+///     }
+/// }
+/// ```
+pub fn exit(code: i32) -> ! {
+    // WASI's `proc_exit` ends the process, and the host reports the status of
+    // the root process as its own.
+    std::process::exit(code)
+}
+
+/// Values that can be returned from a `main` function annotated with
+/// [`lunatic::main`](crate::main).
+#[doc(hidden)]
+pub trait MainResult {
+    fn report(self);
+}
+
+impl MainResult for () {
+    fn report(self) {}
+}
+
+impl<E: Debug> MainResult for Result<(), E> {
+    fn report(self) {
+        if let Err(err) = self {
+            eprintln!("Error: {err:?}");
+            exit(1);
+        }
+    }
+}
@@ -1,3 +1,13 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::host;
+use crate::panic::{catch_panic, payload_message};
+use crate::{process_local, sleep, Mailbox, MailboxResult, Process, ProcessConfig, Tag};
+
 // This function is used internally by the `#[lunatic::test]` macro to check if
 // the value returned from the test is not `Result::Err`.
 pub fn assert_test_result<T: TestReturnValue + std::fmt::Debug>(result: T) {
@@ -8,6 +18,27 @@ pub fn assert_test_result<T: TestReturnValue + std::fmt::Debug>(result: T) {
     );
 }
 
+// This function is used internally by the `#[lunatic::test]` macro to check
+// that tests marked with `#[should_panic]` panic with the `expected` message.
+pub fn assert_test_panics<F: FnOnce()>(name: &str, expected: &str, test: F) {
+    let panicked = match catch_panic(test) {
+        Ok(()) => panic!("test {} did not panic as expected", name),
+        Err(panicked) => panicked,
+    };
+    let message = panicked.message().unwrap_or_default();
+    if !message.contains(expected) {
+        panic!(
+            "test {} panicked with an unexpected message\n      panic message: `{:?}`,\n expected substring: `{:?}`",
+            name, message, expected
+        );
+    }
+}
+
+// Extracts the message out of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload_message(payload).map_or_else(|| "Box<dyn Any>".to_string(), |(message, _)| message)
+}
+
 pub trait TestReturnValue {
     fn is_success(&self) -> bool;
 }
@@ -23,3 +54,278 @@ impl<T, E> TestReturnValue for Result<T, E> {
         self.is_ok()
     }
 }
+
+/// Kills the test process if it doesn't finish before the timeout.
+///
+/// It's used internally by the `#[lunatic::test(timeout = ...)]` macro. The
+/// guard stops watching the test process once it's dropped.
+pub struct TimeoutGuard(Process<()>);
+
+impl Drop for TimeoutGuard {
+    fn drop(&mut self) {
+        self.0.kill();
+    }
+}
+
+// This function is used internally by the `#[lunatic::test]` macro to spawn a
+// process that watches over the test process and kills it after `timeout_ms`.
+pub fn timeout_guard(name: &str, timeout_ms: u64) -> TimeoutGuard {
+    let test: Process<()> = Process::this();
+    let watcher = Process::spawn(
+        (test, name.to_owned(), timeout_ms),
+        |(test, name, timeout_ms), _: Mailbox<()>| {
+            sleep(Duration::from_millis(timeout_ms));
+            // The test could have failed in the meantime.
+            if test.is_alive() {
+                eprintln!("test {} timed out after {}ms", name, timeout_ms);
+                test.kill();
+            }
+        },
+    );
+    TimeoutGuard(watcher)
+}
+
+/// Runs the current process and the processes it spawns on a virtual clock,
+/// until the guard is dropped.
+///
+/// It's used by the `#[lunatic::test(virtual_time)]` macro. [`sleep`], timers
+/// created with `send_after` and receive timeouts wait on the virtual clock,
+/// and [`time::instant`](crate::time::instant) returns the virtual time. The
+/// clock only advances once all of these processes are blocked, and then
+/// jumps to the earliest deadline, so waiting on minutes of virtual time takes
+/// milliseconds.
+///
+/// Messages received on virtual time, and messages sent with a delay, lose the
+/// resources attached to them. Processes on other nodes keep using real time.
+///
+/// # Panics
+///
+/// Panics if the current process already runs on virtual time.
+pub fn virtual_time() -> VirtualTimeGuard {
+    VirtualTimeGuard(crate::virtual_time::start())
+}
+
+/// Switches the process back to real time once it's dropped, see
+/// [`virtual_time`].
+pub struct VirtualTimeGuard(Process<crate::virtual_time::ClockMessage>);
+
+impl Drop for VirtualTimeGuard {
+    fn drop(&mut self) {
+        crate::virtual_time::stop(self.0);
+    }
+}
+
+/// Events sent from a test running under a custom [`ProcessConfig`] back to
+/// the test process.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ConfigTestEvent {
+    Panicked(String),
+    Finished,
+}
+
+// This function is used internally by the `#[lunatic::test(...)]` macro to run
+// the test body in a linked process spawned with `config`.
+//
+// `limits` contains the limits set through macro arguments and is used to
+// explain why the process died if it didn't report back.
+pub fn run_with_config(
+    name: &str,
+    config: ProcessConfig,
+    limits: &[(&str, u64)],
+    entry: fn(Process<ConfigTestEvent>, Mailbox<()>),
+) {
+    let mailbox = unsafe { Mailbox::<ConfigTestEvent>::new() }.catch_link_failure();
+    Process::<()>::spawn_link_config(&config, mailbox.this(), entry);
+
+    let mut last_panic = None;
+    loop {
+        match mailbox.receive() {
+            MailboxResult::Message(ConfigTestEvent::Finished) => return,
+            MailboxResult::Message(ConfigTestEvent::Panicked(message)) => {
+                last_panic = Some(message)
+            }
+            MailboxResult::LinkDied(_) => break,
+            _ => (),
+        }
+    }
+
+    if let Some(message) = last_panic {
+        panic!("{}", message);
+    }
+    // The process died without panicking, this only happens if it was trapped
+    // by the runtime for exceeding one of the limits. The runtime doesn't
+    // report which one it was.
+    let limits = if limits.is_empty() {
+        vec![
+            ("max_memory", config.get_max_memory()),
+            ("max_fuel", config.get_max_fuel()),
+        ]
+    } else {
+        limits.to_vec()
+    };
+    let described = limits
+        .iter()
+        .map(|(limit, value)| format!("`{} = {}`", limit, value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if limits.len() == 1 {
+        panic!("test {} exceeded the process limit {}", name, described);
+    }
+    panic!(
+        "test {} exceeded one of the process limits {}",
+        name, described
+    );
+}
+
+// This function is used internally by the `#[lunatic::test(...)]` macro as the
+// entry of processes created by `run_with_config`.
+pub fn config_test_entry(parent: Process<ConfigTestEvent>, test: fn()) {
+    // The test process already reports this panic, drop the hook installed by
+    // `adopt_test_owner` so that it isn't also reported as a child panic.
+    let _ = std::panic::take_hook();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        parent.send(ConfigTestEvent::Panicked(panic_message(info.payload())));
+        default_hook(info);
+    }));
+    test();
+    parent.send(ConfigTestEvent::Finished);
+}
+
+// This function is used internally by the `#[lunatic::test(...)]` macro to
+// create a configuration for tests that only set limits. Unlike
+// `ProcessConfig::new()`, it keeps all permissions enabled.
+pub fn default_config() -> ProcessConfig {
+    let mut config = ProcessConfig::new().expect("test process can't create configurations");
+    config.set_can_compile_modules(true);
+    config.set_can_create_configs(true);
+    config.set_can_spawn_processes(true);
+    config
+}
+
+// Reserved tag used to send the owner of a test to newly spawned processes.
+const TEST_OWNER_TAG: i64 = 1;
+
+process_local! {
+    // Collector of panics from processes spawned (transitively) by a test.
+    static TEST_OWNER: Cell<Option<Process<ChildReport>>> = Cell::new(None);
+}
+
+/// A panic that happened inside a process spawned by a test.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChildPanic {
+    pub process_id: u64,
+    pub message: String,
+}
+
+impl std::fmt::Display for ChildPanic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "process {} panicked: {}", self.process_id, self.message)
+    }
+}
+
+/// Messages received by the collector of child panics.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ChildReport {
+    Panicked(ChildPanic),
+    Finish(Process<Vec<ChildPanic>>, Tag),
+}
+
+/// Collects panics from processes spawned by a test.
+///
+/// It's used internally by the `#[lunatic::test]` macro.
+pub struct ChildTracker {
+    name: String,
+    collector: Process<ChildReport>,
+    fail_on_child_panic: bool,
+}
+
+impl ChildTracker {
+    // Stops tracking children and fails the test if any of them panicked and
+    // `fail_on_child_panic` is set.
+    pub fn finish(self) {
+        TEST_OWNER.set(None);
+        let tag = Tag::new();
+        self.collector
+            .send(ChildReport::Finish(Process::this(), tag));
+        let panics = unsafe { Mailbox::<Vec<ChildPanic>>::new() }.tag_receive(&[tag]);
+        if self.fail_on_child_panic && !panics.is_empty() {
+            print_child_panics(&self.name, &panics);
+            panic!(
+                "test {} failed because {} child process(es) panicked",
+                self.name,
+                panics.len()
+            );
+        }
+    }
+}
+
+// This function is used internally by the `#[lunatic::test]` macro to mark
+// the current process as the owner of all processes it spawns from now on.
+pub fn track_children(name: &str, fail_on_child_panic: bool) -> ChildTracker {
+    let collector = Process::spawn_link(name.to_owned(), collect_child_panics);
+    TEST_OWNER.set(Some(collector));
+    ChildTracker {
+        name: name.to_owned(),
+        collector,
+        fail_on_child_panic,
+    }
+}
+
+fn collect_child_panics(name: String, mailbox: Mailbox<ChildReport>) {
+    let mailbox = mailbox.catch_link_failure();
+    let mut panics = Vec::new();
+    loop {
+        match mailbox.receive() {
+            MailboxResult::Message(ChildReport::Panicked(panic)) => panics.push(panic),
+            MailboxResult::Message(ChildReport::Finish(test, tag)) => {
+                test.tag_send(tag, panics);
+                return;
+            }
+            // The test failed, attach the child panics to the failure output.
+            MailboxResult::LinkDied(_) => {
+                print_child_panics(&name, &panics);
+                return;
+            }
+            _ => (),
+        }
+    }
+}
+
+fn print_child_panics(name: &str, panics: &[ChildPanic]) {
+    if panics.is_empty() {
+        return;
+    }
+    eprintln!("---- {} child panics ----", name);
+    for panic in panics {
+        eprintln!("{}", panic);
+    }
+}
+
+// Returns the owner that should be propagated to a process spawned from the
+// current one.
+pub(crate) fn test_owner() -> Option<Process<ChildReport>> {
+    TEST_OWNER.get()
+}
+
+// Sends the test owner to a newly spawned process, before any other message.
+pub(crate) fn send_test_owner(process_id: u64, owner: Process<ChildReport>) {
+    let child = Process::<Process<ChildReport>>::new(host::node_id(), process_id);
+    child.tag_send(Tag::from(TEST_OWNER_TAG), owner);
+}
+
+// Called at the start of a process spawned by a test. It waits for the owner
+// and reports all panics inside the process to it.
+pub(crate) fn adopt_test_owner() {
+    let owner =
+        unsafe { Mailbox::<Process<ChildReport>>::new() }.tag_receive(&[Tag::from(TEST_OWNER_TAG)]);
+    TEST_OWNER.set(Some(owner));
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        owner.send(ChildReport::Panicked(ChildPanic {
+            process_id: host::process_id(),
+            message: panic_message(info.payload()),
+        }));
+        default_hook(info);
+    }));
+}
@@ -1,24 +1,252 @@
 //! Contains helper structures to deal with time-related functionality.
 
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
 
 use crate::ap::messages::{RequestMessage, ShutdownMessage};
 use crate::ap::{AbstractProcess, DeferredRequestHandler, ProcessRef, RequestHandler};
-use crate::host;
 use crate::serializer::CanSerialize;
+use crate::{host, MailboxError, MailboxResult};
+
+/// Suspends the current process until `deadline`.
+///
+/// Returns right away if the deadline has already passed. Waking up happens at
+/// the earliest at the deadline, with the millisecond precision of
+/// [`sleep`](crate::sleep). A loop that advances the deadline by a fixed
+/// period doesn't drift, regardless of how long each iteration takes:
+///
+/// ```
+/// let mut next = time::instant();
+/// loop {
+///     next += Duration::from_millis(10);
+///     tick();
+///     sleep_until(next);
+/// }
+/// ```
+pub fn sleep_until(deadline: Instant) {
+    let remaining = deadline.saturating_duration_since(instant());
+    if !remaining.is_zero() {
+        crate::sleep(remaining);
+    }
+}
+
+/// Returns the current time of the clock used by [`sleep`](crate::sleep),
+/// timers and receive timeouts.
+///
+/// It's [`Instant::now`], except in tests with virtual time, see
+/// [`test::virtual_time`](crate::test::virtual_time). There the instant
+/// advances with the virtual clock, starting from the real time at which the
+/// process began using it. Instants of different processes can't be compared
+/// in that case.
+pub fn instant() -> Instant {
+    crate::virtual_time::instant()
+}
+
+/// Returns the current wall-clock time, read from the realtime clock of the
+/// host.
+///
+/// Unlike [`Instant`], the wall-clock time can jump if the clock of the host
+/// is adjusted.
+pub fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Error returned by [`parse_rfc3339`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("invalid RFC 3339 timestamp")]
+pub struct InvalidTimestamp;
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Formats `time` as an RFC 3339 timestamp in UTC, with millisecond
+/// precision, e.g. `2023-04-01T12:30:05.250Z`.
+///
+/// Sub-millisecond precision is truncated. The format is only valid for
+/// years 0 to 9999.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let millis = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(err) => -(err.duration().as_millis() as i64),
+    };
+    let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+    let millis_of_day = millis.rem_euclid(MILLIS_PER_DAY);
+    let seconds = millis_of_day / 1000;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis_of_day % 1000
+    )
+}
+
+/// Parses an RFC 3339 timestamp, like the ones returned by
+/// [`format_rfc3339`].
+///
+/// Timestamps with an offset from UTC, like `2023-04-01T14:30:05+02:00`, are
+/// accepted as well. Fractions of seconds are truncated to milliseconds, and a
+/// leap second is read as the last millisecond before it.
+pub fn parse_rfc3339(timestamp: &str) -> Result<SystemTime, InvalidTimestamp> {
+    let bytes = timestamp.as_bytes();
+    // Parses the number with `len` digits at `at`.
+    let number = |at: usize, len: usize| -> Result<i64, InvalidTimestamp> {
+        let digits = bytes.get(at..at + len).ok_or(InvalidTimestamp)?;
+        digits.iter().try_fold(0, |n, digit| match digit {
+            b'0'..=b'9' => Ok(n * 10 + (digit - b'0') as i64),
+            _ => Err(InvalidTimestamp),
+        })
+    };
+    let expect = |at: usize, allowed: &[u8]| match bytes.get(at) {
+        Some(byte) if allowed.contains(byte) => Ok(()),
+        _ => Err(InvalidTimestamp),
+    };
+
+    let year = number(0, 4)?;
+    expect(4, b"-")?;
+    let month = number(5, 2)?;
+    expect(7, b"-")?;
+    let day = number(8, 2)?;
+    expect(10, b"Tt ")?;
+    let hour = number(11, 2)?;
+    expect(13, b":")?;
+    let minute = number(14, 2)?;
+    expect(16, b":")?;
+    let mut second = number(17, 2)?;
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(InvalidTimestamp);
+    }
+
+    let mut at = 19;
+    let mut millis = 0;
+    if bytes.get(at) == Some(&b'.') {
+        at += 1;
+        let digits = bytes[at..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return Err(InvalidTimestamp);
+        }
+        let fraction = number(at, digits.min(3))?;
+        millis = fraction * 10i64.pow(3 - digits.min(3) as u32);
+        at += digits;
+    }
+    if second == 60 {
+        (second, millis) = (59, 999);
+    }
+
+    let offset_minutes = match bytes.get(at) {
+        Some(b'Z' | b'z') if bytes.len() == at + 1 => 0,
+        Some(sign @ (b'+' | b'-')) if bytes.len() == at + 6 => {
+            let hours = number(at + 1, 2)?;
+            expect(at + 3, b":")?;
+            let minutes = number(at + 4, 2)?;
+            if hours > 23 || minutes > 59 {
+                return Err(InvalidTimestamp);
+            }
+            let offset = hours * 60 + minutes;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return Err(InvalidTimestamp),
+    };
+
+    let seconds = ((hour * 60 + minute - offset_minutes) * 60) + second;
+    let millis = days_from_civil(year, month, day) * MILLIS_PER_DAY + seconds * 1000 + millis;
+    let since_epoch = Duration::from_millis(millis.unsigned_abs());
+    Ok(if millis >= 0 {
+        UNIX_EPOCH + since_epoch
+    } else {
+        UNIX_EPOCH - since_epoch
+    })
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Converts days since 1970-01-01 into a (year, month, day) date of the
+// proleptic Gregorian calendar, see
+// http://howardhinnant.github.io/date_algorithms.html.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Returns `duration` in milliseconds, rounded up.
+pub(crate) fn millis_ceil(duration: Duration) -> u64 {
+    let millis = duration.as_nanos().div_ceil(1_000_000);
+    millis.try_into().unwrap_or(u64::MAX)
+}
+
+/// Sends the message in the scratch buffer to the local process `process_id`
+/// after `delay`.
+///
+/// In tests with virtual time the message is sent by the virtual clock, and
+/// resources attached to it are dropped.
+pub(crate) fn send_after(process_id: u64, delay: Duration) -> TimerRef {
+    if crate::virtual_time::is_enabled() {
+        return TimerRef(Timer::Virtual(crate::virtual_time::send_after(
+            process_id, delay,
+        )));
+    }
+    let timer_id = unsafe { host::api::timer::send_after(process_id, delay.as_millis() as u64) };
+    TimerRef(Timer::Host(timer_id))
+}
 
 /// A reference to a timer created from send_after.
 #[derive(Clone, Copy)]
-pub struct TimerRef(u64);
+pub struct TimerRef(Timer);
 
-impl TimerRef {
-    pub(crate) fn new(timer_id: u64) -> Self {
-        TimerRef(timer_id)
-    }
+#[derive(Clone, Copy)]
+enum Timer {
+    Host(u64),
+    Virtual((u64, u64)),
+}
 
+impl TimerRef {
     /// Cancel the timer, blocking until the timer is canceled.
     pub fn cancel(self) -> bool {
-        unsafe { host::api::timer::cancel_timer(self.0) == 1 }
+        match self.0 {
+            Timer::Host(timer_id) => unsafe { host::api::timer::cancel_timer(timer_id) == 1 },
+            Timer::Virtual(timer) => crate::virtual_time::cancel_timer(timer),
+        }
     }
 }
 
@@ -87,6 +315,32 @@ impl<T: AbstractProcess> WithTimeout<ProcessRef<T>> {
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Timeout;
 
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out waiting on a response")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+impl Timeout {
+    /// Converts the result of a `send_receive` call, which only returns
+    /// messages and timeouts.
+    pub(crate) fn from_result<T>(result: MailboxResult<T>) -> Result<T, Timeout> {
+        match result.into_result() {
+            Ok(message) => Ok(message),
+            Err(MailboxError::TimedOut) => Err(Timeout),
+            Err(err) => unreachable!("send_receive should panic in case of other errors: {err}"),
+        }
+    }
+}
+
+impl From<Timeout> for MailboxError {
+    fn from(_: Timeout) -> Self {
+        MailboxError::TimedOut
+    }
+}
+
 /// Modifies `T` so that all functions on it will be performed with a delay.
 ///
 /// It's used to delay calls such as [`ProcessRef::send`].
@@ -0,0 +1,127 @@
+//! Tracing of the messages handled by a process.
+//!
+//! While tracing is enabled, the process sends a [`TraceEvent`] to a collector
+//! process for each message it handles. The event contains the type of the
+//! message, its tag and size, and how long it took to handle it. A message is
+//! considered handled once the process starts waiting on the next one.
+//!
+//! Tracing can be enabled from inside the process with [`enable`], or from
+//! another process with [`Process::trace`]. In the second case the traced
+//! process picks up the change the next time it waits on a message. When
+//! tracing is disabled, the only cost of a receive is a single check.
+//!
+//! # Example
+//!
+//! ```
+//! let collector = Process::spawn((), |_, mailbox: Mailbox<TraceEvent>| loop {
+//!     let event = mailbox.receive();
+//!     println!("{} took {:?}", event.message_type, event.duration);
+//! });
+//! server.trace(Some(collector));
+//! ```
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::api::message;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, process_local, Process, Tag};
+
+/// Tag of the message enabling or disabling tracing of a process.
+pub(crate) const TRACE_TAG: i64 = 12;
+
+// Message that is currently handled: start, type, handler, tag and size.
+type Pending = (Instant, &'static str, Option<u8>, Tag, u64);
+
+process_local! {
+    static TRACER: Cell<Option<Process<TraceEvent>>> = Cell::new(None);
+    static PENDING: Cell<Option<Pending>> = Cell::new(None);
+}
+
+/// A message handled by a traced process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Node of the traced process.
+    pub node_id: u64,
+    /// The traced process.
+    pub process_id: u64,
+    /// Type name of the message, or of the abstract process that handled it.
+    pub message_type: String,
+    /// Index of the handler, if the message was handled by an
+    /// [`AbstractProcess`](crate::ap::AbstractProcess).
+    pub handler: Option<u8>,
+    pub tag: Tag,
+    /// Size of the serialized message in bytes.
+    pub size: u64,
+    /// Time between receiving the message and waiting on the next one.
+    pub duration: Duration,
+}
+
+/// Enables tracing of the current process, sending events to `collector`.
+pub fn enable(collector: Process<TraceEvent>) {
+    TRACER.set(Some(collector));
+}
+
+/// Disables tracing of the current process.
+pub fn disable() {
+    TRACER.set(None);
+    PENDING.set(None);
+}
+
+/// Returns `true` if tracing of the current process is enabled.
+pub fn is_enabled() -> bool {
+    TRACER.get().is_some()
+}
+
+// Sends the tracing setup to another process.
+pub(crate) fn send_control(node_id: u64, process_id: u64, collector: Option<Process<TraceEvent>>) {
+    let process = Process::<Option<Process<TraceEvent>>>::new(node_id, process_id);
+    process.tag_send(Tag::from(TRACE_TAG), collector);
+}
+
+// Applies the tracing setup in the message buffer.
+pub(crate) fn handle_control() {
+    let collector: Option<Process<TraceEvent>> =
+        <Bincode as CanSerialize<_>>::decode().expect("Failed to deserialize the tracing setup");
+    match collector {
+        Some(collector) => enable(collector),
+        None => disable(),
+    }
+}
+
+// Called after a message of type `M` was received into the message buffer.
+pub(crate) fn received<M>() {
+    start(std::any::type_name::<M>(), None);
+}
+
+// Called before an abstract process dispatches the message in the buffer to
+// `handler`.
+pub(crate) fn start(message_type: &'static str, handler: Option<u8>) {
+    if TRACER.get().is_none() {
+        return;
+    }
+    let tag = Tag::from(unsafe { message::get_tag() });
+    let size = unsafe { message::data_size() };
+    PENDING.set(Some((Instant::now(), message_type, handler, tag, size)));
+}
+
+// Called once the current message is handled, sends the event.
+pub(crate) fn finish() {
+    let Some((start, message_type, handler, tag, size)) = PENDING.take() else {
+        return;
+    };
+    let Some(tracer) = TRACER.get() else {
+        return;
+    };
+    tracer.send(TraceEvent {
+        node_id: host::node_id(),
+        process_id: host::process_id(),
+        message_type: message_type.to_owned(),
+        handler,
+        tag,
+        size,
+        duration: start.elapsed(),
+    });
+}
@@ -0,0 +1,351 @@
+//! A virtual clock for tests of time-dependent code.
+//!
+//! Tests running with `#[lunatic::test(virtual_time)]` share a clock process
+//! with all processes they spawn. Sleeps, timers and receive timeouts of these
+//! processes are measured by the clock instead of the host. Each process tells
+//! the clock when it blocks and when it wakes up again, and once all of them
+//! are blocked, the clock jumps to the earliest deadline and fires it. Waiting
+//! on minutes of virtual time takes a few milliseconds of real time.
+//!
+//! Messages sent to a blocked process are invisible to the clock, so it
+//! waits for [`SETTLE`] before advancing, giving the receiver time to report
+//! that it woke up.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::host::api::message;
+use crate::mailbox::TIMEOUT;
+use crate::serializer::{Bincode, CanSerialize};
+use crate::{host, process_local, Mailbox, MailboxResult, Process, Tag};
+
+/// Tag of the message carrying the clock to a newly spawned process.
+const INHERIT_TAG: i64 = 20;
+/// How long all processes need to stay blocked before the clock advances.
+const SETTLE: Duration = Duration::from_millis(5);
+/// How often the clock checks for processes that died while running.
+const PRUNE_INTERVAL: Duration = Duration::from_millis(10);
+
+process_local! {
+    // The clock of the current process, if it runs on virtual time.
+    static CLOCK: Cell<Option<Process<ClockMessage>>> = Cell::new(None);
+    // The real time at which the process started using the clock.
+    static BASE: Cell<Option<Instant>> = Cell::new(None);
+    static NEXT_TIMER: Cell<u64> = Cell::new(0);
+}
+
+/// Returns `true` if the current process runs on virtual time.
+pub(crate) fn is_enabled() -> bool {
+    CLOCK.get().is_some()
+}
+
+/// Spawns a clock and switches the current process to it.
+pub(crate) fn start() -> Process<ClockMessage> {
+    assert!(!is_enabled(), "the process already runs on virtual time");
+    // The clock itself runs on real time.
+    let clock = Process::spawn((), clock_process);
+    clock.send(ClockMessage::Join(host::process_id()));
+    use_clock(clock);
+    clock
+}
+
+/// Switches the current process back to real time and stops the clock.
+pub(crate) fn stop(clock: Process<ClockMessage>) {
+    CLOCK.set(None);
+    BASE.set(None);
+    clock.kill();
+}
+
+fn use_clock(clock: Process<ClockMessage>) {
+    CLOCK.set(Some(clock));
+    BASE.set(Some(Instant::now()));
+}
+
+/// Returns the current time of the clock, see [`crate::time::instant`].
+pub(crate) fn instant() -> Instant {
+    match (CLOCK.get(), BASE.get()) {
+        (Some(clock), Some(base)) => base + ask(clock, ClockMessage::Now),
+        _ => Instant::now(),
+    }
+}
+
+/// Waits on a message like the host call, with timeouts measured by the
+/// clock of the current process.
+///
+/// If `tags` is empty it waits on any message. Returns [`TIMEOUT`] once the
+/// timeout expired.
+pub(crate) fn receive(tags: &[i64], timeout_ms: u64) -> u32 {
+    let clock = match CLOCK.get() {
+        // Checking the mailbox doesn't block.
+        Some(clock) if timeout_ms != 0 => clock,
+        _ => return unsafe { message::receive(tags.as_ptr(), tags.len(), timeout_ms) },
+    };
+    let timeout = (timeout_ms != u64::MAX).then(|| Duration::from_millis(timeout_ms));
+    let wake = Tag::new().id();
+    let process_id = host::process_id();
+    clock.send(ClockMessage::Block(process_id, timeout, wake));
+    let mut tags = tags.to_vec();
+    if !tags.is_empty() {
+        tags.push(wake);
+    }
+    let message_type = unsafe { message::receive(tags.as_ptr(), tags.len(), u64::MAX) };
+    if unsafe { message::get_tag() } == wake {
+        return TIMEOUT;
+    }
+    // The clock is informed without losing the received message.
+    let data = save_message();
+    if timeout.is_some() {
+        let fired = ask(clock, |reply, tag| {
+            ClockMessage::Unblock(process_id, Some((reply, tag)))
+        });
+        // The wake-up was sent before the reply, so it's already here.
+        if fired {
+            unsafe { message::receive(&wake, 1, 0) };
+        }
+    } else {
+        clock.send(ClockMessage::Unblock(process_id, None));
+    }
+    restore_message(data);
+    message_type
+}
+
+/// Suspends the current process for `duration` of virtual time.
+pub(crate) fn sleep(duration: Duration) {
+    let Some(clock) = CLOCK.get() else {
+        return;
+    };
+    if duration.is_zero() {
+        return;
+    }
+    let wake = Tag::new().id();
+    clock.send(ClockMessage::Block(
+        host::process_id(),
+        Some(duration),
+        wake,
+    ));
+    unsafe { message::receive(&wake, 1, u64::MAX) };
+}
+
+/// Sends the message in the scratch buffer to `process_id` once `delay` of
+/// virtual time passed, and returns the id of the timer.
+///
+/// Resources attached to the message are not sent.
+pub(crate) fn send_after(process_id: u64, delay: Duration) -> (u64, u64) {
+    let clock = CLOCK
+        .get()
+        .expect("the process doesn't run on virtual time");
+    let (tag, data) = save_message();
+    let timer = (host::process_id(), NEXT_TIMER.get());
+    NEXT_TIMER.set(timer.1 + 1);
+    clock.send(ClockMessage::SendAfter(timer, process_id, delay, tag, data));
+    timer
+}
+
+/// Cancels a timer created by [`send_after`], returns `true` if it didn't
+/// fire yet.
+pub(crate) fn cancel_timer(timer: (u64, u64)) -> bool {
+    match CLOCK.get() {
+        Some(clock) => ask(clock, |reply, tag| ClockMessage::Cancel(timer, reply, tag)),
+        None => false,
+    }
+}
+
+/// Returns the clock that processes spawned from the current one should use.
+pub(crate) fn inherited() -> Option<Process<ClockMessage>> {
+    CLOCK.get()
+}
+
+// Registers a newly spawned process with the clock and sends the clock to it.
+pub(crate) fn send_inherited(process_id: u64, clock: Process<ClockMessage>) {
+    // Sent before the parent blocks, so the clock doesn't advance before the
+    // child started.
+    clock.send(ClockMessage::Join(process_id));
+    let child = Process::<Process<ClockMessage>>::new(host::node_id(), process_id);
+    child.tag_send(Tag::from(INHERIT_TAG), clock);
+}
+
+// Called at the start of a process that runs on the clock of its parent.
+pub(crate) fn adopt_inherited() {
+    let clock =
+        unsafe { Mailbox::<Process<ClockMessage>>::new() }.tag_receive(&[Tag::from(INHERIT_TAG)]);
+    use_clock(clock);
+}
+
+// Sends a request to the clock and waits on the reply, without involving the
+// clock in the wait.
+fn ask<R>(clock: Process<ClockMessage>, request: impl FnOnce(Process<R>, Tag) -> ClockMessage) -> R
+where
+    R: Serialize + DeserializeOwned,
+{
+    let tag = Tag::new();
+    clock.send(request(Process::this(), tag));
+    unsafe { message::receive(&tag.id(), 1, u64::MAX) };
+    <Bincode as CanSerialize<R>>::decode().expect("invalid reply of the virtual clock")
+}
+
+// Returns the tag and data of the message in the scratch buffer.
+fn save_message() -> (i64, Vec<u8>) {
+    unsafe {
+        let tag = message::get_tag();
+        let mut data = vec![0; message::data_size() as usize];
+        message::seek_data(0);
+        message::read_data(data.as_mut_ptr(), data.len());
+        message::seek_data(0);
+        (tag, data)
+    }
+}
+
+// Puts a message saved by `save_message` back into the scratch buffer.
+fn restore_message((tag, data): (i64, Vec<u8>)) {
+    unsafe {
+        message::create_data(tag, data.len() as u64);
+        message::write_data(data.as_ptr(), data.len());
+        message::seek_data(0);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum ClockMessage {
+    Join(u64),
+    Block(u64, Option<Duration>, i64),
+    Unblock(u64, Option<(Process<bool>, Tag)>),
+    Now(Process<Duration>, Tag),
+    SendAfter((u64, u64), u64, Duration, i64, Vec<u8>),
+    Cancel((u64, u64), Process<bool>, Tag),
+}
+
+// Timers are ordered by their deadline, and by creation if they share it.
+type Key = (Duration, u64);
+
+enum Participant {
+    Running,
+    // Blocked on a receive or sleep, with the timer of its timeout.
+    Blocked(Option<Key>),
+}
+
+enum Timer {
+    Wake(u64, i64),
+    Send((u64, u64), u64, i64, Vec<u8>),
+}
+
+#[derive(Default)]
+struct Clock {
+    now: Duration,
+    participants: HashMap<u64, Participant>,
+    timers: BTreeMap<Key, Timer>,
+    // Timers created with `send_after`, by id.
+    sent: HashMap<(u64, u64), Key>,
+    next: u64,
+}
+
+impl Clock {
+    fn handle(&mut self, message: ClockMessage) {
+        match message {
+            ClockMessage::Join(process_id) => {
+                // The child could have blocked before the parent's message arrived.
+                self.participants
+                    .entry(process_id)
+                    .or_insert(Participant::Running);
+            }
+            ClockMessage::Block(process_id, timeout, wake) => {
+                let key =
+                    timeout.map(|timeout| self.insert(timeout, Timer::Wake(process_id, wake)));
+                let previous = self
+                    .participants
+                    .insert(process_id, Participant::Blocked(key));
+                if let Some(Participant::Blocked(Some(key))) = previous {
+                    self.timers.remove(&key);
+                }
+            }
+            ClockMessage::Unblock(process_id, reply) => {
+                let previous = self.participants.insert(process_id, Participant::Running);
+                // A process whose timeout fired is already running.
+                let cancelled = match previous {
+                    Some(Participant::Blocked(Some(key))) => self.timers.remove(&key).is_some(),
+                    _ => false,
+                };
+                if let Some((reply, tag)) = reply {
+                    reply.tag_send(tag, !cancelled);
+                }
+            }
+            ClockMessage::Now(reply, tag) => reply.tag_send(tag, self.now),
+            ClockMessage::SendAfter(id, process_id, delay, tag, data) => {
+                let key = self.insert(delay, Timer::Send(id, process_id, tag, data));
+                self.sent.insert(id, key);
+            }
+            ClockMessage::Cancel(id, reply, tag) => {
+                let cancelled = self
+                    .sent
+                    .remove(&id)
+                    .and_then(|key| self.timers.remove(&key))
+                    .is_some();
+                reply.tag_send(tag, cancelled);
+            }
+        }
+    }
+
+    fn insert(&mut self, delay: Duration, timer: Timer) -> Key {
+        let key = (self.now + delay, self.next);
+        self.next += 1;
+        self.timers.insert(key, timer);
+        key
+    }
+
+    fn all_blocked(&self) -> bool {
+        self.participants
+            .values()
+            .all(|participant| matches!(participant, Participant::Blocked(_)))
+    }
+
+    // Drops the processes that are gone.
+    fn prune(&mut self) {
+        self.participants
+            .retain(|&process_id, _| Process::<()>::new(host::node_id(), process_id).is_alive());
+    }
+
+    // Moves to the earliest deadline and fires all timers that have it.
+    fn advance(&mut self) {
+        let Some(&(deadline, _)) = self.timers.keys().next() else {
+            return;
+        };
+        self.now = self.now.max(deadline);
+        while let Some(entry) = self.timers.first_entry() {
+            if entry.key().0 != deadline {
+                break;
+            }
+            match entry.remove() {
+                Timer::Wake(process_id, wake) => {
+                    self.participants.insert(process_id, Participant::Running);
+                    Process::<()>::new(host::node_id(), process_id).tag_send(Tag::from(wake), ());
+                }
+                Timer::Send(id, process_id, tag, data) => {
+                    self.sent.remove(&id);
+                    restore_message((tag, data));
+                    host::send(host::node_id(), process_id);
+                }
+            }
+        }
+    }
+}
+
+fn clock_process(_: (), mailbox: Mailbox<ClockMessage>) {
+    let mut clock = Clock::default();
+    loop {
+        let settled = clock.all_blocked() && !clock.timers.is_empty();
+        let wait = if settled { SETTLE } else { PRUNE_INTERVAL };
+        match mailbox.receive_timeout(wait) {
+            MailboxResult::Message(message) => clock.handle(message),
+            MailboxResult::TimedOut => {
+                clock.prune();
+                if settled {
+                    clock.advance();
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -2,8 +2,8 @@ use std::time::Duration;
 
 use lunatic::ap::handlers::{DeferredRequest, Message, Request};
 use lunatic::ap::{
-    AbstractProcess, Config, DeferredRequestHandler, DeferredResponse, MessageHandler, ProcessRef,
-    RequestHandler, StartupError, State,
+    AbstractProcess, Config, DeferredRequestHandler, DeferredResponse, Idle, MessageHandler,
+    ProcessRef, RequestHandler, StartupError, State,
 };
 use lunatic::serializer::Bincode;
 use lunatic::time::Timeout;
@@ -477,3 +477,117 @@ fn deferred_request_timeout() {
         .deferred_request("Hello".to_owned());
     assert_eq!(response, Err(Timeout));
 }
+
+/// This `AbstractProcess` stops after being idle for 50 ms.
+struct Session;
+
+impl AbstractProcess for Session {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = ();
+    type Handlers = (Request<Touch>,);
+    type StartupError = ();
+
+    fn init(config: Config<Self>, _: ()) -> Result<Self, ()> {
+        config.set_idle_timeout(Some(Duration::from_millis(50)));
+        Ok(Session)
+    }
+
+    fn handle_idle(_: State<Self>) -> Idle {
+        Idle::Stop
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Touch;
+
+impl RequestHandler<Touch> for Session {
+    type Response = ();
+
+    fn handle(_: State<Self>, _: Touch) {}
+}
+
+#[test]
+fn idle_process_stops() {
+    let session = Session::start_as("session/idle", ()).unwrap();
+    // Each handled message resets the idle timeout.
+    for _ in 0..5 {
+        sleep(Duration::from_millis(30));
+        session.request(Touch);
+    }
+    assert!(session.is_alive());
+    sleep(Duration::from_millis(200));
+    assert!(!session.is_alive());
+    assert!(ProcessRef::<Session>::lookup("session/idle").is_none());
+    // The name can be used again.
+    Session::start_as("session/idle", ()).unwrap();
+}
+
+/// `AbstractProcess` that interleaves messages it sends to itself with
+/// messages from other processes.
+struct ChainAP(Vec<String>);
+
+impl AbstractProcess for ChainAP {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = ();
+    type Handlers = (Message<Chain>, Message<External>, Request<Events>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(ChainAP(Vec::new()))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Chain(u32, String);
+impl MessageHandler<Chain> for ChainAP {
+    fn handle(mut state: State<Self>, Chain(left, payload): Chain) {
+        state.0.push(format!("chain {left} {payload}"));
+        // Give the external messages time to arrive first.
+        sleep(Duration::from_millis(50));
+        if left > 0 {
+            state
+                .self_ref()
+                .send(Chain(left - 1, format!("{payload}+")));
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct External(char);
+impl MessageHandler<External> for ChainAP {
+    fn handle(mut state: State<Self>, External(name): External) {
+        state.0.push(format!("external {name}"));
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Events;
+impl RequestHandler<Events> for ChainAP {
+    type Response = Vec<String>;
+
+    fn handle(state: State<Self>, _: Events) -> Vec<String> {
+        state.0.clone()
+    }
+}
+
+#[test]
+fn self_sends_keep_order_with_external_sends() {
+    let ap = ChainAP::link().start(()).unwrap();
+    ap.send(Chain(2, "p".to_owned()));
+    ap.send(External('a'));
+    sleep(Duration::from_millis(75));
+    ap.send(External('b'));
+    sleep(Duration::from_millis(150));
+    assert_eq!(
+        ap.request(Events),
+        [
+            "chain 2 p",
+            "external a",
+            "chain 1 p+",
+            "external b",
+            "chain 0 p++"
+        ]
+    );
+}
@@ -562,3 +562,156 @@ fn generics() {
         .unwrap();
     assert_eq!(PI * 2f32, s);
 }
+
+#[test]
+fn priority_handlers() {
+    struct A {
+        received: Vec<u32>,
+    }
+
+    #[abstract_process]
+    impl A {
+        #[init]
+        fn init(_: Config<Self>, _: ()) -> Result<A, ()> {
+            Ok(A {
+                received: Vec::new(),
+            })
+        }
+
+        #[handle_message]
+        fn block(&self) {
+            sleep(Duration::from_millis(100));
+        }
+
+        #[handle_message]
+        fn push(&mut self, value: u32) {
+            self.received.push(value);
+        }
+
+        #[handle_priority]
+        fn push_priority(&mut self, value: u32) {
+            self.received.push(value);
+        }
+
+        #[handle_request]
+        fn received(&self) -> Vec<u32> {
+            self.received.clone()
+        }
+    }
+
+    let a = A::link().start(()).unwrap();
+    // Keep the process busy, so that the following messages are queued.
+    a.block();
+    a.push(1);
+    a.push_priority(10);
+    a.push(2);
+    a.push_priority(11);
+    assert_eq!(a.received(), vec![10, 11, 1, 2]);
+}
+
+#[test]
+fn generated_client_trait() {
+    mod server {
+        use super::*;
+
+        pub struct Counter {
+            count: u32,
+        }
+
+        #[abstract_process(client_trait_name = "CounterClient", visibility = pub)]
+        impl Counter {
+            #[init]
+            fn init(_config: Config<Self>, count: u32) -> Result<Self, ()> {
+                Ok(Self { count })
+            }
+
+            #[handle_message]
+            fn increment(&mut self) {
+                self.count += 1;
+            }
+
+            #[handle_request]
+            fn count(&self) -> u32 {
+                self.count
+            }
+        }
+    }
+
+    // Only depends on the client trait.
+    fn increment_twice(counter: &impl server::CounterClient) -> u32 {
+        counter.increment();
+        counter.increment();
+        counter.count()
+    }
+
+    let counter = server::Counter::link().start(1).unwrap();
+    assert_eq!(increment_twice(&counter), 3);
+}
+
+// The interface, caller and server would be separate crates, the caller and
+// the server only depend on the interface.
+mod interface {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    pub struct Key(pub String);
+
+    pub trait StoreClient {
+        fn put(&self, key: Key, value: u64);
+        fn get(&self, key: Key) -> Option<u64>;
+        fn get_later(&self, key: Key) -> Option<u64>;
+    }
+}
+
+mod caller {
+    use super::interface::{Key, StoreClient};
+
+    pub fn bump(store: &impl StoreClient, key: &str) -> u64 {
+        let key = Key(key.to_owned());
+        let value = store.get(key.clone()).unwrap_or(0) + 1;
+        store.put(key.clone(), value);
+        store.get_later(key).unwrap()
+    }
+}
+
+mod store {
+    use std::collections::HashMap;
+
+    use lunatic::abstract_process;
+    use lunatic::ap::{Config, DeferredResponse};
+
+    use super::interface::Key;
+
+    pub struct Store(HashMap<String, u64>);
+
+    #[abstract_process(client_trait = super::interface::StoreClient, visibility = pub)]
+    impl Store {
+        #[init]
+        fn init(_config: Config<Self>, _: ()) -> Result<Self, ()> {
+            Ok(Store(HashMap::new()))
+        }
+
+        #[handle_message]
+        fn put(&mut self, key: Key, value: u64) {
+            self.0.insert(key.0, value);
+        }
+
+        #[handle_request]
+        fn get(&self, key: Key) -> Option<u64> {
+            self.0.get(&key.0).copied()
+        }
+
+        #[handle_deferred_request]
+        fn get_later(&self, key: Key, response: DeferredResponse<Option<u64>, Self>) {
+            response.send_response(self.0.get(&key.0).copied());
+        }
+    }
+}
+
+#[test]
+fn client_trait_from_interface() {
+    let store = store::Store::link().start(()).unwrap();
+    assert_eq!(caller::bump(&store, "a"), 1);
+    assert_eq!(caller::bump(&store, "a"), 2);
+    assert_eq!(caller::bump(&store, "b"), 1);
+}
@@ -0,0 +1,62 @@
+use lunatic::cache::{ReplicatedCache, HITS_COUNTER, MISSES_COUNTER};
+use lunatic::metrics::{self, with_labels};
+use lunatic::{test, Mailbox, Process};
+
+// Reads the key "a" once, and again after each message from the parent.
+fn reader((parent, name): (Process<Option<u64>>, String), mailbox: Mailbox<()>) {
+    let cache = ReplicatedCache::<String, u64>::new(&name, 10);
+    loop {
+        parent.send(cache.get(&"a".to_owned()));
+        mailbox.receive();
+    }
+}
+
+// Writes the key "a" and reports back.
+fn writer((parent, name, value): (Process<()>, String, u64), _: Mailbox<()>) {
+    let cache = ReplicatedCache::<String, u64>::new(&name, 10);
+    cache.put("a".to_owned(), value);
+    parent.send(());
+}
+
+#[test]
+fn updates_reach_other_workers(mailbox: Mailbox<Option<u64>>) {
+    let name = "updates".to_owned();
+    let cache = ReplicatedCache::<String, u64>::new(&name, 10);
+    cache.put("a".to_owned(), 1);
+
+    let reader = Process::spawn_link((mailbox.this(), name.clone()), reader);
+    assert_eq!(mailbox.receive(), Some(1));
+
+    let done = unsafe { Mailbox::<()>::new() };
+    Process::spawn_link((done.this(), name, 2), writer);
+    done.receive();
+    // The reader has the old value cached, and sees the update on its next
+    // access.
+    reader.send(());
+    assert_eq!(mailbox.receive(), Some(2));
+
+    cache.remove(&"a".to_owned());
+    reader.send(());
+    assert_eq!(mailbox.receive(), None);
+}
+
+#[test]
+fn local_map_is_bounded() {
+    metrics::record_published(true);
+    let cache = ReplicatedCache::<u64, u64>::new("bounded", 2);
+    for key in 0..3 {
+        cache.put(key, key * 10);
+    }
+    assert_eq!(cache.local_len(), 2);
+
+    // 0 was evicted and is looked up in the owner.
+    assert_eq!(cache.get(&0), Some(0));
+    assert_eq!(cache.get(&0), Some(0));
+    assert_eq!(cache.get(&3), None);
+    assert_eq!(cache.local_len(), 2);
+
+    let published = metrics::published();
+    let labels = [("cache", "bounded + ReplicatedCache + u64/u64")];
+    assert_eq!(published[&with_labels(HITS_COUNTER, &labels)], 1.0);
+    assert_eq!(published[&with_labels(MISSES_COUNTER, &labels)], 2.0);
+}
@@ -0,0 +1,32 @@
+use lunatic::serializer::RawBytes;
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn capture_list(mailbox: Mailbox<String>) {
+    let parent = mailbox.this();
+    let child = Process::spawn_link_with(
+        (parent, 2u64, "text".to_owned()),
+        |(parent, times, text), mailbox: Mailbox<String>| {
+            let suffix = mailbox.receive();
+            parent.send(format!("{}{}", text.repeat(times as usize), suffix));
+        },
+    );
+    child.send("!".to_owned());
+    assert_eq!(mailbox.receive(), "texttext!");
+}
+
+#[test]
+fn raw_bytes_capture(mailbox: Mailbox<u64>) {
+    let buffer: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+    Process::spawn_link_with_serializer::<RawBytes, _>(
+        (buffer,),
+        |(buffer,), mailbox: Mailbox<Process<u64>>| {
+            let parent = mailbox.receive();
+            parent.send(buffer.iter().map(|&byte| byte as u64).sum());
+        },
+    )
+    .send(mailbox.this());
+    let expected: u64 = (0..1024 * 1024u64).map(|i| i % 256).sum();
+    assert_eq!(mailbox.receive(), expected);
+}
@@ -1,4 +1,5 @@
-use lunatic::panic::catch_panic;
+use lunatic::panic::{catch_panic, link_panic, PayloadKind};
+use lunatic::{Mailbox, MailboxResult, Process, Tag};
 use lunatic_test::test;
 
 #[test]
@@ -18,3 +19,36 @@ fn catch_panic_capture() {
 fn catch_assert_fail() {
     assert!(catch_panic(|| assert!(false)).is_err())
 }
+
+#[test]
+fn catch_panic_literal_message() {
+    let panicked = catch_panic(|| panic!("literal message")).unwrap_err();
+    assert_eq!(panicked.message(), Some("literal message"));
+    assert_eq!(panicked.payload_kind(), PayloadKind::Str);
+    assert_eq!(panicked.payload(), b"literal message");
+    let location = panicked.location().unwrap();
+    assert!(location.file().ends_with("catch_panic.rs"));
+}
+
+#[test]
+fn catch_panic_formatted_message() {
+    let value = 42;
+    let line = line!() + 1;
+    let panicked = catch_panic(|| panic!("formatted message {}", value)).unwrap_err();
+    assert_eq!(panicked.message(), Some("formatted message 42"));
+    assert_eq!(panicked.payload_kind(), PayloadKind::String);
+    assert_eq!(panicked.location().unwrap().line(), line);
+}
+
+#[test]
+fn link_panic_is_reported() {
+    let mailbox = unsafe { Mailbox::<()>::new() }.catch_link_failure();
+    let tag = Tag::new();
+    Process::spawn_link_tag((), tag, |_, _: Mailbox<()>| panic!("linked process failed"));
+    match mailbox.receive() {
+        MailboxResult::LinkDied(died) => assert_eq!(died, tag),
+        _ => panic!("expected link died signal"),
+    }
+    let panicked = link_panic(tag).unwrap();
+    assert_eq!(panicked.message(), Some("linked process failed"));
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use lunatic::channel::{RecvTimeoutError, SendError, TryRecvError};
+use lunatic::serializer::Bincode;
+use lunatic::{channel, sleep, sync_channel, Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn send_and_receive() {
+    let (sender, receiver) = channel::<u64, Bincode>();
+    Process::spawn(sender, |sender, _: Mailbox<()>| {
+        for i in 0..10 {
+            sender.send(i).unwrap();
+        }
+    });
+    for i in 0..10 {
+        assert_eq!(receiver.recv(), Ok(i));
+    }
+}
+
+#[test]
+fn try_recv_on_empty_channel() {
+    let (sender, receiver) = channel::<u64, Bincode>();
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    sender.send(1).unwrap();
+    assert_eq!(receiver.recv(), Ok(1));
+}
+
+#[test]
+fn recv_timeout() {
+    let (sender, receiver) = channel::<u64, Bincode>();
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    sender.send(1).unwrap();
+    assert_eq!(receiver.recv_timeout(Duration::from_millis(100)), Ok(1));
+}
+
+#[test]
+fn closed_after_all_senders_drop() {
+    let (sender, receiver) = channel::<u64, Bincode>();
+    let clone = sender.clone();
+    Process::spawn(clone, |sender, _: Mailbox<()>| {
+        sender.send(1).unwrap();
+    });
+    drop(sender);
+    assert_eq!(receiver.recv(), Ok(1));
+    assert!(receiver.recv().is_err());
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Closed));
+}
+
+#[test]
+fn send_fails_after_receiver_drops() {
+    let (sender, receiver) = channel::<u64, Bincode>();
+    drop(receiver);
+    sleep(Duration::from_millis(50));
+    assert_eq!(sender.send(1), Err(SendError::Closed(1)));
+}
+
+#[test]
+fn bounded_send_blocks() {
+    let (sender, receiver) = sync_channel::<u64, Bincode>(1);
+    sender.send(1).unwrap();
+    let child = Process::spawn(sender, |sender, _: Mailbox<()>| {
+        sender.send(2).unwrap();
+    });
+    sleep(Duration::from_millis(50));
+    assert!(child.is_alive());
+    assert_eq!(receiver.recv(), Ok(1));
+    assert_eq!(receiver.recv(), Ok(2));
+}
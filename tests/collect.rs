@@ -0,0 +1,45 @@
+use std::time::{Duration, Instant};
+
+use lunatic::{sleep, spawn_collect, spawn_collect_limit, TaskError};
+use lunatic_test::test;
+
+fn task(input: u64) -> u64 {
+    match input {
+        0 => panic!("task failed"),
+        1 => {
+            sleep(Duration::from_secs(5));
+            1
+        }
+        _ => input * 2,
+    }
+}
+
+#[test]
+fn collect_in_input_order() {
+    let results = spawn_collect(vec![3, 2, 5], task, Duration::from_secs(1));
+    assert_eq!(results, vec![Ok(6), Ok(4), Ok(10)]);
+}
+
+#[test]
+fn fast_slow_and_panicking_tasks() {
+    let start = Instant::now();
+    let results = spawn_collect(vec![2, 1, 0, 4], task, Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(results[0], Ok(4));
+    assert_eq!(results[1], Err(TaskError::Timeout));
+    match &results[2] {
+        Err(TaskError::Panicked(panicked)) => assert_eq!(panicked.message(), Some("task failed")),
+        other => panic!("expected a panic, got {:?}", other),
+    }
+    assert_eq!(results[3], Ok(8));
+}
+
+#[test]
+fn concurrency_limit() {
+    // The slow task blocks the only slot, so the last task never starts.
+    let results = spawn_collect_limit(vec![2, 1, 3], task, Duration::from_millis(200), 1);
+    assert_eq!(
+        results,
+        vec![Ok(4), Err(TaskError::Timeout), Err(TaskError::Timeout)]
+    );
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use lunatic::serializer::Bincode;
+use lunatic::{compat, sleep, test, Mailbox, Process, Tag};
+
+// Replies to the `tag` after a delay.
+fn reply_later((parent, tag, delay, value): (Process<u64>, Tag, u64, u64), _: Mailbox<()>) {
+    sleep(Duration::from_millis(delay));
+    parent.tag_send(tag, value);
+}
+
+fn ask(mailbox: Mailbox<u64>, delay: u64, value: u64) -> compat::Receive<u64, Bincode> {
+    let tag = Tag::new();
+    Process::spawn((mailbox.this(), tag, delay, value), reply_later);
+    compat::tag_receive(mailbox, &[tag])
+}
+
+async fn sum(mailbox: Mailbox<u64>) -> u64 {
+    let first = ask(mailbox, 20, 1).await;
+    let second = ask(mailbox, 0, 2).await;
+    first + second
+}
+
+#[test]
+fn awaits_replies_in_sequence(mailbox: Mailbox<u64>) {
+    assert_eq!(compat::block_on(sum(mailbox)), 3);
+}
+
+#[test]
+fn joins_replies(mailbox: Mailbox<u64>) {
+    // The second reply arrives first.
+    let (first, second) =
+        compat::block_on(async { futures::join!(ask(mailbox, 50, 1), ask(mailbox, 0, 2)) });
+    assert_eq!((first, second), (1, 2));
+}
+
+#[test]
+fn receives_messages_already_in_the_mailbox(mailbox: Mailbox<u64>) {
+    mailbox.this().send(7);
+    assert_eq!(compat::block_on(compat::receive(mailbox)), 7);
+}
+
+#[test]
+fn runs_futures_without_messages() {
+    assert_eq!(compat::block_on(async { 40 + 2 }), 42);
+}
+
+#[test]
+#[should_panic]
+fn futures_that_cant_be_woken_panic() {
+    compat::block_on(futures::future::pending::<()>());
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use lunatic::config_data::{AppConfig, Revision};
+use lunatic::{sleep, Mailbox, Process};
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Settings {
+    url: String,
+}
+
+type Report = (u32, Revision, String);
+
+// Reports the config of every level, spawns the next one, and reports again
+// once the update arrived.
+fn level((parent, depth): (Process<Report>, u32), _: Mailbox<()>) {
+    AppConfig::<Settings>::subscribe();
+    let revision = AppConfig::<Settings>::revision().unwrap();
+    let settings = AppConfig::<Settings>::get().unwrap();
+    parent.send((depth, revision, settings.url.clone()));
+    if depth < 3 {
+        Process::spawn((parent, depth + 1), level);
+    }
+    while AppConfig::<Settings>::revision() == Some(revision) {
+        sleep(Duration::from_millis(5));
+    }
+    let settings = AppConfig::<Settings>::get().unwrap();
+    let revision = AppConfig::<Settings>::revision().unwrap();
+    parent.send((depth, revision, settings.url.clone()));
+}
+
+#[test]
+fn spawned_processes_share_the_config(mailbox: Mailbox<Report>) {
+    let initial = AppConfig::get_or_init(|| Settings {
+        url: "first".to_owned(),
+    });
+    assert_eq!(initial.url, "first");
+    let revision = AppConfig::<Settings>::revision().unwrap();
+    Process::spawn((mailbox.this(), 1), level);
+
+    let mut depths: Vec<_> = (0..3)
+        .map(|_| {
+            let (depth, seen, url) = mailbox.receive();
+            assert_eq!((seen, url.as_str()), (revision, "first"));
+            depth
+        })
+        .collect();
+    depths.sort();
+    assert_eq!(depths, [1, 2, 3]);
+
+    let updated = AppConfig::update(Settings {
+        url: "second".to_owned(),
+    });
+    assert_ne!(updated, revision);
+    assert_eq!(AppConfig::<Settings>::get().unwrap().url, "second");
+    for _ in 0..3 {
+        let (_, seen, url) = mailbox.receive_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((seen, url.as_str()), (updated, "second"));
+    }
+}
+
+#[test]
+fn processes_without_a_config_see_none() {
+    assert!(AppConfig::<Settings>::get().is_none());
+    assert!(AppConfig::<Settings>::revision().is_none());
+}
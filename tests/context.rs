@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::Request;
+use lunatic::ap::{AbstractProcess, Config, ProcessRef, RequestHandler, State};
+use lunatic::serializer::Bincode;
+use lunatic::{context, sleep, test};
+
+// Waits `delay` and forwards the request to the next hop, or returns the
+// remaining time in milliseconds at the end of the chain.
+struct Hop;
+
+impl AbstractProcess for Hop {
+    type Arg = (Option<ProcessRef<Hop>>, Duration);
+    type State = (Option<ProcessRef<Hop>>, Duration);
+    type Serializer = Bincode;
+    type Handlers = (Request<Forward>,);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, arg: Self::Arg) -> Result<Self::State, ()> {
+        Ok(arg)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Forward;
+
+impl RequestHandler<Forward> for Hop {
+    type Response = Result<Option<u64>, String>;
+
+    fn handle(state: State<Self>, _: Forward) -> Self::Response {
+        let (next, delay) = *state;
+        sleep(delay);
+        match next {
+            Some(next) => match next.with_timeout(Duration::from_secs(5)).request(Forward) {
+                Ok(response) => response,
+                Err(_) => Err(format!("timed out with {:?} left", context::remaining())),
+            },
+            None => Ok(context::remaining().map(|remaining| remaining.as_millis() as u64)),
+        }
+    }
+}
+
+fn chain(delays: [u64; 3]) -> ProcessRef<Hop> {
+    let c = Hop::start((None, Duration::from_millis(delays[2]))).unwrap();
+    let b = Hop::start((Some(c), Duration::from_millis(delays[1]))).unwrap();
+    Hop::start((Some(b), Duration::from_millis(delays[0]))).unwrap()
+}
+
+#[test]
+fn budget_is_passed_down_the_chain() {
+    let a = chain([150, 150, 0]);
+    let remaining = a
+        .with_timeout(Duration::from_millis(350))
+        .request(Forward)
+        .unwrap()
+        .unwrap()
+        .expect("the last hop didn't get a deadline");
+    assert!(remaining <= 50, "{remaining}ms left at the last hop");
+}
+
+#[test]
+fn nested_requests_time_out_with_the_budget() {
+    let a = chain([0, 400, 0]);
+    // The request itself waits on the response as long as it takes, but the
+    // first hop gives up on the second one once the budget ran out, instead
+    // of after its own 5s.
+    let response = context::with_timeout(Duration::from_millis(300), || a.request(Forward));
+    assert!(response.unwrap_err().starts_with("timed out"));
+}
+
+#[test]
+fn requests_without_timeout_have_no_deadline() {
+    let a = chain([0, 0, 0]);
+    assert_eq!(a.request(Forward), Ok(None));
+    assert_eq!(context::remaining(), None);
+}
+
+#[test]
+fn expired_deadline_is_observable() {
+    context::with_timeout(Duration::from_millis(50), || {
+        assert!(context::check().is_ok());
+        sleep(Duration::from_millis(60));
+        assert!(context::is_expired());
+        assert_eq!(context::check(), Err(context::DeadlineExceeded));
+    });
+    assert_eq!(context::deadline(), None);
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use lunatic::ap::{AbstractProcess, Config};
+use lunatic::panic::link_panic;
+use lunatic::{abstract_process, crash_dump, Mailbox, MailboxResult, Process, ProcessConfig, Tag};
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Command {
+    Add(u32),
+    Name(String),
+    Crash,
+}
+
+fn crash_on_command(_: (), mailbox: Mailbox<Command>) {
+    loop {
+        if let Command::Crash = mailbox.receive() {
+            panic!("crashed");
+        }
+    }
+}
+
+fn encoded(command: &Command) -> Vec<u8> {
+    bincode::serialize(command).unwrap()
+}
+
+#[test]
+fn dump_contains_the_last_messages_in_order() {
+    let mailbox = unsafe { Mailbox::<()>::new() }.catch_link_failure();
+    let mut config = ProcessConfig::new().unwrap();
+    config.crash_dump_messages(3);
+    let tag = Tag::new();
+    let process = Process::spawn_link_config_tag(&config, (), tag, crash_on_command);
+    process.send(Command::Add(1));
+    process.send(Command::Add(2));
+    process.send(Command::Name("x".repeat(100)));
+    process.send(Command::Crash);
+    assert!(matches!(mailbox.receive(), MailboxResult::LinkDied(died) if died == tag));
+
+    let panicked = link_panic(tag).unwrap();
+    let dump = panicked.crash_dump().unwrap();
+    assert_eq!(dump.handler, None);
+    // The first message doesn't fit into the buffer anymore.
+    assert_eq!(dump.messages.len(), 3);
+    assert!(dump
+        .messages
+        .iter()
+        .all(|m| m.type_name.ends_with("Command")));
+    assert_eq!(dump.messages[0].payload, encoded(&Command::Add(2)));
+    let name = encoded(&Command::Name("x".repeat(100)));
+    assert_eq!(dump.messages[1].size, name.len());
+    assert_eq!(dump.messages[1].payload, name[..crash_dump::PAYLOAD_LIMIT]);
+    assert_eq!(dump.messages[2].payload, encoded(&Command::Crash));
+}
+
+#[test]
+fn processes_dont_record_by_default() {
+    let mailbox = unsafe { Mailbox::<()>::new() }.catch_link_failure();
+    let tag = Tag::new();
+    let process = Process::spawn_link_tag((), tag, crash_on_command);
+    process.send(Command::Crash);
+    mailbox.receive();
+    assert_eq!(link_panic(tag).unwrap().crash_dump(), None);
+}
+
+struct Counter(u32);
+
+#[abstract_process]
+impl Counter {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        crash_dump::record_messages(8);
+        Ok(Counter(0))
+    }
+
+    #[handle_message]
+    fn increment(&mut self, by: u32) {
+        self.0 += by;
+    }
+
+    #[handle_request]
+    fn divide(&self, by: u32) -> u32 {
+        self.0 / by
+    }
+}
+
+#[test]
+fn dump_names_the_running_handler() {
+    let mailbox = unsafe { Mailbox::<()>::new() }.catch_link_failure();
+    let tag = Tag::new();
+    let counter = Counter::link_with(tag).start(()).unwrap();
+    counter.increment(1);
+    counter.increment(2);
+    assert_eq!(counter.divide(3), 1);
+    counter
+        .with_timeout(Duration::from_millis(100))
+        .divide(0)
+        .ok();
+    assert!(matches!(mailbox.receive(), MailboxResult::LinkDied(died) if died == tag));
+
+    let dump = link_panic(tag).unwrap().crash_dump().unwrap().clone();
+    assert_eq!(dump.handler.as_deref(), Some("Divide"));
+    let handlers: Vec<_> = dump
+        .messages
+        .iter()
+        .map(|m| m.type_name.rsplit("::").next().unwrap())
+        .collect();
+    assert_eq!(
+        handlers,
+        [
+            "__MsgWrapIncrement",
+            "__MsgWrapIncrement",
+            "__MsgWrapDivide",
+            "__MsgWrapDivide"
+        ]
+    );
+    assert!(dump.to_string().contains("(in handler Divide)"));
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use lunatic::dead_letter::{self, DeadLetter};
+use lunatic::{sleep, Mailbox, Process, Tag};
+use lunatic_test::test;
+
+#[test]
+fn send_to_dead_process_is_forwarded(mailbox: Mailbox<DeadLetter>) {
+    let dead = Process::spawn((), |_, _: Mailbox<u64>| {});
+    sleep(Duration::from_millis(50));
+
+    dead_letter::set_handler(mailbox.this());
+    let tag = Tag::special(100).unwrap();
+    dead.tag_send(tag, 42);
+
+    let dead_letter = mailbox.receive();
+    assert_eq!(dead_letter.target_id, dead.id());
+    assert_eq!(dead_letter.tag, tag);
+    assert!(!dead_letter.payload.is_empty());
+}
+
+#[test]
+fn send_to_alive_process_is_delivered(mailbox: Mailbox<DeadLetter>) {
+    dead_letter::set_handler(mailbox.this());
+    let alive = Process::spawn(mailbox.this(), |parent, mailbox: Mailbox<u64>| {
+        assert_eq!(mailbox.receive(), 42);
+        // Signal the parent by sending a message to a dead process.
+        let dead = Process::spawn((), |_, _: Mailbox<u64>| {});
+        sleep(Duration::from_millis(50));
+        dead_letter::set_handler(parent);
+        dead.send(43);
+    });
+    alive.send(42);
+    let dead_letter = mailbox.receive();
+    assert_ne!(dead_letter.target_id, alive.id());
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use lunatic::ap::{AbstractProcess, Config};
+use lunatic::{abstract_process, debug, Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn labels_and_entries_are_bounded() {
+    debug::set_label("a".repeat(1000));
+    assert_eq!(debug::label().unwrap().len(), debug::MAX_VALUE_LEN);
+
+    for i in 0..debug::MAX_ENTRIES {
+        assert!(debug::set_debug_kv(format!("key{i}"), "value"));
+    }
+    assert!(!debug::set_debug_kv("one too many", "value"));
+    // Existing keys can still be updated.
+    assert!(debug::set_debug_kv("key0", "updated"));
+    debug::remove_debug_kv("key1");
+    assert!(debug::set_debug_kv("k".repeat(100), "value"));
+
+    let info = debug::debug_info();
+    assert_eq!(info.entries.len(), debug::MAX_ENTRIES);
+    assert_eq!(info.entries["key0"], "updated");
+    assert!(info.entries.contains_key(&"k".repeat(debug::MAX_KEY_LEN)));
+}
+
+#[test]
+fn query_other_process(mailbox: Mailbox<()>) {
+    let process = Process::spawn(mailbox.this(), |parent, mailbox: Mailbox<()>| {
+        debug::set_label("tcp-conn 10.2.3.4:5521");
+        debug::set_debug_kv("state", "awaiting_auth");
+        parent.send(());
+        loop {
+            mailbox.receive();
+        }
+    });
+    mailbox.receive();
+
+    let info = process.debug_info(Duration::from_secs(1)).unwrap();
+    assert_eq!(info.process_id, process.id());
+    assert_eq!(info.label.as_deref(), Some("tcp-conn 10.2.3.4:5521"));
+    assert_eq!(info.handler, None);
+    assert_eq!(info.entries["state"], "awaiting_auth");
+    assert!(info.to_string().contains("state=awaiting_auth"));
+    process.kill();
+}
+
+struct Inspector;
+
+#[abstract_process]
+impl Inspector {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(Inspector)
+    }
+
+    #[handle_request]
+    fn first(&self) -> Option<String> {
+        debug::debug_info().handler
+    }
+
+    #[handle_request]
+    fn second_step(&self) -> Option<String> {
+        debug::debug_info().handler
+    }
+}
+
+#[test]
+fn abstract_process_is_labeled_with_the_running_handler() {
+    let inspector = Inspector::link().start(()).unwrap();
+    assert_eq!(inspector.first().as_deref(), Some("First"));
+    assert_eq!(inspector.second_step().as_deref(), Some("SecondStep"));
+
+    let info = inspector.debug_info(Duration::from_secs(1)).unwrap();
+    assert!(info.label.unwrap().ends_with("Inspector"));
+    // Waiting on the next message.
+    assert_eq!(info.handler, None);
+}
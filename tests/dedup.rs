@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::{Message, Request};
+use lunatic::ap::{AbstractProcess, Config, MessageHandler, RequestHandler, State};
+use lunatic::dedup::{Dedup, HasMessageId, SeenIds, Window};
+use lunatic::serializer::Bincode;
+use lunatic::{sleep, Mailbox, MailboxResult};
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    id: u64,
+}
+
+impl HasMessageId for Event {
+    type Id = u64;
+
+    fn message_id(&self) -> u64 {
+        self.id
+    }
+}
+
+// Each id followed by a retry of it, and a retry of the id before it.
+fn with_duplicates(count: u64) -> Vec<Event> {
+    (0..count)
+        .flat_map(|id| [id, id, id.saturating_sub(1)])
+        .map(|id| Event { id })
+        .collect()
+}
+
+#[test]
+fn duplicates_are_dropped(mailbox: Mailbox<Event>) {
+    for event in with_duplicates(100) {
+        mailbox.this().send(event);
+    }
+    let mut mailbox = Dedup::new(mailbox, Window::count(8));
+    let mut received = Vec::new();
+    while let MailboxResult::Message(event) = mailbox.receive_timeout(Duration::from_millis(100)) {
+        received.push(event.id);
+        assert!(mailbox.seen().len() <= 8);
+    }
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+    assert_eq!(mailbox.seen().dropped(), 200);
+}
+
+#[test]
+fn time_window_forgets_old_ids() {
+    let mut seen = SeenIds::new(Window::time(Duration::from_millis(50), 4));
+    assert!(seen.insert(1));
+    assert!(!seen.insert(1));
+    sleep(Duration::from_millis(60));
+    assert!(seen.insert(1));
+    assert!(!seen.insert(1));
+
+    // The window never holds more than its cap.
+    for id in 2..100 {
+        assert!(seen.insert(id));
+        assert!(seen.len() <= 4);
+    }
+}
+
+struct Ingest;
+
+impl AbstractProcess for Ingest {
+    type Arg = ();
+    type State = (SeenIds<u64>, Vec<u64>);
+    type Serializer = Bincode;
+    type Handlers = (Message<Event>, Request<Handled>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self::State, ()> {
+        let seen = SeenIds::new(Window::count(16)).with_metric("Event");
+        Ok((seen, Vec::new()))
+    }
+}
+
+impl MessageHandler<Event> for Ingest {
+    fn handle(mut state: State<Self>, event: Event) {
+        if state.0.insert(event.message_id()) {
+            state.1.push(event.id);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Handled;
+impl RequestHandler<Handled> for Ingest {
+    type Response = (Vec<u64>, usize);
+
+    fn handle(state: State<Self>, _: Handled) -> (Vec<u64>, usize) {
+        (state.1.clone(), state.0.len())
+    }
+}
+
+#[test]
+fn abstract_process_handles_each_id_once() {
+    let ingest = Ingest::start(()).unwrap();
+    for event in with_duplicates(50) {
+        ingest.send(event);
+    }
+    let (handled, remembered) = ingest.request(Handled);
+    assert_eq!(handled, (0..50).collect::<Vec<_>>());
+    assert!(remembered <= 16);
+}
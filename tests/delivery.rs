@@ -0,0 +1,101 @@
+// Conformance tests of the local delivery guarantees, see the "Delivery
+// guarantees" section of the `Mailbox` documentation.
+
+use std::collections::HashMap;
+
+use lunatic::{Mailbox, MailboxResult, Process, Tag};
+use lunatic_test::test;
+
+const SENDERS: u32 = 8;
+const MESSAGES: u32 = 1_000;
+
+#[test]
+fn per_sender_fifo_without_duplicates(mailbox: Mailbox<(u32, u32)>) {
+    for sender in 0..SENDERS {
+        Process::spawn(
+            (mailbox.this(), sender),
+            |(receiver, sender), _: Mailbox<()>| {
+                for i in 0..MESSAGES {
+                    receiver.send((sender, i));
+                }
+            },
+        );
+    }
+    let mut next: HashMap<u32, u32> = HashMap::new();
+    for _ in 0..SENDERS * MESSAGES {
+        let (sender, i) = mailbox.receive();
+        let expected = next.entry(sender).or_default();
+        assert_eq!(i, *expected, "out of order or duplicated message");
+        *expected += 1;
+    }
+    assert!(next.values().all(|count| *count == MESSAGES));
+    assert!(mailbox
+        .receive_timeout(std::time::Duration::from_millis(50))
+        .is_timed_out());
+}
+
+#[test]
+fn tagged_receive_keeps_order_of_skipped_messages(mailbox: Mailbox<u32>) {
+    let (a, b) = (Tag::new(), Tag::new());
+    let this = mailbox.this();
+    this.tag_send(a, 1);
+    this.tag_send(b, 10);
+    this.tag_send(a, 2);
+    this.tag_send(b, 11);
+    this.send(3);
+
+    assert_eq!(mailbox.tag_receive(&[b]), 10);
+    assert_eq!(mailbox.tag_receive(&[b]), 11);
+    assert_eq!(mailbox.receive(), 1);
+    assert_eq!(mailbox.receive(), 2);
+    assert_eq!(mailbox.receive(), 3);
+}
+
+#[test]
+fn failed_deserialization_takes_only_that_message(mailbox: Mailbox<u64>) {
+    let this = mailbox.this();
+    // A single byte can't be deserialized as `u64`.
+    let wrong = unsafe { Process::<u8>::from_id(this.node_id(), this.id()) };
+    this.send(1);
+    wrong.send(2);
+    this.send(3);
+
+    let timeout = std::time::Duration::from_secs(1);
+    assert_eq!(mailbox.try_receive(timeout).unwrap(), 1);
+    assert!(matches!(
+        mailbox.try_receive(timeout),
+        MailboxResult::DeserializationFailed(_)
+    ));
+    assert_eq!(mailbox.try_receive(timeout).unwrap(), 3);
+}
+
+#[test]
+fn priority_lane_is_fifo_and_first(mailbox: Mailbox<u32>) {
+    let this = mailbox.this();
+    this.send(1);
+    this.send(2);
+    this.send_priority(10);
+    this.send_priority(11);
+
+    let received: Vec<u32> = (0..4).map(|_| mailbox.receive()).collect();
+    assert_eq!(received, [10, 11, 1, 2]);
+}
+
+#[test]
+fn link_death_after_last_messages(mailbox: Mailbox<u32>) {
+    let mailbox = mailbox.catch_link_failure();
+    let tag = Tag::new();
+    Process::spawn_link_tag(mailbox.this(), tag, |parent, _: Mailbox<()>| {
+        for i in 0..MESSAGES {
+            parent.send(i);
+        }
+        panic!("dies right after sending");
+    });
+    for i in 0..MESSAGES {
+        match mailbox.receive() {
+            MailboxResult::Message(message) => assert_eq!(message, i),
+            _ => panic!("link death received before message {i}"),
+        }
+    }
+    assert!(matches!(mailbox.receive(), MailboxResult::LinkDied(died) if died == tag));
+}
@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::{Message, Request};
+use lunatic::ap::singleton::CLAIM_PERIOD;
+use lunatic::ap::{
+    AbstractProcess, Config, GlobalSingleton, MessageHandler, RequestHandler, State,
+};
+use lunatic::distributed::{ConnState, ConnectionEvent};
+use lunatic::serializer::{Bincode, EncodeError};
+use lunatic::{distributed, sleep, LunaticError, Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn local_attributes() {
+    let node_id = distributed::node_id();
+    distributed::set_attribute("version", "1.2.0");
+    assert_eq!(
+        distributed::attributes(node_id)
+            .unwrap()
+            .get("version")
+            .map(String::as_str),
+        Some("1.2.0")
+    );
+    assert!(distributed::nodes_matching("version", "1.2.0").contains(&node_id));
+
+    distributed::remove_attribute("version");
+    assert!(!distributed::nodes_matching("version", "1.2.0").contains(&node_id));
+}
+
+#[test]
+fn local_node_is_healthy() {
+    let node_id = distributed::node_id();
+    assert_eq!(distributed::node_health(node_id), Some(Duration::ZERO));
+}
+
+#[test]
+fn drain_waits_on_tracked_processes() {
+    let worker = Process::spawn((), |_, _: Mailbox<()>| {
+        sleep(Duration::from_millis(200));
+    });
+    distributed::track(worker);
+    assert!(!distributed::drain(Some(Duration::from_millis(50))));
+    assert!(distributed::drain(Some(Duration::from_secs(2))));
+    assert!(distributed::nodes_matching(distributed::DRAINING, "true")
+        .contains(&distributed::node_id()));
+    distributed::remove_attribute(distributed::DRAINING);
+}
+
+#[test]
+fn local_sends_are_not_instrumented() {
+    distributed::instrument_sends(true);
+    let process = Process::spawn((), |_, mailbox: Mailbox<u64>| {
+        mailbox.receive();
+    });
+    process.send(1);
+    assert_eq!(distributed::connection_stats(distributed::node_id()), None);
+    distributed::instrument_sends(false);
+}
+
+#[test]
+fn remote_sends_increase_counters() {
+    let local = distributed::node_id();
+    // Only runs when the test is started as part of a cluster.
+    let Some(node) = distributed::nodes().into_iter().find(|node| *node != local) else {
+        return;
+    };
+    distributed::instrument_sends(true);
+    let process = Process::spawn_node(node, (), |_, mailbox: Mailbox<Vec<u8>>| loop {
+        mailbox.receive();
+    });
+    for _ in 0..100 {
+        process.send(vec![0; 100]);
+    }
+    let stats = distributed::connection_stats(node).unwrap();
+    assert_eq!(stats.sent_messages, 100);
+    assert!(stats.sent_bytes >= 100 * 100);
+    assert!(stats.max_send_latency <= stats.send_latency);
+
+    distributed::instrument_sends(false);
+    process.send(Vec::new());
+    assert_eq!(
+        distributed::connection_stats(node).unwrap().sent_messages,
+        100
+    );
+    process.kill();
+}
+
+struct Coordinator;
+
+impl AbstractProcess for Coordinator {
+    type Arg = ();
+    type State = ();
+    type Serializer = Bincode;
+    type Handlers = (Request<Where>, Message<Crash>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Where;
+impl RequestHandler<Where> for Coordinator {
+    type Response = u64;
+
+    fn handle(_: State<Self>, _: Where) -> u64 {
+        distributed::node_id()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Crash;
+impl MessageHandler<Crash> for Coordinator {
+    fn handle(_: State<Self>, _: Crash) {
+        panic!("coordinator crashed");
+    }
+}
+
+#[test]
+fn singleton_takeover(mailbox: Mailbox<(u64, u64)>) {
+    let local = distributed::node_id();
+    // Only runs when the test is started as part of a cluster.
+    let Some(node) = distributed::nodes().into_iter().find(|node| *node != local) else {
+        return;
+    };
+    // The other node starts the singleton first.
+    Process::spawn_node(node, mailbox.this(), |parent, _: Mailbox<()>| {
+        let owner = GlobalSingleton::<Coordinator>::start_or_lookup("coordinator", ()).unwrap();
+        parent.send((owner.node_id(), owner.id()));
+    });
+    let first = mailbox.receive();
+    assert_eq!(first.0, node);
+    sleep(distributed::HEARTBEAT_INTERVAL * 2);
+
+    let owner = GlobalSingleton::<Coordinator>::start_or_lookup("coordinator", ()).unwrap();
+    assert_eq!((owner.node_id(), owner.id()), first);
+    assert_eq!(owner.request(Where), node);
+
+    let moved = GlobalSingleton::<Coordinator>::watch("coordinator");
+    owner.send(Crash);
+    let next = moved.receive_timeout(Duration::from_secs(15)).unwrap();
+    assert_ne!((next.node_id(), next.id()), first);
+
+    // Both nodes may have started an instance, wait until one of them won.
+    sleep(CLAIM_PERIOD + distributed::HEARTBEAT_INTERVAL * 3);
+    let settled = GlobalSingleton::<Coordinator>::lookup("coordinator").unwrap();
+    assert_eq!(settled.request(Where), settled.node_id());
+}
+
+#[test]
+fn spawned_processes_share_the_module(mailbox: Mailbox<u64>) {
+    Process::spawn(mailbox.this(), |parent, _: Mailbox<()>| {
+        parent.send(distributed::module_id())
+    });
+    assert_eq!(mailbox.receive(), distributed::module_id());
+}
+
+#[test]
+fn connecting_to_unknown_nodes_fails() {
+    assert!(matches!(
+        distributed::connect(u64::MAX),
+        Err(LunaticError::NotConnected { node_id: u64::MAX })
+    ));
+    assert!(distributed::connect(distributed::node_id()).is_ok());
+}
+
+#[test]
+fn sends_in_a_partial_mesh_need_a_connection(mailbox: Mailbox<ConnectionEvent>) {
+    let local = distributed::node_id();
+    // Only runs when the test is started as part of a cluster.
+    let Some(node) = distributed::nodes().into_iter().find(|node| *node != local) else {
+        return;
+    };
+    distributed::subscribe_connections(mailbox.this());
+    distributed::partial_mesh(true);
+    distributed::connect(node).unwrap();
+    assert_eq!(mailbox.receive(), ConnectionEvent::Established(node));
+    assert_eq!(
+        distributed::connections(),
+        vec![(node, ConnState::Connected)]
+    );
+
+    let replies = unsafe { Mailbox::<u64>::new() };
+    let echo = Process::spawn_node(node, replies.this(), |parent, mailbox: Mailbox<u64>| loop {
+        parent.send(mailbox.receive());
+    });
+    echo.send(42);
+    assert_eq!(replies.receive_timeout(Duration::from_secs(1)).unwrap(), 42);
+
+    distributed::disconnect(node);
+    assert_eq!(mailbox.receive(), ConnectionEvent::Closed(node));
+    assert!(distributed::connections().is_empty());
+    assert!(matches!(
+        echo.try_send(1),
+        Err(EncodeError::NotConnected(id)) if id == node
+    ));
+    distributed::partial_mesh(false);
+    assert!(echo.try_send(1).is_ok());
+}
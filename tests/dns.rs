@@ -0,0 +1,249 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use lunatic::net::dns::{DnsError, Resolver, SrvRecord};
+use lunatic::net::{TcpListener, UdpSocket};
+use lunatic::{sleep, Mailbox, MailboxResult, Process};
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum Event {
+    Listening(SocketAddr),
+    // A query for the name, and if it came over TCP.
+    Asked(String, bool),
+}
+
+// Starts a nameserver answering with recorded responses, over UDP and TCP on
+// the same port.
+fn fake_nameserver(mailbox: &Mailbox<Event>) -> SocketAddr {
+    Process::spawn(mailbox.this(), |observer, _: Mailbox<()>| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        Process::spawn((observer, addr), |(observer, addr), _: Mailbox<()>| {
+            let socket = UdpSocket::bind(addr).unwrap();
+            observer.send(Event::Listening(addr));
+            let mut query = [0; 512];
+            loop {
+                let (len, client) = socket.recv_from(&mut query).unwrap();
+                let (name, response) = recorded(&query[..len], false);
+                observer.send(Event::Asked(name, false));
+                socket.send_to(&response, client).unwrap();
+            }
+        });
+        loop {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).unwrap();
+            let (name, response) = recorded(&query, true);
+            observer.send(Event::Asked(name, true));
+            stream
+                .write_all(&(response.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    });
+    match mailbox.receive() {
+        Event::Listening(addr) => addr,
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+// Returns the name in `query` and the recorded response to it.
+fn recorded(query: &[u8], tcp: bool) -> (String, Vec<u8>) {
+    let mut labels = Vec::new();
+    let mut end = 12;
+    while query[end] != 0 {
+        let len = query[end] as usize;
+        labels.push(String::from_utf8(query[end + 1..end + 1 + len].to_vec()).unwrap());
+        end += 1 + len;
+    }
+    let name = labels.join(".");
+    let record_type = u16::from_be_bytes([query[end + 1], query[end + 2]]);
+    let a = |ip: [u8; 4], ttl| record(1, ttl, &ip);
+    let (flags, answers, authority) = match (name.as_str(), record_type) {
+        ("api.internal", 1) => (
+            0x8180,
+            vec![a([10, 0, 0, 1], 60), a([10, 0, 0, 2], 60)],
+            vec![],
+        ),
+        ("api.internal", 28) => (
+            0x8180,
+            vec![record(28, 60, &Ipv6Addr::LOCALHOST.octets())],
+            vec![],
+        ),
+        ("_http._tcp.api.internal", 33) => (
+            0x8180,
+            vec![
+                srv(20, 0, 8081, "web2.internal"),
+                srv(10, 5, 8080, "web1.internal"),
+            ],
+            vec![],
+        ),
+        ("short.internal", 1) => (0x8180, vec![a([10, 0, 0, 3], 1)], vec![]),
+        // Too large for UDP, only the TCP response holds the records.
+        ("big.internal", 1) if !tcp => (0x8380, vec![], vec![]),
+        ("big.internal", 1) => (
+            0x8180,
+            (1..=40).map(|i| a([10, 0, 1, i], 60)).collect(),
+            vec![],
+        ),
+        // Missing names are known to be missing for 30s.
+        _ => (0x8183, vec![], vec![soa(300, 30)]),
+    };
+    let mut response = query[..2].to_vec();
+    for field in [flags, 1, answers.len() as u16, authority.len() as u16, 0] {
+        response.extend_from_slice(&field.to_be_bytes());
+    }
+    response.extend_from_slice(&query[12..end + 5]);
+    response.extend(answers.concat());
+    response.extend(authority.concat());
+    (name, response)
+}
+
+// A record of the name in the question.
+fn record(record_type: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+    let mut record = vec![0xC0, 0x0C];
+    record.extend_from_slice(&record_type.to_be_bytes());
+    record.extend_from_slice(&1u16.to_be_bytes());
+    record.extend_from_slice(&ttl.to_be_bytes());
+    record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+fn srv(priority: u16, weight: u16, port: u16, target: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    for field in [priority, weight, port] {
+        data.extend_from_slice(&field.to_be_bytes());
+    }
+    data.extend(name(target));
+    record(33, 60, &data)
+}
+
+fn soa(ttl: u32, minimum: u32) -> Vec<u8> {
+    let mut data = name("ns.internal");
+    data.extend(name("admin.internal"));
+    for field in [1, 3600, 600, 86400, minimum] {
+        data.extend_from_slice(&u32::to_be_bytes(field));
+    }
+    record(6, ttl, &data)
+}
+
+fn name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+// Returns the queries the nameserver received so far.
+fn asked(mailbox: &Mailbox<Event>) -> Vec<(String, bool)> {
+    let mut asked = Vec::new();
+    while let MailboxResult::Message(Event::Asked(name, tcp)) =
+        mailbox.receive_timeout(Duration::ZERO)
+    {
+        asked.push((name, tcp));
+    }
+    asked
+}
+
+#[test]
+fn lookup_records(mailbox: Mailbox<Event>) {
+    let resolver = Resolver::new(fake_nameserver(&mailbox));
+    assert_eq!(
+        resolver.lookup_a("api.internal"),
+        Ok(vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)])
+    );
+    assert_eq!(
+        resolver.lookup_aaaa("api.internal"),
+        Ok(vec![Ipv6Addr::LOCALHOST])
+    );
+    // Ordered by priority.
+    assert_eq!(
+        resolver.lookup_srv("_http._tcp.api.internal"),
+        Ok(vec![
+            SrvRecord {
+                priority: 10,
+                weight: 5,
+                port: 8080,
+                target: "web1.internal".to_owned()
+            },
+            SrvRecord {
+                priority: 20,
+                weight: 0,
+                port: 8081,
+                target: "web2.internal".to_owned()
+            },
+        ])
+    );
+}
+
+#[test]
+fn answers_are_cached_for_their_ttl(mailbox: Mailbox<Event>) {
+    let resolver = Resolver::new(fake_nameserver(&mailbox));
+    for _ in 0..3 {
+        assert_eq!(
+            resolver.lookup_a("short.internal"),
+            Ok(vec![Ipv4Addr::new(10, 0, 0, 3)])
+        );
+    }
+    assert_eq!(asked(&mailbox), [("short.internal".to_owned(), false)]);
+
+    // Asked again once the TTL of 1s expired.
+    sleep(Duration::from_millis(1100));
+    resolver.lookup_a("short.internal").unwrap();
+    assert_eq!(asked(&mailbox), [("short.internal".to_owned(), false)]);
+}
+
+#[test]
+fn missing_names_are_cached(mailbox: Mailbox<Event>) {
+    let resolver = Resolver::new(fake_nameserver(&mailbox));
+    for _ in 0..2 {
+        assert_eq!(
+            resolver.lookup_a("missing.internal"),
+            Err(DnsError::NotFound("missing.internal".to_owned()))
+        );
+    }
+    assert_eq!(asked(&mailbox), [("missing.internal".to_owned(), false)]);
+}
+
+#[test]
+fn truncated_responses_are_repeated_over_tcp(mailbox: Mailbox<Event>) {
+    let resolver = Resolver::new(fake_nameserver(&mailbox));
+    let addresses = resolver.lookup_a("big.internal").unwrap();
+    assert_eq!(addresses.len(), 40);
+    assert_eq!(
+        asked(&mailbox),
+        [
+            ("big.internal".to_owned(), false),
+            ("big.internal".to_owned(), true)
+        ]
+    );
+}
+
+#[test]
+fn silent_nameservers_are_skipped(mailbox: Mailbox<Event>) {
+    // Receives the queries, but never responds.
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let resolver = Resolver::new(silent.local_addr().unwrap())
+        .with_nameserver(fake_nameserver(&mailbox))
+        .with_timeout(Duration::from_millis(100));
+    assert_eq!(
+        resolver
+            .lookup_a("api.internal")
+            .map(|addresses| addresses.len()),
+        Ok(2)
+    );
+
+    let resolver = Resolver::new(silent.local_addr().unwrap())
+        .with_timeout(Duration::from_millis(100))
+        .with_attempts(3);
+    assert_eq!(resolver.lookup_a("api.internal"), Err(DnsError::TimedOut));
+}
@@ -0,0 +1,27 @@
+use std::io::ErrorKind;
+
+use lunatic::net::TcpStream;
+use lunatic::LunaticError;
+use lunatic_test::test;
+
+#[test]
+fn permission_denied_into_io_error() {
+    let error: std::io::Error = LunaticError::PermissionDenied.into();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn failed_connect_contains_address() {
+    let error = TcpStream::connect("127.0.0.1:1").unwrap_err();
+    let lunatic_error = error
+        .get_ref()
+        .and_then(|error| error.downcast_ref::<LunaticError>())
+        .unwrap();
+    match lunatic_error {
+        LunaticError::Network { address, .. } => {
+            assert_eq!(*address, Some("127.0.0.1:1".parse().unwrap()))
+        }
+        _ => panic!("expected a networking error"),
+    }
+    assert!(std::error::Error::source(lunatic_error).is_some());
+}
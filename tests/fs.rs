@@ -0,0 +1,50 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use lunatic::fs::{self, File, FsError};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn file_handoff_keeps_offset(mailbox: Mailbox<String>) {
+    fs::create_dir_all("target/lunatic-fs").unwrap();
+    let path = "target/lunatic-fs/handoff.txt";
+    fs::write(path, "hello world").unwrap();
+
+    let mut file = File::open(path).unwrap();
+    file.seek(SeekFrom::Start(6)).unwrap();
+    Process::spawn(
+        (mailbox.this(), file),
+        |(parent, mut file), _: Mailbox<()>| {
+            let mut rest = String::new();
+            file.read_to_string(&mut rest).unwrap();
+            parent.send(rest);
+        },
+    );
+    assert_eq!(mailbox.receive(), "world");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn written_file_handoff(mailbox: Mailbox<Vec<u8>>) {
+    fs::create_dir_all("target/lunatic-fs").unwrap();
+    let path = "target/lunatic-fs/written.txt";
+    let mut file = File::create(path).unwrap();
+    file.write_all(b"abc").unwrap();
+    Process::spawn(
+        (mailbox.this(), file),
+        |(parent, mut file), _: Mailbox<()>| {
+            file.write_all(b"def").unwrap();
+            parent.send(fs::read(file.path()).unwrap());
+        },
+    );
+    assert_eq!(mailbox.receive(), b"abcdef");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn path_outside_sandbox() {
+    assert!(matches!(
+        fs::read("/lunatic-outside-of-sandbox"),
+        Err(FsError::OutsideSandbox(_))
+    ));
+}
@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+use lunatic::{fuel, Mailbox, MailboxResult, Process, ProcessConfig, Tag};
+use lunatic_test::test;
+
+#[test]
+fn conversion_is_monotonic() {
+    assert!(fuel::fuel_per_ms() > 0.0);
+    let mut last_fuel = 0;
+    let mut last_duration = Duration::ZERO;
+    for millis in [0, 1, 2, 10, 50, 100, 1_000, 60_000] {
+        let fuel = fuel::duration_to_fuel(Duration::from_millis(millis));
+        assert!(fuel >= last_fuel.max(1));
+        let duration = fuel::fuel_to_duration(fuel);
+        assert!(duration >= last_duration);
+        // Rounding up the fuel never gives less time than asked for.
+        assert!(duration + Duration::from_micros(1) >= Duration::from_millis(millis));
+        last_fuel = fuel;
+        last_duration = duration;
+    }
+}
+
+#[test]
+fn rate_can_be_overridden() {
+    fuel::set_fuel_per_ms(4.0);
+    assert_eq!(fuel::duration_to_fuel(Duration::from_millis(50)), 200);
+    assert_eq!(fuel::fuel_to_duration(200), Duration::from_millis(50));
+}
+
+fn spin(_: (), _: Mailbox<()>) {
+    let mut counter = 0u64;
+    loop {
+        counter = std::hint::black_box(counter.wrapping_add(1));
+    }
+}
+
+#[test]
+fn busy_process_is_stopped_after_cpu_time() {
+    let mailbox = unsafe { Mailbox::<()>::new() }.catch_link_failure();
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_cpu_time(Duration::from_millis(50));
+    let tag = Tag::new();
+    let start = Instant::now();
+    Process::spawn_link_config_tag(&config, (), tag, spin);
+    let result = mailbox.receive_timeout(Duration::from_secs(5));
+    assert!(matches!(result, MailboxResult::LinkDied(died) if died == tag));
+    // The estimate is rough, CI machines are noisy.
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(5),
+        "stopped after {elapsed:?}"
+    );
+    assert!(
+        elapsed <= Duration::from_millis(1_000),
+        "stopped after {elapsed:?}"
+    );
+}
@@ -0,0 +1,84 @@
+use std::io::{Read, Write};
+
+use lunatic::channel::RecvError;
+use lunatic::fs::{self, File};
+use lunatic::io::{self, MailboxStream};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn file_lines_until_eof() {
+    fs::create_dir_all("target/lunatic-io").unwrap();
+    let path = "target/lunatic-io/input.txt";
+    fs::write(path, "first\nsecond\r\n\nlast").unwrap();
+
+    let lines = io::file_lines(File::open(path).unwrap());
+    assert_eq!(lines.recv(), Ok("first".to_owned()));
+    assert_eq!(lines.recv(), Ok("second".to_owned()));
+    assert_eq!(lines.recv(), Ok("".to_owned()));
+    assert_eq!(lines.recv(), Ok("last".to_owned()));
+    // The receiver is closed at EOF.
+    assert_eq!(lines.recv(), Err(RecvError));
+    fs::remove_file(path).unwrap();
+}
+
+const STREAM_SIZE: usize = 10 * 1024 * 1024;
+
+// 64 bit FNV-1a.
+fn checksum(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[test]
+fn stream_through_pipe(mailbox: Mailbox<u64>) {
+    let (writer, mut reader) = MailboxStream::pipe_with_capacity(2, 16 * 1024);
+    Process::spawn(
+        (writer, mailbox.this()),
+        |(mut writer, parent), _: Mailbox<()>| {
+            let mut hash = 0xcbf29ce484222325;
+            let mut state: u32 = 1;
+            let mut written = 0;
+            while written < STREAM_SIZE {
+                // Writes of varying sizes, that don't line up with the chunks.
+                let len = (state as usize % 70_000 + 1).min(STREAM_SIZE - written);
+                let bytes: Vec<u8> = (0..len)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 17;
+                        state ^= state << 5;
+                        state as u8
+                    })
+                    .collect();
+                writer.write_all(&bytes).unwrap();
+                hash = checksum(hash, &bytes);
+                written += len;
+            }
+            parent.send(hash);
+            // Dropping the writer flushes it and closes the pipe.
+        },
+    );
+
+    let mut hash = 0xcbf29ce484222325;
+    let mut read = 0;
+    let mut buffer = vec![0; 10_000];
+    loop {
+        let n = reader.read(&mut buffer).unwrap();
+        if n == 0 {
+            break;
+        }
+        hash = checksum(hash, &buffer[..n]);
+        read += n;
+    }
+    assert_eq!(read, STREAM_SIZE);
+    assert_eq!(hash, mailbox.receive());
+}
+
+#[test]
+fn write_fails_after_reader_is_dropped() {
+    let (mut writer, reader) = MailboxStream::pipe_with_capacity(1, 4);
+    drop(reader);
+    let error = writer.write_all(b"more than one chunk").unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+}
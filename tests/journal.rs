@@ -0,0 +1,143 @@
+use lunatic::ap::handlers::{Message, Request};
+use lunatic::ap::journal::{self, Journal};
+use lunatic::ap::{AbstractProcess, Config, MessageHandler, RequestHandler, Snapshot, State};
+use lunatic::serializer::Bincode;
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+const PATH: &str = "target/lunatic-journal.db";
+
+// Counts the increments it handled, journaled under the name it's started with.
+struct Counter {
+    count: u64,
+    replayed: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Increment;
+
+#[derive(Serialize, Deserialize)]
+struct Count;
+
+#[derive(Serialize, Deserialize)]
+struct Replayed;
+
+impl AbstractProcess for Counter {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = (String, u64);
+    type Handlers = (Message<Increment>, Request<Count>, Request<Replayed>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: (String, u64)) -> Result<Self, ()> {
+        Ok(Counter {
+            count: 0,
+            replayed: 0,
+        })
+    }
+
+    fn journal((name, compact_every): &(String, u64)) -> Option<Journal> {
+        let journal = Journal::open(PATH, name).unwrap();
+        Some(journal.compact_every(*compact_every))
+    }
+
+    fn snapshot(state: &Self) -> Option<Snapshot> {
+        Some(Snapshot::new(1, &state.count))
+    }
+
+    fn init_from_snapshot(
+        _: Config<Self>,
+        _: (String, u64),
+        snapshot: Snapshot,
+    ) -> Result<Self, ()> {
+        Ok(Counter {
+            count: snapshot.decode(1).unwrap_or(0),
+            replayed: 0,
+        })
+    }
+}
+
+impl MessageHandler<Increment> for Counter {
+    fn handle(mut state: State<Self>, _: Increment) {
+        state.count += 1;
+        if journal::is_replaying() {
+            state.replayed += 1;
+        }
+    }
+}
+
+impl RequestHandler<Count> for Counter {
+    type Response = u64;
+
+    fn handle(state: State<Self>, _: Count) -> u64 {
+        state.count
+    }
+}
+
+impl RequestHandler<Replayed> for Counter {
+    type Response = u64;
+
+    fn handle(state: State<Self>, _: Replayed) -> u64 {
+        state.replayed
+    }
+}
+
+fn fresh_journal(name: &str) {
+    std::fs::create_dir_all("target").unwrap();
+    Journal::open(PATH, name).unwrap().clear().unwrap();
+}
+
+#[test]
+fn killed_process_is_restored_from_its_journal() {
+    fresh_journal("killed");
+    let arg = ("killed".to_owned(), u64::MAX);
+    let counter = Counter::start(arg.clone()).unwrap();
+    for _ in 0..100 {
+        counter.send(Increment);
+    }
+    assert_eq!(counter.request(Count), 100);
+    counter.kill();
+
+    let counter = Counter::start(arg).unwrap();
+    assert_eq!(counter.request(Count), 100);
+    assert_eq!(counter.request(Replayed), 100);
+    counter.shutdown();
+}
+
+#[test]
+fn compaction_replaces_messages_with_a_snapshot() {
+    fresh_journal("compacted");
+    let arg = ("compacted".to_owned(), 10);
+    let counter = Counter::start(arg.clone()).unwrap();
+    for _ in 0..25 {
+        counter.send(Increment);
+    }
+    // The requests are journaled too, 26 messages in total.
+    assert_eq!(counter.request(Count), 25);
+    counter.kill();
+    assert_eq!(Journal::open(PATH, "compacted").unwrap().len().unwrap(), 6);
+
+    let counter = Counter::start(arg).unwrap();
+    assert_eq!(counter.request(Count), 25);
+    // Only the increments after the snapshot were replayed.
+    assert_eq!(counter.request(Replayed), 5);
+    counter.shutdown();
+}
+
+#[test]
+fn names_are_bound_as_parameters() {
+    let name = "it's a journal";
+    fresh_journal(name);
+    let arg = (name.to_owned(), u64::MAX);
+    let counter = Counter::start(arg.clone()).unwrap();
+    for _ in 0..3 {
+        counter.send(Increment);
+    }
+    assert_eq!(counter.request(Count), 3);
+    counter.kill();
+    assert_eq!(Journal::open(PATH, name).unwrap().len().unwrap(), 4);
+
+    let counter = Counter::start(arg).unwrap();
+    assert_eq!(counter.request(Replayed), 3);
+    counter.shutdown();
+}
@@ -0,0 +1,49 @@
+use lunatic::serializer::MessagePack;
+use lunatic::{distributed, Mailbox, Process, ProcessConfig};
+use lunatic_test::test;
+
+const PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+// Serializing the payload into memory first would exceed the memory limit.
+#[test]
+fn large_message_is_streamed(mailbox: Mailbox<Vec<u8>, MessagePack>) {
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_memory(128 * 1024 * 1024);
+    Process::spawn_link_config(&config, mailbox.this(), |parent, _: Mailbox<()>| {
+        parent.send(vec![0u8; PAYLOAD_SIZE]);
+    });
+    assert_eq!(mailbox.receive().len(), PAYLOAD_SIZE);
+}
+
+// The capture of a spawned process is decoded by the new process.
+#[test]
+fn large_capture_is_streamed(mailbox: Mailbox<Vec<u8>, MessagePack>) {
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_memory(128 * 1024 * 1024);
+    Process::spawn_link_config(
+        &config,
+        (mailbox.this(), vec![0u8; PAYLOAD_SIZE]),
+        |(parent, payload), _: Mailbox<()>| {
+            parent.send(payload);
+        },
+    );
+    assert_eq!(mailbox.receive().len(), PAYLOAD_SIZE);
+}
+
+#[test]
+fn large_message_is_streamed_to_another_node(mailbox: Mailbox<Vec<u8>, MessagePack>) {
+    let local = distributed::node_id();
+    // Only runs when the test is started as part of a cluster.
+    let Some(node) = distributed::nodes().into_iter().find(|node| *node != local) else {
+        return;
+    };
+    let echo = Process::spawn_node(
+        node,
+        mailbox.this(),
+        |parent, mailbox: Mailbox<Vec<u8>, MessagePack>| {
+            parent.send(mailbox.receive());
+        },
+    );
+    echo.send(vec![0u8; PAYLOAD_SIZE]);
+    assert_eq!(mailbox.receive().len(), PAYLOAD_SIZE);
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use log::LevelFilter;
+use lunatic::logger::{self, LogRecord};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn records_are_forwarded_to_sink(mailbox: Mailbox<LogRecord>) {
+    logger::init_with_sink(mailbox.this(), LevelFilter::Info).unwrap();
+    let before = lunatic::time::now();
+    log::info!("first");
+    log::debug!("filtered");
+    log::warn!("second");
+
+    let first = mailbox.receive();
+    assert_eq!(first.message, "first");
+    assert_eq!(first.process_id, lunatic::host::process_id());
+    assert_eq!(first.module_path.as_deref(), Some(module_path!()));
+    assert!(first.timestamp >= before && first.timestamp <= lunatic::time::now());
+    assert_eq!(mailbox.receive().message, "second");
+    assert!(mailbox
+        .receive_timeout(Duration::from_millis(10))
+        .is_timed_out());
+}
+
+#[test]
+fn children_inherit_logger(mailbox: Mailbox<LogRecord>) {
+    logger::init_with_sink(mailbox.this(), LevelFilter::Warn).unwrap();
+    let child = Process::spawn((), |_, _: Mailbox<()>| {
+        log::info!("filtered");
+        log::error!("from child");
+    });
+
+    let record = mailbox.receive();
+    assert_eq!(record.message, "from child");
+    assert_eq!(record.process_id, child.id());
+}
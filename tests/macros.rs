@@ -1,4 +1,5 @@
 use lunatic::protocol::End;
+use lunatic::serializer::MessagePack;
 use lunatic::{spawn, spawn_link, test, ProcessConfig};
 
 #[test]
@@ -70,6 +71,11 @@ fn spawn_link() {
     // Protocol, capture local_var
     let local_var = "Hello".to_owned();
     spawn_link!(|local_var, _proto: Protocol<End>| assert_eq!(local_var, "Hello"));
+    // Protocol with a custom serializer
+    spawn_link!(|_proto: Protocol<End, MessagePack>| {});
+    // Protocol with a custom serializer, capture local_var
+    let local_var = "Hello".to_owned();
+    spawn_link!(|local_var, _proto: Protocol<End, MessagePack>| assert_eq!(local_var, "Hello"));
 }
 
 #[test]
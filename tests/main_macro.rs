@@ -0,0 +1,39 @@
+// The `main` macro is expanded inside of a module, so that the tests can call
+// the generated function.
+mod app {
+    use lunatic::serializer::Bincode;
+    use lunatic::{Catching, Mailbox, MailboxResult, Process, Tag};
+    use lunatic_test::test;
+
+    #[lunatic::main]
+    fn main(mailbox: Mailbox<(), Bincode, Catching>) {
+        let tag = Tag::new();
+        Process::spawn_link_tag((), tag, |_, _: Mailbox<()>| panic!("child failed"));
+        match mailbox.receive() {
+            MailboxResult::LinkDied(died) => assert_eq!(died, tag),
+            _ => panic!("expected the death of the child"),
+        }
+        let panicked = lunatic::panic::link_panic(tag).unwrap();
+        assert_eq!(panicked.message(), Some("child failed"));
+    }
+
+    #[test]
+    fn main_survives_linked_panic() {
+        main();
+    }
+}
+
+mod fallible_app {
+    use lunatic::Mailbox;
+    use lunatic_test::test;
+
+    #[lunatic::main]
+    fn main(_: Mailbox<()>) -> Result<(), String> {
+        Ok(())
+    }
+
+    #[test]
+    fn main_returning_ok_doesnt_exit() {
+        main();
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use lunatic::memory::{self, MemoryPressure};
+use lunatic::{Mailbox, MailboxResult, Process, ProcessConfig};
+use lunatic_test::test;
+
+const LIMIT: u64 = 32 * 1024 * 1024;
+
+#[test]
+fn memory_limit_is_known_in_child(mailbox: Mailbox<Option<u64>>) {
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_memory(LIMIT);
+    Process::spawn_link_config(&config, mailbox.this(), |parent, _: Mailbox<()>| {
+        assert!(memory::used() > 0);
+        parent.send(memory::limit());
+    });
+    assert_eq!(mailbox.receive(), Some(LIMIT));
+}
+
+#[test]
+fn pressure_notification_before_limit(mailbox: Mailbox<MemoryPressure>) {
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_memory(LIMIT);
+    Process::spawn_link_config(
+        &config,
+        mailbox.this(),
+        |parent, mailbox: Mailbox<MemoryPressure>| {
+            assert!(memory::notify_on_pressure(mailbox.this(), 0.5));
+            let mut chunks = Vec::new();
+            loop {
+                chunks.push(vec![1u8; 1024 * 1024]);
+                if let MailboxResult::Message(pressure) = mailbox.receive_timeout(Duration::ZERO) {
+                    parent.send(pressure);
+                    break;
+                }
+            }
+        },
+    );
+    let pressure = mailbox.receive();
+    assert_eq!(pressure.limit, LIMIT);
+    assert!(pressure.used >= LIMIT / 2);
+    assert!(pressure.used < LIMIT);
+}
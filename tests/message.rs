@@ -0,0 +1,59 @@
+use lunatic::ap::{AbstractProcess, Config, ProcessRef};
+use lunatic::message::{Envelope, Hop};
+use lunatic::{abstract_process, Mailbox, Process, Tag};
+use lunatic_test::test;
+
+/// Correlation, hops and plan sent back by the planner.
+type Reply = (Tag, Vec<Hop>, u64);
+
+struct Planner;
+
+#[abstract_process]
+impl Planner {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(Planner)
+    }
+
+    #[handle_message]
+    fn plan(&self, envelope: Envelope<u64, Reply>) {
+        let correlation = envelope.correlation();
+        let hops = envelope.hops().to_vec();
+        let plan = *envelope.payload() + 1;
+        envelope.reply((correlation, hops, plan));
+    }
+}
+
+#[test]
+fn three_stage_pipeline(mailbox: Mailbox<Reply>) {
+    let planner = Planner::link().start(()).unwrap();
+    // Parses the request and forwards it to the planner.
+    let parser = Process::spawn_link(
+        planner,
+        |planner: ProcessRef<Planner>, mailbox: Mailbox<Envelope<String, Reply>>| {
+            let stage = Process::spawn_link(
+                planner,
+                |planner, mailbox: Mailbox<Envelope<u64, Reply>>| {
+                    planner.plan(mailbox.receive());
+                },
+            );
+            let envelope = mailbox.receive();
+            let query = envelope.payload().parse::<u64>().unwrap();
+            envelope.forward(&stage, query * 10);
+        },
+    );
+
+    let request = Envelope::request("4".to_owned());
+    let correlation = request.correlation();
+    parser.send(request);
+    let (received_correlation, hops, plan) = mailbox.tag_receive(&[correlation]);
+    assert_eq!(received_correlation, correlation);
+    assert_eq!(plan, 41);
+    assert_eq!(
+        hops,
+        vec![Hop {
+            node_id: parser.node_id(),
+            process_id: parser.id(),
+        }]
+    );
+}
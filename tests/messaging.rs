@@ -3,7 +3,7 @@ use std::time::Duration;
 use lunatic::ap::handlers::Request;
 use lunatic::ap::{AbstractProcess, Config, RequestHandler, State};
 use lunatic::serializer::Json;
-use lunatic::{spawn_link, Mailbox, Process};
+use lunatic::{spawn_link, Mailbox, MailboxError, Process};
 use lunatic_test::test;
 
 #[test]
@@ -114,6 +114,29 @@ fn timeout(mailbox: Mailbox<u64>) {
     assert!(result.is_timed_out())
 }
 
+#[test]
+fn timeout_into_result(mailbox: Mailbox<u64>) {
+    fn next(mailbox: Mailbox<u64>) -> Result<u64, MailboxError> {
+        let message = mailbox.receive_timeout(Duration::ZERO).into_result()?;
+        Ok(message + 1)
+    }
+
+    assert!(matches!(next(mailbox), Err(MailboxError::TimedOut)));
+    mailbox.this().send(1);
+    assert_eq!(next(mailbox).unwrap(), 2);
+}
+
+#[test]
+fn mailbox_result_combinators(mailbox: Mailbox<u64>) {
+    mailbox.this().send(2);
+    let doubled = mailbox
+        .receive_timeout(Duration::from_secs(1))
+        .map(|n| n * 2);
+    assert_eq!(doubled.expect_message("no message"), 4);
+    let result = mailbox.receive_timeout(Duration::ZERO).ok_or("gave up");
+    assert_eq!(result, Err("gave up"));
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct Proc(Process<i32>);
 
@@ -143,3 +166,19 @@ struct X {
     enb: E,
     enc: E,
 }
+
+#[test]
+fn priority_messages_first() {
+    let child = Process::spawn_link((), |_, mailbox: Mailbox<u32>| {
+        // Wait until all messages are queued.
+        lunatic::sleep(Duration::from_millis(100));
+        let received: Vec<u32> = (0..5).map(|_| mailbox.receive()).collect();
+        assert_eq!(received, vec![10, 11, 12, 1, 2]);
+    });
+    child.send(1);
+    child.send_priority(10);
+    child.send(2);
+    child.send_priority(11);
+    child.send_priority(12);
+    lunatic::sleep(Duration::from_millis(200));
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use lunatic::ap::{AbstractProcess, Config};
+use lunatic::metrics::{self, MessageStats};
+use lunatic::{abstract_process, Mailbox};
+use lunatic_test::test;
+
+#[test]
+fn mailbox_counters(mailbox: Mailbox<u32>) {
+    metrics::instrument_mailbox(true);
+    let this = mailbox.this();
+    this.send(1);
+    this.send(2);
+    mailbox.receive();
+    mailbox.receive();
+    let stats = metrics::mailbox_stats();
+    assert_eq!(stats["u32"].received, 2);
+}
+
+struct Counter(u32);
+
+#[abstract_process]
+impl Counter {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        metrics::instrument_mailbox(true);
+        Ok(Counter(0))
+    }
+
+    #[handle_message]
+    fn increment(&mut self) {
+        self.0 += 1;
+    }
+
+    #[handle_request]
+    fn stats(&self) -> HashMap<String, MessageStats> {
+        metrics::mailbox_stats()
+    }
+}
+
+fn received(stats: &HashMap<String, MessageStats>, handler: &str) -> u64 {
+    stats
+        .iter()
+        .find(|(name, _)| name.ends_with(handler))
+        .map_or(0, |(_, stats)| stats.received)
+}
+
+#[test]
+fn abstract_process_counters() {
+    let counter = Counter::link().start(()).unwrap();
+    counter.increment();
+    counter.increment();
+    let stats = counter.stats();
+    assert_eq!(received(&stats, "Increment"), 2);
+    // The request that returned the statistics is counted too.
+    assert_eq!(received(&stats, "Stats"), 1);
+
+    counter.increment();
+    let stats = counter.stats();
+    assert_eq!(received(&stats, "Increment"), 3);
+    assert_eq!(received(&stats, "Stats"), 2);
+}
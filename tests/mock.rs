@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+use lunatic::ap::handlers::{Message, Request};
+use lunatic::ap::mock::{Matcher, MockRef};
+use lunatic::ap::{AbstractProcess, Config, MessageHandler, ProcessRef, RequestHandler, State};
+use lunatic::panic::link_panic;
+use lunatic::serializer::Bincode;
+use lunatic::{abstract_process, Mailbox, MailboxResult};
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+// The dependency that is mocked, it's never started.
+struct Db(HashMap<String, u32>);
+
+#[derive(Serialize, Deserialize)]
+struct Load(String);
+
+#[derive(Serialize, Deserialize)]
+struct Store(String, u32);
+
+impl AbstractProcess for Db {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = ();
+    type Handlers = (Request<Load>, Message<Store>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(Db(HashMap::new()))
+    }
+}
+
+impl RequestHandler<Load> for Db {
+    type Response = Option<u32>;
+
+    fn handle(state: State<Self>, Load(key): Load) -> Self::Response {
+        state.0.get(&key).copied()
+    }
+}
+
+impl MessageHandler<Store> for Db {
+    fn handle(mut state: State<Self>, Store(key, value): Store) {
+        state.0.insert(key, value);
+    }
+}
+
+// The server under test, it caches the values of the database.
+struct Cache {
+    db: ProcessRef<Db>,
+    values: HashMap<String, Option<u32>>,
+}
+
+#[abstract_process]
+impl Cache {
+    #[init]
+    fn init(_: Config<Self>, db: ProcessRef<Db>) -> Result<Self, ()> {
+        Ok(Cache {
+            db,
+            values: HashMap::new(),
+        })
+    }
+
+    #[handle_request]
+    fn get(&mut self, key: String) -> Option<u32> {
+        let db = self.db;
+        *self
+            .values
+            .entry(key.clone())
+            .or_insert_with(|| db.request(Load(key)))
+    }
+
+    #[handle_message]
+    fn set(&mut self, key: String, value: u32) {
+        self.db.send(Store(key.clone(), value));
+        self.values.insert(key, Some(value));
+    }
+}
+
+#[test]
+fn cache_loads_missing_keys_once() {
+    let db = MockRef::<Db>::new()
+        .expect_request(Load("a".to_owned()), Some(1))
+        .expect_request(Load("b".to_owned()), None);
+    let cache = Cache::link().start(db.process_ref()).unwrap();
+    assert_eq!(cache.get("a".to_owned()), Some(1));
+    assert_eq!(cache.get("a".to_owned()), Some(1));
+    assert_eq!(cache.get("b".to_owned()), None);
+}
+
+#[test]
+fn cache_writes_through() {
+    let db = MockRef::<Db>::new()
+        .expect_cast(Store("a".to_owned(), 2))
+        .expect_cast(Matcher::<Store>::when(|Store(_, value): &Store| *value > 2));
+    let cache = Cache::link().start(db.process_ref()).unwrap();
+    cache.set("a".to_owned(), 2);
+    cache.set("b".to_owned(), 3);
+    // The write was cached.
+    assert_eq!(cache.get("a".to_owned()), Some(2));
+    db.verify();
+}
+
+#[test]
+#[should_panic(expected = "unmet expectations")]
+fn unmet_expectations_fail_the_test() {
+    let db = MockRef::<Db>::new().expect_request(Matcher::any(), Some(1));
+    let _cache = Cache::link().start(db.process_ref()).unwrap();
+}
+
+#[test]
+fn unexpected_calls_show_the_difference() {
+    let mailbox = unsafe { Mailbox::<()>::new() }.catch_link_failure();
+    let db = MockRef::<Db>::new().expect_request(Load("a".to_owned()), Some(1));
+    let result = db
+        .process_ref()
+        .with_timeout(Duration::from_millis(100))
+        .request(Load("b".to_owned()));
+    assert!(result.is_err());
+
+    let MailboxResult::LinkDied(tag) = mailbox.receive() else {
+        panic!("the mock didn't fail");
+    };
+    let panicked = link_panic(tag).unwrap();
+    let message = panicked.message().unwrap();
+    assert!(message.contains("expected: request `mock::Load` \"a\""));
+    assert!(message.contains("actual: request `mock::Load` \"b\""));
+    // The mock is gone, verifying it would fail too.
+    mem::forget(db);
+}
@@ -0,0 +1,39 @@
+use lunatic::panic::{catch_panic, inherit_hook, set_hook, CrashReport};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+fn report_crash(crash: &CrashReport) {
+    let collector = Process::<CrashReport>::lookup("panic_hook_collector").unwrap();
+    collector.send(crash.clone());
+}
+
+fn report_inherited_crash(crash: &CrashReport) {
+    let collector = Process::<CrashReport>::lookup("inherited_panic_hook_collector").unwrap();
+    collector.send(crash.clone());
+}
+
+#[test]
+fn hook_sends_crash_report(mailbox: Mailbox<CrashReport>) {
+    mailbox.this().register("panic_hook_collector");
+    let child = Process::spawn((), |_, _: Mailbox<()>| {
+        set_hook(report_crash);
+        // Caught panics don't terminate the process and are not reported.
+        assert!(catch_panic(|| panic!("caught")).is_err());
+        panic!("crashed");
+    });
+    let crash = mailbox.receive();
+    assert_eq!(crash.process_id, child.id());
+    assert_eq!(crash.panic.message(), Some("crashed"));
+}
+
+#[test]
+fn hook_is_inherited(mailbox: Mailbox<CrashReport>) {
+    mailbox.this().register("inherited_panic_hook_collector");
+    Process::spawn((), |_, _: Mailbox<()>| {
+        set_hook(report_inherited_crash);
+        inherit_hook(true);
+        Process::spawn((), |_, _: Mailbox<()>| panic!("child crashed"));
+    });
+    let crash = mailbox.receive();
+    assert_eq!(crash.panic.message(), Some("child crashed"));
+}
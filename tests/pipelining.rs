@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::Request;
+use lunatic::ap::{wait_all, AbstractProcess, Config, RequestHandler, State};
+use lunatic::serializer::Bincode;
+use lunatic::{random, sleep, Mailbox};
+use lunatic_test::test;
+
+/// Doubles numbers, taking a random amount of time for each of them.
+struct Doubler;
+
+impl AbstractProcess for Doubler {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = ();
+    type Handlers = (Request<u64>,);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(Doubler)
+    }
+}
+
+impl RequestHandler<u64> for Doubler {
+    type Response = u64;
+
+    fn handle(_: State<Self>, value: u64) -> u64 {
+        sleep(Duration::from_millis(random::u64() % 3));
+        value * 2
+    }
+}
+
+#[test]
+fn pipelined_requests() {
+    let doubler = Doubler::link().start(()).unwrap();
+    let pending: Vec<_> = (0..100).map(|i| doubler.request_async(i)).collect();
+    let results = wait_all(pending, None);
+    let expected: Vec<_> = (0..100u64).map(|i| Ok(i * 2)).collect();
+    assert_eq!(results, expected);
+}
+
+#[test]
+fn replies_waited_on_out_of_order() {
+    let doubler = Doubler::link().start(()).unwrap();
+    let mut pending: Vec<_> = (0..10).map(|i| (i, doubler.request_async(i))).collect();
+    // Wait on the last request first.
+    while let Some((i, reply)) = pending.pop() {
+        assert_eq!(reply.wait(None), Ok(i * 2));
+    }
+}
+
+#[test]
+fn unclaimed_replies_are_dropped(mailbox: Mailbox<u64>) {
+    let doubler = Doubler::link().start(()).unwrap();
+    // Neither of the replies is waited on.
+    drop(doubler.request_async(1u64));
+    let late = doubler.request_async(2u64);
+    let _ = late.wait(Some(Duration::ZERO));
+
+    mailbox.this().send(7);
+    sleep(Duration::from_millis(50));
+    // Only the message sent afterwards is received.
+    assert_eq!(mailbox.receive(), 7);
+    assert!(mailbox
+        .receive_timeout(Duration::from_millis(50))
+        .is_timed_out());
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::{Message, Request};
+use lunatic::ap::{AbstractProcess, Config, MessageHandler, RequestHandler, State};
+use lunatic::pool::{Pool, PoolError, Routing};
+use lunatic::serializer::Bincode;
+use lunatic::{sleep, test};
+
+struct Worker;
+
+impl AbstractProcess for Worker {
+    type Arg = u64;
+    type State = u64;
+    type Serializer = Bincode;
+    type Handlers = (Request<Double>, Request<Slow>, Message<Panic>, Request<Id>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, factor: u64) -> Result<u64, ()> {
+        Ok(factor)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Double(u64);
+impl RequestHandler<Double> for Worker {
+    type Response = u64;
+
+    fn handle(state: State<Self>, Double(value): Double) -> u64 {
+        value * *state
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Slow;
+impl RequestHandler<Slow> for Worker {
+    type Response = ();
+
+    fn handle(_: State<Self>, _: Slow) {
+        sleep(Duration::from_millis(100));
+        panic!("crashed while handling a request");
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Panic;
+impl MessageHandler<Panic> for Worker {
+    fn handle(_: State<Self>, _: Panic) {
+        panic!("worker panicked");
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Id;
+impl RequestHandler<Id> for Worker {
+    type Response = u64;
+
+    fn handle(_: State<Self>, _: Id) -> u64 {
+        lunatic::host::process_id()
+    }
+}
+
+#[test]
+fn requests_are_routed_round_robin() {
+    let pool = Pool::<Worker>::start(3, 2).unwrap();
+    assert_eq!(pool.request(Double(21)), Ok(42));
+    let ids: Vec<u64> = (0..6).map(|_| pool.request(Id).unwrap()).collect();
+    assert_ne!(ids[0], ids[1]);
+    assert_ne!(ids[1], ids[2]);
+    assert_eq!(&ids[0..3], &ids[3..6]);
+}
+
+#[test]
+fn dead_workers_are_restarted() {
+    let pool = Pool::<Worker>::start(1, 2).unwrap();
+    let before = pool.stats()[0].process_id;
+    pool.send(Panic);
+    sleep(Duration::from_millis(100));
+    let stats = pool.stats();
+    assert_eq!(stats[0].restarts, 1);
+    assert_ne!(stats[0].process_id, before);
+    assert_eq!(pool.request(Double(1)), Ok(2));
+}
+
+#[test]
+fn in_flight_request_fails_when_worker_dies() {
+    let pool = Pool::<Worker>::start_routing(2, Routing::LeastBusy, 2).unwrap();
+    assert_eq!(pool.request(Slow), Err(PoolError::WorkerDied));
+    assert_eq!(pool.request(Double(2)), Ok(4));
+}
+
+#[test]
+fn request_timeout() {
+    let pool = Pool::<Worker>::start(1, 2).unwrap();
+    assert_eq!(
+        pool.request_timeout(Slow, Duration::from_millis(10)),
+        Err(PoolError::Timeout)
+    );
+}
+
+#[test]
+fn resize() {
+    let pool = Pool::<Worker>::start(2, 2).unwrap();
+    pool.resize(5);
+    assert_eq!(pool.stats().len(), 5);
+    pool.resize(1);
+    assert_eq!(pool.stats().len(), 1);
+    pool.resize(0);
+    assert_eq!(pool.request(Double(1)), Err(PoolError::Empty));
+}
@@ -2,9 +2,16 @@ use std::time::Duration;
 
 use lunatic::host::api::message::receive;
 use lunatic::host::api::process::die_when_link_dies;
-use lunatic::{spawn_link, Mailbox, Process, ProcessConfig};
+use lunatic::serializer::Bincode;
+use lunatic::{host, spawn_link, Mailbox, Process, ProcessConfig, TypeMismatch};
 use lunatic_test::test;
 
+#[test]
+fn runtime_version() {
+    let (major, minor, _) = host::version();
+    assert!((major, minor) > (0, 0));
+}
+
 #[test]
 fn spawn_non_capturing_child() {
     Process::spawn((), |_, _: Mailbox<()>| {});
@@ -149,3 +156,24 @@ fn is_alive() {
     lunatic::sleep(Duration::from_millis(150));
     assert_eq!(child.is_alive(), false);
 }
+
+#[test]
+fn checked_cast_rejects_wrong_type(mailbox: Mailbox<Process<u64>>) {
+    let child = Process::spawn((), |_, _: Mailbox<String>| {});
+    // Send a `Process<String>` to a mailbox that expects a `Process<u64>`.
+    let this = mailbox.this();
+    let this = unsafe { Process::<Process<String>>::from_id(this.node_id(), this.id()) };
+    this.send(child);
+    let wrong = mailbox.receive();
+
+    assert_eq!(
+        wrong.checked_cast::<u64, Bincode>(),
+        Err(TypeMismatch {
+            expected: std::any::type_name::<Process<u64, Bincode>>()
+        })
+    );
+    assert_eq!(wrong.checked_cast::<String, Bincode>(), Ok(child));
+    // The unchecked path doesn't look at the fingerprint.
+    let unchecked = unsafe { Process::<u64>::from_id(child.node_id(), child.id()) };
+    assert_eq!(unchecked.checked_cast::<u64, Bincode>(), Ok(unchecked));
+}
@@ -1,4 +1,6 @@
-use lunatic::Process;
+use std::time::Duration;
+
+use lunatic::{Mailbox, Process};
 use lunatic_test::test;
 
 #[test]
@@ -73,3 +75,198 @@ fn recursive_protocols() {
 
     let _end = loop_protocol.select_right();
 }
+
+#[cfg(feature = "json_serializer")]
+#[test]
+fn json_serializer_with_choice() {
+    use lunatic::protocol::Branch;
+    use lunatic::protocol::End;
+    use lunatic::protocol::Offer;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::Recv;
+    use lunatic::protocol::Send;
+    use lunatic::serializer::Json;
+    type P = Offer<Recv<String, Send<String, End>>, End>;
+
+    let protocol = Process::spawn_link(
+        "prefix: ".to_owned(),
+        |prefix, proto: Protocol<P, Json>| match proto.offer() {
+            Branch::Left(proto) => {
+                let (proto, input) = proto.receive();
+                let _ = proto.send(format!("{}{}", prefix, input));
+            }
+            Branch::Right(_end) => panic!("expected the left branch"),
+        },
+    );
+
+    let protocol = protocol.select_left();
+    let protocol = protocol.send("hello".to_owned());
+    let (_, result) = protocol.receive();
+    assert_eq!(result, "prefix: hello");
+}
+
+#[test]
+fn drop_mid_session_notifies_peer() {
+    use lunatic::protocol::End;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::Send;
+    let protocol = Process::spawn_link((), |_, _: Protocol<Send<i32, End>>| {
+        // Protocol dropped without sending a message back.
+    });
+    match protocol.try_receive() {
+        Ok(_) => panic!("expected the session to be aborted"),
+        Err(aborted) => assert!(aborted.step.contains("Send")),
+    }
+}
+
+#[test]
+fn explicit_abort_notifies_peer() {
+    use lunatic::protocol::End;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::Send;
+    let protocol = Process::spawn_link((), |_, proto: Protocol<Send<i32, End>>| {
+        proto.abort("nothing to send");
+    });
+    match protocol.try_receive() {
+        Ok(_) => panic!("expected the session to be aborted"),
+        Err(aborted) => assert_eq!(aborted.reason, "nothing to send"),
+    }
+}
+
+#[test]
+fn drop_mid_session_from_parent(mailbox: Mailbox<String>) {
+    use lunatic::protocol::End;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::Recv;
+    let protocol = Process::spawn_link(
+        mailbox.this(),
+        |parent, proto: Protocol<Recv<i32, End>>| match proto.try_receive() {
+            Ok(_) => parent.send("received".to_owned()),
+            Err(aborted) => parent.send(aborted.step),
+        },
+    );
+    drop(protocol);
+    assert!(mailbox.receive().contains("Send"));
+}
+
+#[test]
+fn explicit_abort_from_parent(mailbox: Mailbox<String>) {
+    use lunatic::protocol::End;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::Recv;
+    let protocol = Process::spawn_link(
+        mailbox.this(),
+        |parent, proto: Protocol<Recv<i32, End>>| match proto.try_receive() {
+            Ok(_) => parent.send("received".to_owned()),
+            Err(aborted) => parent.send(aborted.reason),
+        },
+    );
+    protocol.abort("not needed anymore");
+    assert_eq!(mailbox.receive(), "not needed anymore");
+}
+
+#[test]
+#[should_panic(expected = "aborted")]
+fn receive_panics_on_abort() {
+    use lunatic::protocol::End;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::Send;
+    let protocol = Process::spawn_link((), |_, proto: Protocol<Send<i32, End>>| {
+        proto.abort("failed");
+    });
+    let _ = protocol.receive();
+}
+
+#[test]
+fn receive_or_mailbox(mailbox: Mailbox<String>) {
+    use lunatic::protocol::End;
+    use lunatic::protocol::Protocol;
+    use lunatic::protocol::ProtocolOrMailbox;
+    use lunatic::protocol::Send;
+    let protocol =
+        Process::spawn_link(mailbox.this(), |parent, proto: Protocol<Send<i32, End>>| {
+            parent.send("reload".to_owned());
+            let _ = proto.send(42);
+        });
+
+    let protocol = match protocol.receive_or_mailbox(&mailbox) {
+        ProtocolOrMailbox::MailboxMsg(protocol, message) => {
+            assert_eq!(message, "reload");
+            protocol
+        }
+        ProtocolOrMailbox::ProtocolMsg(..) => panic!("expected the mailbox message first"),
+    };
+    match protocol.receive_or_mailbox(&mailbox) {
+        ProtocolOrMailbox::ProtocolMsg(_, value) => assert_eq!(value, 42),
+        ProtocolOrMailbox::MailboxMsg(..) => panic!("expected the protocol message"),
+    }
+}
+
+type Doubling = lunatic::protocol::Send<
+    u64,
+    lunatic::protocol::Recv<
+        u64,
+        lunatic::protocol::Send<u64, lunatic::protocol::Recv<u64, lunatic::protocol::End>>,
+    >,
+>;
+
+#[test]
+fn listener_accepts_interleaved_sessions(mailbox: Mailbox<(u64, u64, u64)>) {
+    use lunatic::protocol::{self, SessionListener};
+
+    let listener = SessionListener::<Doubling>::new();
+    for client in 1..=3 {
+        Process::spawn(
+            (listener.server(), mailbox.this(), client),
+            |(server, parent, client), _: Mailbox<()>| {
+                let session = protocol::connect(&server).unwrap();
+                let (session, first) = session.send(client).receive();
+                let (_, second) = session.send(client + 100).receive();
+                parent.send((client, first, second));
+            },
+        );
+    }
+
+    // Each step is taken on all sessions before the next one, in a different
+    // order than the sessions were accepted.
+    let sessions: Vec<_> = (0..3).map(|_| listener.accept()).collect();
+    let sessions: Vec<_> = sessions
+        .into_iter()
+        .rev()
+        .map(|session| {
+            let (session, value) = session.receive();
+            session.send(value * 2)
+        })
+        .collect();
+    for session in sessions {
+        let (session, value) = session.receive();
+        let _ = session.send(value * 2);
+    }
+
+    let mut results: Vec<_> = (0..3).map(|_| mailbox.receive()).collect();
+    results.sort();
+    assert_eq!(results, [(1, 2, 202), (2, 4, 204), (3, 6, 206)]);
+}
+
+#[test]
+fn listener_rejects_other_protocols(mailbox: Mailbox<String>) {
+    use lunatic::protocol::{self, ConnectError, End, SessionListener, SessionServer};
+
+    let listener = SessionListener::<Doubling>::new();
+    let client = Process::spawn(
+        mailbox.this(),
+        |parent, mailbox: Mailbox<SessionServer<End>>| match protocol::connect(&mailbox.receive()) {
+            Err(ConnectError::Rejected(reason)) => parent.send(reason),
+            _ => parent.send("connected".to_owned()),
+        },
+    );
+    // The handle has the same layout for all protocols.
+    let client =
+        unsafe { Process::<SessionServer<Doubling>>::from_id(client.node_id(), client.id()) };
+    client.send(listener.server());
+
+    assert!(listener
+        .accept_timeout(Duration::from_millis(500))
+        .is_none());
+    assert!(mailbox.receive().contains("expected protocol"));
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use lunatic::pubsub::{Policy, SubscriptionError, Topic};
+use lunatic::{sleep, Mailbox, MailboxResult, Process, Tag};
+use lunatic_test::test;
+
+#[test]
+fn publish_to_subscriber() {
+    let topic = Topic::<u64>::new("publish_to_subscriber");
+    let subscription = topic.subscribe();
+    topic.publish(1);
+    topic.publish(2);
+    assert_eq!(subscription.receive(), 1);
+    assert_eq!(subscription.receive(), 2);
+}
+
+#[test]
+fn topics_are_looked_up_by_name() {
+    let topic = Topic::<u64>::new("topics_are_looked_up_by_name");
+    let subscription = topic.subscribe();
+    Topic::<u64>::new("topics_are_looked_up_by_name").publish(3);
+    assert_eq!(subscription.receive(), 3);
+}
+
+#[test]
+fn unsubscribe_on_drop() {
+    let topic = Topic::<u64>::new("unsubscribe_on_drop");
+    let subscription = topic.subscribe();
+    let tag = subscription.tag();
+    drop(subscription);
+    topic.publish(1);
+    let mailbox = unsafe { Mailbox::<u64>::new() };
+    assert!(matches!(
+        mailbox.tag_receive_timeout(&[tag], Duration::from_millis(50)),
+        MailboxResult::TimedOut
+    ));
+}
+
+#[test]
+fn drop_oldest_for_slow_subscribers() {
+    let topic = Topic::<u64>::with_policy("drop_oldest", Policy::DropOldest(2));
+    let subscription = topic.subscribe();
+    for i in 0..10 {
+        topic.publish(i);
+    }
+    sleep(Duration::from_millis(50));
+    // Two messages are delivered right away and the last two are buffered.
+    let received: Vec<u64> = (0..4).map(|_| subscription.receive()).collect();
+    assert_eq!(received, vec![0, 1, 8, 9]);
+    assert!(matches!(
+        subscription.receive_timeout(Duration::from_millis(50)),
+        MailboxResult::TimedOut
+    ));
+}
+
+#[test]
+fn drop_newest_for_slow_subscribers() {
+    let topic = Topic::<u64>::new("drop_newest");
+    let subscription = topic.subscribe_with_policy(Policy::DropNewest(2));
+    for i in 0..10 {
+        topic.publish(i);
+    }
+    sleep(Duration::from_millis(50));
+    // Two messages are delivered right away and the next two are buffered.
+    let received: Vec<u64> = (0..4).map(|_| subscription.receive()).collect();
+    assert_eq!(received, vec![0, 1, 2, 3]);
+    assert!(matches!(
+        subscription.receive_timeout(Duration::from_millis(50)),
+        MailboxResult::TimedOut
+    ));
+}
+
+#[test]
+fn disconnect_slow_subscribers() {
+    let topic = Topic::<u64>::new("disconnect");
+    let subscription = topic.subscribe_with_policy(Policy::Disconnect(2));
+    let other = topic.subscribe();
+    for i in 0..5 {
+        topic.publish(i);
+    }
+    sleep(Duration::from_millis(50));
+    assert!(subscription.is_disconnected());
+    // Messages delivered before the disconnect are still received.
+    let timeout = Some(Duration::from_millis(50));
+    assert_eq!(subscription.try_receive(timeout).unwrap(), 0);
+    assert_eq!(subscription.try_receive(timeout).unwrap(), 1);
+    assert!(matches!(
+        subscription.try_receive(timeout),
+        Err(SubscriptionError::Disconnected)
+    ));
+    // The other subscriber isn't affected.
+    let received: Vec<u64> = (0..5).map(|_| other.receive()).collect();
+    assert_eq!(received, vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn slow_subscribers_block_the_publisher(mailbox: Mailbox<usize>) {
+    let topic = Topic::<u64>::new("block");
+    let subscription = topic.subscribe_with_policy(Policy::Block(1));
+    let tag = Tag::new();
+    Process::spawn(
+        (mailbox.this(), tag, topic),
+        |(parent, tag, topic), _: Mailbox<()>| {
+            for i in 0..3 {
+                parent.tag_send(tag, topic.publish_confirmed(i));
+            }
+        },
+    );
+    assert_eq!(mailbox.tag_receive(&[tag]), 1);
+    // The second message waits on the subscriber to take the first one.
+    assert!(matches!(
+        mailbox.tag_receive_timeout(&[tag], Duration::from_millis(100)),
+        MailboxResult::TimedOut
+    ));
+    for i in 0..3 {
+        assert_eq!(subscription.receive(), i);
+        if i < 2 {
+            assert_eq!(mailbox.tag_receive(&[tag]), 1);
+        }
+    }
+}
+
+#[test]
+fn acknowledged_messages_are_decoded() {
+    for (name, policy) in [
+        ("acknowledged_drop_oldest", Policy::DropOldest(1)),
+        ("acknowledged_block", Policy::Block(1)),
+    ] {
+        let topic = Topic::<String>::new(name);
+        let subscription = topic.subscribe_with_policy(policy);
+        topic.publish("first".to_owned());
+        topic.publish("second".to_owned());
+        assert_eq!(subscription.receive(), "first");
+        assert_eq!(subscription.receive(), "second");
+    }
+}
+
+#[test]
+fn publish_confirmed_counts_receivers() {
+    let topic = Topic::<u64>::new("publish_confirmed");
+    assert_eq!(topic.publish_confirmed(1), 0);
+    let fast = topic.subscribe();
+    let slow = topic.subscribe_with_policy(Policy::DropNewest(0));
+    assert_eq!(topic.publish_confirmed(2), 1);
+    assert_eq!(fast.receive(), 2);
+    assert!(matches!(
+        slow.try_receive(Some(Duration::from_millis(50))),
+        Err(SubscriptionError::TimedOut)
+    ));
+}
+
+#[test]
+fn thousand_subscribers(mailbox: Mailbox<u64>) {
+    const SUBSCRIBERS: u64 = 1000;
+    const MESSAGES: u64 = 10;
+
+    let topic = Topic::<u64>::new("thousand_subscribers");
+    for _ in 0..SUBSCRIBERS {
+        Process::spawn(
+            (mailbox.this(), topic),
+            |(parent, topic), _: Mailbox<()>| {
+                let subscription = topic.subscribe();
+                parent.send(0);
+                let sum: u64 = (0..MESSAGES).map(|_| subscription.receive()).sum();
+                parent.send(sum);
+            },
+        );
+    }
+    for _ in 0..SUBSCRIBERS {
+        assert_eq!(mailbox.receive(), 0);
+    }
+    for i in 1..=MESSAGES {
+        topic.publish(i);
+    }
+    let expected = MESSAGES * (MESSAGES + 1) / 2;
+    for _ in 0..SUBSCRIBERS {
+        assert_eq!(mailbox.receive(), expected);
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashSet;
+
+use lunatic::random::{self, Uuid};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn uuid_format() {
+    let uuid = random::uuid_v4();
+    assert_eq!(uuid.version(), 4);
+    assert_eq!(uuid.as_bytes()[8] & 0xc0, 0x80);
+    let text = uuid.to_string();
+    assert_eq!(text.len(), 36);
+    assert_eq!(text.matches('-').count(), 4);
+    assert_eq!(&text[14..15], "4");
+}
+
+#[test]
+fn no_repeats_across_processes(mailbox: Mailbox<Vec<Uuid>>) {
+    for _ in 0..10 {
+        Process::spawn(mailbox.this(), |parent, _: Mailbox<()>| {
+            parent.send((0..100).map(|_| random::uuid_v4()).collect());
+        });
+    }
+    let mut uuids = HashSet::new();
+    for _ in 0..10 {
+        uuids.extend(mailbox.receive());
+    }
+    assert_eq!(uuids.len(), 1000);
+}
+
+#[test]
+fn bits_are_balanced() {
+    let samples = 10_000;
+    let ones: u32 = (0..samples).map(|_| random::u64().count_ones()).sum();
+    let mean = ones as f64 / samples as f64;
+    // The expected mean is 32 with a standard error of 0.04.
+    assert!((31.5..32.5).contains(&mean), "mean of set bits: {mean}");
+}
+
+#[test]
+fn fill_bytes_covers_buffer() {
+    let mut buffer = [0u8; 1024];
+    random::fill_bytes(&mut buffer);
+    let distinct: HashSet<u8> = buffer.iter().copied().collect();
+    assert!(distinct.len() > 200);
+}
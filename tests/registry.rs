@@ -1,4 +1,4 @@
-use lunatic::{host, sleep};
+use lunatic::{host, registry, sleep};
 use lunatic_test::test;
 
 #[test]
@@ -68,3 +68,20 @@ fn registry_failed_process_release_lock() {
         host::api::registry::get(name.as_ptr(), name.len(), &mut node_id, &mut process_id);
     }
 }
+
+#[test]
+fn registry_put_get_remove() {
+    assert_eq!(registry::get("safe"), None);
+    registry::put("safe", 1, 2);
+    assert_eq!(registry::get("safe"), Some((1, 2)));
+    registry::remove("safe");
+    assert_eq!(registry::get("safe"), None);
+}
+
+#[test]
+fn registry_get_or_put_later_then_put() {
+    // Locks the name until it's put.
+    assert_eq!(registry::get_or_put_later("safe_later"), None);
+    registry::put("safe_later", 3, 4);
+    assert_eq!(registry::get_or_put_later("safe_later"), Some((3, 4)));
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use lunatic::function::Request;
+use lunatic::time::Timeout;
+use lunatic::{sleep, Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn request_response() {
+    let doubler = Process::spawn((), |_, mailbox: Mailbox<Request<u64, u64>>| loop {
+        let request = mailbox.receive();
+        let value = *request.message();
+        request.reply(value * 2);
+    });
+    assert_eq!(doubler.request(21, None), Ok(42));
+    assert_eq!(doubler.request(1, None), Ok(2));
+}
+
+#[test]
+fn request_timeout() {
+    let silent = Process::spawn((), |_, mailbox: Mailbox<Request<u64, u64>>| loop {
+        let _ = mailbox.receive();
+    });
+    assert_eq!(
+        silent.request(1, Some(Duration::from_millis(10))),
+        Err(Timeout)
+    );
+}
+
+#[test]
+fn late_reply_is_not_confused_with_later_request() {
+    let slow = Process::spawn((), |_, mailbox: Mailbox<Request<u64, u64>>| loop {
+        let request = mailbox.receive();
+        let value = *request.message();
+        if value == 1 {
+            sleep(Duration::from_millis(100));
+        }
+        request.reply(value);
+    });
+    assert_eq!(
+        slow.request(1, Some(Duration::from_millis(10))),
+        Err(Timeout)
+    );
+    // The response to the first request arrives while waiting on this one.
+    assert_eq!(slow.request(2, None), Ok(2));
+}
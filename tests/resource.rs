@@ -0,0 +1,99 @@
+use std::mem;
+use std::time::Duration;
+
+use lunatic::net::{TcpListener, TcpStream};
+use lunatic::resource::{self, ResourceWarning};
+use lunatic::sqlite::Connection;
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn counts_by_kind() {
+    assert_eq!(resource::resource_count(), 0);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let clone = stream.clone();
+
+    let counts = resource::resource_counts();
+    assert_eq!(counts["TcpListener"], 1);
+    assert_eq!(counts["TcpStream"], 3);
+    assert_eq!(resource::resource_count(), 4);
+
+    drop((client, stream, clone));
+    assert!(!resource::resource_counts().contains_key("TcpStream"));
+    drop(listener);
+    assert_eq!(resource::resource_count(), 0);
+}
+
+#[test]
+fn sent_streams_are_counted_by_the_receiver(mailbox: Mailbox<usize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    Process::spawn(
+        (mailbox.this(), client),
+        |(parent, _stream), _: Mailbox<()>| {
+            parent.send(resource::resource_count());
+        },
+    );
+    assert_eq!(mailbox.receive(), 1);
+    assert_eq!(resource::resource_count(), 1);
+}
+
+#[test]
+fn query_results_are_released() {
+    let conn = Connection::open(":memory:").unwrap();
+    conn.execute("CREATE TABLE t (n INTEGER); INSERT INTO t VALUES (1), (2), (3);")
+        .unwrap();
+    for _ in 0..10 {
+        assert_eq!(conn.query("SELECT n FROM t").unwrap().len(), 3);
+    }
+    assert!(!resource::resource_counts().contains_key("SqliteQueryResult"));
+    let mut stmt = conn.prepare("SELECT n FROM t ORDER BY n").unwrap();
+    stmt.query().next().unwrap().unwrap();
+    assert_eq!(resource::resource_counts()["SqliteStatement"], 1);
+    // The statement is finalized once it's dropped.
+    drop(stmt);
+    assert!(!resource::resource_counts().contains_key("SqliteStatement"));
+}
+
+#[test]
+fn report_points_at_leaks() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    for _ in 0..3 {
+        mem::forget(TcpStream::connect(addr).unwrap());
+    }
+
+    let report = resource::resource_report();
+    assert_eq!(report.counts["TcpStream"], 3);
+    let leaks: Vec<_> = report.origins_of("TcpStream").collect();
+    assert_eq!(leaks.len(), 3);
+    assert!(leaks
+        .iter()
+        .all(|origin| origin.location.file().ends_with("resource.rs")));
+    assert!(report.to_string().contains("TcpStream: 3"));
+}
+
+#[test]
+fn warns_once_above_threshold(mailbox: Mailbox<ResourceWarning>) {
+    resource::set_warning_receiver(Some(mailbox.this()));
+    resource::set_warning_threshold(Some(2));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let streams: Vec<_> = (0..4).map(|_| TcpStream::connect(addr).unwrap()).collect();
+
+    let warning = mailbox.receive();
+    assert_eq!(warning.threshold, 2);
+    assert_eq!(warning.counts["TcpListener"], 1);
+    assert_eq!(warning.counts["TcpStream"], 2);
+    // Staying above the threshold doesn't warn again.
+    assert!(mailbox
+        .receive_timeout(Duration::from_millis(50))
+        .is_timed_out());
+
+    // Dropping back to the threshold warns with the next crossing.
+    drop(streams);
+    let _streams: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    assert_eq!(mailbox.receive().counts["TcpStream"], 2);
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::{Message, Request};
+use lunatic::ap::{AbstractProcess, Config, MessageHandler, RequestHandler, State};
+use lunatic::routing::{ShardError, ShardRouter};
+use lunatic::serializer::Bincode;
+use lunatic::{sleep, test};
+
+// Counts the messages it received per key.
+struct Counter;
+
+impl AbstractProcess for Counter {
+    type Arg = ();
+    type State = Vec<String>;
+    type Serializer = Bincode;
+    type Handlers = (Message<Add>, Request<Count>, Message<Crash>, Request<Fail>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Vec<String>, ()> {
+        Ok(Vec::new())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Add(String);
+impl MessageHandler<Add> for Counter {
+    fn handle(mut state: State<Self>, Add(key): Add) {
+        state.push(key);
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Count(String);
+impl RequestHandler<Count> for Counter {
+    type Response = usize;
+
+    fn handle(state: State<Self>, Count(key): Count) -> usize {
+        state.iter().filter(|k| **k == key).count()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Crash;
+impl MessageHandler<Crash> for Counter {
+    fn handle(_: State<Self>, _: Crash) {
+        panic!("shard crashed");
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Fail;
+impl RequestHandler<Fail> for Counter {
+    type Response = ();
+
+    fn handle(_: State<Self>, _: Fail) {
+        panic!("crashed while handling a request");
+    }
+}
+
+fn keys() -> Vec<String> {
+    (0..50).map(|i| format!("user-{i}")).collect()
+}
+
+#[test]
+fn keys_stick_to_their_shard() {
+    let router = ShardRouter::<String, Counter>::start(4, ()).unwrap();
+    for key in keys() {
+        for _ in 0..3 {
+            router.cast(&key, Add(key.clone()));
+        }
+    }
+    for key in keys() {
+        assert_eq!(router.request(&key, Count(key.clone())), Ok(3));
+    }
+    let stats = router.stats();
+    assert_eq!(stats.len(), 4);
+    assert_eq!(stats.iter().map(|shard| shard.routed).sum::<u64>(), 200);
+    // The ring spreads the keys over all shards.
+    assert!(stats.iter().all(|shard| shard.routed > 0));
+    assert!(stats.iter().all(|shard| shard.in_flight == 0));
+}
+
+#[test]
+fn dead_shard_owner_is_restarted() {
+    let router = ShardRouter::<String, Counter>::start(4, ()).unwrap();
+    let keys = keys();
+    let (shard, before) = router.owner(&keys[0]).unwrap();
+    let shard_keys: Vec<&String> = keys
+        .iter()
+        .filter(|key| router.owner(key).unwrap().0 == shard)
+        .collect();
+
+    for (i, key) in shard_keys.iter().enumerate() {
+        if i == shard_keys.len() / 2 {
+            router.cast(key, Crash);
+            sleep(Duration::from_millis(50));
+        }
+        router.cast(key, Add(key.to_string()));
+        assert_eq!(router.request(key, Count(key.to_string())), Ok(1));
+    }
+    // The state of the keys before the crash was lost with the worker.
+    if shard_keys.len() > 1 {
+        assert_eq!(router.request(&keys[0], Count(keys[0].clone())), Ok(0));
+    }
+
+    let (after_shard, after) = router.owner(&keys[0]).unwrap();
+    assert_eq!(after_shard, shard);
+    assert_ne!(after.id(), before.id());
+    assert_eq!(router.stats()[shard].restarts, 1);
+}
+
+#[test]
+fn in_flight_request_fails_when_worker_dies() {
+    let router = ShardRouter::<u32, Counter>::start(2, ()).unwrap();
+    assert_eq!(router.request(&7, Fail), Err(ShardError::WorkerDied));
+    assert_eq!(router.request(&7, Count("7".to_owned())), Ok(0));
+}
+
+#[test]
+fn resize_moves_few_keys() {
+    let router = ShardRouter::<String, Counter>::start(4, ()).unwrap();
+    let keys = keys();
+    let before: Vec<usize> = keys
+        .iter()
+        .map(|key| router.owner(key).unwrap().0)
+        .collect();
+
+    router.resize(5);
+    let after: Vec<usize> = keys
+        .iter()
+        .map(|key| router.owner(key).unwrap().0)
+        .collect();
+    // Keys either stay or move to the new shard.
+    for (before, after) in before.iter().zip(after.iter()) {
+        assert!(before == after || *after == 4);
+    }
+    assert!(after.iter().filter(|&&shard| shard == 4).count() < keys.len() / 2);
+
+    router.resize(4);
+    let shrunk: Vec<usize> = keys
+        .iter()
+        .map(|key| router.owner(key).unwrap().0)
+        .collect();
+    assert_eq!(shrunk, before);
+
+    router.resize(0);
+    assert_eq!(
+        router.request(&keys[0], Count(keys[0].clone())),
+        Err(ShardError::Empty)
+    );
+}
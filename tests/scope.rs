@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+use lunatic::scope::{scope, scope_with_deadline, ScopeError};
+use lunatic::sleep;
+use lunatic_test::test;
+
+#[test]
+fn results_in_completion_order() {
+    let results = scope(|s| {
+        s.spawn(200u64, |delay| {
+            sleep(Duration::from_millis(delay));
+            delay
+        });
+        s.spawn(10u64, |delay| {
+            sleep(Duration::from_millis(delay));
+            delay
+        });
+        let mut results = Vec::new();
+        while let Some(result) = s.next() {
+            results.push(result.unwrap());
+        }
+        results
+    });
+    assert_eq!(results, vec![10, 200]);
+}
+
+#[test]
+fn failures_are_reported() {
+    scope(|s| {
+        s.spawn((), |_| -> u32 { panic!("scoped failure") });
+        assert_eq!(s.next(), Some(Err(ScopeError::Failed)));
+        assert_eq!(s.next(), None);
+    });
+}
+
+#[test]
+fn scope_waits_for_children() {
+    let start = Instant::now();
+    scope(|s| {
+        s.spawn((), |_| sleep(Duration::from_millis(100)));
+    });
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn deadline_kills_stragglers() {
+    let start = Instant::now();
+    scope_with_deadline(Duration::from_millis(50), |s| {
+        s.spawn((), |_| sleep(Duration::from_secs(10)));
+    });
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
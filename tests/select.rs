@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use lunatic::protocol::{End, Send};
+use lunatic::{select, spawn_link, Mailbox, Tag};
+use lunatic_test::test;
+
+#[test]
+fn first_tag_wins(mailbox: Mailbox<u64>) {
+    let first = Tag::new();
+    let second = Tag::new();
+    let this = mailbox.this();
+    this.tag_send(second, 2);
+    this.tag_send(first, 1);
+
+    let received = select! {
+        value = mailbox.tag_receive(&[first]) => value * 10,
+        value = mailbox.tag_receive(&[second]) => value * 100,
+    };
+    assert_eq!(received, 200);
+    // The other message stays in the mailbox.
+    assert_eq!(mailbox.tag_receive(&[first]), 1);
+}
+
+#[test]
+fn untouched_messages(mailbox: Mailbox<u64>) {
+    let tag = Tag::new();
+    let this = mailbox.this();
+    this.send(1);
+    this.tag_send(tag, 2);
+
+    select! {
+        value = mailbox.tag_receive(&[tag]) => assert_eq!(value, 2),
+    }
+    assert_eq!(mailbox.receive(), 1);
+}
+
+#[test]
+fn timeout_arm(mailbox: Mailbox<u64>) {
+    let tag = Tag::new();
+    let timed_out = select! {
+        _ = mailbox.tag_receive(&[tag]) => false,
+        after Duration::from_millis(10) => true,
+    };
+    assert!(timed_out);
+}
+
+#[test]
+fn protocol_arm(mailbox: Mailbox<u64>) {
+    let protocol = spawn_link!(|protocol: Protocol<Send<u64, End>>| {
+        let _ = protocol.send(42);
+    });
+    let tag = Tag::new();
+    select! {
+        _ = mailbox.tag_receive(&[tag]) => panic!("no message was sent to the mailbox"),
+        (_protocol, value) = protocol.receive() => assert_eq!(value, 42),
+    }
+}
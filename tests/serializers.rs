@@ -1,6 +1,13 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use lunatic::net::TcpStream;
-use lunatic::serializer::{Bincode, Json, MessagePack};
-use lunatic::{test, Mailbox, Process};
+use lunatic::serializer::{
+    self, Bincode, CanSerialize, DecodeError, EncodeError, ExternalError, Json, MessagePack,
+    MessageVersion, Versioned,
+};
+use lunatic::{test, Mailbox, MailboxResult, Process};
+use serde::{Deserialize, Serialize};
 
 #[test]
 fn bincode_resource_serialization() {
@@ -19,3 +26,216 @@ fn msgpack_resource_serialization() {
     let stream = TcpStream::connect("google.com:80").unwrap();
     Process::spawn(stream, |_, _: Mailbox<(), MessagePack>| {});
 }
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct UserV1 {
+    name: String,
+}
+
+impl MessageVersion for UserV1 {
+    const VERSION: u8 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct User {
+    name: String,
+    age: Option<u8>,
+}
+
+impl MessageVersion for User {
+    const VERSION: u8 = 2;
+
+    fn upgrade(version: u8, data: &[u8]) -> Result<Self, DecodeError> {
+        match version {
+            1 => {
+                let user: UserV1 = bincode::deserialize(data)?;
+                Ok(User {
+                    name: user.name,
+                    age: None,
+                })
+            }
+            _ => Err(DecodeError::UnknownVersion(version)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserV3 {
+    name: String,
+}
+
+impl MessageVersion for UserV3 {
+    const VERSION: u8 = 3;
+}
+
+// Returns a handle to the same process that sends messages of another type.
+fn retype<M, N>(process: Process<M, Versioned<Bincode>>) -> Process<N, Versioned<Bincode>> {
+    bincode::deserialize(&bincode::serialize(&process).unwrap()).unwrap()
+}
+
+#[test]
+fn versioned_upgrade(mailbox: Mailbox<String>) {
+    // The capture isn't versioned, so it uses a plain serializer.
+    let child = Process::spawn_with_serializer::<Bincode, _>(
+        (mailbox.this(),),
+        |(parent,), mailbox: Mailbox<User, Versioned<Bincode>>| loop {
+            let result = match mailbox.try_receive(Duration::from_secs(1)) {
+                MailboxResult::Message(user) => format!("{:?}", user),
+                MailboxResult::DeserializationFailed(err) => err.to_string(),
+                _ => "timeout".to_owned(),
+            };
+            parent.send(result);
+        },
+    );
+    child.send(User {
+        name: "new".to_owned(),
+        age: Some(1),
+    });
+    retype::<_, UserV1>(child).send(UserV1 {
+        name: "old".to_owned(),
+    });
+    retype::<_, UserV3>(child).send(UserV3 {
+        name: "newer".to_owned(),
+    });
+    assert_eq!(mailbox.receive(), r#"User { name: "new", age: Some(1) }"#);
+    assert_eq!(mailbox.receive(), r#"User { name: "old", age: None }"#);
+    assert_eq!(mailbox.receive(), "unknown message version: 3");
+}
+
+// Sends messages of sizes around the limit of messages kept on the stack
+// through an echo process.
+macro_rules! round_trip_sizes {
+    ($name:ident, $serializer:ty) => {
+        #[test]
+        fn $name(mailbox: Mailbox<Vec<u8>, $serializer>) {
+            let echo = Process::spawn_link(
+                mailbox.this(),
+                |parent, mailbox: Mailbox<Vec<u8>, $serializer>| loop {
+                    parent.send(mailbox.receive());
+                },
+            );
+            for len in [0, 1, 100, 120, 127, 128, 129, 200, 100_000] {
+                let message: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                echo.send(message.clone());
+                assert_eq!(mailbox.receive(), message);
+            }
+        }
+    };
+}
+
+round_trip_sizes!(bincode_message_sizes, Bincode);
+round_trip_sizes!(json_message_sizes, Json);
+round_trip_sizes!(msgpack_message_sizes, MessagePack);
+
+// A string together with the size of the message it was received in.
+#[derive(Debug, PartialEq)]
+struct Measured(u64, String);
+
+// Decodes the message twice to check that it can be read again.
+struct Measuring;
+
+impl CanSerialize<Measured> for Measuring {
+    fn encode(message: &Measured) -> Result<(), EncodeError> {
+        Bincode::encode(&message.1)
+    }
+
+    fn decode() -> Result<Measured, DecodeError> {
+        let first: String = Bincode::decode()?;
+        serializer::rewind_message();
+        let second: String = Bincode::decode()?;
+        assert_eq!(first, second);
+        Ok(Measured(serializer::message_size(), second))
+    }
+}
+
+#[test]
+fn custom_serializer_reads_the_message_buffer() {
+    let mailbox = unsafe { Mailbox::<Measured, Measuring>::new() };
+    mailbox.this().send(Measured(0, "hello".to_owned()));
+    // Bincode prefixes the string with its length as an u64.
+    assert_eq!(mailbox.receive(), Measured(13, "hello".to_owned()));
+}
+
+#[derive(Serialize, Deserialize)]
+struct Team {
+    name: String,
+    pool: Pool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Pool {
+    workers: Vec<Process<u64>>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Handles {
+    Vec(Vec<Process<u64>>),
+    Struct(Team),
+    Map(HashMap<String, Process<u64>>),
+}
+
+impl Handles {
+    // Returns the handles together with a number identifying the shape.
+    fn into_handles(self) -> Vec<(u64, Process<u64>)> {
+        match self {
+            Handles::Vec(handles) => handles.into_iter().map(|handle| (1, handle)).collect(),
+            Handles::Struct(team) => team.pool.workers.into_iter().map(|h| (2, h)).collect(),
+            Handles::Map(handles) => handles.into_values().map(|handle| (3, handle)).collect(),
+        }
+    }
+}
+
+// Sends the handle of the test process to a child in every shape, and checks
+// that the child can use each handle it received.
+macro_rules! send_handles {
+    ($name:ident, $serializer:ty) => {
+        #[test]
+        fn $name(mailbox: Mailbox<u64>) {
+            let this = mailbox.this();
+            // The handle is the whole capture.
+            Process::spawn(this, |parent, _: Mailbox<(), $serializer>| parent.send(0));
+            let child = Process::spawn_link((), |_, mailbox: Mailbox<Handles, $serializer>| loop {
+                for (shape, handle) in mailbox.receive().into_handles() {
+                    handle.checked_cast::<u64, Bincode>().unwrap().send(shape);
+                }
+            });
+            child.send(Handles::Vec(vec![this, this]));
+            child.send(Handles::Struct(Team {
+                name: "team".to_owned(),
+                pool: Pool {
+                    workers: vec![this],
+                },
+            }));
+            child.send(Handles::Map(HashMap::from([("test".to_owned(), this)])));
+            let mut shapes: Vec<u64> = (0..5).map(|_| mailbox.receive()).collect();
+            shapes.sort();
+            assert_eq!(shapes, [0, 1, 1, 2, 3]);
+        }
+    };
+}
+
+send_handles!(bincode_handles, Bincode);
+send_handles!(json_handles, Json);
+send_handles!(msgpack_handles, MessagePack);
+
+#[test]
+fn external_encoding_rejects_handles(mailbox: Mailbox<u64>) {
+    let team = Team {
+        name: "team".to_owned(),
+        pool: Pool {
+            workers: vec![mailbox.this()],
+        },
+    };
+    let mut json = Vec::new();
+    match serializer::encode_external(&team, &mut serde_json::Serializer::new(&mut json)) {
+        Err(ExternalError::Handle { path, .. }) => assert_eq!(path, "pool.workers[0]"),
+        _ => panic!("the handle was encoded for an external system"),
+    }
+    // Values without handles are encoded as usual.
+    let mut json = Vec::new();
+    let user = UserV1 {
+        name: "external".to_owned(),
+    };
+    serializer::encode_external(&user, &mut serde_json::Serializer::new(&mut json)).unwrap();
+    assert_eq!(json, br#"{"name":"external"}"#);
+}
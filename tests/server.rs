@@ -0,0 +1,94 @@
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use lunatic::net::{ControlMessage, DrainReport, Server, ServerHandle, TcpStream};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+type Started = (SocketAddr, ServerHandle);
+
+fn echo(mut stream: TcpStream, _: Mailbox<ControlMessage>) {
+    let mut buffer = [0; 64];
+    while let Ok(len @ 1..) = stream.read(&mut buffer) {
+        stream.write_all(&buffer[..len]).unwrap();
+    }
+}
+
+// Sends `message` and returns the echo, or `None` if it didn't arrive in time.
+fn roundtrip(client: &mut TcpStream, message: &[u8]) -> Option<Vec<u8>> {
+    client.write_all(message).unwrap();
+    let mut buffer = vec![0; message.len()];
+    client.read_exact(&mut buffer).ok()?;
+    Some(buffer)
+}
+
+#[test]
+fn connections_over_the_limit_wait(mailbox: Mailbox<Started>) {
+    Process::spawn(mailbox.this(), |parent, _: Mailbox<()>| {
+        let server = Server::bind("127.0.0.1:0").unwrap().with_max_connections(2);
+        parent.send((server.local_addr().unwrap(), server.handle()));
+        server.serve(echo);
+    });
+    let (addr, handle) = mailbox.receive();
+
+    let mut first = TcpStream::connect(addr).unwrap();
+    let mut second = TcpStream::connect(addr).unwrap();
+    let mut third = TcpStream::connect(addr).unwrap();
+    third
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    assert_eq!(roundtrip(&mut first, b"one").unwrap(), b"one");
+    assert_eq!(roundtrip(&mut second, b"two").unwrap(), b"two");
+    // The third connection is only accepted once another one closes.
+    assert_eq!(roundtrip(&mut third, b"three"), None);
+    drop(first);
+    third
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    let mut buffer = [0; 5];
+    third.read_exact(&mut buffer).unwrap();
+    assert_eq!(&buffer, b"three");
+
+    // The echo handlers ignore the drain and are killed at the deadline.
+    let report = handle.drain(Duration::from_millis(100));
+    assert_eq!(
+        report,
+        DrainReport {
+            finished: 0,
+            killed: 2
+        }
+    );
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn drain_notifies_handlers(mailbox: Mailbox<Started>) {
+    Process::spawn(mailbox.this(), |parent, _: Mailbox<()>| {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        parent.send((server.local_addr().unwrap(), server.handle()));
+        server.serve(|mut stream, mailbox| {
+            stream.write_all(b"hello").unwrap();
+            let ControlMessage::Drain(deadline) = mailbox.receive();
+            assert!(deadline <= Duration::from_secs(5));
+            stream.write_all(b"bye").unwrap();
+        });
+    });
+    let (addr, handle) = mailbox.receive();
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut buffer = [0; 5];
+    client.read_exact(&mut buffer).unwrap();
+    let report = handle.drain(Duration::from_secs(5));
+    assert_eq!(
+        report,
+        DrainReport {
+            finished: 1,
+            killed: 0
+        }
+    );
+    let mut buffer = [0; 3];
+    client.read_exact(&mut buffer).unwrap();
+    assert_eq!(&buffer, b"bye");
+    assert!(TcpStream::connect(addr).is_err());
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use lunatic::shutdown::{self, ShutdownSignal};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+// The shutdown is global, so everything is tested in a single test.
+#[test]
+fn shutdown_notifies_registered_processes(mailbox: Mailbox<String>) {
+    Process::spawn(
+        mailbox.this(),
+        |parent, mailbox: Mailbox<ShutdownSignal>| {
+            shutdown::on_shutdown(mailbox.this());
+            parent.send("registered".to_owned());
+            let signal = mailbox.receive();
+            parent.send("terminated".to_owned());
+            signal.done();
+        },
+    );
+    let stuck = Process::spawn(
+        mailbox.this(),
+        |parent, mailbox: Mailbox<ShutdownSignal>| {
+            shutdown::on_shutdown(mailbox.this());
+            parent.send("registered".to_owned());
+            // Never finishes.
+            let _signal = mailbox.receive();
+            loop {
+                lunatic::sleep(Duration::from_secs(1));
+            }
+        },
+    );
+    assert_eq!(mailbox.receive(), "registered");
+    assert_eq!(mailbox.receive(), "registered");
+
+    shutdown::set_grace_period(Duration::from_millis(200));
+    shutdown::initiate();
+    shutdown::wait_for_shutdown();
+
+    assert_eq!(mailbox.receive(), "terminated");
+    // The stuck process was killed after the grace period.
+    assert!(!stuck.is_alive());
+}
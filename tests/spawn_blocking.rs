@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use lunatic::sleep;
+use lunatic::task::{self, JoinError};
+use lunatic_test::test;
+
+#[test]
+fn spawn_blocking_returns_result() {
+    let handle = task::spawn_blocking_with(10u64, |n| (1..=n).product::<u64>());
+    assert_eq!(handle.join(), Ok(3628800));
+}
+
+#[test]
+fn spawn_blocking_reports_failure() {
+    let handle = task::spawn_blocking(|| -> u64 { panic!("failed") });
+    assert_eq!(handle.join(), Err(JoinError::Failed));
+}
+
+#[test]
+fn spawn_blocking_timeout() {
+    let handle = task::spawn_blocking(|| sleep(Duration::from_secs(10)));
+    assert_eq!(
+        handle.join_timeout(Duration::from_millis(50)),
+        Err(JoinError::Timeout)
+    );
+}
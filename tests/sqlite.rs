@@ -0,0 +1,79 @@
+use lunatic::sqlite::{Connection, Value};
+use lunatic_test::test;
+
+const ROWS: i64 = 100_000;
+
+fn numbers() -> Connection {
+    let conn = Connection::open(":memory:").unwrap();
+    // About 14 MB of text, more than the tests are allowed to use.
+    conn.execute(&format!(
+        "CREATE TABLE numbers (n INTEGER, label TEXT);
+         WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq LIMIT {})
+         INSERT INTO numbers SELECT n, hex(randomblob(64)) FROM seq;",
+        ROWS
+    ))
+    .unwrap();
+    conn
+}
+
+#[test(max_memory = 8_000_000)]
+fn rows_are_streamed() {
+    let conn = numbers();
+    let mut stmt = conn
+        .prepare("SELECT n, label FROM numbers ORDER BY n")
+        .unwrap();
+    let mut count = 0;
+    let mut sum = 0;
+    for row in stmt.query() {
+        let row = row.unwrap();
+        match row.get(0) {
+            Some(Value::Integer(n)) => sum += n,
+            other => panic!("unexpected value {:?}", other),
+        }
+        count += 1;
+    }
+    assert_eq!(count, ROWS);
+    assert_eq!(sum, ROWS * (ROWS + 1) / 2);
+}
+
+#[test]
+fn queries_between_rows() {
+    let conn = numbers();
+    let mut stmt = conn
+        .prepare("SELECT n FROM numbers WHERE n <= 250 ORDER BY n")
+        .unwrap();
+    let mut rows = stmt.query();
+    for expected in 1..=150 {
+        assert_eq!(
+            rows.next().unwrap().unwrap().get(0),
+            Some(&Value::Integer(expected))
+        );
+    }
+    // Running another query doesn't disturb the iteration.
+    let total = conn.query("SELECT count(*) FROM numbers").unwrap();
+    assert_eq!(total[0].get(0), Some(&Value::Integer(ROWS)));
+    let rest: Vec<_> = rows.map(|row| row.unwrap()).collect();
+    assert_eq!(rest.len(), 100);
+    assert_eq!(rest[99].get(0), Some(&Value::Integer(250)));
+}
+
+#[test]
+fn bound_parameters() {
+    let conn = Connection::open(":memory:").unwrap();
+    conn.execute("CREATE TABLE users (id INTEGER, name TEXT)")
+        .unwrap();
+    let mut insert = conn.prepare("INSERT INTO users VALUES (?, ?)").unwrap();
+    for (id, name) in [(1, "O'Brien"), (2, "Robert'); DROP TABLE users; --")] {
+        insert.bind(1, Value::Integer(id)).unwrap();
+        insert.bind(2, Value::Text(name.to_owned())).unwrap();
+        insert.execute().unwrap();
+    }
+    let mut select = conn.prepare("SELECT id FROM users WHERE name = ?").unwrap();
+    select.bind(1, Value::Text("O'Brien".to_owned())).unwrap();
+    let ids: Vec<_> = select.query().map(|row| row.unwrap()).collect();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(ids[0].get(0), Some(&Value::Integer(1)));
+    // The statement is reset after the rows are dropped.
+    select.bind(1, Value::Text("nobody".to_owned())).unwrap();
+    assert_eq!(select.query().count(), 0);
+}
@@ -1,10 +1,18 @@
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::Duration;
 
 use lunatic::ap::handlers::{Message, Request};
-use lunatic::ap::{AbstractProcess, Config, MessageHandler, ProcessRef, RequestHandler, State};
-use lunatic::serializer::{Json, MessagePack};
-use lunatic::supervisor::{Supervisor, SupervisorConfig, SupervisorStrategy};
-use lunatic::{sleep, spawn, test};
+use lunatic::ap::{
+    last_init_panic, AbstractProcess, Config, MessageHandler, ProcessRef, RequestHandler, Snapshot,
+    StartupError, State,
+};
+use lunatic::metrics::with_labels;
+use lunatic::serializer::{Bincode, Json, MessagePack};
+use lunatic::supervisor::{
+    self, ChildArg, ChildFailure, ChildStatus, SupervisedError, SupervisedRef, Supervisor,
+    SupervisorConfig, SupervisorEvent, SupervisorStrategy, RESTARTS_COUNTER, RUNNING_GAUGE,
+};
+use lunatic::{sleep, spawn, test, Mailbox, Process};
 
 const LOGGER_NAME: &'static str = "logger/assert_order";
 
@@ -571,3 +579,469 @@ fn wait_on_shutdown() {
     // the test will hang if block_until_shutdown() fails
     sup_cloned.wait_on_shutdown()
 }
+
+struct Persistent {
+    count: u32,
+}
+
+impl AbstractProcess for Persistent {
+    type Arg = u32;
+    type State = Persistent;
+    type Serializer = Bincode;
+    type Handlers = (Message<Inc>, Request<Count>);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, count: u32) -> Result<Persistent, ()> {
+        Ok(Persistent { count })
+    }
+
+    fn snapshot(state: &Persistent) -> Option<Snapshot> {
+        Some(Snapshot::new(1, &state.count))
+    }
+
+    fn init_from_snapshot(
+        config: Config<Self>,
+        count: u32,
+        snapshot: Snapshot,
+    ) -> Result<Persistent, ()> {
+        match snapshot.decode(1) {
+            Some(count) => Ok(Persistent { count }),
+            None => Self::init(config, count),
+        }
+    }
+}
+
+impl MessageHandler<Inc> for Persistent {
+    fn handle(mut state: State<Self>, _: Inc) {
+        state.count += 1;
+    }
+}
+
+impl RequestHandler<Count> for Persistent {
+    type Response = u32;
+
+    fn handle(state: State<Self>, _: Count) -> u32 {
+        state.count
+    }
+}
+
+#[test]
+fn restart_with_snapshot() {
+    struct Sup;
+    impl Supervisor for Sup {
+        type Arg = ();
+        type Children = (Persistent,);
+
+        fn init(config: &mut SupervisorConfig<Self>, _: ()) {
+            config.set_strategy(SupervisorStrategy::OneForOne);
+            config.restart_with_snapshot(true);
+            config.children_args(((0, None),));
+        }
+    }
+
+    let sup = Sup::link().start(()).unwrap();
+    let child = sup.children().0;
+    for _ in 0..5 {
+        child.send(Inc);
+    }
+    assert_eq!(child.request(Count), 5);
+
+    child.kill();
+    sleep(Duration::from_millis(10));
+
+    // The restarted child continues from the pre-crash count.
+    let child = sup.children().0;
+    assert_eq!(child.request(Count), 5);
+}
+
+// Counts the attempts to start a `Flaky` child.
+struct Attempts(u32);
+
+impl AbstractProcess for Attempts {
+    type Arg = ();
+    type State = Self;
+    type Serializer = Bincode;
+    type Handlers = (Request<NextAttempt>,);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(Attempts(0))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct NextAttempt;
+impl RequestHandler<NextAttempt> for Attempts {
+    type Response = u32;
+
+    fn handle(mut state: State<Self>, _: NextAttempt) -> u32 {
+        state.0 += 1;
+        state.0
+    }
+}
+
+// Panics in `init` until the attempt passed as argument.
+struct Flaky;
+
+impl AbstractProcess for Flaky {
+    type Arg = (String, u32);
+    type State = Self;
+    type Serializer = Bincode;
+    type Handlers = ();
+    type StartupError = ();
+
+    fn init(_: Config<Self>, (attempts, succeed_at): (String, u32)) -> Result<Self, ()> {
+        let attempt = ProcessRef::<Attempts>::lookup(&attempts)
+            .unwrap()
+            .request(NextAttempt);
+        if attempt < succeed_at {
+            panic!("attempt {} failed", attempt);
+        }
+        Ok(Flaky)
+    }
+}
+
+struct FlakySup;
+impl Supervisor for FlakySup {
+    type Arg = (Process<SupervisorEvent>, String, u32);
+    type Children = (Flaky,);
+
+    fn init(
+        config: &mut SupervisorConfig<Self>,
+        (events, attempts, succeed_at): (Process<SupervisorEvent>, String, u32),
+    ) {
+        config.set_restart_intensity(3, Duration::from_secs(5));
+        config.subscribe_events(events);
+        config.children_args((((attempts, succeed_at), None),));
+    }
+}
+
+#[test]
+fn init_failures_are_retried(mailbox: Mailbox<SupervisorEvent>) {
+    Attempts::link().start_as("attempts/retried", ()).unwrap();
+    let sup = FlakySup::link()
+        .start((mailbox.this(), "attempts/retried".to_owned(), 3))
+        .unwrap();
+    for attempt in 1..=2 {
+        assert_eq!(
+            mailbox.receive(),
+            SupervisorEvent::StartFailed {
+                child: 0,
+                reason: format!("attempt {} failed", attempt)
+            }
+        );
+    }
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(
+        sup.which_children(),
+        vec![ChildStatus {
+            index: 0,
+            restarts: 2,
+            last_failure: Some(ChildFailure::FailedToStart("attempt 2 failed".to_owned())),
+        }]
+    );
+}
+
+#[test]
+fn child_that_never_starts(mailbox: Mailbox<SupervisorEvent>) {
+    Attempts::link().start_as("attempts/never", ()).unwrap();
+    let result = FlakySup::link().start((mailbox.this(), "attempts/never".to_owned(), u32::MAX));
+    assert_eq!(result.err(), Some(StartupError::InitPanicked));
+    let message = last_init_panic().unwrap().message().unwrap().to_owned();
+    assert!(message.contains("failed to start child 0"));
+    assert!(message.ends_with("attempt 4 failed"));
+}
+
+// Counts the starts of the child, inside the supervisor process.
+static STARTS: AtomicU32 = AtomicU32::new(0);
+
+fn next_start() -> (u32, char) {
+    (STARTS.fetch_add(1, Ordering::SeqCst) + 1, 'f')
+}
+
+struct FactorySup;
+impl Supervisor for FactorySup {
+    type Arg = Process<SupervisorEvent>;
+    type Children = (A,);
+
+    fn init(config: &mut SupervisorConfig<Self>, events: Process<SupervisorEvent>) {
+        config.subscribe_events(events);
+        config.children_specs(((ChildArg::FromFn(next_start), None),));
+    }
+}
+
+#[test]
+fn child_arg_from_fn_on_restart(mailbox: Mailbox<SupervisorEvent>) {
+    let sup = FactorySup::link().start(mailbox.this()).unwrap();
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    let child = sup.children().0;
+    assert_eq!(child.request(Count), 1);
+
+    child.send(Panic);
+    assert!(matches!(
+        mailbox.receive(),
+        SupervisorEvent::Crashed { child: 0, .. }
+    ));
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    // The argument was created again for the restart.
+    assert_eq!(sup.children().0.request(Count), 2);
+}
+
+struct MetricsSup;
+impl Supervisor for MetricsSup {
+    type Arg = (Process<SupervisorEvent>, Option<u32>);
+    type Children = (A, A);
+
+    fn init(
+        config: &mut SupervisorConfig<Self>,
+        (events, max_restarts): (Process<SupervisorEvent>, Option<u32>),
+    ) {
+        config.set_strategy(SupervisorStrategy::OneForAll);
+        if let Some(max_restarts) = max_restarts {
+            config.set_restart_intensity(max_restarts, Duration::from_secs(5));
+        }
+        config.subscribe_events(events);
+        config.children_args((
+            ((0, 'm'), Some("metrics/named".to_owned())),
+            ((0, 'n'), None),
+        ));
+    }
+}
+
+// Panics the first child and waits until both children are started again.
+fn crash_first_child(sup: ProcessRef<MetricsSup>, mailbox: &Mailbox<SupervisorEvent>) {
+    sup.children().0.send(Panic);
+    assert!(matches!(
+        mailbox.receive(),
+        SupervisorEvent::Crashed { child: 0, .. }
+    ));
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+}
+
+#[test]
+fn restarts_are_published(mailbox: Mailbox<SupervisorEvent>) {
+    let sup = MetricsSup::link().start((mailbox.this(), None)).unwrap();
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+    crash_first_child(sup, &mailbox);
+    crash_first_child(sup, &mailbox);
+
+    let supervisor = std::any::type_name::<MetricsSup>();
+    let restarts = |child, reason| {
+        with_labels(
+            RESTARTS_COUNTER,
+            &[
+                ("supervisor", supervisor),
+                ("child", child),
+                ("reason", reason),
+            ],
+        )
+    };
+    let published = sup.published_metrics();
+    assert_eq!(published[&restarts("metrics/named", "panic")], 2.0);
+    // The second child has no name and was shut down to restart with the first.
+    assert_eq!(published[&restarts("1", "normal")], 2.0);
+    let running = with_labels(RUNNING_GAUGE, &[("supervisor", supervisor)]);
+    assert_eq!(published[&running], 2.0);
+}
+
+#[test]
+fn giving_up_is_published(mailbox: Mailbox<SupervisorEvent>) {
+    let sup = MetricsSup::start((mailbox.this(), Some(1))).unwrap();
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+    crash_first_child(sup, &mailbox);
+
+    sup.children().0.send(Panic);
+    assert!(matches!(
+        mailbox.receive(),
+        SupervisorEvent::Crashed { child: 0, .. }
+    ));
+    assert_eq!(mailbox.receive(), SupervisorEvent::GaveUp { child: 0 });
+}
+
+// Accepts checkouts once it's warmed up, 200ms after `init`.
+struct Pool {
+    accepting: bool,
+}
+
+impl AbstractProcess for Pool {
+    type Arg = ();
+    type State = Self;
+    type Serializer = Bincode;
+    type Handlers = (Message<Warm>, Message<Panic>, Request<Checkout>);
+    type StartupError = ();
+
+    fn init(config: Config<Self>, _: ()) -> Result<Self, ()> {
+        config
+            .self_ref()
+            .with_delay(Duration::from_millis(200))
+            .send(Warm);
+        Ok(Pool { accepting: false })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Warm;
+impl MessageHandler<Warm> for Pool {
+    fn handle(mut state: State<Self>, _: Warm) {
+        state.accepting = true;
+        supervisor::ready();
+    }
+}
+
+impl MessageHandler<Panic> for Pool {
+    fn handle(_: State<Self>, _: Panic) {
+        panic!();
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Checkout;
+impl RequestHandler<Checkout> for Pool {
+    type Response = bool;
+
+    fn handle(state: State<Self>, _: Checkout) -> bool {
+        state.accepting
+    }
+}
+
+// Checks out a connection of the pool on its first request.
+struct Listener;
+
+impl AbstractProcess for Listener {
+    type Arg = ();
+    type State = Self;
+    type Serializer = Bincode;
+    type Handlers = ();
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        let pool = ProcessRef::<Pool>::lookup("ready/pool").unwrap();
+        assert!(pool.request(Checkout), "the pool isn't ready");
+        Ok(Listener)
+    }
+}
+
+struct ReadySup;
+impl Supervisor for ReadySup {
+    type Arg = (Process<SupervisorEvent>, Duration);
+    type Children = (Pool, Listener);
+
+    fn init(
+        config: &mut SupervisorConfig<Self>,
+        (events, timeout): (Process<SupervisorEvent>, Duration),
+    ) {
+        config.set_strategy(SupervisorStrategy::OneForAll);
+        config.set_restart_intensity(1, Duration::from_secs(5));
+        config.subscribe_events(events);
+        config.wait_until_ready(0, timeout);
+        config.children_args((((), Some("ready/pool".to_owned())), ((), None)));
+    }
+}
+
+#[test]
+fn children_wait_on_ready(mailbox: Mailbox<SupervisorEvent>) {
+    let sup = ReadySup::link()
+        .start((mailbox.this(), Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+
+    // Restarts wait on the pool too.
+    sup.children().0.send(Panic);
+    assert!(matches!(
+        mailbox.receive(),
+        SupervisorEvent::Crashed { child: 0, .. }
+    ));
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+}
+
+#[test]
+fn ready_timeout_fails_the_start(mailbox: Mailbox<SupervisorEvent>) {
+    let result = ReadySup::link().start((mailbox.this(), Duration::from_millis(50)));
+    assert_eq!(result.err(), Some(StartupError::InitPanicked));
+    for _ in 0..2 {
+        assert_eq!(
+            mailbox.receive(),
+            SupervisorEvent::StartFailed {
+                child: 0,
+                reason: "not ready within 50ms".to_owned()
+            }
+        );
+    }
+}
+
+struct UnlimitedReadySup;
+impl Supervisor for UnlimitedReadySup {
+    type Arg = Process<SupervisorEvent>;
+    type Children = (Pool, Listener);
+
+    fn init(config: &mut SupervisorConfig<Self>, events: Process<SupervisorEvent>) {
+        config.subscribe_events(events);
+        config.wait_until_ready(0, Duration::from_millis(50));
+        config.children_args((((), Some("ready/unlimited".to_owned())), ((), None)));
+    }
+}
+
+#[test]
+fn failed_start_without_restart_intensity_gives_up(mailbox: Mailbox<SupervisorEvent>) {
+    let result = UnlimitedReadySup::link().start(mailbox.this());
+    assert_eq!(result.err(), Some(StartupError::InitPanicked));
+    assert_eq!(
+        mailbox.receive(),
+        SupervisorEvent::StartFailed {
+            child: 0,
+            reason: "not ready within 50ms".to_owned()
+        }
+    );
+    assert_eq!(mailbox.receive(), SupervisorEvent::GaveUp { child: 0 });
+}
+
+#[test]
+fn supervised_refs_follow_restarts(mailbox: Mailbox<SupervisorEvent>) {
+    let sup = MetricsSup::start((mailbox.this(), None)).unwrap();
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+    let old = sup.children().1;
+    let supervised = sup.supervised::<A>(1).unwrap();
+    let registered = SupervisedRef::<A>::lookup("metrics/named").unwrap();
+    supervised.send(Inc).unwrap();
+    assert_eq!(supervised.request(Count), Ok(1));
+
+    crash_first_child(sup, &mailbox);
+    // The plain reference points to the instance that was shut down.
+    assert!(!old.is_alive());
+    assert!(old
+        .with_timeout(Duration::from_millis(100))
+        .request(Count)
+        .is_err());
+    assert_eq!(supervised.request(Count), Ok(0));
+    assert_eq!(supervised.process(), Ok(sup.children().1));
+    assert_eq!(registered.request(Count), Ok(0));
+
+    // Without the supervisor, the child isn't restarted anymore.
+    sup.shutdown();
+    sleep(Duration::from_millis(50));
+    assert_eq!(supervised.request(Count), Err(SupervisedError::Gone));
+}
+
+#[test]
+fn supervised_refs_wait_on_the_restart(mailbox: Mailbox<SupervisorEvent>) {
+    let sup = MetricsSup::start((mailbox.this(), None)).unwrap();
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 0 });
+    assert_eq!(mailbox.receive(), SupervisorEvent::Started { child: 1 });
+    let supervised = sup.supervised::<A>(1).unwrap();
+    let old = supervised.process().unwrap();
+    supervised.send(Panic).unwrap();
+    assert!(matches!(
+        mailbox.receive(),
+        SupervisorEvent::Crashed { child: 1, .. }
+    ));
+    // The restart may still be in progress.
+    assert_eq!(supervised.request(Count), Ok(0));
+    assert_ne!(supervised.process(), Ok(old));
+    sup.shutdown();
+}
@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+use lunatic::sync::{CancellationToken, RateLimiter, Semaphore};
+use lunatic::{sleep, Mailbox, Process, Tag};
+use lunatic_test::test;
+
+#[test]
+fn semaphore_bounds_permits() {
+    let semaphore = Semaphore::new(2);
+    let first = semaphore.acquire();
+    let _second = semaphore.acquire();
+    assert!(semaphore.try_acquire().is_none());
+    drop(first);
+    assert!(semaphore.try_acquire().is_some());
+}
+
+#[test]
+fn semaphore_acquire_timeout() {
+    let semaphore = Semaphore::new(1);
+    let permit = semaphore.acquire();
+    assert!(semaphore
+        .acquire_timeout(Duration::from_millis(10))
+        .is_none());
+    permit.release();
+    assert!(semaphore
+        .acquire_timeout(Duration::from_millis(10))
+        .is_some());
+}
+
+#[test]
+fn semaphore_waiters_are_woken_up(mailbox: Mailbox<()>) {
+    let semaphore = Semaphore::new(1);
+    let permit = semaphore.acquire();
+    Process::spawn(
+        (semaphore, mailbox.this()),
+        |(semaphore, parent), _: Mailbox<()>| {
+            let _permit = semaphore.acquire();
+            parent.send(());
+        },
+    );
+    sleep(Duration::from_millis(20));
+    drop(permit);
+    mailbox.receive();
+}
+
+#[test]
+fn permits_of_dead_holders_are_released(mailbox: Mailbox<()>) {
+    let semaphore = Semaphore::new(1);
+    Process::spawn(
+        (semaphore, mailbox.this()),
+        |(semaphore, parent), _: Mailbox<()>| {
+            let permit = semaphore.acquire();
+            parent.send(());
+            std::mem::forget(permit);
+        },
+    );
+    mailbox.receive();
+    assert!(semaphore.acquire_timeout(Duration::from_secs(1)).is_some());
+}
+
+#[test]
+fn rate_limiter_shapes_requests() {
+    let limiter = RateLimiter::new(20, 1);
+    let start = Instant::now();
+    for _ in 0..5 {
+        limiter.until_ready();
+    }
+    // The first token is available right away, the next 4 take 50ms each.
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn rate_limiter_try_ready() {
+    let limiter = RateLimiter::new(1, 2);
+    assert!(limiter.try_ready());
+    assert!(limiter.try_ready());
+    assert!(!limiter.try_ready());
+}
+
+// Counts until the token is cancelled and reports the count.
+fn count_until_cancelled((token, parent): (CancellationToken, Process<u64>), _: Mailbox<()>) {
+    let mut count = 0u64;
+    while !token.is_cancelled() {
+        count += 1;
+        sleep(Duration::from_millis(1));
+    }
+    parent.send(count);
+}
+
+#[test]
+fn cancellation_is_observed_by_other_processes(mailbox: Mailbox<u64>) {
+    let token = CancellationToken::new();
+    for _ in 0..2 {
+        Process::spawn_link((token, mailbox.this()), count_until_cancelled);
+    }
+    sleep(Duration::from_millis(20));
+    // Cancelled from a third process.
+    Process::spawn(token, |token, _: Mailbox<()>| token.cancel());
+    let start = Instant::now();
+    for _ in 0..2 {
+        assert!(mailbox
+            .receive_timeout(Duration::from_millis(100))
+            .is_message());
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert!(token.is_cancelled());
+}
+
+#[test]
+fn child_tokens_are_cancelled_with_their_parent() {
+    let parent = CancellationToken::new();
+    let first = parent.child_token();
+    let second = parent.child_token();
+    first.cancel();
+    assert!(first.is_cancelled());
+    assert!(!second.is_cancelled());
+    assert!(!parent.is_cancelled());
+
+    parent.cancel();
+    let start = Instant::now();
+    while !second.is_cancelled() {
+        assert!(start.elapsed() < Duration::from_millis(100));
+        sleep(Duration::from_millis(1));
+    }
+    // Children of a cancelled token start cancelled.
+    assert!(parent.child_token().is_cancelled());
+}
+
+#[test]
+fn cancellation_is_delivered_into_the_mailbox(mailbox: Mailbox<()>) {
+    let token = CancellationToken::new();
+    let tag = Tag::new();
+    token.cancelled_into_mailbox(tag);
+    assert!(mailbox
+        .tag_receive_timeout(&[tag], Duration::from_millis(10))
+        .is_timed_out());
+    token.cancel();
+    assert!(mailbox
+        .tag_receive_timeout(&[tag], Duration::from_millis(100))
+        .is_message());
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use lunatic::ap::handlers::Request;
+use lunatic::ap::{AbstractProcess, Config, RequestHandler, State};
+use lunatic::serializer::Bincode;
+use lunatic::{sleep, Mailbox, Process, Tag};
+use lunatic_test::test;
+
+#[test]
+fn processes_derive_same_named_tag(mailbox: Mailbox<Tag>) {
+    let parent = mailbox.this();
+    Process::spawn(parent, |parent, _: Mailbox<()>| {
+        parent.send(Tag::from_str("shutdown"));
+    });
+    Process::spawn(parent, |parent, _: Mailbox<()>| {
+        parent.send(Tag::from_str("shutdown"));
+    });
+    let first = mailbox.receive();
+    let second = mailbox.receive();
+    assert_eq!(first, second);
+    assert_eq!(first, Tag::from_str("shutdown"));
+}
+
+#[test]
+fn receive_by_named_tag(mailbox: Mailbox<u64>) {
+    let parent = mailbox.this();
+    Process::spawn(parent, |parent, _: Mailbox<()>| {
+        parent.send(1);
+        parent.tag_send(Tag::from_str("priority"), 2);
+    });
+    assert_eq!(mailbox.tag_receive(&[Tag::from_str("priority")]), 2);
+    assert_eq!(mailbox.receive(), 1);
+}
+
+#[test]
+fn secure_tags_are_random() {
+    let first = Tag::new_secure();
+    let second = Tag::new_secure();
+    assert_ne!(first, second);
+    for tag in [first, second] {
+        assert!(tag.id() > 128 && tag.id() < 1 << 56);
+    }
+}
+
+/// Echoes requests after a delay, so that forged replies arrive first.
+struct SlowEcho;
+
+impl AbstractProcess for SlowEcho {
+    type State = Self;
+    type Serializer = Bincode;
+    type Arg = ();
+    type Handlers = (Request<u64>,);
+    type StartupError = ();
+
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(SlowEcho)
+    }
+}
+
+impl RequestHandler<u64> for SlowEcho {
+    type Response = u64;
+
+    fn handle(_: State<Self>, value: u64) -> u64 {
+        sleep(Duration::from_millis(50));
+        value
+    }
+}
+
+#[test]
+fn guessed_tag_cant_spoof_reply(mailbox: Mailbox<u64>) {
+    Tag::use_secure_tags(true);
+    let echo = SlowEcho::start(()).unwrap();
+    // The forger knows the last sequential tag of this process and sends a
+    // forged reply for each of the following ones.
+    let last = Tag::new().id();
+    let forger = Process::spawn(mailbox.this(), |parent, mailbox: Mailbox<Vec<Tag>>| {
+        for tag in mailbox.receive() {
+            parent.tag_send(tag, 666);
+        }
+    });
+    // Tags can't be created from numbers, the guesses are decoded as tags.
+    let forger = unsafe { Process::<Vec<i64>>::from_id(forger.node_id(), forger.id()) };
+    forger.send((last + 1..=last + 64).collect());
+    assert_eq!(echo.request(42), 42);
+}
@@ -0,0 +1,331 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use lunatic::net::{
+    proxy, Direction, DrainReport, GracefulListener, ListenerOptions, Poller, ProxyOptions,
+    ProxyStats, Readiness, SharedListener, StreamId, TcpListener, TcpStream,
+};
+use lunatic::serializer::EncodeError;
+use lunatic::{host, sleep, Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn stream_handoff_keeps_timeouts(mailbox: Mailbox<(Option<Duration>, Option<Duration>)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    stream
+        .set_write_timeout(Some(Duration::from_millis(700)))
+        .unwrap();
+
+    Process::spawn(
+        (mailbox.this(), stream),
+        |(parent, mut stream), _: Mailbox<()>| {
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+            parent.send((stream.read_timeout(), stream.write_timeout()));
+        },
+    );
+    client.write_all(b"hello").unwrap();
+    assert_eq!(
+        mailbox.receive(),
+        (
+            Some(Duration::from_millis(500)),
+            Some(Duration::from_millis(700))
+        )
+    );
+}
+
+#[test]
+fn stream_to_other_node_is_rejected() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    // A process on another node, the message never leaves this one.
+    let remote = unsafe { Process::<TcpStream>::from_id(host::node_id() + 1, 1) };
+    let error = remote.try_send(stream).unwrap_err();
+    assert!(matches!(error, EncodeError::LocalResource("TcpStream")));
+}
+
+fn accept_connections(listener: &GracefulListener, count: usize, work: Duration) -> Vec<TcpStream> {
+    let addr = listener.local_addr().unwrap();
+    let mut clients = Vec::new();
+    for _ in 0..count {
+        clients.push(TcpStream::connect(addr).unwrap());
+        let (stream, _) = listener.accept().unwrap();
+        listener.spawn((stream, work), |(mut stream, work), _: Mailbox<()>| {
+            sleep(work);
+            stream.write_all(b"done").unwrap();
+        });
+    }
+    clients
+}
+
+#[test]
+fn drain_waits_for_handlers() {
+    let listener = GracefulListener::new(TcpListener::bind("127.0.0.1:0").unwrap());
+    let addr = listener.local_addr().unwrap();
+    let mut clients = accept_connections(&listener, 3, Duration::from_millis(50));
+    let report = listener.begin_drain(Duration::from_secs(5));
+    assert_eq!(
+        report,
+        DrainReport {
+            finished: 3,
+            killed: 0
+        }
+    );
+    for client in clients.iter_mut() {
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"done");
+    }
+    // The listening socket is closed.
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn drain_kills_handlers_after_deadline() {
+    let listener = GracefulListener::new(TcpListener::bind("127.0.0.1:0").unwrap());
+    let _clients = accept_connections(&listener, 2, Duration::from_secs(60));
+    let report = listener.begin_drain(Duration::from_millis(100));
+    assert_eq!(
+        report,
+        DrainReport {
+            finished: 0,
+            killed: 2
+        }
+    );
+}
+
+// Returns the client and the backend side of a relay running in its own
+// process, which sends the stats to `parent` when it's done.
+fn start_relay(parent: Process<ProxyStats>, idle_timeout: Duration) -> (TcpStream, TcpStream) {
+    let frontend = TcpListener::bind("127.0.0.1:0").unwrap();
+    let backend = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(frontend.local_addr().unwrap()).unwrap();
+    let (a, _) = frontend.accept().unwrap();
+    let b = TcpStream::connect(backend.local_addr().unwrap()).unwrap();
+    let (server, _) = backend.accept().unwrap();
+    Process::spawn(
+        (parent, a, b, idle_timeout),
+        |(parent, a, b, idle_timeout), _: Mailbox<()>| {
+            let mut metered = (0, 0);
+            let options =
+                ProxyOptions::new()
+                    .idle_timeout(idle_timeout)
+                    .on_transfer(|direction, bytes| match direction {
+                        Direction::AToB => metered.0 += bytes as u64,
+                        Direction::BToA => metered.1 += bytes as u64,
+                    });
+            let stats = proxy(a, b, options);
+            assert_eq!(metered, (stats.a_to_b, stats.b_to_a));
+            parent.send(stats);
+        },
+    );
+    (client, server)
+}
+
+#[test]
+fn proxy_keeps_direction_after_half_close(mailbox: Mailbox<ProxyStats>) {
+    let (mut client, mut server) = start_relay(mailbox.this(), Duration::from_secs(5));
+    client.write_all(b"request").unwrap();
+    let mut buf = [0; 7];
+    server.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"request");
+    server.write_all(b"bye").unwrap();
+    // Closing the server ends the direction towards the client.
+    drop(server);
+    let mut buf = [0; 3];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"bye");
+    sleep(Duration::from_millis(50));
+    // The other direction is still relayed.
+    client.write_all(b"more").unwrap();
+    sleep(Duration::from_millis(50));
+    drop(client);
+    assert_eq!(
+        mailbox.receive(),
+        ProxyStats {
+            a_to_b: 11,
+            b_to_a: 3,
+            idle_timed_out: false
+        }
+    );
+}
+
+#[test]
+fn proxy_idle_timeout(mailbox: Mailbox<ProxyStats>) {
+    let start = Instant::now();
+    let (mut client, _server) = start_relay(mailbox.this(), Duration::from_millis(100));
+    let stats = mailbox.receive();
+    assert!(stats.idle_timed_out);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    // The relay closed the connection.
+    let mut buf = [0; 1];
+    assert!(matches!(client.read(&mut buf), Ok(0) | Err(_)));
+}
+
+// Echoes the id of the acceptor to each accepted connection.
+fn acceptor((listener, id): (SharedListener, u8), _: Mailbox<()>) {
+    while let Ok((mut stream, _)) = listener.accept() {
+        stream.write_all(&[id]).unwrap();
+    }
+}
+
+fn served_by(listener: SharedListener) -> u8 {
+    let mut client = TcpStream::connect(listener.local_addr()).unwrap();
+    let mut id = [0];
+    client.read_exact(&mut id).unwrap();
+    id[0]
+}
+
+#[test]
+fn shared_listener_survives_acceptor_restart() {
+    let listener = SharedListener::bind("127.0.0.1:0").unwrap();
+    let first = Process::spawn((listener, 1), acceptor);
+    assert_eq!(served_by(listener), 1);
+
+    // Killed while waiting on a connection.
+    first.kill();
+    sleep(Duration::from_millis(50));
+    // Connections that arrive before the replacement are kept in the backlog.
+    let mut early = TcpStream::connect(listener.local_addr()).unwrap();
+    Process::spawn((listener, 2), acceptor);
+    let mut id = [0];
+    early.read_exact(&mut id).unwrap();
+    assert_eq!(id, [2]);
+    assert_eq!(served_by(listener), 2);
+
+    listener.close();
+    sleep(Duration::from_millis(50));
+    assert!(TcpStream::connect(listener.local_addr()).is_err());
+}
+
+#[test]
+fn unsupported_listener_options() {
+    let options = ListenerOptions::new().reuse_port(true);
+    let error = TcpListener::bind_with("127.0.0.1:0", options).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn bind_retries_while_addr_is_in_use(mailbox: Mailbox<std::net::SocketAddr>) {
+    // Listeners can't be moved, the address is held by a process that exits soon.
+    Process::spawn(mailbox.this(), |parent, _: Mailbox<()>| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        parent.send(listener.local_addr().unwrap());
+        sleep(Duration::from_millis(100));
+    });
+    let addr = mailbox.receive();
+    assert!(TcpListener::bind(addr).is_err());
+    let options = ListenerOptions::new().retry_timeout(Duration::from_secs(5));
+    let rebound = TcpListener::bind_with(addr, options).unwrap();
+    assert_eq!(rebound.local_addr().unwrap(), addr);
+}
+
+#[test]
+fn peek_then_handoff(mailbox: Mailbox<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n").unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+
+    let mut first = [0; 4];
+    assert_eq!(stream.peek(&mut first).unwrap(), 4);
+    assert_eq!(&first, b"GET ");
+    // The peeked bytes are still read by the handler.
+    Process::spawn(
+        (mailbox.this(), stream),
+        |(parent, mut stream), _: Mailbox<()>| {
+            let mut line = Vec::new();
+            stream.read_until_limited(b'\n', 64, &mut line).unwrap();
+            parent.send(String::from_utf8(line).unwrap());
+            let mut rest = [0; 9];
+            stream.read_exact(&mut rest).unwrap();
+            parent.send(String::from_utf8(rest.to_vec()).unwrap());
+        },
+    );
+    assert_eq!(mailbox.receive(), "GET / HTTP/1.1\r\n");
+    assert_eq!(mailbox.receive(), "Host: a\r\n");
+}
+
+#[test]
+fn read_until_limited_over_limit() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(&[b'a'; 100]).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_peek_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+
+    let mut line = Vec::new();
+    let error = stream.read_until_limited(b'\n', 16, &mut line).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(line.len(), 16);
+}
+
+#[test]
+fn poller_reports_ready_streams() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut poller = Poller::new();
+    let mut clients = Vec::new();
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        clients.push(TcpStream::connect(addr).unwrap());
+        ids.push(poller.register(listener.accept().unwrap().0));
+    }
+    assert!(poller
+        .poll_ready(Some(Duration::from_millis(20)))
+        .is_empty());
+
+    clients[1].write_all(b"hello").unwrap();
+    assert_eq!(
+        poller.poll_ready(Some(Duration::from_secs(1))),
+        [(ids[1], Readiness::Readable)]
+    );
+    let mut buf = [0; 16];
+    let len = poller.read(ids[1], &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"hello");
+    // All data was read, reading again doesn't wait.
+    let err = poller.read(ids[1], &mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+    drop(clients.remove(2));
+    assert_eq!(
+        poller.poll_ready(Some(Duration::from_secs(1))),
+        [(ids[2], Readiness::Closed)]
+    );
+    let stream = poller.deregister(ids[2]).unwrap();
+    assert_eq!(stream.peek_timeout(), None);
+    assert_eq!(poller.len(), 2);
+}
+
+#[test]
+fn poller_wakes_on_messages(mailbox: Mailbox<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let mut poller = Poller::with_mailbox(mailbox);
+    poller.set_max_interval(Duration::from_secs(10));
+    poller.register(listener.accept().unwrap().0);
+
+    Process::spawn(mailbox.this(), |parent, _: Mailbox<()>| {
+        sleep(Duration::from_millis(100));
+        parent.send("stop".to_owned());
+    });
+    let start = Instant::now();
+    assert_eq!(
+        poller.poll_ready(None),
+        [(StreamId::MAILBOX, Readiness::Readable)]
+    );
+    // The message didn't wait for the next check of the streams.
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(poller.take_message().as_deref(), Some("stop"));
+    assert_eq!(poller.take_message(), None);
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use lunatic::time::{format_rfc3339, now, parse_rfc3339, InvalidTimestamp};
+use lunatic_test::test;
+
+#[test]
+fn format_known_timestamps() {
+    assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    let leap_day = UNIX_EPOCH + Duration::from_millis(951_827_696_789);
+    assert_eq!(format_rfc3339(leap_day), "2000-02-29T12:34:56.789Z");
+    let before_epoch = UNIX_EPOCH - Duration::from_millis(1);
+    assert_eq!(format_rfc3339(before_epoch), "1969-12-31T23:59:59.999Z");
+}
+
+#[test]
+fn format_parse_round_trip() {
+    let mut time = UNIX_EPOCH - Duration::from_secs(400 * 24 * 3600);
+    // Steps of a bit more than a month, through leap and non-leap years.
+    for _ in 0..1000 {
+        let formatted = format_rfc3339(time);
+        assert_eq!(parse_rfc3339(&formatted), Ok(time), "{formatted}");
+        time += Duration::from_millis(2_700_000_123);
+    }
+}
+
+#[test]
+fn parse_offsets_and_fractions() {
+    let utc = parse_rfc3339("2023-04-01T12:30:05.250Z").unwrap();
+    assert_eq!(parse_rfc3339("2023-04-01T14:30:05.250+02:00"), Ok(utc));
+    assert_eq!(parse_rfc3339("2023-04-01T10:00:05.25-02:30"), Ok(utc));
+    assert_eq!(parse_rfc3339("2023-04-01t12:30:05.250999z"), Ok(utc));
+    assert_eq!(
+        parse_rfc3339("2023-04-01T12:30:05Z"),
+        Ok(utc - Duration::from_millis(250))
+    );
+}
+
+#[test]
+fn parse_invalid_timestamps() {
+    for timestamp in [
+        "",
+        "2023-04-01",
+        "2023-04-01T12:30:05",
+        "2023-13-01T12:30:05Z",
+        "2023-02-29T12:30:05Z",
+        "2023-04-01T24:00:00Z",
+        "2023-04-01T12:30:05.Z",
+        "2023-04-01T12:30:05+0200",
+        "2023-04-01T12:30:05Zjunk",
+        "2023-04-01T12:30:0äZ",
+    ] {
+        assert_eq!(
+            parse_rfc3339(timestamp),
+            Err(InvalidTimestamp),
+            "{timestamp}"
+        );
+    }
+}
+
+#[test]
+fn consecutive_timestamps_are_ordered() {
+    let mut last = now();
+    for _ in 0..100 {
+        let next = now();
+        assert!(next >= last);
+        assert!(format_rfc3339(next) >= format_rfc3339(last));
+        last = next;
+    }
+}
@@ -1,8 +1,9 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use lunatic::ap::handlers::Message;
 use lunatic::ap::{AbstractProcess, Config, MessageHandler, ProcessRef, State};
 use lunatic::serializer::Bincode;
+use lunatic::time::sleep_until;
 use lunatic_test::test;
 
 struct P;
@@ -51,3 +52,28 @@ fn cancel_send_after() {
     // give enough time for the message to be sent if it wasn't canceled
     lunatic::sleep(Duration::from_millis(25));
 }
+
+#[test]
+fn sleep_rounds_up() {
+    let start = Instant::now();
+    lunatic::sleep(Duration::from_micros(500));
+    assert!(start.elapsed() >= Duration::from_micros(500));
+}
+
+#[test]
+fn sleep_until_stays_aligned() {
+    let period = Duration::from_millis(2);
+    let start = Instant::now();
+    let mut next = start;
+    for i in 0..100 {
+        next += period;
+        // Work that takes a varying amount of time.
+        lunatic::sleep(Duration::from_micros(100 * (i % 10)));
+        sleep_until(next);
+        assert!(Instant::now() >= next);
+    }
+    // Without drift the loop ends close to 100 periods after the start.
+    let elapsed = start.elapsed();
+    assert!(elapsed >= period * 100);
+    assert!(elapsed < period * 100 + Duration::from_millis(100));
+}
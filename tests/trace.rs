@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use lunatic::trace::{self, TraceEvent};
+use lunatic::{Mailbox, Process};
+use lunatic_test::test;
+
+#[test]
+fn traced_process_reports_handled_messages(mailbox: Mailbox<TraceEvent>) {
+    let child = Process::spawn_link((), |_, mailbox: Mailbox<u64>| loop {
+        let _ = mailbox.receive();
+    });
+    child.trace(Some(mailbox.this()));
+    child.send(1);
+    child.send(2);
+
+    let event = mailbox.receive_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(event.process_id, child.id());
+    assert_eq!(event.message_type, "u64");
+    assert_eq!(event.handler, None);
+    assert!(event.size > 0);
+}
+
+#[test]
+fn tracing_can_be_turned_off(mailbox: Mailbox<TraceEvent>) {
+    assert!(!trace::is_enabled());
+    trace::enable(mailbox.this());
+    assert!(trace::is_enabled());
+    trace::disable();
+    assert!(!trace::is_enabled());
+}
@@ -127,3 +127,33 @@ fn udp_broadcast_setter_getter_false() {
 
     assert_eq!(cur_broadcast, false);
 }
+
+#[test]
+fn multicast_options_are_unsupported() {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use lunatic::LunaticError;
+
+    let socket = net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    let group = Ipv4Addr::new(239, 255, 0, 1);
+    let results = [
+        socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED),
+        socket.leave_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED),
+        socket.join_multicast_v6(&Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1), 0),
+        socket.set_multicast_loop_v4(true),
+        socket.set_multicast_ttl_v4(4),
+        socket.set_multicast_loop_v6(true),
+    ];
+    for result in results {
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert!(matches!(
+            error.get_ref().unwrap().downcast_ref::<LunaticError>(),
+            Some(LunaticError::Unsupported { .. })
+        ));
+    }
+    assert_eq!(
+        socket.multicast_ttl_v4().unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+}
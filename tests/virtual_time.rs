@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+use lunatic::{time, Mailbox, MailboxResult, Process, Tag};
+use lunatic_test::test;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Attempt(Process<bool>, Tag);
+
+// Loses the first two requests and fails the next three.
+fn flaky_server(_: (), mailbox: Mailbox<Attempt>) {
+    for attempt in 0.. {
+        let Attempt(client, tag) = mailbox.receive();
+        match attempt {
+            0 | 1 => (),
+            2..=4 => client.tag_send(tag, false),
+            _ => client.tag_send(tag, true),
+        }
+    }
+}
+
+#[test(virtual_time)]
+fn exponential_backoff_runs_on_virtual_time(mailbox: Mailbox<bool>) {
+    let server = Process::spawn((), flaky_server);
+    // `Instant::now` keeps measuring real time.
+    let started = Instant::now();
+    let mut attempts = Vec::new();
+    let mut backoff = Duration::from_secs(2);
+    loop {
+        attempts.push(time::instant());
+        let tag = Tag::new();
+        server.send(Attempt(mailbox.this(), tag));
+        let reply = mailbox.tag_receive_timeout(&[tag], Duration::from_secs(30));
+        if let MailboxResult::Message(true) = reply {
+            break;
+        }
+        lunatic::sleep(backoff);
+        backoff *= 2;
+    }
+    let delays: Vec<Duration> = attempts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    // Lost requests wait on the timeout before backing off.
+    assert_eq!(delays, [32, 34, 8, 16, 32].map(Duration::from_secs));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test(virtual_time)]
+fn timers_fire_on_virtual_time(mailbox: Mailbox<u32>) {
+    let this = mailbox.this();
+    let start = time::instant();
+    let cancelled = this.send_after(1, Duration::from_secs(60 * 60));
+    this.send_after(2, Duration::from_secs(2 * 60 * 60));
+    assert!(cancelled.cancel());
+    assert_eq!(mailbox.receive(), 2);
+    assert_eq!(time::instant() - start, Duration::from_secs(2 * 60 * 60));
+    // The cancelled timer never fires.
+    assert!(mailbox
+        .receive_timeout(Duration::from_secs(24 * 60 * 60))
+        .is_timed_out());
+}
+
+#[test(virtual_time)]
+fn spawned_processes_share_the_clock(mailbox: Mailbox<u64>) {
+    for minutes in [30, 10, 20] {
+        Process::spawn(
+            (mailbox.this(), minutes),
+            |(parent, minutes), _: Mailbox<()>| {
+                lunatic::sleep(Duration::from_secs(minutes * 60));
+                parent.send(minutes);
+            },
+        );
+    }
+    let woken: Vec<u64> = (0..3).map(|_| mailbox.receive()).collect();
+    assert_eq!(woken, [10, 20, 30]);
+}